@@ -75,6 +75,49 @@ pub mod syscalls {
         GetAddrinfo,
         FreeAddrinfo,
         NanoSleep,
+        Splice,
+        ClockGetRes,
+        GetTimeOfDay,
+        SetTimeOfDay,
+        AdjTime,
+        Poll,
+        Ppoll,
+        EpollCreate1,
+        EpollCtl,
+        EpollWait,
+        GetDents64,
+        Rename,
+        Realpath,
+        Symlink,
+        Readlink,
+        Lstat,
+        Dup,
+        Dup2,
+        Dup3,
+        Chroot,
+        Mmap,
+        Munmap,
+        Msync,
+        SchedGetInfo,
+        Pipe,
+        Pipe2,
+        Mkfifo,
+        GetRandom,
+        Mknod,
+        Syslog,
+        ClockSetTime,
+        TimerCreate,
+        TimerSetTime,
+        TimerGetTime,
+        TimerGetOverrun,
+        TimerDelete,
+        Futex,
+        MqOpen,
+        MqClose,
+        MqUnlink,
+        MqTimedSend,
+        MqTimedReceive,
+        MqGetSetAttr,
         LastNR,
     }
 }
@@ -98,6 +141,41 @@ pub mod thread {
         pub stack_size: usize,
     }
 
+    pub const STATE_CREATED: u32 = 0;
+    pub const STATE_READY: u32 = 1;
+    pub const STATE_RUNNING: u32 = 2;
+    pub const STATE_SUSPENDED: u32 = 3;
+    pub const STATE_RETIRED: u32 = 4;
+
+    pub const WAIT_NONE: u32 = 0;
+    pub const WAIT_FUTEX: u32 = 1;
+    /// Semaphore, mutex, condition variable or event flags.
+    pub const WAIT_SYNC: u32 = 2;
+    pub const WAIT_SLEEP: u32 = 3;
+    pub const WAIT_ASYNC: u32 = 4;
+
+    pub const THREAD_NAME_LEN: usize = 16;
+    pub const WAIT_DEVICE_LEN: usize = 16;
+
+    /// A thread, as reported by the `SchedGetInfo` syscall.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct ThreadInfo {
+        pub tid: usize,
+        /// NUL-padded, and truncated if longer.
+        pub name: [u8; THREAD_NAME_LEN],
+        pub state: u32,
+        pub priority: u32,
+        /// One of `WAIT_*`, `WAIT_NONE` unless the thread is suspended.
+        pub wait_reason: u32,
+        /// Address of the futex or of the synchronization object.
+        pub wait_addr: usize,
+        /// Device waited on, NUL-padded, empty if none.
+        pub wait_device: [u8; WAIT_DEVICE_LEN],
+        /// How long the thread has been suspended, in milliseconds.
+        pub wait_ms: u64,
+    }
+
     #[repr(C)]
     #[derive(Clone, Debug)]
     pub struct ExitArgs {
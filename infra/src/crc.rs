@@ -0,0 +1,205 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cyclic redundancy checks and simpler checksums.
+//!
+//! CRC-32 uses the CRC instructions of ARMv8 when the target has them, and
+//! a table otherwise.
+
+// Reflected polynomial of the CRC-32 used by zlib, Ethernet and FAT.
+const CRC32_POLY: u32 = 0xedb8_8320;
+// Polynomial of the CRC-16 used by XMODEM, SD cards and Bluetooth.
+const CRC16_POLY: u16 = 0x1021;
+// Largest prime below 2^16.
+const ADLER_MOD: u32 = 65521;
+// Bytes which can be summed before the Adler-32 sums may overflow.
+const ADLER_NMAX: usize = 5552;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ CRC16_POLY
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// Runs the CRC-32 register over `data`, without the inversions.
+fn crc32_table(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "crc"))]
+fn crc32_raw(crc: u32, data: &[u8]) -> u32 {
+    use core::arch::aarch64::{__crc32b, __crc32d};
+
+    let (words, rest) = data.as_chunks::<8>();
+    // SAFETY: The target has the CRC instructions.
+    let crc = words.iter().fold(crc, |crc, word| unsafe {
+        __crc32d(crc, u64::from_le_bytes(*word))
+    });
+    rest.iter().fold(crc, |crc, &b| unsafe { __crc32b(crc, b) })
+}
+
+#[cfg(not(all(target_arch = "aarch64", target_feature = "crc")))]
+fn crc32_raw(crc: u32, data: &[u8]) -> u32 {
+    crc32_table(crc, data)
+}
+
+/// Continues a CRC-32 over `data`. Start from 0 and feed the result back
+/// to checksum data which comes in pieces.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !crc32_raw(!crc, data)
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continues a CRC-16/XMODEM over `data`, starting from 0 like
+/// [`crc32_update`].
+pub fn crc16_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &b| {
+        CRC16_TABLE[((crc >> 8) as u8 ^ b) as usize] ^ (crc << 8)
+    })
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+/// Continues an Adler-32 over `data`. Start from 1, as zlib does.
+pub fn adler32_update(adler: u32, data: &[u8]) -> u32 {
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    for chunk in data.chunks(ADLER_NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_MOD;
+        b %= ADLER_MOD;
+    }
+    (b << 16) | a
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    adler32_update(1, data)
+}
+
+/// The ones' complement checksum of IPv4, TCP and UDP (RFC 1071), for
+/// cards which can't compute it. An odd last byte is padded with zero.
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    let (words, rest) = data.as_chunks::<2>();
+    let mut sum = words
+        .iter()
+        .fold(0u64, |sum, word| sum + u16::from_be_bytes(*word) as u64);
+    if let Some(&last) = rest.first() {
+        sum += (last as u64) << 8;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn crc32_matches_table() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        for len in [0, 1, 7, 8, 9, 999] {
+            assert_eq!(
+                crc32(&data[..len]),
+                !crc32_table(!0, &data[..len]),
+                "len {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b""), 0);
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(crc16_update(crc16(b"1234"), b"56789"), 0x31c3);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32_update(adler32(b"Wiki"), b"pedia"), 0x11e6_0398);
+        // Long enough for the sums to be reduced on the way.
+        let data = [0xffu8; 3 * ADLER_NMAX];
+        let expected = data.iter().fold((1u64, 0u64), |(a, b), &byte| {
+            let a = (a + byte as u64) % ADLER_MOD as u64;
+            (a, (b + a) % ADLER_MOD as u64)
+        });
+        assert_eq!(adler32(&data), ((expected.1 << 16) | expected.0) as u32);
+    }
+
+    #[test]
+    fn internet_checksum_rfc1071() {
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(internet_checksum(&data), !0xddf2);
+        assert_eq!(internet_checksum(&data[..7]), !0xdcfb);
+        // A packet with its checksum sums to zero.
+        let mut packet = data.to_vec();
+        packet.extend_from_slice(&internet_checksum(&data).to_be_bytes());
+        assert_eq!(internet_checksum(&packet), 0);
+    }
+}
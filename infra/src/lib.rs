@@ -30,6 +30,7 @@
 #![feature(slice_ptr_get)]
 #![feature(strict_provenance_atomic_ptr)]
 
+pub mod crc;
 pub mod intrusive;
 pub mod list;
 pub mod ringbuffer;
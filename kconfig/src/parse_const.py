@@ -14,13 +14,13 @@
 # See the License for the specific language governing permissions and
 # limitations under the License.
 """
-Parse the int configuration item in Kconfig
+Parse the int and string configuration items in Kconfig
 Use the value of .config first, if not, use the default value
 Generate const value to rust
 """
 
 import sys
-from kconfiglib import Kconfig, INT
+from kconfiglib import Kconfig, INT, STRING
 import os
 import argparse
 
@@ -35,7 +35,7 @@ def parse_int_configs(kconfig_path, board, build_type):
     configs = {}
 
     for sym in kconf.defined_syms:
-        if sym.orig_type != INT or not sym.visibility:
+        if sym.orig_type not in (INT, STRING) or not sym.visibility:
             continue
 
         # check depends on
@@ -45,7 +45,9 @@ def parse_int_configs(kconfig_path, board, build_type):
         value = None
         try:
             # 1. The value set in .config is used first
-            if sym.str_value:
+            if sym.orig_type == STRING:
+                value = sym.str_value
+            elif sym.str_value:
                 value = int(sym.str_value)
             # 2. Try to get a default value (check the default ... if ... condition)
             elif sym.defaults:
@@ -71,7 +73,11 @@ def generate_rust_const(configs, output):
 #![allow(unused)]
 """
     for name, value in sorted(configs.items()):
-        rust_code += f"pub const {name}: usize = {value};\n"
+        if isinstance(value, str):
+            escaped = value.replace("\\", "\\\\").replace('"', '\\"')
+            rust_code += f"pub const {name}: &str = \"{escaped}\";\n"
+        else:
+            rust_code += f"pub const {name}: usize = {value};\n"
     output_dir = os.path.dirname(output)
     os.makedirs(output_dir, exist_ok=True)
     with open(output, "w") as f:
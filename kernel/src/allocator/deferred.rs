@@ -0,0 +1,169 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deferred freeing of the memory released by interrupt handlers.
+//!
+//! Freeing takes the heap lock with interrupts disabled, for as long as
+//! the allocator needs to merge the block, which adds to the latency of
+//! every interrupt. Instead, a block released in a handler is pushed on a
+//! lock-free list of its CPU, and the reclaimer thread gives it back to the
+//! heap later. The list is threaded through the blocks themselves, so
+//! deferring never allocates. Blocks too small to hold a link are freed
+//! right away.
+//!
+//! The reclaimer also runs the callbacks of the heap watermarks crossed by
+//! allocations, which can't run them themselves.
+
+use super::{watermark, HEAP};
+use crate::{
+    arch,
+    config::RECLAIMER_THREAD_PRIORITY,
+    scheduler,
+    sync::atomic_wait::{atomic_wait, atomic_wake},
+    thread::{self, Entry, SystemThreadStorage, ThreadKind, ThreadNode},
+};
+use alloc::alloc::Layout;
+use blueos_kconfig::NUM_CORES;
+use core::{
+    mem::{size_of, MaybeUninit},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+// Written at the start of a deferred block, which may not be aligned for
+// it.
+struct Node {
+    next: *mut Node,
+    size: usize,
+    align: usize,
+}
+
+static LISTS: [AtomicPtr<Node>; NUM_CORES] = [const { AtomicPtr::new(ptr::null_mut()) }; NUM_CORES];
+// Blocks are only deferred once the reclaimer runs.
+static READY: AtomicBool = AtomicBool::new(false);
+static RECLAIMER_WAKER: AtomicUsize = AtomicUsize::new(0);
+static RECLAIMER_STORAGE: SystemThreadStorage = SystemThreadStorage::new(ThreadKind::Reclaimer);
+static mut RECLAIMER: MaybeUninit<ThreadNode> = MaybeUninit::zeroed();
+
+pub(crate) fn init() {
+    let reclaimer = thread::build_static_thread(
+        unsafe { &mut RECLAIMER },
+        &RECLAIMER_STORAGE,
+        RECLAIMER_THREAD_PRIORITY,
+        thread::CREATED,
+        Entry::C(reclaim),
+        ThreadKind::Reclaimer,
+    );
+    let ok = scheduler::queue_ready_thread(thread::CREATED, reclaimer);
+    assert!(ok, "reclaimer can't be queued");
+    READY.store(true, Ordering::Release);
+}
+
+/// Queues the block at `ptr` to be freed by the reclaimer. Returns false if
+/// it must be freed by the caller, because it's too small or the reclaimer
+/// isn't running.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated from the heap with `layout`, and not be
+/// used anymore.
+pub unsafe fn defer_free(ptr: *mut u8, layout: Layout) -> bool {
+    if layout.size() < size_of::<Node>() || !READY.load(Ordering::Acquire) {
+        return false;
+    }
+    let node = ptr as *mut Node;
+    let list = &LISTS[arch::current_cpu_id()];
+    let mut head = list.load(Ordering::Relaxed);
+    loop {
+        ptr::write_unaligned(
+            node,
+            Node {
+                next: head,
+                size: layout.size(),
+                align: layout.align(),
+            },
+        );
+        // A nested handler may push on the same list.
+        match list.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(current) => head = current,
+        }
+    }
+    // The reclaimer drains every list, waking it for the first block is
+    // enough.
+    if head.is_null() {
+        wake();
+    }
+    true
+}
+
+/// Wakes the reclaimer up, to free the deferred blocks and run the
+/// watermark callbacks.
+pub(super) fn wake() {
+    RECLAIMER_WAKER.fetch_add(1, Ordering::Release);
+    let _ = atomic_wake(&RECLAIMER_WAKER, 1);
+}
+
+/// Frees the blocks deferred on all CPUs, and returns how many there were.
+pub fn drain() -> usize {
+    let mut freed = 0;
+    for list in LISTS.iter() {
+        let mut node = list.swap(ptr::null_mut(), Ordering::Acquire);
+        while !node.is_null() {
+            let Node { next, size, align } = unsafe { ptr::read_unaligned(node) };
+            let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
+            unsafe { HEAP.dealloc(node as *mut u8, layout) };
+            node = next;
+            freed += 1;
+        }
+    }
+    if freed > 0 {
+        watermark::check();
+    }
+    freed
+}
+
+extern "C" fn reclaim() {
+    loop {
+        let n = RECLAIMER_WAKER.load(Ordering::Acquire);
+        drain();
+        watermark::run_callbacks();
+        let _ = atomic_wait(&RECLAIMER_WAKER, n, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::alloc::{alloc, dealloc};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_defer_free() {
+        let layout = Layout::from_size_align(64, 1).unwrap();
+        let block = unsafe { alloc(layout) };
+        assert!(!block.is_null());
+        assert!(unsafe { defer_free(block, layout) });
+        // The reclaimer may have freed it already.
+        drain();
+        let block = unsafe { alloc(layout) };
+        assert!(!block.is_null());
+        unsafe { dealloc(block, layout) };
+
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        let block = unsafe { alloc(layout) };
+        assert!(!unsafe { defer_free(block, layout) });
+        unsafe { dealloc(block, layout) };
+    }
+}
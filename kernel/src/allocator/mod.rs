@@ -14,11 +14,17 @@
 
 extern crate alloc;
 
-use crate::static_arc;
+use crate::{
+    events::{self, Event},
+    irq, static_arc,
+};
 use alloc::alloc::Layout;
-use core::{alloc::GlobalAlloc, ptr};
+use core::{alloc::GlobalAlloc, ptr, ptr::NonNull};
 
 pub mod block;
+pub mod deferred;
+pub mod page;
+pub mod watermark;
 #[cfg(any(allocator = "tlsf", allocator = "slab"))]
 pub(crate) mod tlsf;
 #[cfg(allocator = "tlsf")]
@@ -39,14 +45,38 @@ static_arc! {
    HEAP(Heap, Heap::new()),
 }
 
+// Allocates from the heap, counting the failures. Blocks waiting to be
+// freed by the reclaimer are freed before giving up.
+fn heap_alloc(layout: Layout) -> Option<NonNull<u8>> {
+    let mut allocation = HEAP.alloc(layout);
+    if allocation.is_none() && !irq::is_in_irq() && deferred::drain() > 0 {
+        allocation = HEAP.alloc(layout);
+    }
+    if allocation.is_none() {
+        events::count(Event::AllocFailure);
+    }
+    allocation
+}
+
+// Frees a block to the heap, or leaves it to the reclaimer in interrupt
+// handlers.
+unsafe fn heap_dealloc(ptr: *mut u8, layout: Layout) {
+    if irq::is_in_irq() && deferred::defer_free(ptr, layout) {
+        return;
+    }
+    HEAP.dealloc(ptr, layout);
+    watermark::check();
+}
+
 unsafe impl GlobalAlloc for KernelAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        HEAP.alloc(layout)
-            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+        let ptr = heap_alloc(layout).map_or(ptr::null_mut(), |ptr| ptr.as_ptr());
+        watermark::check();
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        HEAP.dealloc(ptr, layout);
+        heap_dealloc(ptr, layout);
     }
 }
 
@@ -67,14 +97,18 @@ mod allocator_api {
         fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
             match layout.size() {
                 0 => Ok(NonNull::slice_from_raw_parts(layout.dangling(), 0)),
-                size => HEAP.alloc(layout).map_or(Err(AllocError), |allocation| {
-                    Ok(NonNull::slice_from_raw_parts(allocation, size))
-                }),
+                size => {
+                    let allocation = heap_alloc(layout);
+                    watermark::check();
+                    allocation.map_or(Err(AllocError), |allocation| {
+                        Ok(NonNull::slice_from_raw_parts(allocation, size))
+                    })
+                }
             }
         }
         unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
             if layout.size() != 0 {
-                HEAP.dealloc(ptr.as_ptr(), layout);
+                heap_dealloc(ptr.as_ptr(), layout);
             }
         }
     }
@@ -109,8 +143,9 @@ pub fn malloc(size: usize) -> *mut u8 {
     }
     const ALIGN: usize = core::mem::size_of::<usize>();
     let layout = Layout::from_size_align(size, ALIGN).unwrap();
-    HEAP.alloc(layout)
-        .map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+    let ptr = heap_alloc(layout).map_or(ptr::null_mut(), |allocation| allocation.as_ptr());
+    watermark::check();
+    ptr
 }
 
 /// Free previously allocated memory pointed by ptr.
@@ -123,6 +158,7 @@ pub fn free(ptr: *mut u8) {
         return;
     }
     unsafe { HEAP.deallocate_unknown_align(ptr) };
+    watermark::check();
 }
 
 /// Reallocate memory pointed by ptr to have a new size.
@@ -139,10 +175,15 @@ pub fn realloc(ptr: *mut u8, newsize: usize) -> *mut u8 {
     if ptr.is_null() {
         return malloc(newsize);
     }
-    unsafe {
-        HEAP.realloc_unknown_align(ptr, newsize)
-            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
-    }
+    let ptr = unsafe { HEAP.realloc_unknown_align(ptr, newsize) }.map_or_else(
+        || {
+            events::count(Event::AllocFailure);
+            ptr::null_mut()
+        },
+        |ptr| ptr.as_ptr(),
+    );
+    watermark::check();
+    ptr
 }
 
 /// Allocates memory for an array of elements and initializes all bytes in this block to zero.
@@ -155,7 +196,9 @@ pub fn calloc(count: usize, size: usize) -> *mut u8 {
     let required_size = count * size;
     const ALIGN: usize = core::mem::size_of::<usize>();
     let layout = Layout::from_size_align(required_size, ALIGN).unwrap();
-    if let Some(alloc_ptr) = HEAP.alloc(layout) {
+    let allocation = heap_alloc(layout);
+    watermark::check();
+    if let Some(alloc_ptr) = allocation {
         unsafe { ptr::write_bytes(alloc_ptr.as_ptr(), 0, required_size) };
         alloc_ptr.as_ptr()
     } else {
@@ -175,8 +218,9 @@ pub fn malloc_align(size: usize, align: usize) -> *mut u8 {
     }
 
     let layout = Layout::from_size_align(size, align).unwrap();
-    HEAP.alloc(layout)
-        .map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+    let ptr = heap_alloc(layout).map_or(ptr::null_mut(), |allocation| allocation.as_ptr());
+    watermark::check();
+    ptr
 }
 
 /// Deallocates memory that was allocated using `malloc_align`.
@@ -192,6 +236,7 @@ pub fn free_align(ptr: *mut u8, align: usize) {
         let layout = Layout::from_size_align_unchecked(0, align);
         HEAP.dealloc(ptr, layout);
     }
+    watermark::check();
 }
 
 /// Returns the offset of the address within the alignment.
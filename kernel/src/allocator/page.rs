@@ -0,0 +1,172 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Page allocations with owner tracking.
+//!
+//! Pages are carved out of the heap, page aligned. Every run of pages
+//! records the subsystem owning it and the place it was allocated from, so
+//! that when a board runs out of RAM the pages can be attributed, through
+//! [`dump`] or `/proc/pageowner`.
+//!
+//! The heap has no merging of identical pages, so there is nothing for an
+//! allocation to opt out of.
+
+use crate::sync::SpinLock;
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{fmt, panic::Location, ptr};
+
+pub const PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageOwner {
+    /// The subsystem the pages belong to, such as "mmap".
+    pub owner: &'static str,
+    /// Where the pages were allocated.
+    pub site: &'static Location<'static>,
+    pub pages: usize,
+}
+
+// Most runs of pages allocated at once. The table is fixed so that nothing
+// is allocated from the heap while its irqsave lock is held.
+const MAX_RUNS: usize = 128;
+
+// Runs of pages, by address when there's one.
+static OWNERS: SpinLock<[Option<(usize, PageOwner)>; MAX_RUNS]> = SpinLock::new([None; MAX_RUNS]);
+
+/// Allocates `count` zeroed pages on behalf of `owner`. Returns a null
+/// pointer if `count` is zero, the heap is exhausted or there are already
+/// `MAX_RUNS` runs of pages.
+#[track_caller]
+pub fn alloc_pages(count: usize, owner: &'static str) -> *mut u8 {
+    let Some(size) = count.checked_mul(PAGE_SIZE) else {
+        return ptr::null_mut();
+    };
+    let ptr = super::malloc_align(size, PAGE_SIZE);
+    if ptr.is_null() {
+        return ptr;
+    }
+    let run = PageOwner {
+        owner,
+        site: Location::caller(),
+        pages: count,
+    };
+    {
+        let mut owners = OWNERS.irqsave_lock();
+        if let Some(slot) = owners.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some((ptr as usize, run));
+            drop(owners);
+            unsafe { ptr.write_bytes(0, size) };
+            return ptr;
+        }
+    }
+    log::warn!("{} pages for {}: too many runs of pages", count, owner);
+    super::free_align(ptr, PAGE_SIZE);
+    ptr::null_mut()
+}
+
+/// Frees the pages at `ptr`, which must have been returned by
+/// [`alloc_pages`].
+pub fn free_pages(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    {
+        let mut owners = OWNERS.irqsave_lock();
+        let Some(slot) = owners
+            .iter_mut()
+            .find(|slot| matches!(slot, Some((start, _)) if *start == ptr as usize))
+        else {
+            drop(owners);
+            log::warn!("{:p} is not a page allocation", ptr);
+            return;
+        };
+        *slot = None;
+    }
+    super::free_align(ptr, PAGE_SIZE);
+}
+
+/// Returns the owner of the page holding `addr`, with the address of the
+/// run of pages it's part of.
+pub fn owner_of(addr: usize) -> Option<(usize, PageOwner)> {
+    OWNERS
+        .irqsave_lock()
+        .iter()
+        .flatten()
+        .find(|(start, owner)| (*start..start + owner.pages * PAGE_SIZE).contains(&addr))
+        .copied()
+}
+
+/// Returns every run of pages allocated, by address.
+pub fn page_owners() -> Vec<(usize, PageOwner)> {
+    let mut runs = Vec::with_capacity(MAX_RUNS);
+    runs.extend(OWNERS.irqsave_lock().iter().flatten().copied());
+    runs.sort_unstable_by_key(|(start, _)| *start);
+    runs
+}
+
+/// Writes the number of pages held by each owner, then every run of pages
+/// with the place it was allocated from.
+pub fn dump(w: &mut impl fmt::Write) -> fmt::Result {
+    let runs = page_owners();
+    let mut totals: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, owner) in runs.iter() {
+        *totals.entry(owner.owner).or_default() += owner.pages;
+    }
+    writeln!(w, "owner pages")?;
+    for (owner, pages) in totals.iter() {
+        writeln!(w, "{} {}", owner, pages)?;
+    }
+    writeln!(w, "\naddress pages owner site")?;
+    for (start, owner) in runs.iter() {
+        writeln!(
+            w,
+            "{:#x} {} {} {}:{}",
+            start,
+            owner.pages,
+            owner.owner,
+            owner.site.file(),
+            owner.site.line()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_page_owner() {
+        assert!(alloc_pages(0, "test").is_null());
+        let ptr = alloc_pages(2, "test");
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % PAGE_SIZE, 0);
+        assert_eq!(unsafe { *ptr.add(PAGE_SIZE) }, 0);
+
+        let (start, owner) = owner_of(ptr as usize + PAGE_SIZE + 8).unwrap();
+        assert_eq!(start, ptr as usize);
+        assert_eq!((owner.owner, owner.pages), ("test", 2));
+        assert_eq!(owner.site.file(), file!());
+        assert!(owner_of(ptr as usize + 2 * PAGE_SIZE).map_or(true, |(s, _)| s != start));
+
+        let mut out = String::new();
+        dump(&mut out).unwrap();
+        assert!(out.contains(&alloc::format!("{:#x} 2 test", start)));
+
+        free_pages(ptr);
+        assert!(owner_of(ptr as usize).map_or(true, |(s, _)| s != start));
+    }
+}
@@ -0,0 +1,396 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Heap usage watermarks.
+//!
+//! A watermark is a usage threshold expressed as a percentage of the heap.
+//! When the heap usage crosses a registered threshold, the callback bound to
+//! it is invoked, so that subsystems holding caches can shrink them, or log
+//! the pressure, before allocations start failing. Crossings above a
+//! threshold are also counted as [`Event::HeapWatermark`].
+//!
+//! The allocation or deallocation that caused a crossing only records it,
+//! since it may be an IRQ or hold locks. The callbacks are run later by the
+//! reclaimer thread of [`deferred`](super::deferred), where they may block
+//! and allocate. A crossing which is undone before its callback runs isn't
+//! reported.
+//!
+//! Subsystems holding caches which can be dropped register a [`Shrinker`].
+//! [`shrink_watermark`] is a ready-made callback which runs them all when
+//! the usage goes above its threshold.
+
+use super::{deferred, MemoryInfo};
+use crate::{
+    arch,
+    error::{code, Error},
+    events::{self, Event},
+    irq, scheduler,
+    sync::SpinLock,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Maximum number of watermarks that can be registered at the same time.
+/// The registry is a fixed array so that checking it never allocates.
+pub const MAX_WATERMARKS: usize = 8;
+
+/// Maximum number of shrinkers that can be registered at the same time.
+pub const MAX_SHRINKERS: usize = 8;
+
+/// Usage has to drop this many percent below a threshold before the
+/// watermark is re-armed, so that an allocation pattern hovering around the
+/// threshold doesn't flood the callback.
+pub const WATERMARK_HYSTERESIS: u8 = 5;
+
+pub type WatermarkCallback = fn(&WatermarkEvent);
+
+/// Frees what it can of a cache and returns the number of bytes freed.
+pub type Shrinker = fn() -> usize;
+
+#[derive(Debug)]
+pub struct WatermarkEvent {
+    /// The threshold that was crossed, in percent.
+    pub threshold: u8,
+    /// True if the usage went above the threshold, false if it went back
+    /// below the re-arm level.
+    pub rising: bool,
+    pub info: MemoryInfo,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Watermark {
+    threshold: u8,
+    callback: WatermarkCallback,
+    triggered: bool,
+    // The crossing the callback hasn't been called for yet, and the usage
+    // when it happened.
+    pending: Option<(bool, usize, usize)>,
+}
+
+impl Watermark {
+    const fn new(threshold: u8, callback: WatermarkCallback) -> Self {
+        Self {
+            threshold,
+            callback,
+            triggered: false,
+            pending: None,
+        }
+    }
+
+    // Returns Some(rising) if the usage has crossed the watermark.
+    fn update(&mut self, usage: u8) -> Option<bool> {
+        if !self.triggered && usage >= self.threshold {
+            self.triggered = true;
+            return Some(true);
+        }
+        if self.triggered && usage < self.threshold.saturating_sub(WATERMARK_HYSTERESIS) {
+            self.triggered = false;
+            return Some(false);
+        }
+        None
+    }
+}
+
+static WATERMARKS: SpinLock<[Option<Watermark>; MAX_WATERMARKS]> =
+    SpinLock::new([None; MAX_WATERMARKS]);
+static NUM_WATERMARKS: AtomicUsize = AtomicUsize::new(0);
+// A check is in progress, and another one was asked for since it started.
+static CHECKING: AtomicBool = AtomicBool::new(false);
+static RECHECK: AtomicBool = AtomicBool::new(false);
+// Crossings are waiting for the reclaimer to be woken up.
+static PENDING: AtomicBool = AtomicBool::new(false);
+static SHRINKERS: SpinLock<[Option<Shrinker>; MAX_SHRINKERS]> =
+    SpinLock::new([None; MAX_SHRINKERS]);
+
+/// Registers `callback` to be invoked when the heap usage crosses
+/// `threshold` percent. Returns an id that can be passed to [`unregister`].
+pub fn register(threshold: u8, callback: WatermarkCallback) -> Result<usize, Error> {
+    if threshold == 0 || threshold > 100 {
+        return Err(code::EINVAL);
+    }
+    let mut w = WATERMARKS.irqsave_lock();
+    let Some(id) = w.iter().position(|slot| slot.is_none()) else {
+        return Err(code::ENOSPC);
+    };
+    w[id] = Some(Watermark::new(threshold, callback));
+    NUM_WATERMARKS.fetch_add(1, Ordering::Release);
+    Ok(id)
+}
+
+pub fn unregister(id: usize) -> Result<(), Error> {
+    let mut w = WATERMARKS.irqsave_lock();
+    let Some(slot) = w.get_mut(id) else {
+        return Err(code::EINVAL);
+    };
+    if slot.take().is_none() {
+        return Err(code::ENOENT);
+    }
+    NUM_WATERMARKS.fetch_sub(1, Ordering::Release);
+    Ok(())
+}
+
+/// Registers `shrinker` to be run by [`shrink`]. Returns an id that can be
+/// passed to [`unregister_shrinker`].
+pub fn register_shrinker(shrinker: Shrinker) -> Result<usize, Error> {
+    let mut shrinkers = SHRINKERS.irqsave_lock();
+    let Some(id) = shrinkers.iter().position(|slot| slot.is_none()) else {
+        return Err(code::ENOSPC);
+    };
+    shrinkers[id] = Some(shrinker);
+    Ok(id)
+}
+
+pub fn unregister_shrinker(id: usize) -> Result<(), Error> {
+    let mut shrinkers = SHRINKERS.irqsave_lock();
+    let Some(slot) = shrinkers.get_mut(id) else {
+        return Err(code::EINVAL);
+    };
+    slot.take().map(|_| ()).ok_or(code::ENOENT)
+}
+
+/// Runs all the registered shrinkers, returns the number of bytes they
+/// freed.
+pub fn shrink() -> usize {
+    // Shrinkers are run without the lock held, they free memory and may
+    // well go through the allocator.
+    let shrinkers = *SHRINKERS.irqsave_lock();
+    shrinkers.iter().flatten().map(|shrinker| shrinker()).sum()
+}
+
+/// A ready-made callback which runs the shrinkers when the usage goes
+/// above the threshold.
+pub fn shrink_watermark(event: &WatermarkEvent) {
+    if event.rising {
+        let freed = shrink();
+        log::info!(
+            "Heap usage above {}%: shrinkers freed {} bytes",
+            event.threshold,
+            freed
+        );
+    }
+}
+
+/// A ready-made callback which reports the crossing to the kernel log.
+pub fn log_watermark(event: &WatermarkEvent) {
+    if event.rising {
+        log::warn!(
+            "Heap usage above {}%: {}/{} bytes used",
+            event.threshold,
+            event.info.used,
+            event.info.total
+        );
+    } else {
+        log::info!(
+            "Heap usage back below {}%: {}/{} bytes used",
+            event.threshold,
+            event.info.used,
+            event.info.total
+        );
+    }
+}
+
+#[inline]
+pub(crate) fn check() {
+    if NUM_WATERMARKS.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    check_slow();
+}
+
+#[inline(never)]
+fn check_slow() {
+    // Checks racing with one in progress, on another core or from an IRQ,
+    // leave it to check again once done, so that no crossing is missed.
+    RECHECK.store(true, Ordering::Release);
+    while CHECKING
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok()
+    {
+        while RECHECK.swap(false, Ordering::AcqRel) {
+            update();
+        }
+        CHECKING.store(false, Ordering::Release);
+        if !RECHECK.load(Ordering::Acquire) {
+            break;
+        }
+    }
+    // Waking the reclaimer takes locks which are held with IRQs disabled,
+    // so it's left to a later check when this one may be inside them.
+    if arch::local_irq_enabled()
+        && !irq::is_in_irq()
+        && scheduler::is_initialized()
+        && PENDING.swap(false, Ordering::AcqRel)
+    {
+        deferred::wake();
+    }
+}
+
+// Records the crossings of the current usage.
+fn update() {
+    let info = super::memory_info();
+    if info.total == 0 {
+        return;
+    }
+    // In u64, `used * 100` would overflow usize on 32-bit targets.
+    let usage = (info.used as u64 * 100 / info.total as u64) as u8;
+    let mut w = WATERMARKS.irqsave_lock();
+    for wm in w.iter_mut().flatten() {
+        let Some(rising) = wm.update(usage) else {
+            continue;
+        };
+        if rising {
+            events::count(Event::HeapWatermark);
+        }
+        wm.pending = match wm.pending {
+            // Undone before the callback ran.
+            Some(_) => None,
+            None => Some((rising, info.used, info.max_used)),
+        };
+        PENDING.store(true, Ordering::Release);
+    }
+}
+
+/// Calls the callbacks of the crossings recorded since the last call. Run
+/// by the reclaimer thread.
+pub(super) fn run_callbacks() {
+    let total = super::memory_info().total;
+    let mut fired: [Option<(WatermarkCallback, WatermarkEvent)>; MAX_WATERMARKS] =
+        [const { None }; MAX_WATERMARKS];
+    {
+        let mut w = WATERMARKS.irqsave_lock();
+        for (i, wm) in w.iter_mut().enumerate() {
+            let Some(wm) = wm else {
+                continue;
+            };
+            if let Some((rising, used, max_used)) = wm.pending.take() {
+                let info = MemoryInfo {
+                    total,
+                    used,
+                    max_used,
+                };
+                fired[i] = Some((
+                    wm.callback,
+                    WatermarkEvent {
+                        threshold: wm.threshold,
+                        rising,
+                        info,
+                    },
+                ));
+            }
+        }
+    }
+    // Callbacks are invoked without the registry lock held, so that
+    // they are allowed to register or unregister watermarks.
+    for (callback, event) in fired.iter().flatten() {
+        callback(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn nop(_: &WatermarkEvent) {}
+
+    #[test]
+    fn test_watermark_crossing() {
+        let mut wm = Watermark::new(80, nop);
+        assert_eq!(wm.update(10), None);
+        assert_eq!(wm.update(80), Some(true));
+        assert_eq!(wm.update(90), None);
+        // Still inside the hysteresis band.
+        assert_eq!(wm.update(76), None);
+        assert_eq!(wm.update(74), Some(false));
+        assert_eq!(wm.update(74), None);
+        assert_eq!(wm.update(85), Some(true));
+    }
+
+    static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_fired(event: &WatermarkEvent) {
+        if event.rising {
+            FIRED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_watermark_deferred() {
+        let info = crate::allocator::memory_info();
+        let usage = (info.used as u64 * 100 / info.total as u64) as u8;
+        if usage == 0 {
+            return;
+        }
+        // Crossed right away.
+        let id = register(usage, count_fired).unwrap();
+        let crossings = events::get(Event::HeapWatermark);
+        check();
+        assert!(events::get(Event::HeapWatermark) > crossings);
+        // Only the reclaimer runs the callback, running them here races
+        // with it.
+        run_callbacks();
+        let mut fired = FIRED.load(Ordering::Relaxed);
+        for _ in 0..100 {
+            if fired != 0 {
+                break;
+            }
+            scheduler::yield_me();
+            fired = FIRED.load(Ordering::Relaxed);
+        }
+        assert_eq!(fired, 1);
+        // Still above, so nothing more is reported.
+        check();
+        run_callbacks();
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+        assert_eq!(unregister(id), Ok(()));
+    }
+
+    #[test]
+    fn test_watermark_register() {
+        assert_eq!(register(0, nop), Err(code::EINVAL));
+        assert_eq!(register(101, nop), Err(code::EINVAL));
+        let id = register(100, nop).unwrap();
+        assert_eq!(unregister(id), Ok(()));
+        assert_eq!(unregister(id), Err(code::ENOENT));
+        assert_eq!(unregister(MAX_WATERMARKS), Err(code::EINVAL));
+    }
+
+    static SHRUNK: AtomicUsize = AtomicUsize::new(0);
+
+    fn shrink_cache() -> usize {
+        SHRUNK.fetch_add(1, Ordering::Relaxed);
+        64
+    }
+
+    #[test]
+    fn test_shrinker() {
+        let id = register_shrinker(shrink_cache).unwrap();
+        let shrunk = SHRUNK.load(Ordering::Relaxed);
+        assert!(shrink() >= 64);
+        assert_eq!(SHRUNK.load(Ordering::Relaxed), shrunk + 1);
+        let info = MemoryInfo {
+            total: 100,
+            used: 0,
+            max_used: 0,
+        };
+        shrink_watermark(&WatermarkEvent {
+            threshold: 80,
+            rising: false,
+            info,
+        });
+        assert_eq!(SHRUNK.load(Ordering::Relaxed), shrunk + 1);
+        assert_eq!(unregister_shrinker(id), Ok(()));
+        assert_eq!(unregister_shrinker(id), Err(code::ENOENT));
+        assert_eq!(unregister_shrinker(MAX_SHRINKERS), Err(code::EINVAL));
+    }
+}
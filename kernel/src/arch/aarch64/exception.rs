@@ -15,6 +15,7 @@
 use super::{irq, registers::esr_el1::ESR_EL1, Context, NR_SWITCH};
 use crate::{
     arch::aarch64::{disable_local_irq, enable_local_irq},
+    irq::IrqTrace,
     scheduler::{self, ContextSwitchHookHolder},
     support::sideeffect,
     syscalls::{dispatch_syscall, Context as ScContext},
@@ -176,7 +177,12 @@ extern "C" fn trap_exception(context: &mut Context) -> usize {
 extern "C" fn trap_irq(context: &mut Context) -> usize {
     let sp = context as *const _ as usize;
     let irq = irq::get_interrupt();
-    irq::trigger_irq(irq);
+    {
+        let _trace = IrqTrace::new(irq);
+        #[cfg(nested_irq)]
+        let _nested = irq::NestedIrqGuard::new();
+        let _ = irq::trigger_irq(irq);
+    }
     irq::end_interrupt(irq);
     sp
 }
@@ -14,11 +14,15 @@
 
 extern crate alloc;
 
-use crate::{arch::current_cpu_id, sync::SpinLock};
+#[cfg(nested_irq)]
+use super::{disable_local_irq, enable_local_irq};
+use crate::{
+    arch::current_cpu_id,
+    sync::{KOnce, SpinLock},
+};
 use alloc::boxed::Box;
 pub use arm_gic::Trigger as IrqTrigger;
 use arm_gic::{gicv3::*, IntId};
-use spin::Once;
 use tock_registers::interfaces::Readable;
 
 // aarch64 irq priority is 0-255
@@ -37,7 +41,7 @@ const SPI_START: u32 = 32;
 const SPECIAL_START: u32 = 1020;
 const SPECIAL_END: u32 = 1024;
 
-static GIC: Once<SpinLock<GicV3>> = Once::new();
+static GIC: KOnce<SpinLock<GicV3>> = KOnce::new();
 
 #[derive(Debug, Copy, Clone, Eq, Ord, PartialOrd, PartialEq)]
 #[repr(transparent)]
@@ -116,34 +120,36 @@ impl IrqContext {
     }
 }
 
+// Each context has its own lock, so that a handler can be preempted by the
+// handler of a higher priority interrupt. The GIC never signals an active
+// interrupt again before it is ended, so a context can't be re-entered.
 pub struct IrqManager {
-    pub contexts: [Option<IrqContext>; INTERRUPT_TABLE_LEN],
+    pub contexts: [SpinLock<Option<IrqContext>>; INTERRUPT_TABLE_LEN],
 }
 
-pub static IRQ_MANAGER: SpinLock<IrqManager> = SpinLock::new(IrqManager::new());
+pub static IRQ_MANAGER: IrqManager = IrqManager::new();
 
 impl IrqManager {
     const fn new() -> Self {
-        const NONE_CONTEXT: Option<IrqContext> = None;
         Self {
-            contexts: [NONE_CONTEXT; INTERRUPT_TABLE_LEN],
+            contexts: [const { SpinLock::new(None) }; INTERRUPT_TABLE_LEN],
         }
     }
 
     fn register_handler(
-        &mut self,
+        &self,
         irq: IrqNumber,
         handler: Box<dyn IrqHandler>,
     ) -> Result<(), &'static str> {
         if u32::from(irq) >= INTERRUPT_TABLE_LEN as u32 {
             return Err("IRQ number out of range");
         }
-        self.contexts[usize::from(irq)] = Some(IrqContext::new(irq, handler));
+        *self.contexts[usize::from(irq)].irqsave_lock() = Some(IrqContext::new(irq, handler));
         Ok(())
     }
 
-    fn trigger_irq(&mut self, irq: IrqNumber) -> Result<(), &'static str> {
-        if let Some(context) = &mut self.contexts[usize::from(irq)] {
+    fn trigger_irq(&self, irq: IrqNumber) -> Result<(), &'static str> {
+        if let Some(context) = &mut *self.contexts[usize::from(irq)].lock() {
             context.handler.handle();
             return Ok(());
         }
@@ -153,12 +159,12 @@ impl IrqManager {
 
 // Register interrupt handler
 pub fn register_handler(irq: IrqNumber, handler: Box<dyn IrqHandler>) -> Result<(), &'static str> {
-    IRQ_MANAGER.lock().register_handler(irq, handler)
+    IRQ_MANAGER.register_handler(irq, handler)
 }
 
 // Trigger interrupt
 pub fn trigger_irq(irq: IrqNumber) -> Result<(), &'static str> {
-    IRQ_MANAGER.lock().trigger_irq(irq)
+    IRQ_MANAGER.trigger_irq(irq)
 }
 
 // enable interrupt
@@ -193,6 +199,46 @@ pub fn set_priority_mask(priority: u8) {
     GicV3::set_priority_mask(priority);
 }
 
+// Get priority mask for current CPU
+pub fn priority_mask() -> u8 {
+    let pmr: u64;
+    unsafe { core::arch::asm!("mrs {}, icc_pmr_el1", out(reg) pmr, options(nostack, nomem)) };
+    pmr as u8
+}
+
+// Get priority of the interrupt being handled on current CPU, 0xff if none
+pub fn running_priority() -> u8 {
+    let rpr: u64;
+    unsafe { core::arch::asm!("mrs {}, icc_rpr_el1", out(reg) rpr, options(nostack, nomem)) };
+    rpr as u8
+}
+
+/// Lets interrupts of higher priority than the one being handled preempt
+/// its handler, until dropped. Must be created after the interrupt has been
+/// acknowledged and dropped before it is ended.
+#[cfg(nested_irq)]
+pub struct NestedIrqGuard {
+    priority_mask: u8,
+}
+
+#[cfg(nested_irq)]
+impl NestedIrqGuard {
+    pub fn new() -> Self {
+        let priority_mask = priority_mask();
+        set_priority_mask(running_priority());
+        enable_local_irq();
+        Self { priority_mask }
+    }
+}
+
+#[cfg(nested_irq)]
+impl Drop for NestedIrqGuard {
+    fn drop(&mut self) {
+        disable_local_irq();
+        set_priority_mask(self.priority_mask);
+    }
+}
+
 // Configures the trigger type for the interrupt with the given ID
 pub fn set_trigger(irq: IrqNumber, cpu_id: usize, trigger: IrqTrigger) {
     get_gic()
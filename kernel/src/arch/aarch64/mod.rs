@@ -394,6 +394,15 @@ pub(crate) extern "C" fn current_sp() -> usize {
     x
 }
 
+/// The address the current exception returns to. Only meaningful in a
+/// handler.
+#[inline]
+pub(crate) fn interrupted_pc() -> Option<usize> {
+    let x: usize;
+    unsafe { core::arch::asm!("mrs {}, elr_el1", out(reg) x, options(nostack, nomem)) };
+    Some(x)
+}
+
 #[inline]
 pub extern "C" fn disable_local_irq_save() -> usize {
     let old: usize;
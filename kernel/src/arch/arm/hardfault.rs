@@ -13,6 +13,10 @@
 // limitations under the License.
 
 use super::{xpsr, IsrContext};
+use crate::{
+    scheduler,
+    thread::{Thread, ThreadKind, ThreadNode},
+};
 use core::fmt;
 use cortex_m::peripheral::SCB;
 
@@ -213,21 +217,131 @@ impl fmt::Display for HardFaultRegs {
     }
 }
 
-pub(crate) extern "C" fn panic_on_hardfault(ctx: &IsrContext) {
+// Exception numbers, as found in IPSR.
+const NMI: u32 = 2;
+const HARD_FAULT: u32 = 3;
+const MEM_MANAGE: u32 = 4;
+const BUS_FAULT: u32 = 5;
+const USAGE_FAULT: u32 = 6;
+
+// HFSR.FORCED: a configurable fault was escalated to HardFault.
+const HFSR_FORCED: u32 = 1 << 30;
+// CFSR bits telling that the exception frame couldn't be stacked.
+#[cfg(not(armv8m))]
+const CFSR_STACKING_ERRORS: u32 = (1 << 4) | (1 << 12);
+#[cfg(armv8m)]
+const CFSR_STACKING_ERRORS: u32 = (1 << 4) | (1 << 12) | (1 << 20);
+
+// EXC_RETURN bits telling the exception was taken from thread mode, with
+// the process stack.
+const EXC_RETURN_THREAD_PSP: usize = 0b1100;
+
+fn exception_name(number: u32) -> &'static str {
+    match number {
+        NMI => "NMI",
+        HARD_FAULT => "HARD FAULT",
+        MEM_MANAGE => "MEMMANAGE FAULT",
+        BUS_FAULT => "BUS FAULT",
+        USAGE_FAULT => "USAGE FAULT",
+        _ => "UNKNOWN EXCEPTION",
+    }
+}
+
+// The thread running when the fault was taken, if it was taken from thread
+// mode.
+struct FaultingThread(Option<ThreadNode>);
+
+impl fmt::Display for FaultingThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(t) = &self.0 else {
+            return write!(f, "none, in handler mode");
+        };
+        let name = t.name();
+        let name = match &*name {
+            "" => t.kind_to_str(),
+            name => name,
+        };
+        write!(f, "{} (0x{:x})", name, Thread::id(t))
+    }
+}
+
+// Whether the faulting thread can be killed while the rest of the system
+// keeps running. This needs the fault to come from a normal thread whose
+// exception frame was stacked successfully, and which had interrupts
+// enabled: inside an irqsave section it may hold spin locks nobody would
+// ever release.
+fn is_recoverable(
+    number: u32,
+    regs: &HardFaultRegs,
+    thread: &FaultingThread,
+    irqs_enabled: bool,
+) -> bool {
+    let escalated = number == HARD_FAULT && regs.hfsr & HFSR_FORCED != 0;
+    irqs_enabled
+        && (matches!(number, MEM_MANAGE | BUS_FAULT | USAGE_FAULT) || escalated)
+        && regs.cfsr & CFSR_STACKING_ERRORS == 0
+        && thread
+            .0
+            .as_ref()
+            .is_some_and(|t| t.kind() == ThreadKind::Normal)
+}
+
+// The faulting thread resumes here, in thread mode, and retires itself.
+extern "C" fn retire_faulting_thread() -> ! {
+    scheduler::retire_me()
+}
+
+extern "C" fn handle_fault(ctx: &mut IsrContext, exc_return: usize) {
+    // BASEPRI isn't stacked, so it still tells whether the thread was
+    // inside an irqsave section.
+    let irqs_enabled = super::local_irq_enabled();
     super::disable_local_irq();
     let fault_regs: HardFaultRegs = HardFaultRegs::from_scb();
     let xpsr = xpsr::read();
+    let number = xpsr.e();
+    let thread = FaultingThread(
+        (exc_return & EXC_RETURN_THREAD_PSP == EXC_RETURN_THREAD_PSP)
+            .then(scheduler::current_thread),
+    );
+    if cfg!(fault_recovery) && is_recoverable(number, &fault_regs, &thread, irqs_enabled) {
+        log::error!(
+            "==== {} ==== in thread {}, killing it\nFRAME: {:?}\nFAULT REGS: {}",
+            exception_name(number),
+            thread,
+            ctx,
+            fault_regs,
+        );
+        // SAFETY: SCB::PTR comes from cortex_m crate and is a valid pointer.
+        // Status bits are cleared by writing ones.
+        unsafe {
+            let scb = &*SCB::PTR;
+            scb.cfsr.write(fault_regs.cfsr);
+            scb.hfsr.write(fault_regs.hfsr);
+        }
+        ctx.pc = retire_faulting_thread as usize;
+        // Only keep the Thumb bit.
+        ctx.xpsr = 1 << 24;
+        super::enable_local_irq();
+        return;
+    }
     panic!(
         "
-        ==== HARD FAULT ====
+        ==== {} ====
+        THREAD: {}
         FRAME: {:?}
         FAULT REGS: {}
         XPSR: {}
         ",
-        ctx, fault_regs, xpsr,
+        exception_name(number),
+        thread,
+        ctx,
+        fault_regs,
+        xpsr,
     );
 }
 
+// Entry of NMI and of all the faults. The handler may rewrite the stacked
+// frame to resume somewhere else, so we return through EXC_RETURN.
 #[naked]
 pub(crate) unsafe extern "C" fn handle_hardfault() {
     core::arch::naked_asm!(
@@ -237,8 +351,11 @@ pub(crate) unsafe extern "C" fn handle_hardfault() {
         beq 1f
         mrs r0, psp
         1:
-        bl {panic}
+        mov r1, lr
+        push {{r1, lr}}
+        bl {handler}
+        pop {{r1, pc}}
         ",
-        panic = sym panic_on_hardfault
+        handler = sym handle_fault
     )
 }
@@ -12,7 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use cortex_m::{interrupt::InterruptNumber, peripheral::scb::SystemHandler, Peripherals};
+use cortex_m::{
+    interrupt::InterruptNumber,
+    peripheral::scb::{Exception, SystemHandler},
+    Peripherals,
+};
 
 #[cfg(irq_priority_bits_2)]
 pub const IRQ_PRIORITY_STEP: u8 = 0x40;
@@ -65,6 +69,11 @@ pub fn init() {
         scb.SCB.set_priority(SystemHandler::SVCall, SVC_PRIORITY);
         scb.SCB
             .set_priority(SystemHandler::PendSV, IRQ_PRIORITY_FOR_SCHEDULER);
+        // Take configurable faults on their own vectors, rather than as
+        // HardFaults, so that they can be told apart.
+        scb.SCB.enable(Exception::MemoryManagement);
+        scb.SCB.enable(Exception::BusFault);
+        scb.SCB.enable(Exception::UsageFault);
     }
 }
 
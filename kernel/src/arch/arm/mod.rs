@@ -348,6 +348,15 @@ pub unsafe extern "C" fn handle_pendsv() {
     )
 }
 
+// The SysTick exception doesn't go through an IRQ trace like the other
+// handlers, the hard timers it runs must still see that they're in
+// interrupt context.
+pub extern "C" fn handle_systick() {
+    crate::irq::enter_irq();
+    crate::time::handle_tick_increment();
+    crate::irq::leave_irq();
+}
+
 impl Context {
     #[inline(never)]
     pub fn set_return_address(&mut self, pc: usize) -> &mut Self {
@@ -434,9 +443,25 @@ pub extern "C" fn enable_local_irq_restore(old: usize) {
     }
 }
 
+// WFI is not woken up by interrupts masked by BASEPRI, so they are masked
+// by PRIMASK instead while waiting. Callers may then disable interrupts
+// around idle() and handle the interrupt which woke the core up later.
 #[inline]
 pub extern "C" fn idle() {
-    unsafe { core::arch::asm!("wfi") }
+    unsafe {
+        core::arch::asm!(
+            "
+            cpsid i
+            mrs {old}, basepri
+            msr basepri, {zero}
+            wfi
+            msr basepri, {old}
+            cpsie i
+            ",
+            old = out(reg) _,
+            zero = in(reg) 0,
+        )
+    }
 }
 
 #[inline]
@@ -460,6 +485,21 @@ pub extern "C" fn current_psp() -> usize {
     x
 }
 
+/// The address the current exception returns to, if it interrupted a
+/// thread rather than another exception. Only meaningful in a handler.
+pub(crate) fn interrupted_pc() -> Option<usize> {
+    const ICSR_RETTOBASE: u32 = 1 << 11;
+    // SAFETY: SCB::PTR comes from cortex_m crate and is a valid pointer.
+    let scb = unsafe { &*SCB::PTR };
+    if scb.icsr.read() & ICSR_RETTOBASE == 0 {
+        return None;
+    }
+    // Threads run on the PSP, where the exception frame holds the PC after
+    // r0-r3, r12 and lr.
+    let frame = current_psp() as *const usize;
+    Some(unsafe { frame.add(6).read() })
+}
+
 #[naked]
 pub extern "C" fn switch_context_with_hook(
     saved_sp_mut: *mut u8,
@@ -19,11 +19,13 @@ use crate::{irq as sysirq, scheduler, scheduler::ContextSwitchHookHolder};
 use blueos_kconfig::NUM_CORES;
 use core::{
     mem::offset_of,
-    sync::atomic::{compiler_fence, AtomicU8, Ordering},
+    sync::atomic::{compiler_fence, AtomicU8, AtomicUsize, Ordering},
 };
 pub use trap::*;
 
 pub(crate) static READY_CORES: AtomicU8 = AtomicU8::new(0);
+/// The device tree QEMU passes in a1 at reset, saved by `arch_bootstrap!`.
+pub static BOOT_FDT: AtomicUsize = AtomicUsize::new(0);
 pub(crate) const NR_SWITCH: usize = !0;
 
 // See https://five-embeddev.com/riscv-priv-isa-manual/Priv-v1.12/machine.html#machine-status-registers-mstatus-and-mstatush
@@ -59,6 +61,8 @@ macro_rules! arch_bootstrap {
     ($stack_start:path, $stack_end:path, $cont: path) => {
         core::arch::naked_asm!(
             "csrci mstatus, 0x8",
+            "la t0, {boot_fdt}",
+            "sd a1, 0(t0)",
             "csrr t0, mhartid",
             "la sp, {stack_end}",
             "slli t0, t0, 14",
@@ -68,6 +72,7 @@ macro_rules! arch_bootstrap {
             "jalr x0, t0, 0",
             stack_end = sym $stack_end,
             bootstrap = sym $crate::arch::riscv64::bootstrap,
+            boot_fdt = sym $crate::arch::riscv64::BOOT_FDT,
             cont = sym $cont,
         );
     }
@@ -234,6 +239,14 @@ pub(crate) extern "C" fn current_sp() -> usize {
     x
 }
 
+/// The address the current trap returns to. Only meaningful in a handler.
+#[inline]
+pub(crate) fn interrupted_pc() -> Option<usize> {
+    let x: usize;
+    unsafe { core::arch::asm!("csrr {}, mepc", out(reg) x, options(nostack, nomem)) };
+    Some(x)
+}
+
 #[inline(always)]
 pub(crate) extern "C" fn switch_context(saved_sp_mut: *mut u8, to_sp: usize) {
     switch_context_with_hook(saved_sp_mut, to_sp, core::ptr::null_mut());
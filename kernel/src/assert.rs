@@ -0,0 +1,321 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Assertions and warnings which record where they fired and from which
+//! thread.
+//!
+//! A failed [`kassert!`] panics, so that it goes through the panic
+//! reporting path. The `kdebug_assert` flavors are only checked with debug
+//! assertions, for checks too costly for hot paths in release builds.
+//! [`kwarn_once!`] and [`kwarn_ratelimited!`] go through the logger, and
+//! count how often their call site is hit so that a noisy warning doesn't
+//! flood the log. [`ratelimit!`] applies the same limit to anything else a
+//! noisy path does.
+//!
+//! Reports format through the logger, so the allocators and the scheduler
+//! internals they rely on keep using core's assertions.
+
+use crate::{irq, scheduler, thread::Thread, time::tick_get_millisecond};
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Minimum interval between two reports of a [`kwarn_ratelimited!`] site.
+pub const WARN_INTERVAL_MS: usize = 5000;
+
+/// Asserts that a condition holds, in all builds. The failure report
+/// carries the location, the thread and an optional message.
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            $crate::assert::assert_failed(file!(), line!(), stringify!($cond), None);
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::assert::assert_failed(
+                file!(),
+                line!(),
+                stringify!($cond),
+                Some(format_args!($($arg)+)),
+            );
+        }
+    };
+}
+
+/// Asserts that two expressions are equal, in all builds. Both values are
+/// printed when they differ.
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    $crate::assert::assert_failed(
+                        file!(),
+                        line!(),
+                        concat!(stringify!($left), " == ", stringify!($right)),
+                        Some(format_args!("left: {:?}, right: {:?}", left, right)),
+                    );
+                }
+            }
+        }
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    $crate::assert::assert_failed(
+                        file!(),
+                        line!(),
+                        concat!(stringify!($left), " == ", stringify!($right)),
+                        Some(format_args!(
+                            "left: {:?}, right: {:?}: {}",
+                            left,
+                            right,
+                            format_args!($($arg)+)
+                        )),
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Asserts that two expressions differ, in all builds.
+#[macro_export]
+macro_rules! kassert_ne {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left == *right {
+                    $crate::assert::assert_failed(
+                        file!(),
+                        line!(),
+                        concat!(stringify!($left), " != ", stringify!($right)),
+                        Some(format_args!("both: {:?}", left)),
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Like [`kassert!`], but only checked with debug assertions.
+#[macro_export]
+macro_rules! kdebug_assert {
+    ($($arg:tt)+) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert!($($arg)+);
+        }
+    };
+}
+
+/// Like [`kassert_eq!`], but only checked with debug assertions.
+#[macro_export]
+macro_rules! kdebug_assert_eq {
+    ($($arg:tt)+) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert_eq!($($arg)+);
+        }
+    };
+}
+
+/// Like [`kassert_ne!`], but only checked with debug assertions.
+#[macro_export]
+macro_rules! kdebug_assert_ne {
+    ($($arg:tt)+) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert_ne!($($arg)+);
+        }
+    };
+}
+
+/// Logs a warning the first time this call site is hit.
+#[macro_export]
+macro_rules! kwarn_once {
+    ($($arg:tt)+) => {{
+        static SITE: $crate::assert::WarnSite = $crate::assert::WarnSite::new(file!(), line!());
+        if let Some(suppressed) = SITE.hit_once() {
+            SITE.warn(suppressed, format_args!($($arg)+));
+        }
+    }};
+}
+
+/// Logs a warning at most once every [`WARN_INTERVAL_MS`] for this call
+/// site, along with the number of hits which weren't logged.
+#[macro_export]
+macro_rules! kwarn_ratelimited {
+    ($($arg:tt)+) => {{
+        static SITE: $crate::assert::WarnSite = $crate::assert::WarnSite::new(file!(), line!());
+        if let Some(suppressed) = SITE.hit_ratelimited($crate::assert::WARN_INTERVAL_MS) {
+            SITE.warn(suppressed, format_args!($($arg)+));
+        }
+    }};
+}
+
+/// Evaluates to `Some` with the number of hits skipped since the last one
+/// let through, at most once every `interval_ms` for this call site, and to
+/// `None` otherwise.
+#[macro_export]
+macro_rules! ratelimit {
+    ($interval_ms:expr) => {{
+        static SITE: $crate::assert::WarnSite = $crate::assert::WarnSite::new(file!(), line!());
+        SITE.hit_ratelimited($interval_ms)
+    }};
+}
+
+// Names the current thread in reports, or the context when there is no
+// thread to name.
+struct CurrentThread;
+
+impl fmt::Display for CurrentThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !scheduler::is_initialized() {
+            return f.write_str("early boot");
+        }
+        if irq::is_in_irq() {
+            return f.write_str("irq");
+        }
+        let t = scheduler::current_thread();
+        let name = t.name();
+        let name = match &*name {
+            "" => t.kind_to_str(),
+            name => name,
+        };
+        write!(f, "{} (0x{:x})", name, Thread::id(&t))
+    }
+}
+
+/// Call site of a warning macro.
+#[derive(Debug)]
+pub struct WarnSite {
+    file: &'static str,
+    line: u32,
+    hits: AtomicUsize,
+    // Hits when the site was last reported.
+    reported: AtomicUsize,
+    last_ms: AtomicUsize,
+}
+
+impl WarnSite {
+    pub const fn new(file: &'static str, line: u32) -> Self {
+        Self {
+            file,
+            line,
+            hits: AtomicUsize::new(0),
+            reported: AtomicUsize::new(0),
+            last_ms: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of times the site has been hit.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Counts a hit. Returns the number of hits which weren't reported if
+    /// this one must be.
+    pub fn hit_once(&self) -> Option<usize> {
+        (self.hits.fetch_add(1, Ordering::Relaxed) == 0).then_some(0)
+    }
+
+    /// Counts a hit. Returns the number of hits which weren't reported if
+    /// this one must be, which happens at most once every `interval_ms`.
+    pub fn hit_ratelimited(&self, interval_ms: usize) -> Option<usize> {
+        let hits = self.hits.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = tick_get_millisecond();
+        let last = self.last_ms.load(Ordering::Relaxed);
+        if hits > 1 && now.wrapping_sub(last) < interval_ms {
+            return None;
+        }
+        // Another CPU may be reporting the same site.
+        self.last_ms
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .ok()?;
+        let reported = self.reported.swap(hits, Ordering::Relaxed);
+        Some(hits.saturating_sub(reported + 1))
+    }
+
+    pub fn warn(&self, suppressed: usize, args: fmt::Arguments) {
+        if suppressed == 0 {
+            log::warn!("{}:{} in {}: {}", self.file, self.line, CurrentThread, args);
+        } else {
+            log::warn!(
+                "{}:{} in {}: {} ({} more not reported)",
+                self.file,
+                self.line,
+                CurrentThread,
+                args,
+                suppressed
+            );
+        }
+    }
+}
+
+#[cold]
+#[inline(never)]
+pub fn assert_failed(
+    file: &'static str,
+    line: u32,
+    cond: &'static str,
+    args: Option<fmt::Arguments>,
+) -> ! {
+    match args {
+        Some(args) => panic!(
+            "assertion `{}` failed at {}:{} in {}: {}",
+            cond, file, line, CurrentThread, args
+        ),
+        None => panic!(
+            "assertion `{}` failed at {}:{} in {}",
+            cond, file, line, CurrentThread
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_warn_site_hits() {
+        let site = WarnSite::new(file!(), line!());
+        assert_eq!(site.hit_once(), Some(0));
+        assert_eq!(site.hit_once(), None);
+        assert_eq!(site.hits(), 2);
+
+        let site = WarnSite::new(file!(), line!());
+        assert_eq!(site.hit_ratelimited(usize::MAX), Some(0));
+        assert_eq!(site.hit_ratelimited(usize::MAX), None);
+        assert_eq!(site.hit_ratelimited(usize::MAX), None);
+        // Everything is reported without an interval.
+        assert_eq!(site.hit_ratelimited(0), Some(2));
+        assert_eq!(site.hits(), 4);
+    }
+
+    #[test]
+    fn test_ratelimit() {
+        let mut passed = 0;
+        for _ in 0..3 {
+            if let Some(skipped) = crate::ratelimit!(usize::MAX) {
+                assert_eq!(skipped, 0);
+                passed += 1;
+            }
+        }
+        assert_eq!(passed, 1);
+    }
+}
@@ -12,24 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-// Asynk contains a simple executor, however runs fast.
+//! Asynk runs kernel futures on a single poller thread, so that protocol
+//! stacks can be written as state machines instead of a thread per
+//! connection.
+//!
+//! Each tasklet has its own [`Waker`]. Waking it queues the tasklet on the
+//! run queue and wakes the poller, which polls only the queued tasklets.
+//! Wakers can be called from interrupt handlers, and files can wake them
+//! through [`PollWaiter`](crate::vfs::poll::PollWaiter), see
+//! [`poll_file`](crate::vfs::poll::poll_file).
 
 extern crate alloc;
 use crate::{
     config::MAX_THREAD_PRIORITY,
     scheduler, static_arc,
-    support::ArcBufferingQueue,
-    sync::{atomic_wait, ISpinLock, SpinLockGuard},
+    sync::{atomic_wait, ISpinLock, SpinLock, SpinLockGuard},
     thread::{self, Entry, SystemThreadStorage, ThreadKind, ThreadNode},
-    types::{impl_simple_intrusive_adapter, Arc, IlistHead},
+    types::{impl_simple_intrusive_adapter, Arc, ArcList, IlistHead},
 };
 use alloc::boxed::Box;
 use core::{
     future::Future,
-    mem::MaybeUninit,
+    mem::{ManuallyDrop, MaybeUninit},
     pin::Pin,
-    sync::atomic::{AtomicUsize, Ordering},
-    task::{Context, Poll, Waker},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
 };
 
 impl_simple_intrusive_adapter!(TaskletNode, Tasklet, node);
@@ -38,35 +45,67 @@ impl_simple_intrusive_adapter!(TaskletLock, Tasklet, lock);
 pub struct Tasklet {
     node: IlistHead<Tasklet, TaskletNode>,
     lock: ISpinLock<Tasklet, TaskletLock>,
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    // None once it has completed, or while it's being polled.
+    future: Option<Pin<Box<dyn Future<Output = ()>>>>,
     blocked: Option<ThreadNode>,
+    // Whether it's on the run queue, so that it's queued once however many
+    // times it's woken.
+    queued: AtomicBool,
 }
 
 impl Tasklet {
     pub fn new(future: Pin<Box<dyn Future<Output = ()>>>) -> Self {
         Self {
             node: IlistHead::new(),
-            future,
+            future: Some(future),
             lock: ISpinLock::new(),
             blocked: None,
+            queued: AtomicBool::new(false),
         }
     }
 
     pub fn lock(&self) -> SpinLockGuard<'_, Tasklet> {
         self.lock.irqsave_lock()
     }
+
+    fn waker(this: &Arc<Self>) -> Waker {
+        let ptr = Arc::into_raw(this.clone()) as *const ();
+        // SAFETY: the vtable keeps the reference count of the tasklet.
+        unsafe { Waker::from_raw(RawWaker::new(ptr, &WAKER_VTABLE)) }
+    }
+}
+
+static WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const Tasklet));
+    let _ = Arc::into_raw((*task).clone());
+    RawWaker::new(ptr, &WAKER_VTABLE)
+}
+
+unsafe fn wake(ptr: *const ()) {
+    enqueue_active_tasklet(Arc::from_raw(ptr as *const Tasklet));
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const Tasklet));
+    enqueue_active_tasklet((*task).clone());
+}
+
+unsafe fn drop_waker(ptr: *const ()) {
+    drop(Arc::from_raw(ptr as *const Tasklet));
 }
 
-type AsyncWorkQueue = ArcBufferingQueue<Tasklet, TaskletNode, 2>;
 static POLLER_STORAGE: SystemThreadStorage = SystemThreadStorage::new(ThreadKind::AsyncPoller);
 static mut POLLER: MaybeUninit<ThreadNode> = MaybeUninit::zeroed();
 static POLLER_WAKER: AtomicUsize = AtomicUsize::new(0);
 static_arc! {
-    ASYNC_WORK_QUEUE(AsyncWorkQueue, AsyncWorkQueue::new()),
+    RUN_QUEUE(SpinLock<ArcList<Tasklet, TaskletNode>>, SpinLock::new(ArcList::new())),
 }
 
 pub(crate) fn init() {
-    ASYNC_WORK_QUEUE.init_queues();
+    RUN_QUEUE.irqsave_lock().init();
     let poller = thread::build_static_thread(
         unsafe { &mut POLLER },
         &POLLER_STORAGE,
@@ -76,7 +115,7 @@ pub(crate) fn init() {
         ThreadKind::AsyncPoller,
     );
     let ok = scheduler::queue_ready_thread(thread::CREATED, poller);
-    debug_assert!(ok);
+    crate::kassert!(ok, "async poller can't be queued");
 }
 
 fn create_tasklet(future: impl Future<Output = ()> + 'static) -> Arc<Tasklet> {
@@ -86,74 +125,134 @@ fn create_tasklet(future: impl Future<Output = ()> + 'static) -> Arc<Tasklet> {
 
 pub fn block_on(future: impl Future<Output = ()> + Send + 'static) {
     let t = scheduler::current_thread();
-    let mut task = create_tasklet(future);
+    let task = create_tasklet(future);
     task.lock().blocked = Some(t.clone());
+    t.start_waiting(thread::WaitReason::Async);
     scheduler::suspend_me_with_hook(move || {
         let ok = t.transfer_state(thread::RUNNING, thread::SUSPENDED);
         assert!(ok);
-        enqueue_active_tasklet(task);
         #[cfg(debugging_scheduler)]
         crate::trace!(
             "[TH:0x{:x}] is waking up the poller",
             scheduler::current_thread_id()
         );
-        wake_poller();
+        enqueue_active_tasklet(task);
     });
 }
 
 fn wake_poller() {
     POLLER_WAKER.fetch_add(1, Ordering::Release);
-    atomic_wait::atomic_wake(&POLLER_WAKER, 1);
+    let _ = atomic_wait::atomic_wake(&POLLER_WAKER, 1);
 }
 
 pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> Arc<Tasklet> {
     let task = create_tasklet(future);
     enqueue_active_tasklet(task.clone());
-    wake_poller();
     task
 }
 
+/// Queues `t` to be polled and wakes the poller, unless it's queued
+/// already. Can be called from interrupt context.
 pub fn enqueue_active_tasklet(t: Arc<Tasklet>) {
+    if t.queued.swap(true, Ordering::AcqRel) {
+        return;
+    }
     #[cfg(debugging_scheduler)]
     crate::trace!(
         "[TH:0x{:x}] is enqueuing tasklet",
         scheduler::current_thread_id()
     );
-    let mut q = ASYNC_WORK_QUEUE.get_active_queue();
-    let _guard = t.lock();
-    q.push_back(t.clone());
-    #[cfg(debugging_scheduler)]
-    crate::trace!(
-        "[TH:0x{:x}] has enqueued tasklet",
-        scheduler::current_thread_id()
-    );
+    RUN_QUEUE.irqsave_lock().push_back(t);
+    wake_poller();
 }
 
-fn poll_inner() {
-    let mut ctx = Context::from_waker(Waker::noop());
-    let mut w = ASYNC_WORK_QUEUE.advance_active_queue();
-    for mut task in w.iter() {
-        let mut l = task.lock();
-        if let Poll::Ready(()) = l.future.as_mut().poll(&mut ctx) {
-            if let Some(t) = l.blocked.take() {
-                scheduler::queue_ready_thread(thread::SUSPENDED, t);
-            }
-            // If we detach the task what ever it's ready or
-            // pending, it would be edge-level triggered. Now
-            // we're using level-trigger mode conservatively.
-            AsyncWorkQueue::WorkList::detach(&mut task.clone());
-        } else {
-            // FIXME: This is not an efficient impl right now. We
-            // might need a waker for each future, so that the poller
-            // doesn't need to poll all futures when woken up.
-        }
+fn next_tasklet() -> Option<Arc<Tasklet>> {
+    RUN_QUEUE.irqsave_lock().pop_front()
+}
+
+fn run(task: Arc<Tasklet>) {
+    // Cleared first, so that a wake while it's polled queues it again.
+    task.queued.store(false, Ordering::Release);
+    // Taken out to be polled with interrupts enabled. Only the poller
+    // polls, nobody puts it back in between.
+    let Some(mut future) = task.lock().future.take() else {
+        return;
+    };
+    let waker = Tasklet::waker(&task);
+    let mut ctx = Context::from_waker(&waker);
+    let ready = future.as_mut().poll(&mut ctx).is_ready();
+    let mut l = task.lock();
+    if !ready {
+        l.future = Some(future);
+        return;
+    }
+    if let Some(t) = l.blocked.take() {
+        scheduler::queue_ready_thread(thread::SUSPENDED, t);
     }
 }
 
 extern "C" fn poll() {
     loop {
         let n = POLLER_WAKER.load(Ordering::Acquire);
-        poll_inner();
-        atomic_wait::atomic_wait(&POLLER_WAKER, n, None);
+        while let Some(task) = next_tasklet() {
+            run(task);
+        }
+        let _ = atomic_wait::atomic_wait(&POLLER_WAKER, n, None);
+    }
+}
+
+/// Returns pending once, after waking the current tasklet, so that other
+/// tasklets get polled.
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|ctx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        ctx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    static WAKER: SpinLock<Option<Waker>> = SpinLock::new(None);
+    static FLAG: AtomicBool = AtomicBool::new(false);
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    #[test]
+    fn test_wake_tasklet() {
+        spawn(async {
+            core::future::poll_fn(|ctx| {
+                POLLS.fetch_add(1, Ordering::Relaxed);
+                if FLAG.load(Ordering::Acquire) {
+                    return Poll::Ready(());
+                }
+                *WAKER.irqsave_lock() = Some(ctx.waker().clone());
+                Poll::Pending
+            })
+            .await;
+            yield_now().await;
+            DONE.store(true, Ordering::Release);
+        });
+        while WAKER.irqsave_lock().is_none() {
+            scheduler::yield_me();
+        }
+        // Running another tasklet doesn't poll this one again.
+        let polls = POLLS.load(Ordering::Relaxed);
+        block_on(async {});
+        assert_eq!(POLLS.load(Ordering::Relaxed), polls);
+
+        FLAG.store(true, Ordering::Release);
+        WAKER.irqsave_lock().take().unwrap().wake();
+        while !DONE.load(Ordering::Acquire) {
+            scheduler::yield_me();
+        }
     }
 }
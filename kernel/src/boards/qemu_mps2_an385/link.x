@@ -121,6 +121,11 @@ SECTIONS
     KEEP(*(.bk_app_array))
     PROVIDE_HIDDEN (__bk_app_array_end = .);
 
+    . = ALIGN(4);
+    PROVIDE_HIDDEN (__bk_driver_array_start = .);
+    KEEP(*(.bk_driver_array))
+    PROVIDE_HIDDEN (__bk_driver_array_end = .);
+
     . = ALIGN(4);
     __start___llvm_prf_cnts = .;
     KEEP(*(__llvm_prf_cnts))
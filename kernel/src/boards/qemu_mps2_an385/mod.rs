@@ -18,12 +18,10 @@ mod uart;
 use crate::{
     arch, boot,
     devices::{console, tty::n_tty::Tty},
-    error::Error,
     time,
 };
 use alloc::{string::String, sync::Arc};
 use boot::INIT_BSS_DONE;
-pub(crate) use uart::get_early_uart; // re-export
 use uart::{get_serial, uart_init};
 #[repr(C)]
 struct CopyTable {
@@ -87,11 +85,11 @@ pub(crate) fn init() {
         String::from("ttyS0"),
     ) {
         Ok(_) => (),
-        Err(e) => panic!("Failed to init uart: {}", Error::from(e)),
+        Err(e) => panic!("Failed to init uart: {}", e),
     }
-    match console::init_console(Tty::init(get_serial(0).clone()).clone()) {
+    match console::init_console("ttyS0", Tty::init(get_serial(0).clone()).clone()) {
         Ok(_) => (),
-        Err(e) => panic!("Failed to init console: {}", Error::from(e)),
+        Err(e) => panic!("Failed to init console: {}", e),
     }
 }
 
@@ -18,35 +18,22 @@ use super::config;
 use crate::{
     arch::irq::IrqNumber,
     devices::{
-        tty::{
-            serial::{Serial, UartOps},
-            termios::Termios,
-        },
+        console,
+        tty::{serial::Serial, termios::Termios},
         Device, DeviceManager,
     },
     drivers::uart::cmsdk_uart::Driver,
+    error::Error,
     irq::IrqTrace,
-    sync::SpinLock,
+    sync::{KOnce, SpinLock},
     vfs::AccessMode,
 };
 use alloc::{string::String, sync::Arc};
-use embedded_io::ErrorKind;
-use spin::Once;
 
-static UART0: Once<Arc<SpinLock<Driver>>> = Once::new();
+static UART0: KOnce<Arc<SpinLock<Driver>>> = KOnce::new();
 // could add more UART if needed
 
-pub fn get_early_uart(index: u32) -> Arc<SpinLock<dyn UartOps>> {
-    match index {
-        0 => UART0
-            .get()
-            .expect("uart_init must be called before get_early_uart")
-            .clone(),
-        _ => panic!("unsupported UART number"),
-    }
-}
-
-static SERIAL0: Once<Arc<Serial>> = Once::new();
+static SERIAL0: KOnce<Arc<Serial>> = KOnce::new();
 // could add more SERIAL if needed
 
 pub fn get_serial(index: u32) -> &'static Arc<Serial> {
@@ -65,8 +52,8 @@ pub fn uart_init(
     rx_irq_num: IrqNumber,
     tx_irq_num: IrqNumber,
     name: String,
-) -> Result<(), ErrorKind> {
-    // must be called before get_serial and get_early_uart
+) -> Result<(), Error> {
+    // must be called before get_serial
 
     match index {
         0 => {
@@ -76,7 +63,6 @@ pub fn uart_init(
                 uart.enable(115200);
                 Arc::new(SpinLock::new(uart))
             });
-
             SERIAL0.call_once(|| {
                 Arc::new(Serial::new(
                     index,
@@ -89,12 +75,14 @@ pub fn uart_init(
     }
 
     let serial = get_serial(0);
+    console::register_early_console("ttyS0", serial.clone());
+    serial.enable_sysrq();
     DeviceManager::get().register_device(name, serial.clone())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn uart0rx_handler() {
-    let _ = IrqTrace::new(config::UART0RX_IRQn);
+    let _trace = IrqTrace::new(config::UART0RX_IRQn);
     let uart = get_serial(0);
     uart.uart_ops.irqsave_lock().clear_rx_interrupt();
     if let Err(_e) = uart.recvchars() {
@@ -103,7 +91,7 @@ pub unsafe extern "C" fn uart0rx_handler() {
 }
 #[no_mangle]
 pub unsafe extern "C" fn uart0tx_handler() {
-    let _ = IrqTrace::new(config::UART0TX_IRQn);
+    let _trace = IrqTrace::new(config::UART0TX_IRQn);
     let uart = get_serial(0);
     uart.uart_ops.irqsave_lock().clear_tx_interrupt();
     if let Err(_e) = uart.xmitchars() {
@@ -18,7 +18,6 @@ use crate::{
         irq::{InterruptTable, Vector, INTERRUPT_TABLE_LEN},
     },
     boot::_start,
-    time,
 };
 
 unsafe extern "C" fn do_nothing() {}
@@ -61,7 +60,7 @@ const fn build_exception_handlers() -> [Vector; 15] {
         handler: arch::arm::handle_pendsv,
     };
     tbl[14] = Vector {
-        handler: time::handle_tick_increment,
+        handler: arch::arm::handle_systick,
     };
     tbl
 }
@@ -15,13 +15,11 @@
 pub mod config;
 mod handlers;
 pub mod uart;
-pub(crate) use uart::get_early_uart; // re-export
 use uart::{get_serial, uart_init};
 
 use crate::{
     arch, boot,
     devices::{console, tty::n_tty::Tty},
-    error::Error,
     time,
 };
 use alloc::{string::String, sync::Arc};
@@ -91,11 +89,11 @@ pub(crate) fn init() {
         String::from("ttyS0"),
     ) {
         Ok(_) => (),
-        Err(e) => panic!("Failed to init uart: {}", Error::from(e)),
+        Err(e) => panic!("Failed to init uart: {}", e),
     }
-    match console::init_console(Tty::init(get_serial(0).clone()).clone()) {
+    match console::init_console("ttyS0", Tty::init(get_serial(0).clone()).clone()) {
         Ok(_) => (),
-        Err(e) => panic!("Failed to init console: {}", Error::from(e)),
+        Err(e) => panic!("Failed to init console: {}", e),
     }
 }
 
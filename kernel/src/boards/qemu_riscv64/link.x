@@ -66,6 +66,13 @@ SECTIONS
     PROVIDE_HIDDEN(__bk_app_array_end = .);
   }
 
+  .bk_driver_array : {
+    . = ALIGN(16);
+    PROVIDE_HIDDEN(__bk_driver_array_start = .);
+    KEEP (*(.bk_driver_array))
+    PROVIDE_HIDDEN(__bk_driver_array_end = .);
+  }
+
   .heap : {
     . = ALIGN(4096);
     __heap_start = .;
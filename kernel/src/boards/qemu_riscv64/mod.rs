@@ -21,6 +21,8 @@
 
 mod config;
 mod uart;
+#[cfg(virtio)]
+use crate::devices::virtio;
 use crate::{
     arch,
     arch::riscv64::{local_irq_enabled, trap_entry, Context, READY_CORES},
@@ -32,7 +34,8 @@ use crate::{
 };
 use alloc::string::String;
 use core::sync::atomic::Ordering;
-pub(crate) use uart::get_early_uart; // re-export
+#[cfg(virtio)]
+use flat_device_tree::Fdt;
 pub(crate) static PLIC: Plic = Plic::new(config::PLIC_BASE);
 
 const CLOCK_ADDR: usize = 0x0200_0000;
@@ -89,6 +92,17 @@ pub(crate) fn set_timeout_after(ns: usize) {
     set_timecmp(current_ticks() + ns / NS_PER_TICK);
 }
 
+/// Time since reset, as counted by the CLINT.
+#[cfg(tickless)]
+pub(crate) fn current_nanos() -> u64 {
+    (current_ticks() * NS_PER_TICK) as u64
+}
+
+#[cfg(tickless)]
+pub(crate) fn set_timeout_at(ns: u64) {
+    set_timecmp((ns / NS_PER_TICK as u64) as usize);
+}
+
 pub(crate) fn get_cycles_to_duration(cycles: u64) -> core::time::Duration {
     core::time::Duration::from_nanos(cycles)
 }
@@ -136,9 +150,20 @@ pub(crate) fn init() {
 
 fn enumerate_devices() {
     uart::uart_init(0);
+    #[cfg(virtio)]
+    {
+        // SAFETY: QEMU passes a valid device tree, which is above the
+        // kernel's memory.
+        let fdt =
+            unsafe { Fdt::from_ptr(arch::riscv64::BOOT_FDT.load(Ordering::Relaxed) as *const u8) };
+        match fdt {
+            Ok(fdt) => virtio::init_virtio(&fdt),
+            Err(e) => log::warn!("No device tree to find virtio devices, {:?}", e),
+        }
+    }
 }
 
 fn register_devices_in_vfs() {
-    console::init_console(dumb::get_serial0().clone());
+    console::init_console("ttyS0", dumb::get_serial0().clone());
     DeviceManager::get().register_device(String::from("ttyS0"), dumb::get_serial0().clone());
 }
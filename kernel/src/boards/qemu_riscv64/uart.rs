@@ -18,12 +18,15 @@ use super::{config, PLIC};
 use crate::{
     arch,
     arch::irq::IrqNumber,
-    devices::tty::{
-        serial::{Serial, SerialError, UartOps},
-        termios::Termios,
+    devices::{
+        console,
+        tty::{
+            serial::{Serial, SerialError, UartOps},
+            termios::Termios,
+        },
     },
-    drivers::uart::ns16550a::Uart,
-    sync::SpinLock,
+    drivers::{io::Mmio, uart::ns16550a::Uart},
+    sync::{KOnce, SpinLock},
     vfs::AccessMode,
 };
 use alloc::sync::Arc;
@@ -35,22 +38,12 @@ use safe_mmio::{
     fields::{ReadPure, ReadPureWrite, ReadWrite, WriteOnly},
     UniqueMmioPointer,
 };
-use spin::{Mutex, Once};
+use spin::Mutex;
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
-static UART0: Once<Arc<SpinLock<Uart>>> = Once::new();
+static UART0: KOnce<Arc<SpinLock<Uart>>> = KOnce::new();
 // could add more UART if needed
-pub fn get_early_uart(index: u32) -> Arc<SpinLock<dyn UartOps>> {
-    match index {
-        0 => UART0
-            .get()
-            .expect("uart_init must be called before get_early_uart")
-            .clone(),
-        _ => panic!("unsupported UART number"),
-    }
-}
-
-static SERIAL0: Once<Arc<Serial>> = Once::new();
+static SERIAL0: KOnce<Arc<Serial>> = KOnce::new();
 // could add more SERIAL if needed
 pub fn get_serial(index: u32) -> &'static Arc<Serial> {
     match index {
@@ -78,9 +71,10 @@ pub(crate) fn uart_init(index: u32) {
             );
 
             UART0.call_once(|| {
+                // according to base, not always uart0
                 Arc::new(SpinLock::new(Uart::new(unsafe {
-                    UniqueMmioPointer::new(NonNull::new(config::UART0 as *mut _).unwrap())
-                }))) // according to base, not always uart0
+                    Mmio::new(config::UART0 as usize)
+                })))
             });
 
             SERIAL0.call_once(|| {
@@ -92,6 +86,8 @@ pub(crate) fn uart_init(index: u32) {
             });
 
             UART0.get().unwrap().lock().init();
+            console::register_early_console("ttyS0", SERIAL0.get().unwrap().clone());
+            SERIAL0.get().unwrap().enable_sysrq();
         }
         _ => panic!("unsupported index for UART & SERIAL number"),
     }
@@ -18,8 +18,15 @@ pub const UART0_BASE_S: u64 = 0x59303000;
 pub const APBP_CLOCK: u32 = 0x16e3600;
 pub const PL011_UART0_BASE: u64 = 0x900_0000;
 pub const PL011_UART0_IRQNUM: IrqNumber = IrqNumber::new(33);
+pub const PL031_RTC_BASE: usize = 0x901_0000;
 pub const HEAP_SIZE: u64 = 16 * 1024 * 1024;
 pub const PSCI_BASE: u32 = 0x84000000;
 pub const GICD: usize = 0x8000000;
 pub const GICR: usize = 0x80a0000;
 pub const DRAM_BASE: u64 = 0x4000_0000;
+// The first pflash would hold the firmware, QEMU loads the kernel itself
+// so it holds the key-value store instead. The second one is for littlefs.
+pub const PFLASH0_BASE: usize = 0x0;
+pub const PFLASH1_BASE: usize = 0x400_0000;
+pub const PFLASH_SIZE: usize = 0x400_0000;
+pub const PFLASH_SECTOR_SIZE: usize = 0x4_0000;
@@ -18,7 +18,6 @@ use super::uart::{enable_uart, get_serial};
 use crate::{
     arch::{self, READY_CORES},
     devices::{console, tty::n_tty::Tty, virtio},
-    error::Error,
     scheduler,
     support::SmpStagedInit,
     time,
@@ -59,12 +58,13 @@ pub(crate) fn init() {
         String::from("ttyS0"),
     ) {
         Ok(_) => (),
-        Err(e) => panic!("Failed to init uart: {}", Error::from(e)),
+        Err(e) => panic!("Failed to init uart: {}", e),
     }
-    match console::init_console(Tty::init(get_serial(0).clone()).clone()) {
+    match console::init_console("ttyS0", Tty::init(get_serial(0).clone()).clone()) {
         Ok(_) => (),
-        Err(e) => panic!("Failed to init console: {}", Error::from(e)),
+        Err(e) => panic!("Failed to init console: {}", e),
     }
+    register_rtc();
     #[cfg(virtio)]
     {
         // initialize fdt
@@ -74,6 +74,69 @@ pub(crate) fn init() {
         // initialize virtio
         virtio::init_virtio(&fdt);
     }
+    #[cfg(littlefs)]
+    register_pflash();
+    #[cfg(kvstore)]
+    register_kvstore_flash();
+}
+
+// Registers the PL031 as "rtc0", which the wall clock is set from.
+fn register_rtc() {
+    use crate::drivers::{io::Mmio, pl031};
+
+    // SAFETY: The RTC is mapped as device memory, and only driven from
+    // here.
+    let io = unsafe { Mmio::new(config::PL031_RTC_BASE) };
+    if let Err(e) = pl031::register(io) {
+        log::warn!("Failed to register rtc0: {}", e);
+    }
+}
+
+// Registers the first pflash as the block device "pflash0", where the
+// key-value store lives. Its first sector is left out, since the flash is
+// mapped at address 0, which no reference may point to.
+#[cfg(kvstore)]
+fn register_kvstore_flash() {
+    use crate::{
+        devices::DeviceManager,
+        drivers::cfi_flash::{CfiFlash, CfiFlashDevice},
+    };
+
+    // SAFETY: The pflash is mapped as device memory, and only driven from
+    // here.
+    let flash = unsafe {
+        CfiFlash::new(
+            config::PFLASH0_BASE + config::PFLASH_SECTOR_SIZE,
+            config::PFLASH_SIZE - config::PFLASH_SECTOR_SIZE,
+            config::PFLASH_SECTOR_SIZE,
+        )
+    };
+    let result = CfiFlashDevice::new("pflash0", flash).and_then(|device| {
+        DeviceManager::get().register_device(String::from("pflash0"), Arc::new(device))
+    });
+    if let Err(e) = result {
+        log::warn!("Failed to register pflash0: {}", e);
+    }
+}
+
+// Registers the second pflash for littlefs mounts, as "pflash1". Without
+// a drive given to QEMU it starts blank and only lives as long as the VM.
+#[cfg(littlefs)]
+fn register_pflash() {
+    use crate::{drivers::cfi_flash::CfiFlash, vfs::littlefs};
+
+    // SAFETY: The pflash is mapped as device memory, and only driven from
+    // here.
+    let flash = unsafe {
+        CfiFlash::new(
+            config::PFLASH1_BASE,
+            config::PFLASH_SIZE,
+            config::PFLASH_SECTOR_SIZE,
+        )
+    };
+    if let Err(e) = littlefs::register_device("pflash1", Arc::new(flash)) {
+        log::warn!("Failed to register pflash1: {}", e);
+    }
 }
 
 fn wait_and_then_start_schedule() {
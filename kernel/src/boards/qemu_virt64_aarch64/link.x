@@ -69,6 +69,13 @@ SECTIONS
       PROVIDE_HIDDEN (__bk_app_array_end = .);
     } > DRAM :data
 
+    .bk_driver_array : {
+      . = ALIGN(16);
+      PROVIDE_HIDDEN (__bk_driver_array_start = .);
+      KEEP (*(.bk_driver_array))
+      PROVIDE_HIDDEN (__bk_driver_array_end = .);
+    } > DRAM :data
+
     .stack : ALIGN(4096)
     {
         __sys_stack_start = .;
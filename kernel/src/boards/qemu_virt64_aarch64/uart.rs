@@ -21,6 +21,7 @@ use crate::{
         irq::{IrqHandler, IrqNumber},
     },
     devices::{
+        console,
         tty::{
             serial::{Serial, UartOps},
             termios::{Cflags, Iflags, Lflags, Oflags, Termios},
@@ -28,8 +29,8 @@ use crate::{
         DeviceManager,
     },
     drivers::uart::arm_pl011::Driver,
-    irq::IrqTrace,
-    sync::SpinLock,
+    error::Error,
+    sync::{KOnce, SpinLock},
 };
 use alloc::{
     boxed::Box,
@@ -37,23 +38,12 @@ use alloc::{
     sync::Arc,
 };
 use core::ptr::NonNull;
-use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
 use safe_mmio::UniqueMmioPointer;
-use spin::Once;
 
-static UART0: Once<Arc<SpinLock<Driver<'static>>>> = Once::new();
+static UART0: KOnce<Arc<SpinLock<Driver>>> = KOnce::new();
 // could add more UART if needed
-pub fn get_early_uart(index: u32) -> Arc<SpinLock<dyn UartOps>> {
-    match index {
-        0 => UART0
-            .get()
-            .expect("uart_init must be called before get_early_uart")
-            .clone(),
-        _ => panic!("unsupported UART number"),
-    }
-}
-
-static SERIAL0: Once<Arc<Serial>> = Once::new();
+static SERIAL0: KOnce<Arc<Serial>> = KOnce::new();
 // could add more SERIAL if needed
 pub fn get_serial(index: u32) -> &'static Arc<Serial> {
     match index {
@@ -70,7 +60,7 @@ pub fn uart_init(
     clock: u32,
     irq_num: IrqNumber,
     name: String,
-) -> Result<(), ErrorKind> {
+) -> Result<(), Error> {
     match index {
         0 => {
             for cpu_id in 0..blueos_kconfig::NUM_CORES {
@@ -91,7 +81,6 @@ pub fn uart_init(
                 uart.enable(&termios);
                 Arc::new(SpinLock::new(uart))
             });
-
             SERIAL0.call_once(|| {
                 let termios = Termios::new(
                     Iflags::default(),
@@ -105,6 +94,8 @@ pub fn uart_init(
             });
 
             let serial = get_serial(0);
+            console::register_early_console("ttyS0", serial.clone());
+            serial.enable_sysrq();
             DeviceManager::get().register_device(name, serial.clone())
         }
         _ => panic!("unsupported index for UART & SERIAL number"),
@@ -118,12 +109,13 @@ pub fn enable_uart(cpu_id: usize, irq_num: IrqNumber) {
 pub struct Serial0Irq {}
 impl IrqHandler for Serial0Irq {
     fn handle(&mut self) {
-        let _ = IrqTrace::new(config::PL011_UART0_IRQNUM);
         let serial0 = get_serial(0);
         let _ = serial0.recvchars();
         serial0.uart_ops.lock().clear_rx_interrupt();
 
         let _ = serial0.xmitchars();
         serial0.uart_ops.lock().clear_tx_interrupt();
+
+        serial0.update_modem_status();
     }
 }
@@ -15,7 +15,6 @@
 use crate::{
     arch::{self, irq::Vector},
     boot::_start,
-    time,
 };
 
 #[used]
@@ -52,7 +51,7 @@ const fn build_exception_handlers() -> [Vector; 15] {
         handler: arch::arm::handle_pendsv,
     };
     tbl[14] = Vector {
-        handler: time::handle_tick_increment,
+        handler: arch::arm::handle_systick,
     };
     tbl
 }
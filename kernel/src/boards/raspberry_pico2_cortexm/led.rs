@@ -13,7 +13,8 @@
 // limitations under the License.
 
 use crate::{
-    devices::{Device, DeviceId, DeviceManager},
+    devices::{devno::LED_MAJOR, Device, DeviceId, DeviceManager},
+    error::{code, Error},
     sync::SpinLock,
 };
 use alloc::{format, string::String, sync::Arc};
@@ -43,38 +44,28 @@ impl<P: OutputPin + Send + Sync> Device for Led<P> {
     }
 
     fn id(&self) -> DeviceId {
-        DeviceId::new(6, self.index as usize)
+        DeviceId::new(LED_MAJOR, self.index as usize)
     }
 
-    fn open(&self) -> Result<(), embedded_io::ErrorKind> {
+    fn open(&self) -> Result<(), Error> {
         // Initialize the LED hardware here if needed
         Ok(())
     }
 
-    fn close(&self) -> Result<(), embedded_io::ErrorKind> {
+    fn close(&self) -> Result<(), Error> {
         // Clean up the LED hardware here if needed
         Ok(())
     }
 
-    fn read(
-        &self,
-        _pos: u64,
-        _buf: &mut [u8],
-        _is_nonblocking: bool,
-    ) -> Result<usize, embedded_io::ErrorKind> {
+    fn read(&self, _pos: u64, _buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
         // Reading from LED doesn't make sense, return an error
-        Err(embedded_io::ErrorKind::Unsupported)
+        Err(code::ENOSYS)
     }
 
-    fn write(
-        &self,
-        _pos: u64,
-        buf: &[u8],
-        is_nonblocking: bool,
-    ) -> Result<usize, embedded_io::ErrorKind> {
+    fn write(&self, _pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, Error> {
         // Writing to the LED hardware here
         if buf.is_empty() {
-            return Err(embedded_io::ErrorKind::InvalidInput);
+            return Err(code::EINVAL);
         }
 
         if buf.contains(&b'1') {
@@ -82,14 +73,14 @@ impl<P: OutputPin + Send + Sync> Device for Led<P> {
         } else if buf.contains(&b'0') {
             let _ = self.pin.irqsave_lock().set_low();
         } else {
-            return Err(embedded_io::ErrorKind::InvalidInput);
+            return Err(code::EINVAL);
         }
 
         Ok(buf.len())
     }
 }
 
-pub fn led_init(pin: Arc<dyn Device>) -> Result<(), embedded_io::ErrorKind> {
+pub fn led_init(pin: Arc<dyn Device>) -> Result<(), Error> {
     DeviceManager::get().register_device(String::from("led0"), pin)?;
     Ok(())
 }
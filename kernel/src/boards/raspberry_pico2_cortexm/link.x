@@ -165,6 +165,11 @@ SECTIONS
     KEEP(*(.bk_app_array))
     PROVIDE_HIDDEN (__bk_app_array_end = .);
 
+    . = ALIGN(4);
+    PROVIDE_HIDDEN (__bk_driver_array_start = .);
+    KEEP(*(.bk_driver_array))
+    PROVIDE_HIDDEN (__bk_driver_array_end = .);
+
     KEEP(*(.jcr*))
     . = ALIGN(4);
     __data_end = .;
@@ -25,12 +25,12 @@ use crate::{
         rp235x::{
             block,
             clocks::{
-                PeripheralAuxiliaryClockSource, ReferenceAuxiliaryClockSource,
+                PeripheralAuxiliaryClockSource, PeripheralClockGate, ReferenceAuxiliaryClockSource,
                 ReferenceClockSource, SystemAuxiliaryClockSource, SystemClockSource,
             },
-            gpio::{GpioFunction, GpioPin},
+            gpio::{GpioPin, Pinctrl},
             pll,
-            reset::{Peripheral, Resets},
+            reset::{Peripheral, Resets, RESET_LINES},
             uart::Uart,
             xosc,
         },
@@ -38,20 +38,23 @@ use crate::{
     boot,
     boot::INIT_BSS_DONE,
     devices::{
+        clk::{self, Clock, FixedFactor, FixedRate},
         console,
+        pinctrl::{self, PinConfig, PinSetting, PinState},
+        reset,
         tty::{
             n_tty::Tty,
-            serial::{Serial, UartOps},
+            serial::Serial,
             termios::Termios,
         },
     },
     kprintln,
-    sync::SpinLock,
+    panic::{self, PanicHooks},
+    sync::{KOnce, SpinLock},
     time,
 };
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc};
 use core::ptr::addr_of;
-use spin::Once;
 
 #[link_section = ".start_block"]
 #[used]
@@ -169,27 +172,34 @@ pub(crate) fn init() {
 
     rp235x::clocks::configure_peripheral_clock(PeripheralAuxiliaryClockSource::PllSys);
 
+    register_clocks(pll_sys_freq, pll_usb_freq);
+    register_resets(reset);
+
     time::systick_init(pll_sys_freq);
 
+    register_pinctrl();
+
+    pinctrl::select_default("led0").expect("Failed to set led0 pins up");
     let pin25 = GpioPin::<25>::new();
-    pin25.set_function(GpioFunction::SIO);
-    pin25.activate_pads();
 
     rp235x::sio::set_sio_oe_set(25);
     rp235x::sio::enable_sio_gpio_out(25);
 
-    let pin2 = GpioPin::<2>::new();
-    pin2.set_function(GpioFunction::UART0_TX);
-    let pin3 = GpioPin::<3>::new();
-    pin3.set_function(GpioFunction::UART0_RX);
-
-    reset.reset(&[Peripheral::Uart0]);
-    reset.unreset(&[Peripheral::Uart0], true);
+    pinctrl::select_default("uart0").expect("Failed to set uart0 pins up");
+    let clk_peri = clk::get("clk_peri").expect("clk_peri is registered above");
+    clk_peri.enable().expect("Failed to enable clk_peri");
+    let uart0_reset = reset::get("uart0").expect("uart0 reset is registered above");
+    uart0_reset.reset().expect("Failed to reset uart0");
 
-    let mut u = Uart::new();
-    u.enable(115200);
+    UART0.call_once(|| {
+        let mut u = Uart::new(clk_peri);
+        u.enable(115200);
+        Arc::new(SpinLock::new(u))
+    });
+    console::register_early_console("ttyS0", get_serial0().clone());
+    get_serial0().enable_sysrq();
 
-    match console::init_console(Tty::init(get_serial0(u).clone()).clone()) {
+    match console::init_console("ttyS0", Tty::init(get_serial0().clone()).clone()) {
         Ok(_) => (),
         Err(e) => panic!("Failed to init console"),
     }
@@ -200,6 +210,77 @@ pub(crate) fn init() {
         Ok(_) => kprintln!("LED initialized successfully"),
         Err(e) => panic!("Failed to initialize LED: {:?}", e),
     }
+    panic::register_hooks(PanicHooks {
+        indicate: Some(light_led),
+        ..Default::default()
+    });
+}
+
+// Describes the clock tree set up by init() so that drivers find their
+// clocks by name.
+fn register_clocks(pll_sys_freq: u32, pll_usb_freq: u32) {
+    let xosc_freq = config::XOSC_FREQ as u64;
+    let xosc = Arc::new(Clock::new("xosc", None, Box::new(FixedRate(xosc_freq))));
+    let pll = |name, freq: u32| {
+        let factor = FixedFactor {
+            mult: freq as u64,
+            div: xosc_freq,
+        };
+        Arc::new(Clock::new(name, Some(xosc.clone()), Box::new(factor)))
+    };
+    let pll_sys = pll("pll_sys", pll_sys_freq);
+    let pll_usb = pll("pll_usb", pll_usb_freq);
+    let clk_sys = Arc::new(Clock::new(
+        "clk_sys",
+        Some(pll_sys.clone()),
+        Box::new(FixedFactor { mult: 1, div: 1 }),
+    ));
+    let clk_peri = Arc::new(Clock::new(
+        "clk_peri",
+        Some(pll_sys.clone()),
+        Box::new(PeripheralClockGate),
+    ));
+    for clock in [xosc, pll_sys, pll_usb, clk_sys, clk_peri] {
+        clk::register(clock).expect("Failed to register clock");
+    }
+}
+
+static UART0_PINS: [PinState; 1] = [PinState {
+    name: pinctrl::STATE_DEFAULT,
+    settings: &[PinSetting {
+        group: "uart0_gp2_3",
+        function: "uart0",
+        config: PinConfig::NONE,
+    }],
+}];
+
+static LED0_PINS: [PinState; 1] = [PinState {
+    name: pinctrl::STATE_DEFAULT,
+    settings: &[PinSetting {
+        group: "gp25",
+        function: "sio",
+        config: PinConfig::NONE,
+    }],
+}];
+
+fn register_pinctrl() {
+    let bank0 = Arc::new(Pinctrl);
+    for (device, states) in [("uart0", &UART0_PINS), ("led0", &LED0_PINS)] {
+        pinctrl::register(device, bank0.clone(), states).expect("Failed to register pin states");
+    }
+}
+
+fn register_resets(resets: Resets) {
+    let resets = Arc::new(resets);
+    for (name, id) in RESET_LINES {
+        reset::register(name, resets.clone(), id).expect("Failed to register reset line");
+    }
+}
+
+// Lights led0 on panic, straight through SIO since the device may be
+// locked.
+fn light_led() {
+    rp235x::sio::set_sio_gpio_out(25);
 }
 
 // FIXME: support float
@@ -213,17 +294,12 @@ pub(crate) fn get_cycles_to_ms(cycles: u64) -> u64 {
     return (cycles as u128 * 1_000 as u128 / config::PLL_SYS_FREQ as u128) as u64;
 }
 
-pub fn get_early_uart() -> &'static SpinLock<dyn UartOps> {
-    todo!()
-}
+static UART0: KOnce<Arc<SpinLock<Uart>>> = KOnce::new();
 
-pub(crate) static SERIAL0: Once<Arc<Serial>> = Once::new();
-pub fn get_serial0(u: Uart) -> &'static Arc<Serial> {
+pub(crate) static SERIAL0: KOnce<Arc<Serial>> = KOnce::new();
+pub fn get_serial0() -> &'static Arc<Serial> {
     SERIAL0.call_once(|| {
-        Arc::new(Serial::new(
-            0,
-            Termios::default(),
-            Arc::new(SpinLock::new(u)),
-        ))
+        let uart = UART0.get().expect("UART0 must be enabled before get_serial0");
+        Arc::new(Serial::new(0, Termios::default(), uart.clone()))
     })
 }
@@ -19,6 +19,7 @@
 // Copyright Tock Contributors 2022.
 
 use super::static_ref::StaticRef;
+use crate::{devices::clk::ClockOps, error::Error};
 use tock_registers::{
     interfaces::{ReadWriteable, Readable},
     register_bitfields, register_structs,
@@ -334,3 +335,19 @@ pub fn configure_peripheral_clock(aux_clk: PeripheralAuxiliaryClockSource) {
 
     clk_peri.modify(CLK_PERI_CTRL::ENABLE::SET);
 }
+
+/// Gate of clk_peri, whose source is picked by [`configure_peripheral_clock`].
+pub struct PeripheralClockGate;
+
+impl ClockOps for PeripheralClockGate {
+    fn enable(&self) -> Result<(), Error> {
+        CLOCKS_BASE.clk_peri_ctrl.modify(CLK_PERI_CTRL::ENABLE::SET);
+        Ok(())
+    }
+
+    fn disable(&self) {
+        CLOCKS_BASE
+            .clk_peri_ctrl
+            .modify(CLK_PERI_CTRL::ENABLE::CLEAR);
+    }
+}
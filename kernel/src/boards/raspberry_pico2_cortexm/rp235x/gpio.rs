@@ -18,7 +18,11 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
-use crate::boards::raspberry_pico2_cortexm::rp235x::static_ref::StaticRef;
+use crate::{
+    boards::raspberry_pico2_cortexm::rp235x::static_ref::StaticRef,
+    devices::pinctrl::{Bias, PinConfig, PinFunction, PinGroup, PinctrlOps},
+    error::{code, Error},
+};
 use embedded_hal::digital::{ErrorType, OutputPin};
 use tock_registers::{
     interfaces::{ReadWriteable, Writeable},
@@ -200,6 +204,87 @@ impl<const PIN: usize> GpioPin<PIN> {
     }
 }
 
+/// The pin controller of bank 0, with the pins and functions the board
+/// uses.
+pub struct Pinctrl;
+
+const PIN_GROUPS: &[PinGroup] = &[
+    PinGroup {
+        name: "uart0_gp2_3",
+        pins: &[2, 3],
+    },
+    PinGroup {
+        name: "gp25",
+        pins: &[25],
+    },
+];
+
+// Function selects are the same on every pin, FUNCSEL is in the table
+// below at the function's index.
+const PIN_FUNCTIONS: &[PinFunction] = &[
+    PinFunction {
+        name: "uart0",
+        groups: &["uart0_gp2_3"],
+    },
+    PinFunction {
+        name: "sio",
+        groups: &["uart0_gp2_3", "gp25"],
+    },
+];
+const FUNCSEL: [u32; 2] = [11, 5];
+
+impl PinctrlOps for Pinctrl {
+    fn groups(&self) -> &'static [PinGroup] {
+        PIN_GROUPS
+    }
+
+    fn functions(&self) -> &'static [PinFunction] {
+        PIN_FUNCTIONS
+    }
+
+    fn set_mux(&self, function: usize, group: usize) -> Result<(), Error> {
+        for &pin in PIN_GROUPS[group].pins {
+            let pin = pin as usize;
+            GPIO_PAD_BASE.gpio_pad[pin].modify(GPIO_PAD::OD::CLEAR + GPIO_PAD::IE::SET);
+            GPIO_PAD_BASE.gpio_pad[pin].modify(GPIO_PAD::ISO::CLEAR);
+            GPIO_BASE.pin[pin].ctrl.set(0);
+            GPIO_BASE.pin[pin]
+                .ctrl
+                .modify(GPIOx_CTRL::FUNCSEL.val(FUNCSEL[function]));
+        }
+        Ok(())
+    }
+
+    fn set_config(&self, pin: u32, config: &PinConfig) -> Result<(), Error> {
+        // OD is output disable, the pads can't do open drain.
+        if config.open_drain == Some(true) {
+            return Err(code::ENOTSUP);
+        }
+        let pad = &GPIO_PAD_BASE.gpio_pad[pin as usize];
+        let drive = match config.drive_strength_ma {
+            None => None,
+            Some(2) => Some(0),
+            Some(4) => Some(1),
+            Some(8) => Some(2),
+            Some(12) => Some(3),
+            Some(_) => return Err(code::EINVAL),
+        };
+        match config.bias {
+            None => (),
+            Some(Bias::Disable) => pad.modify(GPIO_PAD::PUE::CLEAR + GPIO_PAD::PDE::CLEAR),
+            Some(Bias::PullUp) => pad.modify(GPIO_PAD::PUE::SET + GPIO_PAD::PDE::CLEAR),
+            Some(Bias::PullDown) => pad.modify(GPIO_PAD::PUE::CLEAR + GPIO_PAD::PDE::SET),
+        }
+        if let Some(enable) = config.input_enable {
+            pad.modify(GPIO_PAD::IE.val(enable as u32));
+        }
+        if let Some(drive) = drive {
+            pad.modify(GPIO_PAD::DRIVE.val(drive));
+        }
+        Ok(())
+    }
+}
+
 trait FunctionIndex<const F: u8> {
     const INDEX: usize;
 }
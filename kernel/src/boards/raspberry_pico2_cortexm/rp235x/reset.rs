@@ -18,7 +18,11 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
-use crate::boards::raspberry_pico2_cortexm::rp235x::static_ref::StaticRef;
+use crate::{
+    boards::raspberry_pico2_cortexm::rp235x::static_ref::StaticRef,
+    devices::reset::ResetOps,
+    error::{code, Error},
+};
 use tock_registers::{
     fields::FieldValue,
     interfaces::{ReadWriteable, Readable, Writeable},
@@ -217,6 +221,39 @@ register_bitfields![u32,
         adc OFFSET(0) NUMBITS(1) []
     ]
 ];
+/// Reset lines by name, indexed by their bit in the RESET register.
+pub const RESET_LINES: [(&str, usize); 29] = [
+    ("adc", 0),
+    ("busctrl", 1),
+    ("dma", 2),
+    ("hstx", 3),
+    ("i2c0", 4),
+    ("i2c1", 5),
+    ("io_bank0", 6),
+    ("io_qspi", 7),
+    ("jtag", 8),
+    ("pads_bank0", 9),
+    ("pads_qspi", 10),
+    ("pio0", 11),
+    ("pio1", 12),
+    ("pio2", 13),
+    ("pll_sys", 14),
+    ("pll_usb", 15),
+    ("pwm", 16),
+    ("sha256", 17),
+    ("spi0", 18),
+    ("spi1", 19),
+    ("syscfg", 20),
+    ("sysinfo", 21),
+    ("tbman", 22),
+    ("timer0", 23),
+    ("timer1", 24),
+    ("trng", 25),
+    ("uart0", 26),
+    ("uart1", 27),
+    ("usbctrl", 28),
+];
+
 const RESETS_BASE: StaticRef<ResetsRegisters> =
     unsafe { StaticRef::new(0x40020000 as *const ResetsRegisters) };
 
@@ -427,3 +464,33 @@ impl Resets {
         self.registers.wdsel.set(value);
     }
 }
+
+impl ResetOps for Resets {
+    fn assert(&self, id: usize) -> Result<(), Error> {
+        if id >= RESET_LINES.len() {
+            return Err(code::EINVAL);
+        }
+        self.registers
+            .reset
+            .set(self.registers.reset.get() | (1 << id));
+        Ok(())
+    }
+
+    fn deassert(&self, id: usize) -> Result<(), Error> {
+        if id >= RESET_LINES.len() {
+            return Err(code::EINVAL);
+        }
+        self.registers
+            .reset
+            .set(self.registers.reset.get() & !(1 << id));
+        while self.registers.reset_done.get() & (1 << id) == 0 {}
+        Ok(())
+    }
+
+    fn status(&self, id: usize) -> Result<bool, Error> {
+        if id >= RESET_LINES.len() {
+            return Err(code::EINVAL);
+        }
+        Ok(self.registers.reset.get() & (1 << id) != 0)
+    }
+}
@@ -21,9 +21,13 @@
 use crate::{
     arch::{self, irq::IrqNumber},
     boards::raspberry_pico2_cortexm::rp235x::static_ref::StaticRef,
-    devices::tty::serial::{SerialError, UartOps},
+    devices::{
+        clk::Clock,
+        tty::serial::{SerialError, UartOps},
+    },
     irq::IrqTrace,
 };
+use alloc::sync::Arc;
 use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
 use tock_registers::{
     interfaces::{ReadWriteable, Readable, Writeable},
@@ -380,28 +384,35 @@ const UART1_BASE: StaticRef<UartRegisters> =
 
 pub struct Uart {
     registers: StaticRef<UartRegisters>,
+    // clk_peri, enabled by the caller and released on drop.
+    clock: Arc<Clock>,
 }
 
 impl Uart {
-    pub const fn new() -> Self {
+    pub fn new(clock: Arc<Clock>) -> Self {
         Self {
             registers: UART0_BASE,
+            clock,
         }
     }
 
-    pub fn enable(&self, baud_rate: u32) {
-        let clk = crate::boards::raspberry_pico2_cortexm::config::PLL_SYS_FREQ as u32;
+    fn baud_rate_divisor(&self, baud_rate: u32) -> (u32, u32) {
+        let clk = self.clock.rate() as u32;
         let baud_rate_div = 8 * clk / baud_rate;
-        let mut baud_ibrd = baud_rate_div >> 7;
-        let mut baud_fbrd = (baud_rate_div & 0x7f).div_ceil(2);
+        let baud_ibrd = baud_rate_div >> 7;
+        let baud_fbrd = (baud_rate_div & 0x7f).div_ceil(2);
 
         if baud_ibrd == 0 {
-            baud_ibrd = 1;
-            baud_fbrd = 0;
+            (1, 0)
         } else if baud_ibrd >= 65535 {
-            baud_ibrd = 65535;
-            baud_fbrd = 0;
+            (65535, 0)
+        } else {
+            (baud_ibrd, baud_fbrd)
         }
+    }
+
+    pub fn enable(&self, baud_rate: u32) {
+        let (baud_ibrd, baud_fbrd) = self.baud_rate_divisor(baud_rate);
 
         self.registers
             .uartibrd
@@ -496,6 +507,7 @@ unsafe impl Sync for Uart {}
 impl Drop for Uart {
     fn drop(&mut self) {
         self.disable();
+        self.clock.disable();
     }
 }
 
@@ -619,6 +631,12 @@ impl UartOps for Uart {
         self.registers.uarticr.modify(UARTICR::TXIC::SET);
     }
 
+    fn closest_baud_rate(&self, baud_rate: u32) -> Result<u32, SerialError> {
+        let clk = self.clock.rate();
+        let (baud_ibrd, baud_fbrd) = self.baud_rate_divisor(baud_rate);
+        Ok((clk * 4 / ((baud_ibrd as u64) << 6 | baud_fbrd as u64)) as u32)
+    }
+
     fn ioctl(&mut self, _request: u32, _arg: usize) -> Result<(), SerialError> {
         Ok(())
     }
@@ -626,7 +644,7 @@ impl UartOps for Uart {
 
 #[coverage(off)]
 pub unsafe extern "C" fn uart0_handler() {
-    let _ = IrqTrace::new(IrqNumber::new(33));
+    let _trace = IrqTrace::new(IrqNumber::new(33));
     if UART0_BASE.uartimsc.is_set(UARTIMSC::TXIM) {
         if UART0_BASE.uartfr.is_set(UARTFR::TXFE) {
             let uart = crate::boards::raspberry_pico2_cortexm::SERIAL0
@@ -12,13 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{allocator, arch, asynk, boards, logger, net, scheduler, thread, time, vfs};
+use crate::{
+    allocator, arch, asynk, boards, devices, logger, net, scheduler, softirq, thread, time, vfs,
+    workqueue,
+};
 use core::ptr::{addr_of, addr_of_mut};
 
 pub(crate) static mut INIT_BSS_DONE: bool = false;
 pub(crate) static mut INIT_ARRAY_DONE: bool = false;
 pub(crate) static mut INIT_HEAP_DONE: bool = false;
 pub(crate) static mut INIT_VFS_DONE: bool = false;
+pub(crate) static mut INIT_DRIVERS_DONE: bool = false;
 
 // See https://github.com/rust-lang/rust/pull/134213 for more details about naked function.
 #[no_mangle]
@@ -46,6 +50,7 @@ extern "C" {
 
 extern "C" fn init() {
     boards::init();
+    devices::console::register_builtin_sinks();
     init_runtime();
     init_heap();
     scheduler::init();
@@ -54,8 +59,21 @@ extern "C" fn init() {
     logger::logger_init();
     time::timer::system_timer_init();
     asynk::init();
+    workqueue::init();
+    softirq::init();
+    devices::console::spawn_flusher();
+    allocator::deferred::init();
+    init_drivers();
+    #[cfg(kvstore)]
+    init_kvstore();
     net::net_manager::init();
     init_vfs();
+    // Boards without an RTC start counting from the epoch.
+    let _ = time::realtime::sync_from_rtc();
+    #[cfg(profiler)]
+    crate::profiler::init();
+    #[cfg(boot_script)]
+    crate::script::spawn_boot_script();
     init_apps();
     arch::start_schedule(scheduler::schedule);
     unreachable!("We should have jumped to the schedule loop!");
@@ -66,7 +84,41 @@ pub(crate) fn init_runtime() {
     run_init_array();
 }
 
+// Boards may set up the VFS themselves, the devices of the drivers must be
+// registered before /dev is populated.
+pub(crate) fn init_drivers() {
+    unsafe {
+        if INIT_DRIVERS_DONE {
+            return;
+        }
+        devices::driver::init_drivers();
+        INIT_DRIVERS_DONE = true;
+    }
+}
+
+// Opens the key-value store before /etc is mounted, on the device the
+// board registered for it.
+#[cfg(kvstore)]
+fn init_kvstore() {
+    use blueos_kconfig::{KVSTORE_DEVICE, KVSTORE_PAGES, KVSTORE_PAGE_SIZE};
+
+    let region = crate::kvstore::Region {
+        device: alloc::string::String::from(KVSTORE_DEVICE),
+        offset: 0,
+        page_size: KVSTORE_PAGE_SIZE,
+        pages: KVSTORE_PAGES,
+    };
+    if let Err(e) = crate::kvstore::init(region) {
+        log::warn!(
+            "Failed to open the key-value store on {}: {}",
+            KVSTORE_DEVICE,
+            e
+        );
+    }
+}
+
 pub(crate) fn init_vfs() {
+    init_drivers();
     unsafe {
         if INIT_VFS_DONE {
             return;
@@ -42,3 +42,5 @@ pub const DEFAULT_STACK_SIZE: usize = 16 << 10;
 pub const DEFAULT_STACK_SIZE: usize = 8 << 10;
 
 pub const SOFT_TIMER_THREAD_PRIORITY: ThreadPriority = 0;
+pub const RECLAIMER_THREAD_PRIORITY: ThreadPriority = MAX_THREAD_PRIORITY - 1;
+pub const SYSTEM_WORK_QUEUE_PRIORITY: ThreadPriority = 1;
@@ -12,20 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::devices::console::{get_console, get_early_uart};
-use core::{fmt, str};
+use crate::{devices::console, klog};
+use core::{fmt, panic::PanicInfo, str};
 
 #[macro_export]
 macro_rules! kprintln {
     ($fmt:expr) => ({
-        use core::fmt::Write;
-        let mut writer = $crate::console::Console {};
-        writer.write_fmt(format_args!(concat!($fmt, "\n"))).unwrap();
+        $crate::console::println(format_args!(concat!($fmt, "\n")));
     });
     ($fmt:expr, $($arg:tt)*) => ({
-        use core::fmt::Write;
-        let mut writer = $crate::console::Console {};
-        writer.write_fmt(format_args!(concat!($fmt, "\n"), $($arg)*)).unwrap();
+        $crate::console::println(format_args!(concat!($fmt, "\n"), $($arg)*));
     });
 }
 
@@ -46,16 +42,34 @@ macro_rules! kearly_println {
 pub struct Console;
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let _ = get_console().write(0, s.as_bytes(), true);
+        console::write_str(s);
         Ok(())
     }
 }
 
+/// Keeps `args` in the kernel log and writes it out to the console, see
+/// `kprintln!`. Lines are cut at [`klog::KLOG_LINE_SIZE`] bytes.
+pub fn println(args: fmt::Arguments) {
+    klog::record(klog::LOG_INFO, args);
+    if console::is_panicking() {
+        let _ = fmt::Write::write_fmt(&mut EarlyConsole, args);
+        return;
+    }
+    console::flush_log();
+}
+
 pub struct EarlyConsole;
 impl fmt::Write for EarlyConsole {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let mut uart = get_early_uart().lock();
-        let _ = uart.write_str(s);
+        console::write_str_early(s);
         Ok(())
     }
 }
+
+/// Prints the panic message on the emergency path: the console device is
+/// bypassed and the early console, usually the serial port in polling mode,
+/// flushes whatever is still queued before writing the message.
+pub fn report_panic(info: &PanicInfo) {
+    console::enter_panic_mode();
+    let _ = fmt::Write::write_fmt(&mut EarlyConsole, format_args!("\nOops: {}\n", info));
+}
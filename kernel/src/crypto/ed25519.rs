@@ -0,0 +1,363 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ed25519 signature verification, as specified by RFC 8032.
+//!
+//! Only verification is provided: the kernel checks signatures made
+//! offline with keys it never sees. Field elements are 16 limbs of 16 bits,
+//! which keeps the arithmetic simple at the cost of speed.
+
+use super::{sha512, Digest};
+
+pub const PUBLIC_KEY_SIZE: usize = 32;
+pub const SIGNATURE_SIZE: usize = 64;
+
+type Fe = [i64; 16];
+// Extended coordinates (X, Y, Z, T).
+type Point = [Fe; 4];
+
+const FE_ZERO: Fe = [0; 16];
+const FE_ONE: Fe = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const D: Fe = [
+    0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070, 0xe898, 0x7779, 0x4079, 0x8cc7,
+    0xfe73, 0x2b6f, 0x6cee, 0x5203,
+];
+const D2: Fe = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+const BASE_X: Fe = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const BASE_Y: Fe = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+// Square root of -1.
+const SQRT_M1: Fe = [
+    0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43, 0xd7a7, 0x3dfb, 0x0099, 0x2b4d,
+    0xdf0b, 0x4fc1, 0x2480, 0x2b83,
+];
+// Order of the base point, little endian.
+const L: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+fn carry(o: &mut Fe) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+// Swaps p and q if b is 1, in constant time.
+fn select(p: &mut Fe, q: &mut Fe, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack(n: &Fe) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    let mut m = FE_ZERO;
+    for _ in 0..2 {
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+    let mut o = [0u8; 32];
+    for i in 0..16 {
+        o[2 * i] = t[i] as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn unpack(n: &[u8; 32]) -> Fe {
+    let mut o = FE_ZERO;
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn neq(a: &Fe, b: &Fe) -> bool {
+    pack(a) != pack(b)
+}
+
+fn parity(a: &Fe) -> u8 {
+    pack(a)[0] & 1
+}
+
+fn add(a: &Fe, b: &Fe) -> Fe {
+    core::array::from_fn(|i| a[i] + b[i])
+}
+
+fn sub(a: &Fe, b: &Fe) -> Fe {
+    core::array::from_fn(|i| a[i] - b[i])
+}
+
+fn mul(a: &Fe, b: &Fe) -> Fe {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o: Fe = t[..16].try_into().unwrap();
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn square(a: &Fe) -> Fe {
+    mul(a, a)
+}
+
+fn invert(i: &Fe) -> Fe {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = square(&c);
+        if a != 2 && a != 4 {
+            c = mul(&c, i);
+        }
+    }
+    c
+}
+
+// Raises to the power (p - 5) / 8.
+fn pow2523(i: &Fe) -> Fe {
+    let mut c = *i;
+    for a in (0..=250).rev() {
+        c = square(&c);
+        if a != 1 {
+            c = mul(&c, i);
+        }
+    }
+    c
+}
+
+fn point_add(p: &mut Point, q: &Point) {
+    let a = mul(&sub(&p[1], &p[0]), &sub(&q[1], &q[0]));
+    let b = mul(&add(&p[0], &p[1]), &add(&q[0], &q[1]));
+    let c = mul(&mul(&p[3], &q[3]), &D2);
+    let d = mul(&p[2], &q[2]);
+    let d = add(&d, &d);
+    let e = sub(&b, &a);
+    let f = sub(&d, &c);
+    let g = add(&d, &c);
+    let h = add(&b, &a);
+    p[0] = mul(&e, &f);
+    p[1] = mul(&h, &g);
+    p[2] = mul(&g, &f);
+    p[3] = mul(&e, &h);
+}
+
+fn point_swap(p: &mut Point, q: &mut Point, b: i64) {
+    for (a, c) in p.iter_mut().zip(q.iter_mut()) {
+        select(a, c, b);
+    }
+}
+
+fn point_pack(p: &Point) -> [u8; 32] {
+    let zi = invert(&p[2]);
+    let x = mul(&p[0], &zi);
+    let y = mul(&p[1], &zi);
+    let mut r = pack(&y);
+    r[31] ^= parity(&x) << 7;
+    r
+}
+
+// Decodes a point and negates it, returns None if it isn't on the curve.
+fn point_unpack_neg(p: &[u8; 32]) -> Option<Point> {
+    let y = unpack(p);
+    let num = square(&y);
+    let den = mul(&num, &D);
+    let num = sub(&num, &FE_ONE);
+    let den = add(&FE_ONE, &den);
+    let den2 = square(&den);
+    let den4 = square(&den2);
+    let den6 = mul(&den4, &den2);
+    let mut t = mul(&mul(&den6, &num), &den);
+    t = pow2523(&t);
+    t = mul(&mul(&mul(&t, &num), &den), &den);
+    let mut x = mul(&t, &den);
+    if neq(&mul(&square(&x), &den), &num) {
+        x = mul(&x, &SQRT_M1);
+    }
+    if neq(&mul(&square(&x), &den), &num) {
+        return None;
+    }
+    if parity(&x) == p[31] >> 7 {
+        x = sub(&FE_ZERO, &x);
+    }
+    let t = mul(&x, &y);
+    Some([x, y, FE_ONE, t])
+}
+
+fn scalar_mult(q: &mut Point, s: &[u8; 32]) -> Point {
+    let mut p = [FE_ZERO, FE_ONE, FE_ONE, FE_ZERO];
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        point_swap(&mut p, q, b);
+        point_add(q, &p);
+        let double = p;
+        point_add(&mut p, &double);
+        point_swap(&mut p, q, b);
+    }
+    p
+}
+
+fn scalar_base(s: &[u8; 32]) -> Point {
+    let mut q = [BASE_X, BASE_Y, FE_ONE, mul(&BASE_X, &BASE_Y)];
+    scalar_mult(&mut q, s)
+}
+
+// Reduces a 512-bit little endian number modulo L.
+fn reduce(h: &[u8; 64]) -> [u8; 32] {
+    let mut x: [i64; 64] = core::array::from_fn(|i| h[i] as i64);
+    for i in (32..64).rev() {
+        let mut c = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += c - 16 * x[i] * L[j - (i - 32)];
+            c = (x[j] + 128) >> 8;
+            x[j] -= c << 8;
+            j += 1;
+        }
+        x[j] += c;
+        x[i] = 0;
+    }
+    let mut c = 0;
+    for j in 0..32 {
+        x[j] += c - (x[31] >> 4) * L[j];
+        c = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= c * L[j];
+    }
+    let mut r = [0u8; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    r
+}
+
+// Whether a little endian scalar is below L, which RFC 8032 requires of S
+// so that signatures aren't malleable.
+fn is_canonical(s: &[u8; 32]) -> bool {
+    for i in (0..32).rev() {
+        if (s[i] as i64) != L[i] {
+            return (s[i] as i64) < L[i];
+        }
+    }
+    false
+}
+
+/// Checks `signature` over `message` against `public_key`.
+pub fn verify(
+    public_key: &[u8; PUBLIC_KEY_SIZE],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_SIZE],
+) -> bool {
+    let (r, s) = signature.split_at(32);
+    let s: &[u8; 32] = s.try_into().unwrap();
+    if !is_canonical(s) {
+        return false;
+    }
+    let Some(mut a) = point_unpack_neg(public_key) else {
+        return false;
+    };
+    let mut hasher = sha512::Sha512::new();
+    hasher.update(r);
+    hasher.update(public_key);
+    hasher.update(message);
+    let h = reduce(&hasher.finish());
+    // [S]B - [h]A must be R.
+    let mut p = scalar_mult(&mut a, &h);
+    point_add(&mut p, &scalar_base(s));
+    point_pack(&p) == r
+}
+
+/// Test 2 of RFC 8032, section 7.1, which signs the single byte 0x72.
+#[cfg(test)]
+pub(crate) mod rfc8032 {
+    use super::{PUBLIC_KEY_SIZE, SIGNATURE_SIZE};
+
+    pub const PUBLIC_KEY: [u8; PUBLIC_KEY_SIZE] = [
+        0x3d, 0x40, 0x17, 0xc3, 0xe8, 0x43, 0x89, 0x5a, 0x92, 0xb7, 0x0a, 0xa7, 0x4d, 0x1b, 0x7e,
+        0xbc, 0x9c, 0x98, 0x2c, 0xcf, 0x2e, 0xc4, 0x96, 0x8c, 0xc0, 0xcd, 0x55, 0xf1, 0x2a, 0xf4,
+        0x66, 0x0c,
+    ];
+    pub const SIGNATURE: [u8; SIGNATURE_SIZE] = [
+        0x92, 0xa0, 0x09, 0xa9, 0xf0, 0xd4, 0xca, 0xb8, 0x72, 0x0e, 0x82, 0x0b, 0x5f, 0x64, 0x25,
+        0x40, 0xa2, 0xb2, 0x7b, 0x54, 0x16, 0x50, 0x3f, 0x8f, 0xb3, 0x76, 0x22, 0x23, 0xeb, 0xdb,
+        0x69, 0xda, 0x08, 0x5a, 0xc1, 0xe4, 0x3e, 0x15, 0x99, 0x6e, 0x45, 0x8f, 0x36, 0x13, 0xd0,
+        0xf1, 0x1d, 0x8c, 0x38, 0x7b, 0x2e, 0xae, 0xb4, 0x30, 0x2a, 0xee, 0xb0, 0x0d, 0x29, 0x16,
+        0x12, 0xbb, 0x0c, 0x00,
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rfc8032::*, *};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_ed25519_verify() {
+        assert!(verify(&PUBLIC_KEY, &[0x72], &SIGNATURE));
+        assert!(!verify(&PUBLIC_KEY, &[0x73], &SIGNATURE));
+        assert!(!verify(&PUBLIC_KEY, &[], &SIGNATURE));
+
+        let mut signature = SIGNATURE;
+        signature[5] ^= 1;
+        assert!(!verify(&PUBLIC_KEY, &[0x72], &signature));
+
+        // S + L verifies in the group, but isn't canonical.
+        let mut signature = SIGNATURE;
+        let mut c = 0;
+        for i in 0..32 {
+            let v = signature[32 + i] as i64 + L[i] + c;
+            signature[32 + i] = v as u8;
+            c = v >> 8;
+        }
+        assert!(!verify(&PUBLIC_KEY, &[0x72], &signature));
+    }
+}
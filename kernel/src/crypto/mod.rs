@@ -0,0 +1,35 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod ed25519;
+pub mod random;
+pub mod sha256;
+pub mod sha512;
+
+pub use sha256::Sha256;
+pub use sha512::Sha512;
+
+/// A hash function fed incrementally.
+pub trait Digest {
+    type Output: AsRef<[u8]>;
+
+    fn update(&mut self, data: &[u8]);
+    fn finish(self) -> Self::Output;
+}
+
+/// Compares two digests in constant time, so that the position of the
+/// first difference doesn't leak.
+pub fn digest_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
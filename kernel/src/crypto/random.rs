@@ -0,0 +1,197 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The entropy pool behind /dev/random, /dev/urandom and `getrandom`.
+//!
+//! Samples, mostly the cycle counter at each interrupt, are folded into a
+//! small pool without locking, so that interrupt handlers can feed it.
+//! Random bytes are SHA-256 hashes of a key and a counter. Each request
+//! first replaces the key with a hash of itself and the pool, so that the
+//! output follows new samples and earlier output can't be recovered from
+//! the key.
+//!
+//! The pool is initialized once it has been fed [`INIT_SAMPLES`] samples.
+
+use super::{sha256::DIGEST_SIZE, Digest, Sha256};
+use crate::{
+    error::{code, Error},
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    time,
+};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Fail with EAGAIN instead of waiting for the pool to be initialized.
+pub const GRND_NONBLOCK: u32 = 0x1;
+/// Accepted for compatibility, /dev/random and /dev/urandom are the same
+/// once the pool is initialized.
+pub const GRND_RANDOM: u32 = 0x2;
+/// Don't wait for the pool to be initialized.
+pub const GRND_INSECURE: u32 = 0x4;
+
+/// Samples credited before the pool is initialized. The timing of an
+/// interrupt carries a few bits of entropy at best.
+pub const INIT_SAMPLES: usize = 64;
+const POOL_WORDS: usize = 16;
+
+static POOL: [AtomicU32; POOL_WORDS] = [const { AtomicU32::new(0) }; POOL_WORDS];
+// Next word of the pool to mix into.
+static CURSOR: AtomicUsize = AtomicUsize::new(0);
+static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+// Set to 1 once initialized, waited on until then.
+static READY: AtomicUsize = AtomicUsize::new(0);
+static GENERATOR: SpinLock<Generator> = SpinLock::new(Generator {
+    key: [0; DIGEST_SIZE],
+    requests: 0,
+});
+
+struct Generator {
+    key: [u8; DIGEST_SIZE],
+    requests: u64,
+}
+
+fn mix(word: u32) {
+    let i = CURSOR.fetch_add(1, Ordering::Relaxed);
+    let rotation = (i / POOL_WORDS) as u32 % 32;
+    // A nested handler may mix into the same word.
+    let _ = POOL[i % POOL_WORDS].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+        Some(old.rotate_left(7) ^ word.rotate_left(rotation))
+    });
+}
+
+fn mix_u64(value: u64) {
+    mix(value as u32);
+    mix((value >> 32) as u32);
+}
+
+fn set_ready() {
+    if READY.swap(1, Ordering::AcqRel) == 0 {
+        let _ = atomic_wake(&READY, usize::MAX);
+    }
+}
+
+/// Feeds the cycle counter at the current interrupt. Can be called from
+/// interrupt context.
+pub fn add_interrupt_randomness() {
+    mix_u64(time::get_sys_cycles());
+    if SAMPLES.fetch_add(1, Ordering::Relaxed) + 1 == INIT_SAMPLES {
+        set_ready();
+    }
+}
+
+/// Mixes `data` into the pool without crediting it, for data which may be
+/// known, like MAC addresses or what is written to /dev/random.
+pub fn add_device_randomness(data: &[u8]) {
+    for chunk in data.chunks(4) {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        mix(u32::from_le_bytes(word));
+    }
+    mix_u64(time::get_sys_cycles());
+}
+
+/// Mixes the cycle counter at boot into the pool.
+pub(crate) fn init() {
+    mix_u64(time::get_sys_cycles());
+}
+
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Acquire) != 0
+}
+
+/// Waits until the pool is initialized, or fails with EAGAIN if `nonblock`.
+pub fn wait_ready(nonblock: bool) -> Result<(), Error> {
+    while !is_ready() {
+        if nonblock {
+            return Err(code::EAGAIN);
+        }
+        let _ = atomic_wait(&READY, 0, None);
+    }
+    Ok(())
+}
+
+/// Fills `buf` with random bytes, whether the pool is initialized or not.
+pub fn fill_bytes(buf: &mut [u8]) {
+    let request_key = {
+        let mut generator = GENERATOR.irqsave_lock();
+        let mut hasher = Sha256::new();
+        hasher.update(&generator.key);
+        for word in POOL.iter() {
+            hasher.update(&word.load(Ordering::Relaxed).to_le_bytes());
+        }
+        hasher.update(&generator.requests.to_le_bytes());
+        hasher.update(&time::get_sys_cycles().to_le_bytes());
+        generator.key = hasher.finish();
+        generator.requests += 1;
+        // Derived, so that the output doesn't reveal the next key.
+        let mut hasher = Sha256::new();
+        hasher.update(&generator.key);
+        hasher.update(b"output");
+        hasher.finish()
+    };
+    for (counter, chunk) in buf.chunks_mut(DIGEST_SIZE).enumerate() {
+        let mut hasher = Sha256::new();
+        hasher.update(&request_key);
+        hasher.update(&(counter as u64).to_le_bytes());
+        let block = hasher.finish();
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+/// Fills `buf` like the `getrandom` syscall, waiting for the pool to be
+/// initialized unless GRND_NONBLOCK or GRND_INSECURE is given.
+pub fn getrandom(buf: &mut [u8], flags: u32) -> Result<usize, Error> {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+        || flags & (GRND_RANDOM | GRND_INSECURE) == (GRND_RANDOM | GRND_INSECURE)
+    {
+        return Err(code::EINVAL);
+    }
+    if flags & GRND_INSECURE == 0 {
+        wait_ready(flags & GRND_NONBLOCK != 0)?;
+    }
+    fill_bytes(buf);
+    Ok(buf.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_fill_bytes() {
+        let mut a = [0u8; 100];
+        let mut b = [0u8; 100];
+        fill_bytes(&mut a);
+        fill_bytes(&mut b);
+        assert_ne!(a, b);
+        // Blocks of a request differ too.
+        assert_ne!(a[..DIGEST_SIZE], a[DIGEST_SIZE..2 * DIGEST_SIZE]);
+        assert!(a[2 * DIGEST_SIZE..].iter().any(|&x| x != 0));
+    }
+
+    #[test]
+    fn test_getrandom() {
+        let mut buf = [0u8; 16];
+        assert_eq!(getrandom(&mut buf, 0x8), Err(code::EINVAL));
+        assert_eq!(
+            getrandom(&mut buf, GRND_RANDOM | GRND_INSECURE),
+            Err(code::EINVAL)
+        );
+        assert_eq!(getrandom(&mut buf, GRND_INSECURE), Ok(16));
+        // The tick feeds the pool, it's initialized soon after boot.
+        assert_eq!(getrandom(&mut buf, 0), Ok(16));
+        assert!(is_ready());
+        assert_eq!(getrandom(&mut buf, GRND_NONBLOCK), Ok(16));
+    }
+}
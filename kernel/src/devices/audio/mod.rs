@@ -0,0 +1,690 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PCM audio.
+//!
+//! Sound drivers implement [`PcmOps`] for each of their streams and
+//! register it with [`register_pcm`], as a char device "pcm0p" for playback
+//! or "pcm0c" for capture. Samples written to or read from the device go
+//! through a ring buffer of [`PcmParams::periods`] periods. Once started,
+//! the driver moves a period at a time, as its hardware interrupts:
+//! playback drivers take the next period with [`PcmStream::take_period`],
+//! capture drivers hand theirs over with [`PcmStream::put_period`].
+//!
+//! The format is set with [`PCM_SET_PARAMS`], which settles on the closest
+//! one the driver supports and passes it back. Streams which weren't set
+//! up are opened with [`PcmParams::DEFAULT`].
+//!
+//! A playback stream which runs out of samples counts an underrun and is
+//! stopped, to be started again by the next write. A capture stream whose
+//! ring is full drops its oldest samples and counts an overrun.
+
+#[cfg(virtio)]
+pub mod virtio;
+
+use super::{devno, Device, DeviceBase, DeviceClass, DeviceId, DeviceManager};
+use crate::{
+    error::{code, Error},
+    sync::{
+        atomic_wait::{atomic_wait, atomic_wake},
+        SpinLock,
+    },
+    thread,
+    time::{self, Instant},
+};
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+crate::ioctl_readwrite!(
+    /// Sets the format of a stream up, stopping it. The closest format the
+    /// driver supports is passed back.
+    pub PCM_SET_PARAMS, b'A', 0x10, PcmParams
+);
+crate::ioctl_read!(
+    /// Reads the format of a stream.
+    pub PCM_GET_PARAMS, b'A', 0x11, PcmParams
+);
+crate::ioctl_read!(
+    /// Reads the [`PcmStatus`] of a stream.
+    pub PCM_GET_STATUS, b'A', 0x20, PcmStatus
+);
+crate::ioctl_none!(
+    /// Waits for the samples written to a playback stream to be played,
+    /// then stops it.
+    pub PCM_DRAIN, b'A', 0x44
+);
+crate::ioctl_none!(
+    /// Stops a stream at once, dropping the samples in its ring.
+    pub PCM_DROP, b'A', 0x43
+);
+
+/// Samples of unsigned 8 bits.
+pub const PCM_FORMAT_U8: u32 = 0;
+/// Samples of signed 16 bits, little endian.
+pub const PCM_FORMAT_S16_LE: u32 = 1;
+/// Samples of signed 32 bits, little endian.
+pub const PCM_FORMAT_S32_LE: u32 = 2;
+
+// Formats to fall back to, best first.
+const FORMAT_PREFERENCE: [u32; 3] = [PCM_FORMAT_S16_LE, PCM_FORMAT_S32_LE, PCM_FORMAT_U8];
+const MIN_PERIODS: u32 = 2;
+const MAX_PERIODS: u32 = 32;
+const MAX_BUFFER_BYTES: usize = 256 * 1024;
+// How long draining may take past the time the ring takes to play.
+const DRAIN_SLACK: Duration = Duration::from_millis(500);
+
+static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+fn sample_bytes(format: u32) -> Option<usize> {
+    match format {
+        PCM_FORMAT_U8 => Some(1),
+        PCM_FORMAT_S16_LE => Some(2),
+        PCM_FORMAT_S32_LE => Some(4),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Playback,
+    Capture,
+}
+
+/// The format of a stream, and the size of its ring buffer.
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct PcmParams {
+    /// One of the `PCM_FORMAT_*` formats.
+    pub format: u32,
+    pub channels: u32,
+    /// Frames per second.
+    pub rate: u32,
+    /// Frames moved by the driver at a time.
+    pub period_frames: u32,
+    /// Periods in the ring buffer.
+    pub periods: u32,
+}
+
+impl PcmParams {
+    /// CD quality stereo, in periods of about 20ms.
+    pub const DEFAULT: PcmParams = PcmParams {
+        format: PCM_FORMAT_S16_LE,
+        channels: 2,
+        rate: 44100,
+        period_frames: 882,
+        periods: 4,
+    };
+
+    pub fn frame_bytes(&self) -> usize {
+        sample_bytes(self.format).unwrap_or(1) * self.channels as usize
+    }
+
+    pub fn period_bytes(&self) -> usize {
+        self.frame_bytes() * self.period_frames as usize
+    }
+
+    pub fn buffer_bytes(&self) -> usize {
+        self.period_bytes() * self.periods as usize
+    }
+
+    pub fn period_time(&self) -> Duration {
+        Duration::from_nanos(self.period_frames as u64 * 1_000_000_000 / self.rate.max(1) as u64)
+    }
+
+    fn silence(&self) -> u8 {
+        if self.format == PCM_FORMAT_U8 {
+            0x80
+        } else {
+            0
+        }
+    }
+}
+
+/// What a stream's driver supports.
+#[derive(Debug, Clone)]
+pub struct PcmCaps {
+    /// Bit `1 << f` is set for each supported `PCM_FORMAT_*` format `f`.
+    pub formats: u32,
+    pub rates: Vec<u32>,
+    pub channels: RangeInclusive<u32>,
+    pub period_bytes: RangeInclusive<usize>,
+}
+
+impl PcmCaps {
+    /// Returns the parameters closest to `wanted` which are supported.
+    pub fn negotiate(&self, wanted: &PcmParams) -> Result<PcmParams, Error> {
+        let supported = |f: u32| f < u32::BITS && self.formats & (1 << f) != 0;
+        let format = if supported(wanted.format) {
+            wanted.format
+        } else {
+            *FORMAT_PREFERENCE
+                .iter()
+                .find(|&&f| supported(f))
+                .ok_or(code::EINVAL)?
+        };
+        // The closest rate, the higher one of two as close.
+        let rate = *self
+            .rates
+            .iter()
+            .min_by_key(|&&r| (r.abs_diff(wanted.rate), u32::MAX - r))
+            .ok_or(code::EINVAL)?;
+        if self.channels.is_empty() || *self.channels.end() == 0 || self.period_bytes.is_empty() {
+            return Err(code::EINVAL);
+        }
+        let channels = wanted
+            .channels
+            .clamp((*self.channels.start()).max(1), *self.channels.end());
+
+        let frame_bytes = sample_bytes(format).unwrap() * channels as usize;
+        let min_frames = self.period_bytes.start().div_ceil(frame_bytes).max(1);
+        let max_frames = self.period_bytes.end() / frame_bytes;
+        if min_frames > max_frames {
+            return Err(code::EINVAL);
+        }
+        let period_frames = (wanted.period_frames as usize).clamp(min_frames, max_frames);
+        let max_periods = (MAX_BUFFER_BYTES / (period_frames * frame_bytes)) as u32;
+        let periods = wanted
+            .periods
+            .clamp(MIN_PERIODS, MAX_PERIODS.min(max_periods.max(MIN_PERIODS)));
+        Ok(PcmParams {
+            format,
+            channels,
+            rate,
+            period_frames: period_frames as u32,
+            periods,
+        })
+    }
+}
+
+/// The state of a stream, for [`PCM_GET_STATUS`].
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct PcmStatus {
+    /// 1 if the driver is moving periods.
+    pub running: u32,
+    /// Bytes in the ring, waiting to be played or read.
+    pub queued: u32,
+    /// Underruns of a playback stream, or overruns of a capture stream.
+    pub xruns: u32,
+}
+
+/// The hardware side of a stream.
+pub trait PcmOps: Send + Sync {
+    fn caps(&self) -> PcmCaps;
+    /// Sets the hardware up for `params`, as negotiated from the caps. The
+    /// stream is stopped.
+    fn prepare(&mut self, params: &PcmParams) -> Result<(), Error>;
+    /// Starts moving periods of `stream`, until stopped or, for playback,
+    /// until [`PcmStream::take_period`] runs dry.
+    fn start(&mut self, stream: &Arc<PcmStream>) -> Result<(), Error>;
+    fn stop(&mut self) -> Result<(), Error>;
+}
+
+// The ring buffer of a stream, and the format of what's in it.
+#[derive(Debug)]
+struct Ring {
+    params: PcmParams,
+    data: VecDeque<u8>,
+    running: bool,
+    // Running dry ends a drain rather than being an underrun.
+    draining: bool,
+    xruns: u32,
+}
+
+impl Ring {
+    fn new(params: PcmParams) -> Self {
+        Self {
+            params,
+            data: VecDeque::with_capacity(params.buffer_bytes()),
+            running: false,
+            draining: false,
+            xruns: 0,
+        }
+    }
+
+    fn room(&self) -> usize {
+        self.params.buffer_bytes() - self.data.len()
+    }
+
+    // Queues whole frames of `buf` for playback, as many as there's room
+    // for.
+    fn push(&mut self, buf: &[u8]) -> usize {
+        let frame_bytes = self.params.frame_bytes();
+        let len = buf.len().min(self.room()) / frame_bytes * frame_bytes;
+        self.data.extend(&buf[..len]);
+        len
+    }
+
+    fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.data.len());
+        for (dst, src) in buf.iter_mut().zip(self.data.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
+
+    // Fills `period` with the next samples to play, padding it with
+    // silence if there aren't enough. Returns false, and stops, if there
+    // are none.
+    fn take_period(&mut self, period: &mut [u8]) -> bool {
+        if !self.running {
+            return false;
+        }
+        if self.data.is_empty() {
+            self.running = false;
+            if !self.draining {
+                self.xruns = self.xruns.saturating_add(1);
+            }
+            return false;
+        }
+        let len = self.pop(period);
+        period[len..].fill(self.params.silence());
+        true
+    }
+
+    // Queues captured samples, dropping the oldest ones if the ring is
+    // full.
+    fn put_period(&mut self, period: &[u8]) {
+        let capacity = self.params.buffer_bytes();
+        let period = &period[period.len().saturating_sub(capacity)..];
+        let overflow = (self.data.len() + period.len()).saturating_sub(capacity);
+        if overflow > 0 {
+            self.data.drain(..overflow);
+            self.xruns = self.xruns.saturating_add(1);
+        }
+        self.data.extend(period);
+    }
+}
+
+/// A playback or capture stream, registered as a char device.
+pub struct PcmStream {
+    base: DeviceBase,
+    name: String,
+    id: DeviceId,
+    direction: Direction,
+    this: Weak<PcmStream>,
+    ops: SpinLock<Box<dyn PcmOps>>,
+    ring: SpinLock<Option<Ring>>,
+    // Bumped and woken up whenever a period is moved.
+    futex: AtomicUsize,
+}
+
+impl PcmStream {
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Fills `period` with the next samples to play, for playback drivers.
+    /// Returns false, once the ring has run dry or the stream was stopped,
+    /// and the driver stops.
+    pub fn take_period(&self, period: &mut [u8]) -> bool {
+        let taken = self
+            .ring
+            .irqsave_lock()
+            .as_mut()
+            .is_some_and(|ring| ring.take_period(period));
+        self.wake();
+        taken
+    }
+
+    /// Queues the samples of a period, for capture drivers.
+    pub fn put_period(&self, period: &[u8]) {
+        if let Some(ring) = self.ring.irqsave_lock().as_mut() {
+            if ring.running {
+                ring.put_period(period);
+            }
+        }
+        self.wake();
+    }
+
+    fn wake(&self) {
+        self.futex.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.futex, usize::MAX);
+    }
+
+    // Waits for a period to be moved, or for about as long as one takes,
+    // whichever comes first.
+    fn wait_period(&self, seen: usize, params: &PcmParams) {
+        let ticks = time::duration_to_ticks(params.period_time()).max(1);
+        let _wait = thread::wait_on_device("pcm");
+        let _ = atomic_wait(&self.futex, seen, Some(ticks));
+    }
+
+    // Returns the params of the ring, setting it up with the default ones
+    // if there's none yet.
+    fn params(&self) -> Result<PcmParams, Error> {
+        if let Some(ring) = self.ring.irqsave_lock().as_ref() {
+            return Ok(ring.params);
+        }
+        self.set_params(&PcmParams::DEFAULT)
+    }
+
+    fn set_params(&self, wanted: &PcmParams) -> Result<PcmParams, Error> {
+        let mut ops = self.ops.lock();
+        let params = ops.caps().negotiate(wanted)?;
+        self.stop(&mut **ops)?;
+        ops.prepare(&params)?;
+        *self.ring.irqsave_lock() = Some(Ring::new(params));
+        Ok(params)
+    }
+
+    fn stop(&self, ops: &mut dyn PcmOps) -> Result<(), Error> {
+        if let Some(ring) = self.ring.irqsave_lock().as_mut() {
+            ring.running = false;
+            ring.draining = false;
+            ring.data.clear();
+        }
+        let result = ops.stop();
+        self.wake();
+        result
+    }
+
+    // Starts the driver unless it's running already.
+    fn start(&self) -> Result<(), Error> {
+        let mut ops = self.ops.lock();
+        {
+            let mut ring = self.ring.irqsave_lock();
+            let Some(ring) = ring.as_mut() else {
+                return Err(code::EINVAL);
+            };
+            if ring.running {
+                return Ok(());
+            }
+            ring.running = true;
+        }
+        let this = self.this.upgrade().ok_or(code::ENODEV)?;
+        let result = ops.start(&this);
+        if result.is_err() {
+            if let Some(ring) = self.ring.irqsave_lock().as_mut() {
+                ring.running = false;
+            }
+        }
+        result
+    }
+
+    // Waits for the driver to have played what's in the ring, and asked
+    // for more, then stops it.
+    fn drain(&self) -> Result<(), Error> {
+        let params = self.params()?;
+        let queued = {
+            let mut ring = self.ring.irqsave_lock();
+            let Some(ring) = ring.as_mut() else {
+                return Ok(());
+            };
+            ring.draining = true;
+            ring.data.len()
+        };
+        // What's left may be less than the period writes wait for.
+        if queued > 0 {
+            self.start()?;
+        }
+        let deadline = Instant::now() + params.period_time() * (params.periods + 1) + DRAIN_SLACK;
+        let mut result = Ok(());
+        loop {
+            let seen = self.futex.load(Ordering::Acquire);
+            let running = self
+                .ring
+                .irqsave_lock()
+                .as_ref()
+                .is_some_and(|ring| ring.running);
+            if !running {
+                break;
+            }
+            if Instant::now() >= deadline {
+                result = Err(code::ETIMEDOUT);
+                break;
+            }
+            self.wait_period(seen, &params);
+        }
+        self.stop(&mut **self.ops.lock()).and(result)
+    }
+
+    fn status(&self) -> PcmStatus {
+        let ring = self.ring.irqsave_lock();
+        ring.as_ref()
+            .map_or_else(PcmStatus::default, |ring| PcmStatus {
+                running: ring.running as u32,
+                queued: ring.data.len() as u32,
+                xruns: ring.xruns,
+            })
+    }
+}
+
+impl Device for PcmStream {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn open(&self) -> Result<(), Error> {
+        self.base.inc_open_count();
+        Ok(())
+    }
+
+    // Playback is drained, and capture stopped, once the last user is
+    // gone.
+    fn close(&self) -> Result<(), Error> {
+        if self.base.dec_open_count() != 1 {
+            return Ok(());
+        }
+        match self.direction {
+            Direction::Playback => self.drain(),
+            Direction::Capture => self.stop(&mut **self.ops.lock()),
+        }
+    }
+
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        if self.direction != Direction::Capture {
+            return Err(code::EINVAL);
+        }
+        let params = self.params()?;
+        // Whole frames only.
+        let len = buf.len() / params.frame_bytes() * params.frame_bytes();
+        if len == 0 {
+            return Ok(0);
+        }
+        self.start()?;
+        loop {
+            let seen = self.futex.load(Ordering::Acquire);
+            let read = {
+                let mut ring = self.ring.irqsave_lock();
+                let Some(ring) = ring.as_mut() else {
+                    return Err(code::EIO);
+                };
+                let available = ring.data.len() / params.frame_bytes() * params.frame_bytes();
+                ring.pop(&mut buf[..len.min(available)])
+            };
+            if read > 0 {
+                return Ok(read);
+            }
+            if is_nonblocking {
+                return Err(code::EAGAIN);
+            }
+            self.wait_period(seen, &params);
+        }
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, Error> {
+        if self.direction != Direction::Playback {
+            return Err(code::EINVAL);
+        }
+        let params = self.params()?;
+        let mut written = 0;
+        loop {
+            let seen = self.futex.load(Ordering::Acquire);
+            let (queued, running) = {
+                let mut ring = self.ring.irqsave_lock();
+                let Some(ring) = ring.as_mut() else {
+                    return Err(code::EIO);
+                };
+                written += ring.push(&buf[written..]);
+                (ring.data.len(), ring.running)
+            };
+            // Started once a period is queued, so that the driver doesn't
+            // run dry right away.
+            if !running && queued >= params.period_bytes() {
+                self.start()?;
+            }
+            if buf.len() - written < params.frame_bytes() {
+                return Ok(written);
+            }
+            if is_nonblocking {
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(code::EAGAIN)
+                };
+            }
+            self.wait_period(seen, &params);
+        }
+    }
+
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if PCM_SET_PARAMS.matches(request) {
+            let wanted = PCM_SET_PARAMS.copy_in(arg)?;
+            let params = self.set_params(&wanted)?;
+            return PCM_SET_PARAMS.copy_out(arg, &params);
+        }
+        if PCM_GET_PARAMS.matches(request) {
+            return PCM_GET_PARAMS.copy_out(arg, &self.params()?);
+        }
+        if PCM_GET_STATUS.matches(request) {
+            return PCM_GET_STATUS.copy_out(arg, &self.status());
+        }
+        if PCM_DRAIN.matches(request) {
+            return match self.direction {
+                Direction::Playback => self.drain(),
+                Direction::Capture => Err(code::EINVAL),
+            };
+        }
+        if PCM_DROP.matches(request) {
+            return self.stop(&mut **self.ops.lock());
+        }
+        Err(code::ENOSYS)
+    }
+}
+
+/// Registers a stream of a sound driver, as "pcm<n>p" or "pcm<n>c".
+pub fn register_pcm(direction: Direction, ops: Box<dyn PcmOps>) -> Result<Arc<PcmStream>, Error> {
+    let index = STREAMS.fetch_add(1, Ordering::Relaxed);
+    let name = match direction {
+        Direction::Playback => format!("pcm{}p", index),
+        Direction::Capture => format!("pcm{}c", index),
+    };
+    let major = devno::register_major(DeviceClass::Char, 0, "pcm")?;
+    let id = devno::alloc_minor(DeviceClass::Char, major)?;
+    let stream = Arc::new_cyclic(|this| PcmStream {
+        base: DeviceBase::new(),
+        name: name.clone(),
+        id,
+        direction,
+        this: this.clone(),
+        ops: SpinLock::new(ops),
+        ring: SpinLock::new(None),
+        futex: AtomicUsize::new(0),
+    });
+    DeviceManager::get().register_device(name, stream.clone())?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_pcm_params() {
+        let caps = PcmCaps {
+            formats: 1 << PCM_FORMAT_U8 | 1 << PCM_FORMAT_S32_LE,
+            rates: vec![8000, 44100, 48000],
+            channels: 1..=2,
+            period_bytes: 64..=4096,
+        };
+        let params = caps.negotiate(&PcmParams::DEFAULT).unwrap();
+        assert_eq!(params.format, PCM_FORMAT_S32_LE);
+        assert_eq!((params.channels, params.rate), (2, 44100));
+        assert_eq!(params.period_frames, 512, "at most 4096 bytes");
+        assert_eq!(params.periods, 4);
+
+        let wanted = PcmParams {
+            format: PCM_FORMAT_U8,
+            channels: 6,
+            rate: 46050,
+            period_frames: 1,
+            periods: 1000,
+        };
+        let params = caps.negotiate(&wanted).unwrap();
+        assert_eq!((params.format, params.channels), (PCM_FORMAT_U8, 2));
+        assert_eq!(params.rate, 48000);
+        assert_eq!(params.period_frames, 32);
+        assert_eq!(params.periods, MAX_PERIODS);
+        let none = PcmCaps {
+            formats: 1 << 7,
+            ..caps
+        };
+        assert_eq!(none.negotiate(&wanted), Err(code::EINVAL));
+
+        // Playback pads the last period and stops once it's dry.
+        let mut ring = Ring::new(PcmParams {
+            format: PCM_FORMAT_U8,
+            channels: 1,
+            rate: 8000,
+            period_frames: 4,
+            periods: 2,
+        });
+        assert_eq!(ring.push(&[1, 2, 3, 4, 5, 6, 7, 8, 9]), 8);
+        ring.running = true;
+        let mut period = [0u8; 4];
+        assert!(ring.take_period(&mut period));
+        assert_eq!(ring.push(&[9]), 1);
+        assert!(ring.take_period(&mut period));
+        assert!(ring.take_period(&mut period));
+        assert_eq!(period, [9, 0x80, 0x80, 0x80]);
+        assert!(!ring.take_period(&mut period));
+        assert_eq!((ring.running, ring.xruns), (false, 1));
+
+        // Capture drops the oldest samples.
+        ring.put_period(&[1, 2, 3, 4]);
+        ring.put_period(&[5, 6, 7, 8]);
+        ring.put_period(&[9, 10, 11, 12]);
+        assert_eq!(ring.xruns, 2);
+        let mut buf = [0u8; 16];
+        assert_eq!(ring.pop(&mut buf), 8);
+        assert_eq!(buf[..8], [5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+}
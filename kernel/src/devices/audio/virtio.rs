@@ -0,0 +1,224 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! virtio-sound playback.
+//!
+//! Each output stream of the device is registered as a playback stream.
+//! The device isn't driven by interrupts: while a stream runs, the worker
+//! of the stream takes its periods and hands them to the device, which
+//! returns each about once it's played. The virtio driver can't capture, so input
+//! streams are left out.
+
+use super::{
+    register_pcm, Direction, PcmCaps, PcmOps, PcmParams, PcmStream, PCM_FORMAT_S16_LE,
+    PCM_FORMAT_S32_LE, PCM_FORMAT_U8,
+};
+use crate::{
+    devices::virtio::VirtioHal,
+    error::{code, Error},
+    sync::SpinLock,
+    thread::ThreadPool,
+};
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{debug, error, warn};
+use virtio_drivers::{
+    device::sound::{PcmFeatures, PcmFormat, PcmFormats, PcmRate, PcmRates, VirtIOSound},
+    transport::SomeTransport,
+};
+
+type Sound = VirtIOSound<VirtioHal, SomeTransport<'static>>;
+
+const FORMATS: [(u32, PcmFormat, PcmFormats); 3] = [
+    (PCM_FORMAT_U8, PcmFormat::U8, PcmFormats::U8),
+    (PCM_FORMAT_S16_LE, PcmFormat::S16, PcmFormats::S16),
+    (PCM_FORMAT_S32_LE, PcmFormat::S32, PcmFormats::S32),
+];
+
+const RATES: [(u32, PcmRate, PcmRates); 14] = [
+    (5512, PcmRate::Rate5512, PcmRates::RATE_5512),
+    (8000, PcmRate::Rate8000, PcmRates::RATE_8000),
+    (11025, PcmRate::Rate11025, PcmRates::RATE_11025),
+    (16000, PcmRate::Rate16000, PcmRates::RATE_16000),
+    (22050, PcmRate::Rate22050, PcmRates::RATE_22050),
+    (32000, PcmRate::Rate32000, PcmRates::RATE_32000),
+    (44100, PcmRate::Rate44100, PcmRates::RATE_44100),
+    (48000, PcmRate::Rate48000, PcmRates::RATE_48000),
+    (64000, PcmRate::Rate64000, PcmRates::RATE_64000),
+    (88200, PcmRate::Rate88200, PcmRates::RATE_88200),
+    (96000, PcmRate::Rate96000, PcmRates::RATE_96000),
+    (176400, PcmRate::Rate176400, PcmRates::RATE_176400),
+    (192000, PcmRate::Rate192000, PcmRates::RATE_192000),
+    (384000, PcmRate::Rate384000, PcmRates::RATE_384000),
+];
+
+// The device takes periods through a single descriptor.
+const MAX_PERIOD_BYTES: usize = 64 * 1024;
+
+fn to_error(e: virtio_drivers::Error) -> Error {
+    match e {
+        virtio_drivers::Error::Unsupported => code::ENOTSUP,
+        virtio_drivers::Error::InvalidParam => code::EINVAL,
+        virtio_drivers::Error::DmaError => code::ENOMEM,
+        _ => code::EIO,
+    }
+}
+
+struct VirtioPcm {
+    sound: Arc<SpinLock<Sound>>,
+    stream_id: u32,
+    caps: PcmCaps,
+    params: PcmParams,
+    started: bool,
+    // Bumped to retire the job moving the periods of the last start.
+    generation: Arc<AtomicUsize>,
+    // A retired job stops at its next period, so the job of a new start
+    // only waits for that.
+    worker: ThreadPool,
+}
+
+impl VirtioPcm {
+    fn new(sound: &Arc<SpinLock<Sound>>, stream_id: u32) -> Result<Self, Error> {
+        let (formats, rates, channels) = {
+            let mut sound = sound.lock();
+            (
+                sound.formats_supported(stream_id).map_err(to_error)?,
+                sound.rates_supported(stream_id).map_err(to_error)?,
+                sound.channel_range_supported(stream_id).map_err(to_error)?,
+            )
+        };
+        let caps = PcmCaps {
+            formats: FORMATS
+                .iter()
+                .filter(|(_, _, f)| formats.contains(*f))
+                .fold(0, |acc, (format, _, _)| acc | 1 << format),
+            rates: RATES
+                .iter()
+                .filter(|(_, _, r)| rates.contains(*r))
+                .map(|(rate, _, _)| *rate)
+                .collect(),
+            channels: *channels.start() as u32..=*channels.end() as u32,
+            period_bytes: 1..=MAX_PERIOD_BYTES,
+        };
+        Ok(Self {
+            sound: sound.clone(),
+            stream_id,
+            caps,
+            params: PcmParams::DEFAULT,
+            started: false,
+            generation: Arc::new(AtomicUsize::new(0)),
+            worker: ThreadPool::new("virtio-snd", 1, 2)?,
+        })
+    }
+}
+
+impl PcmOps for VirtioPcm {
+    fn caps(&self) -> PcmCaps {
+        self.caps.clone()
+    }
+
+    fn prepare(&mut self, params: &PcmParams) -> Result<(), Error> {
+        let format = FORMATS
+            .iter()
+            .find(|(f, _, _)| *f == params.format)
+            .ok_or(code::EINVAL)?
+            .1;
+        let rate = RATES
+            .iter()
+            .find(|(r, _, _)| *r == params.rate)
+            .ok_or(code::EINVAL)?
+            .1;
+        let mut sound = self.sound.lock();
+        // Fails if the stream was never prepared.
+        let _ = sound.pcm_release(self.stream_id);
+        sound
+            .pcm_set_params(
+                self.stream_id,
+                params.buffer_bytes() as u32,
+                params.period_bytes() as u32,
+                PcmFeatures::empty(),
+                params.channels as u8,
+                format,
+                rate,
+            )
+            .map_err(to_error)?;
+        sound.pcm_prepare(self.stream_id).map_err(to_error)?;
+        self.params = *params;
+        Ok(())
+    }
+
+    fn start(&mut self, stream: &Arc<PcmStream>) -> Result<(), Error> {
+        // The device keeps running when the ring runs dry, it's only
+        // stopped with the stream.
+        if !self.started {
+            self.sound
+                .lock()
+                .pcm_start(self.stream_id)
+                .map_err(to_error)?;
+            self.started = true;
+        }
+        let this = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let generation = self.generation.clone();
+        let sound = self.sound.clone();
+        let stream = stream.clone();
+        let stream_id = self.stream_id;
+        let mut period = vec![0u8; self.params.period_bytes()];
+        self.worker.try_execute(move || {
+            while generation.load(Ordering::Acquire) == this && stream.take_period(&mut period) {
+                if let Err(e) = sound.lock().pcm_xfer(stream_id, &period) {
+                    warn!("Failed to play a period of virtio-sound: {:?}", e);
+                    break;
+                }
+            }
+        })
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        if !self.started {
+            return Ok(());
+        }
+        self.started = false;
+        self.sound.lock().pcm_stop(self.stream_id).map_err(to_error)
+    }
+}
+
+pub fn init(transport: SomeTransport<'static>) {
+    let mut sound = match Sound::new(transport) {
+        Ok(sound) => sound,
+        Err(e) => {
+            error!("Failed to init virtio-sound, {:?}", e);
+            return;
+        }
+    };
+    let outputs: Vec<u32> = match sound.output_streams() {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            error!("Failed to list the streams of virtio-sound, {:?}", e);
+            return;
+        }
+    };
+    debug!("virtio-sound has {} output streams", outputs.len());
+    let sound = Arc::new(SpinLock::new(sound));
+    for stream_id in outputs {
+        let result = VirtioPcm::new(&sound, stream_id)
+            .and_then(|ops| register_pcm(Direction::Playback, Box::new(ops)));
+        if let Err(e) = result {
+            warn!(
+                "Failed to register virtio-sound stream {}: {:?}",
+                stream_id, e
+            );
+        }
+    }
+}
@@ -12,20 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod sched;
+
 use crate::{
-    devices::{virtio::VirtioHal, Device, DeviceClass, DeviceId, DeviceManager},
+    devices::{
+        devno,
+        storage::{StorageHealth, STORAGE_HEALTH},
+        virtio::VirtioHal,
+        Device, DeviceClass, DeviceId, DeviceManager,
+    },
+    error::{code, Error},
     sync::SpinLock,
 };
-use alloc::{string::String, sync::Arc, vec};
-use core::cmp::min;
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    cmp::min,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use embedded_io::{Error as IOError, ErrorKind};
+use sched::{Callback, Op, Request, RequestDriver, RequestQueue};
 use virtio_drivers::{
     device::blk::{VirtIOBlk, SECTOR_SIZE},
     transport::SomeTransport,
     Hal,
 };
 
-pub const VIRTUAL_STORAGE_NAME: &str = "virt-storage";
+/// The first virtio disk, `/dev/vda`.
+pub const VIRTUAL_STORAGE_NAME: &str = "vda";
+// Virtio disks are named vda, vdb, ... in the order they're found.
+const VIRTIO_DISK_NAMES: usize = 26;
+static VIRTIO_DISKS: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
 pub enum BlockError<T> {
@@ -68,6 +84,11 @@ pub trait BlockDriverOps: Send + Sync + ErrorType {
     fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> Result<(), Self::Error>;
     /// Requests the device to flush any pending writes to storage.
     fn flush(&mut self) -> Result<(), Self::Error>;
+    /// Gets the erases of all the erase blocks and of the most worn one,
+    /// for flash media which keeps track of them.
+    fn erase_counts(&self) -> Option<(u64, u64)> {
+        None
+    }
 }
 
 impl<H: Hal> ErrorType for VirtIOBlk<H, SomeTransport<'static>> {
@@ -107,19 +128,33 @@ impl<H: Hal> BlockDriverOps for VirtIOBlk<H, SomeTransport<'static>> {
 
 pub fn init_virtio_block(
     driver: VirtIOBlk<VirtioHal, SomeTransport<'static>>,
-) -> Result<(), ErrorKind> {
-    let block = Block::new(VIRTUAL_STORAGE_NAME, Arc::new(SpinLock::new(driver)));
-    DeviceManager::get().register_device(String::from(VIRTUAL_STORAGE_NAME), Arc::new(block))
+) -> Result<(), Error> {
+    let index = VIRTIO_DISKS.fetch_add(1, Ordering::Relaxed);
+    if index >= VIRTIO_DISK_NAMES {
+        return Err(code::ENOMEM);
+    }
+    let name = format!("vd{}", (b'a' + index as u8) as char);
+    let major = devno::register_major(DeviceClass::Block, 0, "virtblk")?;
+    let id = devno::alloc_minor(DeviceClass::Block, major)?;
+    let block = Block::new(&name, id, Arc::new(SpinLock::new(driver)));
+    DeviceManager::get().register_device(name, Arc::new(block))
 }
 
 pub struct Block<E: embedded_io::Error, const SECTOR_SIZE: usize> {
     driver: Arc<SpinLock<dyn BlockDriverOps<Error = E>>>,
     name: String,
+    id: DeviceId,
     total_size: u64, // in bytes
+    queue: RequestQueue,
+    health: SpinLock<StorageHealth>,
 }
 
 impl<E: embedded_io::Error> Block<E, SECTOR_SIZE> {
-    pub fn new(name: &str, driver: Arc<SpinLock<dyn BlockDriverOps<Error = E>>>) -> Self {
+    pub fn new(
+        name: &str,
+        id: DeviceId,
+        driver: Arc<SpinLock<dyn BlockDriverOps<Error = E>>>,
+    ) -> Self {
         let total_size = {
             let capacity = driver.lock().capacity();
             capacity * SECTOR_SIZE as u64
@@ -127,8 +162,68 @@ impl<E: embedded_io::Error> Block<E, SECTOR_SIZE> {
         Block {
             driver,
             name: String::from(name),
+            id,
             total_size,
+            queue: RequestQueue::new(sched::by_name("deadline").unwrap()),
+            health: SpinLock::new(StorageHealth::default()),
+        }
+    }
+
+    /// Switches to the I/O scheduler called `name`, "noop" or "deadline".
+    pub fn set_scheduler(&self, name: &str) -> Result<(), Error> {
+        let sched = sched::by_name(name).ok_or(code::EINVAL)?;
+        self.queue.set_scheduler(sched);
+        Ok(())
+    }
+
+    pub fn scheduler(&self) -> &'static str {
+        self.queue.scheduler()
+    }
+
+    fn execute(&self, op: Op, sector: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let mut driver = self.driver.lock();
+        let result = match op {
+            Op::Read => driver.read_blocks(sector, buf),
+            Op::Write => driver.write_blocks(sector, buf),
         }
+        .map_err(|e| Error::from(IOError::kind(&e)));
+        let mut health = self.health.irqsave_lock();
+        match op {
+            Op::Read => health.account_read(buf.len(), result.is_ok()),
+            Op::Write => health.account_write(buf.len(), result.is_ok()),
+        }
+        result
+    }
+
+    /// Queues a read or write of whole sectors starting at `sector`,
+    /// without waiting for it. `callback` gets `data` back once it's done.
+    pub fn submit_async(
+        &self,
+        op: Op,
+        sector: usize,
+        data: Vec<u8>,
+        callback: Callback,
+    ) -> Result<(), Error> {
+        self.queue.submit_async(op, sector, data, callback, self)
+    }
+
+    fn read_sectors(&self, sector: usize, count: usize) -> Result<Vec<u8>, Error> {
+        self.queue
+            .submit(Op::Read, sector, vec![0u8; count * SECTOR_SIZE], self)
+    }
+
+    fn write_sectors(&self, sector: usize, data: Vec<u8>) -> Result<(), Error> {
+        self.queue.submit(Op::Write, sector, data, self).map(|_| ())
+    }
+}
+
+// The virtio driver waits for each request, so they're run one at a time
+// as they're started.
+impl<E: embedded_io::Error> RequestDriver for Block<E, SECTOR_SIZE> {
+    fn start(&self, mut req: Request) -> Result<(), Request> {
+        let result = self.execute(req.op(), req.sector(), req.buf_mut());
+        req.complete(result);
+        Ok(())
     }
 }
 
@@ -142,10 +237,10 @@ impl<E: embedded_io::Error> Device for Block<E, SECTOR_SIZE> {
     }
 
     fn id(&self) -> DeviceId {
-        todo!()
+        self.id
     }
 
-    fn read(&self, pos: u64, buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, ErrorKind> {
+    fn read(&self, pos: u64, buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
         // TODO: handle nonblocking read
         let max_read = min(buf.len() as u64, self.total_size.saturating_sub(pos)) as usize;
         if max_read == 0 {
@@ -155,17 +250,13 @@ impl<E: embedded_io::Error> Device for Block<E, SECTOR_SIZE> {
         let start_sector = (pos / SECTOR_SIZE as u64) as usize;
         let sector_offset = (pos % SECTOR_SIZE as u64) as usize;
         let sectors_coverred = (sector_offset + max_read).div_ceil(SECTOR_SIZE);
-        let mut sector_buf = vec![0u8; sectors_coverred * SECTOR_SIZE];
-        self.driver
-            .lock()
-            .read_blocks(start_sector, &mut sector_buf)
-            .map_err(|e| IOError::kind(&e))?;
+        let sector_buf = self.read_sectors(start_sector, sectors_coverred)?;
         // Copy to output buffer
         buf[..max_read].copy_from_slice(&sector_buf[sector_offset..sector_offset + max_read]);
         Ok(max_read)
     }
 
-    fn write(&self, pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, ErrorKind> {
+    fn write(&self, pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
         // TODO: handle nonblocking write
         let total_write_size = min(buf.len() as u64, self.total_size.saturating_sub(pos)) as usize;
         if total_write_size == 0 {
@@ -177,72 +268,70 @@ impl<E: embedded_io::Error> Device for Block<E, SECTOR_SIZE> {
 
         // 1. Write first sector
         let mut write_size = min(SECTOR_SIZE - sector_offset, total_write_size);
-        let mut sector_buf = [0u8; SECTOR_SIZE];
-        if sector_offset != 0 || write_size != SECTOR_SIZE {
+        let mut sector_buf = if sector_offset != 0 || write_size != SECTOR_SIZE {
             // If the content to be written cannot completely cover the sector, it needs to be read out first
-            self.driver
-                .lock()
-                .read_blocks(start_sector, &mut sector_buf)
-                .map_err(|e| IOError::kind(&e))?;
-        }
+            self.read_sectors(start_sector, 1)?
+        } else {
+            vec![0u8; SECTOR_SIZE]
+        };
         // Update the parts that need to be modified
         sector_buf[sector_offset..sector_offset + write_size].copy_from_slice(&data[..write_size]);
         // Write back to the modified sectors
-        self.driver
-            .lock()
-            .write_blocks(start_sector, &sector_buf)
-            .map_err(|e| IOError::kind(&e))?;
+        self.write_sectors(start_sector, sector_buf)?;
         data = &data[write_size..];
         start_sector += 1;
         // 2. Write continuous sectors
         let continuous_sectors = data.len() / SECTOR_SIZE;
         if continuous_sectors != 0 {
             write_size = SECTOR_SIZE * continuous_sectors;
-            let mut sector_buf = vec![0u8; write_size];
-            sector_buf[..write_size].copy_from_slice(&data[..write_size]);
             // Write back to the modified sectors
-            self.driver
-                .lock()
-                .write_blocks(start_sector, &sector_buf)
-                .map_err(|e| IOError::kind(&e))?;
+            self.write_sectors(start_sector, data[..write_size].to_vec())?;
             data = &data[write_size..];
             start_sector += continuous_sectors;
         }
         // 3. Write last sector
         write_size = data.len();
         if write_size > 0 {
-            let mut sector_buf = [0u8; SECTOR_SIZE];
-            self.driver
-                .lock()
-                .read_blocks(start_sector, &mut sector_buf)
-                .map_err(|e| IOError::kind(&e))?;
+            let mut sector_buf = self.read_sectors(start_sector, 1)?;
             // Update the parts that need to be modified
             sector_buf[..write_size].copy_from_slice(&data[..write_size]);
             // Write back to the modified sectors
-            self.driver
-                .lock()
-                .write_blocks(start_sector, &sector_buf)
-                .map_err(|e| IOError::kind(&e))?;
+            self.write_sectors(start_sector, sector_buf)?;
         }
         Ok(total_write_size)
     }
 
-    fn capacity(&self) -> Result<u64, ErrorKind> {
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if STORAGE_HEALTH.matches(request) {
+            return STORAGE_HEALTH.copy_out(arg, &self.health()?);
+        }
+        Err(code::ENOSYS)
+    }
+
+    fn capacity(&self) -> Result<u64, Error> {
         let driver = self.driver.lock();
         Ok(driver.capacity())
     }
 
-    fn sector_size(&self) -> Result<u16, ErrorKind> {
+    fn sector_size(&self) -> Result<u16, Error> {
         let driver = self.driver.lock();
         Ok(driver.sector_size())
     }
 
-    fn sync(&self) -> Result<(), ErrorKind> {
+    fn sync(&self) -> Result<(), Error> {
         let mut driver = self.driver.lock();
         match driver.flush() {
             Ok(_) => Ok(()),
-            Err(error) => Err(embedded_io::Error::kind(&error)),
+            Err(error) => Err(IOError::kind(&error).into()),
+        }
+    }
+
+    fn health(&self) -> Result<StorageHealth, Error> {
+        let mut health = *self.health.irqsave_lock();
+        if let Some((erases, max_block_erases)) = self.driver.lock().erase_counts() {
+            health.set_erase_counts(erases, max_block_erases);
         }
+        Ok(health)
     }
 }
 
@@ -0,0 +1,728 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block I/O scheduling.
+//!
+//! Reads and writes of whole sectors are submitted to a [`RequestQueue`],
+//! with a callback run once they're done. The queue's [`IoScheduler`]
+//! merges adjacent ones into larger requests and decides in which order
+//! they're started on the [`RequestDriver`]. There's no dispatch thread:
+//! requests are dispatched by whoever submits one, and by the driver when
+//! a request completes, usually from its interrupt handler, so that a
+//! device can have several requests in flight.
+
+use super::SECTOR_SIZE;
+use crate::{
+    error::{code, Error},
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    thread, time,
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Most sectors merged into one request.
+pub const MAX_REQUEST_SECTORS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+impl Op {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Called with the buffer submitted, filled for a read, once the I/O is
+/// done. May run in interrupt context.
+pub type Callback = Box<dyn FnOnce(Result<Vec<u8>, Error>) + Send>;
+
+// Lets a caller wait for its I/O.
+struct Completion {
+    done: AtomicUsize,
+    result: SpinLock<Result<Vec<u8>, Error>>,
+}
+
+impl Completion {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            done: AtomicUsize::new(0),
+            result: SpinLock::new(Err(code::EINTR)),
+        })
+    }
+
+    fn callback(self: &Arc<Self>) -> Callback {
+        let this = self.clone();
+        Box::new(move |result| {
+            *this.result.irqsave_lock() = result;
+            this.done.store(1, Ordering::Release);
+            let _ = atomic_wake(&this.done, usize::MAX);
+        })
+    }
+
+    fn wait(&self) -> Result<Vec<u8>, Error> {
+        while self.done.load(Ordering::Acquire) == 0 {
+            let _wait = thread::wait_on_device("block");
+            let _ = atomic_wait(&self.done, 0, None);
+        }
+        core::mem::replace(&mut *self.result.irqsave_lock(), Ok(Vec::new()))
+    }
+}
+
+// The I/O of one caller.
+struct Bio {
+    // Data to write, or buffer to read into.
+    data: Vec<u8>,
+    callback: Callback,
+}
+
+/// Contiguous sectors read or written by the driver in one go, on behalf
+/// of one or more callers.
+pub struct Request {
+    op: Op,
+    sector: usize,
+    sectors: usize,
+    // Tick at which the oldest I/O of the request was submitted.
+    submitted: usize,
+    bios: Vec<Bio>,
+    // Data of all the bios, once the request is dispatched.
+    buf: Vec<u8>,
+}
+
+impl Request {
+    fn new(op: Op, sector: usize, data: Vec<u8>, callback: Callback) -> Self {
+        Self {
+            op,
+            sector,
+            sectors: data.len() / SECTOR_SIZE,
+            submitted: time::get_sys_ticks(),
+            bios: vec![Bio { data, callback }],
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn op(&self) -> Op {
+        self.op
+    }
+
+    pub fn sector(&self) -> usize {
+        self.sector
+    }
+
+    pub fn sectors(&self) -> usize {
+        self.sectors
+    }
+
+    /// Sector following the last one of the request.
+    pub fn end(&self) -> usize {
+        self.sector + self.sectors
+    }
+
+    pub fn submitted(&self) -> usize {
+        self.submitted
+    }
+
+    /// Data to write, or buffer to read into.
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Merges `other` into the request if it's the same operation and
+    /// starts right after or ends right before it. Gives it back otherwise.
+    pub fn try_merge(&mut self, mut other: Request) -> Result<(), Request> {
+        if other.op != self.op || self.sectors + other.sectors > MAX_REQUEST_SECTORS {
+            return Err(other);
+        }
+        if other.sector == self.end() {
+            self.bios.append(&mut other.bios);
+        } else if other.end() == self.sector {
+            other.bios.append(&mut self.bios);
+            self.bios = other.bios;
+            self.sector = other.sector;
+        } else {
+            return Err(other);
+        }
+        self.sectors += other.sectors;
+        self.submitted = self.submitted.min(other.submitted);
+        Ok(())
+    }
+
+    // Gathers the data of the bios into one buffer for the driver.
+    fn prepare(&mut self) {
+        self.buf = if let [bio] = &mut self.bios[..] {
+            core::mem::take(&mut bio.data)
+        } else {
+            match self.op {
+                Op::Read => vec![0u8; self.sectors * SECTOR_SIZE],
+                Op::Write => self
+                    .bios
+                    .iter()
+                    .flat_map(|bio| bio.data.iter().copied())
+                    .collect(),
+            }
+        };
+    }
+
+    // Undoes `prepare`, for the request to go back to the scheduler.
+    fn unprepare(&mut self) {
+        let buf = core::mem::take(&mut self.buf);
+        if let [bio] = &mut self.bios[..] {
+            bio.data = buf;
+        }
+    }
+
+    /// Ends the request, running the callbacks of the I/Os merged in it.
+    pub fn complete(mut self, result: Result<(), Error>) {
+        if self.bios.len() == 1 {
+            let bio = self.bios.pop().unwrap();
+            (bio.callback)(result.map(|_| self.buf));
+            return;
+        }
+        let mut pos = 0;
+        for mut bio in self.bios {
+            let len = bio.data.len();
+            if self.op == Op::Read {
+                bio.data.copy_from_slice(&self.buf[pos..pos + len]);
+            }
+            pos += len;
+            (bio.callback)(result.map(|_| bio.data));
+        }
+    }
+}
+
+/// A device running the requests of a [`RequestQueue`].
+pub trait RequestDriver: Send + Sync {
+    /// Starts `req`, and calls [`Request::complete`] once it's done. Gives
+    /// the request back if the device can't take more for now, in which
+    /// case the driver must call [`RequestQueue::run`] once it can.
+    fn start(&self, req: Request) -> Result<(), Request>;
+}
+
+/// Decides how queued requests are merged and in which order they're
+/// dispatched.
+pub trait IoScheduler: Send {
+    fn name(&self) -> &'static str;
+    /// Queues `req`, merging it with a queued request if possible.
+    fn add(&mut self, req: Request);
+    /// Takes the next request to dispatch, `now` being the current tick.
+    fn dispatch(&mut self, now: usize) -> Option<Request>;
+    fn is_empty(&self) -> bool;
+}
+
+/// Dispatches requests in the order they come, only merging a request
+/// with the last one queued. Suits devices without seek costs.
+#[derive(Default)]
+pub struct Noop {
+    queue: VecDeque<Request>,
+}
+
+impl Noop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IoScheduler for Noop {
+    fn name(&self) -> &'static str {
+        "noop"
+    }
+
+    fn add(&mut self, req: Request) {
+        let req = match self.queue.back_mut() {
+            Some(last) => match last.try_merge(req) {
+                Ok(()) => return,
+                Err(req) => req,
+            },
+            None => req,
+        };
+        self.queue.push_back(req);
+    }
+
+    fn dispatch(&mut self, _now: usize) -> Option<Request> {
+        self.queue.pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+const READ_EXPIRE_MS: usize = 500;
+const WRITE_EXPIRE_MS: usize = 5000;
+// Requests dispatched in sector order before looking at deadlines again.
+const FIFO_BATCH: usize = 16;
+// Times reads are preferred over pending writes before writes get a turn.
+const WRITES_STARVED: usize = 2;
+
+/// Sorts requests by sector and dispatches them in one direction, like an
+/// elevator, which keeps the device's accesses sequential. Requests which
+/// have waited longer than their deadline go first, and reads are
+/// preferred over writes, since callers usually wait for them, but only a
+/// few times in a row.
+pub struct Deadline {
+    // Reads and writes, by start sector. The second part of the key keeps
+    // requests for the same sector in order.
+    queues: [BTreeMap<(usize, usize), Request>; 2],
+    seq: usize,
+    expire: [usize; 2],
+    // Sector following the last request dispatched.
+    head: usize,
+    // Direction of the current batch and requests left in it.
+    batch: Option<(Op, usize)>,
+    starved: usize,
+}
+
+impl Deadline {
+    pub fn new() -> Self {
+        Self {
+            queues: [BTreeMap::new(), BTreeMap::new()],
+            seq: 0,
+            expire: [
+                time::tick_from_millisecond(READ_EXPIRE_MS),
+                time::tick_from_millisecond(WRITE_EXPIRE_MS),
+            ],
+            head: 0,
+            batch: None,
+            starved: 0,
+        }
+    }
+
+    fn expired(&self, op: Op, now: usize) -> Option<(usize, usize)> {
+        let expire = self.expire[op.index()];
+        self.queues[op.index()]
+            .iter()
+            .min_by_key(|(_, req)| req.submitted)
+            .filter(|(_, req)| now.saturating_sub(req.submitted) >= expire)
+            .map(|(key, _)| *key)
+    }
+
+    fn next_in_order(&self, op: Op) -> Option<(usize, usize)> {
+        let queue = &self.queues[op.index()];
+        queue
+            .range((self.head, 0)..)
+            .next()
+            .or_else(|| queue.iter().next())
+            .map(|(key, _)| *key)
+    }
+
+    fn choose(&mut self, now: usize) -> Option<(Op, (usize, usize))> {
+        if let Some((op, left)) = self.batch {
+            if left > 0 {
+                // Keep going up while there's something ahead.
+                if let Some((key, _)) = self.queues[op.index()].range((self.head, 0)..).next() {
+                    return Some((op, *key));
+                }
+            }
+        }
+        let reads = !self.queues[Op::Read.index()].is_empty();
+        let writes = !self.queues[Op::Write.index()].is_empty();
+        let op = match (reads, writes) {
+            (false, false) => return None,
+            (true, true) if self.starved < WRITES_STARVED => {
+                self.starved += 1;
+                Op::Read
+            }
+            (true, false) => Op::Read,
+            _ => {
+                self.starved = 0;
+                Op::Write
+            }
+        };
+        self.batch = Some((op, FIFO_BATCH));
+        let key = self.expired(op, now).or_else(|| self.next_in_order(op))?;
+        Some((op, key))
+    }
+}
+
+impl Default for Deadline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoScheduler for Deadline {
+    fn name(&self) -> &'static str {
+        "deadline"
+    }
+
+    fn add(&mut self, mut req: Request) {
+        let queue = &mut self.queues[req.op.index()];
+        // Back merge, into the request ending where this one starts.
+        let prev = queue
+            .range(..(req.sector, 0))
+            .next_back()
+            .filter(|(_, prev)| prev.end() == req.sector)
+            .map(|(key, _)| *key);
+        if let Some(key) = prev {
+            let mut prev = queue.remove(&key).unwrap();
+            match prev.try_merge(req) {
+                Ok(()) => req = prev,
+                Err(unmerged) => {
+                    queue.insert(key, prev);
+                    req = unmerged;
+                }
+            }
+        }
+        // Front merge, with the request starting where this one ends.
+        let next = queue
+            .range((req.end(), 0)..)
+            .next()
+            .filter(|(_, next)| next.sector == req.end())
+            .map(|(key, _)| *key);
+        if let Some(key) = next {
+            let next = queue.remove(&key).unwrap();
+            if let Err(next) = req.try_merge(next) {
+                queue.insert(key, next);
+            }
+        }
+        self.seq += 1;
+        queue.insert((req.sector, self.seq), req);
+    }
+
+    fn dispatch(&mut self, now: usize) -> Option<Request> {
+        let (op, key) = self.choose(now)?;
+        let req = self.queues[op.index()].remove(&key)?;
+        if let Some((_, left)) = &mut self.batch {
+            *left -= 1;
+        }
+        self.head = req.end();
+        Some(req)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(|queue| queue.is_empty())
+    }
+}
+
+/// Returns the scheduler called `name`.
+pub fn by_name(name: &str) -> Option<Box<dyn IoScheduler>> {
+    match name {
+        "noop" => Some(Box::new(Noop::new())),
+        "deadline" => Some(Box::new(Deadline::new())),
+        _ => None,
+    }
+}
+
+pub struct RequestQueue {
+    sched: SpinLock<Box<dyn IoScheduler>>,
+    dispatching: AtomicBool,
+    // Set when there may be something new to dispatch.
+    kicked: AtomicBool,
+}
+
+impl RequestQueue {
+    pub fn new(sched: Box<dyn IoScheduler>) -> Self {
+        Self {
+            sched: SpinLock::new(sched),
+            dispatching: AtomicBool::new(false),
+            kicked: AtomicBool::new(false),
+        }
+    }
+
+    pub fn scheduler(&self) -> &'static str {
+        self.sched.irqsave_lock().name()
+    }
+
+    /// Replaces the scheduler, handing it the requests still queued.
+    pub fn set_scheduler(&self, mut sched: Box<dyn IoScheduler>) {
+        let mut current = self.sched.irqsave_lock();
+        while let Some(req) = current.dispatch(usize::MAX) {
+            sched.add(req);
+        }
+        *current = sched;
+    }
+
+    /// Queues a read or write of the sectors starting at `sector`, `data`
+    /// holding whole sectors, and dispatches what can be. `callback` runs
+    /// once it's done.
+    pub fn submit_async(
+        &self,
+        op: Op,
+        sector: usize,
+        data: Vec<u8>,
+        callback: Callback,
+        driver: &dyn RequestDriver,
+    ) -> Result<(), Error> {
+        if data.is_empty() || data.len() % SECTOR_SIZE != 0 {
+            return Err(code::EINVAL);
+        }
+        self.sched
+            .irqsave_lock()
+            .add(Request::new(op, sector, data, callback));
+        self.run(driver);
+        Ok(())
+    }
+
+    /// Like [`submit_async`](Self::submit_async), but waits for the I/O
+    /// and returns `data`, filled for a read.
+    pub fn submit(
+        &self,
+        op: Op,
+        sector: usize,
+        data: Vec<u8>,
+        driver: &dyn RequestDriver,
+    ) -> Result<Vec<u8>, Error> {
+        let completion = Completion::new();
+        self.submit_async(op, sector, data, completion.callback(), driver)?;
+        completion.wait()
+    }
+
+    /// Starts queued requests until the driver is busy or none is left.
+    pub fn run(&self, driver: &dyn RequestDriver) {
+        self.kicked.store(true, Ordering::Release);
+        // Whoever finds the queue being dispatched leaves it to the
+        // dispatcher, which looks again before stopping.
+        while self.kicked.load(Ordering::Acquire) && !self.dispatching.swap(true, Ordering::Acquire)
+        {
+            self.kicked.store(false, Ordering::Relaxed);
+            loop {
+                let next = self.sched.irqsave_lock().dispatch(time::get_sys_ticks());
+                let Some(mut req) = next else {
+                    break;
+                };
+                req.prepare();
+                if let Err(mut req) = driver.start(req) {
+                    // Back to the scheduler, where it may still be merged.
+                    req.unprepare();
+                    self.sched.irqsave_lock().add(req);
+                    break;
+                }
+            }
+            self.dispatching.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn request(op: Op, sector: usize, sectors: usize) -> Request {
+        Request::new(
+            op,
+            sector,
+            vec![0u8; sectors * SECTOR_SIZE],
+            Box::new(|_| {}),
+        )
+    }
+
+    fn order(sched: &mut dyn IoScheduler, now: usize) -> Vec<(Op, usize, usize)> {
+        let mut order = Vec::new();
+        while let Some(req) = sched.dispatch(now) {
+            order.push((req.op(), req.sector(), req.sectors()));
+        }
+        order
+    }
+
+    #[test]
+    fn test_request_merge() {
+        let mut req = request(Op::Write, 8, 2);
+        assert!(req.try_merge(request(Op::Write, 10, 1)).is_ok());
+        assert!(req.try_merge(request(Op::Write, 4, 4)).is_ok());
+        assert_eq!((req.sector(), req.sectors()), (4, 7));
+        assert!(req.try_merge(request(Op::Read, 11, 1)).is_err());
+        assert!(req.try_merge(request(Op::Write, 12, 1)).is_err());
+        assert!(req
+            .try_merge(request(Op::Write, 11, MAX_REQUEST_SECTORS))
+            .is_err());
+    }
+
+    #[test]
+    fn test_noop_order() {
+        let mut noop = Noop::new();
+        noop.add(request(Op::Read, 100, 1));
+        noop.add(request(Op::Read, 101, 1));
+        noop.add(request(Op::Write, 0, 1));
+        noop.add(request(Op::Read, 50, 1));
+        assert_eq!(
+            order(&mut noop, 0),
+            [(Op::Read, 100, 2), (Op::Write, 0, 1), (Op::Read, 50, 1)]
+        );
+    }
+
+    #[test]
+    fn test_deadline_elevator() {
+        let mut sched = Deadline::new();
+        for sector in [40, 10, 30, 11, 20] {
+            sched.add(request(Op::Read, sector, 1));
+        }
+        // Fills the gap between 11 and 20, merging both ways.
+        sched.add(request(Op::Read, 12, 8));
+        assert_eq!(
+            order(&mut sched, 0),
+            [(Op::Read, 10, 11), (Op::Read, 30, 1), (Op::Read, 40, 1)]
+        );
+
+        // The elevator goes on from where it stopped, then wraps around.
+        sched.add(request(Op::Read, 45, 1));
+        sched.add(request(Op::Read, 5, 1));
+        sched.add(request(Op::Read, 50, 1));
+        assert_eq!(
+            order(&mut sched, 0),
+            [(Op::Read, 45, 1), (Op::Read, 50, 1), (Op::Read, 5, 1)]
+        );
+    }
+
+    #[test]
+    fn test_deadline_starvation() {
+        let mut sched = Deadline::new();
+        sched.add(request(Op::Write, 0, 1));
+        for i in 0..FIFO_BATCH * (WRITES_STARVED + 1) {
+            sched.add(request(Op::Read, 100 + 2 * i, 1));
+        }
+        let order = order(&mut sched, 0);
+        let write = order.iter().position(|(op, ..)| *op == Op::Write).unwrap();
+        assert_eq!(write, FIFO_BATCH * WRITES_STARVED);
+
+        // An expired request jumps the queue.
+        let mut sched = Deadline::new();
+        let mut old = request(Op::Read, 500, 1);
+        old.submitted = 0;
+        sched.add(old);
+        let now = time::tick_from_millisecond(READ_EXPIRE_MS) + 1;
+        for sector in [10, 20] {
+            let mut req = request(Op::Read, sector, 1);
+            req.submitted = now;
+            sched.add(req);
+        }
+        assert_eq!(sched.dispatch(now).unwrap().sector(), 500);
+    }
+
+    // Keeps up to `depth` requests in flight, until they're completed by
+    // `finish`, as an interrupt handler would.
+    struct Disk {
+        data: SpinLock<Vec<u8>>,
+        depth: usize,
+        in_flight: SpinLock<Vec<Request>>,
+        started: AtomicUsize,
+    }
+
+    impl Disk {
+        fn new(sectors: usize, depth: usize) -> Self {
+            Self {
+                data: SpinLock::new(vec![0u8; sectors * SECTOR_SIZE]),
+                depth,
+                in_flight: SpinLock::new(Vec::new()),
+                started: AtomicUsize::new(0),
+            }
+        }
+
+        fn execute(&self, req: &mut Request) {
+            let range = req.sector() * SECTOR_SIZE..req.end() * SECTOR_SIZE;
+            let mut data = self.data.irqsave_lock();
+            match req.op() {
+                Op::Read => req.buf_mut().copy_from_slice(&data[range]),
+                Op::Write => data[range].copy_from_slice(req.buf()),
+            }
+        }
+
+        fn finish(&self, queue: &RequestQueue) {
+            let done = core::mem::take(&mut *self.in_flight.irqsave_lock());
+            for mut req in done {
+                self.execute(&mut req);
+                req.complete(Ok(()));
+            }
+            queue.run(self);
+        }
+    }
+
+    impl RequestDriver for Disk {
+        fn start(&self, mut req: Request) -> Result<(), Request> {
+            self.started.fetch_add(1, Ordering::Relaxed);
+            if self.depth == 0 {
+                self.execute(&mut req);
+                req.complete(Ok(()));
+                return Ok(());
+            }
+            let mut in_flight = self.in_flight.irqsave_lock();
+            if in_flight.len() == self.depth {
+                self.started.fetch_sub(1, Ordering::Relaxed);
+                return Err(req);
+            }
+            in_flight.push(req);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_request_queue_submit() {
+        let queue = RequestQueue::new(by_name("deadline").unwrap());
+        assert_eq!(queue.scheduler(), "deadline");
+        let disk = Disk::new(8, 0);
+        let data = vec![7u8; 2 * SECTOR_SIZE];
+        assert!(queue.submit(Op::Write, 3, data, &disk).is_ok());
+        let data = queue
+            .submit(Op::Read, 2, vec![0u8; 3 * SECTOR_SIZE], &disk)
+            .unwrap();
+        assert!(data[..SECTOR_SIZE].iter().all(|b| *b == 0));
+        assert!(data[SECTOR_SIZE..].iter().all(|b| *b == 7));
+        assert_eq!(
+            queue.submit(Op::Read, 0, vec![0u8; 10], &disk),
+            Err(code::EINVAL)
+        );
+
+        queue.set_scheduler(by_name("noop").unwrap());
+        assert_eq!(queue.scheduler(), "noop");
+    }
+
+    #[test]
+    fn test_request_queue_async() {
+        let queue = RequestQueue::new(by_name("deadline").unwrap());
+        let disk = Disk::new(16, 2);
+        let results = Arc::new(SpinLock::new(Vec::new()));
+        let submit = |op: Op, sector: usize, fill: u8| {
+            let results = results.clone();
+            let callback: Callback = Box::new(move |result| {
+                let data = result.unwrap();
+                results.irqsave_lock().push((sector, data[0]));
+            });
+            let data = vec![fill; SECTOR_SIZE];
+            assert!(queue
+                .submit_async(op, sector, data, callback, &disk)
+                .is_ok());
+        };
+        // Two requests in flight, the other ones wait in the scheduler,
+        // where adjacent writes are merged.
+        submit(Op::Write, 0, 1);
+        submit(Op::Write, 8, 2);
+        submit(Op::Write, 4, 3);
+        submit(Op::Write, 5, 4);
+        assert_eq!(disk.started.load(Ordering::Relaxed), 2);
+        assert!(results.irqsave_lock().is_empty());
+
+        disk.finish(&queue);
+        assert_eq!(*results.irqsave_lock(), [(0, 1), (8, 2)]);
+        assert_eq!(disk.started.load(Ordering::Relaxed), 3);
+        disk.finish(&queue);
+        assert_eq!(results.irqsave_lock()[2..], [(4, 3), (5, 4)]);
+
+        submit(Op::Read, 5, 0);
+        disk.finish(&queue);
+        assert_eq!(results.irqsave_lock()[4], (5, 4));
+        assert!(disk.in_flight.irqsave_lock().is_empty());
+    }
+}
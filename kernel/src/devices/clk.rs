@@ -0,0 +1,317 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Clock framework.
+//!
+//! Boards describe their clock tree by registering [`Clock`]s, each backed by
+//! a [`ClockOps`] implementation that drives the hardware. Peripheral drivers
+//! look their clocks up by name and enable them before touching registers,
+//! instead of assuming the boot code left them running.
+//!
+//! Enabling a clock enables its parents first, and every clock keeps an
+//! enable count so that clocks shared by several drivers are only gated
+//! once the last user disables them. Rates are not cached: [`Clock::rate`]
+//! walks up the tree, so a rate change is seen by every descendant.
+
+use crate::{
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use spin::RwLock as SpinRwLock;
+
+/// Hardware operations of a clock.
+///
+/// Operations run with the clock tree locked and interrupts disabled, so
+/// they must not sleep. Rates are in Hz.
+pub trait ClockOps: Send + Sync {
+    /// Ungates the clock. The parent is already running.
+    fn enable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Gates the clock.
+    fn disable(&self) {}
+
+    /// Returns the output rate given the rate of the parent.
+    fn recalc_rate(&self, parent_rate: u64) -> u64 {
+        parent_rate
+    }
+
+    /// Returns the closest rate to `rate` the clock is able to produce.
+    fn round_rate(&self, rate: u64, parent_rate: u64) -> Result<u64, Error> {
+        let _ = rate;
+        Ok(self.recalc_rate(parent_rate))
+    }
+
+    /// Programs the clock to run at `rate`, which has been rounded by
+    /// [`ClockOps::round_rate`].
+    fn set_rate(&self, rate: u64, parent_rate: u64) -> Result<(), Error> {
+        if rate == self.recalc_rate(parent_rate) {
+            Ok(())
+        } else {
+            Err(code::ENOTSUP)
+        }
+    }
+
+    /// Switches the clock's mux to `parent`.
+    fn set_parent(&self, parent: &Clock) -> Result<(), Error> {
+        let _ = parent;
+        Err(code::ENOTSUP)
+    }
+}
+
+struct ClockState {
+    parent: Option<Arc<Clock>>,
+    enable_count: usize,
+}
+
+pub struct Clock {
+    name: &'static str,
+    ops: Box<dyn ClockOps>,
+    state: SpinLock<ClockState>,
+}
+
+// Serializes every operation walking or changing the tree, so that a clock
+// and its parents are updated together.
+static TREE: SpinLock<()> = SpinLock::new(());
+static CLOCKS: SpinRwLock<BTreeMap<&'static str, Arc<Clock>>> = SpinRwLock::new(BTreeMap::new());
+
+impl Clock {
+    pub fn new(name: &'static str, parent: Option<Arc<Clock>>, ops: Box<dyn ClockOps>) -> Self {
+        Self {
+            name,
+            ops,
+            state: SpinLock::new(ClockState {
+                parent,
+                enable_count: 0,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn parent(&self) -> Option<Arc<Clock>> {
+        self.state.irqsave_lock().parent.clone()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.irqsave_lock().enable_count > 0
+    }
+
+    /// Enables the clock and all its parents.
+    pub fn enable(&self) -> Result<(), Error> {
+        let _tree = TREE.irqsave_lock();
+        self.enable_locked()
+    }
+
+    /// Drops one enable reference, gating the clock and releasing its parent
+    /// once no user is left. Unbalanced calls are ignored.
+    pub fn disable(&self) {
+        let _tree = TREE.irqsave_lock();
+        self.disable_locked();
+    }
+
+    fn enable_locked(&self) -> Result<(), Error> {
+        if self.state.irqsave_lock().enable_count == 0 {
+            let parent = self.parent();
+            if let Some(parent) = &parent {
+                parent.enable_locked()?;
+            }
+            if let Err(e) = self.ops.enable() {
+                if let Some(parent) = &parent {
+                    parent.disable_locked();
+                }
+                return Err(e);
+            }
+        }
+        self.state.irqsave_lock().enable_count += 1;
+        Ok(())
+    }
+
+    fn disable_locked(&self) {
+        let mut state = self.state.irqsave_lock();
+        match state.enable_count {
+            0 => (),
+            1 => {
+                state.enable_count = 0;
+                let parent = state.parent.clone();
+                drop(state);
+                self.ops.disable();
+                if let Some(parent) = parent {
+                    parent.disable_locked();
+                }
+            }
+            _ => state.enable_count -= 1,
+        }
+    }
+
+    fn parent_rate(&self) -> u64 {
+        self.parent().map_or(0, |parent| parent.rate_locked())
+    }
+
+    fn rate_locked(&self) -> u64 {
+        self.ops.recalc_rate(self.parent_rate())
+    }
+
+    /// Returns the current rate in Hz, or 0 for an orphan clock without a
+    /// rate of its own.
+    pub fn rate(&self) -> u64 {
+        let _tree = TREE.irqsave_lock();
+        self.rate_locked()
+    }
+
+    /// Returns the rate the clock would run at if asked for `rate`.
+    pub fn round_rate(&self, rate: u64) -> Result<u64, Error> {
+        let _tree = TREE.irqsave_lock();
+        self.ops.round_rate(rate, self.parent_rate())
+    }
+
+    /// Sets the rate to the closest one the clock supports. Parents are left
+    /// untouched.
+    pub fn set_rate(&self, rate: u64) -> Result<(), Error> {
+        let _tree = TREE.irqsave_lock();
+        let parent_rate = self.parent_rate();
+        let rate = self.ops.round_rate(rate, parent_rate)?;
+        self.ops.set_rate(rate, parent_rate)
+    }
+
+    /// Reparents the clock. Running clocks keep their parent, since
+    /// switching would glitch the consumers.
+    pub fn set_parent(&self, parent: Arc<Clock>) -> Result<(), Error> {
+        let _tree = TREE.irqsave_lock();
+        if self.state.irqsave_lock().enable_count > 0 {
+            return Err(code::EBUSY);
+        }
+        let mut ancestor = Some(parent.clone());
+        while let Some(clock) = ancestor {
+            if core::ptr::eq(Arc::as_ptr(&clock), self) {
+                return Err(code::EINVAL);
+            }
+            ancestor = clock.parent();
+        }
+        self.ops.set_parent(&parent)?;
+        self.state.irqsave_lock().parent = Some(parent);
+        Ok(())
+    }
+}
+
+/// Makes `clock` available to drivers under its name.
+pub fn register(clock: Arc<Clock>) -> Result<(), Error> {
+    let mut clocks = CLOCKS.write();
+    if clocks.contains_key(clock.name) {
+        return Err(code::EEXIST);
+    }
+    clocks.insert(clock.name, clock);
+    Ok(())
+}
+
+pub fn get(name: &str) -> Result<Arc<Clock>, Error> {
+    CLOCKS.read().get(name).cloned().ok_or(code::ENOENT)
+}
+
+/// A root clock, such as a crystal oscillator, running at a fixed rate.
+pub struct FixedRate(pub u64);
+
+impl ClockOps for FixedRate {
+    fn recalc_rate(&self, _parent_rate: u64) -> u64 {
+        self.0
+    }
+}
+
+/// A clock running at a fixed ratio of its parent.
+pub struct FixedFactor {
+    pub mult: u64,
+    pub div: u64,
+}
+
+impl ClockOps for FixedFactor {
+    fn recalc_rate(&self, parent_rate: u64) -> u64 {
+        parent_rate * self.mult / self.div
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Gate(&'static AtomicUsize);
+
+    impl ClockOps for Gate {
+        fn enable(&self) -> Result<(), Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn disable(&self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_clock_enable_parents() {
+        static ROOT_ON: AtomicUsize = AtomicUsize::new(0);
+        static LEAF_ON: AtomicUsize = AtomicUsize::new(0);
+        let root = Arc::new(Clock::new("test_root", None, Box::new(Gate(&ROOT_ON))));
+        let a = Clock::new("test_a", Some(root.clone()), Box::new(Gate(&LEAF_ON)));
+        let b = Clock::new("test_b", Some(root.clone()), Box::new(FixedRate(1)));
+
+        a.enable().unwrap();
+        a.enable().unwrap();
+        b.enable().unwrap();
+        assert_eq!(ROOT_ON.load(Ordering::Relaxed), 1);
+        assert_eq!(LEAF_ON.load(Ordering::Relaxed), 1);
+
+        a.disable();
+        assert_eq!(LEAF_ON.load(Ordering::Relaxed), 1);
+        a.disable();
+        assert_eq!(LEAF_ON.load(Ordering::Relaxed), 0);
+        assert!(root.is_enabled());
+        b.disable();
+        assert!(!root.is_enabled());
+        assert_eq!(ROOT_ON.load(Ordering::Relaxed), 0);
+        b.disable();
+        assert!(!root.is_enabled());
+    }
+
+    #[test]
+    fn test_clock_rates() {
+        let xtal = Arc::new(Clock::new(
+            "test_xtal",
+            None,
+            Box::new(FixedRate(12_000_000)),
+        ));
+        let pll = Arc::new(Clock::new(
+            "test_pll",
+            Some(xtal.clone()),
+            Box::new(FixedFactor { mult: 25, div: 2 }),
+        ));
+        let peri = Clock::new("test_peri", Some(xtal.clone()), Box::new(FixedRate(0)));
+        assert_eq!(pll.rate(), 150_000_000);
+        assert_eq!(pll.set_rate(150_000_000), Ok(()));
+        assert_eq!(pll.set_rate(100_000_000), Ok(()));
+        assert_eq!(pll.rate(), 150_000_000);
+        assert_eq!(peri.set_parent(pll.clone()), Err(code::ENOTSUP));
+        assert_eq!(xtal.set_parent(pll.clone()), Err(code::EINVAL));
+
+        register(pll.clone()).unwrap();
+        assert_eq!(register(pll), Err(code::EEXIST));
+        assert_eq!(get("test_pll").unwrap().rate(), 150_000_000);
+        assert!(get("test_nonexistent").is_err());
+    }
+}
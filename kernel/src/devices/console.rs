@@ -12,24 +12,572 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{dumb, tty::serial::UartOps, Device, DeviceManager};
-use crate::sync::SpinLock;
+//! The kernel console, where `kprintln!` and the logger write.
+//!
+//! Output goes to every selected sink. A sink is named like "ttyS0" and
+//! has a polled output, a console device, or both: the device is written
+//! to once it's up, the polled output before that and when panicking.
+//! Boards register their serial ports, [`register_builtin_sinks`] adds
+//! "semihosting" and "rtt". Which ones are used is picked by the CONSOLE
+//! kconfig, a comma separated list of names like `console=` on Linux, and
+//! can be changed at runtime with [`select`].
+//!
+//! `kprintln!` and the logger go through the kernel log: each sink keeps
+//! its place in it, and [`flush_log`] hands it the records it's missing.
+//! Console devices get them as slices of the log ring, without a copy, as
+//! long as they have room. Sinks registered late start from the oldest
+//! record kept.
+//!
+//! Interrupt handlers never write to the console device, which may wait
+//! for the UART or for a thread holding it. Their output is copied into a
+//! fixed set of slots without locking, and written out by the next thread
+//! printing something, or by the flusher tasklet. When all the slots are
+//! taken the output is dropped and counted, see [`lost_messages`].
+
+use super::{tty::serial::UartOps, Device, DeviceManager};
+use crate::{
+    asynk,
+    error::{code, Error},
+    irq, klog,
+    sync::{KOnce, SpinLock},
+};
 use alloc::{string::String, sync::Arc};
-use embedded_io::ErrorKind;
-use spin::Once;
+use core::{
+    cell::UnsafeCell,
+    future, str,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    task::{Poll, Waker},
+};
+
+/// Polled output which works without interrupts and without the TTY layer.
+/// Boards register one from their init code, as soon as the UART is
+/// programmed.
+pub trait EarlyConsole: Send + Sync {
+    fn write_str(&self, s: &str);
+}
+
+impl<T: UartOps + ?Sized> EarlyConsole for SpinLock<T> {
+    fn write_str(&self, s: &str) {
+        // The panicking context may be the one holding the UART, don't wait
+        // for it forever.
+        let uart = if is_panicking() {
+            self.try_irqsave_lock()
+        } else {
+            Some(self.irqsave_lock())
+        };
+        if let Some(mut uart) = uart {
+            let _ = uart.write_str(s);
+        }
+    }
+}
+
+/// Most sinks which can be registered.
+pub const MAX_SINKS: usize = 8;
+/// Longest selection, see [`select`].
+pub const SELECTION_SIZE: usize = 64;
+
+struct Sink {
+    // Set before the sink is counted in NR_SINKS.
+    name: KOnce<&'static str>,
+    early: KOnce<Arc<dyn EarlyConsole>>,
+    device: KOnce<Arc<dyn Device>>,
+    selected: AtomicBool,
+    // Only moved by the thread flushing the log, and by select.
+    log: SpinLock<klog::Cursor>,
+}
+
+impl Sink {
+    const fn new() -> Self {
+        Self {
+            name: KOnce::new(),
+            early: KOnce::new(),
+            device: KOnce::new(),
+            selected: AtomicBool::new(false),
+            log: SpinLock::new(klog::Cursor::new(0)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        self.name.get().copied().unwrap_or("")
+    }
+
+    fn is_selected(&self) -> bool {
+        self.selected.load(Ordering::Relaxed)
+    }
+}
+
+struct Selection {
+    buf: [u8; SELECTION_SIZE],
+    len: usize,
+}
+
+impl Selection {
+    const fn new(list: &str) -> Self {
+        assert!(list.len() <= SELECTION_SIZE);
+        let mut buf = [0; SELECTION_SIZE];
+        let mut i = 0;
+        while i < list.len() {
+            buf[i] = list.as_bytes()[i];
+            i += 1;
+        }
+        Self {
+            buf,
+            len: list.len(),
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        // Only ever copied from a str.
+        str::from_utf8(&self.buf[..self.len])
+            .unwrap_or("")
+            .split(',')
+            .any(|selected| selected.trim() == name)
+    }
+}
+
+static SINKS: [Sink; MAX_SINKS] = [const { Sink::new() }; MAX_SINKS];
+static NR_SINKS: AtomicUsize = AtomicUsize::new(0);
+// Also held while adding a sink, so that it gets selected or not.
+static SELECTION: SpinLock<Selection> = SpinLock::new(Selection::new(blueos_kconfig::CONSOLE));
+static CONSOLE: KOnce<Arc<dyn Device>> = KOnce::new();
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+fn sinks() -> impl Iterator<Item = &'static Sink> {
+    SINKS[..NR_SINKS.load(Ordering::Acquire)].iter()
+}
+
+// Finds the sink called `name`, adds it if there's none yet and there's
+// room left.
+fn sink(name: &'static str) -> Option<&'static Sink> {
+    let selection = SELECTION.irqsave_lock();
+    if let Some(sink) = sinks().find(|sink| sink.name() == name) {
+        return Some(sink);
+    }
+    let n = NR_SINKS.load(Ordering::Relaxed);
+    let sink = SINKS.get(n)?;
+    sink.name.call_once(|| name);
+    sink.selected
+        .store(selection.contains(name), Ordering::Relaxed);
+    NR_SINKS.store(n + 1, Ordering::Release);
+    Some(sink)
+}
+
+/// Registers the console device of the sink `name`, which is written to
+/// instead of its polled output from then on. The first device registered
+/// is also /dev/console.
+pub fn init_console(name: &'static str, device: Arc<dyn Device>) -> Result<(), Error> {
+    let sink = sink(name).ok_or(code::ENOSPC)?;
+    sink.device.call_once(|| device.clone());
+    let mut first = false;
+    CONSOLE.call_once(|| {
+        first = true;
+        device.clone()
+    });
+    if !first {
+        return Ok(());
+    }
+    DeviceManager::get().register_device(String::from("console"), device)
+}
 
-static CONSOLE: Once<Arc<dyn Device>> = Once::new();
+pub fn get_console() -> Option<&'static Arc<dyn Device>> {
+    CONSOLE.get()
+}
+
+/// Registers the polled output of the sink `name`. Only the first call
+/// for a sink has an effect, and none once [`MAX_SINKS`] sinks exist.
+pub fn register_early_console(name: &'static str, console: Arc<dyn EarlyConsole>) {
+    if let Some(sink) = sink(name) {
+        sink.early.call_once(|| console);
+    }
+}
+
+/// Registers the sinks which don't depend on the board: "semihosting",
+/// the debugger's console, and "rtt", a SEGGER RTT channel read by the
+/// debug probe. RTT is /dev/console if the board registered no console
+/// device.
+pub fn register_builtin_sinks() {
+    #[cfg(not(use_defmt))]
+    {
+        register_early_console("semihosting", Arc::new(Semihosting));
+        if let Err(e) = super::rtt::init() {
+            crate::kprintln!("Failed to set RTT up: {:?}", e);
+        }
+    }
+}
 
-pub fn init_console(device: Arc<dyn Device>) -> Result<(), ErrorKind> {
-    CONSOLE.call_once(|| device.clone());
-    DeviceManager::get().register_device(String::from("console"), device.clone())
+/// Picks the sinks the output goes to from now on, from a comma separated
+/// list of names. Sinks registered later are picked from it too. Fails
+/// with EINVAL if the list is longer than [`SELECTION_SIZE`].
+pub fn select(list: &str) -> Result<(), Error> {
+    if list.len() > SELECTION_SIZE {
+        return Err(code::EINVAL);
+    }
+    let mut selection = SELECTION.irqsave_lock();
+    selection.buf[..list.len()].copy_from_slice(list.as_bytes());
+    selection.len = list.len();
+    for sink in sinks() {
+        let selected = selection.contains(sink.name());
+        // Sinks picked again start from now, not from what they missed.
+        if selected && !sink.is_selected() {
+            *sink.log.irqsave_lock() = klog::Cursor::new(klog::next_seq());
+        }
+        sink.selected.store(selected, Ordering::Relaxed);
+    }
+    Ok(())
 }
 
-pub fn get_console() -> Arc<dyn Device> {
-    CONSOLE.get().unwrap().clone()
+/// Whether the sink `name` is registered and selected.
+pub fn is_selected(name: &str) -> bool {
+    sinks().any(|sink| sink.name() == name && sink.is_selected())
 }
 
-#[allow(unconditional_recursion)]
-pub fn get_early_uart() -> &'static SpinLock<dyn UartOps> {
-    get_early_uart()
+/// Routes all further console output to the early console.
+pub fn enter_panic_mode() {
+    PANICKING.store(true, Ordering::Release);
+}
+
+/// Routes console output back to the console device, once a panic didn't
+/// take the system down.
+pub fn leave_panic_mode() {
+    PANICKING.store(false, Ordering::Release);
+}
+
+pub fn is_panicking() -> bool {
+    PANICKING.load(Ordering::Acquire)
+}
+
+/// Writes to the selected sinks, through their console device if it's up
+/// and their polled output otherwise. In interrupt context, `s` is queued
+/// and written out later.
+pub fn write_str(s: &str) {
+    if is_panicking() {
+        write_str_early(s);
+        return;
+    }
+    if irq::is_in_irq() {
+        defer(s);
+        return;
+    }
+    flush_deferred();
+    write_str_now(s);
+}
+
+fn write_str_now(s: &str) {
+    if is_panicking() {
+        write_str_early(s);
+        return;
+    }
+    for sink in sinks().filter(|sink| sink.is_selected()) {
+        if let Some(device) = sink.device.get() {
+            let _ = device.write(0, s.as_bytes(), true);
+        } else if let Some(early) = sink.early.get() {
+            early.write_str(s);
+        }
+    }
+}
+
+/// Writes to the polled output of the selected sinks.
+pub fn write_str_early(s: &str) {
+    for sink in sinks().filter(|sink| sink.is_selected()) {
+        if let Some(early) = sink.early.get() {
+            early.write_str(s);
+        }
+    }
+}
+
+// Size of the pieces of the log copied out for polled outputs, and for
+// console devices without room left.
+const CHUNK_SIZE: usize = 128;
+
+static FLUSHING_LOG: AtomicBool = AtomicBool::new(false);
+
+/// Writes out the kernel log records the selected sinks haven't got yet.
+/// From interrupt context, this is left to the flusher tasklet, and to the
+/// thread at it if there's one.
+pub fn flush_log() {
+    if irq::is_in_irq() {
+        wake_flusher();
+        return;
+    }
+    flush_deferred();
+    loop {
+        if FLUSHING_LOG.swap(true, Ordering::Acquire) {
+            return;
+        }
+        for sink in sinks().filter(|sink| sink.is_selected()) {
+            flush_log_to(sink);
+        }
+        FLUSHING_LOG.store(false, Ordering::Release);
+        // Records logged by a thread which found us at it, once we were past
+        // them, would be left behind.
+        let next_seq = klog::next_seq();
+        if !sinks().any(|sink| sink.is_selected() && sink.log.irqsave_lock().seq < next_seq) {
+            return;
+        }
+    }
+}
+
+fn flush_log_to(sink: &Sink) {
+    let mut cursor = *sink.log.irqsave_lock();
+    let device = sink.device.get();
+    loop {
+        if let Some(device) = device {
+            let (dropped, done) = klog::write_records(&mut cursor, |bufs| {
+                device.write_vectored(0, bufs, true).unwrap_or(0)
+            });
+            LOST.fetch_add(dropped as usize, Ordering::Relaxed);
+            if done {
+                break;
+            }
+        }
+        // The device is full, or there is only the polled output: a piece
+        // of the log is copied out and written, however long it takes.
+        let mut buf = [0; CHUNK_SIZE];
+        let (dropped, len) = take_chunk(&mut cursor, &mut buf);
+        LOST.fetch_add(dropped as usize, Ordering::Relaxed);
+        if len == 0 {
+            break;
+        }
+        if let Some(device) = device {
+            let _ = device.write(0, &buf[..len], false);
+        } else if let Some(early) = sink.early.get() {
+            for chunk in buf[..len].utf8_chunks() {
+                early.write_str(chunk.valid());
+                if !chunk.invalid().is_empty() {
+                    early.write_str(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4]));
+                }
+            }
+        }
+    }
+    *sink.log.irqsave_lock() = cursor;
+}
+
+// Copies the next bytes of the log into `buf`, not cutting a character in
+// two when it can help it. Returns how many records were dropped before
+// the cursor got to them, and the bytes copied.
+fn take_chunk(cursor: &mut klog::Cursor, buf: &mut [u8]) -> (u64, usize) {
+    let mut len = 0;
+    let (dropped, _) = klog::write_records(cursor, |bufs| {
+        let start = len;
+        for src in bufs {
+            let n = src.len().min(buf.len() - len);
+            buf[len..len + n].copy_from_slice(&src[..n]);
+            len += n;
+        }
+        if let Err(e) = str::from_utf8(&buf[start..len]) {
+            if e.error_len().is_none() && e.valid_up_to() > 0 {
+                len = start + e.valid_up_to();
+            }
+        }
+        len - start
+    });
+    (dropped, len)
+}
+
+#[cfg(not(use_defmt))]
+struct Semihosting;
+
+#[cfg(not(use_defmt))]
+impl EarlyConsole for Semihosting {
+    fn write_str(&self, s: &str) {
+        semihosting::print!("{}", s);
+    }
+}
+
+const DEFERRED_SLOTS: usize = 32;
+// Longer output takes several slots.
+const SLOT_SIZE: usize = 128;
+
+const FREE: u8 = 0;
+const WRITING: u8 = 1;
+const QUEUED: u8 = 2;
+
+struct Slot {
+    state: AtomicU8,
+    // Order of the output, slots are taken in any order.
+    seq: AtomicUsize,
+    len: AtomicUsize,
+    buf: UnsafeCell<[u8; SLOT_SIZE]>,
+}
+
+// SAFETY: `buf` is only accessed by whoever moved `state` out of FREE.
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(FREE),
+            seq: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            buf: UnsafeCell::new([0; SLOT_SIZE]),
+        }
+    }
+}
+
+static SLOTS: [Slot; DEFERRED_SLOTS] = [const { Slot::new() }; DEFERRED_SLOTS];
+static NEXT_SEQ: AtomicUsize = AtomicUsize::new(0);
+static LOST: AtomicUsize = AtomicUsize::new(0);
+// Lost messages already reported on the console.
+static REPORTED: AtomicUsize = AtomicUsize::new(0);
+static FLUSHING: AtomicBool = AtomicBool::new(false);
+static FLUSHER: SpinLock<Option<Waker>> = SpinLock::new(None);
+
+/// Number of writes from interrupt context and of log records dropped
+/// since boot, because the console couldn't keep up.
+pub fn lost_messages() -> usize {
+    LOST.load(Ordering::Relaxed) + klog::lost_records()
+}
+
+fn wake_flusher() {
+    if let Some(waker) = FLUSHER.irqsave_lock().as_ref() {
+        waker.wake_by_ref();
+    }
+}
+
+// Queues `s` without blocking, a nested handler may queue at the same
+// time.
+fn defer(mut s: &str) {
+    while !s.is_empty() {
+        let mut len = s.len().min(SLOT_SIZE);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        let (chunk, rest) = s.split_at(len);
+        let Some(slot) = SLOTS.iter().find(|slot| {
+            slot.state
+                .compare_exchange(FREE, WRITING, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }) else {
+            LOST.fetch_add(1, Ordering::Relaxed);
+            break;
+        };
+        // SAFETY: The slot is ours until it's queued.
+        unsafe { (*slot.buf.get())[..len].copy_from_slice(chunk.as_bytes()) };
+        slot.len.store(len, Ordering::Relaxed);
+        slot.seq
+            .store(NEXT_SEQ.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        slot.state.store(QUEUED, Ordering::Release);
+        s = rest;
+    }
+    wake_flusher();
+}
+
+/// Writes out the output queued from interrupt context. Does nothing if
+/// another thread is at it.
+pub fn flush_deferred() {
+    if FLUSHING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    // Oldest first. Output queued meanwhile is picked up too.
+    while let Some(slot) = SLOTS
+        .iter()
+        .filter(|slot| slot.state.load(Ordering::Acquire) == QUEUED)
+        .min_by_key(|slot| slot.seq.load(Ordering::Relaxed))
+    {
+        let mut buf = [0; SLOT_SIZE];
+        let len = slot.len.load(Ordering::Relaxed);
+        // SAFETY: The slot is queued, nobody writes to it until it's freed.
+        buf[..len].copy_from_slice(unsafe { &(*slot.buf.get())[..len] });
+        // Freed before the console is waited for.
+        slot.state.store(FREE, Ordering::Release);
+        // Chunks are cut at character boundaries.
+        if let Ok(s) = str::from_utf8(&buf[..len]) {
+            write_str_now(s);
+        }
+    }
+    let lost = lost_messages();
+    let reported = REPORTED.swap(lost, Ordering::Relaxed);
+    if lost != reported {
+        let _ = core::fmt::Write::write_fmt(
+            &mut crate::console::Console,
+            format_args!("[{} console messages lost]\n", lost - reported),
+        );
+    }
+    FLUSHING.store(false, Ordering::Release);
+}
+
+/// Spawns the tasklet writing out the output of interrupt handlers when no
+/// thread prints anything after them, and the log records they add.
+pub fn spawn_flusher() {
+    asynk::spawn(future::poll_fn(|ctx| {
+        FLUSHER
+            .irqsave_lock()
+            .get_or_insert_with(|| ctx.waker().clone());
+        flush_log();
+        Poll::Pending
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kprintln, scheduler, time::timer::Timer};
+    use alloc::{boxed::Box, format};
+    use blueos_test_macro::test;
+
+    static TICKS: AtomicUsize = AtomicUsize::new(0);
+    static IN_IRQ: AtomicBool = AtomicBool::new(true);
+
+    #[test]
+    fn test_print_from_timer_irq() {
+        let lost = lost_messages();
+        let timer = Timer::new_hard_periodic(
+            1,
+            Box::new(|| {
+                if !irq::is_in_irq() {
+                    IN_IRQ.store(false, Ordering::Relaxed);
+                }
+                kprintln!("printed from the timer");
+                // More than the slots hold.
+                for _ in 0..2 * DEFERRED_SLOTS {
+                    write_str("written from the timer\n");
+                }
+                log::warn!("logged from the timer");
+                TICKS.fetch_add(1, Ordering::Release);
+            }),
+        );
+        timer.start();
+        while TICKS.load(Ordering::Acquire) < 4 {
+            kprintln!("printed from the thread");
+            log::warn!("logged from the thread");
+            scheduler::yield_me();
+        }
+        timer.stop();
+        assert!(IN_IRQ.load(Ordering::Relaxed));
+        assert!(lost_messages() > lost);
+        while SLOTS
+            .iter()
+            .any(|slot| slot.state.load(Ordering::Acquire) != FREE)
+        {
+            flush_deferred();
+            scheduler::yield_me();
+        }
+    }
+
+    struct Capture(SpinLock<String>);
+
+    impl EarlyConsole for Capture {
+        fn write_str(&self, s: &str) {
+            self.0.irqsave_lock().push_str(s);
+        }
+    }
+
+    #[test]
+    fn test_select_sinks() {
+        let capture = Arc::new(Capture(SpinLock::new(String::new())));
+        register_early_console("test", capture.clone());
+        assert!(!is_selected("test"));
+        select(&format!("{},test", blueos_kconfig::CONSOLE)).unwrap();
+        assert!(is_selected("test"));
+        kprintln!("to the test sink");
+        // Another thread may be the one writing the log out.
+        while !capture.0.irqsave_lock().contains("to the test sink\n") {
+            scheduler::yield_me();
+        }
+        select(blueos_kconfig::CONSOLE).unwrap();
+        assert!(!is_selected("test"));
+        kprintln!("not to the test sink");
+        assert!(!capture.0.irqsave_lock().contains("not to"));
+        assert_eq!(select(&"x".repeat(SELECTION_SIZE + 1)), Err(code::EINVAL));
+    }
 }
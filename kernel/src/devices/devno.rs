@@ -0,0 +1,239 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Major and minor numbers of the devices.
+//!
+//! Majors belong to drivers: the well-known ones below are reserved from
+//! the start, others are handed out by [`register_major`]. A device's
+//! number is claimed when it's registered with the
+//! [`DeviceManager`](super::DeviceManager), which fails if another device
+//! already has it. Drivers numbering their devices as they find them get
+//! free minors from [`alloc_minor`].
+
+use super::{DeviceClass, DeviceId};
+use crate::{
+    error::{code, Error},
+    sync::{Lazy, SpinLock},
+};
+use alloc::{collections::BTreeMap, string::String};
+use log::warn;
+
+/// null and zero.
+pub const MEM_MAJOR: usize = 1;
+/// Serial ports, from minor [`SERIAL_MINOR_BASE`].
+pub const TTY_MAJOR: usize = 4;
+pub const SERIAL_MINOR_BASE: usize = 64;
+/// The terminal and the console.
+pub const TTYAUX_MAJOR: usize = 5;
+pub const LED_MAJOR: usize = 6;
+
+// Dynamic majors are handed out downwards, as Linux does.
+const DYNAMIC_MAJORS: core::ops::RangeInclusive<usize> = 234..=254;
+
+const WELL_KNOWN: &[(DeviceClass, usize, &str)] = &[
+    (DeviceClass::Char, MEM_MAJOR, "mem"),
+    (DeviceClass::Char, TTY_MAJOR, "ttyS"),
+    (DeviceClass::Char, TTYAUX_MAJOR, "tty"),
+    (DeviceClass::Char, LED_MAJOR, "led"),
+];
+
+#[derive(Debug)]
+enum Minor {
+    // Handed out by alloc_minor, the device isn't registered yet.
+    Reserved,
+    Claimed(String),
+}
+
+#[derive(Debug)]
+struct Major {
+    driver: String,
+    minors: BTreeMap<usize, Minor>,
+}
+
+type Registry = BTreeMap<(DeviceClass, usize), Major>;
+
+static REGISTRY: Lazy<SpinLock<Registry>> = Lazy::new(|| {
+    let mut registry = Registry::new();
+    for (class, major, driver) in WELL_KNOWN {
+        registry.insert(
+            (*class, *major),
+            Major {
+                driver: String::from(*driver),
+                minors: BTreeMap::new(),
+            },
+        );
+    }
+    SpinLock::new(registry)
+});
+
+/// Registers `driver` as the owner of `major`, or of a free major if
+/// `major` is 0. Returns the major. Registering the same driver again is
+/// fine, which lets drivers with several instances call this from each.
+pub fn register_major(class: DeviceClass, major: usize, driver: &str) -> Result<usize, Error> {
+    let mut registry = REGISTRY.irqsave_lock();
+    let major = if major == 0 {
+        if let Some(((_, major), _)) = registry.iter().find(|((c, m), owner)| {
+            *c == class && DYNAMIC_MAJORS.contains(m) && owner.driver == driver
+        }) {
+            return Ok(*major);
+        }
+        DYNAMIC_MAJORS
+            .rev()
+            .find(|m| !registry.contains_key(&(class, *m)))
+            .ok_or(code::ENOMEM)?
+    } else {
+        major
+    };
+    match registry.get(&(class, major)) {
+        Some(owner) if owner.driver == driver => {}
+        Some(owner) => {
+            warn!(
+                "{} can't have major {} of {:?} devices, {} has it",
+                driver, major, class, owner.driver
+            );
+            return Err(code::EEXIST);
+        }
+        None => {
+            registry.insert(
+                (class, major),
+                Major {
+                    driver: String::from(driver),
+                    minors: BTreeMap::new(),
+                },
+            );
+        }
+    }
+    Ok(major)
+}
+
+/// Reserves the lowest free minor of `major`, which must be registered.
+pub fn alloc_minor(class: DeviceClass, major: usize) -> Result<DeviceId, Error> {
+    let mut registry = REGISTRY.irqsave_lock();
+    let owner = registry.get_mut(&(class, major)).ok_or(code::ENOENT)?;
+    let minor = (0..)
+        .zip(owner.minors.keys())
+        .find(|(i, m)| i != *m)
+        .map_or(owner.minors.len(), |(i, _)| i);
+    owner.minors.insert(minor, Minor::Reserved);
+    Ok(DeviceId::new(major, minor))
+}
+
+/// Claims `id` for the device registered as `name`.
+pub(super) fn claim(class: DeviceClass, id: DeviceId, name: &str) -> Result<(), Error> {
+    let mut registry = REGISTRY.irqsave_lock();
+    let owner = registry
+        .entry((class, id.major()))
+        .or_insert_with(|| Major {
+            // Nobody registered the major, the device is taken as its owner.
+            driver: String::from(name),
+            minors: BTreeMap::new(),
+        });
+    match owner.minors.get(&id.minor()) {
+        Some(Minor::Claimed(other)) => {
+            warn!(
+                "{} can't be registered as {:?} device {}:{}, {} is",
+                name,
+                class,
+                id.major(),
+                id.minor(),
+                other
+            );
+            Err(code::EEXIST)
+        }
+        _ => {
+            owner
+                .minors
+                .insert(id.minor(), Minor::Claimed(String::from(name)));
+            Ok(())
+        }
+    }
+}
+
+/// Frees `id` if it's claimed by the device registered as `name`.
+pub(super) fn release(class: DeviceClass, id: DeviceId, name: &str) {
+    let mut registry = REGISTRY.irqsave_lock();
+    let Some(owner) = registry.get_mut(&(class, id.major())) else {
+        return;
+    };
+    if matches!(owner.minors.get(&id.minor()), Some(Minor::Claimed(other)) if other == name) {
+        owner.minors.remove(&id.minor());
+    }
+}
+
+/// Returns the driver owning `major`.
+pub fn major_owner(class: DeviceClass, major: usize) -> Option<String> {
+    REGISTRY
+        .irqsave_lock()
+        .get(&(class, major))
+        .map(|owner| owner.driver.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_devno_majors() {
+        assert_eq!(
+            register_major(DeviceClass::Char, MEM_MAJOR, "mem"),
+            Ok(MEM_MAJOR)
+        );
+        assert_eq!(
+            register_major(DeviceClass::Char, MEM_MAJOR, "devno_test"),
+            Err(code::EEXIST)
+        );
+        // Char and block majors are separate.
+        assert_eq!(
+            register_major(DeviceClass::Block, MEM_MAJOR, "devno_test"),
+            Ok(MEM_MAJOR)
+        );
+
+        let major = register_major(DeviceClass::Char, 0, "devno_test").unwrap();
+        assert!(DYNAMIC_MAJORS.contains(&major));
+        assert_eq!(
+            register_major(DeviceClass::Char, 0, "devno_test"),
+            Ok(major)
+        );
+        assert_eq!(
+            major_owner(DeviceClass::Char, major).as_deref(),
+            Some("devno_test")
+        );
+    }
+
+    #[test]
+    fn test_devno_minors() {
+        let major = register_major(DeviceClass::Misc, 0, "devno_minors").unwrap();
+        assert_eq!(
+            alloc_minor(DeviceClass::Misc, major),
+            Ok(DeviceId::new(major, 0))
+        );
+        claim(DeviceClass::Misc, DeviceId::new(major, 0), "a").unwrap();
+        claim(DeviceClass::Misc, DeviceId::new(major, 2), "c").unwrap();
+        assert_eq!(
+            claim(DeviceClass::Misc, DeviceId::new(major, 2), "d"),
+            Err(code::EEXIST)
+        );
+        // Allocation fills the gaps.
+        assert_eq!(
+            alloc_minor(DeviceClass::Misc, major),
+            Ok(DeviceId::new(major, 1))
+        );
+        assert_eq!(
+            alloc_minor(DeviceClass::Misc, major),
+            Ok(DeviceId::new(major, 3))
+        );
+        assert_eq!(alloc_minor(DeviceClass::Misc, 0), Err(code::ENOENT));
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Drivers initialized at boot without being listed anywhere.
+//!
+//! [`driver_init!`](crate::driver_init) puts a [`DriverInit`] in the
+//! `.bk_driver_array` section, which the linker script collects like the
+//! apps of `.bk_app_array`. [`init_drivers`] runs them all, by level, before
+//! the network stack and /dev are set up.
+
+use crate::error::Error;
+use alloc::vec::Vec;
+use core::ptr::addr_of;
+
+/// Order in which drivers are initialized. Drivers of the same level are
+/// initialized in link order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InitLevel {
+    /// Buses and controllers which other drivers use.
+    Bus,
+    Device,
+    /// Drivers built on the devices of other drivers.
+    Late,
+}
+
+pub struct DriverInit {
+    pub name: &'static str,
+    pub level: InitLevel,
+    pub init: fn() -> Result<(), Error>,
+}
+
+/// Registers a driver to be initialized at boot, like
+/// `driver_init!("null", InitLevel::Device, init)`.
+#[macro_export]
+macro_rules! driver_init {
+    ($name:expr, $level:expr, $init:expr $(,)?) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".bk_driver_array"]
+            static DRIVER: $crate::devices::driver::DriverInit =
+                $crate::devices::driver::DriverInit {
+                    name: $name,
+                    level: $level,
+                    init: $init,
+                };
+        };
+    };
+}
+
+extern "C" {
+    static __bk_driver_array_start: DriverInit;
+    static __bk_driver_array_end: DriverInit;
+}
+
+/// The drivers registered with [`driver_init!`](crate::driver_init).
+pub fn drivers() -> &'static [DriverInit] {
+    unsafe {
+        let start = addr_of!(__bk_driver_array_start);
+        let end = addr_of!(__bk_driver_array_end);
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Initializes the registered drivers. A driver which fails is reported
+/// and skipped.
+pub(crate) fn init_drivers() {
+    let mut drivers: Vec<&DriverInit> = drivers().iter().collect();
+    // Stable, so that link order is kept within a level.
+    drivers.sort_by_key(|driver| driver.level);
+    for driver in drivers {
+        match (driver.init)() {
+            Ok(()) => log::debug!("Driver {} initialized", driver.name),
+            Err(e) => log::warn!("Driver {} failed to initialize: {}", driver.name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_drivers() {
+        let names: Vec<&str> = drivers().iter().map(|driver| driver.name).collect();
+        assert!(names.contains(&"null"));
+        assert!(names.contains(&"zero"));
+        assert!(names.contains(&"full"));
+    }
+}
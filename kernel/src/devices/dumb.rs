@@ -20,11 +20,10 @@ use crate::{
         },
         Device,
     },
-    sync::SpinLock,
+    sync::{KOnce, SpinLock},
 };
 use alloc::sync::Arc;
 use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
-use spin::Once;
 pub(crate) struct DumbUart;
 
 pub(crate) static DUMB_UART0: SpinLock<DumbUart> = SpinLock::new(DumbUart);
@@ -102,7 +101,7 @@ pub(crate) fn get_early_uart<'a>() -> &'a SpinLock<dyn UartOps> {
     &DUMB_UART0
 }
 
-static DUMB_SERIAL0: Once<Arc<dyn Device>> = Once::new();
+static DUMB_SERIAL0: KOnce<Arc<dyn Device>> = KOnce::new();
 
 pub(crate) fn get_serial0() -> &'static Arc<dyn Device> {
     DUMB_SERIAL0.call_once(|| {
@@ -0,0 +1,608 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text console on a framebuffer.
+//!
+//! The screen is a grid of cells of the console font, drawn twice as high
+//! as it's defined so that it stays readable. The last
+//! [`SCROLLBACK_LINES`] lines scrolled off the top are kept, and can be
+//! looked at again with [`FBCON_SCROLL`]. Writing brings the view back to
+//! the bottom.
+//!
+//! Besides the usual control characters, a subset of the ANSI escape
+//! sequences is understood: cursor moves (CUU, CUD, CUF, CUB, CHA, CUP),
+//! erasing in the display and in the line (ED, EL), and SGR with bold,
+//! reverse, and the 8 colors and their bright variants for the text and
+//! the background. Other sequences are swallowed.
+//!
+//! The console is the console sink "tty0", a char device of that name and
+//! a virtual terminal. Nothing's behind it to type on, reads find nothing.
+//! It only draws while in the foreground, and draws the whole screen again
+//! when switched back to, so that programs may use the framebuffer in the
+//! meantime.
+
+use super::{
+    font::{glyph, FONT_HEIGHT, FONT_WIDTH},
+    Framebuffer, BYTES_PER_PIXEL,
+};
+use crate::{
+    asynk,
+    devices::{
+        console, devno,
+        tty::vt::{self, Vt, VT_ACTIVATE, VT_GETACTIVE},
+        Device, DeviceBase, DeviceClass, DeviceId, DeviceManager,
+    },
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    future,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Poll, Waker},
+};
+
+crate::ioctl_write!(
+    /// Scrolls the view back by the number of lines passed, forward if it's
+    /// negative.
+    pub FBCON_SCROLL, b'F', 0x10, i32
+);
+
+/// Lines kept once scrolled off the screen.
+pub const SCROLLBACK_LINES: usize = 200;
+
+const SINK: &str = "tty0";
+const SCALE: usize = 2;
+const CELL_WIDTH: usize = FONT_WIDTH;
+const CELL_HEIGHT: usize = FONT_HEIGHT * SCALE;
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMS: usize = 8;
+
+const ESC: u8 = 0x1b;
+const DEFAULT_FG: u8 = 7;
+const DEFAULT_BG: u8 = 0;
+const BRIGHT: u8 = 8;
+
+// The VGA palette, as XRGB8888.
+const PALETTE: [u32; 16] = [
+    0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa, 0x555555,
+    0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    ch: u8,
+    // Indexes in the palette.
+    fg: u8,
+    bg: u8,
+}
+
+impl Cell {
+    const fn blank(bg: u8) -> Self {
+        Self {
+            ch: b' ',
+            fg: DEFAULT_FG,
+            bg,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Normal,
+    Escape,
+    Csi,
+}
+
+// The text of the console. Drawing it is left to the caller, which is
+// told the rows which changed and how many lines scrolled since it last
+// drew.
+struct Screen {
+    cols: usize,
+    rows: usize,
+    // The scrollback, then the rows on screen.
+    lines: VecDeque<Vec<Cell>>,
+    // May be one past the last column, the line wraps at the next
+    // character then.
+    x: usize,
+    y: usize,
+    fg: u8,
+    bg: u8,
+    bold: bool,
+    reverse: bool,
+    state: State,
+    params: [u16; MAX_PARAMS],
+    nparams: usize,
+    // Lines the view is scrolled back by.
+    view: usize,
+    dirty: Vec<bool>,
+    scrolled: usize,
+}
+
+impl Screen {
+    fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            lines: (0..rows)
+                .map(|_| vec![Cell::blank(DEFAULT_BG); cols])
+                .collect(),
+            x: 0,
+            y: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            reverse: false,
+            state: State::Normal,
+            params: [0; MAX_PARAMS],
+            nparams: 0,
+            view: 0,
+            dirty: vec![true; rows],
+            scrolled: 0,
+        }
+    }
+
+    // The row `y` of the screen, as it is at the bottom.
+    fn row_mut(&mut self, y: usize) -> &mut Vec<Cell> {
+        let first = self.lines.len() - self.rows;
+        self.dirty[y] = true;
+        &mut self.lines[first + y]
+    }
+
+    // The row `y` of the view.
+    fn view_row(&self, y: usize) -> &[Cell] {
+        &self.lines[self.lines.len() - self.rows - self.view + y]
+    }
+
+    fn cursor(&self) -> Option<(usize, usize)> {
+        (self.view == 0).then_some((self.x.min(self.cols - 1), self.y))
+    }
+
+    fn scroll_view(&mut self, lines: isize) {
+        let max = self.lines.len() - self.rows;
+        let view = self.view.saturating_add_signed(lines).min(max);
+        if view != self.view {
+            self.view = view;
+            self.dirty.fill(true);
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        if self.view != 0 {
+            self.view = 0;
+            self.dirty.fill(true);
+        }
+        // Where the cursor is drawn now, and will be.
+        self.dirty[self.y] = true;
+        for &byte in buf {
+            self.put(byte);
+        }
+        self.dirty[self.y] = true;
+    }
+
+    fn put(&mut self, byte: u8) {
+        match self.state {
+            State::Normal => self.put_normal(byte),
+            State::Escape => {
+                self.state = State::Normal;
+                match byte {
+                    b'[' => {
+                        self.state = State::Csi;
+                        self.params = [0; MAX_PARAMS];
+                        self.nparams = 0;
+                    }
+                    b'c' => self.reset(),
+                    _ => {}
+                }
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    self.nparams = self.nparams.max(1);
+                    if let Some(param) = self.params.get_mut(self.nparams - 1) {
+                        *param = param
+                            .saturating_mul(10)
+                            .saturating_add((byte - b'0') as u16);
+                    }
+                }
+                b';' => self.nparams = (self.nparams.max(1) + 1).min(MAX_PARAMS + 1),
+                0x40..=0x7e => {
+                    self.state = State::Normal;
+                    self.csi(byte);
+                }
+                // Private markers and intermediate bytes.
+                _ => {}
+            },
+        }
+    }
+
+    fn put_normal(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.x = 0;
+                self.line_feed();
+            }
+            b'\r' => self.x = 0,
+            0x08 => self.x = self.x.min(self.cols - 1).saturating_sub(1),
+            b'\t' => self.x = ((self.x / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            ESC => self.state = State::Escape,
+            // Continuation bytes of UTF-8, their lead byte was drawn.
+            0x80..=0xbf => {}
+            0x20..=0x7e | 0xc0..=0xff => {
+                if self.x >= self.cols {
+                    self.x = 0;
+                    self.line_feed();
+                }
+                let (mut fg, mut bg) = (self.fg, self.bg);
+                if self.bold {
+                    fg |= BRIGHT;
+                }
+                if self.reverse {
+                    (fg, bg) = (bg, fg);
+                }
+                let x = self.x;
+                let y = self.y;
+                self.row_mut(y)[x] = Cell { ch: byte, fg, bg };
+                self.x += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.y + 1 < self.rows {
+            self.y += 1;
+            return;
+        }
+        if self.lines.len() == self.rows + SCROLLBACK_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(vec![Cell::blank(self.bg); self.cols]);
+        self.dirty.remove(0);
+        self.dirty.push(true);
+        self.scrolled += 1;
+    }
+
+    fn reset(&mut self) {
+        self.fg = DEFAULT_FG;
+        self.bg = DEFAULT_BG;
+        self.bold = false;
+        self.reverse = false;
+        self.erase(0..self.rows, 0..self.cols);
+        self.x = 0;
+        self.y = 0;
+    }
+
+    fn erase(&mut self, rows: core::ops::Range<usize>, cols: core::ops::Range<usize>) {
+        let blank = Cell::blank(self.bg);
+        for y in rows {
+            self.row_mut(y)[cols.clone()].fill(blank);
+        }
+    }
+
+    // The parameter `i`, `default` if it's missing or 0.
+    fn param(&self, i: usize, default: usize) -> usize {
+        match self.params.get(i) {
+            Some(&param) if i < self.nparams && param != 0 => param as usize,
+            _ => default,
+        }
+    }
+
+    fn csi(&mut self, command: u8) {
+        let n = self.param(0, 1);
+        let x = self.x.min(self.cols - 1);
+        match command {
+            b'A' => self.y = self.y.saturating_sub(n),
+            b'B' => self.y = (self.y + n).min(self.rows - 1),
+            b'C' => self.x = (x + n).min(self.cols - 1),
+            b'D' => self.x = x.saturating_sub(n),
+            b'G' => self.x = n.min(self.cols) - 1,
+            b'H' | b'f' => {
+                self.y = n.min(self.rows) - 1;
+                self.x = self.param(1, 1).min(self.cols) - 1;
+            }
+            b'J' => {
+                let y = self.y;
+                match self.param(0, 0) {
+                    0 => {
+                        self.erase(y..y + 1, x..self.cols);
+                        self.erase(y + 1..self.rows, 0..self.cols);
+                    }
+                    1 => {
+                        self.erase(0..y, 0..self.cols);
+                        self.erase(y..y + 1, 0..x + 1);
+                    }
+                    _ => self.erase(0..self.rows, 0..self.cols),
+                }
+            }
+            b'K' => {
+                let y = self.y;
+                match self.param(0, 0) {
+                    0 => self.erase(y..y + 1, x..self.cols),
+                    1 => self.erase(y..y + 1, 0..x + 1),
+                    _ => self.erase(y..y + 1, 0..self.cols),
+                }
+            }
+            b'm' => self.sgr(),
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self) {
+        for i in 0..self.nparams.clamp(1, MAX_PARAMS) {
+            match self.params[i] {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bold = false;
+                    self.reverse = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                p @ 30..=37 => self.fg = (p - 30) as u8,
+                39 => self.fg = DEFAULT_FG,
+                p @ 40..=47 => self.bg = (p - 40) as u8,
+                49 => self.bg = DEFAULT_BG,
+                p @ 90..=97 => self.fg = (p - 90) as u8 | BRIGHT,
+                p @ 100..=107 => self.bg = (p - 100) as u8 | BRIGHT,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The text console of a framebuffer.
+pub struct FbConsole {
+    base: DeviceBase,
+    id: DeviceId,
+    fb: Arc<Framebuffer>,
+    screen: SpinLock<Screen>,
+    foreground: AtomicBool,
+    // Set when the whole screen has to be drawn again.
+    stale: AtomicBool,
+    redraw: SpinLock<Option<Waker>>,
+}
+
+impl FbConsole {
+    // Draws what changed on the screen, if in the foreground.
+    fn draw(&self, screen: &mut Screen) -> Result<(), Error> {
+        if !self.foreground.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let stride = self.fb.info().stride as usize;
+        let row_bytes = CELL_HEIGHT * stride;
+        let text_bytes = screen.rows * row_bytes;
+        let full = self.stale.swap(false, Ordering::AcqRel) || screen.scrolled >= screen.rows;
+        let scrolled = screen.scrolled;
+        screen.scrolled = 0;
+        if full {
+            screen.dirty.fill(true);
+        }
+        let mut pixels = self.fb.pixels();
+        if full {
+            // The margins out of the grid too.
+            fill(&mut pixels, PALETTE[DEFAULT_BG as usize]);
+        } else if scrolled > 0 {
+            pixels.copy_within(scrolled * row_bytes..text_bytes, 0);
+        }
+        let cursor = screen.cursor();
+        let mut flushed = if scrolled > 0 { 0..screen.rows } else { 0..0 };
+        for y in 0..screen.rows {
+            if !screen.dirty[y] {
+                continue;
+            }
+            screen.dirty[y] = false;
+            for (x, cell) in screen.view_row(y).iter().enumerate() {
+                let cell = match cursor {
+                    Some(at) if at == (x, y) => Cell {
+                        ch: cell.ch,
+                        fg: cell.bg,
+                        bg: cell.fg,
+                    },
+                    _ => *cell,
+                };
+                draw_cell(&mut pixels, stride, x, y, cell);
+            }
+            flushed = if flushed.is_empty() {
+                y..y + 1
+            } else {
+                flushed.start.min(y)..flushed.end.max(y + 1)
+            };
+        }
+        drop(pixels);
+        if full {
+            return self.fb.flush(0..self.fb.info().height);
+        }
+        self.fb
+            .flush((flushed.start * CELL_HEIGHT) as u32..(flushed.end * CELL_HEIGHT) as u32)
+    }
+
+    // Draws the whole screen again, once back in the foreground.
+    fn refresh(&self) {
+        if self.stale.load(Ordering::Acquire) {
+            let _ = self.draw(&mut self.screen.lock());
+        }
+    }
+}
+
+fn fill(pixels: &mut [u8], color: u32) {
+    for pixel in pixels.chunks_exact_mut(BYTES_PER_PIXEL) {
+        pixel.copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+fn draw_cell(pixels: &mut [u8], stride: usize, x: usize, y: usize, cell: Cell) {
+    let fg = PALETTE[cell.fg as usize & 0xf].to_le_bytes();
+    let bg = PALETTE[cell.bg as usize & 0xf].to_le_bytes();
+    for (i, bits) in glyph(cell.ch).iter().enumerate() {
+        for j in 0..SCALE {
+            let start =
+                (y * CELL_HEIGHT + i * SCALE + j) * stride + x * CELL_WIDTH * BYTES_PER_PIXEL;
+            let row = &mut pixels[start..start + CELL_WIDTH * BYTES_PER_PIXEL];
+            for (k, pixel) in row.chunks_exact_mut(BYTES_PER_PIXEL).enumerate() {
+                let color = if bits & (0x80 >> k) != 0 { &fg } else { &bg };
+                pixel.copy_from_slice(color);
+            }
+        }
+    }
+}
+
+impl Vt for FbConsole {
+    fn sink(&self) -> &'static str {
+        SINK
+    }
+
+    fn set_foreground(&self, foreground: bool) {
+        if self.foreground.swap(foreground, Ordering::AcqRel) || !foreground {
+            return;
+        }
+        self.stale.store(true, Ordering::Release);
+        if let Some(waker) = self.redraw.irqsave_lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl Device for FbConsole {
+    fn name(&self) -> String {
+        String::from(SINK)
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn open(&self) -> Result<(), Error> {
+        self.base.inc_open_count();
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), Error> {
+        self.base.dec_open_count();
+        Ok(())
+    }
+
+    fn read(&self, _pos: u64, _buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let mut screen = self.screen.lock();
+        screen.write(buf);
+        self.draw(&mut screen)?;
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if FBCON_SCROLL.matches(request) {
+            let lines = FBCON_SCROLL.copy_in(arg)?;
+            let mut screen = self.screen.lock();
+            screen.scroll_view(lines as isize);
+            return self.draw(&mut screen);
+        }
+        if VT_ACTIVATE.matches(request) {
+            return vt::activate(VT_ACTIVATE.copy_in(arg)? as usize);
+        }
+        if VT_GETACTIVE.matches(request) {
+            return VT_GETACTIVE.copy_out(arg, &(vt::active() as u32));
+        }
+        Err(code::ENOSYS)
+    }
+}
+
+/// Sets the text console up on `fb`.
+pub(super) fn init(fb: Arc<Framebuffer>) -> Result<(), Error> {
+    let info = fb.info();
+    let cols = info.width as usize / CELL_WIDTH;
+    let rows = info.height as usize / CELL_HEIGHT;
+    if cols == 0 || rows == 0 {
+        return Err(code::EINVAL);
+    }
+    let major = devno::register_major(DeviceClass::Char, 0, "vt")?;
+    let id = devno::alloc_minor(DeviceClass::Char, major)?;
+    let fbcon = Arc::new(FbConsole {
+        base: DeviceBase::new(),
+        id,
+        fb,
+        screen: SpinLock::new(Screen::new(cols, rows)),
+        foreground: AtomicBool::new(false),
+        stale: AtomicBool::new(true),
+        redraw: SpinLock::new(None),
+    });
+    DeviceManager::get().register_device(String::from(SINK), fbcon.clone())?;
+    let this = fbcon.clone();
+    asynk::spawn(future::poll_fn(move |ctx| {
+        this.redraw
+            .irqsave_lock()
+            .get_or_insert_with(|| ctx.waker().clone());
+        this.refresh();
+        Poll::Pending
+    }));
+    vt::register(fbcon.clone())?;
+    console::init_console(SINK, fbcon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn text(screen: &Screen, y: usize) -> String {
+        screen
+            .view_row(y)
+            .iter()
+            .map(|cell| cell.ch as char)
+            .collect()
+    }
+
+    #[test]
+    fn test_fbcon_screen() {
+        let mut screen = Screen::new(8, 3);
+        screen.write(b"hello\nworld, wraps\n");
+        assert_eq!(text(&screen, 0), "world, w");
+        assert_eq!(text(&screen, 1), "raps    ");
+        assert_eq!(screen.scrolled, 1);
+        assert_eq!((screen.x, screen.y), (0, 2));
+
+        // Colors, bold and reverse.
+        screen.write(b"\x1b[31;1ma\x1b[7mb\x1b[0mc");
+        let row = screen.view_row(2);
+        assert_eq!((row[0].fg, row[0].bg), (1 | BRIGHT, DEFAULT_BG));
+        assert_eq!((row[1].fg, row[1].bg), (DEFAULT_BG, 1 | BRIGHT));
+        assert_eq!((row[2].fg, row[2].bg), (DEFAULT_FG, DEFAULT_BG));
+
+        // Cursor moves and erasing.
+        screen.write(b"\x1b[1;3HX\x1b[2DY\x1b[K");
+        assert_eq!(text(&screen, 0), "wY      ");
+        screen.write(b"\x1b[2J\x1b[H!");
+        assert_eq!(text(&screen, 0), "!       ");
+        assert_eq!(text(&screen, 2), "        ");
+        // Unknown sequences are swallowed.
+        screen.write(b"\x1b[?25l\x1b[5n\xc3\xa9");
+        assert_eq!(text(&screen, 0), "!\u{c3}      ");
+
+        // The scrollback keeps what went off the screen.
+        screen.scroll_view(100);
+        assert_eq!(screen.view, 1);
+        assert_eq!(text(&screen, 0), "hello   ");
+        assert_eq!(screen.cursor(), None);
+        screen.write(b"");
+        assert_eq!(screen.view, 0);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Framebuffers.
+//!
+//! Display drivers hand their linear framebuffer over to
+//! [`register_framebuffer`], which registers it as a char device "fb0".
+//! Pixels are [`FB_FORMAT_XRGB8888`], rows [`FbInfo::stride`] bytes apart.
+//! User code draws by writing at an offset of the device, and reads the
+//! geometry with [`FB_GET_INFO`]. Displays which don't scan the memory out
+//! by themselves are pushed the rows written to through [`FbOps::flush`].
+//!
+//! The first framebuffer also gets the text console, see [`fbcon`].
+
+pub mod fbcon;
+mod font;
+#[cfg(virtio)]
+pub mod virtio;
+
+use super::{devno, Device, DeviceBase, DeviceClass, DeviceId, DeviceManager};
+use crate::{
+    error::{code, Error},
+    sync::{SpinLock, SpinLockGuard},
+};
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+crate::ioctl_read!(
+    /// Reads the [`FbInfo`] of a framebuffer.
+    pub FB_GET_INFO, b'F', 0x00, FbInfo
+);
+
+/// 32 bits per pixel, blue in the lowest byte and the highest one unused.
+pub const FB_FORMAT_XRGB8888: u32 = 0;
+
+const BYTES_PER_PIXEL: usize = 4;
+
+static FRAMEBUFFERS: AtomicUsize = AtomicUsize::new(0);
+
+/// The geometry of a framebuffer.
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct FbInfo {
+    /// In pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from a row to the next.
+    pub stride: u32,
+    /// One of the `FB_FORMAT_*` formats.
+    pub format: u32,
+}
+
+impl FbInfo {
+    pub fn size(&self) -> usize {
+        self.stride as usize * self.height as usize
+    }
+}
+
+/// The display side of a framebuffer.
+pub trait FbOps: Send + Sync {
+    /// Pushes `rows` of the framebuffer to the display. Displays scanning
+    /// the memory out by themselves have nothing to do.
+    fn flush(&self, rows: Range<u32>) -> Result<(), Error>;
+}
+
+/// A framebuffer, registered as a char device.
+pub struct Framebuffer {
+    base: DeviceBase,
+    name: String,
+    id: DeviceId,
+    info: FbInfo,
+    pixels: SpinLock<&'static mut [u8]>,
+    ops: Box<dyn FbOps>,
+}
+
+impl Framebuffer {
+    pub fn info(&self) -> FbInfo {
+        self.info
+    }
+
+    /// Locks the pixels for drawing. Whatever is drawn is only sure to be
+    /// shown once flushed.
+    pub fn pixels(&self) -> SpinLockGuard<'_, &'static mut [u8]> {
+        self.pixels.lock()
+    }
+
+    pub fn flush(&self, rows: Range<u32>) -> Result<(), Error> {
+        let rows = rows.start.min(self.info.height)..rows.end.min(self.info.height);
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.ops.flush(rows)
+    }
+
+    // The rows the bytes at `pos` with length `len` are on.
+    fn rows_of(&self, pos: usize, len: usize) -> Range<u32> {
+        let stride = self.info.stride as usize;
+        (pos / stride) as u32..(pos + len).div_ceil(stride) as u32
+    }
+}
+
+impl Device for Framebuffer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn open(&self) -> Result<(), Error> {
+        self.base.inc_open_count();
+        Ok(())
+    }
+
+    fn close(&self) -> Result<(), Error> {
+        self.base.dec_open_count();
+        Ok(())
+    }
+
+    fn read(&self, pos: u64, buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let pixels = self.pixels();
+        let pos = (pos as usize).min(pixels.len());
+        let len = buf.len().min(pixels.len() - pos);
+        buf[..len].copy_from_slice(&pixels[pos..pos + len]);
+        Ok(len)
+    }
+
+    fn write(&self, pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let len = {
+            let mut pixels = self.pixels();
+            let pos = (pos as usize).min(pixels.len());
+            let len = buf.len().min(pixels.len() - pos);
+            if len == 0 && !buf.is_empty() {
+                return Err(code::ENOSPC);
+            }
+            pixels[pos..pos + len].copy_from_slice(&buf[..len]);
+            len
+        };
+        self.flush(self.rows_of(pos as usize, len))?;
+        Ok(len)
+    }
+
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if FB_GET_INFO.matches(request) {
+            return FB_GET_INFO.copy_out(arg, &self.info);
+        }
+        Err(code::ENOSYS)
+    }
+
+    fn capacity(&self) -> Result<u64, Error> {
+        Ok(self.info.size() as u64)
+    }
+}
+
+/// Registers the framebuffer of a display driver as "fb<n>", `pixels`
+/// being laid out as `info` says. The first one also gets the text
+/// console.
+pub fn register_framebuffer(
+    info: FbInfo,
+    pixels: &'static mut [u8],
+    ops: Box<dyn FbOps>,
+) -> Result<Arc<Framebuffer>, Error> {
+    if info.format != FB_FORMAT_XRGB8888
+        || (info.stride as usize) < info.width as usize * BYTES_PER_PIXEL
+        || pixels.len() < info.size()
+    {
+        return Err(code::EINVAL);
+    }
+    let index = FRAMEBUFFERS.fetch_add(1, Ordering::Relaxed);
+    let name = format!("fb{}", index);
+    let major = devno::register_major(DeviceClass::Char, 0, "fb")?;
+    let id = devno::alloc_minor(DeviceClass::Char, major)?;
+    let fb = Arc::new(Framebuffer {
+        base: DeviceBase::new(),
+        name: name.clone(),
+        id,
+        info,
+        pixels: SpinLock::new(pixels),
+        ops,
+    });
+    DeviceManager::get().register_device(name, fb.clone())?;
+    if index == 0 {
+        fbcon::init(fb.clone())?;
+    }
+    Ok(fb)
+}
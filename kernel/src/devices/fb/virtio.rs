@@ -0,0 +1,85 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! virtio-gpu framebuffer.
+//!
+//! The first scanout of the device is set up with a framebuffer at its
+//! resolution. The device doesn't scan the memory out by itself, so
+//! flushing transfers the framebuffer to it and has it shown again. The
+//! driver only transfers the whole framebuffer at once.
+
+use super::{register_framebuffer, FbInfo, FbOps, BYTES_PER_PIXEL, FB_FORMAT_XRGB8888};
+use crate::{
+    devices::virtio::VirtioHal,
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::ops::Range;
+use log::{debug, error};
+use virtio_drivers::{device::gpu::VirtIOGpu, transport::SomeTransport};
+
+type Gpu = VirtIOGpu<VirtioHal, SomeTransport<'static>>;
+
+struct VirtioFb {
+    gpu: Arc<SpinLock<Gpu>>,
+}
+
+impl FbOps for VirtioFb {
+    fn flush(&self, _rows: Range<u32>) -> Result<(), Error> {
+        self.gpu.lock().flush().map_err(|_| code::EIO)
+    }
+}
+
+pub fn init(transport: SomeTransport<'static>) {
+    let mut gpu = match Gpu::new(transport) {
+        Ok(gpu) => gpu,
+        Err(e) => {
+            error!("Failed to init virtio-gpu, {:?}", e);
+            return;
+        }
+    };
+    let (width, height) = match gpu.resolution() {
+        Ok(resolution) => resolution,
+        Err(e) => {
+            error!("Failed to get the resolution of virtio-gpu, {:?}", e);
+            return;
+        }
+    };
+    // The device takes B8G8R8A8 pixels, which are XRGB8888 in memory.
+    let pixels = match gpu.setup_framebuffer() {
+        Ok(pixels) => pixels,
+        Err(e) => {
+            error!("Failed to set the framebuffer of virtio-gpu up, {:?}", e);
+            return;
+        }
+    };
+    // SAFETY: The framebuffer is DMA memory owned by the driver, which
+    // never frees it, and the driver itself never writes to it. The pixels
+    // are only touched through the framebuffer from now on.
+    let pixels = unsafe { core::slice::from_raw_parts_mut(pixels.as_mut_ptr(), pixels.len()) };
+    debug!("virtio-gpu has a {}x{} framebuffer", width, height);
+    let info = FbInfo {
+        width,
+        height,
+        stride: width * BYTES_PER_PIXEL as u32,
+        format: FB_FORMAT_XRGB8888,
+    };
+    let ops = VirtioFb {
+        gpu: Arc::new(SpinLock::new(gpu)),
+    };
+    if let Err(e) = register_framebuffer(info, pixels, Box::new(ops)) {
+        error!("Failed to register the virtio-gpu framebuffer: {:?}", e);
+    }
+}
@@ -12,11 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::println;
+use crate::{println, sync::KOnce};
 use flat_device_tree::Fdt;
-use spin::Once;
 
-static FDT: Once<Fdt<'static>> = Once::new();
+static FDT: KOnce<Fdt<'static>> = KOnce::new();
 
 pub fn fdt_init(base: u64) {
     // SAFETY: FDT pointer given by the bootloader/qemu is valid.
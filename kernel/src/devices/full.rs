@@ -0,0 +1,96 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    devices::{devno::MEM_MAJOR, driver::InitLevel, Device, DeviceClass, DeviceId, DeviceManager},
+    error::{code, Error},
+};
+use alloc::{string::String, sync::Arc};
+
+/// A device which is always full, to test how programs handle ENOSPC.
+pub struct Full;
+
+crate::driver_init!("full", InitLevel::Device, init);
+
+fn init() -> Result<(), Error> {
+    Full::register()
+}
+
+impl Full {
+    pub fn register() -> Result<(), Error> {
+        let full = Arc::new(Full);
+        DeviceManager::get().register_device(String::from("full"), full)
+    }
+}
+
+impl Device for Full {
+    fn name(&self) -> String {
+        String::from("full")
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::new(MEM_MAJOR, 7)
+    }
+
+    fn read(&self, _pos: u64, buf: &mut [u8], _is_blocking: bool) -> Result<usize, Error> {
+        // Reads like /dev/zero
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _pos: u64, _buf: &[u8], _is_blocking: bool) -> Result<usize, Error> {
+        // There is never any space left
+        Err(code::ENOSPC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_full_device_read() {
+        let full = Full;
+        let mut buffer = [1u8; 10];
+
+        // Read should fill buffer with zeros
+        let result = full.read(0, &mut buffer, true);
+        assert_eq!(result, Ok(buffer.len()));
+        assert!(buffer.iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn test_full_device_write() {
+        let full = Full;
+        let buffer = [1u8, 2, 3, 4, 5];
+
+        // Write should always fail with ENOSPC
+        let result = full.write(0, &buffer, true);
+        assert_eq!(result, Err(code::ENOSPC));
+    }
+
+    #[test]
+    fn test_full_device_id() {
+        let full = Full;
+        let id = full.id();
+
+        assert_eq!(id.major(), 1);
+        assert_eq!(id.minor(), 7);
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ioctl request encoding, compatible with Linux's `_IO/_IOR/_IOW/_IOWR`.
+//!
+//! ```text
+//! 31  30 29          16 15        8 7          0
+//! +-----+--------------+-----------+------------+
+//! | dir |     size     |   type    |   number   |
+//! +-----+--------------+-----------+------------+
+//! ```
+//!
+//! The general requests of [`DeviceRequest`](super::DeviceRequest) have no
+//! direction, type or size, so they decode as plain `_IO(0, nr)` requests.
+//!
+//! Drivers declare their requests once with the `ioctl_*!` macros and use
+//! [`Ioctl::copy_in`] and [`Ioctl::copy_out`] to move the argument, which
+//! check the request's direction, the argument pointer and its alignment.
+//! Older requests, like Linux's terminal ones, predate the encoding and are
+//! plain numbers; the `_bad` macros declare them with their direction.
+
+use crate::error::{code, Error};
+use core::{marker::PhantomData, mem};
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+pub const IOC_NRBITS: u32 = 8;
+pub const IOC_TYPEBITS: u32 = 8;
+pub const IOC_SIZEBITS: u32 = 14;
+pub const IOC_DIRBITS: u32 = 2;
+
+pub const IOC_NRSHIFT: u32 = 0;
+pub const IOC_TYPESHIFT: u32 = IOC_NRSHIFT + IOC_NRBITS;
+pub const IOC_SIZESHIFT: u32 = IOC_TYPESHIFT + IOC_TYPEBITS;
+pub const IOC_DIRSHIFT: u32 = IOC_SIZESHIFT + IOC_SIZEBITS;
+
+/// No data is transferred.
+pub const IOC_NONE: u32 = 0;
+/// The caller passes data to the driver.
+pub const IOC_WRITE: u32 = 1;
+/// The driver passes data back to the caller.
+pub const IOC_READ: u32 = 2;
+
+pub const fn ioc(dir: u32, ty: u8, nr: u8, size: usize) -> u32 {
+    assert!(size < 1 << IOC_SIZEBITS, "ioctl argument is too large");
+    (dir << IOC_DIRSHIFT)
+        | ((ty as u32) << IOC_TYPESHIFT)
+        | ((nr as u32) << IOC_NRSHIFT)
+        | ((size as u32) << IOC_SIZESHIFT)
+}
+
+#[inline]
+pub const fn ioc_dir(request: u32) -> u32 {
+    (request >> IOC_DIRSHIFT) & ((1 << IOC_DIRBITS) - 1)
+}
+
+#[inline]
+pub const fn ioc_type(request: u32) -> u8 {
+    (request >> IOC_TYPESHIFT) as u8
+}
+
+#[inline]
+pub const fn ioc_nr(request: u32) -> u8 {
+    (request >> IOC_NRSHIFT) as u8
+}
+
+#[inline]
+pub const fn ioc_size(request: u32) -> usize {
+    ((request >> IOC_SIZESHIFT) & ((1 << IOC_SIZEBITS) - 1)) as usize
+}
+
+/// An ioctl request whose argument is a pointer to a `T`.
+#[derive(Debug)]
+pub struct Ioctl<T> {
+    request: u32,
+    dir: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Ioctl<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ioctl<T> {}
+
+impl Ioctl<()> {
+    pub const fn none(ty: u8, nr: u8) -> Self {
+        Self::from_raw(ioc(IOC_NONE, ty, nr, 0))
+    }
+}
+
+impl<T> Ioctl<T> {
+    const fn from_raw(request: u32) -> Self {
+        Self::with_dir(request, ioc_dir(request))
+    }
+
+    const fn with_dir(request: u32, dir: u32) -> Self {
+        Self {
+            request,
+            dir,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn read(ty: u8, nr: u8) -> Self {
+        Self::from_raw(ioc(IOC_READ, ty, nr, mem::size_of::<T>()))
+    }
+
+    pub const fn write(ty: u8, nr: u8) -> Self {
+        Self::from_raw(ioc(IOC_WRITE, ty, nr, mem::size_of::<T>()))
+    }
+
+    pub const fn read_write(ty: u8, nr: u8) -> Self {
+        Self::from_raw(ioc(IOC_READ | IOC_WRITE, ty, nr, mem::size_of::<T>()))
+    }
+
+    /// A request numbered `request` as is, whose argument is filled by the
+    /// driver.
+    pub const fn read_bad(request: u32) -> Self {
+        Self::with_dir(request, IOC_READ)
+    }
+
+    /// A request numbered `request` as is, whose argument is passed to the
+    /// driver.
+    pub const fn write_bad(request: u32) -> Self {
+        Self::with_dir(request, IOC_WRITE)
+    }
+
+    #[inline]
+    pub const fn request(&self) -> u32 {
+        self.request
+    }
+
+    #[inline]
+    pub const fn matches(&self, request: u32) -> bool {
+        self.request == request
+    }
+
+    fn check_arg(&self, arg: usize, dir: u32) -> Result<*mut T, Error> {
+        if self.dir & dir == 0 {
+            return Err(code::ENOSYS);
+        }
+        let ptr = arg as *mut T;
+        if ptr.is_null() || !ptr.is_aligned() {
+            return Err(code::EINVAL);
+        }
+        Ok(ptr)
+    }
+}
+
+impl<T: FromBytes> Ioctl<T> {
+    /// Reads the argument passed by the caller.
+    pub fn copy_in(&self, arg: usize) -> Result<T, Error> {
+        let ptr = self.check_arg(arg, IOC_WRITE)?;
+        // SAFETY: The pointer is non-null and aligned, and any bit pattern
+        // is a valid T.
+        Ok(unsafe { ptr.read() })
+    }
+}
+
+impl<T: IntoBytes + Immutable> Ioctl<T> {
+    /// Passes `val` back to the caller.
+    pub fn copy_out(&self, arg: usize, val: &T) -> Result<(), Error> {
+        let ptr = self.check_arg(arg, IOC_READ)?;
+        // SAFETY: The pointer is non-null and aligned.
+        unsafe {
+            ptr.cast::<u8>()
+                .copy_from_nonoverlapping(val.as_bytes().as_ptr(), mem::size_of::<T>())
+        };
+        Ok(())
+    }
+}
+
+/// Declares an ioctl request without argument, like `_IO`.
+#[macro_export]
+macro_rules! ioctl_none {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $ty:expr, $nr:expr) => {
+        $(#[$attr])*
+        $vis const $name: $crate::devices::ioctl::Ioctl<()> =
+            $crate::devices::ioctl::Ioctl::none($ty, $nr);
+    };
+}
+
+/// Declares an ioctl request whose argument is filled by the driver, like
+/// `_IOR`.
+#[macro_export]
+macro_rules! ioctl_read {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $ty:expr, $nr:expr, $arg:ty) => {
+        $(#[$attr])*
+        $vis const $name: $crate::devices::ioctl::Ioctl<$arg> =
+            $crate::devices::ioctl::Ioctl::read($ty, $nr);
+    };
+}
+
+/// Declares an ioctl request whose argument is passed to the driver, like
+/// `_IOW`.
+#[macro_export]
+macro_rules! ioctl_write {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $ty:expr, $nr:expr, $arg:ty) => {
+        $(#[$attr])*
+        $vis const $name: $crate::devices::ioctl::Ioctl<$arg> =
+            $crate::devices::ioctl::Ioctl::write($ty, $nr);
+    };
+}
+
+/// Declares an ioctl request whose argument goes both ways, like `_IOWR`.
+#[macro_export]
+macro_rules! ioctl_readwrite {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $ty:expr, $nr:expr, $arg:ty) => {
+        $(#[$attr])*
+        $vis const $name: $crate::devices::ioctl::Ioctl<$arg> =
+            $crate::devices::ioctl::Ioctl::read_write($ty, $nr);
+    };
+}
+
+/// Declares a request which doesn't follow the encoding, whose argument is
+/// filled by the driver.
+#[macro_export]
+macro_rules! ioctl_read_bad {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $request:expr, $arg:ty) => {
+        $(#[$attr])*
+        $vis const $name: $crate::devices::ioctl::Ioctl<$arg> =
+            $crate::devices::ioctl::Ioctl::read_bad($request);
+    };
+}
+
+/// Declares a request which doesn't follow the encoding, whose argument is
+/// passed to the driver.
+#[macro_export]
+macro_rules! ioctl_write_bad {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $request:expr, $arg:ty) => {
+        $(#[$attr])*
+        $vis const $name: $crate::devices::ioctl::Ioctl<$arg> =
+            $crate::devices::ioctl::Ioctl::write_bad($request);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    crate::ioctl_none!(TEST_RESET, b'f', 0);
+    crate::ioctl_read!(TEST_GET, b'f', 1, u32);
+    crate::ioctl_write!(TEST_SET, b'f', 2, u32);
+    crate::ioctl_readwrite!(TEST_XCHG, b'f', 3, u64);
+    crate::ioctl_read_bad!(TEST_GET_BAD, 0x6604, u32);
+
+    #[test]
+    fn test_ioctl_encoding() {
+        // Same values as Linux's _IO('f', 0), _IOR('f', 1, u32) and
+        // _IOW('f', 2, u32).
+        assert_eq!(TEST_RESET.request(), 0x6600);
+        assert_eq!(TEST_GET.request(), 0x8004_6601);
+        assert_eq!(TEST_SET.request(), 0x4004_6602);
+        assert_eq!(TEST_XCHG.request(), 0xc008_6603);
+
+        let request = TEST_XCHG.request();
+        assert_eq!(ioc_dir(request), IOC_READ | IOC_WRITE);
+        assert_eq!(ioc_type(request), b'f');
+        assert_eq!(ioc_nr(request), 3);
+        assert_eq!(ioc_size(request), 8);
+    }
+
+    #[test]
+    fn test_ioctl_copy() {
+        let mut val = 0x1234u32;
+        let arg = &mut val as *mut u32 as usize;
+        assert_eq!(TEST_SET.copy_in(arg), Ok(0x1234));
+        assert_eq!(TEST_GET.copy_out(arg, &0x5678), Ok(()));
+        assert_eq!(val, 0x5678);
+
+        // Direction mismatch.
+        assert_eq!(TEST_GET.copy_in(arg), Err(code::ENOSYS));
+        assert_eq!(TEST_SET.copy_out(arg, &0), Err(code::ENOSYS));
+        // Bad pointers.
+        assert_eq!(TEST_SET.copy_in(0), Err(code::EINVAL));
+        assert_eq!(TEST_SET.copy_in(arg + 1), Err(code::EINVAL));
+
+        // The direction of an unencoded request is the declared one.
+        assert_eq!(TEST_GET_BAD.request(), 0x6604);
+        assert_eq!(TEST_GET_BAD.copy_out(arg, &0x9abc), Ok(()));
+        assert_eq!(val, 0x9abc);
+        assert_eq!(TEST_GET_BAD.copy_in(arg), Err(code::ENOSYS));
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! /dev/kmsg, the [kernel log](crate::klog) one record per read. The file
+//! position is the sequence number of the next record, so each open file
+//! reads on from where it is, and seeking to 0 goes back to the oldest
+//! record kept. Each write is logged as one record, at the level given by
+//! a "<N>" prefix or at LOG_INFO.
+
+use crate::{
+    devices::{devno::MEM_MAJOR, driver::InitLevel, Device, DeviceClass, DeviceId, DeviceManager},
+    error::{code, Error},
+    klog,
+};
+use alloc::{string::String, sync::Arc};
+use core::str;
+
+pub struct Kmsg;
+
+crate::driver_init!("kmsg", InitLevel::Device, init);
+
+fn init() -> Result<(), Error> {
+    DeviceManager::get().register_device(String::from("kmsg"), Arc::new(Kmsg))
+}
+
+// Splits a leading "<N>" off `s`.
+fn split_level(s: &str) -> (u8, &str) {
+    let level = s
+        .strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .and_then(|(level, text)| Some((level.parse::<u8>().ok()? & 7, text)));
+    level.unwrap_or((klog::LOG_INFO, s))
+}
+
+impl Device for Kmsg {
+    fn name(&self) -> String {
+        String::from("kmsg")
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::new(MEM_MAJOR, 11)
+    }
+
+    fn read(&self, pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        let mut seq = pos;
+        klog::read_record(&mut seq, buf, is_nonblocking)
+    }
+
+    fn read_at(&self, pos: &mut u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        klog::read_record(pos, buf, is_nonblocking)
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let s = str::from_utf8(buf).map_err(|_| code::EINVAL)?;
+        let (level, text) = split_level(s);
+        klog::record(level, format_args!("{}", text));
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_kmsg() {
+        assert_eq!(split_level("<3>oops"), (3, "oops"));
+        assert_eq!(split_level("<x>oops"), (klog::LOG_INFO, "<x>oops"));
+        assert_eq!(split_level("oops"), (klog::LOG_INFO, "oops"));
+
+        let kmsg = Kmsg;
+        assert_eq!(kmsg.write(0, b"<4>kmsg test\n", false), Ok(13));
+        let mut pos = 0;
+        let mut buf = [0u8; 512];
+        let mut found = false;
+        while let Ok(n) = kmsg.read_at(&mut pos, &mut buf, true) {
+            let line = str::from_utf8(&buf[..n]).unwrap();
+            assert!(line.ends_with('\n'));
+            found |= line.starts_with("4,") && line.ends_with(",-;kmsg test\n");
+        }
+        assert!(found);
+    }
+}
@@ -12,28 +12,56 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::error::Error;
-use alloc::{collections::BTreeMap, string::String, sync::Arc};
+use crate::{
+    error::{code, Error},
+    sync::KOnce,
+    vfs::{
+        self,
+        poll::{PollEvents, PollWaiter},
+    },
+};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
 use core::{
     fmt::Debug,
     sync::atomic::{AtomicU32, Ordering},
 };
-use embedded_io::ErrorKind;
 use libc::*;
-use spin::{Once, RwLock as SpinRwLock};
+use spin::RwLock as SpinRwLock;
+use storage::StorageHealth;
+#[cfg(audio)]
+pub mod audio;
 #[cfg(virtio)]
 pub mod block;
+pub mod clk;
 pub mod console;
+pub mod devno;
+pub(crate) mod driver;
 pub(crate) mod dumb;
-mod error;
+#[cfg(fb)]
+pub mod fb;
+mod full;
+pub mod ioctl;
+mod kmsg;
 pub(crate) mod net;
 mod null;
+pub mod pinctrl;
+pub mod power;
+#[cfg(all(test, any(firmware_update, kvstore, virtio)))]
+pub(crate) mod ramdisk;
+mod random;
+pub mod reset;
+pub mod rtc;
+#[cfg(not(use_defmt))]
+mod rtt;
+pub mod storage;
 pub mod tty;
+#[cfg(usb)]
+pub mod usb;
 #[cfg(virtio)]
 pub mod virtio;
 mod zero;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 pub enum DeviceClass {
     Char,
@@ -176,27 +204,77 @@ pub trait Device: Send + Sync {
     fn name(&self) -> String;
     fn class(&self) -> DeviceClass;
     fn id(&self) -> DeviceId;
-    fn open(&self) -> Result<(), ErrorKind> {
+    fn open(&self) -> Result<(), Error> {
         Ok(())
     }
-    fn close(&self) -> Result<(), ErrorKind> {
+    fn close(&self) -> Result<(), Error> {
         Ok(())
     }
-    fn read(&self, pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, ErrorKind>;
-    fn write(&self, pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, ErrorKind>;
-    fn ioctl(&self, request: u32, arg: usize) -> Result<(), ErrorKind> {
-        Err(ErrorKind::Unsupported)
+    fn read(&self, pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error>;
+    /// Reads at `*pos` and advances it, for devices whose positions aren't
+    /// byte offsets. Reads and advances by the bytes read by default.
+    fn read_at(&self, pos: &mut u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        let n = self.read(*pos, buf, is_nonblocking)?;
+        *pos += n as u64;
+        Ok(n)
+    }
+    fn write(&self, pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, Error>;
+    /// Writes `bufs` one after the other, like writev. Writes them one by
+    /// one by default, stopping at the first short write.
+    fn write_vectored(
+        &self,
+        pos: u64,
+        bufs: &[&[u8]],
+        is_nonblocking: bool,
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+        for buf in bufs {
+            let n = self.write(pos + count as u64, buf, is_nonblocking)?;
+            count += n;
+            if n < buf.len() {
+                break;
+            }
+        }
+        Ok(count)
+    }
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        Err(code::ENOSYS)
     }
     /// Returns the device capacity.
     /// For block devices, returns the number of sectors
-    fn capacity(&self) -> Result<u64, ErrorKind> {
-        Err(ErrorKind::Unsupported)
+    fn capacity(&self) -> Result<u64, Error> {
+        Err(code::ENOSYS)
+    }
+    fn sector_size(&self) -> Result<u16, Error> {
+        Err(code::ENOSYS)
+    }
+    fn sync(&self) -> Result<(), Error> {
+        Err(code::ENOSYS)
+    }
+    /// Sets `len` bytes at `pos` back to all ones, as flash must be before
+    /// it's written again. Media which are overwritten in place just write
+    /// the ones.
+    fn erase(&self, pos: u64, len: u64) -> Result<(), Error> {
+        let ones = vec![0xffu8; len.min(4096) as usize];
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(ones.len() as u64) as usize;
+            if self.write(pos + done, &ones[..n], false)? != n {
+                return Err(code::EIO);
+            }
+            done += n as u64;
+        }
+        Ok(())
     }
-    fn sector_size(&self) -> Result<u16, ErrorKind> {
-        Err(ErrorKind::Unsupported)
+    /// Returns the I/O and wear statistics of a storage device.
+    fn health(&self) -> Result<StorageHealth, Error> {
+        Err(code::ENOSYS)
     }
-    fn sync(&self) -> Result<(), ErrorKind> {
-        Err(ErrorKind::Unsupported)
+    /// Returns which of `events` the device is ready for. If `waiter` is
+    /// given, it's woken when that may have changed. Devices which never
+    /// block are always ready.
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        PollEvents::ready(events)
     }
 }
 
@@ -210,7 +288,7 @@ impl Debug for dyn Device {
     }
 }
 
-static DEVICE_MANAGER: Once<DeviceManager> = Once::new();
+static DEVICE_MANAGER: KOnce<DeviceManager> = KOnce::new();
 
 pub struct DeviceManager {
     pub char_devices: SpinRwLock<BTreeMap<String, Arc<dyn Device>>>,
@@ -237,30 +315,34 @@ impl DeviceManager {
             + self.misc_devices.read().len()
     }
 
-    pub fn register_device(&self, name: String, dev: Arc<dyn Device>) -> Result<(), ErrorKind> {
-        match dev.class() {
-            DeviceClass::Char => {
-                let mut devices = self.char_devices.write();
-                devices
-                    .try_insert(name, dev)
-                    .map_err(|_| ErrorKind::AlreadyExists)?;
-            }
-            DeviceClass::Block => {
-                let mut devices = self.block_devices.write();
-                devices
-                    .try_insert(name, dev)
-                    .map_err(|_| ErrorKind::AlreadyExists)?;
-            }
-            DeviceClass::Misc => {
-                let mut devices = self.misc_devices.write();
-                devices
-                    .try_insert(name, dev)
-                    .map_err(|_| ErrorKind::AlreadyExists)?;
+    /// Registers `dev` as `name`, and adds it to /dev if it's mounted. Fails
+    /// if the name is taken or if another device of the same class has the
+    /// same number.
+    pub fn register_device(&self, name: String, dev: Arc<dyn Device>) -> Result<(), Error> {
+        {
+            let mut devices = self.devices_of(dev.class()).write();
+            if devices.contains_key(&name) {
+                return Err(code::EEXIST);
             }
-        };
+            devno::claim(dev.class(), dev.id(), &name)?;
+            devices.insert(name.clone(), dev.clone());
+        }
+        vfs::device_registered(&name, dev);
         Ok(())
     }
 
+    /// Unregisters the device registered as `name`, releases its number and
+    /// removes it from /dev. Files open on it keep it alive.
+    pub fn unregister_device(&self, name: &str) -> Result<Arc<dyn Device>, Error> {
+        let dev = [DeviceClass::Char, DeviceClass::Block, DeviceClass::Misc]
+            .into_iter()
+            .find_map(|class| self.devices_of(class).write().remove(name))
+            .ok_or(code::ENOENT)?;
+        devno::release(dev.class(), dev.id(), name);
+        vfs::device_unregistered(name);
+        Ok(dev)
+    }
+
     pub fn get_block_device(&self, str: &str) -> Option<Arc<dyn Device>> {
         self.block_devices.read().get(str).cloned()
     }
@@ -273,6 +355,43 @@ impl DeviceManager {
         self.misc_devices.read().get(str).cloned()
     }
 
+    fn devices_of(&self, class: DeviceClass) -> &SpinRwLock<BTreeMap<String, Arc<dyn Device>>> {
+        match class {
+            DeviceClass::Char => &self.char_devices,
+            DeviceClass::Block => &self.block_devices,
+            DeviceClass::Misc => &self.misc_devices,
+        }
+    }
+
+    /// Returns the devices of `class` with the names they're registered
+    /// under, sorted by name.
+    pub fn get_devices_by_class(&self, class: DeviceClass) -> Vec<(String, Arc<dyn Device>)> {
+        self.devices_of(class)
+            .read()
+            .iter()
+            .map(|(name, dev)| (name.clone(), dev.clone()))
+            .collect()
+    }
+
+    /// Looks a device up by its major and minor numbers. Numbers are only
+    /// unique within a class.
+    pub fn get_device_by_id(&self, class: DeviceClass, id: DeviceId) -> Option<Arc<dyn Device>> {
+        self.devices_of(class)
+            .read()
+            .values()
+            .find(|dev| dev.id() == id)
+            .cloned()
+    }
+
+    /// Iterates over all the registered devices, char devices first, then
+    /// block and misc ones. Each class is snapshotted when reached, so the
+    /// registry isn't locked while the caller looks at the devices.
+    pub fn iter(&self) -> impl Iterator<Item = (String, Arc<dyn Device>)> + '_ {
+        [DeviceClass::Char, DeviceClass::Block, DeviceClass::Misc]
+            .into_iter()
+            .flat_map(|class| self.get_devices_by_class(class))
+    }
+
     pub fn foreach<F>(&self, callback: F) -> Result<(), Error>
     where
         F: Fn(&str, Arc<dyn Device>) -> Result<(), Error>,
@@ -299,17 +418,53 @@ impl DeviceManager {
     }
 }
 
-pub fn init() -> Result<(), Error> {
-    null::Null::register().map_err(Error::from)?;
-    zero::Zero::register().map_err(Error::from)?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use blueos_test_macro::test;
 
+    struct Dummy;
+
+    impl Device for Dummy {
+        fn name(&self) -> String {
+            String::from("enum_dummy")
+        }
+
+        fn class(&self) -> DeviceClass {
+            DeviceClass::Misc
+        }
+
+        fn id(&self) -> DeviceId {
+            DeviceId::new(240, 7)
+        }
+
+        fn read(&self, _pos: u64, _buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+            Ok(0)
+        }
+
+        fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_device_enumeration() {
+        let manager = DeviceManager::get();
+        manager
+            .register_device(String::from("enum_dummy"), Arc::new(Dummy))
+            .unwrap();
+
+        let id = DeviceId::new(240, 7);
+        let dev = manager.get_device_by_id(DeviceClass::Misc, id).unwrap();
+        assert_eq!(dev.name(), "enum_dummy");
+        assert!(manager.get_device_by_id(DeviceClass::Char, id).is_none());
+        assert!(manager
+            .get_devices_by_class(DeviceClass::Misc)
+            .iter()
+            .any(|(name, _)| name == "enum_dummy"));
+        assert_eq!(manager.iter().count(), manager.get_device_number());
+    }
+
     #[test]
     fn test_device_id_creation() {
         let device_id = DeviceId::new(123, 456);
@@ -12,65 +12,100 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::cell::RefCell;
+//! The `lo` card, which receives the frames it sends. It goes through the
+//! same path as real cards, so sockets and protocols can be tested without
+//! virtio-net or a host network.
 
+use super::{NetDeviceOps, Nic};
 use crate::{
-    net::net_interface::{NetDevice, NetInterface},
-    time::tick_get_millisecond,
-};
-use alloc::{rc::Rc, vec};
-use smoltcp::{
-    iface::{Config, Interface, SocketSet},
-    phy::{Device, Medium},
-    time::{Duration, Instant},
-    wire::{EthernetAddress, IpAddress, IpCidr},
+    devices::driver::InitLevel,
+    error::{code, Error},
 };
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+
+/// Name of the loopback card.
+pub const LOOPBACK_NAME: &str = "lo";
+// Frames sent and not received yet. Sending fails once it's reached, like
+// a card whose ring is full.
+const MAX_QUEUED_FRAMES: usize = 32;
+
+#[derive(Default)]
+pub struct Loopback {
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl NetDeviceOps for Loopback {
+    fn mac_address(&self) -> [u8; 6] {
+        [0; 6]
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+
+    fn can_transmit(&self) -> bool {
+        self.frames.len() < MAX_QUEUED_FRAMES
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if !self.can_transmit() {
+            return Err(code::EAGAIN);
+        }
+        self.frames.push_back(frame.to_vec());
+        Ok(())
+    }
+
+    fn can_receive(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let frame = self.frames.pop_front().ok_or(code::EAGAIN)?;
+        let dst = buf.get_mut(..frame.len()).ok_or(code::EOVERFLOW)?;
+        dst.copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+}
+
+crate::driver_init!(LOOPBACK_NAME, InitLevel::Device, init);
+
+fn init() -> Result<(), Error> {
+    super::register_as(LOOPBACK_NAME.into(), Box::new(Loopback::default()));
+    Ok(())
+}
+
+/// The loopback card, once drivers are initialized.
+pub fn lo() -> Option<Arc<Nic>> {
+    super::nics().into_iter().find(|nic| nic.is_loopback())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::net::{ETHERNET_HEADER_SIZE, ETHERNET_MTU};
+    use alloc::vec;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_loopback() {
+        let lo = lo().unwrap();
+        assert_eq!(lo.name(), LOOPBACK_NAME);
+
+        let mut card = Loopback::default();
+        let mut buf = vec![0; ETHERNET_HEADER_SIZE + ETHERNET_MTU];
+        assert_eq!(card.receive(&mut buf), Err(code::EAGAIN));
+        card.transmit(&[1, 2, 3]).unwrap();
+        card.transmit(&[4, 5]).unwrap();
+        assert_eq!(card.receive(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(card.receive(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], &[4, 5]);
+        assert!(!card.can_receive());
 
-/// Use smoltcp::phy::Loopback as inner net device
-/// Add SmoltcpNetInterface wrapper to smoltcp::phy::Loopback device
-impl NetInterface<'_> {
-    pub fn create_loopback_interface() -> Self {
-        let mut inner = smoltcp::phy::Loopback::new(Medium::Ip);
-
-        // Create Device
-        let mut socket_set = SocketSet::new(vec![]);
-
-        // Create interface
-        let mut config = match inner.capabilities().medium {
-            Medium::Ethernet => {
-                Config::new(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]).into())
-            }
-            Medium::Ip => Config::new(smoltcp::wire::HardwareAddress::Ip),
-            Medium::Ieee802154 => todo!(),
-        };
-
-        let mut interface = Interface::new(
-            config,
-            &mut inner,
-            Instant::from_millis(i64::try_from(tick_get_millisecond()).unwrap_or(0)),
-        );
-
-        interface.update_ip_addrs(|ip_addrs| {
-            if ip_addrs
-                .push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)) // local ip
-                .is_err()
-            {
-                log::error!("Add ip v4 addrs to loopback device fail");
-            };
-
-            if ip_addrs
-                .push(IpCidr::new(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1), 128))
-                .is_err()
-            {
-                log::error!("Add ip v6 addrs to loopback device fail");
-            };
-        });
-
-        let device = Rc::new(RefCell::new(NetDevice::Loopback(inner)));
-
-        let interface = Rc::new(RefCell::new(interface));
-        let socket_set = Rc::new(RefCell::new(socket_set));
-
-        NetInterface::new("Loopback".into(), device, interface, socket_set)
+        for _ in 0..MAX_QUEUED_FRAMES {
+            card.transmit(&[0]).unwrap();
+        }
+        assert!(!card.can_transmit());
+        assert_eq!(card.transmit(&[0]), Err(code::EAGAIN));
     }
 }
@@ -12,6 +12,256 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Network interface cards, which send and receive Ethernet frames. A
+//! driver registers its card as a [`NetDeviceOps`], and the network stack
+//! binds to the [`Nic`]s found at boot.
+
 pub mod loopback;
 #[cfg(virtio)]
 pub mod virtio_net_device;
+
+use crate::{
+    error::{code, Error},
+    softirq::{self, Softirq},
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Default MTU of Ethernet.
+pub const ETHERNET_MTU: usize = 1500;
+/// Size of the header of an Ethernet frame, without VLAN tag.
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+
+pub trait NetDeviceOps: Send + Sync {
+    fn mac_address(&self) -> [u8; 6];
+    /// Largest payload of a frame, header excluded.
+    fn mtu(&self) -> usize {
+        ETHERNET_MTU
+    }
+    /// Whether the card receives the frames it sends, instead of a network.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+    /// Whether a frame can be sent without waiting.
+    fn can_transmit(&self) -> bool;
+    /// Sends `frame`, header included. Returns EAGAIN if the card is busy.
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Error>;
+    /// Whether a frame has been received.
+    fn can_receive(&self) -> bool;
+    /// Moves the next frame received to `buf` and returns its size.
+    /// Returns EAGAIN if there is none, and EOVERFLOW if it doesn't fit, in
+    /// which case it's dropped.
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+    /// Enables or disables the interrupt raised when a frame is received.
+    fn set_interrupt(&mut self, _enable: bool) {}
+    /// Acknowledges the interrupt of the card. Returns whether frames may
+    /// have been received.
+    fn ack_interrupt(&mut self) -> bool {
+        false
+    }
+}
+
+/// Called from the network receive softirq once a card has received
+/// frames.
+pub type RxCallback = fn(&Nic);
+
+/// A registered network card, named ethX in the order cards are found, or
+/// [`lo`](loopback::LOOPBACK_NAME).
+pub struct Nic {
+    name: String,
+    ops: SpinLock<Box<dyn NetDeviceOps>>,
+    rx_callback: SpinLock<Option<RxCallback>>,
+    // Frames may have been received since the last receive softirq.
+    rx_pending: AtomicBool,
+}
+
+impl Nic {
+    fn new(name: String, ops: Box<dyn NetDeviceOps>) -> Self {
+        Self {
+            name,
+            ops: SpinLock::new(ops),
+            rx_callback: SpinLock::new(None),
+            rx_pending: AtomicBool::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.ops.irqsave_lock().mac_address()
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.ops.irqsave_lock().mtu()
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.ops.irqsave_lock().is_loopback()
+    }
+
+    pub fn can_transmit(&self) -> bool {
+        self.ops.irqsave_lock().can_transmit()
+    }
+
+    pub fn transmit(&self, frame: &[u8]) -> Result<(), Error> {
+        self.ops.irqsave_lock().transmit(frame)
+    }
+
+    pub fn can_receive(&self) -> bool {
+        self.ops.irqsave_lock().can_receive()
+    }
+
+    pub fn receive(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.ops.irqsave_lock().receive(buf)
+    }
+
+    /// Binds the network stack to the card: `callback` is called once
+    /// frames are received, and the receive interrupt is enabled. A card
+    /// can only be bound once.
+    pub fn bind(&self, callback: RxCallback) -> Result<(), Error> {
+        let mut rx_callback = self.rx_callback.irqsave_lock();
+        if rx_callback.is_some() {
+            return Err(code::EBUSY);
+        }
+        *rx_callback = Some(callback);
+        self.ops.irqsave_lock().set_interrupt(true);
+        Ok(())
+    }
+
+    /// Called by the interrupt handler of the card. Received frames are
+    /// handed to the stack by the network receive softirq.
+    pub fn handle_interrupt(&self) {
+        if !self.ops.irqsave_lock().ack_interrupt() {
+            return;
+        }
+        self.rx_pending.store(true, Ordering::Release);
+        softirq::raise(Softirq::NetRx);
+    }
+
+    fn run_rx_callback(&self) {
+        if !self.rx_pending.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let callback = *self.rx_callback.irqsave_lock();
+        if let Some(callback) = callback {
+            callback(self);
+        }
+    }
+}
+
+static NICS: SpinLock<Vec<Arc<Nic>>> = SpinLock::new(Vec::new());
+
+/// Registers a network card found by a driver, and returns it.
+pub fn register(ops: Box<dyn NetDeviceOps>) -> Arc<Nic> {
+    let mut nics = NICS.irqsave_lock();
+    let eths = nics.iter().filter(|nic| !nic.is_loopback()).count();
+    let nic = Arc::new(Nic::new(format!("eth{}", eths), ops));
+    nics.push(nic.clone());
+    nic
+}
+
+fn register_as(name: String, ops: Box<dyn NetDeviceOps>) -> Arc<Nic> {
+    let nic = Arc::new(Nic::new(name, ops));
+    NICS.irqsave_lock().push(nic.clone());
+    nic
+}
+
+/// The network receive softirq, runs the receive callbacks of the cards
+/// which interrupted.
+pub(crate) fn rx_action() {
+    for nic in NICS.irqsave_lock().iter() {
+        nic.run_rx_callback();
+    }
+}
+
+/// The network cards registered, in the order they were found.
+pub fn nics() -> Vec<Arc<Nic>> {
+    NICS.irqsave_lock().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{collections::VecDeque, vec};
+    use blueos_test_macro::test;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // Sends frames back to itself.
+    #[derive(Default)]
+    struct EchoCard {
+        frames: VecDeque<Vec<u8>>,
+        interrupt: bool,
+    }
+
+    impl NetDeviceOps for EchoCard {
+        fn mac_address(&self) -> [u8; 6] {
+            [0x02, 0, 0, 0, 0, 0x01]
+        }
+
+        fn can_transmit(&self) -> bool {
+            true
+        }
+
+        fn transmit(&mut self, frame: &[u8]) -> Result<(), Error> {
+            self.frames.push_back(frame.to_vec());
+            Ok(())
+        }
+
+        fn can_receive(&self) -> bool {
+            !self.frames.is_empty()
+        }
+
+        fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let frame = self.frames.pop_front().ok_or(code::EAGAIN)?;
+            let dst = buf.get_mut(..frame.len()).ok_or(code::EOVERFLOW)?;
+            dst.copy_from_slice(&frame);
+            Ok(frame.len())
+        }
+
+        fn set_interrupt(&mut self, enable: bool) {
+            self.interrupt = enable;
+        }
+
+        fn ack_interrupt(&mut self) -> bool {
+            self.interrupt && !self.frames.is_empty()
+        }
+    }
+
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_nic() {
+        let nic = Nic::new("test0".into(), Box::new(EchoCard::default()));
+        assert_eq!(nic.mtu(), ETHERNET_MTU);
+        nic.handle_interrupt();
+        nic.transmit(&[1, 2, 3]).unwrap();
+        // Nothing is received before the stack binds.
+        nic.handle_interrupt();
+        assert_eq!(RECEIVED.load(Ordering::Relaxed), 0);
+
+        nic.bind(|_| {
+            RECEIVED.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+        assert_eq!(nic.bind(|_| {}), Err(code::EBUSY));
+        nic.handle_interrupt();
+        // The receive softirq only walks the registered cards.
+        assert!(nic.rx_pending.load(Ordering::Acquire));
+        nic.run_rx_callback();
+        assert_eq!(RECEIVED.load(Ordering::Relaxed), 1);
+        nic.run_rx_callback();
+        assert_eq!(RECEIVED.load(Ordering::Relaxed), 1);
+
+        let mut buf = [0; 2];
+        assert_eq!(nic.receive(&mut buf), Err(code::EOVERFLOW));
+        nic.transmit(&[1, 2, 3]).unwrap();
+        let mut buf = vec![0; ETHERNET_HEADER_SIZE + ETHERNET_MTU];
+        assert_eq!(nic.receive(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert!(!nic.can_receive());
+        assert_eq!(nic.receive(&mut buf), Err(code::EAGAIN));
+    }
+}
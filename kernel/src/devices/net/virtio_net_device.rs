@@ -12,209 +12,91 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::cell::RefCell;
+//! virtio-net cards of the QEMU virt machines.
 
+use super::{NetDeviceOps, ETHERNET_HEADER_SIZE, ETHERNET_MTU};
 use crate::{
-    devices::virtio::{self, VirtioHal},
-    net::net_interface::NetInterface,
-    time::tick_get_millisecond,
+    devices::virtio::VirtioHal,
+    error::{code, Error},
 };
-use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
-use smoltcp::{
-    iface::{Config, Interface, SocketSet},
-    phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken},
-    time::{Duration, Instant},
-    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address},
-};
-use spin::rwlock::RwLock;
+use alloc::boxed::Box;
 use virtio_drivers::{
-    device::net::{RxBuffer, VirtIONet},
-    transport::SomeTransport,
+    device::net::VirtIONet,
+    transport::{InterruptStatus, SomeTransport},
 };
 
-const VIRTIO_NET_BUFFER_SIZE: usize = 65536;
+// Large enough for a frame of the default MTU and its virtio-net header.
+const VIRTIO_NET_BUFFER_SIZE: usize = 2048;
 const VIRTIO_NET_QUEUE_SIZE: usize = 16;
 
-static VIRTIO_NET_DEVICES: RwLock<
-    Vec<VirtIONet<VirtioHal, SomeTransport<'static>, VIRTIO_NET_QUEUE_SIZE>>,
-> = RwLock::new(Vec::new());
 type VirtIONetType = VirtIONet<VirtioHal, SomeTransport<'static>, VIRTIO_NET_QUEUE_SIZE>;
 
 pub fn register_virtio_net_device(transport: SomeTransport<'static>) {
-    let mut guard = VIRTIO_NET_DEVICES.write();
-    guard.push(VirtIONet::new(transport, VIRTIO_NET_BUFFER_SIZE).unwrap());
-}
-
-pub fn with_net_device<F, R>(index: usize, f: F) -> Option<R>
-where
-    F: FnOnce(&mut VirtIONetType) -> R,
-{
-    let mut guard = VIRTIO_NET_DEVICES.write();
-    guard.get_mut(index).map(f)
-}
-
-pub fn net_dev_exist() -> bool {
-    VIRTIO_NET_DEVICES.read().len() > 0
-}
-
-pub struct VirtIONetDevice {
-    net_device_index: usize,
-}
-
-impl VirtIONetDevice {
-    pub fn new(device_index: usize) -> Self {
-        Self {
-            net_device_index: device_index,
+    match VirtIONet::new(transport, VIRTIO_NET_BUFFER_SIZE) {
+        Ok(net) => {
+            let nic = super::register(Box::new(VirtioNic(net)));
+            log::info!("{}: virtio-net, MAC {:02x?}", nic.name(), nic.mac_address());
         }
+        Err(e) => log::warn!("Failed to initialize virtio-net device: {:?}", e),
     }
 }
 
-impl<'a> NetInterface<'_>
-where
-    'a: 'static,
-{
-    pub fn create_virtio_device() -> Self {
-        let mut inner: VirtIONetDevice = VirtIONetDevice::new(0);
-        // Create Device
-        let mut socket_set = SocketSet::new(vec![]);
-
-        // Get MAC address from VirtIO device
-        let mac_addr = with_net_device(0, |net| net.mac_address())
-            .unwrap_or([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
-
-        // Create interface
-        let mut config = match inner.capabilities().medium {
-            Medium::Ethernet => Config::new(EthernetAddress(mac_addr).into()),
-            Medium::Ip => Config::new(smoltcp::wire::HardwareAddress::Ip),
-            Medium::Ieee802154 => todo!(),
-        };
-
-        let mut interface = Interface::new(
-            config,
-            &mut inner,
-            Instant::from_millis(i64::try_from(tick_get_millisecond()).unwrap_or(0)),
-        );
-
-        // Configure static guest IP (QEMU user networking)
-        interface.update_ip_addrs(|ip_addrs| {
-            // TODO config static ip by kconfig
-            if ip_addrs
-                .push(IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24))
-                .is_err()
-            {
-                log::error!("Add ip addrs to virtio net device fail");
-            }
-        });
-
-        // Set gateway to reach host
-        // In QEMU user networking, the ipv4 gateway is 10.0.2.2
-        if interface
-            .routes_mut()
-            .add_default_ipv4_route(Ipv4Address::new(10, 0, 2, 2))
-            .is_err()
-        {
-            {
-                log::error!("Add default ipv4 route to virtio net device fail");
-            }
-        }
-
-        let device = Rc::new(RefCell::new(
-            crate::net::net_interface::NetDevice::VirtioNetDevice(inner),
-        ));
+struct VirtioNic(VirtIONetType);
 
-        let interface = Rc::new(RefCell::new(interface));
-        let socket_set = Rc::new(RefCell::new(socket_set));
-        NetInterface::new("VirtIONet".into(), device, interface, socket_set)
+impl NetDeviceOps for VirtioNic {
+    fn mac_address(&self) -> [u8; 6] {
+        self.0.mac_address()
     }
-}
-
-impl Device for VirtIONetDevice {
-    type RxToken<'a>
-        = VirtIONetRxToken
-    where
-        Self: 'a;
-    type TxToken<'a>
-        = VirtIONetTxToken
-    where
-        Self: 'a;
 
-    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        with_net_device(self.net_device_index, |net| {
-            if net.can_recv() {
-                if let Ok(rx_buf) = net.receive() {
-                    return Some((
-                        VirtIONetRxToken {
-                            device_index: self.net_device_index,
-                            buffer: rx_buf,
-                        },
-                        VirtIONetTxToken {
-                            device_index: self.net_device_index,
-                        },
-                    ));
-                }
-            }
-            None
-        })
-        .flatten()
+    fn can_transmit(&self) -> bool {
+        self.0.can_send()
     }
 
-    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
-        with_net_device(self.net_device_index, |net| {
-            if net.can_send() {
-                Some(VirtIONetTxToken {
-                    device_index: self.net_device_index,
-                })
-            } else {
-                None
-            }
-        })
-        .flatten()
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if frame.len() > ETHERNET_HEADER_SIZE + ETHERNET_MTU {
+            return Err(code::EINVAL);
+        }
+        if !self.0.can_send() {
+            return Err(code::EAGAIN);
+        }
+        let mut tx_buf = self.0.new_tx_buffer(frame.len());
+        tx_buf.packet_mut().copy_from_slice(frame);
+        self.0.send(tx_buf).map_err(|_| code::EIO)
     }
 
-    fn capabilities(&self) -> DeviceCapabilities {
-        let mut caps = DeviceCapabilities::default();
-        caps.max_transmission_unit = 1500;
-        caps.max_burst_size = Some(1);
-        caps.medium = Medium::Ethernet;
-        caps
+    fn can_receive(&self) -> bool {
+        self.0.can_recv()
     }
-}
-
-pub struct VirtIONetRxToken {
-    device_index: usize,
-    buffer: RxBuffer,
-}
 
-impl RxToken for VirtIONetRxToken {
-    fn consume<R, F>(self, f: F) -> R
-    where
-        F: FnOnce(&[u8]) -> R,
-    {
-        let packet = self.buffer.packet();
-
-        let result = f(packet);
-
-        // Recycle rx buffer to ensure virtqueue has space for new packets.
-        with_net_device(self.device_index, |net| net.recycle_rx_buffer(self.buffer));
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if !self.0.can_recv() {
+            return Err(code::EAGAIN);
+        }
+        let rx_buf = self.0.receive().map_err(|_| code::EIO)?;
+        let packet = rx_buf.packet();
+        let result = match buf.get_mut(..packet.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(packet);
+                Ok(packet.len())
+            }
+            None => Err(code::EOVERFLOW),
+        };
+        // The buffer goes back to the queue, for the next frames.
+        self.0.recycle_rx_buffer(rx_buf).map_err(|_| code::EIO)?;
         result
     }
-}
 
-pub struct VirtIONetTxToken {
-    device_index: usize,
-}
+    fn set_interrupt(&mut self, enable: bool) {
+        if enable {
+            self.0.enable_interrupts();
+        } else {
+            self.0.disable_interrupts();
+        }
+    }
 
-impl TxToken for VirtIONetTxToken {
-    fn consume<R, F>(self, len: usize, f: F) -> R
-    where
-        F: FnOnce(&mut [u8]) -> R,
-    {
-        with_net_device(self.device_index, |net| {
-            let mut tx_buf = net.new_tx_buffer(len);
-            let result = f(tx_buf.packet_mut());
-            let _ = net.send(tx_buf);
-            result
-        })
-        .expect("Found no virtio net device!")
+    fn ack_interrupt(&mut self) -> bool {
+        self.0
+            .ack_interrupt()
+            .contains(InterruptStatus::QUEUE_INTERRUPT)
     }
 }
@@ -12,14 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::devices::{Device, DeviceClass, DeviceId, DeviceManager};
+use crate::{
+    devices::{devno::MEM_MAJOR, driver::InitLevel, Device, DeviceClass, DeviceId, DeviceManager},
+    error::Error,
+};
 use alloc::{string::String, sync::Arc};
-use embedded_io::ErrorKind;
 
 pub struct Null;
 
+crate::driver_init!("null", InitLevel::Device, init);
+
+fn init() -> Result<(), Error> {
+    Null::register()
+}
+
 impl Null {
-    pub fn register() -> Result<(), ErrorKind> {
+    pub fn register() -> Result<(), Error> {
         let null_dev = Arc::new(Null);
         DeviceManager::get().register_device(String::from("null"), null_dev)
     }
@@ -34,15 +42,15 @@ impl Device for Null {
         DeviceClass::Char
     }
     fn id(&self) -> DeviceId {
-        DeviceId::new(1, 3)
+        DeviceId::new(MEM_MAJOR, 3)
     }
 
-    fn read(&self, _pos: u64, _buf: &mut [u8], _is_blocking: bool) -> Result<usize, ErrorKind> {
+    fn read(&self, _pos: u64, _buf: &mut [u8], _is_blocking: bool) -> Result<usize, Error> {
         // Always return EOF (0 bytes read)
         Ok(0)
     }
 
-    fn write(&self, _pos: u64, buf: &[u8], _is_blocking: bool) -> Result<usize, ErrorKind> {
+    fn write(&self, _pos: u64, buf: &[u8], _is_blocking: bool) -> Result<usize, Error> {
         // Always succeed, but discard the data
         Ok(buf.len())
     }
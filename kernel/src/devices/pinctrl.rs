@@ -0,0 +1,335 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pin multiplexing and configuration.
+//!
+//! A pin controller describes its pins as named groups, and the functions
+//! it can route to them, like "uart0" on the group of pins wired to the
+//! UART. Boards register, for each device, the states its pins can be in,
+//! "default" and "sleep" usually, each a list of groups with the function
+//! and the electrical configuration to give them. Drivers then select a
+//! state by name at probe time.
+//!
+//! Selecting a state claims its pins for the device, so that two drivers
+//! sharing pins fail with EBUSY instead of silently undoing each other's
+//! setup. [`release`] gives them back.
+
+use crate::{
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{collections::BTreeMap, sync::Arc};
+use spin::RwLock as SpinRwLock;
+
+pub const STATE_DEFAULT: &str = "default";
+pub const STATE_SLEEP: &str = "sleep";
+
+/// Pins which are muxed together.
+pub struct PinGroup {
+    pub name: &'static str,
+    pub pins: &'static [u32],
+}
+
+/// A function of the controller and the groups it can be routed to.
+pub struct PinFunction {
+    pub name: &'static str,
+    pub groups: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bias {
+    Disable,
+    PullUp,
+    PullDown,
+}
+
+/// Electrical configuration of a pin. Settings left to `None` are not
+/// changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinConfig {
+    pub bias: Option<Bias>,
+    pub input_enable: Option<bool>,
+    pub open_drain: Option<bool>,
+    pub drive_strength_ma: Option<u8>,
+}
+
+impl PinConfig {
+    pub const NONE: Self = Self {
+        bias: None,
+        input_enable: None,
+        open_drain: None,
+        drive_strength_ma: None,
+    };
+}
+
+pub trait PinctrlOps: Send + Sync {
+    fn groups(&self) -> &'static [PinGroup];
+    fn functions(&self) -> &'static [PinFunction];
+
+    /// Routes function `function` to the pins of group `group`, both
+    /// indices in the tables above. The function is known to support the
+    /// group.
+    fn set_mux(&self, function: usize, group: usize) -> Result<(), Error>;
+
+    /// Configures `pin`. Controllers whose pins have no settings fail with
+    /// ENOTSUP.
+    fn set_config(&self, pin: u32, config: &PinConfig) -> Result<(), Error> {
+        let _ = (pin, config);
+        Err(code::ENOTSUP)
+    }
+}
+
+/// Puts the pins of `group` in `function`, configured as `config`.
+pub struct PinSetting {
+    pub group: &'static str,
+    pub function: &'static str,
+    pub config: PinConfig,
+}
+
+pub struct PinState {
+    pub name: &'static str,
+    pub settings: &'static [PinSetting],
+}
+
+struct Map {
+    controller: Arc<dyn PinctrlOps>,
+    states: &'static [PinState],
+}
+
+static MAPS: SpinRwLock<BTreeMap<&'static str, Map>> = SpinRwLock::new(BTreeMap::new());
+// The device each claimed pin belongs to, by controller and pin.
+static OWNERS: SpinLock<BTreeMap<(usize, u32), &'static str>> = SpinLock::new(BTreeMap::new());
+
+fn controller_key(controller: &Arc<dyn PinctrlOps>) -> usize {
+    Arc::as_ptr(controller) as *const () as usize
+}
+
+// Returns the indices of the group and the function of `setting`.
+fn resolve(controller: &dyn PinctrlOps, setting: &PinSetting) -> Result<(usize, usize), Error> {
+    let group = controller
+        .groups()
+        .iter()
+        .position(|group| group.name == setting.group)
+        .ok_or(code::EINVAL)?;
+    let function = controller
+        .functions()
+        .iter()
+        .position(|function| {
+            function.name == setting.function && function.groups.contains(&setting.group)
+        })
+        .ok_or(code::EINVAL)?;
+    Ok((group, function))
+}
+
+/// Registers the pin states of `device`, whose pins are on `controller`.
+/// Fails with EINVAL if a state uses a group or a function the controller
+/// doesn't have, or a function on a group it can't be routed to.
+pub fn register(
+    device: &'static str,
+    controller: Arc<dyn PinctrlOps>,
+    states: &'static [PinState],
+) -> Result<(), Error> {
+    for state in states {
+        for setting in state.settings {
+            resolve(&*controller, setting)?;
+        }
+    }
+    let mut maps = MAPS.write();
+    if maps.contains_key(device) {
+        return Err(code::EEXIST);
+    }
+    maps.insert(device, Map { controller, states });
+    Ok(())
+}
+
+/// Puts the pins of `device` in the state `state`, claiming them. Fails
+/// with ENOENT if there's no such state, and with EBUSY if another device
+/// has one of the pins.
+pub fn select_state(device: &str, state: &str) -> Result<(), Error> {
+    let maps = MAPS.read();
+    let (&device, map) = maps.get_key_value(device).ok_or(code::ENOENT)?;
+    let state = map
+        .states
+        .iter()
+        .find(|s| s.name == state)
+        .ok_or(code::ENOENT)?;
+    let controller = &*map.controller;
+    let key = controller_key(&map.controller);
+    let pins = || {
+        state
+            .settings
+            .iter()
+            .flat_map(|setting| resolve(controller, setting))
+            .flat_map(|(group, _)| controller.groups()[group].pins)
+    };
+    {
+        let mut owners = OWNERS.irqsave_lock();
+        if pins().any(|pin| {
+            owners
+                .get(&(key, *pin))
+                .is_some_and(|owner| *owner != device)
+        }) {
+            return Err(code::EBUSY);
+        }
+        for pin in pins() {
+            owners.insert((key, *pin), device);
+        }
+    }
+    for setting in state.settings {
+        let (group, function) = resolve(controller, setting)?;
+        controller.set_mux(function, group)?;
+        if setting.config != PinConfig::NONE {
+            for pin in controller.groups()[group].pins {
+                controller.set_config(*pin, &setting.config)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Puts the pins of `device` in their default state.
+pub fn select_default(device: &str) -> Result<(), Error> {
+    select_state(device, STATE_DEFAULT)
+}
+
+/// Gives back the pins claimed by `device`. They are left as they are.
+pub fn release(device: &str) {
+    OWNERS.irqsave_lock().retain(|_, owner| *owner != device);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use blueos_test_macro::test;
+
+    struct Controller {
+        muxed: SpinLock<Vec<(usize, usize)>>,
+        configured: SpinLock<Vec<u32>>,
+    }
+
+    impl PinctrlOps for Controller {
+        fn groups(&self) -> &'static [PinGroup] {
+            &[
+                PinGroup {
+                    name: "a_pins",
+                    pins: &[0, 1],
+                },
+                PinGroup {
+                    name: "b_pins",
+                    pins: &[1, 2],
+                },
+            ]
+        }
+
+        fn functions(&self) -> &'static [PinFunction] {
+            &[
+                PinFunction {
+                    name: "gpio",
+                    groups: &["a_pins", "b_pins"],
+                },
+                PinFunction {
+                    name: "uart",
+                    groups: &["a_pins"],
+                },
+            ]
+        }
+
+        fn set_mux(&self, function: usize, group: usize) -> Result<(), Error> {
+            self.muxed.irqsave_lock().push((function, group));
+            Ok(())
+        }
+
+        fn set_config(&self, pin: u32, _config: &PinConfig) -> Result<(), Error> {
+            self.configured.irqsave_lock().push(pin);
+            Ok(())
+        }
+    }
+
+    static A_STATES: [PinState; 2] = [
+        PinState {
+            name: STATE_DEFAULT,
+            settings: &[PinSetting {
+                group: "a_pins",
+                function: "uart",
+                config: PinConfig {
+                    bias: Some(Bias::PullUp),
+                    ..PinConfig::NONE
+                },
+            }],
+        },
+        PinState {
+            name: STATE_SLEEP,
+            settings: &[PinSetting {
+                group: "a_pins",
+                function: "gpio",
+                config: PinConfig::NONE,
+            }],
+        },
+    ];
+    static B_STATES: [PinState; 1] = [PinState {
+        name: STATE_DEFAULT,
+        settings: &[PinSetting {
+            group: "b_pins",
+            function: "gpio",
+            config: PinConfig::NONE,
+        }],
+    }];
+    static BAD_STATES: [PinState; 1] = [PinState {
+        name: STATE_DEFAULT,
+        settings: &[PinSetting {
+            group: "b_pins",
+            function: "uart",
+            config: PinConfig::NONE,
+        }],
+    }];
+
+    #[test]
+    fn test_pinctrl_states() {
+        let controller = Arc::new(Controller {
+            muxed: SpinLock::new(Vec::new()),
+            configured: SpinLock::new(Vec::new()),
+        });
+        register("test_pinctrl_a", controller.clone(), &A_STATES).unwrap();
+        register("test_pinctrl_b", controller.clone(), &B_STATES).unwrap();
+        assert_eq!(
+            register("test_pinctrl_bad", controller.clone(), &BAD_STATES),
+            Err(code::EINVAL)
+        );
+        assert_eq!(
+            register("test_pinctrl_a", controller.clone(), &A_STATES),
+            Err(code::EEXIST)
+        );
+
+        select_default("test_pinctrl_a").unwrap();
+        assert_eq!(*controller.muxed.irqsave_lock(), [(1, 0)]);
+        assert_eq!(*controller.configured.irqsave_lock(), [0, 1]);
+        select_state("test_pinctrl_a", STATE_SLEEP).unwrap();
+        assert_eq!(*controller.muxed.irqsave_lock(), [(1, 0), (0, 0)]);
+        assert_eq!(controller.configured.irqsave_lock().len(), 2);
+        assert_eq!(select_state("test_pinctrl_a", "idle"), Err(code::ENOENT));
+
+        // Pin 1 is shared.
+        assert_eq!(select_default("test_pinctrl_b"), Err(code::EBUSY));
+        assert_eq!(controller.muxed.irqsave_lock().len(), 2);
+        release("test_pinctrl_a");
+        select_default("test_pinctrl_b").unwrap();
+        let key = controller_key(&(controller.clone() as Arc<dyn PinctrlOps>));
+        assert_eq!(
+            OWNERS.irqsave_lock().get(&(key, 2)),
+            Some(&"test_pinctrl_b")
+        );
+        release("test_pinctrl_b");
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Power domains with runtime power management.
+//!
+//! A power domain is a set of peripherals which are powered, and usually
+//! clocked, together. Boards register their domains with the clocks to gate
+//! along, and attach the devices living in them. Drivers take a reference
+//! on their domain while they use the hardware, with [`PowerDomain::get`] or
+//! [`PowerDomain::hold`], and drop it when they are done.
+//!
+//! A domain is powered while it has users, and its parent while it is
+//! powered. Once the last user leaves, the attached devices are sent
+//! [`DeviceRequest::Suspend`], the clocks are disabled and the domain is
+//! powered off. Powering it back on does the reverse, ending with
+//! [`DeviceRequest::Resume`]. Devices without suspend hooks fail these
+//! requests with ENOSYS, which is fine. A device failing to suspend keeps
+//! the domain powered until its users drop to zero again.
+
+use super::{clk::Clock, Device, DeviceRequest};
+use crate::{
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use log::warn;
+use spin::RwLock as SpinRwLock;
+
+/// Hardware operations of a power domain.
+///
+/// Operations, like the suspend hooks of the attached devices, run with
+/// the domains locked and interrupts disabled, so they must not sleep.
+pub trait PowerDomainOps: Send + Sync {
+    /// Powers the domain on. The parent is already powered.
+    fn power_on(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Powers the domain off.
+    fn power_off(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+struct DomainState {
+    users: usize,
+    powered: bool,
+    clocks: Vec<Arc<Clock>>,
+    devices: Vec<Arc<dyn Device>>,
+}
+
+pub struct PowerDomain {
+    name: &'static str,
+    parent: Option<Arc<PowerDomain>>,
+    ops: Box<dyn PowerDomainOps>,
+    state: SpinLock<DomainState>,
+}
+
+// Serializes power transitions, so that a domain and its parents change
+// together.
+static TREE: SpinLock<()> = SpinLock::new(());
+static DOMAINS: SpinRwLock<BTreeMap<&'static str, Arc<PowerDomain>>> =
+    SpinRwLock::new(BTreeMap::new());
+
+fn send(device: &dyn Device, request: DeviceRequest) -> Result<(), Error> {
+    match device.ioctl(request as u32, 0) {
+        Err(e) if e == code::ENOSYS => Ok(()),
+        result => result,
+    }
+}
+
+impl PowerDomain {
+    /// Creates a domain, which is assumed to be off.
+    pub fn new(
+        name: &'static str,
+        parent: Option<Arc<PowerDomain>>,
+        ops: Box<dyn PowerDomainOps>,
+    ) -> Self {
+        Self {
+            name,
+            parent,
+            ops,
+            state: SpinLock::new(DomainState {
+                users: 0,
+                powered: false,
+                clocks: Vec::new(),
+                devices: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn parent(&self) -> Option<&Arc<PowerDomain>> {
+        self.parent.as_ref()
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.state.irqsave_lock().powered
+    }
+
+    pub fn users(&self) -> usize {
+        self.state.irqsave_lock().users
+    }
+
+    /// Adds a clock enabled while the domain is powered.
+    pub fn add_clock(&self, clock: Arc<Clock>) -> Result<(), Error> {
+        let _tree = TREE.irqsave_lock();
+        let mut state = self.state.irqsave_lock();
+        if state.powered {
+            clock.enable()?;
+        }
+        state.clocks.push(clock);
+        Ok(())
+    }
+
+    /// Attaches `device`, which is then suspended and resumed along with
+    /// the domain.
+    pub fn attach(&self, device: Arc<dyn Device>) {
+        let _tree = TREE.irqsave_lock();
+        self.state.irqsave_lock().devices.push(device);
+    }
+
+    /// Takes a reference on the domain, powering it and its parents on if
+    /// needed.
+    pub fn get(&self) -> Result<(), Error> {
+        let _tree = TREE.irqsave_lock();
+        self.get_locked()
+    }
+
+    /// Drops a reference on the domain, powering it off once no user is
+    /// left. Unbalanced calls are ignored.
+    pub fn put(&self) {
+        let _tree = TREE.irqsave_lock();
+        self.put_locked();
+    }
+
+    /// Takes a reference on the domain, dropped with the returned guard.
+    pub fn hold(this: &Arc<Self>) -> Result<PowerRef, Error> {
+        this.get()?;
+        Ok(PowerRef(this.clone()))
+    }
+
+    fn get_locked(&self) -> Result<(), Error> {
+        if !self.is_powered() {
+            self.power_on_locked()?;
+        }
+        self.state.irqsave_lock().users += 1;
+        Ok(())
+    }
+
+    fn put_locked(&self) {
+        let mut state = self.state.irqsave_lock();
+        match state.users {
+            0 => (),
+            1 => {
+                state.users = 0;
+                drop(state);
+                if let Err(e) = self.power_off_locked() {
+                    warn!("power domain {} stays on: {:?}", self.name, e);
+                }
+            }
+            _ => state.users -= 1,
+        }
+    }
+
+    fn power_on_locked(&self) -> Result<(), Error> {
+        if let Some(parent) = &self.parent {
+            parent.get_locked()?;
+        }
+        let (clocks, devices) = {
+            let state = self.state.irqsave_lock();
+            (state.clocks.clone(), state.devices.clone())
+        };
+        let result = self.ops.power_on().and_then(|()| {
+            for (i, clock) in clocks.iter().enumerate() {
+                if let Err(e) = clock.enable() {
+                    clocks[..i].iter().for_each(|clock| clock.disable());
+                    let _ = self.ops.power_off();
+                    return Err(e);
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            if let Some(parent) = &self.parent {
+                parent.put_locked();
+            }
+            return Err(e);
+        }
+        self.state.irqsave_lock().powered = true;
+        for device in &devices {
+            if let Err(e) = send(&**device, DeviceRequest::Resume) {
+                warn!("{} failed to resume: {:?}", device.name(), e);
+            }
+        }
+        Ok(())
+    }
+
+    fn power_off_locked(&self) -> Result<(), Error> {
+        let (clocks, devices) = {
+            let state = self.state.irqsave_lock();
+            (state.clocks.clone(), state.devices.clone())
+        };
+        for (i, device) in devices.iter().enumerate() {
+            if let Err(e) = send(&**device, DeviceRequest::Suspend) {
+                for device in &devices[..i] {
+                    let _ = send(&**device, DeviceRequest::Resume);
+                }
+                return Err(e);
+            }
+        }
+        clocks.iter().rev().for_each(|clock| clock.disable());
+        if let Err(e) = self.ops.power_off() {
+            // Still clocked, so back to where it was.
+            for clock in &clocks {
+                let _ = clock.enable();
+            }
+            for device in &devices {
+                let _ = send(&**device, DeviceRequest::Resume);
+            }
+            return Err(e);
+        }
+        self.state.irqsave_lock().powered = false;
+        if let Some(parent) = &self.parent {
+            parent.put_locked();
+        }
+        Ok(())
+    }
+}
+
+/// A reference on a power domain, dropped with the guard.
+pub struct PowerRef(Arc<PowerDomain>);
+
+impl PowerRef {
+    pub fn domain(&self) -> &Arc<PowerDomain> {
+        &self.0
+    }
+}
+
+impl Drop for PowerRef {
+    fn drop(&mut self) {
+        self.0.put();
+    }
+}
+
+/// Makes `domain` available to drivers under its name.
+pub fn register(domain: Arc<PowerDomain>) -> Result<(), Error> {
+    let mut domains = DOMAINS.write();
+    if domains.contains_key(domain.name) {
+        return Err(code::EEXIST);
+    }
+    domains.insert(domain.name, domain);
+    Ok(())
+}
+
+pub fn get(name: &str) -> Result<Arc<PowerDomain>, Error> {
+    DOMAINS.read().get(name).cloned().ok_or(code::ENOENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{clk::ClockOps, DeviceClass, DeviceId};
+    use alloc::string::String;
+    use blueos_test_macro::test;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    struct Switch(&'static AtomicUsize);
+
+    impl PowerDomainOps for Switch {
+        fn power_on(&self) -> Result<(), Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn power_off(&self) -> Result<(), Error> {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    struct Gate(&'static AtomicUsize);
+
+    impl ClockOps for Gate {
+        fn enable(&self) -> Result<(), Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn disable(&self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    #[derive(Default)]
+    struct Peripheral {
+        suspended: AtomicBool,
+        busy: AtomicBool,
+    }
+
+    impl Device for Peripheral {
+        fn name(&self) -> String {
+            String::from("test_pd_dev")
+        }
+
+        fn class(&self) -> DeviceClass {
+            DeviceClass::Misc
+        }
+
+        fn id(&self) -> DeviceId {
+            DeviceId::new(0, 0)
+        }
+
+        fn read(&self, _pos: u64, _buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+            Err(code::ENOSYS)
+        }
+
+        fn write(&self, _pos: u64, _buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+            Err(code::ENOSYS)
+        }
+
+        fn ioctl(&self, request: u32, _arg: usize) -> Result<(), Error> {
+            match DeviceRequest::from(request) {
+                DeviceRequest::Suspend if self.busy.load(Ordering::Relaxed) => Err(code::EBUSY),
+                DeviceRequest::Suspend => {
+                    self.suspended.store(true, Ordering::Relaxed);
+                    Ok(())
+                }
+                DeviceRequest::Resume => {
+                    self.suspended.store(false, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(code::ENOSYS),
+            }
+        }
+    }
+
+    #[test]
+    fn test_power_domains() {
+        static ROOT_ON: AtomicUsize = AtomicUsize::new(0);
+        static LEAF_ON: AtomicUsize = AtomicUsize::new(0);
+        static CLOCK_ON: AtomicUsize = AtomicUsize::new(0);
+        let root = Arc::new(PowerDomain::new(
+            "test_pd_root",
+            None,
+            Box::new(Switch(&ROOT_ON)),
+        ));
+        let leaf = Arc::new(PowerDomain::new(
+            "test_pd_leaf",
+            Some(root.clone()),
+            Box::new(Switch(&LEAF_ON)),
+        ));
+        let clock = Arc::new(Clock::new("test_pd_clk", None, Box::new(Gate(&CLOCK_ON))));
+        leaf.add_clock(clock).unwrap();
+        let dev = Arc::new(Peripheral::default());
+        leaf.attach(dev.clone());
+
+        let first = PowerDomain::hold(&leaf).unwrap();
+        leaf.get().unwrap();
+        assert_eq!(leaf.users(), 2);
+        assert_eq!(root.users(), 1);
+        assert_eq!(ROOT_ON.load(Ordering::Relaxed), 1);
+        assert_eq!(LEAF_ON.load(Ordering::Relaxed), 1);
+        assert_eq!(CLOCK_ON.load(Ordering::Relaxed), 1);
+
+        leaf.put();
+        assert!(leaf.is_powered());
+        assert!(!dev.suspended.load(Ordering::Relaxed));
+        drop(first);
+        assert!(!leaf.is_powered());
+        assert!(!root.is_powered());
+        assert!(dev.suspended.load(Ordering::Relaxed));
+        assert_eq!(CLOCK_ON.load(Ordering::Relaxed), 0);
+        assert_eq!(LEAF_ON.load(Ordering::Relaxed), 0);
+        leaf.put();
+        assert_eq!(leaf.users(), 0);
+
+        // A busy device vetoes the power off.
+        leaf.get().unwrap();
+        assert!(!dev.suspended.load(Ordering::Relaxed));
+        dev.busy.store(true, Ordering::Relaxed);
+        leaf.put();
+        assert!(leaf.is_powered());
+        assert_eq!(CLOCK_ON.load(Ordering::Relaxed), 1);
+        dev.busy.store(false, Ordering::Relaxed);
+        leaf.get().unwrap();
+        leaf.put();
+        assert!(!leaf.is_powered());
+        assert!(!root.is_powered());
+
+        register(leaf.clone()).unwrap();
+        assert_eq!(register(leaf).err(), Some(code::EEXIST));
+        assert_eq!(get("test_pd_leaf").unwrap().name(), "test_pd_leaf");
+        assert!(get("test_pd_none").is_err());
+    }
+}
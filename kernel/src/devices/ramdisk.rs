@@ -0,0 +1,84 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A block device in RAM, for the tests of what runs on top of storage.
+
+use super::{Device, DeviceClass, DeviceId};
+use crate::{
+    error::Error,
+    sync::{SpinLock, SpinLockGuard},
+};
+use alloc::{string::String, vec, vec::Vec};
+
+pub(crate) struct RamDisk {
+    data: SpinLock<Vec<u8>>,
+    flash: bool,
+}
+
+impl RamDisk {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data: SpinLock::new(data),
+            flash: false,
+        }
+    }
+
+    /// A disk of `size` erased bytes which behaves like NOR flash: writes
+    /// can only clear bits, and [`Device::erase`] sets them back.
+    pub fn flash(size: usize) -> Self {
+        Self {
+            data: SpinLock::new(vec![0xff; size]),
+            flash: true,
+        }
+    }
+
+    pub fn data(&self) -> SpinLockGuard<'_, Vec<u8>> {
+        self.data.lock()
+    }
+}
+
+impl Device for RamDisk {
+    fn name(&self) -> String {
+        String::from("ramdisk")
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Block
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::new(0, 0)
+    }
+
+    fn read(&self, pos: u64, buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let pos = pos as usize;
+        buf.copy_from_slice(&self.data.lock()[pos..pos + buf.len()]);
+        Ok(buf.len())
+    }
+
+    fn write(&self, pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let pos = pos as usize;
+        let mut data = self.data.lock();
+        for (byte, &new) in data[pos..pos + buf.len()].iter_mut().zip(buf) {
+            *byte = if self.flash { *byte & new } else { new };
+        }
+        Ok(buf.len())
+    }
+
+    fn erase(&self, pos: u64, len: u64) -> Result<(), Error> {
+        let pos = pos as usize;
+        self.data.lock()[pos..pos + len as usize].fill(0xff);
+        Ok(())
+    }
+}
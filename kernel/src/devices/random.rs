@@ -0,0 +1,106 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! /dev/random and /dev/urandom, read from the
+//! [entropy pool](crate::crypto::random). Both return the same bytes, but
+//! /dev/random waits for the pool to be initialized first, or fails with
+//! EAGAIN if opened with O_NONBLOCK. Writes are mixed into the pool without
+//! being credited.
+
+use crate::{
+    crypto::random,
+    devices::{devno::MEM_MAJOR, driver::InitLevel, Device, DeviceClass, DeviceId, DeviceManager},
+    error::Error,
+};
+use alloc::{string::String, sync::Arc};
+
+pub struct Random {
+    // Whether reads wait for the pool to be initialized.
+    blocking: bool,
+}
+
+crate::driver_init!("random", InitLevel::Device, init);
+
+fn init() -> Result<(), Error> {
+    random::init();
+    Random::register()
+}
+
+impl Random {
+    pub const fn random() -> Self {
+        Self { blocking: true }
+    }
+
+    pub const fn urandom() -> Self {
+        Self { blocking: false }
+    }
+
+    pub fn register() -> Result<(), Error> {
+        let manager = DeviceManager::get();
+        manager.register_device(String::from("random"), Arc::new(Self::random()))?;
+        manager.register_device(String::from("urandom"), Arc::new(Self::urandom()))
+    }
+}
+
+impl Device for Random {
+    fn name(&self) -> String {
+        if self.blocking {
+            String::from("random")
+        } else {
+            String::from("urandom")
+        }
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        DeviceId::new(MEM_MAJOR, if self.blocking { 8 } else { 9 })
+    }
+
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        if self.blocking {
+            random::wait_ready(is_nonblocking)?;
+        }
+        random::fill_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        random::add_device_randomness(buf);
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_random_device() {
+        for device in [Random::random(), Random::urandom()] {
+            let mut a = [0u8; 64];
+            let mut b = [0u8; 64];
+            assert_eq!(device.read(0, &mut a, false), Ok(a.len()));
+            assert_eq!(device.read(0, &mut b, false), Ok(b.len()));
+            assert_ne!(a, b);
+            assert_eq!(device.write(0, b"seed", false), Ok(4));
+        }
+        assert!(random::is_ready());
+        assert_eq!(Random::random().id().minor(), 8);
+        assert_eq!(Random::urandom().id().minor(), 9);
+    }
+}
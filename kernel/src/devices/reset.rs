@@ -0,0 +1,146 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reset controllers.
+//!
+//! A reset controller drives the reset lines of a set of peripherals,
+//! identified by a controller specific index. Boards register every line
+//! under a name, and drivers pulse or release the lines they need before
+//! programming their peripheral.
+
+use crate::error::{code, Error};
+use alloc::{collections::BTreeMap, sync::Arc};
+use spin::RwLock as SpinRwLock;
+
+pub trait ResetOps: Send + Sync {
+    /// Holds the peripheral in reset.
+    fn assert(&self, id: usize) -> Result<(), Error>;
+
+    /// Takes the peripheral out of reset, returning once it is usable.
+    fn deassert(&self, id: usize) -> Result<(), Error>;
+
+    /// Returns whether the peripheral is held in reset.
+    fn status(&self, id: usize) -> Result<bool, Error> {
+        let _ = id;
+        Err(code::ENOTSUP)
+    }
+
+    /// Puts the peripheral through a full reset cycle.
+    fn reset(&self, id: usize) -> Result<(), Error> {
+        self.assert(id)?;
+        self.deassert(id)
+    }
+}
+
+pub struct ResetLine {
+    name: &'static str,
+    controller: Arc<dyn ResetOps>,
+    id: usize,
+}
+
+static LINES: SpinRwLock<BTreeMap<&'static str, Arc<ResetLine>>> = SpinRwLock::new(BTreeMap::new());
+
+impl ResetLine {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn assert(&self) -> Result<(), Error> {
+        self.controller.assert(self.id)
+    }
+
+    pub fn deassert(&self) -> Result<(), Error> {
+        self.controller.deassert(self.id)
+    }
+
+    pub fn reset(&self) -> Result<(), Error> {
+        self.controller.reset(self.id)
+    }
+
+    pub fn is_asserted(&self) -> Result<bool, Error> {
+        self.controller.status(self.id)
+    }
+}
+
+/// Registers line `id` of `controller` under `name`.
+pub fn register(
+    name: &'static str,
+    controller: Arc<dyn ResetOps>,
+    id: usize,
+) -> Result<Arc<ResetLine>, Error> {
+    let mut lines = LINES.write();
+    if lines.contains_key(name) {
+        return Err(code::EEXIST);
+    }
+    let line = Arc::new(ResetLine {
+        name,
+        controller,
+        id,
+    });
+    lines.insert(name, line.clone());
+    Ok(line)
+}
+
+pub fn get(name: &str) -> Result<Arc<ResetLine>, Error> {
+    LINES.read().get(name).cloned().ok_or(code::ENOENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Controller {
+        asserted: AtomicUsize,
+        pulses: AtomicUsize,
+    }
+
+    impl ResetOps for Controller {
+        fn assert(&self, id: usize) -> Result<(), Error> {
+            self.asserted.fetch_or(1 << id, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn deassert(&self, id: usize) -> Result<(), Error> {
+            if self.asserted.fetch_and(!(1 << id), Ordering::Relaxed) & (1 << id) != 0 {
+                self.pulses.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+
+        fn status(&self, id: usize) -> Result<bool, Error> {
+            Ok(self.asserted.load(Ordering::Relaxed) & (1 << id) != 0)
+        }
+    }
+
+    #[test]
+    fn test_reset_lines() {
+        let controller = Arc::new(Controller::default());
+        let a = register("test_reset_a", controller.clone(), 0).unwrap();
+        register("test_reset_b", controller.clone(), 3).unwrap();
+        assert!(register("test_reset_b", controller.clone(), 4).is_err());
+
+        let b = get("test_reset_b").unwrap();
+        b.assert().unwrap();
+        assert_eq!(b.is_asserted(), Ok(true));
+        assert_eq!(a.is_asserted(), Ok(false));
+        b.deassert().unwrap();
+        assert_eq!(b.is_asserted(), Ok(false));
+        a.reset().unwrap();
+        assert_eq!(controller.pulses.load(Ordering::Relaxed), 2);
+        assert_eq!(get("test_reset_c").err(), Some(code::ENOENT));
+    }
+}
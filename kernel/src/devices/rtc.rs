@@ -0,0 +1,170 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Real-time clock devices.
+//!
+//! RTC drivers register a char device and handle the Linux compatible
+//! [`RTC_RD_TIME`] and [`RTC_SET_TIME`] requests. The kernel keeps the wall
+//! clock in sync with the device named [`RTC_DEVICE_NAME`].
+
+use super::Device;
+use crate::error::{code, Error};
+use core::ffi::c_int;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+pub const RTC_DEVICE_NAME: &str = "rtc0";
+
+crate::ioctl_read!(
+    /// Reads the time of the RTC.
+    pub RTC_RD_TIME, b'p', 0x09, RtcTime
+);
+crate::ioctl_write!(
+    /// Sets the time of the RTC.
+    pub RTC_SET_TIME, b'p', 0x0a, RtcTime
+);
+
+const SECS_PER_DAY: u64 = 86400;
+// Days from 0000-03-01 to 1970-01-01.
+const UNIX_EPOCH_DAYS: i64 = 719468;
+
+/// Broken-down UTC time, with the same layout as Linux's `struct rtc_time`.
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct RtcTime {
+    pub tm_sec: c_int,
+    pub tm_min: c_int,
+    pub tm_hour: c_int,
+    pub tm_mday: c_int,
+    /// Months since January, 0-11.
+    pub tm_mon: c_int,
+    /// Years since 1900.
+    pub tm_year: c_int,
+    pub tm_wday: c_int,
+    pub tm_yday: c_int,
+    pub tm_isdst: c_int,
+}
+
+// Proleptic Gregorian calendar conversions, with years starting in March
+// so that the leap day is the last day of the year.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - UNIX_EPOCH_DAYS
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + UNIX_EPOCH_DAYS;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+impl RtcTime {
+    /// Converts seconds since the Unix epoch.
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / SECS_PER_DAY) as i64;
+        let secs = secs % SECS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            tm_sec: (secs % 60) as c_int,
+            tm_min: (secs / 60 % 60) as c_int,
+            tm_hour: (secs / 3600) as c_int,
+            tm_mday: day as c_int,
+            tm_mon: (month - 1) as c_int,
+            tm_year: (year - 1900) as c_int,
+            // 1970-01-01 was a Thursday.
+            tm_wday: ((days + 4) % 7) as c_int,
+            tm_yday: (days - days_from_civil(year, 1, 1)) as c_int,
+            tm_isdst: 0,
+        }
+    }
+
+    /// Converts to seconds since the Unix epoch, None if the time is invalid
+    /// or before the epoch. The week and year days are ignored.
+    pub fn to_unix(&self) -> Option<u64> {
+        if !(0..60).contains(&self.tm_sec)
+            || !(0..60).contains(&self.tm_min)
+            || !(0..24).contains(&self.tm_hour)
+            || !(1..=31).contains(&self.tm_mday)
+            || !(0..12).contains(&self.tm_mon)
+        {
+            return None;
+        }
+        let days = days_from_civil(
+            self.tm_year as i64 + 1900,
+            self.tm_mon as i64 + 1,
+            self.tm_mday as i64,
+        );
+        let secs = days * SECS_PER_DAY as i64
+            + self.tm_hour as i64 * 3600
+            + self.tm_min as i64 * 60
+            + self.tm_sec as i64;
+        u64::try_from(secs).ok()
+    }
+}
+
+/// Reads the time of `dev` in seconds since the Unix epoch.
+pub fn read_time(dev: &dyn Device) -> Result<u64, Error> {
+    let mut tm = RtcTime::default();
+    dev.ioctl(RTC_RD_TIME.request(), &mut tm as *mut _ as usize)?;
+    tm.to_unix().ok_or(code::EIO)
+}
+
+pub fn set_time(dev: &dyn Device, secs: u64) -> Result<(), Error> {
+    let mut tm = RtcTime::from_unix(secs);
+    dev.ioctl(RTC_SET_TIME.request(), &mut tm as *mut _ as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_rtc_time_conversion() {
+        let epoch = RtcTime::from_unix(0);
+        assert_eq!(
+            (epoch.tm_year, epoch.tm_mon, epoch.tm_mday, epoch.tm_wday),
+            (70, 0, 1, 4)
+        );
+        // 2024-02-29 12:34:56, a Thursday and the 60th day of the year.
+        let leap = RtcTime::from_unix(1709210096);
+        assert_eq!(
+            (leap.tm_year, leap.tm_mon, leap.tm_mday, leap.tm_yday),
+            (124, 1, 29, 59)
+        );
+        assert_eq!((leap.tm_hour, leap.tm_min, leap.tm_sec), (12, 34, 56));
+        assert_eq!(leap.tm_wday, 4);
+        assert_eq!(leap.to_unix(), Some(1709210096));
+
+        let before_epoch = RtcTime {
+            tm_year: 69,
+            tm_mday: 1,
+            ..Default::default()
+        };
+        assert_eq!(before_epoch.to_unix(), None);
+        assert_eq!(RtcTime::default().to_unix(), None);
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SEGGER RTT, a pair of rings in RAM which debug probes find through the
+//! `_SEGGER_RTT` control block and access from the debug port while the
+//! target runs, so no UART is needed.
+//!
+//! The up channel is the "rtt" console sink. Together with the down
+//! channel, it's also a char device, /dev/rtt0, or /dev/console itself
+//! when the board has no other console, which gives a shell over SWD.
+//! Nothing waits for the probe unless it asked for it by setting the up
+//! channel to block when full: output which doesn't fit is dropped.
+//! Probes don't signal anything, so blocking reads and writes poll every
+//! [`POLL_INTERVAL_MS`].
+
+use super::{
+    console::{self, EarlyConsole},
+    devno, Device, DeviceClass, DeviceId, DeviceManager,
+};
+use crate::{
+    error::{code, Error},
+    irq, scheduler,
+    sync::SpinLock,
+    thread,
+};
+use alloc::{string::String, sync::Arc};
+use blueos_kconfig::TICKS_PER_SECOND;
+use core::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+const RTT_UP_BUF_SIZE: usize = 1024;
+const RTT_DOWN_BUF_SIZE: usize = 64;
+const RTT_ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+const POLL_INTERVAL_MS: usize = 10;
+// The low bits of the flags, which the probe may change.
+const MODE_MASK: u32 = 3;
+// Drop what doesn't fit rather than block.
+const MODE_NO_BLOCK_SKIP: u32 = 0;
+const MODE_BLOCK_IF_FULL: u32 = 2;
+
+#[repr(C)]
+struct Channel {
+    name: *const u8,
+    buf: *mut u8,
+    size: u32,
+    write: AtomicU32,
+    read: AtomicU32,
+    flags: AtomicU32,
+}
+
+impl Channel {
+    const fn new() -> Self {
+        Self {
+            name: ptr::null(),
+            buf: ptr::null_mut(),
+            size: 0,
+            write: AtomicU32::new(0),
+            read: AtomicU32::new(0),
+            flags: AtomicU32::new(MODE_NO_BLOCK_SKIP),
+        }
+    }
+}
+
+// The layout the probes look for, with one up and one down channel.
+#[repr(C)]
+struct ControlBlock {
+    id: [u8; 16],
+    max_up: i32,
+    max_down: i32,
+    up: Channel,
+    down: Channel,
+}
+
+struct Shared<T>(UnsafeCell<T>);
+
+// SAFETY: The control block is filled in once by Rtt::new, then the up
+// channel is only written with WRITER held and the down channel only read
+// with READER held.
+unsafe impl<T> Sync for Shared<T> {}
+
+#[used]
+#[export_name = "_SEGGER_RTT"]
+static CONTROL_BLOCK: Shared<ControlBlock> = Shared(UnsafeCell::new(ControlBlock {
+    // Written last by Rtt::new, so that the probe doesn't find a block
+    // which isn't set up.
+    id: [0; 16],
+    max_up: 1,
+    max_down: 1,
+    up: Channel::new(),
+    down: Channel::new(),
+}));
+static UP_BUF: Shared<[u8; RTT_UP_BUF_SIZE]> = Shared(UnsafeCell::new([0; RTT_UP_BUF_SIZE]));
+static DOWN_BUF: Shared<[u8; RTT_DOWN_BUF_SIZE]> = Shared(UnsafeCell::new([0; RTT_DOWN_BUF_SIZE]));
+static WRITER: SpinLock<()> = SpinLock::new(());
+static READER: SpinLock<()> = SpinLock::new(());
+
+fn channels() -> (&'static Channel, &'static Channel) {
+    // SAFETY: Only the atomics and the buffers the rings own are touched
+    // once Rtt::new has set the block up.
+    let cb = unsafe { &*CONTROL_BLOCK.0.get() };
+    (&cb.up, &cb.down)
+}
+
+// Copies as much of `bytes` as fits into the up channel.
+fn write_up(bytes: &[u8]) -> usize {
+    // Like the UART, don't wait for the panicking context.
+    let _guard = if console::is_panicking() {
+        match WRITER.try_irqsave_lock() {
+            Some(guard) => guard,
+            None => return 0,
+        }
+    } else {
+        WRITER.irqsave_lock()
+    };
+    let (up, _) = channels();
+    // The write offset and the buffer are ours while WRITER is held, the
+    // probe only moves `read`.
+    let read = up.read.load(Ordering::Acquire) as usize;
+    let mut write = up.write.load(Ordering::Relaxed) as usize;
+    let mut count = 0;
+    for &b in bytes {
+        let next = (write + 1) % RTT_UP_BUF_SIZE;
+        if next == read {
+            break;
+        }
+        // SAFETY: `write` is within the buffer.
+        unsafe { up.buf.add(write).write_volatile(b) };
+        write = next;
+        count += 1;
+    }
+    up.write.store(write as u32, Ordering::Release);
+    count
+}
+
+// Copies what the probe sent into `buf`.
+fn read_down(buf: &mut [u8]) -> usize {
+    let _guard = READER.irqsave_lock();
+    let (_, down) = channels();
+    // The read offset is ours while READER is held, the probe only moves
+    // `write`.
+    let write = down.write.load(Ordering::Acquire) as usize;
+    let mut read = down.read.load(Ordering::Relaxed) as usize;
+    let mut count = 0;
+    while count < buf.len() && read != write {
+        // SAFETY: `read` is within the buffer.
+        buf[count] = unsafe { down.buf.add(read).read_volatile() };
+        read = (read + 1) % RTT_DOWN_BUF_SIZE;
+        count += 1;
+    }
+    down.read.store(read as u32, Ordering::Release);
+    count
+}
+
+fn poll_wait() {
+    let _wait = thread::wait_on_device("rtt");
+    scheduler::suspend_me_for((TICKS_PER_SECOND * POLL_INTERVAL_MS / 1000).max(1));
+}
+
+pub(crate) struct Rtt {
+    id: DeviceId,
+}
+
+impl Rtt {
+    /// Sets the control block up, there must be only one.
+    fn new(id: DeviceId) -> Self {
+        let cb = CONTROL_BLOCK.0.get();
+        // SAFETY: The probe doesn't touch the block before the id is
+        // written, and nothing else does before Rtt exists.
+        unsafe {
+            (*cb).up.name = c"Terminal".as_ptr().cast();
+            (*cb).up.buf = UP_BUF.0.get().cast();
+            (*cb).up.size = RTT_UP_BUF_SIZE as u32;
+            (*cb).down.name = c"Terminal".as_ptr().cast();
+            (*cb).down.buf = DOWN_BUF.0.get().cast();
+            (*cb).down.size = RTT_DOWN_BUF_SIZE as u32;
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(ptr::addr_of_mut!((*cb).id), *RTT_ID);
+        }
+        Self { id }
+    }
+}
+
+/// Sets RTT up and registers it as the "rtt" console sink and as a char
+/// device.
+pub(crate) fn init() -> Result<(), Error> {
+    let major = devno::register_major(DeviceClass::Char, 0, "rtt")?;
+    let rtt = Arc::new(Rtt::new(DeviceId::new(major, 0)));
+    console::register_early_console("rtt", rtt.clone());
+    console::init_console("rtt", rtt.clone())?;
+    // A device is registered under one name only.
+    if console::get_console()
+        .is_some_and(|console| ptr::addr_eq(Arc::as_ptr(console), Arc::as_ptr(&rtt)))
+    {
+        return Ok(());
+    }
+    DeviceManager::get().register_device(String::from("rtt0"), rtt)
+}
+
+impl EarlyConsole for Rtt {
+    fn write_str(&self, s: &str) {
+        write_up(s.as_bytes());
+    }
+}
+
+impl Device for Rtt {
+    fn name(&self) -> String {
+        String::from("rtt0")
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = read_down(buf);
+            if n > 0 {
+                return Ok(n);
+            }
+            if is_nonblocking {
+                return Err(code::EAGAIN);
+            }
+            poll_wait();
+        }
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, Error> {
+        let mut count = write_up(buf);
+        if count == buf.len() || is_nonblocking {
+            return if count == 0 && !buf.is_empty() {
+                Err(code::EAGAIN)
+            } else {
+                Ok(count)
+            };
+        }
+        let (up, _) = channels();
+        while count < buf.len() {
+            let blocking = up.flags.load(Ordering::Relaxed) & MODE_MASK == MODE_BLOCK_IF_FULL;
+            if !blocking || irq::is_in_irq() || console::is_panicking() {
+                // Dropped, as the probe asked.
+                return Ok(buf.len());
+            }
+            poll_wait();
+            count += write_up(&buf[count..]);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_rtt_ring() {
+        // Registered at boot, whether selected or not.
+        let (up, _) = channels();
+        assert_eq!(unsafe { (*CONTROL_BLOCK.0.get()).id }, *RTT_ID);
+        // Play the probe, which nobody else does under test.
+        let start = up.write.load(Ordering::Acquire);
+        up.read.store(start, Ordering::Release);
+        assert_eq!(write_up(b"rtt"), 3);
+        let end = up.write.load(Ordering::Acquire);
+        assert_eq!(
+            (end as usize + RTT_UP_BUF_SIZE - start as usize) % RTT_UP_BUF_SIZE,
+            3
+        );
+        // Fills up without overwriting what the probe hasn't read.
+        assert_eq!(write_up(&[b'x'; RTT_UP_BUF_SIZE]), RTT_UP_BUF_SIZE - 4);
+        let full = up.write.load(Ordering::Acquire) as usize;
+        assert_eq!((full + 1) % RTT_UP_BUF_SIZE, start as usize);
+        up.read.store(full as u32, Ordering::Release);
+    }
+
+    #[test]
+    fn test_rtt_device() {
+        let rtt = DeviceManager::get()
+            .get_char_device("rtt0")
+            .or_else(|| console::get_console().cloned())
+            .unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(rtt.read(0, &mut buf, true), Err(code::EAGAIN));
+
+        // The probe sends a line.
+        let (up, down) = channels();
+        let mut write = down.write.load(Ordering::Relaxed) as usize;
+        for &b in b"ls\n" {
+            unsafe { down.buf.add(write).write_volatile(b) };
+            write = (write + 1) % RTT_DOWN_BUF_SIZE;
+        }
+        down.write.store(write as u32, Ordering::Release);
+        assert_eq!(rtt.read(0, &mut buf[..2], false), Ok(2));
+        assert_eq!(rtt.read(0, &mut buf[2..], false), Ok(1));
+        assert_eq!(&buf[..3], b"ls\n");
+
+        // Without the probe reading, a full channel drops blocking writes
+        // and refuses non-blocking ones.
+        let start = up.write.load(Ordering::Acquire);
+        up.read.store(start, Ordering::Release);
+        let big = [b'x'; RTT_UP_BUF_SIZE];
+        assert_eq!(rtt.write(0, &big, false), Ok(RTT_UP_BUF_SIZE));
+        assert_eq!(rtt.write(0, b"x", true), Err(code::EAGAIN));
+        let full = up.write.load(Ordering::Acquire);
+        up.read.store(full, Ordering::Release);
+    }
+}
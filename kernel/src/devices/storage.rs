@@ -0,0 +1,102 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Health and media statistics of storage devices, so that the wear of
+//! flash can be monitored in the field. They're read with the
+//! [`STORAGE_HEALTH`] ioctl on the device, or from `/proc/storage`.
+
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+crate::ioctl_read!(
+    /// Reads the [`StorageHealth`] of a storage device.
+    pub STORAGE_HEALTH, b'S', 0x01, StorageHealth
+);
+
+/// [`StorageHealth::erases`] and [`StorageHealth::max_block_erases`] are
+/// known.
+pub const HEALTH_ERASE_COUNTS: u32 = 1 << 0;
+
+/// Counters since the device was registered, except for the erase counts
+/// which are kept by the media.
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct StorageHealth {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub reads: u64,
+    pub writes: u64,
+    pub read_errors: u64,
+    pub write_errors: u64,
+    /// Erases of all the erase blocks of flash media.
+    pub erases: u64,
+    /// Erases of the most worn erase block.
+    pub max_block_erases: u64,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+impl StorageHealth {
+    /// Counts a read of `bytes`, which failed unless `ok`.
+    pub fn account_read(&mut self, bytes: usize, ok: bool) {
+        self.reads += 1;
+        if ok {
+            self.bytes_read += bytes as u64;
+        } else {
+            self.read_errors += 1;
+        }
+    }
+
+    pub fn account_write(&mut self, bytes: usize, ok: bool) {
+        self.writes += 1;
+        if ok {
+            self.bytes_written += bytes as u64;
+        } else {
+            self.write_errors += 1;
+        }
+    }
+
+    /// Sets the erase counts reported by the media.
+    pub fn set_erase_counts(&mut self, erases: u64, max_block_erases: u64) {
+        self.erases = erases;
+        self.max_block_erases = max_block_erases;
+        self.flags |= HEALTH_ERASE_COUNTS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_storage_health_account() {
+        let mut health = StorageHealth::default();
+        health.account_write(4096, true);
+        health.account_write(512, false);
+        health.account_read(1024, true);
+        assert_eq!(
+            (health.writes, health.bytes_written, health.write_errors),
+            (2, 4096, 1)
+        );
+        assert_eq!(
+            (health.reads, health.bytes_read, health.read_errors),
+            (1, 1024, 0)
+        );
+        assert_eq!(health.flags & HEALTH_ERASE_COUNTS, 0);
+        health.set_erase_counts(300, 7);
+        assert_eq!(health.flags & HEALTH_ERASE_COUNTS, HEALTH_ERASE_COUNTS);
+    }
+}
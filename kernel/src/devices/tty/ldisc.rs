@@ -0,0 +1,374 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line discipline of the terminals: turns the bytes received on the line
+//! into the input read from the terminal, as set by its [`Termios`].
+//!
+//! In canonical mode input is edited a line at a time, and only handed out
+//! once the line is complete. Otherwise bytes are handed out as they
+//! arrive, [`Tty`](super::n_tty::Tty) applying VMIN and VTIME.
+
+use super::{
+    n_tty::TtySignal,
+    termios::{CcIndex, Iflags, Lflags, Termios},
+};
+use crate::events::{self, Event};
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// Longest line accepted in canonical mode, delimiter included.
+pub const MAX_CANON: usize = 512;
+/// Bytes buffered in raw mode before further input is dropped.
+pub const MAX_INPUT: usize = 4096;
+const HISTORY_SIZE: usize = 5;
+const ERASE_SEQ: &[u8] = b"\x08 \x08";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    // ESC received.
+    Esc,
+    // ESC [ received.
+    Csi,
+}
+
+#[derive(Debug, Default)]
+pub struct LineDiscipline {
+    // Line being edited in canonical mode.
+    line: Vec<u8>,
+    // Input ready to be read.
+    ready: VecDeque<u8>,
+    // Lengths of the complete lines at the front of `ready`, in canonical
+    // mode. An end of file ends a line without adding a delimiter, so an
+    // empty one reads as end of file.
+    lines: VecDeque<usize>,
+    escape: Escape,
+    // Lines entered, most recent first, recalled with the up and down keys.
+    history: VecDeque<Vec<u8>>,
+    // 0 while editing a new line, n while showing history[n - 1].
+    history_pos: usize,
+}
+
+fn is_char(termios: &Termios, index: CcIndex, ch: u8) -> bool {
+    // A control character set to 0 is disabled.
+    let c = termios.cc[index as usize];
+    c != 0 && c == ch
+}
+
+fn signal_for(termios: &Termios, ch: u8) -> Option<TtySignal> {
+    if is_char(termios, CcIndex::Vintr, ch) {
+        Some(TtySignal::Interrupt)
+    } else if is_char(termios, CcIndex::Vquit, ch) {
+        Some(TtySignal::Quit)
+    } else if is_char(termios, CcIndex::Vsusp, ch) {
+        Some(TtySignal::Suspend)
+    } else {
+        None
+    }
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes a byte received on the line. What has to be echoed back
+    /// is appended to `echo`, and a signal to raise is returned.
+    pub fn receive(
+        &mut self,
+        termios: &Termios,
+        mut ch: u8,
+        echo: &mut Vec<u8>,
+    ) -> Option<TtySignal> {
+        let iflag = termios.iflag;
+        let lflag = termios.lflag;
+        if iflag.contains(Iflags::ISTRIP) {
+            ch &= 0x7f;
+        }
+        if ch == b'\r' {
+            if iflag.contains(Iflags::IGNCR) {
+                return None;
+            }
+            if iflag.contains(Iflags::ICRNL) {
+                ch = b'\n';
+            }
+        } else if ch == b'\n' && iflag.contains(Iflags::INLCR) {
+            ch = b'\r';
+        }
+
+        if lflag.contains(Lflags::ISIG) {
+            if let Some(signal) = signal_for(termios, ch) {
+                // Echo like ^C and drop the pending input.
+                if lflag.contains(Lflags::ECHO) {
+                    echo.extend_from_slice(&[b'^', ch ^ 0x40, b'\n']);
+                }
+                if !lflag.contains(Lflags::NOFLSH) {
+                    self.flush();
+                }
+                return Some(signal);
+            }
+        }
+
+        let echo_on = lflag.contains(Lflags::ECHO);
+        if !lflag.contains(Lflags::ICANON) {
+            if self.ready.len() < MAX_INPUT {
+                self.ready.push_back(ch);
+                if echo_on {
+                    echo.push(ch);
+                }
+            } else {
+                events::count(Event::RxDropped);
+            }
+            return None;
+        }
+
+        if self.escape(ch, echo_on, echo) {
+            return None;
+        }
+        if is_char(termios, CcIndex::Verase, ch) {
+            if self.line.pop().is_some() && echo_on {
+                if lflag.contains(Lflags::ECHOE) {
+                    echo.extend_from_slice(ERASE_SEQ);
+                } else {
+                    echo.push(ch);
+                }
+            }
+        } else if is_char(termios, CcIndex::Vkill, ch) {
+            if echo_on && lflag.contains(Lflags::ECHOK) {
+                for _ in 0..self.line.len() {
+                    echo.extend_from_slice(ERASE_SEQ);
+                }
+            }
+            self.line.clear();
+        } else if is_char(termios, CcIndex::Veof, ch) {
+            self.complete_line();
+        } else if ch == b'\n' || is_char(termios, CcIndex::Veol, ch) {
+            if echo_on || (ch == b'\n' && lflag.contains(Lflags::ECHONL)) {
+                echo.push(ch);
+            }
+            self.add_history();
+            self.line.push(ch);
+            self.complete_line();
+        } else if self.line.len() < MAX_CANON - 1 {
+            self.line.push(ch);
+            if echo_on {
+                echo.push(ch);
+            }
+        } else {
+            events::count(Event::RxDropped);
+        }
+        None
+    }
+
+    // Handles the escape sequences of the up and down keys, which recall
+    // the history. Other sequences are dropped. Returns false if `ch` isn't
+    // part of a sequence.
+    fn escape(&mut self, ch: u8, echo_on: bool, echo: &mut Vec<u8>) -> bool {
+        match (self.escape, ch) {
+            (Escape::None, 0x1b) => self.escape = Escape::Esc,
+            (Escape::None, _) => return false,
+            (Escape::Esc, b'[') => self.escape = Escape::Csi,
+            (Escape::Esc, _) => {
+                self.escape = Escape::None;
+                return false;
+            }
+            // Parameter and intermediate bytes.
+            (Escape::Csi, 0x20..=0x3f) => {}
+            (Escape::Csi, _) => {
+                self.escape = Escape::None;
+                let pos = match ch {
+                    b'A' if self.history_pos < self.history.len() => self.history_pos + 1,
+                    b'B' if self.history_pos > 0 => self.history_pos - 1,
+                    _ => return true,
+                };
+                self.history_pos = pos;
+                self.line.clear();
+                if pos > 0 {
+                    self.line.extend_from_slice(&self.history[pos - 1]);
+                }
+                if echo_on {
+                    echo.extend_from_slice(b"\r\x1b[2K");
+                    echo.extend_from_slice(&self.line);
+                }
+            }
+        }
+        true
+    }
+
+    fn add_history(&mut self) {
+        self.history_pos = 0;
+        if self.line.is_empty() || self.history.front() == Some(&self.line) {
+            return;
+        }
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_back();
+        }
+        self.history.push_front(self.line.clone());
+    }
+
+    fn complete_line(&mut self) {
+        self.lines.push_back(self.line.len());
+        self.ready.extend(self.line.drain(..));
+    }
+
+    /// Drops the input which hasn't been read, complete or not.
+    pub fn flush(&mut self) {
+        self.line.clear();
+        self.ready.clear();
+        self.lines.clear();
+        self.escape = Escape::None;
+    }
+
+    /// Adapts the buffered input to a change of mode. Leaving canonical
+    /// mode makes the line being edited readable, entering it makes what
+    /// hasn't been read a line of its own.
+    pub fn set_canonical(&mut self, canonical: bool) {
+        if canonical {
+            if !self.ready.is_empty() {
+                self.lines.clear();
+                self.lines.push_back(self.ready.len());
+            }
+        } else {
+            self.ready.extend(self.line.drain(..));
+            self.lines.clear();
+        }
+        self.escape = Escape::None;
+    }
+
+    /// Whether a read in canonical mode would return.
+    pub fn has_line(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    /// Bytes which can be read in raw mode.
+    pub fn available(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Reads the input ready, not going past the end of a line in
+    /// canonical mode.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = match self.lines.front_mut() {
+            Some(len) if *len <= buf.len() => self.lines.pop_front().unwrap(),
+            Some(len) => {
+                *len -= buf.len();
+                buf.len()
+            }
+            None => self.ready.len().min(buf.len()),
+        };
+        for (dst, src) in buf.iter_mut().zip(self.ready.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn feed(ldisc: &mut LineDiscipline, termios: &Termios, input: &[u8]) -> Vec<u8> {
+        let mut echo = Vec::new();
+        for ch in input {
+            assert_eq!(ldisc.receive(termios, *ch, &mut echo), None);
+        }
+        echo
+    }
+
+    #[test]
+    fn test_ldisc_canonical_editing() {
+        let termios = Termios::default();
+        let mut ldisc = LineDiscipline::new();
+        // DEL erases, ^U kills the line, CR ends it.
+        let echo = feed(&mut ldisc, &termios, b"lx\x7fs\x15ls -l\r");
+        assert_eq!(echo, b"lx\x08 \x08s\x08 \x08\x08 \x08ls -l\n");
+        assert!(ldisc.has_line());
+
+        // Reads stop at the end of a line, even a partial one.
+        feed(&mut ldisc, &termios, b"pwd\rcd");
+        let mut buf = [0u8; 4];
+        assert_eq!(ldisc.read(&mut buf), 4);
+        assert_eq!(&buf, b"ls -");
+        assert_eq!(ldisc.read(&mut buf), 2);
+        assert_eq!(&buf[..2], b"l\n");
+        assert_eq!(ldisc.read(&mut buf), 4);
+        assert_eq!(&buf, b"pwd\n");
+        assert!(!ldisc.has_line());
+
+        // ^D ends the line without a delimiter, and reads as end of file
+        // on an empty line.
+        feed(&mut ldisc, &termios, b"\x04\x04");
+        assert_eq!(ldisc.read(&mut buf), 2);
+        assert_eq!(&buf[..2], b"cd");
+        assert!(ldisc.has_line());
+        assert_eq!(ldisc.read(&mut buf), 0);
+        assert!(!ldisc.has_line());
+    }
+
+    #[test]
+    fn test_ldisc_history() {
+        let termios = Termios::default();
+        let mut ldisc = LineDiscipline::new();
+        let mut buf = [0u8; 16];
+        feed(&mut ldisc, &termios, b"one\rtwo\r");
+        ldisc.read(&mut buf);
+        ldisc.read(&mut buf);
+
+        let echo = feed(&mut ldisc, &termios, b"\x1b[A\x1b[A\x1b[A\x1b[B\r");
+        assert_eq!(echo, b"\r\x1b[2Ktwo\r\x1b[2Kone\r\x1b[2Ktwo\n");
+        assert_eq!(ldisc.read(&mut buf), 4);
+        assert_eq!(&buf[..4], b"two\n");
+    }
+
+    #[test]
+    fn test_ldisc_signals() {
+        let termios = Termios::default();
+        let mut ldisc = LineDiscipline::new();
+        let mut echo = Vec::new();
+        feed(&mut ldisc, &termios, b"sleep 10");
+        assert_eq!(
+            ldisc.receive(&termios, 0x03, &mut echo),
+            Some(TtySignal::Interrupt)
+        );
+        assert_eq!(echo, b"^C\n");
+        // The line is flushed.
+        feed(&mut ldisc, &termios, b"\r");
+        assert_eq!(ldisc.read(&mut [0u8; 16]), 1);
+    }
+
+    #[test]
+    fn test_ldisc_raw() {
+        let mut termios = Termios::default();
+        termios.lflag.remove(Lflags::ICANON | Lflags::ECHO);
+        termios.iflag.remove(Iflags::ICRNL);
+        let mut ldisc = LineDiscipline::new();
+        let echo = feed(&mut ldisc, &termios, b"a\x7f\r\x1b[A");
+        assert!(echo.is_empty());
+        assert!(!ldisc.has_line());
+        assert_eq!(ldisc.available(), 6);
+        let mut buf = [0u8; 8];
+        assert_eq!(ldisc.read(&mut buf), 6);
+        assert_eq!(&buf[..6], b"a\x7f\r\x1b[A");
+
+        // Switching modes keeps what has been typed.
+        termios.lflag.insert(Lflags::ICANON);
+        feed(&mut ldisc, &termios, b"ab");
+        ldisc.set_canonical(false);
+        assert_eq!(ldisc.available(), 2);
+        ldisc.set_canonical(true);
+        assert!(ldisc.has_line());
+        assert_eq!(ldisc.read(&mut buf), 2);
+    }
+}
@@ -12,6 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod ldisc;
 pub mod n_tty;
 pub mod serial;
+#[cfg(magic_sysrq)]
+pub mod sysrq;
 pub mod termios;
+pub mod vt;
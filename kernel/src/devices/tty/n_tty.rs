@@ -12,67 +12,165 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::devices::{
-    tty::{
-        serial,
-        termios::{CcIndex, Iflags},
+use crate::{
+    devices::{
+        devno::TTYAUX_MAJOR,
+        tty::{
+            ldisc::LineDiscipline,
+            serial,
+            termios::{
+                CcIndex, Lflags, RawTermios, RawTermios2, Termios, TCGETS, TCGETS2, TCSETS, TCSETS2,
+            },
+        },
+        Device, DeviceClass, DeviceId, DeviceRequest,
     },
-    Device, DeviceClass, DeviceId,
+    error::Error,
+    static_arc,
+    sync::KOnce,
+    time,
+    vfs::poll::{PollEvents, PollWaiter},
+    workqueue::Work,
 };
-use alloc::{collections::VecDeque, string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use core::sync::atomic::{AtomicUsize, Ordering};
-use embedded_io::ErrorKind;
 use serial::Serial;
-use spin::{Mutex, Once};
+use spin::Mutex;
 
-static TTY: Once<Arc<Tty>> = Once::new();
+static TTY: KOnce<Arc<Tty>> = KOnce::new();
 
-enum SpecKey {
-    Up,
-    Down,
+static_arc! {
+    RX_WORK(Work, Work::new(receive)),
+}
+
+// Runs the line discipline as soon as input arrives, so that signals are
+// raised and input echoed even when nobody reads the terminal.
+fn receive() {
+    if let Some(tty) = TTY.get() {
+        let _ = tty.pump();
+    }
+}
+
+/// Job control signals generated by the line discipline when ISIG is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtySignal {
+    /// VINTR, ^C by default.
+    Interrupt,
+    /// VQUIT, ^\ by default.
+    Quit,
+    /// VSUSP, ^Z by default.
+    Suspend,
+}
+
+/// Delivers a job control signal to the foreground group of the terminal.
+/// The kernel has no signals yet, so delivery is left to whoever registers
+/// the handler, e.g. a shell running in kernel space.
+pub type TtySignalHandler = fn(foreground: usize, signal: TtySignal);
+
+static SIGNAL_HANDLER: KOnce<TtySignalHandler> = KOnce::new();
+
+pub fn set_signal_handler(handler: TtySignalHandler) {
+    SIGNAL_HANDLER.call_once(|| handler);
 }
 
 pub struct Tty {
     serial: Arc<Serial>,
-    line_buf: Mutex<[u8; 512]>,
-    cursor: AtomicUsize,
-    history: Mutex<VecDeque<String>>,
-    history_cursor: AtomicUsize,
-    spec_key: Mutex<Option<SpecKey>>,
+    // Held while moving input from the serial port to the line discipline,
+    // which the RX work and readers both do.
+    rx: Mutex<()>,
+    // Locked before ldisc when both are needed.
+    termios: Mutex<Termios>,
+    ldisc: Mutex<LineDiscipline>,
+    // Id of the foreground group of the session controlling this terminal,
+    // 0 if there is no controlling session.
+    foreground: AtomicUsize,
 }
 
 impl Tty {
     pub fn init(serial: Arc<Serial>) -> &'static Arc<Tty> {
         TTY.call_once(|| {
+            serial.set_rx_work(RX_WORK.clone());
             Arc::new(Self {
+                termios: Mutex::new(serial.termios()),
                 serial,
-                line_buf: Mutex::new([0u8; 512]),
-                cursor: AtomicUsize::new(0),
-                history: Mutex::new(VecDeque::with_capacity(5)),
-                history_cursor: AtomicUsize::new(0),
-                spec_key: Mutex::new(None),
+                rx: Mutex::new(()),
+                ldisc: Mutex::new(LineDiscipline::new()),
+                foreground: AtomicUsize::new(0),
             })
         })
     }
 
-    fn add_history(&self, command: &str) {
-        let mut history = self.history.lock();
-        if history.front().map(|s| s.as_str()) != Some(command) {
-            if history.len() == 5 {
-                history.pop_back();
+    /// Makes `id` the foreground group of the terminal. Signals generated
+    /// from the keyboard are sent to it.
+    pub fn set_foreground(&self, id: usize) {
+        self.foreground.store(id, Ordering::Relaxed);
+    }
+
+    pub fn foreground(&self) -> usize {
+        self.foreground.load(Ordering::Relaxed)
+    }
+
+    fn raise(&self, signal: TtySignal) {
+        let foreground = self.foreground();
+        if foreground == 0 {
+            return;
+        }
+        if let Some(handler) = SIGNAL_HANDLER.get() {
+            handler(foreground, signal);
+        }
+    }
+
+    // Passes what the serial has received to the line discipline, and
+    // echoes it. Returns whether anything was received.
+    fn pump(&self) -> Result<bool, Error> {
+        let mut raw = [0u8; 64];
+        let mut echo = Vec::new();
+        let mut signals = Vec::new();
+        let mut received = false;
+        let rx = self.rx.lock();
+        loop {
+            let n = self.serial.read(0, &mut raw, true)?;
+            if n == 0 {
+                break;
+            }
+            received = true;
+            let termios = self.termios.lock();
+            let mut ldisc = self.ldisc.lock();
+            for ch in &raw[..n] {
+                if let Some(signal) = ldisc.receive(&termios, *ch, &mut echo) {
+                    signals.push(signal);
+                }
             }
-            history.push_front(String::from(command));
         }
-        self.history_cursor.store(0, Ordering::Relaxed);
+        drop(rx);
+        if !echo.is_empty() {
+            self.serial.write(0, &echo, false)?;
+        }
+        // Handlers may well use the terminal.
+        for signal in signals {
+            self.raise(signal);
+        }
+        Ok(received)
     }
 
-    fn get_history(&self, index: usize) -> Option<String> {
-        self.history.lock().get(index).cloned()
+    fn readable(termios: &Termios, ldisc: &LineDiscipline) -> bool {
+        if termios.lflag.contains(Lflags::ICANON) {
+            ldisc.has_line()
+        } else {
+            ldisc.available() > 0
+        }
     }
 
-    fn clear_line(&self, pos: u64, is_blocking: bool) -> Result<(), ErrorKind> {
-        self.serial.write(pos, b"\r", is_blocking)?;
-        self.serial.write(pos, b"\x1b[2K", is_blocking)?;
+    fn set_termios(&self, termios: Termios) -> Result<(), Error> {
+        self.serial.ioctl(
+            DeviceRequest::Config as u32,
+            &termios as *const Termios as usize,
+        )?;
+        let mut current = self.termios.lock();
+        let canonical = termios.lflag.contains(Lflags::ICANON);
+        if canonical != current.lflag.contains(Lflags::ICANON) {
+            self.ldisc.lock().set_canonical(canonical);
+        }
+        *current = termios;
         Ok(())
     }
 }
@@ -87,126 +185,118 @@ impl Device for Tty {
     }
 
     fn id(&self) -> DeviceId {
-        DeviceId::new(5, 0)
+        DeviceId::new(TTYAUX_MAJOR, 0)
     }
 
-    fn open(&self) -> Result<(), ErrorKind> {
+    fn open(&self) -> Result<(), Error> {
         self.serial.open()
     }
 
-    fn close(&self) -> Result<(), ErrorKind> {
+    fn close(&self) -> Result<(), Error> {
         self.serial.close()
     }
 
-    fn read(&self, _pos: u64, buf: &mut [u8], is_blocking: bool) -> Result<usize, ErrorKind> {
-        let mut line_buf = self.line_buf.lock();
-        // handle special characters
-        if let Some(key) = &*self.spec_key.lock() {
-            let history_cursor = self.history_cursor.load(Ordering::Relaxed);
-            match key {
-                SpecKey::Up => {
-                    if history_cursor < self.history.lock().len() {
-                        if let Some(hist_cmd) = self.get_history(history_cursor) {
-                            line_buf[..hist_cmd.len()].copy_from_slice(hist_cmd.as_bytes());
-                            self.cursor.store(hist_cmd.len(), Ordering::Relaxed);
-                            self.serial.write(_pos, hist_cmd.as_bytes(), false)?;
-                            self.history_cursor
-                                .store(history_cursor + 1, Ordering::Relaxed);
-                        }
-                    }
-                }
-                SpecKey::Down => {
-                    if history_cursor != 0 {
-                        if let Some(hist_cmd) = self.get_history(history_cursor - 1) {
-                            line_buf[..hist_cmd.len()].copy_from_slice(hist_cmd.as_bytes());
-                            self.cursor.store(hist_cmd.len(), Ordering::Relaxed);
-                            self.serial.write(_pos, hist_cmd.as_bytes(), false)?;
-                        }
-                        self.history_cursor
-                            .store(history_cursor - 1, Ordering::Relaxed);
-                    } else {
-                        line_buf.fill(0);
-                        self.cursor.store(0, Ordering::Relaxed);
-                        self.history_cursor.store(0, Ordering::Relaxed);
-                    }
-                }
-            }
+    // In canonical mode a read returns a line. Otherwise it follows VMIN
+    // and VTIME: with both set, VTIME is the longest gap between bytes once
+    // one has arrived; with VMIN only it waits for VMIN bytes; with VTIME
+    // only it waits that long for a byte; with neither it doesn't wait.
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
         }
-        *self.spec_key.lock() = None;
-        // normal character
+        let waiter = PollWaiter::new();
+        let start = time::get_sys_ticks();
+        let mut last_rx = start;
         loop {
-            let mut temp_buf = [0u8; 512];
-            let nbytes = self.serial.read(_pos, &mut temp_buf, is_blocking).unwrap();
-            let mut i = 0;
-            while i < nbytes {
-                let ch = temp_buf[i];
-                let cursor = self.cursor.load(Ordering::Relaxed);
-                if self.serial.termios.iflag.contains(Iflags::ICRNL) && ch == b'\r' {
-                    let _ = self.serial.write(_pos, b"\n", false);
-                    line_buf[cursor] = b'\n';
-                    buf[..cursor + 1].copy_from_slice(&line_buf[..cursor + 1]);
-                    let command = String::from_utf8_lossy(&line_buf[..cursor]).into_owned();
-                    if !command.is_empty() {
-                        self.add_history(&command);
-                    }
-                    line_buf.fill(0);
-                    self.cursor.store(0, Ordering::Relaxed);
-                    return Ok(cursor + 1);
+            waiter.reset();
+            self.serial.poll(PollEvents::POLLIN, Some(&waiter));
+            if self.pump()? {
+                last_rx = time::get_sys_ticks();
+            }
+            let termios = self.termios.lock();
+            let mut ldisc = self.ldisc.lock();
+            let mut timeout = None;
+            if termios.lflag.contains(Lflags::ICANON) {
+                if ldisc.has_line() {
+                    return Ok(ldisc.read(buf));
                 }
-                if self.serial.termios.cc[CcIndex::Verase as usize] == ch {
-                    if cursor > 0 {
-                        let backspace_seq = [8u8, b' ', 8u8];
-                        let _ = self.serial.write(_pos, &backspace_seq, false);
-                        let _ = self.cursor.fetch_sub(1, Ordering::Relaxed);
-                        line_buf[cursor - 1] = 0;
-                    }
-                    i += 1;
-                    continue;
+                if is_nonblocking {
+                    return Ok(0);
                 }
-
-                if self.serial.termios.cc[CcIndex::Vkill as usize] == ch {
-                    line_buf.fill(0);
-                    self.cursor.store(0, Ordering::Relaxed);
-                    i += 1;
-                    continue;
+            } else {
+                let vmin = (termios.cc[CcIndex::Vmin as usize] as usize).min(buf.len());
+                let vtime =
+                    time::tick_from_millisecond(termios.cc[CcIndex::Vtime as usize] as usize * 100);
+                let available = ldisc.available();
+                if available >= vmin.max(1) || is_nonblocking || (vmin == 0 && vtime == 0) {
+                    return Ok(ldisc.read(buf));
                 }
-
-                // get commandline history
-                // up key  : 0x1b 0x5b 0x41
-                // down key: 0x1b 0x5b 0x42
-                if ch == 0x1b && i <= temp_buf.len() - 3 && temp_buf[i + 1] == 0x5b {
-                    match temp_buf[i + 2] {
-                        0x41 => {
-                            *self.spec_key.lock() = Some(SpecKey::Up);
-                            self.clear_line(_pos, false)?;
-                            buf[0] = b'\n';
-                            return Ok(1);
-                        }
-                        0x42 => {
-                            *self.spec_key.lock() = Some(SpecKey::Down);
-                            self.clear_line(_pos, false)?;
-                            buf[0] = b'\n';
-                            return Ok(1);
-                        }
-                        _ => {
-                            i += 3;
-                            continue;
-                        }
+                // The inter-byte timer only starts with the first byte.
+                let deadline = match (vmin, vtime) {
+                    (_, 0) => None,
+                    (0, _) => Some(start + vtime),
+                    _ if available == 0 => None,
+                    _ => Some(last_rx + vtime),
+                };
+                if let Some(deadline) = deadline {
+                    let now = time::get_sys_ticks();
+                    if now >= deadline {
+                        return Ok(ldisc.read(buf));
                     }
+                    timeout = Some(deadline - now);
                 }
-                i += 1;
-                line_buf[cursor] = ch;
-                let _ = self.cursor.fetch_add(1, Ordering::Relaxed);
-                let _ = self.serial.write(_pos, &[ch], false);
             }
+            drop(ldisc);
+            drop(termios);
+            waiter.wait(timeout);
         }
     }
 
-    fn write(&self, _pos: u64, buf: &[u8], is_blocking: bool) -> Result<usize, ErrorKind> {
-        self.serial.write(_pos, buf, is_blocking)
+    fn write(&self, _pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, Error> {
+        self.serial.write(_pos, buf, is_nonblocking)
+    }
+
+    fn write_vectored(
+        &self,
+        pos: u64,
+        bufs: &[&[u8]],
+        is_nonblocking: bool,
+    ) -> Result<usize, Error> {
+        self.serial.write_vectored(pos, bufs, is_nonblocking)
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        // Register for input before looking at it, so that none is missed.
+        let mut revents = self.serial.poll(events | PollEvents::POLLIN, waiter);
+        revents.remove(PollEvents::POLLIN);
+        let _ = self.pump();
+        if Self::readable(&self.termios.lock(), &self.ldisc.lock()) {
+            revents |= PollEvents::POLLIN;
+        }
+        revents & events
     }
 
-    fn ioctl(&self, request: u32, arg: usize) -> Result<(), ErrorKind> {
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if TCGETS.matches(request) {
+            let termios = *self.termios.lock();
+            return TCGETS.copy_out(arg, &RawTermios::from(&termios));
+        }
+        if TCSETS.matches(request) {
+            let raw = TCSETS.copy_in(arg)?;
+            let mut termios = *self.termios.lock();
+            termios.set_raw(&raw, None);
+            return self.set_termios(termios);
+        }
+        if TCGETS2.matches(request) {
+            let termios = *self.termios.lock();
+            return TCGETS2.copy_out(arg, &RawTermios2::from(&termios));
+        }
+        if TCSETS2.matches(request) {
+            let raw = TCSETS2.copy_in(arg)?;
+            let mut termios = *self.termios.lock();
+            termios.set_raw(&raw.termios, Some((raw.ispeed, raw.ospeed)));
+            return self.set_termios(termios);
+        }
         self.serial.ioctl(request, arg)
     }
 }
@@ -0,0 +1,195 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A scriptable [`UartOps`] for testing [`Serial`](super::Serial) without
+//! hardware.
+
+use super::{ModemLines, SerialError, UartOps};
+use crate::devices::tty::termios::Termios;
+use alloc::{collections::VecDeque, vec::Vec};
+use embedded_io::{ErrorType, Read, ReadReady, Write, WriteReady};
+
+/// What the UART receives next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RxEvent {
+    Byte(u8),
+    Error(SerialError),
+}
+
+/// A UART whose RX FIFO is fed by the test and whose TX FIFO records what
+/// is written to it.
+#[derive(Debug, Default)]
+pub(crate) struct MockUart {
+    rx: VecDeque<RxEvent>,
+    tx: Vec<u8>,
+    // Bytes the TX FIFO still accepts, unlimited if None.
+    tx_room: Option<usize>,
+    tx_error: Option<SerialError>,
+    pub rx_interrupt: bool,
+    pub tx_interrupt: bool,
+    pub modem_interrupt: bool,
+    /// The modem status lines which are asserted.
+    pub modem_lines: ModemLines,
+}
+
+impl MockUart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `data` arrive on the line.
+    pub fn push_rx(&mut self, data: &[u8]) {
+        self.rx.extend(data.iter().map(|b| RxEvent::Byte(*b)));
+    }
+
+    /// Makes the UART report `error` once the bytes received so far have
+    /// been read.
+    pub fn push_rx_error(&mut self, error: SerialError) {
+        self.rx.push_back(RxEvent::Error(error));
+    }
+
+    /// Events not read by the driver yet.
+    pub fn rx_pending(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Limits the bytes the TX FIFO accepts until the next call. `None`
+    /// lifts the limit.
+    pub fn set_tx_room(&mut self, room: Option<usize>) {
+        self.tx_room = room;
+    }
+
+    /// Makes the next write fail with `error`.
+    pub fn set_tx_error(&mut self, error: SerialError) {
+        self.tx_error = Some(error);
+    }
+
+    /// Returns and forgets what has been written.
+    pub fn take_tx(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.tx)
+    }
+}
+
+impl ErrorType for MockUart {
+    type Error = SerialError;
+}
+
+impl ReadReady for MockUart {
+    fn read_ready(&mut self) -> Result<bool, SerialError> {
+        Ok(!self.rx.is_empty())
+    }
+}
+
+impl WriteReady for MockUart {
+    fn write_ready(&mut self) -> Result<bool, SerialError> {
+        Ok(self.tx_room != Some(0))
+    }
+}
+
+impl Read for MockUart {
+    // Stops before an error, which is then returned by the next read.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.rx.front() {
+                Some(RxEvent::Byte(b)) => {
+                    buf[n] = *b;
+                    n += 1;
+                    self.rx.pop_front();
+                }
+                Some(RxEvent::Error(e)) if n == 0 => {
+                    let e = e.clone();
+                    self.rx.pop_front();
+                    return Err(e);
+                }
+                _ => break,
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockUart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
+        if let Some(e) = self.tx_error.take() {
+            return Err(e);
+        }
+        let n = self.tx_room.map_or(buf.len(), |room| room.min(buf.len()));
+        self.tx.extend_from_slice(&buf[..n]);
+        if let Some(room) = self.tx_room.as_mut() {
+            *room -= n;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), SerialError> {
+        Ok(())
+    }
+}
+
+impl UartOps for MockUart {
+    fn setup(&mut self, _termios: &Termios) -> Result<(), SerialError> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<(), SerialError> {
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, SerialError> {
+        let mut byte = [0];
+        match self.read(&mut byte)? {
+            0 => Err(SerialError::BufferEmpty),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), SerialError> {
+        match self.write(&[byte])? {
+            0 => Err(SerialError::Overrun),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<(), SerialError> {
+        for byte in s.bytes() {
+            self.write_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    fn ioctl(&mut self, _request: u32, _arg: usize) -> Result<(), SerialError> {
+        Ok(())
+    }
+
+    fn set_rx_interrupt(&mut self, enable: bool) {
+        self.rx_interrupt = enable;
+    }
+
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        self.tx_interrupt = enable;
+    }
+
+    fn clear_rx_interrupt(&mut self) {}
+
+    fn clear_tx_interrupt(&mut self) {}
+
+    fn modem_lines(&self) -> ModemLines {
+        self.modem_lines
+    }
+
+    fn set_modem_interrupt(&mut self, enable: bool) {
+        self.modem_interrupt = enable;
+    }
+}
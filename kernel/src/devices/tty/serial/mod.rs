@@ -12,23 +12,70 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(magic_sysrq)]
+use crate::devices::tty::sysrq::{self, SysrqState};
 use crate::{
-    devices::{tty::termios::Termios, Device, DeviceBase, DeviceClass, DeviceId, DeviceRequest},
+    devices::{
+        console::{self, EarlyConsole},
+        devno::{SERIAL_MINOR_BASE, TTY_MAJOR},
+        tty::termios::{CcIndex, Cflags, Iflags, Oflags, Termios},
+        Device, DeviceBase, DeviceClass, DeviceId, DeviceRequest,
+    },
+    error::{code, Error},
+    events::{self, Event},
     irq,
     sync::{
         atomic_wait::{atomic_wait, atomic_wake},
         spinlock::SpinLock,
+        KOnce,
     },
+    thread, types,
+    vfs::poll::{PollEvents, PollQueue, PollWaiter},
+    workqueue::{self, Work},
 };
 use alloc::{format, string::String, sync::Arc};
+use bitflags::bitflags;
 use blueos_infra::ringbuffer::BoxedRingBuffer;
 use blueos_kconfig::{SERIAL_RX_FIFO_SIZE, SERIAL_TX_FIFO_SIZE};
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use delegate::delegate;
 use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+#[cfg(test)]
+mod mock;
+
+// Linux's numbers, which predate the ioctl encoding.
+crate::ioctl_read_bad!(
+    /// Reads the modem status lines of a serial port, as [`ModemLines`]
+    /// bits.
+    pub TIOCMGET, 0x5415, u32
+);
+crate::ioctl_read_bad!(
+    /// Reads the [`SerialCounters`] of a serial port.
+    pub TIOCGICOUNT, 0x545d, SerialCounters
+);
+crate::ioctl_read!(
+    /// Reads the FIFO levels a serial port uses, BlueOS extension.
+    pub TIOCGFIFO, b'T', 0x60, FifoLevels
+);
+crate::ioctl_write!(
+    /// Sets the FIFO levels of a serial port, BlueOS extension.
+    pub TIOCSFIFO, b'T', 0x61, FifoLevels
+);
 
 const SERIAL_RX_FIFO_MIN_SIZE: usize = 256;
 const SERIAL_TX_FIFO_MIN_SIZE: usize = 256;
+// With IXOFF, the other end is stopped when less than this fraction of the
+// RX fifo is free, and restarted once less than it is used.
+const RX_THROTTLE_FRACTION: usize = 4;
+
+/// Maximum deviation between a requested baud rate and the one the UART
+/// actually generates, in per mille.
+pub const BAUD_RATE_TOLERANCE: u32 = 20;
+/// IrDA SIR pulses only last 3/16 of a bit, which leaves the receiver less
+/// margin than NRZ framing.
+pub const SIR_BAUD_RATE_TOLERANCE: u32 = 8;
 
 #[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
 pub enum SerialError {
@@ -48,6 +95,8 @@ pub enum SerialError {
     InvalidParameter,
     #[error("Operation timed out")]
     TimedOut,
+    #[error("Baud rate out of tolerance")]
+    BaudRate,
 }
 
 impl embedded_io::Error for SerialError {
@@ -55,25 +104,80 @@ impl embedded_io::Error for SerialError {
         match self {
             Self::Break | Self::Overrun => ErrorKind::Other,
             Self::Framing | Self::Parity => ErrorKind::InvalidData,
-            Self::BufferEmpty | Self::InvalidParameter => ErrorKind::InvalidInput,
+            Self::BufferEmpty | Self::InvalidParameter | Self::BaudRate => ErrorKind::InvalidInput,
             Self::DeviceError => ErrorKind::Other,
             Self::TimedOut => ErrorKind::TimedOut,
         }
     }
 }
 
-impl From<SerialError> for ErrorKind {
+impl From<SerialError> for Error {
     fn from(error: SerialError) -> Self {
         match error {
-            SerialError::Break | SerialError::Overrun => ErrorKind::Other,
-            SerialError::Framing | SerialError::Parity => ErrorKind::InvalidData,
-            SerialError::BufferEmpty | SerialError::InvalidParameter => ErrorKind::InvalidInput,
-            SerialError::DeviceError => ErrorKind::Other,
-            SerialError::TimedOut => ErrorKind::TimedOut,
+            SerialError::Overrun => code::EOVERFLOW,
+            SerialError::Break | SerialError::DeviceError => code::EIO,
+            SerialError::Framing | SerialError::Parity => code::EBADMSG,
+            SerialError::BufferEmpty => code::EAGAIN,
+            SerialError::InvalidParameter | SerialError::BaudRate => code::EINVAL,
+            SerialError::TimedOut => code::ETIMEDOUT,
         }
     }
 }
 
+bitflags! {
+    /// Modem status lines, with the values of Linux's `TIOCM_*`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct ModemLines: u32 {
+        /// Clear to send.
+        const CTS = 0x020;
+        /// Data carrier detect.
+        const DCD = 0x040;
+        /// Ring indicator.
+        const RI = 0x080;
+        /// Data set ready.
+        const DSR = 0x100;
+    }
+}
+
+/// Events on a serial port since it was opened, with the layout of Linux's
+/// `struct serial_icounter_struct`.
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct SerialCounters {
+    /// Changes of each modem status line.
+    pub cts: u32,
+    pub dsr: u32,
+    pub rng: u32,
+    pub dcd: u32,
+    /// Bytes received and sent.
+    pub rx: u32,
+    pub tx: u32,
+    /// Errors reported by the UART.
+    pub frame: u32,
+    pub overrun: u32,
+    pub parity: u32,
+    pub brk: u32,
+    /// Kept for the layout, data which doesn't fit in the RX fifo is left
+    /// in the UART and counted as an overrun there.
+    pub buf_overrun: u32,
+    pub reserved: [u32; 9],
+}
+
+/// Fill levels of the UART FIFOs, in bytes, at which it interrupts: RX
+/// when at least `rx` bytes were received, TX when at most `tx` bytes are
+/// left to send. A level of 0 lets the driver pick
+/// one for the baud rate.
+#[repr(C)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout,
+)]
+pub struct FifoLevels {
+    pub rx: u32,
+    pub tx: u32,
+}
+
 // TODO: add DMA support
 pub trait UartOps:
     Read
@@ -94,6 +198,106 @@ pub trait UartOps:
     fn set_tx_interrupt(&mut self, enable: bool);
     fn clear_rx_interrupt(&mut self);
     fn clear_tx_interrupt(&mut self);
+
+    /// Returns the baud rate the UART generates when `baud_rate` is
+    /// requested, after rounding its divisor. Drivers whose rate can't be
+    /// programmed keep the default, which accepts any rate.
+    fn closest_baud_rate(&self, baud_rate: u32) -> Result<u32, SerialError> {
+        Ok(baud_rate)
+    }
+
+    /// Sets the levels at which the UART interrupts, which it rounds to ones it supports. Drivers of UARTs
+    /// without configurable FIFOs keep the default, which refuses.
+    fn set_fifo_levels(&mut self, _levels: FifoLevels) -> Result<(), SerialError> {
+        Err(SerialError::InvalidParameter)
+    }
+
+    /// Returns the levels the UART uses, 0 for a FIFO without level.
+    fn fifo_levels(&self) -> FifoLevels {
+        FifoLevels::default()
+    }
+
+    /// Returns the modem status lines which are asserted. UARTs without
+    /// them report none.
+    fn modem_lines(&self) -> ModemLines {
+        ModemLines::empty()
+    }
+
+    /// Lets the UART interrupt when a modem status line changes.
+    fn set_modem_interrupt(&mut self, _enable: bool) {}
+
+    fn clear_modem_interrupt(&mut self) {}
+}
+
+/// Deviation between the requested and the actual baud rate, in per mille.
+pub fn baud_rate_deviation(requested: u32, actual: u32) -> u32 {
+    (requested.abs_diff(actual) as u64 * 1000 / requested as u64) as u32
+}
+
+/// Checks that the UART can generate the speeds of `termios` closely
+/// enough for the other end to sample the line reliably.
+pub fn check_baud_rate<U: UartOps + ?Sized>(
+    uart_ops: &U,
+    termios: &Termios,
+) -> Result<(), SerialError> {
+    let tolerance = if termios.cflag.contains(Cflags::CSIR) {
+        SIR_BAUD_RATE_TOLERANCE
+    } else {
+        BAUD_RATE_TOLERANCE
+    };
+    for requested in [termios.getispeed(), termios.getospeed()] {
+        if requested == 0 {
+            return Err(SerialError::InvalidParameter);
+        }
+        let actual = uart_ops.closest_baud_rate(requested)?;
+        if baud_rate_deviation(requested, actual) > tolerance {
+            return Err(SerialError::BaudRate);
+        }
+    }
+    Ok(())
+}
+
+// What `byte` is sent as with the output processing set by `oflag`, if it
+// isn't sent as is.
+fn translate_output(oflag: Oflags, byte: u8) -> Option<&'static str> {
+    if !oflag.contains(Oflags::OPOST) {
+        return None;
+    }
+    match byte {
+        b'\n' if oflag.contains(Oflags::ONLCR) => Some("\r\n"),
+        b'\r' if oflag.contains(Oflags::OCRNL) => Some("\n"),
+        _ => None,
+    }
+}
+
+// Writes `s` by polling the UART, with the output processing set by
+// `oflag`.
+fn write_polled(uart_ops: &mut dyn UartOps, oflag: Oflags, s: &str) {
+    let mut rest = s;
+    while let Some(i) = rest
+        .bytes()
+        .position(|byte| translate_output(oflag, byte).is_some())
+    {
+        let _ = uart_ops.write_str(&rest[..i]);
+        let _ = uart_ops.write_str(translate_output(oflag, rest.as_bytes()[i]).unwrap());
+        rest = &rest[i + 1..];
+    }
+    let _ = uart_ops.write_str(rest);
+}
+
+// Sends a flow control character right away, ahead of the queued output.
+fn send_xchar(uart_ops: &mut dyn UartOps, ch: u8) -> bool {
+    // A control character set to 0 is disabled.
+    ch != 0 && matches!(uart_ops.write(&[ch]), Ok(1))
+}
+
+// What TIOCGICOUNT reports, and the modem status lines as last seen.
+#[derive(Debug, Default)]
+struct Icount {
+    counters: SerialCounters,
+    modem_lines: ModemLines,
+    // A modem status change hasn't been read with TIOCGICOUNT yet.
+    modem_changed: bool,
 }
 
 #[derive(Debug)]
@@ -129,9 +333,23 @@ impl SerialTxFifo {
 pub struct Serial {
     base: DeviceBase,
     index: u32,
-    pub termios: Termios,
+    // Set with Config requests, it also drives the output processing and
+    // the software flow control.
+    termios: SpinLock<Termios>,
+    // Output was stopped by the STOP character, with IXON.
+    tx_stopped: AtomicBool,
+    // The STOP character was sent to the other end, with IXOFF.
+    rx_throttled: AtomicBool,
     rx_fifo: SerialRxFifo,
     tx_fifo: SerialTxFifo,
+    #[cfg(magic_sysrq)]
+    sysrq: SysrqState,
+    icount: SpinLock<Icount>,
+    // Notified when data arrives, room is made in the TX fifo or a modem
+    // status line changes.
+    poll_queue: PollQueue,
+    // Scheduled when data arrives, see `set_rx_work`.
+    rx_work: KOnce<types::Arc<Work>>,
     pub uart_ops: Arc<SpinLock<dyn UartOps>>,
 }
 
@@ -140,13 +358,93 @@ impl Serial {
         Self {
             base: DeviceBase::new(),
             index,
-            termios,
+            termios: SpinLock::new(termios),
+            tx_stopped: AtomicBool::new(false),
+            rx_throttled: AtomicBool::new(false),
             rx_fifo: SerialRxFifo::new(SERIAL_RX_FIFO_SIZE.max(SERIAL_RX_FIFO_MIN_SIZE)),
             tx_fifo: SerialTxFifo::new(SERIAL_TX_FIFO_SIZE.max(SERIAL_TX_FIFO_MIN_SIZE)),
+            #[cfg(magic_sysrq)]
+            sysrq: SysrqState::new(),
+            icount: SpinLock::new(Icount::default()),
+            poll_queue: PollQueue::new(),
+            rx_work: KOnce::new(),
             uart_ops,
         }
     }
 
+    pub fn termios(&self) -> Termios {
+        *self.termios.irqsave_lock()
+    }
+
+    // Takes the settings of `termios`, once the UART has. Output stopped or
+    // input throttled is restarted if flow control is turned off.
+    fn set_termios(&self, termios: Termios) {
+        *self.termios.irqsave_lock() = termios;
+        if !termios.iflag.contains(Iflags::IXON) && self.tx_stopped.swap(false, Ordering::AcqRel) {
+            let _ = self.xmitchars();
+        }
+        if !termios.iflag.contains(Iflags::IXOFF) {
+            self.unthrottle(&termios);
+        }
+    }
+
+    // Restarts the other end if it was sent the STOP character.
+    fn unthrottle(&self, termios: &Termios) {
+        if !self.rx_throttled.load(Ordering::Acquire) {
+            return;
+        }
+        let mut uart_ops = self.uart_ops.irqsave_lock();
+        if self.rx_throttled.load(Ordering::Acquire)
+            && send_xchar(&mut *uart_ops, termios.cc[CcIndex::Vstart as usize])
+        {
+            self.rx_throttled.store(false, Ordering::Release);
+        }
+    }
+
+    // Applies software flow control to `buf`, just received: with IXON the
+    // STOP and START characters stop and restart the output and are
+    // dropped, with IXANY as well any other byte restarts it. Returns the
+    // length of what's left, and whether the output was restarted.
+    fn flow_control(&self, termios: &Termios, buf: &mut [u8]) -> (usize, bool) {
+        if !termios.iflag.contains(Iflags::IXON) {
+            return (buf.len(), false);
+        }
+        let stop = termios.cc[CcIndex::Vstop as usize];
+        let start = termios.cc[CcIndex::Vstart as usize];
+        let mut restarted = false;
+        let mut len = 0;
+        for i in 0..buf.len() {
+            let ch = buf[i];
+            if ch != 0 && ch == stop {
+                self.tx_stopped.store(true, Ordering::Release);
+                continue;
+            }
+            let is_start = ch != 0 && ch == start;
+            if is_start || termios.iflag.contains(Iflags::IXANY) {
+                restarted |= self.tx_stopped.swap(false, Ordering::AcqRel);
+            }
+            if !is_start {
+                buf[len] = ch;
+                len += 1;
+            }
+        }
+        (len, restarted)
+    }
+
+    /// Has `work` scheduled on the system work queue whenever data is
+    /// received, for a layer above which has to process the input without
+    /// waiting for a read. Only the first call has an effect.
+    pub fn set_rx_work(&self, work: types::Arc<Work>) {
+        self.rx_work.call_once(|| work);
+    }
+
+    /// Watches this port for magic SysRq sequences. A no-op unless
+    /// MAGIC_SYSRQ is enabled.
+    pub fn enable_sysrq(&self) {
+        #[cfg(magic_sysrq)]
+        self.sysrq.enable();
+    }
+
     delegate! {
         to self.base {
             fn inc_open_count(&self) -> u32;
@@ -185,10 +483,17 @@ impl Serial {
                 n += slice_len;
             }
             reader.pop_done(n);
+            if self.rx_throttled.load(Ordering::Acquire) {
+                let used: usize = reader.pop_slices().iter().map(|slice| slice.len()).sum();
+                if used < self.rx_fifo.rb.capacity() / RX_THROTTLE_FRACTION {
+                    self.unthrottle(&self.termios());
+                }
+            }
 
             if !is_nonblocking {
                 // if the available data is less than the requested data, wait for data
                 if n == 0 {
+                    let _wait = thread::wait_on_device("serial");
                     atomic_wait(&self.rx_fifo.futex, 0, None).map_err(|_| SerialError::TimedOut)?;
                 } else {
                     break;
@@ -202,22 +507,45 @@ impl Serial {
     }
 
     fn fifo_tx(&self, buf: &[u8], is_nonblocking: bool) -> Result<usize, SerialError> {
-        let len = buf.len();
+        self.fifo_tx_vectored(&[buf], is_nonblocking)
+    }
+
+    // Queues `bufs` one after the other, the UART is kicked once for all of
+    // them. Returns how many bytes of `bufs` were queued, which the output
+    // processing may have turned into more.
+    fn fifo_tx_vectored(&self, bufs: &[&[u8]], is_nonblocking: bool) -> Result<usize, SerialError> {
+        let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let oflag = self.termios.irqsave_lock().oflag;
         let mut count = 0;
+        // Where the next byte to queue is in `bufs`.
+        let (mut i, mut offset) = (0, 0);
         let mut writer = unsafe { self.tx_fifo.rb.writer() };
 
         loop {
-            // Get all slice for writing
-            let slices = writer.push_slices();
+            // Fill the free space of the fifo, as a single run of bytes.
+            let [first, second] = writer.push_slices();
+            let mut room = first.len() + second.len();
+            let mut slots = first.iter_mut().chain(second.iter_mut());
             let mut n = 0;
-            for slice in slices {
-                if slice.is_empty() {
+            while i < bufs.len() {
+                let Some(&byte) = bufs[i].get(offset) else {
+                    i += 1;
+                    offset = 0;
                     continue;
+                };
+                let plain = [byte];
+                let out = translate_output(oflag, byte).map_or(&plain[..], str::as_bytes);
+                // A translated byte is queued whole or not at all.
+                if out.len() > room {
+                    break;
                 }
-                let slice_len = slice.len().min(len - count);
-                slice[..slice_len].copy_from_slice(&buf[count..count + slice_len]);
-                count += slice_len;
-                n += slice_len;
+                for (b, slot) in out.iter().zip(slots.by_ref()) {
+                    *slot = *b;
+                }
+                room -= out.len();
+                n += out.len();
+                count += 1;
+                offset += 1;
             }
             if n > 0 {
                 writer.push_done(n);
@@ -231,6 +559,7 @@ impl Serial {
             if !is_nonblocking && !irq::is_in_irq() {
                 if !writer.is_empty() {
                     // wait for data to be written
+                    let _wait = thread::wait_on_device("serial");
                     atomic_wait(&self.tx_fifo.futex, 0, None).map_err(|_| SerialError::TimedOut)?;
                     self.uart_ops.irqsave_lock().set_tx_interrupt(false);
                 } else if count >= len {
@@ -251,6 +580,11 @@ impl Serial {
         let mut nbytes: usize = 0;
         {
             let mut uart_ops = self.uart_ops.irqsave_lock();
+            // Sending resumes once the START character is received.
+            if self.tx_stopped.load(Ordering::Acquire) {
+                uart_ops.set_tx_interrupt(false);
+                return Ok(0);
+            }
             // Safety: tx_fifo reader is only accessed in the UART interrupt handler
             let mut reader = unsafe { self.tx_fifo.rb.reader() };
             while !reader.is_empty() && uart_ops.write_ready()? {
@@ -269,41 +603,197 @@ impl Serial {
         }
 
         if nbytes > 0 {
-            // TODO: add notify for poll/select
+            self.count_bytes(0, nbytes);
             let _ = atomic_wake(&self.tx_fifo.futex, 1);
+            self.poll_queue.notify();
         }
 
         Ok(nbytes)
     }
 
+    /// Writes `s` by polling the UART, after synchronously draining what is
+    /// still queued in the TX fifo. The TX interrupt is left disabled, so
+    /// this is meant for the panic path where it will never fire again.
+    /// Output stopped by flow control is sent anyway.
+    pub fn emergency_write(&self, s: &str) {
+        // Don't spin on a lock the panicking context may hold.
+        let oflag = self
+            .termios
+            .try_irqsave_lock()
+            .map_or(Oflags::empty(), |termios| termios.oflag);
+        let Some(mut uart_ops) = self.uart_ops.try_irqsave_lock() else {
+            return;
+        };
+        uart_ops.set_tx_interrupt(false);
+        // Safety: the TX interrupt is disabled and we hold the uart lock, so
+        // xmitchars can't race with us on the reader side.
+        let mut reader = unsafe { self.tx_fifo.rb.reader() };
+        while !reader.is_empty() {
+            let buf = reader.pop_slice();
+            let n = buf.len();
+            for byte in buf {
+                let _ = uart_ops.write_byte(*byte);
+            }
+            reader.pop_done(n);
+        }
+        write_polled(&mut *uart_ops, oflag, s);
+    }
+
     /// this Function is called from the UART interrupt handler
     /// when an interrupt is received indicating that there is more data in the
     /// receive FIFO
     pub fn recvchars(&self) -> Result<usize, SerialError> {
+        let termios = self.termios();
         let mut nbytes: usize = 0;
+        let mut restarted = false;
+        #[cfg(magic_sysrq)]
+        let mut sysrq_key = None;
         {
             let mut uart_ops = self.uart_ops.irqsave_lock();
             // Safety: rx_fifo writer is only accessed in the UART interrupt handler
             let mut writer = unsafe { self.rx_fifo.rb.writer() };
-            while !writer.is_full() && uart_ops.read_ready()? {
+            let mut result = Ok(());
+            while !writer.is_full() {
+                match uart_ops.read_ready() {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
                 let buf = writer.push_slice();
                 match uart_ops.read(buf) {
                     Ok(n) => {
+                        #[cfg(magic_sysrq)]
+                        let n = {
+                            let (n, key) = self.sysrq.filter(&mut buf[..n]);
+                            sysrq_key = key.or(sysrq_key);
+                            n
+                        };
+                        let (n, restart) = self.flow_control(&termios, &mut buf[..n]);
+                        restarted |= restart;
                         nbytes += n;
                         writer.push_done(n);
                     }
-                    Err(e) => return Err(e),
+                    #[cfg(magic_sysrq)]
+                    Err(SerialError::Break) => {
+                        self.count_error(&SerialError::Break);
+                        self.sysrq.arm();
+                    }
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
                 }
             }
+            let room: usize = writer.push_slices().iter().map(|slice| slice.len()).sum();
+            drop(writer);
+            if termios.iflag.contains(Iflags::IXOFF)
+                && room < self.rx_fifo.rb.capacity() / RX_THROTTLE_FRACTION
+                && !self.rx_throttled.load(Ordering::Acquire)
+                && send_xchar(&mut *uart_ops, termios.cc[CcIndex::Vstop as usize])
+            {
+                self.rx_throttled.store(true, Ordering::Release);
+            }
+            self.count_bytes(nbytes, 0);
+            if let Err(e) = &result {
+                self.count_error(e);
+            }
+            result?;
+        }
+
+        // SysRq actions print through the early console, which takes the
+        // UART lock, so they can only run once it has been released.
+        #[cfg(magic_sysrq)]
+        if let Some(key) = sysrq_key {
+            sysrq::handle(key);
+        }
+
+        if restarted {
+            let _ = self.xmitchars();
         }
 
-        // TODO: add notify for poll/select
         if nbytes > 0 {
             let _ = atomic_wake(&self.rx_fifo.futex, 1);
+            self.poll_queue.notify();
+            if let Some(work) = self.rx_work.get() {
+                workqueue::schedule_work(work);
+            }
         }
 
         Ok(nbytes)
     }
+
+    // Counts the bytes moved for TIOCGICOUNT.
+    fn count_bytes(&self, rx: usize, tx: usize) {
+        let counters = &mut self.icount.irqsave_lock().counters;
+        counters.rx = counters.rx.wrapping_add(rx as u32);
+        counters.tx = counters.tx.wrapping_add(tx as u32);
+    }
+
+    // Counts an error reported by the UART for TIOCGICOUNT.
+    fn count_error(&self, error: &SerialError) {
+        let counters = &mut self.icount.irqsave_lock().counters;
+        let counter = match error {
+            SerialError::Framing => &mut counters.frame,
+            SerialError::Overrun => {
+                // The UART lost at least the byte which didn't fit.
+                events::count(Event::RxDropped);
+                &mut counters.overrun
+            }
+            SerialError::Parity => &mut counters.parity,
+            SerialError::Break => &mut counters.brk,
+            _ => return,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    /// Called from the UART interrupt handler to pick up changes of the
+    /// modem status lines. A change is counted for TIOCGICOUNT and raises
+    /// POLLPRI until the counters are read.
+    pub fn update_modem_status(&self) {
+        let lines = {
+            let mut uart_ops = self.uart_ops.irqsave_lock();
+            uart_ops.clear_modem_interrupt();
+            uart_ops.modem_lines()
+        };
+        {
+            let mut icount = self.icount.irqsave_lock();
+            let changed = icount.modem_lines ^ lines;
+            if changed.is_empty() {
+                return;
+            }
+            let counters = &mut icount.counters;
+            for (line, counter) in [
+                (ModemLines::CTS, &mut counters.cts),
+                (ModemLines::DSR, &mut counters.dsr),
+                (ModemLines::RI, &mut counters.rng),
+                (ModemLines::DCD, &mut counters.dcd),
+            ] {
+                if changed.contains(line) {
+                    *counter = counter.wrapping_add(1);
+                }
+            }
+            icount.modem_lines = lines;
+            icount.modem_changed = true;
+        }
+        self.poll_queue.notify();
+    }
+}
+
+impl EarlyConsole for Serial {
+    // Only a panic takes the port over. Otherwise `s` is written by polling
+    // under the UART lock, leaving the fifo and the TX interrupt to the
+    // normal path.
+    fn write_str(&self, s: &str) {
+        if console::is_panicking() {
+            self.emergency_write(s);
+            return;
+        }
+        let oflag = self.termios.irqsave_lock().oflag;
+        write_polled(&mut *self.uart_ops.irqsave_lock(), oflag, s);
+    }
 }
 
 impl Device for Serial {
@@ -316,14 +806,21 @@ impl Device for Serial {
     }
 
     fn id(&self) -> DeviceId {
-        DeviceId::new(4, 64 + self.index as usize)
+        DeviceId::new(TTY_MAJOR, SERIAL_MINOR_BASE + self.index as usize)
     }
 
-    fn open(&self) -> Result<(), ErrorKind> {
+    fn open(&self) -> Result<(), Error> {
         if !self.is_opened() {
+            let termios = self.termios();
             let mut uart_ops = self.uart_ops.irqsave_lock();
-            uart_ops.setup(&self.termios)?;
+            check_baud_rate(&*uart_ops, &termios)?;
+            uart_ops.setup(&termios)?;
             uart_ops.set_rx_interrupt(true);
+            *self.icount.irqsave_lock() = Icount {
+                modem_lines: uart_ops.modem_lines(),
+                ..Default::default()
+            };
+            uart_ops.set_modem_interrupt(true);
         }
 
         // Update device state
@@ -331,9 +828,9 @@ impl Device for Serial {
         Ok(())
     }
 
-    fn close(&self) -> Result<(), ErrorKind> {
+    fn close(&self) -> Result<(), Error> {
         if !self.is_opened() {
-            return Err(ErrorKind::NotFound);
+            return Err(code::ENOENT);
         }
 
         if self.dec_open_count() == 0 {
@@ -341,22 +838,431 @@ impl Device for Serial {
             self.tx_disable()?;
 
             let mut uart_ops = self.uart_ops.irqsave_lock();
+            uart_ops.set_modem_interrupt(false);
             uart_ops.ioctl(DeviceRequest::Close as u32, 0)?;
         }
 
         Ok(())
     }
 
-    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, ErrorKind> {
-        self.fifo_rx(buf, is_nonblocking).map_err(|e| e.into())
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        Ok(self.fifo_rx(buf, is_nonblocking)?)
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, Error> {
+        Ok(self.fifo_tx(buf, is_nonblocking)?)
     }
 
-    fn write(&self, _pos: u64, buf: &[u8], is_nonblocking: bool) -> Result<usize, ErrorKind> {
-        self.fifo_tx(buf, is_nonblocking).map_err(|e| e.into())
+    fn write_vectored(
+        &self,
+        _pos: u64,
+        bufs: &[&[u8]],
+        is_nonblocking: bool,
+    ) -> Result<usize, Error> {
+        Ok(self.fifo_tx_vectored(bufs, is_nonblocking)?)
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        if let Some(waiter) = waiter {
+            self.poll_queue.register(waiter);
+        }
+        let mut revents = PollEvents::empty();
+        if !self.rx_fifo.rb.is_empty() {
+            revents |= PollEvents::POLLIN;
+        }
+        if !self.tx_fifo.rb.is_full() {
+            revents |= PollEvents::POLLOUT;
+        }
+        if self.icount.irqsave_lock().modem_changed {
+            revents |= PollEvents::POLLPRI;
+        }
+        revents & events
     }
 
-    fn ioctl(&self, request: u32, arg: usize) -> Result<(), ErrorKind> {
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if TIOCMGET.matches(request) {
+            let lines = self.uart_ops.irqsave_lock().modem_lines();
+            return TIOCMGET.copy_out(arg, &lines.bits());
+        }
+        if TIOCGICOUNT.matches(request) {
+            let counters = {
+                let mut icount = self.icount.irqsave_lock();
+                icount.modem_changed = false;
+                icount.counters
+            };
+            return TIOCGICOUNT.copy_out(arg, &counters);
+        }
+        if TIOCGFIFO.matches(request) {
+            let levels = self.uart_ops.irqsave_lock().fifo_levels();
+            return TIOCGFIFO.copy_out(arg, &levels);
+        }
+        if TIOCSFIFO.matches(request) {
+            let levels = TIOCSFIFO.copy_in(arg)?;
+            return Ok(self.uart_ops.irqsave_lock().set_fifo_levels(levels)?);
+        }
         let mut uart_ops = self.uart_ops.irqsave_lock();
-        uart_ops.ioctl(request, arg).map_err(|e| e.into())
+        if DeviceRequest::from(request) != DeviceRequest::Config {
+            return Ok(uart_ops.ioctl(request, arg)?);
+        }
+        // SAFETY: Config requests carry a pointer to a Termios.
+        let termios = unsafe { *(arg as *const Termios) };
+        check_baud_rate(&*uart_ops, &termios)?;
+        uart_ops.ioctl(request, arg)?;
+        drop(uart_ops);
+        self.set_termios(termios);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::MockUart, *};
+    use crate::{devices::tty::termios::Lflags, scheduler, thread};
+    use alloc::vec::Vec;
+    use blueos_test_macro::test;
+
+    fn mock_serial_with(termios: Termios) -> (Arc<Serial>, Arc<SpinLock<MockUart>>) {
+        let uart = Arc::new(SpinLock::new(MockUart::new()));
+        let serial = Arc::new(Serial::new(0, termios, uart.clone()));
+        (serial, uart)
+    }
+
+    // A port moving bytes as is, without output processing nor flow
+    // control.
+    fn mock_serial() -> (Arc<Serial>, Arc<SpinLock<MockUart>>) {
+        mock_serial_with(raw_termios(Iflags::empty(), Oflags::empty()))
+    }
+
+    fn raw_termios(iflag: Iflags, oflag: Oflags) -> Termios {
+        Termios::new(
+            iflag,
+            oflag,
+            Cflags::default(),
+            Lflags::empty(),
+            115200,
+            115200,
+        )
+    }
+
+    fn configure(serial: &Serial, termios: Termios) {
+        assert_eq!(
+            serial.ioctl(
+                DeviceRequest::Config as u32,
+                &termios as *const Termios as usize
+            ),
+            Ok(())
+        );
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_serial_recv_wrap_around() {
+        let (serial, uart) = mock_serial();
+        let capacity = serial.rx_fifo.rb.capacity();
+        let data = pattern(capacity + 10);
+        uart.lock().push_rx(&data);
+
+        // What doesn't fit in the fifo stays in the UART.
+        assert_eq!(serial.recvchars(), Ok(capacity));
+        assert_eq!(uart.lock().rx_pending(), 10);
+
+        let mut buf = alloc::vec![0u8; capacity];
+        assert_eq!(serial.fifo_rx(&mut buf[..20], true), Ok(20));
+        assert_eq!(buf[..20], data[..20]);
+
+        // The rest is pushed at the start of the buffer, and read back in
+        // order.
+        assert_eq!(serial.recvchars(), Ok(10));
+        assert_eq!(serial.fifo_rx(&mut buf, true), Ok(capacity - 10));
+        assert_eq!(buf[..capacity - 10], data[20..]);
+        assert_eq!(serial.fifo_rx(&mut buf, true), Ok(0));
+    }
+
+    #[test]
+    fn test_serial_recv_error() {
+        let (serial, uart) = mock_serial();
+        uart.lock().push_rx(b"ab");
+        uart.lock().push_rx_error(SerialError::Overrun);
+        uart.lock().push_rx(b"c");
+
+        // Bytes received before the error are kept.
+        assert_eq!(serial.recvchars(), Err(SerialError::Overrun));
+        let mut buf = [0u8; 4];
+        assert_eq!(serial.fifo_rx(&mut buf, true), Ok(2));
+        assert_eq!(&buf[..2], b"ab");
+
+        assert_eq!(serial.recvchars(), Ok(1));
+        assert_eq!(serial.fifo_rx(&mut buf, true), Ok(1));
+        assert_eq!(buf[0], b'c');
+
+        let counters = serial.icount.lock().counters;
+        assert_eq!((counters.rx, counters.overrun), (3, 1));
+    }
+
+    #[test]
+    fn test_serial_xmit_partial() {
+        let (serial, uart) = mock_serial();
+        uart.lock().set_tx_room(Some(4));
+
+        // The UART takes what it has room for, the rest waits in the fifo
+        // for the TX interrupt.
+        assert_eq!(serial.fifo_tx(b"hello world", true), Ok(11));
+        assert_eq!(uart.lock().take_tx(), b"hell");
+        assert!(uart.lock().tx_interrupt);
+
+        uart.lock().set_tx_room(None);
+        assert_eq!(serial.xmitchars(), Ok(7));
+        assert_eq!(uart.lock().take_tx(), b"o world");
+        assert!(!uart.lock().tx_interrupt);
+        assert_eq!(serial.xmitchars(), Ok(0));
+    }
+
+    #[test]
+    fn test_serial_xmit_error() {
+        let (serial, uart) = mock_serial();
+        uart.lock().set_tx_error(SerialError::DeviceError);
+        assert_eq!(serial.fifo_tx(b"x", true), Ok(1));
+        assert!(uart.lock().take_tx().is_empty());
+
+        // The data is still queued and goes out on the next attempt.
+        assert_eq!(serial.xmitchars(), Ok(1));
+        assert_eq!(uart.lock().take_tx(), b"x");
+    }
+
+    #[test]
+    fn test_serial_xmit_fifo_full() {
+        let (serial, uart) = mock_serial();
+        uart.lock().set_tx_room(Some(0));
+        let capacity = serial.tx_fifo.rb.capacity();
+        let data = pattern(capacity + 1);
+
+        // A nonblocking write only queues what fits.
+        assert_eq!(serial.fifo_tx(&data, true), Ok(capacity));
+        assert_eq!(serial.fifo_tx(&data[capacity..], true), Ok(0));
+
+        uart.lock().set_tx_room(None);
+        assert_eq!(serial.xmitchars(), Ok(capacity));
+        assert_eq!(serial.fifo_tx(&data[capacity..], true), Ok(1));
+        assert_eq!(uart.lock().take_tx(), data);
+    }
+
+    #[test]
+    fn test_serial_write_vectored() {
+        let (serial, uart) = mock_serial();
+        let bufs: [&[u8]; 3] = [b"hello ", b"", b"world"];
+        assert_eq!(serial.write_vectored(0, &bufs, true), Ok(11));
+        serial.xmitchars().unwrap();
+        assert_eq!(uart.lock().take_tx(), b"hello world");
+
+        // Only what fits is queued, across buffers.
+        uart.lock().set_tx_room(Some(0));
+        let capacity = serial.tx_fifo.rb.capacity();
+        let data = pattern(capacity + 2);
+        let (first, second) = data.split_at(capacity - 1);
+        assert_eq!(
+            serial.write_vectored(0, &[first, second], true),
+            Ok(capacity)
+        );
+        uart.lock().set_tx_room(None);
+        serial.xmitchars().unwrap();
+        assert_eq!(uart.lock().take_tx(), &data[..capacity]);
+    }
+
+    #[test]
+    fn test_serial_modem_status() {
+        let (serial, uart) = mock_serial();
+        uart.lock().modem_lines = ModemLines::DSR;
+        assert_eq!(serial.open(), Ok(()));
+        assert!(uart.lock().modem_interrupt);
+
+        // Lines asserted when the port is opened aren't changes.
+        serial.update_modem_status();
+        assert!(serial.poll(PollEvents::POLLPRI, None).is_empty());
+
+        uart.lock().modem_lines = ModemLines::CTS | ModemLines::DCD | ModemLines::DSR;
+        serial.update_modem_status();
+        uart.lock().modem_lines = ModemLines::DCD | ModemLines::DSR;
+        serial.update_modem_status();
+        assert_eq!(serial.poll(PollEvents::POLLPRI, None), PollEvents::POLLPRI);
+
+        let mut lines = 0u32;
+        assert_eq!(
+            (TIOCMGET.request(), TIOCGICOUNT.request()),
+            (0x5415, 0x545d)
+        );
+        assert_eq!(
+            serial.ioctl(TIOCMGET.request(), &mut lines as *mut _ as usize),
+            Ok(())
+        );
+        assert_eq!(lines, (ModemLines::DCD | ModemLines::DSR).bits());
+
+        // Reading the counters acknowledges the changes.
+        let mut counters = SerialCounters::default();
+        assert_eq!(
+            serial.ioctl(TIOCGICOUNT.request(), &mut counters as *mut _ as usize),
+            Ok(())
+        );
+        assert_eq!(
+            (counters.cts, counters.dsr, counters.rng, counters.dcd),
+            (2, 0, 0, 1)
+        );
+        assert!(serial.poll(PollEvents::POLLPRI, None).is_empty());
+
+        assert_eq!(serial.close(), Ok(()));
+        assert!(!uart.lock().modem_interrupt);
+    }
+
+    #[test]
+    fn test_serial_output_processing() {
+        let (serial, uart) = mock_serial_with(Termios::default());
+        assert_eq!(serial.fifo_tx(b"a\nb\r", true), Ok(4));
+        assert_eq!(uart.lock().take_tx(), b"a\r\nb\r");
+
+        configure(
+            &serial,
+            raw_termios(Iflags::empty(), Oflags::OPOST | Oflags::OCRNL),
+        );
+        assert_eq!(serial.fifo_tx(b"a\nb\r", true), Ok(4));
+        assert_eq!(uart.lock().take_tx(), b"a\nb\n");
+
+        // A newline isn't split when only one byte of the fifo is left.
+        configure(&serial, Termios::default());
+        uart.lock().set_tx_room(Some(0));
+        let capacity = serial.tx_fifo.rb.capacity();
+        let newlines = alloc::vec![b'\n'; capacity];
+        let queued = serial.fifo_tx(&newlines, true).unwrap();
+        assert_eq!(queued, capacity / 2);
+        uart.lock().set_tx_room(None);
+        serial.emergency_write("!\n");
+        let mut expected = b"\r\n".repeat(queued);
+        expected.extend_from_slice(b"!\r\n");
+        assert_eq!(uart.lock().take_tx(), expected);
+    }
+
+    #[test]
+    fn test_serial_early_console() {
+        let (serial, uart) = mock_serial_with(Termios::default());
+        uart.lock().set_tx_room(Some(0));
+        assert_eq!(serial.fifo_tx(b"queued", true), Ok(6));
+        uart.lock().set_tx_room(None);
+        EarlyConsole::write_str(&*serial, "early\n");
+        // What was queued is left to the TX interrupt.
+        assert_eq!(uart.lock().take_tx(), b"early\r\n");
+        assert!(uart.lock().tx_interrupt);
+        assert_eq!(serial.xmitchars(), Ok(6));
+        assert_eq!(uart.lock().take_tx(), b"queued");
+    }
+
+    #[test]
+    fn test_serial_xon_xoff() {
+        let (serial, uart) = mock_serial_with(Termios::default());
+        let mut buf = [0u8; 4];
+        uart.lock().push_rx(b"a\x13b");
+        assert_eq!(serial.recvchars(), Ok(2));
+        assert_eq!(serial.fifo_rx(&mut buf, true), Ok(2));
+        assert_eq!(&buf[..2], b"ab");
+
+        // Output waits in the fifo until the START character.
+        assert_eq!(serial.fifo_tx(b"out", true), Ok(3));
+        assert!(uart.lock().take_tx().is_empty());
+        uart.lock().push_rx(b"\x11");
+        assert_eq!(serial.recvchars(), Ok(0));
+        assert_eq!(uart.lock().take_tx(), b"out");
+
+        // With IXANY any byte restarts it.
+        configure(
+            &serial,
+            raw_termios(Iflags::IXON | Iflags::IXANY, Oflags::empty()),
+        );
+        uart.lock().push_rx(b"\x13");
+        serial.recvchars().unwrap();
+        assert_eq!(serial.fifo_tx(b"x", true), Ok(1));
+        assert!(uart.lock().take_tx().is_empty());
+        uart.lock().push_rx(b"c");
+        assert_eq!(serial.recvchars(), Ok(1));
+        assert_eq!(uart.lock().take_tx(), b"x");
+
+        // Turning IXON off restarts it too, and lets the characters in.
+        uart.lock().push_rx(b"\x13");
+        serial.recvchars().unwrap();
+        assert_eq!(serial.fifo_tx(b"y", true), Ok(1));
+        configure(&serial, raw_termios(Iflags::empty(), Oflags::empty()));
+        assert_eq!(uart.lock().take_tx(), b"y");
+        uart.lock().push_rx(b"\x13");
+        assert_eq!(serial.recvchars(), Ok(1));
+    }
+
+    #[test]
+    fn test_serial_input_throttling() {
+        let (serial, uart) = mock_serial_with(raw_termios(Iflags::IXOFF, Oflags::empty()));
+        let capacity = serial.rx_fifo.rb.capacity();
+        let threshold = capacity / RX_THROTTLE_FRACTION;
+
+        // The other end is stopped once the fifo is nearly full.
+        let received = capacity - threshold;
+        uart.lock().push_rx(&pattern(received));
+        assert_eq!(serial.recvchars(), Ok(received));
+        assert!(uart.lock().take_tx().is_empty());
+        uart.lock().push_rx(b"z");
+        assert_eq!(serial.recvchars(), Ok(1));
+        assert_eq!(uart.lock().take_tx(), b"\x13");
+
+        // And restarted once it's nearly drained.
+        let mut buf = alloc::vec![0u8; capacity];
+        let read = received + 1 - threshold;
+        assert_eq!(serial.fifo_rx(&mut buf[..read], true), Ok(read));
+        assert!(uart.lock().take_tx().is_empty());
+        assert_eq!(serial.fifo_rx(&mut buf[..1], true), Ok(1));
+        assert_eq!(uart.lock().take_tx(), b"\x11");
+    }
+
+    // Feeds the serial port from another thread until `done` is set. The
+    // fifo futex isn't a counter, so a wakeup sent before the reader sleeps
+    // would be lost; keep waking it instead of relying on a single one.
+    fn feed(serial: Arc<Serial>, uart: Arc<SpinLock<MockUart>>, done: Arc<AtomicBool>) {
+        thread::spawn(move || {
+            scheduler::yield_me();
+            uart.lock().push_rx(b"late");
+            let _ = serial.recvchars();
+            uart.lock().set_tx_room(None);
+            let _ = serial.xmitchars();
+            while !done.load(Ordering::Acquire) {
+                let _ = atomic_wake(&serial.rx_fifo.futex, 1);
+                let _ = atomic_wake(&serial.tx_fifo.futex, 1);
+                scheduler::yield_me();
+            }
+        });
+    }
+
+    // Stops the feeder when dropped, even if an assertion fails first.
+    struct StopFeeder(Arc<AtomicBool>);
+
+    impl Drop for StopFeeder {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Release);
+        }
+    }
+
+    #[test]
+    fn test_serial_blocking_interleaving() {
+        let (serial, uart) = mock_serial();
+        uart.lock().set_tx_room(Some(0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        // A nonblocking read doesn't wait for the feeder.
+        let mut buf = [0u8; 8];
+        assert_eq!(serial.fifo_rx(&mut buf, true), Ok(0));
+
+        feed(serial.clone(), uart.clone(), done.clone());
+        let _stop = StopFeeder(done);
+        // A blocking read returns once some data has arrived.
+        assert_eq!(serial.fifo_rx(&mut buf, false), Ok(4));
+        assert_eq!(&buf[..4], b"late");
+        // A blocking write returns once the fifo has drained.
+        assert_eq!(serial.fifo_tx(b"out", false), Ok(3));
+        assert_eq!(uart.lock().take_tx(), b"out");
     }
 }
@@ -0,0 +1,312 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Magic SysRq keys on the serial console.
+//!
+//! A break condition on the line, or the [`SYSRQ_MAGIC`] byte, arms the
+//! detector and the next received byte selects the action to run. Actions
+//! run right in the UART RX interrupt and print through the early console,
+//! so they still work when the scheduler is wedged. The thread dump, which
+//! takes the scheduler locks, is left to the system work queue instead. Sending the magic byte
+//! twice passes it through to the reader.
+//!
+//! Only the ports which called [`SysrqState::enable`] are watched, boards
+//! enable it on the console UART.
+
+use crate::{
+    allocator,
+    error::{code, Error},
+    kearly_println, panic, static_arc,
+    sync::SpinLock,
+    thread::{GlobalQueueVisitor, Thread},
+    workqueue::{self, Work},
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// ^O, which isn't used by the line discipline.
+pub const SYSRQ_MAGIC: u8 = 0x0f;
+
+/// Maximum number of actions registered on top of the built-in ones.
+pub const MAX_SYSRQ_ACTIONS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SysrqAction {
+    pub key: u8,
+    pub help: &'static str,
+    pub handler: fn(),
+}
+
+static BUILTIN_ACTIONS: [SysrqAction; 5] = [
+    SysrqAction {
+        key: b'h',
+        help: "show this help",
+        handler: show_help,
+    },
+    SysrqAction {
+        key: b't',
+        help: "dump threads",
+        handler: schedule_dump_threads,
+    },
+    SysrqAction {
+        key: b'm',
+        help: "dump memory info",
+        handler: dump_memory_info,
+    },
+    SysrqAction {
+        key: b'c',
+        help: "trigger a panic",
+        handler: crash,
+    },
+    SysrqAction {
+        key: b'b',
+        help: "reboot",
+        handler: reboot,
+    },
+];
+
+static ACTIONS: SpinLock<[Option<SysrqAction>; MAX_SYSRQ_ACTIONS]> =
+    SpinLock::new([None; MAX_SYSRQ_ACTIONS]);
+
+pub fn register(action: SysrqAction) -> Result<(), Error> {
+    let is_builtin = BUILTIN_ACTIONS.iter().any(|a| a.key == action.key);
+    // Checked and inserted under the same lock, so that two actions can't
+    // be registered for the same key.
+    let mut actions = ACTIONS.irqsave_lock();
+    if action.key == SYSRQ_MAGIC
+        || is_builtin
+        || actions.iter().flatten().any(|a| a.key == action.key)
+    {
+        return Err(code::EEXIST);
+    }
+    let Some(slot) = actions.iter_mut().find(|slot| slot.is_none()) else {
+        return Err(code::ENOSPC);
+    };
+    *slot = Some(action);
+    Ok(())
+}
+
+pub fn unregister(key: u8) -> Result<(), Error> {
+    let mut actions = ACTIONS.irqsave_lock();
+    let Some(slot) = actions
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|action| action.key == key))
+    else {
+        return Err(code::ENOENT);
+    };
+    *slot = None;
+    Ok(())
+}
+
+fn find(key: u8) -> Option<SysrqAction> {
+    if let Some(action) = BUILTIN_ACTIONS.iter().find(|action| action.key == key) {
+        return Some(*action);
+    }
+    ACTIONS
+        .irqsave_lock()
+        .iter()
+        .flatten()
+        .find(|action| action.key == key)
+        .copied()
+}
+
+/// Runs the action bound to `key`, or shows the help if there is none.
+pub fn handle(key: u8) {
+    match find(key) {
+        Some(action) => {
+            kearly_println!("SysRq: {}", action.help);
+            (action.handler)();
+        }
+        None => show_help(),
+    }
+}
+
+/// Per-port state of the magic sequence detector.
+#[derive(Debug, Default)]
+pub struct SysrqState {
+    enabled: AtomicBool,
+    armed: AtomicBool,
+}
+
+impl SysrqState {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            armed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Called by the serial core when a break condition is received.
+    pub fn arm(&self) {
+        if self.enabled.load(Ordering::Relaxed) {
+            self.armed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes the magic sequences from the received bytes. Returns the
+    /// number of bytes left in `buf` and the last action key found.
+    pub fn filter(&self, buf: &mut [u8]) -> (usize, Option<u8>) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return (buf.len(), None);
+        }
+        let mut armed = self.armed.load(Ordering::Relaxed);
+        let mut key = None;
+        let mut len = 0;
+        for i in 0..buf.len() {
+            let ch = buf[i];
+            if armed {
+                armed = false;
+                if ch != SYSRQ_MAGIC {
+                    key = Some(ch);
+                    continue;
+                }
+            } else if ch == SYSRQ_MAGIC {
+                armed = true;
+                continue;
+            }
+            buf[len] = ch;
+            len += 1;
+        }
+        self.armed.store(armed, Ordering::Relaxed);
+        (len, key)
+    }
+}
+
+fn show_help() {
+    kearly_println!("SysRq keys:");
+    for action in BUILTIN_ACTIONS.iter() {
+        kearly_println!("  {}: {}", action.key as char, action.help);
+    }
+    for action in ACTIONS.irqsave_lock().iter().flatten() {
+        kearly_println!("  {}: {}", action.key as char, action.help);
+    }
+}
+
+static_arc! {
+    DUMP_THREADS_WORK(Work, Work::new(dump_threads)),
+}
+
+fn schedule_dump_threads() {
+    if !workqueue::schedule_work(&DUMP_THREADS_WORK) {
+        kearly_println!("SysRq: a thread dump is pending or can't be run yet");
+    }
+}
+
+fn dump_threads() {
+    kearly_println!(
+        "{:>18} {:<15} {:>10} {:>8} {:>10}",
+        "ID",
+        "NAME",
+        "STATE",
+        "PRIO",
+        "STACK"
+    );
+    let mut visitor = GlobalQueueVisitor::new();
+    while let Some(t) = visitor.next() {
+        let name = t.name();
+        let name = if name.is_empty() {
+            t.kind_to_str()
+        } else {
+            &name
+        };
+        kearly_println!(
+            "{:>#18x} {:<15} {:>10} {:>8} {:>5}/{:<5}",
+            Thread::id(&t),
+            name,
+            t.state_to_str(),
+            t.priority(),
+            t.saved_stack_usage(),
+            t.stack_size()
+        );
+    }
+}
+
+fn dump_memory_info() {
+    let info = allocator::memory_info();
+    kearly_println!(
+        "total: {}, used: {}, max used: {}",
+        info.total,
+        info.used,
+        info.max_used
+    );
+}
+
+fn crash() {
+    panic!("SysRq triggered crash");
+}
+
+fn reboot() {
+    panic::reboot();
+    kearly_println!("SysRq: reboot is not supported on this platform");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn nop() {}
+
+    #[test]
+    fn test_sysrq_filter() {
+        let state = SysrqState::new();
+        let mut buf = *b"ab\x0ftcd";
+        assert_eq!(state.filter(&mut buf), (5, None));
+        state.arm();
+        assert_eq!(state.filter(&mut buf), (5, None));
+
+        state.enable();
+        assert_eq!(state.filter(&mut buf), (4, Some(b't')));
+        assert_eq!(&buf[..4], b"abcd");
+
+        // The sequence may be split across two reads.
+        let mut buf = *b"a\x0f";
+        assert_eq!(state.filter(&mut buf), (1, None));
+        let mut buf = *b"mb";
+        assert_eq!(state.filter(&mut buf), (1, Some(b'm')));
+        assert_eq!(&buf[..1], b"b");
+
+        // Doubled magic byte is passed through.
+        let mut buf = *b"\x0f\x0f";
+        assert_eq!(state.filter(&mut buf), (1, None));
+        assert_eq!(buf[0], SYSRQ_MAGIC);
+
+        state.arm();
+        let mut buf = *b"h";
+        assert_eq!(state.filter(&mut buf), (0, Some(b'h')));
+    }
+
+    #[test]
+    fn test_sysrq_register() {
+        let action = SysrqAction {
+            key: b'z',
+            help: "nothing",
+            handler: nop,
+        };
+        assert_eq!(register(action), Ok(()));
+        assert_eq!(register(action), Err(code::EEXIST));
+        assert_eq!(
+            register(SysrqAction {
+                key: b't',
+                ..action
+            }),
+            Err(code::EEXIST)
+        );
+        assert_eq!(unregister(b'z'), Ok(()));
+        assert_eq!(unregister(b'z'), Err(code::ENOENT));
+    }
+}
@@ -13,6 +13,70 @@
 // limitations under the License.
 
 use bitflags::bitflags;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+// The requests and the layouts are Linux's, so that a libc's tcgetattr()
+// and tcsetattr() work unchanged.
+crate::ioctl_read_bad!(
+    /// Reads the termios of a terminal, with the speeds coded in the
+    /// control modes.
+    pub TCGETS, 0x5401, RawTermios
+);
+crate::ioctl_write_bad!(
+    /// Sets the termios of a terminal, taking effect immediately.
+    pub TCSETS, 0x5402, RawTermios
+);
+crate::ioctl_read!(
+    /// Reads the termios of a terminal, with the speeds in bauds.
+    pub TCGETS2, b'T', 0x2a, RawTermios2
+);
+crate::ioctl_write!(
+    /// Sets the termios of a terminal with the speeds in bauds, taking
+    /// effect immediately.
+    pub TCSETS2, b'T', 0x2b, RawTermios2
+);
+
+// Number of control characters of the raw termios.
+const NCCS: usize = 19;
+
+// Speed codes of the control modes.
+const CBAUD: u32 = 0x0000_100f;
+const CIBAUD: u32 = 0x100f_0000;
+const IBSHIFT: u32 = 16;
+// The speed is given in bauds by the termios2 fields.
+const BOTHER: u32 = 0x1000;
+const BAUD_CODES: [(u32, u32); 30] = [
+    (0x0001, 50),
+    (0x0002, 75),
+    (0x0003, 110),
+    (0x0004, 134),
+    (0x0005, 150),
+    (0x0006, 200),
+    (0x0007, 300),
+    (0x0008, 600),
+    (0x0009, 1200),
+    (0x000a, 1800),
+    (0x000b, 2400),
+    (0x000c, 4800),
+    (0x000d, 9600),
+    (0x000e, 19200),
+    (0x000f, 38400),
+    (0x1001, 57600),
+    (0x1002, 115200),
+    (0x1003, 230400),
+    (0x1004, 460800),
+    (0x1005, 500000),
+    (0x1006, 576000),
+    (0x1007, 921600),
+    (0x1008, 1000000),
+    (0x1009, 1152000),
+    (0x100a, 1500000),
+    (0x100b, 2000000),
+    (0x100c, 2500000),
+    (0x100d, 3000000),
+    (0x100e, 3500000),
+    (0x100f, 4000000),
+];
 
 /// Termios flags, see: https://pubs.opengroup.org/onlinepubs/9699919799/basedefs/termios.h.html.
 #[repr(C)]
@@ -76,7 +140,7 @@ bitflags! {
         // Any character will restart after stop.
         const IXANY = 0x800;
         // Enable start/stop output control.
-        const IXON = 0x0200;
+        const IXON = 0x400;
         // Enable start/stop input control.
         const IXOFF = 0x1000;
     }
@@ -137,6 +201,8 @@ bitflags! {
         const PARENB = 0x100;
         // Odd parity, else even.
         const PARODD = 0x200;
+        // IrDA SIR encoding, BlueOS extension in bits Linux leaves unused.
+        const CSIR = 0x0800_0000;
     }
 }
 
@@ -168,7 +234,7 @@ bitflags! {
         // Send SIGTTOU for background output.
         const TOSTOP = 0x100;
         // Enable extended input character processing.
-        const IEXTEN = 0x800;
+        const IEXTEN = 0x8000;
     }
 }
 
@@ -297,3 +363,143 @@ impl Termios {
         self.ospeed = baud_rate;
     }
 }
+
+// Codes `speed` for the control modes, as BOTHER when it has no code.
+fn baud_code(speed: u32) -> u32 {
+    BAUD_CODES
+        .iter()
+        .find(|&&(_, baud)| baud == speed)
+        .map_or(BOTHER, |&(code, _)| code)
+}
+
+// The speed coded by `code`, None for BOTHER or B0, which hangs up on
+// Linux and leaves the speed as it is here.
+fn baud_rate(code: u32) -> Option<u32> {
+    BAUD_CODES
+        .iter()
+        .find(|&&(c, _)| c == code)
+        .map(|&(_, baud)| baud)
+}
+
+/// Linux's `struct termios`, passed by the TCGETS and TCSETS requests.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct RawTermios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+/// Linux's `struct termios2`, passed by the TCGETS2 and TCSETS2 requests.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct RawTermios2 {
+    pub termios: RawTermios,
+    pub ispeed: u32,
+    pub ospeed: u32,
+}
+
+impl From<&Termios> for RawTermios {
+    fn from(termios: &Termios) -> Self {
+        let mut cc = [0; NCCS];
+        cc[..termios.cc.len()].copy_from_slice(&termios.cc);
+        let mut cflag = termios.cflag.bits() | baud_code(termios.ospeed);
+        if termios.ispeed != termios.ospeed {
+            cflag |= baud_code(termios.ispeed) << IBSHIFT;
+        }
+        Self {
+            iflag: termios.iflag.bits(),
+            oflag: termios.oflag.bits(),
+            cflag,
+            lflag: termios.lflag.bits(),
+            line: 0,
+            cc,
+        }
+    }
+}
+
+impl From<&Termios> for RawTermios2 {
+    fn from(termios: &Termios) -> Self {
+        Self {
+            termios: RawTermios::from(termios),
+            ispeed: termios.ispeed,
+            ospeed: termios.ospeed,
+        }
+    }
+}
+
+impl Termios {
+    /// Applies `raw` over the termios, taking the speeds from the codes of
+    /// its control modes, or from `speeds` in bauds for BOTHER. An input
+    /// speed left at B0 is the output speed.
+    pub fn set_raw(&mut self, raw: &RawTermios, speeds: Option<(u32, u32)>) {
+        let ocode = raw.cflag & CBAUD;
+        let icode = (raw.cflag & CIBAUD) >> IBSHIFT;
+        let ospeed = match (baud_rate(ocode), speeds) {
+            (Some(speed), _) => speed,
+            (None, Some((_, ospeed))) if ocode == BOTHER => ospeed,
+            _ => self.ospeed,
+        };
+        let ispeed = match (baud_rate(icode), speeds) {
+            (Some(speed), _) => speed,
+            (None, Some((ispeed, _))) if icode == BOTHER => ispeed,
+            _ if icode == 0 => ospeed,
+            _ => self.ispeed,
+        };
+        self.iflag = Iflags::from_bits_retain(raw.iflag);
+        self.oflag = Oflags::from_bits_retain(raw.oflag);
+        self.cflag = Cflags::from_bits_retain(raw.cflag & !(CBAUD | CIBAUD));
+        self.lflag = Lflags::from_bits_retain(raw.lflag);
+        let n = self.cc.len();
+        self.cc.copy_from_slice(&raw.cc[..n]);
+        self.ispeed = ispeed;
+        self.ospeed = ospeed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::mem;
+
+    #[test]
+    fn test_termios_linux_abi() {
+        assert_eq!(mem::size_of::<RawTermios>(), 36);
+        assert_eq!(mem::size_of::<RawTermios2>(), 44);
+        assert_eq!(TCGETS.request(), 0x5401);
+        assert_eq!(TCSETS.request(), 0x5402);
+        assert_eq!(TCGETS2.request(), 0x802c_542a);
+        assert_eq!(TCSETS2.request(), 0x402c_542b);
+    }
+
+    #[test]
+    fn test_termios_speeds() {
+        let termios = Termios::default();
+        let raw = RawTermios::from(&termios);
+        // B115200, for both directions.
+        assert_eq!(raw.cflag & (CBAUD | CIBAUD), 0x1002);
+        assert_eq!(raw.cc[CcIndex::Vintr as usize], 0x03);
+
+        let mut set = termios;
+        let mut raw = RawTermios::from(&termios);
+        raw.cflag = (raw.cflag & !CBAUD) | 0x000d;
+        set.set_raw(&raw, None);
+        assert_eq!((set.getispeed(), set.getospeed()), (9600, 9600));
+        assert_eq!(set.cflag.bits(), termios.cflag.bits());
+
+        // Speeds without a code go through termios2.
+        raw.cflag = (raw.cflag & !CBAUD) | BOTHER;
+        set.set_raw(&raw, Some((250000, 250000)));
+        assert_eq!((set.getispeed(), set.getospeed()), (250000, 250000));
+        let raw2 = RawTermios2::from(&set);
+        assert_eq!(raw2.termios.cflag & CBAUD, BOTHER);
+        assert_eq!((raw2.ispeed, raw2.ospeed), (250000, 250000));
+        // TCSETS can't carry them, and leaves them as they are.
+        set.set_raw(&raw, None);
+        assert_eq!(set.getospeed(), 250000);
+    }
+}
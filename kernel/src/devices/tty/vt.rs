@@ -0,0 +1,159 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Virtual terminals, the consoles which can be brought to the foreground.
+//!
+//! VT 0 is the console the kernel booted with, the sinks of the CONSOLE
+//! kconfig. Consoles with a screen of their own, like the framebuffer
+//! console, add a VT with [`register`]. Switching to a VT selects its sink
+//! alone, see [`console::select`](crate::devices::console::select), and
+//! tells each VT whether it's in the foreground now, so that it may stop
+//! drawing in the background and draw again when switched back to. The
+//! magic SysRq key `v` switches to the next VT.
+
+use crate::{
+    devices::console,
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+crate::ioctl_write!(
+    /// Switches to the VT whose number is passed.
+    pub VT_ACTIVATE, b'V', 0x06, u32
+);
+crate::ioctl_read!(
+    /// Reads the number of the VT in the foreground.
+    pub VT_GETACTIVE, b'V', 0x07, u32
+);
+
+/// Most VTs, VT 0 included.
+pub const MAX_VTS: usize = 4;
+
+pub trait Vt: Send + Sync {
+    /// The console sink the VT writes to.
+    fn sink(&self) -> &'static str;
+    /// Tells the VT whether it's in the foreground, that is whether its
+    /// sink is selected. May be called from interrupt context.
+    fn set_foreground(&self, foreground: bool);
+}
+
+// VT 0 has no entry.
+static VTS: SpinLock<[Option<Arc<dyn Vt>>; MAX_VTS]> = SpinLock::new([const { None }; MAX_VTS]);
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Adds a VT, and returns its number.
+pub fn register(vt: Arc<dyn Vt>) -> Result<usize, Error> {
+    let mut vts = VTS.irqsave_lock();
+    let (n, slot) = vts
+        .iter_mut()
+        .enumerate()
+        .skip(1)
+        .find(|(_, slot)| slot.is_none())
+        .ok_or(code::ENOSPC)?;
+    vt.set_foreground(console::is_selected(vt.sink()));
+    *slot = Some(vt);
+    #[cfg(magic_sysrq)]
+    if n == 1 {
+        let _ = super::sysrq::register(super::sysrq::SysrqAction {
+            key: b'v',
+            help: "switch to the next virtual terminal",
+            handler: switch_next,
+        });
+    }
+    Ok(n)
+}
+
+/// The number of the VT in the foreground.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Brings VT `n` to the foreground. Fails with ENXIO if there's no such
+/// VT.
+pub fn activate(n: usize) -> Result<(), Error> {
+    let vts = VTS.irqsave_lock();
+    if n >= MAX_VTS || (n != 0 && vts[n].is_none()) {
+        return Err(code::ENXIO);
+    }
+    let selection = vts[n]
+        .as_ref()
+        .map_or(blueos_kconfig::CONSOLE, |vt| vt.sink());
+    console::select(selection)?;
+    ACTIVE.store(n, Ordering::Relaxed);
+    for vt in vts.iter().flatten() {
+        vt.set_foreground(console::is_selected(vt.sink()));
+    }
+    Ok(())
+}
+
+/// Brings the VT after the one in the foreground to it, wrapping around to
+/// VT 0.
+pub fn switch_next() {
+    let next = {
+        let vts = VTS.irqsave_lock();
+        (active() + 1..MAX_VTS)
+            .find(|&n| vts[n].is_some())
+            .unwrap_or(0)
+    };
+    let _ = activate(next);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::sync::atomic::AtomicBool;
+
+    struct TestVt(AtomicBool);
+
+    struct Discard;
+
+    impl console::EarlyConsole for Discard {
+        fn write_str(&self, _s: &str) {}
+    }
+
+    impl Vt for TestVt {
+        fn sink(&self) -> &'static str {
+            "testvt"
+        }
+
+        fn set_foreground(&self, foreground: bool) {
+            self.0.store(foreground, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_vt_switch() {
+        assert_eq!(activate(MAX_VTS), Err(code::ENXIO));
+        console::register_early_console("testvt", Arc::new(Discard));
+        let vt = Arc::new(TestVt(AtomicBool::new(true)));
+        let n = register(vt.clone()).unwrap();
+        assert!(!vt.0.load(Ordering::Relaxed));
+        assert_eq!(active(), 0);
+
+        activate(n).unwrap();
+        assert_eq!(active(), n);
+        assert!(vt.0.load(Ordering::Relaxed));
+        assert!(console::is_selected("testvt"));
+
+        // Back to the boot console.
+        while active() != 0 {
+            switch_next();
+        }
+        assert!(!vt.0.load(Ordering::Relaxed));
+        assert!(!console::is_selected("testvt"));
+    }
+}
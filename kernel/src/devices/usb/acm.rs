@@ -0,0 +1,192 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CDC-ACM serial ports.
+//!
+//! Modems and serial adapters of the Abstract Control Model are registered
+//! as char devices "ttyACM0", "ttyACM1" and so on. Their line is set to
+//! 115200 8N1 with DTR and RTS raised when they're found.
+
+use super::{InterfaceDescriptor, TransferType, UsbDevice, RECIPIENT_INTERFACE, TYPE_CLASS};
+use crate::{
+    devices::{devno, Device, DeviceClass, DeviceId, DeviceManager},
+    error::{code, Error},
+    scheduler,
+    sync::SpinLock,
+    thread,
+};
+use alloc::{collections::VecDeque, format, string::String, sync::Arc, vec};
+use blueos_kconfig::TICKS_PER_SECOND;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use log::warn;
+
+const CLASS_COMM: u8 = 0x02;
+const SUBCLASS_ACM: u8 = 0x02;
+const CLASS_DATA: u8 = 0x0a;
+const REQ_SET_LINE_CODING: u8 = 0x20;
+const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
+const LINE_DTR: u16 = 1 << 0;
+const LINE_RTS: u16 = 1 << 1;
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+// The controller is polled, so reads only wait there briefly before
+// sleeping.
+const READ_POLL: Duration = Duration::from_millis(1);
+const POLL_INTERVAL_MS: usize = 10;
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+static PORTS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct AcmPort {
+    device: Arc<UsbDevice>,
+    name: String,
+    id: DeviceId,
+    bulk_in: u8,
+    bulk_out: u8,
+    max_packet: usize,
+    // Bytes received past what the last read asked for. Held by the
+    // reader while it receives.
+    received: SpinLock<VecDeque<u8>>,
+}
+
+fn poll_wait() {
+    let _wait = thread::wait_on_device("ttyACM");
+    scheduler::suspend_me_for((TICKS_PER_SECOND * POLL_INTERVAL_MS / 1000).max(1));
+}
+
+impl Device for AcmPort {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn read(&self, _pos: u64, buf: &mut [u8], is_nonblocking: bool) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            {
+                let mut received = self.received.lock();
+                if !received.is_empty() {
+                    let len = buf.len().min(received.len());
+                    for (dst, src) in buf.iter_mut().zip(received.drain(..len)) {
+                        *dst = src;
+                    }
+                    return Ok(len);
+                }
+                // Whole packets are asked for, a device sending more than
+                // asked would babble.
+                let mut packet = vec![0u8; self.max_packet];
+                match self
+                    .device
+                    .transfer_in(self.bulk_in, &mut packet, READ_POLL)
+                {
+                    Ok(0) => (),
+                    Ok(len) => {
+                        let copied = len.min(buf.len());
+                        buf[..copied].copy_from_slice(&packet[..copied]);
+                        received.extend(&packet[copied..len]);
+                        return Ok(copied);
+                    }
+                    Err(e) if e == code::ETIMEDOUT => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            if is_nonblocking {
+                return Err(code::EAGAIN);
+            }
+            poll_wait();
+        }
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.device.transfer_out(self.bulk_out, buf, WRITE_TIMEOUT)
+    }
+}
+
+pub(super) fn probe(device: &Arc<UsbDevice>, interface: &InterfaceDescriptor) -> Result<(), Error> {
+    if (interface.class, interface.subclass) != (CLASS_COMM, SUBCLASS_ACM) {
+        return Err(code::ENODEV);
+    }
+    // The data interface usually follows its comm interface. The union
+    // descriptor telling them apart isn't looked at.
+    let Some(data) = device
+        .configuration()
+        .interfaces
+        .iter()
+        .find(|i| i.class == CLASS_DATA && i.number > interface.number)
+    else {
+        return Err(code::EINVAL);
+    };
+    let (Some(bulk_in), Some(bulk_out)) = (
+        data.find_endpoint(TransferType::Bulk, true),
+        data.find_endpoint(TransferType::Bulk, false),
+    ) else {
+        return Err(code::EINVAL);
+    };
+
+    let mut coding = [0u8; 7];
+    coding[0..4].copy_from_slice(&DEFAULT_BAUD_RATE.to_le_bytes());
+    // 1 stop bit, no parity, 8 data bits.
+    coding[6] = 8;
+    let index = interface.number as u16;
+    let line = device
+        .control_out(
+            TYPE_CLASS | RECIPIENT_INTERFACE,
+            REQ_SET_LINE_CODING,
+            0,
+            index,
+            &coding,
+        )
+        .and_then(|_| {
+            device.control_out(
+                TYPE_CLASS | RECIPIENT_INTERFACE,
+                REQ_SET_CONTROL_LINE_STATE,
+                LINE_DTR | LINE_RTS,
+                index,
+                &[],
+            )
+        });
+    // Some devices have no line to set up.
+    if let Err(e) = line {
+        warn!("Failed to set the line of USB serial port up: {:?}", e);
+    }
+
+    let name = format!("ttyACM{}", PORTS.fetch_add(1, Ordering::Relaxed));
+    let major = devno::register_major(DeviceClass::Char, 0, "cdc-acm")?;
+    let id = devno::alloc_minor(DeviceClass::Char, major)?;
+    let port = AcmPort {
+        device: device.clone(),
+        name: name.clone(),
+        id,
+        bulk_in: bulk_in.address,
+        bulk_out: bulk_out.address,
+        max_packet: bulk_in.max_packet().max(1) as usize,
+        received: SpinLock::new(VecDeque::new()),
+    };
+    DeviceManager::get().register_device(name, Arc::new(port))
+}
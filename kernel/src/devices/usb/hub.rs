@@ -0,0 +1,215 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! USB hubs.
+//!
+//! A hub's ports are powered and the devices on them enumerated when the
+//! hub is. Its status change endpoint isn't polled.
+
+use super::{
+    delay, enumerate, InterfaceDescriptor, Location, Speed, UsbDevice, RECIPIENT_DEVICE,
+    RECIPIENT_OTHER, REQ_CLEAR_FEATURE, REQ_GET_DESCRIPTOR, REQ_GET_STATUS, REQ_SET_FEATURE,
+    TYPE_CLASS,
+};
+use crate::error::{code, Error};
+use alloc::sync::Arc;
+use core::time::Duration;
+use log::warn;
+
+const CLASS_HUB: u8 = 0x09;
+const DESC_HUB: u8 = 0x29;
+const DESC_SUPERSPEED_HUB: u8 = 0x2a;
+const REQ_SET_HUB_DEPTH: u8 = 12;
+// Hub protocol of high-speed hubs with a TT per port.
+const PROTOCOL_MULTI_TT: u8 = 2;
+
+const PORT_RESET: u16 = 4;
+const PORT_POWER: u16 = 8;
+const C_PORT_CONNECTION: u16 = 16;
+const C_PORT_RESET: u16 = 20;
+
+const STATUS_CONNECTION: u16 = 1 << 0;
+const STATUS_ENABLE: u16 = 1 << 1;
+const STATUS_LOW_SPEED: u16 = 1 << 9;
+const STATUS_HIGH_SPEED: u16 = 1 << 10;
+const CHANGE_RESET: u16 = 1 << 4;
+
+// Route strings have room for 5 hubs, of 15 ports at most.
+const MAX_DEPTH: u8 = 5;
+const MAX_PORTS: u8 = 15;
+const RESET_POLL: Duration = Duration::from_millis(10);
+const RESET_TRIES: usize = 50;
+const RESET_RECOVERY: Duration = Duration::from_millis(10);
+
+fn set_port_feature(hub: &UsbDevice, port: u8, feature: u16) -> Result<(), Error> {
+    hub.control_out(
+        TYPE_CLASS | RECIPIENT_OTHER,
+        REQ_SET_FEATURE,
+        feature,
+        port as u16,
+        &[],
+    )
+}
+
+fn clear_port_feature(hub: &UsbDevice, port: u8, feature: u16) -> Result<(), Error> {
+    hub.control_out(
+        TYPE_CLASS | RECIPIENT_OTHER,
+        REQ_CLEAR_FEATURE,
+        feature,
+        port as u16,
+        &[],
+    )
+}
+
+// Returns the status and the changes of `port`.
+fn port_status(hub: &UsbDevice, port: u8) -> Result<(u16, u16), Error> {
+    let mut status = [0u8; 4];
+    if hub.control_in(
+        TYPE_CLASS | RECIPIENT_OTHER,
+        REQ_GET_STATUS,
+        0,
+        port as u16,
+        &mut status,
+    )? < status.len()
+    {
+        return Err(code::EIO);
+    }
+    Ok((
+        u16::from_le_bytes([status[0], status[1]]),
+        u16::from_le_bytes([status[2], status[3]]),
+    ))
+}
+
+// Resets `port`, returning the speed of the device on it.
+fn reset_port(hub: &UsbDevice, port: u8) -> Result<Option<Speed>, Error> {
+    let (status, _) = port_status(hub, port)?;
+    if status & STATUS_CONNECTION == 0 {
+        return Ok(None);
+    }
+    set_port_feature(hub, port, PORT_RESET)?;
+    let mut tries = 0;
+    let status = loop {
+        delay(RESET_POLL);
+        let (status, change) = port_status(hub, port)?;
+        if change & CHANGE_RESET != 0 {
+            break status;
+        }
+        tries += 1;
+        if tries == RESET_TRIES {
+            return Err(code::ETIMEDOUT);
+        }
+    };
+    clear_port_feature(hub, port, C_PORT_RESET)?;
+    clear_port_feature(hub, port, C_PORT_CONNECTION)?;
+    if status & STATUS_ENABLE == 0 {
+        return Err(code::EIO);
+    }
+    delay(RESET_RECOVERY);
+    Ok(Some(if hub.location().speed == Speed::Super {
+        Speed::Super
+    } else if status & STATUS_LOW_SPEED != 0 {
+        Speed::Low
+    } else if status & STATUS_HIGH_SPEED != 0 {
+        Speed::High
+    } else {
+        Speed::Full
+    }))
+}
+
+pub(super) fn probe(hub: &Arc<UsbDevice>, interface: &InterfaceDescriptor) -> Result<(), Error> {
+    if interface.class != CLASS_HUB {
+        return Err(code::ENODEV);
+    }
+    let location = *hub.location();
+    if location.depth >= MAX_DEPTH {
+        warn!("USB hub {} is too deep in the tree", hub.slot());
+        return Err(code::ENOTSUP);
+    }
+    let superspeed = location.speed == Speed::Super;
+    let kind = if superspeed {
+        DESC_SUPERSPEED_HUB
+    } else {
+        DESC_HUB
+    };
+    let mut desc = [0u8; 12];
+    if hub.control_in(
+        TYPE_CLASS | RECIPIENT_DEVICE,
+        REQ_GET_DESCRIPTOR,
+        (kind as u16) << 8,
+        0,
+        &mut desc,
+    )? < 7
+    {
+        return Err(code::EIO);
+    }
+    let ports = desc[2];
+    let characteristics = u16::from_le_bytes([desc[3], desc[4]]);
+    let power_on = Duration::from_millis(desc[5] as u64 * 2);
+    let ttt = if superspeed {
+        0
+    } else {
+        (characteristics >> 5) as u8 & 0b11
+    };
+    let multi_tt = location.speed == Speed::High && hub.descriptor().protocol == PROTOCOL_MULTI_TT;
+    hub.controller().set_hub(hub.slot(), ports, ttt, multi_tt)?;
+    if superspeed {
+        hub.control_out(
+            TYPE_CLASS | RECIPIENT_DEVICE,
+            REQ_SET_HUB_DEPTH,
+            location.depth as u16,
+            0,
+            &[],
+        )?;
+    }
+
+    for port in 1..=ports {
+        set_port_feature(hub, port, PORT_POWER)?;
+    }
+    delay(power_on);
+    for port in 1..=ports.min(MAX_PORTS) {
+        let speed = match reset_port(hub, port) {
+            Ok(Some(speed)) => speed,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!(
+                    "Failed to reset port {} of USB hub {}: {:?}",
+                    port,
+                    hub.slot(),
+                    e
+                );
+                continue;
+            }
+        };
+        let tt = match (location.speed, speed) {
+            (Speed::High, Speed::Low | Speed::Full) => Some((hub.slot(), port)),
+            _ => location.tt,
+        };
+        let child = Location {
+            root_port: location.root_port,
+            route: location.route | (port as u32) << (4 * location.depth),
+            depth: location.depth + 1,
+            speed,
+            tt,
+        };
+        if let Err(e) = enumerate(hub.controller(), child) {
+            warn!(
+                "Failed to enumerate the device on port {} of USB hub {}: {:?}",
+                port,
+                hub.slot(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,721 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! USB host stack.
+//!
+//! Host controller drivers implement [`HostController`] and hand every
+//! device they find on their root ports to [`enumerate`], which addresses
+//! it, reads its descriptors, selects its first configuration and offers
+//! each interface to the class drivers: hubs, mass storage and CDC-ACM
+//! serial ports. Hubs enumerate the devices behind them the same way.
+//!
+//! Controllers are polled, yielding the CPU between polls once the
+//! scheduler runs, and the bus is only scanned once when the
+//! controller is probed: devices plugged in later aren't seen.
+
+pub mod acm;
+pub mod hub;
+pub mod storage;
+pub mod xhci;
+
+use crate::{
+    arch,
+    error::{code, Error},
+    irq, scheduler, time,
+};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+use log::{debug, warn};
+use spin::RwLock as SpinRwLock;
+
+// bmRequestType.
+pub const DIR_OUT: u8 = 0x00;
+pub const DIR_IN: u8 = 0x80;
+pub const TYPE_STANDARD: u8 = 0x00;
+pub const TYPE_CLASS: u8 = 0x20;
+pub const RECIPIENT_DEVICE: u8 = 0x00;
+pub const RECIPIENT_INTERFACE: u8 = 0x01;
+pub const RECIPIENT_ENDPOINT: u8 = 0x02;
+pub const RECIPIENT_OTHER: u8 = 0x03;
+
+// Standard requests.
+pub const REQ_GET_STATUS: u8 = 0x00;
+pub const REQ_CLEAR_FEATURE: u8 = 0x01;
+pub const REQ_SET_FEATURE: u8 = 0x03;
+pub const REQ_GET_DESCRIPTOR: u8 = 0x06;
+pub const REQ_SET_CONFIGURATION: u8 = 0x09;
+
+pub const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+pub const DESC_DEVICE: u8 = 0x01;
+pub const DESC_CONFIGURATION: u8 = 0x02;
+pub const DESC_INTERFACE: u8 = 0x04;
+pub const DESC_ENDPOINT: u8 = 0x05;
+
+/// Timeout of the requests sent while enumerating.
+pub const CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Speed {
+    Low,
+    Full,
+    High,
+    Super,
+}
+
+impl Speed {
+    /// Packet size of the default control pipe, before the device
+    /// descriptor tells the real one.
+    pub fn default_max_packet0(self) -> u16 {
+        match self {
+            Speed::Low | Speed::Full => 8,
+            Speed::High => 64,
+            Speed::Super => 512,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn is_in(&self) -> bool {
+        self.request_type & DIR_IN != 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0] = self.request_type;
+        bytes[1] = self.request;
+        bytes[2..4].copy_from_slice(&self.value.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.index.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub const SIZE: usize = 18;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < Self::SIZE || bytes[1] != DESC_DEVICE {
+            return Err(code::EINVAL);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        Ok(Self {
+            usb_version: u16_at(2),
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size0: bytes[7],
+            vendor_id: u16_at(8),
+            product_id: u16_at(10),
+            num_configurations: bytes[17],
+        })
+    }
+
+    /// Returns the packet size of the default control pipe. SuperSpeed
+    /// devices give it as a power of two.
+    pub fn max_packet0(&self, speed: Speed) -> u16 {
+        max_packet0(self.max_packet_size0, speed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn number(&self) -> u8 {
+        self.address & 0x0f
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.address & DIR_IN != 0
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0b11 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// Returns the packet size, without the additional transactions of
+    /// high-bandwidth endpoints.
+    pub fn max_packet(&self) -> u16 {
+        self.max_packet_size & 0x7ff
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+impl InterfaceDescriptor {
+    /// Returns the first endpoint of type `kind` in direction `is_in`.
+    pub fn find_endpoint(&self, kind: TransferType, is_in: bool) -> Option<EndpointDescriptor> {
+        self.endpoints
+            .iter()
+            .find(|ep| ep.transfer_type() == kind && ep.is_in() == is_in)
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Configuration {
+    pub value: u8,
+    /// The default setting of each interface, alternate settings are left
+    /// out.
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+impl Configuration {
+    /// Parses a configuration descriptor followed by its interface,
+    /// endpoint and class specific descriptors.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 9 || bytes[1] != DESC_CONFIGURATION {
+            return Err(code::EINVAL);
+        }
+        let mut config = Self {
+            value: bytes[5],
+            interfaces: Vec::new(),
+        };
+        // Whether the endpoints seen belong to a default setting.
+        let mut current = false;
+        let mut rest = bytes.get(bytes[0] as usize..).ok_or(code::EINVAL)?;
+        while rest.len() >= 2 {
+            let len = rest[0] as usize;
+            if len < 2 || len > rest.len() {
+                return Err(code::EINVAL);
+            }
+            let desc = &rest[..len];
+            match desc[1] {
+                DESC_INTERFACE if len >= 9 => {
+                    current = desc[3] == 0;
+                    if current {
+                        config.interfaces.push(InterfaceDescriptor {
+                            number: desc[2],
+                            class: desc[5],
+                            subclass: desc[6],
+                            protocol: desc[7],
+                            endpoints: Vec::new(),
+                        });
+                    }
+                }
+                DESC_ENDPOINT if len >= 7 && current => {
+                    if let Some(interface) = config.interfaces.last_mut() {
+                        interface.endpoints.push(EndpointDescriptor {
+                            address: desc[2],
+                            attributes: desc[3],
+                            max_packet_size: u16::from_le_bytes([desc[4], desc[5]]),
+                            interval: desc[6],
+                        });
+                    }
+                }
+                _ => (),
+            }
+            rest = &rest[len..];
+        }
+        Ok(config)
+    }
+}
+
+/// Where a device sits on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    /// Root port the device is behind, from 1.
+    pub root_port: u8,
+    /// Ports of the hubs between the root port and the device, 4 bits per
+    /// hub, the one nearest to the root first.
+    pub route: u32,
+    /// Hubs between the root port and the device.
+    pub depth: u8,
+    pub speed: Speed,
+    /// Slot and port of the high-speed hub translating for a low or full
+    /// speed device.
+    pub tt: Option<(u8, u8)>,
+}
+
+/// A USB host controller.
+///
+/// Devices are identified by the slot the controller gave them in
+/// [`HostController::alloc_device`], endpoints by their address. Buffers
+/// can be of any length, controllers split them as they need.
+pub trait HostController: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Allocates a slot for the device at `location` and gives it an
+    /// address. The default control pipe is then usable.
+    fn alloc_device(&self, location: &Location) -> Result<u8, Error>;
+
+    /// Frees the slot of a device which is gone or which failed to
+    /// enumerate.
+    fn free_device(&self, slot: u8);
+
+    /// Updates the packet size of the default control pipe.
+    fn set_max_packet0(&self, slot: u8, max_packet: u16) -> Result<(), Error>;
+
+    /// Sets the endpoints of the selected configuration up.
+    fn configure_endpoints(&self, slot: u8, endpoints: &[EndpointDescriptor]) -> Result<(), Error>;
+
+    /// Tells the controller the device is a hub with `ports` ports. `ttt`
+    /// is the think time of its transaction translator.
+    fn set_hub(&self, slot: u8, ports: u8, ttt: u8, multi_tt: bool) -> Result<(), Error>;
+
+    /// Runs a control transfer on the default pipe. `data` is filled for
+    /// requests to the host, and sent otherwise. Returns the bytes moved.
+    fn control(&self, slot: u8, setup: &SetupPacket, data: &mut [u8]) -> Result<usize, Error>;
+
+    /// Receives into `buf` from a bulk or interrupt endpoint, returning the
+    /// bytes received. A short packet ends the transfer. Fails with
+    /// ETIMEDOUT if nothing came before `timeout`, and with EPIPE if the
+    /// endpoint stalled.
+    fn transfer_in(
+        &self,
+        slot: u8,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error>;
+
+    /// Sends `buf` to a bulk or interrupt endpoint.
+    fn transfer_out(
+        &self,
+        slot: u8,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error>;
+}
+
+// Whether the caller is a thread which may give up the CPU. Devices
+// plugged at boot are enumerated before the scheduler runs, with
+// interrupts off, and have to spin.
+fn can_sleep() -> bool {
+    scheduler::is_initialized() && arch::local_irq_enabled() && !irq::is_in_irq()
+}
+
+fn delay(duration: Duration) {
+    if can_sleep() {
+        time::sleep(duration);
+        return;
+    }
+    let deadline = time::get_uptime() + duration;
+    while time::get_uptime() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+// Lets other threads run while polling a controller.
+fn relax() {
+    if can_sleep() {
+        scheduler::yield_me();
+    } else {
+        core::hint::spin_loop();
+    }
+}
+
+fn max_packet0(field: u8, speed: Speed) -> u16 {
+    if speed == Speed::Super {
+        1 << field.min(9)
+    } else {
+        field as u16
+    }
+}
+
+/// An addressed and configured device.
+pub struct UsbDevice {
+    controller: Arc<dyn HostController>,
+    slot: u8,
+    location: Location,
+    descriptor: DeviceDescriptor,
+    config: Configuration,
+}
+
+impl UsbDevice {
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    pub fn configuration(&self) -> &Configuration {
+        &self.config
+    }
+
+    pub fn controller(&self) -> &Arc<dyn HostController> {
+        &self.controller
+    }
+
+    pub fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let setup = SetupPacket {
+            request_type: request_type | DIR_IN,
+            request,
+            value,
+            index,
+            length: u16::try_from(buf.len()).map_err(|_| code::EINVAL)?,
+        };
+        self.controller.control(self.slot, &setup, buf)
+    }
+
+    pub fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let setup = SetupPacket {
+            request_type: request_type & !DIR_IN,
+            request,
+            value,
+            index,
+            length: u16::try_from(data.len()).map_err(|_| code::EINVAL)?,
+        };
+        let mut data = Vec::from(data);
+        self.controller.control(self.slot, &setup, &mut data)?;
+        Ok(())
+    }
+
+    pub fn transfer_in(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.controller
+            .transfer_in(self.slot, endpoint, buf, timeout)
+    }
+
+    pub fn transfer_out(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.controller
+            .transfer_out(self.slot, endpoint, buf, timeout)
+    }
+
+    /// Clears the halt of an endpoint which stalled.
+    pub fn clear_halt(&self, endpoint: u8) -> Result<(), Error> {
+        self.control_out(
+            TYPE_STANDARD | RECIPIENT_ENDPOINT,
+            REQ_CLEAR_FEATURE,
+            FEATURE_ENDPOINT_HALT,
+            endpoint as u16,
+            &[],
+        )
+    }
+
+    fn get_descriptor(&self, kind: u8, index: u8, buf: &mut [u8]) -> Result<usize, Error> {
+        self.control_in(
+            TYPE_STANDARD | RECIPIENT_DEVICE,
+            REQ_GET_DESCRIPTOR,
+            (kind as u16) << 8 | index as u16,
+            0,
+            buf,
+        )
+    }
+}
+
+/// A class driver, probed with every interface of every device. Drivers
+/// fail with ENODEV for the interfaces they don't handle.
+pub struct ClassDriver {
+    pub name: &'static str,
+    pub probe: fn(&Arc<UsbDevice>, &InterfaceDescriptor) -> Result<(), Error>,
+}
+
+static CLASS_DRIVERS: [ClassDriver; 3] = [
+    ClassDriver {
+        name: "hub",
+        probe: hub::probe,
+    },
+    ClassDriver {
+        name: "usb-storage",
+        probe: storage::probe,
+    },
+    ClassDriver {
+        name: "cdc-acm",
+        probe: acm::probe,
+    },
+];
+
+static DEVICES: SpinRwLock<Vec<Arc<UsbDevice>>> = SpinRwLock::new(Vec::new());
+
+/// Returns the devices enumerated so far.
+pub fn devices() -> Vec<Arc<UsbDevice>> {
+    DEVICES.read().clone()
+}
+
+/// Enumerates the device at `location`, which has just been reset, and
+/// binds its interfaces to the class drivers.
+pub fn enumerate(
+    controller: &Arc<dyn HostController>,
+    location: Location,
+) -> Result<Arc<UsbDevice>, Error> {
+    let slot = controller.alloc_device(&location)?;
+    let device = match configure(controller, slot, location) {
+        Ok(device) => Arc::new(device),
+        Err(e) => {
+            controller.free_device(slot);
+            return Err(e);
+        }
+    };
+    debug!(
+        "USB device {:04x}:{:04x} on {} port {} route {:#x}, {:?} speed",
+        device.descriptor.vendor_id,
+        device.descriptor.product_id,
+        controller.name(),
+        location.root_port,
+        location.route,
+        location.speed
+    );
+    DEVICES.write().push(device.clone());
+    for interface in &device.config.interfaces {
+        for driver in &CLASS_DRIVERS {
+            match (driver.probe)(&device, interface) {
+                Ok(()) => break,
+                Err(e) if e == code::ENODEV => continue,
+                Err(e) => {
+                    warn!(
+                        "{} failed on interface {} of USB device {}: {:?}",
+                        driver.name, interface.number, slot, e
+                    );
+                    break;
+                }
+            }
+        }
+    }
+    Ok(device)
+}
+
+// Reads the descriptors of the device in `slot` and selects its first
+// configuration.
+fn configure(
+    controller: &Arc<dyn HostController>,
+    slot: u8,
+    location: Location,
+) -> Result<UsbDevice, Error> {
+    let mut device = UsbDevice {
+        controller: controller.clone(),
+        slot,
+        location,
+        descriptor: DeviceDescriptor {
+            usb_version: 0,
+            class: 0,
+            subclass: 0,
+            protocol: 0,
+            max_packet_size0: 0,
+            vendor_id: 0,
+            product_id: 0,
+            num_configurations: 0,
+        },
+        config: Configuration {
+            value: 0,
+            interfaces: Vec::new(),
+        },
+    };
+    // Only the first 8 bytes are sure to fit in a packet before the packet
+    // size is known.
+    let mut buf = [0u8; DeviceDescriptor::SIZE];
+    if device.get_descriptor(DESC_DEVICE, 0, &mut buf[..8])? < 8 {
+        return Err(code::EIO);
+    }
+    let max_packet0 = max_packet0(buf[7], location.speed);
+    if max_packet0 != location.speed.default_max_packet0() {
+        controller.set_max_packet0(slot, max_packet0)?;
+    }
+    if device.get_descriptor(DESC_DEVICE, 0, &mut buf)? < buf.len() {
+        return Err(code::EIO);
+    }
+    device.descriptor = DeviceDescriptor::parse(&buf)?;
+    if device.descriptor.num_configurations == 0 {
+        return Err(code::ENODEV);
+    }
+
+    let mut header = [0u8; 9];
+    device.get_descriptor(DESC_CONFIGURATION, 0, &mut header)?;
+    let total = u16::from_le_bytes([header[2], header[3]]) as usize;
+    let mut bytes = vec![0u8; total.max(header.len())];
+    let len = device.get_descriptor(DESC_CONFIGURATION, 0, &mut bytes)?;
+    device.config = Configuration::parse(&bytes[..len])?;
+
+    let endpoints: Vec<EndpointDescriptor> = device
+        .config
+        .interfaces
+        .iter()
+        .flat_map(|interface| interface.endpoints.iter().copied())
+        .collect();
+    controller.configure_endpoints(slot, &endpoints)?;
+    device.control_out(
+        TYPE_STANDARD | RECIPIENT_DEVICE,
+        REQ_SET_CONFIGURATION,
+        device.config.value as u16,
+        0,
+        &[],
+    )?;
+    Ok(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    // A CDC-ACM configuration: a comm interface with a class specific
+    // header and an interrupt endpoint, then a data interface with an
+    // unused alternate setting before its default one.
+    const ACM_CONFIG: [u8; 62] = [
+        9, 2, 62, 0, 2, 1, 0, 0x80, 50, //
+        9, 4, 0, 0, 1, 2, 2, 1, 0, //
+        5, 0x24, 0, 0x10, 1, //
+        7, 5, 0x81, 3, 8, 0, 16, //
+        9, 4, 1, 1, 0, 10, 0, 0, 0, //
+        9, 4, 1, 0, 2, 10, 0, 0, 0, //
+        7, 5, 0x02, 2, 0, 2, 0, //
+        7, 5, 0x82, 2, 0, 2, 0, //
+    ];
+
+    #[test]
+    fn test_usb_descriptors() {
+        let config = Configuration::parse(&ACM_CONFIG).unwrap();
+        assert_eq!(config.value, 1);
+        assert_eq!(config.interfaces.len(), 2);
+        let comm = &config.interfaces[0];
+        assert_eq!((comm.class, comm.subclass), (2, 2));
+        let notify = comm.find_endpoint(TransferType::Interrupt, true).unwrap();
+        assert_eq!((notify.number(), notify.max_packet()), (1, 8));
+        let data = &config.interfaces[1];
+        assert_eq!((data.number, data.class), (1, 10));
+        assert_eq!(data.endpoints.len(), 2);
+        let bulk_out = data.find_endpoint(TransferType::Bulk, false).unwrap();
+        assert_eq!((bulk_out.address, bulk_out.max_packet()), (0x02, 512));
+        assert!(data.find_endpoint(TransferType::Interrupt, true).is_none());
+
+        // Descriptors running past the end are refused.
+        assert_eq!(
+            Configuration::parse(&ACM_CONFIG[..28]).err(),
+            Some(code::EINVAL)
+        );
+
+        let device = DeviceDescriptor::parse(&[
+            18, 1, 0x00, 0x03, 0, 0, 0, 9, 0x6b, 0x1d, 0x04, 0x01, 0, 1, 1, 2, 3, 1,
+        ])
+        .unwrap();
+        assert_eq!(device.usb_version, 0x300);
+        assert_eq!((device.vendor_id, device.product_id), (0x1d6b, 0x0104));
+        assert_eq!(device.max_packet0(Speed::Super), 512);
+        assert_eq!(device.num_configurations, 1);
+        assert!(DeviceDescriptor::parse(&ACM_CONFIG).is_err());
+
+        let setup = SetupPacket {
+            request_type: DIR_IN,
+            request: REQ_GET_DESCRIPTOR,
+            value: (DESC_DEVICE as u16) << 8,
+            index: 0,
+            length: 18,
+        };
+        assert!(setup.is_in());
+        assert_eq!(setup.to_bytes(), [0x80, 6, 0, 1, 0, 0, 18, 0]);
+    }
+
+    // The usb-storage device QEMU attaches to its xHCI controller.
+    #[cfg(target_board = "qemu_virt64_aarch64")]
+    #[test]
+    fn test_usb_enumeration() {
+        const QEMU_VENDOR_ID: u16 = 0x46f4;
+        let devices = devices();
+        let device = devices
+            .iter()
+            .find(|device| device.descriptor().vendor_id == QEMU_VENDOR_ID)
+            .expect("the usb-storage device wasn't enumerated");
+        assert_eq!(device.controller().name(), "xhci0");
+        assert_eq!(device.location().depth, 0);
+        let interface = &device.configuration().interfaces[0];
+        assert_eq!(
+            (interface.class, interface.subclass, interface.protocol),
+            (0x08, 0x06, 0x50)
+        );
+        assert!(interface.find_endpoint(TransferType::Bulk, true).is_some());
+        assert!(interface.find_endpoint(TransferType::Bulk, false).is_some());
+
+        // Now that there are threads, the controller is polled between
+        // yields.
+        let mut buf = [0u8; DeviceDescriptor::SIZE];
+        let len = device
+            .control_in(
+                TYPE_STANDARD | RECIPIENT_DEVICE,
+                REQ_GET_DESCRIPTOR,
+                (DESC_DEVICE as u16) << 8,
+                0,
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(DeviceDescriptor::parse(&buf).unwrap(), *device.descriptor());
+    }
+}
@@ -0,0 +1,526 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! USB mass storage.
+//!
+//! Bulk-only transport devices speaking SCSI, such as flash drives and card
+//! readers, have each of their logical units registered as a block device,
+//! "sda", "sdb" and so on. The primary partitions of an MBR found on a unit
+//! are registered as well, "sda1" to "sda4", so that the FAT volume on a
+//! flash drive can be mounted as "vfat". Units must have 512 byte blocks.
+
+use super::{delay, InterfaceDescriptor, TransferType, UsbDevice, RECIPIENT_INTERFACE, TYPE_CLASS};
+use crate::{
+    devices::{
+        block::{Block, BlockDriverOps, ErrorType},
+        devno, DeviceClass, DeviceManager,
+    },
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use log::{debug, warn};
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+const REQ_GET_MAX_LUN: u8 = 0xfe;
+const REQ_BULK_ONLY_RESET: u8 = 0xff;
+const MAX_LUNS: u8 = 16;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_SIZE: usize = 31;
+const CSW_SIZE: usize = 13;
+const CBW_DATA_IN: u8 = 0x80;
+const CSW_PASSED: u8 = 0;
+const CSW_FAILED: u8 = 1;
+
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+// Peripheral device type of disks, in the INQUIRY data.
+const TYPE_DIRECT_ACCESS: u8 = 0x00;
+
+const BLOCK_SIZE: usize = 512;
+const MAX_BLOCKS_PER_COMMAND: usize = 128;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+// Devices report a unit attention to the first commands after a reset.
+const READY_TRIES: usize = 5;
+const READY_POLL: Duration = Duration::from_millis(100);
+const MAX_DISKS: usize = 26;
+
+const MBR_ENTRIES: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+// Disks with a GPT have a single partition of this type in their MBR.
+const MBR_TYPE_GPT: u8 = 0xee;
+
+static DISKS: AtomicUsize = AtomicUsize::new(0);
+
+enum Data<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+
+// The bulk-only transport of an interface, shared by its logical units.
+struct Transport {
+    device: Arc<UsbDevice>,
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    tag: u32,
+}
+
+/// A logical unit, or a partition of one.
+pub struct UsbStorage {
+    transport: Arc<SpinLock<Transport>>,
+    lun: u8,
+    start: u64,
+    blocks: u64,
+}
+
+// A READ(10) or WRITE(10) of `count` blocks from `lba`.
+fn rw10(opcode: u8, lba: usize, count: usize) -> Result<[u8; 10], Error> {
+    let lba = u32::try_from(lba).map_err(|_| code::EINVAL)?;
+    let count = u16::try_from(count).map_err(|_| code::EINVAL)?;
+    let mut cb = [0u8; 10];
+    cb[0] = opcode;
+    cb[2..6].copy_from_slice(&lba.to_be_bytes());
+    cb[7..9].copy_from_slice(&count.to_be_bytes());
+    Ok(cb)
+}
+
+impl Transport {
+    // Runs a SCSI command on `lun`, returning the bytes of data moved.
+    // Fails with EIO if the device reports the command failed.
+    fn command(&mut self, lun: u8, cb: &[u8], data: Data) -> Result<usize, Error> {
+        self.tag = self.tag.wrapping_add(1);
+        let (len, flags) = match &data {
+            Data::None => (0, 0),
+            Data::In(buf) => (buf.len(), CBW_DATA_IN),
+            Data::Out(buf) => (buf.len(), 0),
+        };
+        let mut cbw = [0u8; CBW_SIZE];
+        cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        cbw[12] = flags;
+        cbw[13] = lun;
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+        if let Err(e) = self
+            .device
+            .transfer_out(self.bulk_out, &cbw, COMMAND_TIMEOUT)
+        {
+            self.reset_recovery();
+            return Err(e);
+        }
+
+        let (moved, endpoint) = match data {
+            Data::None => (Ok(0), self.bulk_in),
+            Data::In(buf) => (
+                self.device.transfer_in(self.bulk_in, buf, COMMAND_TIMEOUT),
+                self.bulk_in,
+            ),
+            Data::Out(buf) => (
+                self.device
+                    .transfer_out(self.bulk_out, buf, COMMAND_TIMEOUT),
+                self.bulk_out,
+            ),
+        };
+        let moved = match moved {
+            Ok(moved) => moved,
+            // The device ends the data stage early by stalling, the status
+            // follows.
+            Err(e) if e == code::EPIPE => {
+                self.device.clear_halt(endpoint)?;
+                0
+            }
+            Err(e) => {
+                self.reset_recovery();
+                return Err(e);
+            }
+        };
+
+        let csw = self.read_csw()?;
+        let signature = u32::from_le_bytes(csw[0..4].try_into().unwrap());
+        let tag = u32::from_le_bytes(csw[4..8].try_into().unwrap());
+        if signature != CSW_SIGNATURE || tag != self.tag {
+            self.reset_recovery();
+            return Err(code::EIO);
+        }
+        match csw[12] {
+            CSW_PASSED => Ok(moved),
+            CSW_FAILED => Err(code::EIO),
+            // A phase error, the device is lost.
+            _ => {
+                self.reset_recovery();
+                Err(code::EIO)
+            }
+        }
+    }
+
+    fn read_csw(&self) -> Result<[u8; CSW_SIZE], Error> {
+        let mut csw = [0u8; CSW_SIZE];
+        let len = match self
+            .device
+            .transfer_in(self.bulk_in, &mut csw, COMMAND_TIMEOUT)
+        {
+            // Stalled at the end of the data stage, the status is sent
+            // again once the halt is cleared.
+            Err(e) if e == code::EPIPE => {
+                self.device.clear_halt(self.bulk_in)?;
+                self.device
+                    .transfer_in(self.bulk_in, &mut csw, COMMAND_TIMEOUT)?
+            }
+            result => result?,
+        };
+        if len != CSW_SIZE {
+            self.reset_recovery();
+            return Err(code::EIO);
+        }
+        Ok(csw)
+    }
+
+    fn reset_recovery(&self) {
+        let _ = self.device.control_out(
+            TYPE_CLASS | RECIPIENT_INTERFACE,
+            REQ_BULK_ONLY_RESET,
+            0,
+            self.interface as u16,
+            &[],
+        );
+        let _ = self.device.clear_halt(self.bulk_in);
+        let _ = self.device.clear_halt(self.bulk_out);
+    }
+
+    // Returns the number of logical units. Devices with a single one may
+    // stall the request.
+    fn luns(&self) -> u8 {
+        let mut max_lun = [0u8; 1];
+        match self.device.control_in(
+            TYPE_CLASS | RECIPIENT_INTERFACE,
+            REQ_GET_MAX_LUN,
+            0,
+            self.interface as u16,
+            &mut max_lun,
+        ) {
+            Ok(1) => (max_lun[0] + 1).min(MAX_LUNS),
+            _ => 1,
+        }
+    }
+}
+
+impl UsbStorage {
+    fn command(&self, cb: &[u8], data: Data) -> Result<usize, Error> {
+        self.transport.lock().command(self.lun, cb, data)
+    }
+
+    fn wait_ready(&self) -> Result<(), Error> {
+        for _ in 0..READY_TRIES {
+            if self
+                .command(&[TEST_UNIT_READY, 0, 0, 0, 0, 0], Data::None)
+                .is_ok()
+            {
+                return Ok(());
+            }
+            // Clears the unit attention.
+            let mut sense = [0u8; 18];
+            let _ = self.command(
+                &[REQUEST_SENSE, 0, 0, 0, sense.len() as u8, 0],
+                Data::In(&mut sense),
+            );
+            delay(READY_POLL);
+        }
+        Err(code::EIO)
+    }
+
+    // Returns the peripheral device type.
+    fn inquiry(&self) -> Result<u8, Error> {
+        let mut data = [0u8; 36];
+        let len = self.command(
+            &[INQUIRY, 0, 0, 0, data.len() as u8, 0],
+            Data::In(&mut data),
+        )?;
+        if len < 8 {
+            return Err(code::EIO);
+        }
+        debug!(
+            "USB storage {}",
+            core::str::from_utf8(&data[8..len.min(32)]).unwrap_or("?")
+        );
+        Ok(data[0] & 0x1f)
+    }
+
+    // Returns the number of blocks and their size.
+    fn read_capacity(&self) -> Result<(u64, usize), Error> {
+        let mut data = [0u8; 8];
+        let len = self.command(
+            &[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            Data::In(&mut data),
+        )?;
+        if len < data.len() {
+            return Err(code::EIO);
+        }
+        let last = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let block_size = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        Ok((last as u64 + 1, block_size as usize))
+    }
+
+    // Checks that `len` bytes from `block_id` are whole blocks within the
+    // unit or partition, returning the address of the first one.
+    fn lba(&self, block_id: usize, len: usize) -> Result<usize, Error> {
+        if len % BLOCK_SIZE != 0 {
+            return Err(code::EINVAL);
+        }
+        let end = (block_id as u64).checked_add((len / BLOCK_SIZE) as u64);
+        if end.is_none_or(|end| end > self.blocks) {
+            return Err(code::EINVAL);
+        }
+        Ok(self.start as usize + block_id)
+    }
+
+    fn partition(&self, start: u64, blocks: u64) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            lun: self.lun,
+            start: self.start + start,
+            blocks,
+        }
+    }
+}
+
+impl ErrorType for UsbStorage {
+    type Error = Error;
+}
+
+impl BlockDriverOps for UsbStorage {
+    fn capacity(&self) -> u64 {
+        self.blocks
+    }
+
+    fn sector_size(&self) -> u16 {
+        BLOCK_SIZE as u16
+    }
+
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let lba = self.lba(block_id, buf.len())?;
+        for (i, chunk) in buf
+            .chunks_mut(MAX_BLOCKS_PER_COMMAND * BLOCK_SIZE)
+            .enumerate()
+        {
+            let cb = rw10(
+                READ_10,
+                lba + i * MAX_BLOCKS_PER_COMMAND,
+                chunk.len() / BLOCK_SIZE,
+            )?;
+            let len = chunk.len();
+            if self.command(&cb, Data::In(chunk))? != len {
+                return Err(code::EIO);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> Result<(), Self::Error> {
+        let lba = self.lba(block_id, buf.len())?;
+        for (i, chunk) in buf.chunks(MAX_BLOCKS_PER_COMMAND * BLOCK_SIZE).enumerate() {
+            let cb = rw10(
+                WRITE_10,
+                lba + i * MAX_BLOCKS_PER_COMMAND,
+                chunk.len() / BLOCK_SIZE,
+            )?;
+            if self.command(&cb, Data::Out(chunk))? != chunk.len() {
+                return Err(code::EIO);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.command(
+            &[SYNCHRONIZE_CACHE_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            Data::None,
+        )
+        .map(|_| ())
+    }
+}
+
+// Returns the first block and the number of blocks of the primary
+// partitions in `sector`, if it's an MBR. The boot sector of a volume
+// spanning the whole disk has the same signature, but not valid entries.
+fn parse_mbr(sector: &[u8], blocks: u64) -> Option<Vec<(u64, u64)>> {
+    if sector.len() < BLOCK_SIZE || sector[510..512] != MBR_SIGNATURE {
+        return None;
+    }
+    let mut partitions = Vec::new();
+    for entry in sector[MBR_ENTRIES..510].chunks_exact(MBR_ENTRY_SIZE) {
+        let (status, kind) = (entry[0], entry[4]);
+        let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+        let count = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+        if status & 0x7f != 0 {
+            return None;
+        }
+        if kind == 0 || count == 0 {
+            continue;
+        }
+        if kind == MBR_TYPE_GPT {
+            warn!("USB storage with a GPT isn't supported");
+            return None;
+        }
+        if start == 0 || start + count > blocks {
+            return None;
+        }
+        partitions.push((start, count));
+    }
+    if partitions.is_empty() {
+        return None;
+    }
+    Some(partitions)
+}
+
+fn register(name: String, storage: UsbStorage) -> Result<(), Error> {
+    let major = devno::register_major(DeviceClass::Block, 0, "usb-storage")?;
+    let id = devno::alloc_minor(DeviceClass::Block, major)?;
+    let block = Block::new(&name, id, Arc::new(SpinLock::new(storage)));
+    DeviceManager::get().register_device(name, Arc::new(block))
+}
+
+// Registers logical unit `lun`, and its partitions. Fails with ENODEV if
+// it isn't a disk.
+fn probe_lun(transport: &Arc<SpinLock<Transport>>, lun: u8) -> Result<(), Error> {
+    let mut storage = UsbStorage {
+        transport: transport.clone(),
+        lun,
+        start: 0,
+        blocks: 0,
+    };
+    if storage.inquiry()? != TYPE_DIRECT_ACCESS {
+        return Err(code::ENODEV);
+    }
+    storage.wait_ready()?;
+    let (blocks, block_size) = storage.read_capacity()?;
+    if block_size != BLOCK_SIZE {
+        warn!(
+            "USB storage with {} byte blocks isn't supported",
+            block_size
+        );
+        return Err(code::ENOTSUP);
+    }
+    storage.blocks = blocks;
+
+    let mut sector = vec![0u8; BLOCK_SIZE];
+    let partitions = match storage.read_blocks(0, &mut sector) {
+        Ok(()) => parse_mbr(&sector, blocks).unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to read the MBR of USB storage: {:?}", e);
+            Vec::new()
+        }
+    };
+    let index = DISKS.fetch_add(1, Ordering::Relaxed);
+    if index >= MAX_DISKS {
+        return Err(code::ENOMEM);
+    }
+    let name = format!("sd{}", (b'a' + index as u8) as char);
+    let partitions: Vec<UsbStorage> = partitions
+        .iter()
+        .map(|&(start, count)| storage.partition(start, count))
+        .collect();
+    register(name.clone(), storage)?;
+    for (i, partition) in partitions.into_iter().enumerate() {
+        register(format!("{}{}", name, i + 1), partition)?;
+    }
+    Ok(())
+}
+
+pub(super) fn probe(device: &Arc<UsbDevice>, interface: &InterfaceDescriptor) -> Result<(), Error> {
+    if (interface.class, interface.subclass, interface.protocol)
+        != (CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY)
+    {
+        return Err(code::ENODEV);
+    }
+    let (Some(bulk_in), Some(bulk_out)) = (
+        interface.find_endpoint(TransferType::Bulk, true),
+        interface.find_endpoint(TransferType::Bulk, false),
+    ) else {
+        return Err(code::EINVAL);
+    };
+    let transport = Transport {
+        device: device.clone(),
+        interface: interface.number,
+        bulk_in: bulk_in.address,
+        bulk_out: bulk_out.address,
+        tag: 0,
+    };
+    let luns = transport.luns();
+    let transport = Arc::new(SpinLock::new(transport));
+    let mut result = Err(code::ENODEV);
+    for lun in 0..luns {
+        match probe_lun(&transport, lun) {
+            Ok(()) => result = Ok(()),
+            Err(e) if e == code::ENODEV => (),
+            // Card readers have a unit per slot, which may be empty.
+            Err(e) => warn!("Failed to probe LUN {} of USB storage: {:?}", lun, e),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn entry(sector: &mut [u8], i: usize, kind: u8, start: u32, count: u32) {
+        let entry = &mut sector[MBR_ENTRIES + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse_mbr() {
+        let mut sector = vec![0u8; BLOCK_SIZE];
+        assert_eq!(parse_mbr(&sector, 4096), None);
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE);
+        assert_eq!(parse_mbr(&sector, 4096), None);
+
+        // FAT32 with LBA, and a second partition after it.
+        entry(&mut sector, 0, 0x0c, 2048, 1024);
+        entry(&mut sector, 2, 0x83, 3072, 1024);
+        sector[MBR_ENTRIES] = 0x80;
+        assert_eq!(
+            parse_mbr(&sector, 4096),
+            Some(vec![(2048, 1024), (3072, 1024)])
+        );
+        assert_eq!(parse_mbr(&sector, 4000), None, "past the end of the disk");
+
+        // The boot code of a volume boot sector.
+        sector[MBR_ENTRIES] = 0x4f;
+        assert_eq!(parse_mbr(&sector, 4096), None);
+        sector[MBR_ENTRIES] = 0;
+        entry(&mut sector, 0, MBR_TYPE_GPT, 1, 4095);
+        assert_eq!(parse_mbr(&sector, 4096), None);
+    }
+}
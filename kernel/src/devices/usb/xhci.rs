@@ -0,0 +1,1105 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! xHCI host controller driver.
+//!
+//! The controller runs without interrupts: the event ring is polled by
+//! whoever waits for a command or a transfer to complete, which yields
+//! between polls so that slow devices don't hold up the other threads. Each endpoint has
+//! at most one transfer in flight, made of a single TRB per stage, so data
+//! goes through bounce buffers which never cross the 64 KiB boundaries
+//! TRBs can't cross.
+
+use super::{
+    delay, enumerate, relax, EndpointDescriptor, HostController, Location, SetupPacket, Speed,
+    TransferType, CONTROL_TIMEOUT,
+};
+use crate::{
+    allocator::page::{self, PAGE_SIZE},
+    error::{code, Error},
+    sync::SpinLock,
+    time,
+};
+use alloc::{
+    alloc::{alloc_zeroed, dealloc, handle_alloc_error},
+    collections::BTreeMap,
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    alloc::Layout,
+    ptr,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+    time::Duration,
+};
+use log::{debug, warn};
+
+// TRBs of a ring, which fills a page.
+const RING_SIZE: usize = 256;
+const TRB_SIZE: usize = 16;
+const MAX_TRB_LEN: usize = 0x10000;
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(500);
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+const PORT_RESET_TIMEOUT: Duration = Duration::from_millis(500);
+// Reset recovery time, before the device is addressed.
+const PORT_RECOVERY: Duration = Duration::from_millis(10);
+// Time for the devices to connect once the ports are powered.
+const POWER_ON_DELAY: Duration = Duration::from_millis(100);
+
+// Capability registers.
+const CAPLENGTH: usize = 0x00;
+const HCSPARAMS1: usize = 0x04;
+const HCSPARAMS2: usize = 0x08;
+const HCCPARAMS1: usize = 0x10;
+const DBOFF: usize = 0x14;
+const RTSOFF: usize = 0x18;
+
+const HCCPARAMS1_CSZ: u32 = 1 << 2;
+const HCCPARAMS1_PPC: u32 = 1 << 3;
+
+// Operational registers.
+const USBCMD: usize = 0x00;
+const USBSTS: usize = 0x04;
+const PAGESIZE: usize = 0x08;
+const CRCR: usize = 0x18;
+const DCBAAP: usize = 0x30;
+const CONFIG: usize = 0x38;
+const PORTSC: usize = 0x400;
+
+const USBCMD_RS: u32 = 1 << 0;
+const USBCMD_HCRST: u32 = 1 << 1;
+const USBSTS_HCH: u32 = 1 << 0;
+const USBSTS_CNR: u32 = 1 << 11;
+
+const PORTSC_CCS: u32 = 1 << 0;
+const PORTSC_PED: u32 = 1 << 1;
+const PORTSC_PR: u32 = 1 << 4;
+const PORTSC_PP: u32 = 1 << 9;
+const PORTSC_PRC: u32 = 1 << 21;
+const PORTSC_CHANGES: u32 = 0x7f << 17;
+// Bits written back as they're read. Writing the others back would
+// disable the port or clear change bits.
+const PORTSC_PRESERVE: u32 = (1 << 0)
+    | (1 << 3)
+    | (0xf << 5)
+    | PORTSC_PP
+    | (0xf << 10)
+    | (0x3 << 14)
+    | (0x7 << 25)
+    | (1 << 30);
+
+// Registers of interrupter 0, in the runtime registers.
+const IR0: usize = 0x20;
+const ERSTSZ: usize = 0x08;
+const ERSTBA: usize = 0x10;
+const ERDP: usize = 0x18;
+const ERDP_EHB: u64 = 1 << 3;
+
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_DISABLE_SLOT: u32 = 10;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_RESET_ENDPOINT: u32 = 14;
+const TRB_STOP_ENDPOINT: u32 = 15;
+const TRB_SET_TR_DEQUEUE: u32 = 16;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_ISP: u32 = 1 << 2;
+const TRB_CHAIN: u32 = 1 << 4;
+const TRB_IOC: u32 = 1 << 5;
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+// Transfer types of setup TRBs.
+const TRT_OUT: u32 = 2 << 16;
+const TRT_IN: u32 = 3 << 16;
+
+const CC_SUCCESS: u8 = 1;
+const CC_BABBLE: u8 = 3;
+const CC_STALL: u8 = 6;
+const CC_SHORT_PACKET: u8 = 13;
+const CC_STOPPED: u8 = 26;
+const CC_STOPPED_LENGTH_INVALID: u8 = 27;
+
+const EP_TYPE_CONTROL: u32 = 4;
+// Added to the type of OUT endpoints for IN ones.
+const EP_TYPE_IN: u32 = 4;
+// Retries of transaction errors.
+const EP_CERR: u32 = 3 << 1;
+
+static CONTROLLERS: SpinLock<Vec<Arc<Xhci>>> = SpinLock::new(Vec::new());
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+fn read32(addr: usize) -> u32 {
+    // SAFETY: Only called on the registers of a probed controller, which
+    // are mapped.
+    unsafe { ptr::read_volatile(addr as *const u32) }
+}
+
+fn write32(addr: usize, value: u32) {
+    // SAFETY: As for read32.
+    unsafe { ptr::write_volatile(addr as *mut u32, value) }
+}
+
+fn write64(addr: usize, value: u64) {
+    write32(addr, value as u32);
+    write32(addr + 4, (value >> 32) as u32);
+}
+
+fn wait_until(timeout: Duration, mut done: impl FnMut() -> bool) -> Result<(), Error> {
+    let deadline = time::get_uptime() + timeout;
+    while !done() {
+        if time::get_uptime() >= deadline {
+            return Err(code::ETIMEDOUT);
+        }
+        relax();
+    }
+    Ok(())
+}
+
+// Memory shared with the controller. Like for VirtIO, physical addresses
+// are the virtual ones.
+struct Dma {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+// SAFETY: The memory is owned, and only accessed under the controller lock.
+unsafe impl Send for Dma {}
+unsafe impl Sync for Dma {}
+
+impl Dma {
+    // Page aligned buffers are whole pages, attributed to the controller.
+    #[track_caller]
+    fn new(size: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = if align == PAGE_SIZE {
+            page::alloc_pages(size.div_ceil(PAGE_SIZE), "xhci")
+        } else {
+            // SAFETY: The size isn't 0.
+            unsafe { alloc_zeroed(layout) }
+        };
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        Self { ptr, layout }
+    }
+
+    // A buffer of `len` bytes, at most MAX_TRB_LEN, aligned so that it
+    // doesn't cross a 64 KiB boundary.
+    fn bounce(len: usize) -> Self {
+        let size = len.next_power_of_two().max(64);
+        Self::new(size, size)
+    }
+
+    fn addr(&self) -> u64 {
+        self.ptr as u64
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        crate::kdebug_assert!(offset + 4 <= self.layout.size());
+        // SAFETY: The offset is within the buffer.
+        unsafe { ptr::read_volatile(self.ptr.add(offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        crate::kdebug_assert!(offset + 4 <= self.layout.size());
+        // SAFETY: As for read32.
+        unsafe { ptr::write_volatile(self.ptr.add(offset) as *mut u32, value) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+
+    fn copy_from(&self, data: &[u8]) {
+        assert!(data.len() <= self.layout.size());
+        // SAFETY: The buffer is large enough.
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.ptr, data.len()) }
+    }
+
+    fn copy_to(&self, data: &mut [u8]) {
+        assert!(data.len() <= self.layout.size());
+        // SAFETY: As for copy_from.
+        unsafe { ptr::copy_nonoverlapping(self.ptr, data.as_mut_ptr(), data.len()) }
+    }
+}
+
+impl Drop for Dma {
+    fn drop(&mut self) {
+        if self.layout.align() == PAGE_SIZE {
+            page::free_pages(self.ptr);
+        } else {
+            // SAFETY: Allocated with the same layout in new.
+            unsafe { dealloc(self.ptr, self.layout) }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Trb {
+    param: u64,
+    status: u32,
+    control: u32,
+}
+
+impl Trb {
+    fn kind(&self) -> u32 {
+        (self.control >> 10) & 0x3f
+    }
+
+    fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    fn slot(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    fn endpoint(&self) -> usize {
+        ((self.control >> 16) & 0x1f) as usize
+    }
+}
+
+// A command or transfer ring, whose last TRB links back to the first.
+struct Ring {
+    trbs: Dma,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new() -> Self {
+        let trbs = Dma::new(RING_SIZE * TRB_SIZE, PAGE_SIZE);
+        trbs.write64((RING_SIZE - 1) * TRB_SIZE, trbs.addr());
+        Self {
+            trbs,
+            enqueue: 0,
+            cycle: true,
+        }
+    }
+
+    // The enqueue pointer with the cycle state, as the controller expects
+    // dequeue pointers.
+    fn dequeue_pointer(&self) -> u64 {
+        (self.trbs.addr() + (self.enqueue * TRB_SIZE) as u64) | self.cycle as u64
+    }
+
+    // Returns the address of the TRB, which the controller gives back in
+    // the events about it.
+    fn push(&mut self, param: u64, status: u32, control: u32) -> u64 {
+        let offset = self.enqueue * TRB_SIZE;
+        self.trbs.write64(offset, param);
+        self.trbs.write32(offset + 8, status);
+        // The controller owns the TRB as soon as the cycle bit flips.
+        fence(Ordering::Release);
+        self.trbs.write32(offset + 12, control | self.cycle as u32);
+        self.enqueue += 1;
+        if self.enqueue == RING_SIZE - 1 {
+            // A TD going on past the link has the link chained.
+            let link = TRB_LINK << 10 | TRB_TOGGLE_CYCLE | (control & TRB_CHAIN);
+            fence(Ordering::Release);
+            self.trbs
+                .write32(self.enqueue * TRB_SIZE + 12, link | self.cycle as u32);
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        self.trbs.addr() + offset as u64
+    }
+}
+
+struct EventRing {
+    trbs: Dma,
+    // The event ring segment table, of one segment.
+    segments: Dma,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    fn new() -> Self {
+        let trbs = Dma::new(RING_SIZE * TRB_SIZE, PAGE_SIZE);
+        let segments = Dma::new(16, 64);
+        segments.write64(0, trbs.addr());
+        segments.write32(8, RING_SIZE as u32);
+        Self {
+            trbs,
+            segments,
+            dequeue: 0,
+            cycle: true,
+        }
+    }
+
+    fn dequeue_pointer(&self) -> u64 {
+        self.trbs.addr() + (self.dequeue * TRB_SIZE) as u64
+    }
+
+    fn pop(&mut self) -> Option<Trb> {
+        let offset = self.dequeue * TRB_SIZE;
+        let control = self.trbs.read32(offset + 12);
+        if (control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let trb = Trb {
+            param: self.trbs.read32(offset) as u64 | (self.trbs.read32(offset + 4) as u64) << 32,
+            status: self.trbs.read32(offset + 8),
+            control,
+        };
+        self.dequeue += 1;
+        if self.dequeue == RING_SIZE {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+}
+
+// A transfer in flight.
+struct Td {
+    // The TRB of the data stage, whose events tell the bytes moved.
+    data_trb: Option<u64>,
+    data_len: usize,
+    // The TRB completing the transfer.
+    last_trb: u64,
+    actual: usize,
+    stopped: bool,
+    result: Option<Result<usize, Error>>,
+}
+
+impl Td {
+    fn new(data_trb: Option<u64>, data_len: usize, last_trb: u64) -> Self {
+        Self {
+            data_trb,
+            data_len,
+            last_trb,
+            // Data stages only report short packets.
+            actual: data_len,
+            stopped: false,
+            result: None,
+        }
+    }
+
+    fn update(&mut self, event: &Trb) {
+        let code = event.completion_code();
+        let residual = (event.status & 0xff_ffff) as usize;
+        if Some(event.param) == self.data_trb {
+            self.actual = if code == CC_STOPPED_LENGTH_INVALID {
+                0
+            } else {
+                self.data_len.saturating_sub(residual)
+            };
+        }
+        self.result = match code {
+            // A short data stage of a control transfer is followed by the
+            // status stage.
+            CC_SUCCESS | CC_SHORT_PACKET if event.param == self.last_trb => Some(Ok(self.actual)),
+            CC_SUCCESS | CC_SHORT_PACKET => None,
+            CC_STOPPED | CC_STOPPED_LENGTH_INVALID => {
+                self.stopped = true;
+                None
+            }
+            CC_STALL => Some(Err(code::EPIPE)),
+            CC_BABBLE => Some(Err(code::EOVERFLOW)),
+            _ => {
+                debug!("xHCI transfer failed with completion code {}", code);
+                Some(Err(code::EIO))
+            }
+        };
+    }
+}
+
+struct Endpoint {
+    ring: Ring,
+    td: Option<Td>,
+}
+
+struct Slot {
+    input: Dma,
+    output: Dma,
+    // By device context index, 1 being the default control pipe.
+    endpoints: [Option<Endpoint>; 32],
+}
+
+struct State {
+    commands: Ring,
+    events: EventRing,
+    dcbaa: Dma,
+    _scratchpad: Vec<Dma>,
+    slots: BTreeMap<u8, Slot>,
+    // Completion code and slot of the commands done, by TRB.
+    completions: BTreeMap<u64, (u8, u8)>,
+}
+
+impl State {
+    fn endpoint(&mut self, slot: u8, dci: usize) -> Option<&mut Endpoint> {
+        self.slots.get_mut(&slot)?.endpoints[dci].as_mut()
+    }
+}
+
+pub struct Xhci {
+    name: String,
+    op: usize,
+    runtime: usize,
+    doorbells: usize,
+    ports: u8,
+    context_size: usize,
+    state: SpinLock<State>,
+}
+
+// Device context index of an endpoint.
+fn dci(endpoint: u8) -> usize {
+    (endpoint & 0x0f) as usize * 2 + (endpoint >> 7) as usize
+}
+
+fn speed_id(speed: Speed) -> u32 {
+    match speed {
+        Speed::Full => 1,
+        Speed::Low => 2,
+        Speed::High => 3,
+        Speed::Super => 4,
+    }
+}
+
+// The interval of a periodic endpoint, in 125 us units as a power of two.
+fn interval(ep: &EndpointDescriptor, speed: Speed) -> u32 {
+    let interval = ep.interval.clamp(1, 16) as u32;
+    match (ep.transfer_type(), speed) {
+        (TransferType::Bulk | TransferType::Control, _) => 0,
+        (_, Speed::High | Speed::Super) => interval - 1,
+        (TransferType::Isochronous, _) => interval + 2,
+        // Full and low speed interrupt endpoints give it in frames.
+        _ => (ep.interval.max(1) as u32 * 8).ilog2().clamp(3, 10),
+    }
+}
+
+impl Xhci {
+    fn new(base: usize) -> Result<Self, Error> {
+        let op = base + (read32(base + CAPLENGTH) & 0xff) as usize;
+        let hcsparams1 = read32(base + HCSPARAMS1);
+        let hcsparams2 = read32(base + HCSPARAMS2);
+        let hccparams1 = read32(base + HCCPARAMS1);
+        let max_slots = hcsparams1 & 0xff;
+        let ports = (hcsparams1 >> 24) as u8;
+        let scratchpads = ((hcsparams2 >> 21) & 0x1f) << 5 | (hcsparams2 >> 27);
+
+        wait_until(COMMAND_TIMEOUT, || read32(op + USBSTS) & USBSTS_CNR == 0)?;
+        write32(op + USBCMD, read32(op + USBCMD) & !USBCMD_RS);
+        wait_until(HALT_TIMEOUT, || read32(op + USBSTS) & USBSTS_HCH != 0)?;
+        write32(op + USBCMD, USBCMD_HCRST);
+        wait_until(COMMAND_TIMEOUT, || {
+            read32(op + USBCMD) & USBCMD_HCRST == 0 && read32(op + USBSTS) & USBSTS_CNR == 0
+        })?;
+
+        write32(op + CONFIG, max_slots);
+        let dcbaa = Dma::new(PAGE_SIZE, PAGE_SIZE);
+        let mut scratchpad = Vec::new();
+        if scratchpads > 0 {
+            let page_size = 1 << ((read32(op + PAGESIZE) & 0xffff).trailing_zeros() + 12);
+            let array = Dma::new(scratchpads as usize * 8, 64);
+            for i in 0..scratchpads as usize {
+                let page = Dma::new(page_size, page_size);
+                array.write64(i * 8, page.addr());
+                scratchpad.push(page);
+            }
+            dcbaa.write64(0, array.addr());
+            scratchpad.push(array);
+        }
+        write64(op + DCBAAP, dcbaa.addr());
+        let commands = Ring::new();
+        write64(op + CRCR, commands.dequeue_pointer());
+
+        let runtime = base + (read32(base + RTSOFF) & !0x1f) as usize;
+        let events = EventRing::new();
+        write32(runtime + IR0 + ERSTSZ, 1);
+        write64(runtime + IR0 + ERDP, events.dequeue_pointer());
+        write64(runtime + IR0 + ERSTBA, events.segments.addr());
+
+        write32(op + USBCMD, USBCMD_RS);
+        wait_until(HALT_TIMEOUT, || read32(op + USBSTS) & USBSTS_HCH == 0)?;
+        if hccparams1 & HCCPARAMS1_PPC != 0 {
+            for port in 1..=ports {
+                let portsc = op + PORTSC + 0x10 * (port as usize - 1);
+                write32(portsc, read32(portsc) & PORTSC_PRESERVE | PORTSC_PP);
+            }
+        }
+
+        Ok(Self {
+            name: format!("xhci{}", NEXT_INDEX.fetch_add(1, Ordering::Relaxed)),
+            op,
+            runtime,
+            doorbells: base + (read32(base + DBOFF) & !0x3) as usize,
+            ports,
+            context_size: if hccparams1 & HCCPARAMS1_CSZ != 0 {
+                64
+            } else {
+                32
+            },
+            state: SpinLock::new(State {
+                commands,
+                events,
+                dcbaa,
+                _scratchpad: scratchpad,
+                slots: BTreeMap::new(),
+                completions: BTreeMap::new(),
+            }),
+        })
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u32) {
+        fence(Ordering::SeqCst);
+        write32(self.doorbells + 4 * slot as usize, target);
+    }
+
+    // Hands the events to the commands and transfers waiting for them.
+    fn poll_events(&self, state: &mut State) {
+        let mut handled = false;
+        while let Some(event) = state.events.pop() {
+            handled = true;
+            match event.kind() {
+                TRB_TRANSFER_EVENT => {
+                    if let Some(td) = state
+                        .endpoint(event.slot(), event.endpoint())
+                        .and_then(|ep| ep.td.as_mut())
+                    {
+                        td.update(&event);
+                    }
+                }
+                TRB_COMMAND_COMPLETION => {
+                    state
+                        .completions
+                        .insert(event.param, (event.completion_code(), event.slot()));
+                }
+                // Port changes aren't acted upon, the ports are only
+                // scanned at probe time.
+                _ => (),
+            }
+        }
+        if handled {
+            write64(
+                self.runtime + IR0 + ERDP,
+                state.events.dequeue_pointer() | ERDP_EHB,
+            );
+        }
+    }
+
+    // Polls the events until `done` returns something, or `timeout`
+    // elapses. The lock is dropped, and the CPU yielded, between polls.
+    fn wait<T>(
+        &self,
+        timeout: Duration,
+        mut done: impl FnMut(&mut State) -> Option<T>,
+    ) -> Option<T> {
+        let deadline = time::get_uptime() + timeout;
+        loop {
+            {
+                let mut state = self.state.irqsave_lock();
+                self.poll_events(&mut state);
+                if let Some(value) = done(&mut state) {
+                    return Some(value);
+                }
+            }
+            if time::get_uptime() >= deadline {
+                return None;
+            }
+            relax();
+        }
+    }
+
+    // Runs a command, returning the slot of its completion event.
+    fn command(&self, param: u64, control: u32) -> Result<u8, Error> {
+        let trb = {
+            let mut state = self.state.irqsave_lock();
+            let trb = state.commands.push(param, 0, control);
+            self.ring_doorbell(0, 0);
+            trb
+        };
+        match self.wait(COMMAND_TIMEOUT, |state| state.completions.remove(&trb)) {
+            Some((CC_SUCCESS, slot)) => Ok(slot),
+            Some((code, _)) => {
+                debug!(
+                    "xHCI command {} failed with completion code {}",
+                    (control >> 10) & 0x3f,
+                    code
+                );
+                Err(code::EIO)
+            }
+            None => {
+                warn!(
+                    "{}: command {} timed out",
+                    self.name,
+                    (control >> 10) & 0x3f
+                );
+                Err(code::ETIMEDOUT)
+            }
+        }
+    }
+
+    fn endpoint_command(&self, kind: u32, slot: u8, dci: usize) -> Result<u8, Error> {
+        self.command(0, kind << 10 | (dci as u32) << 16 | (slot as u32) << 24)
+    }
+
+    // Writes dword `dword` of context `index` of the input context, 0
+    // being the input control context.
+    fn input_write(&self, slot: &Slot, index: usize, dword: usize, value: u32) {
+        slot.input
+            .write32(index * self.context_size + dword * 4, value);
+    }
+
+    fn input_read(&self, slot: &Slot, index: usize, dword: usize) -> u32 {
+        slot.input.read32(index * self.context_size + dword * 4)
+    }
+
+    // Runs a command on the input context of `slot`, with the contexts in
+    // `add` flagged as added.
+    fn context_command(&self, kind: u32, slot: u8, add: u32) -> Result<(), Error> {
+        let input = {
+            let state = self.state.irqsave_lock();
+            let slot = state.slots.get(&slot).ok_or(code::ENODEV)?;
+            self.input_write(slot, 0, 0, 0);
+            self.input_write(slot, 0, 1, add);
+            slot.input.addr()
+        };
+        self.command(input, kind << 10 | (slot as u32) << 24)
+            .map(|_| ())
+    }
+
+    fn submit(
+        &self,
+        slot: u8,
+        dci: usize,
+        build: impl FnOnce(&mut Ring) -> Td,
+    ) -> Result<(), Error> {
+        {
+            let mut state = self.state.irqsave_lock();
+            let ep = state.endpoint(slot, dci).ok_or(code::ENODEV)?;
+            if ep.td.is_some() {
+                return Err(code::EBUSY);
+            }
+            ep.td = Some(build(&mut ep.ring));
+        }
+        self.ring_doorbell(slot, dci as u32);
+        Ok(())
+    }
+
+    // Waits for the transfer in flight on the endpoint, cancelling it after
+    // `timeout`. Endpoints which fail are reset and left ready for the
+    // next transfer.
+    fn finish(&self, slot: u8, dci: usize, timeout: Duration) -> Result<usize, Error> {
+        let result = self.wait(timeout, |state| {
+            let ep = state.endpoint(slot, dci)?;
+            ep.td.as_ref()?.result.as_ref()?;
+            ep.td.take()?.result
+        });
+        match result {
+            Some(Ok(len)) => Ok(len),
+            Some(Err(e)) => {
+                self.skip_td(slot, dci, true);
+                Err(e)
+            }
+            None => {
+                let _ = self.endpoint_command(TRB_STOP_ENDPOINT, slot, dci);
+                let td = {
+                    let mut state = self.state.irqsave_lock();
+                    self.poll_events(&mut state);
+                    state.endpoint(slot, dci).and_then(|ep| ep.td.take())
+                };
+                let Some(td) = td else {
+                    return Err(code::ENODEV);
+                };
+                self.skip_td(slot, dci, matches!(td.result, Some(Err(_))));
+                match td.result {
+                    Some(result) => result,
+                    None if td.stopped && td.actual > 0 => Ok(td.actual),
+                    None => Err(code::ETIMEDOUT),
+                }
+            }
+        }
+    }
+
+    // Moves the dequeue pointer of a stopped or halted endpoint past the
+    // transfer which ended there.
+    fn skip_td(&self, slot: u8, dci: usize, halted: bool) {
+        if halted {
+            let _ = self.endpoint_command(TRB_RESET_ENDPOINT, slot, dci);
+        }
+        let pointer = {
+            let mut state = self.state.irqsave_lock();
+            match state.endpoint(slot, dci) {
+                Some(ep) => ep.ring.dequeue_pointer(),
+                None => return,
+            }
+        };
+        let _ = self.command(
+            pointer,
+            TRB_SET_TR_DEQUEUE << 10 | (dci as u32) << 16 | (slot as u32) << 24,
+        );
+    }
+
+    fn normal(
+        &self,
+        slot: u8,
+        dci: usize,
+        bounce: &Dma,
+        len: usize,
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        self.submit(slot, dci, |ring| {
+            let trb = ring.push(
+                bounce.addr(),
+                len as u32,
+                TRB_NORMAL << 10 | TRB_ISP | TRB_IOC,
+            );
+            Td::new(Some(trb), len, trb)
+        })?;
+        self.finish(slot, dci, timeout)
+    }
+
+    fn portsc(&self, port: u8) -> usize {
+        self.op + PORTSC + 0x10 * (port as usize - 1)
+    }
+
+    // Resets root port `port`, returning the speed of the device on it.
+    fn reset_port(&self, port: u8) -> Result<Option<Speed>, Error> {
+        let portsc = self.portsc(port);
+        if read32(portsc) & PORTSC_CCS == 0 {
+            return Ok(None);
+        }
+        write32(portsc, read32(portsc) & PORTSC_PRESERVE | PORTSC_PR);
+        wait_until(PORT_RESET_TIMEOUT, || read32(portsc) & PORTSC_PRC != 0)?;
+        let status = read32(portsc);
+        write32(portsc, status & PORTSC_PRESERVE | PORTSC_CHANGES);
+        if status & PORTSC_PED == 0 {
+            return Err(code::EIO);
+        }
+        Ok(Some(match (status >> 10) & 0xf {
+            1 => Speed::Full,
+            2 => Speed::Low,
+            3 => Speed::High,
+            _ => Speed::Super,
+        }))
+    }
+}
+
+impl HostController for Xhci {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn alloc_device(&self, location: &Location) -> Result<u8, Error> {
+        let slot_id = self.command(0, TRB_ENABLE_SLOT << 10)?;
+        let ring = Ring::new();
+        let mut slot = Slot {
+            input: Dma::new(PAGE_SIZE, PAGE_SIZE),
+            output: Dma::new(PAGE_SIZE, PAGE_SIZE),
+            endpoints: core::array::from_fn(|_| None),
+        };
+        let mut tt = 0;
+        if let Some((hub, port)) = location.tt {
+            tt = hub as u32 | (port as u32) << 8;
+        }
+        self.input_write(
+            &slot,
+            1,
+            0,
+            location.route | speed_id(location.speed) << 20 | 1 << 27,
+        );
+        self.input_write(&slot, 1, 1, (location.root_port as u32) << 16);
+        self.input_write(&slot, 1, 2, tt);
+        let max_packet0 = location.speed.default_max_packet0() as u32;
+        self.input_write(
+            &slot,
+            2,
+            1,
+            EP_CERR | EP_TYPE_CONTROL << 3 | max_packet0 << 16,
+        );
+        let pointer = ring.dequeue_pointer();
+        self.input_write(&slot, 2, 2, pointer as u32);
+        self.input_write(&slot, 2, 3, (pointer >> 32) as u32);
+        self.input_write(&slot, 2, 4, 8);
+        {
+            let mut state = self.state.irqsave_lock();
+            state
+                .dcbaa
+                .write64(slot_id as usize * 8, slot.output.addr());
+            slot.endpoints[1] = Some(Endpoint { ring, td: None });
+            state.slots.insert(slot_id, slot);
+        }
+        if let Err(e) = self.context_command(TRB_ADDRESS_DEVICE, slot_id, 0b11) {
+            self.free_device(slot_id);
+            return Err(e);
+        }
+        Ok(slot_id)
+    }
+
+    fn free_device(&self, slot: u8) {
+        let _ = self.command(0, TRB_DISABLE_SLOT << 10 | (slot as u32) << 24);
+        let mut state = self.state.irqsave_lock();
+        state.dcbaa.write64(slot as usize * 8, 0);
+        state.slots.remove(&slot);
+    }
+
+    fn set_max_packet0(&self, slot: u8, max_packet: u16) -> Result<(), Error> {
+        {
+            let state = self.state.irqsave_lock();
+            let slot = state.slots.get(&slot).ok_or(code::ENODEV)?;
+            let dword = self.input_read(slot, 2, 1) & 0xffff;
+            self.input_write(slot, 2, 1, dword | (max_packet as u32) << 16);
+        }
+        self.context_command(TRB_EVALUATE_CONTEXT, slot, 1 << 1)
+    }
+
+    fn configure_endpoints(&self, slot: u8, endpoints: &[EndpointDescriptor]) -> Result<(), Error> {
+        let mut add = 1;
+        {
+            let mut state = self.state.irqsave_lock();
+            let slot = state.slots.get_mut(&slot).ok_or(code::ENODEV)?;
+            let speed = match (self.input_read(slot, 1, 0) >> 20) & 0xf {
+                1 => Speed::Full,
+                2 => Speed::Low,
+                3 => Speed::High,
+                _ => Speed::Super,
+            };
+            let mut last = 1;
+            for ep in endpoints {
+                let kind = match ep.transfer_type() {
+                    TransferType::Bulk => 2,
+                    TransferType::Interrupt => 3,
+                    kind => {
+                        debug!("{:?} endpoint {:#x} isn't supported", kind, ep.address);
+                        continue;
+                    }
+                };
+                let kind = if ep.is_in() { kind + EP_TYPE_IN } else { kind };
+                let index = dci(ep.address);
+                let max_packet = ep.max_packet() as u32;
+                // Additional transactions per microframe of high-speed
+                // periodic endpoints.
+                let burst = if speed == Speed::High && ep.transfer_type() == TransferType::Interrupt
+                {
+                    (ep.max_packet_size as u32 >> 11) & 0b11
+                } else {
+                    0
+                };
+                let ring = Ring::new();
+                let pointer = ring.dequeue_pointer();
+                let (average, payload) = match ep.transfer_type() {
+                    TransferType::Bulk => (3072, 0),
+                    _ => (max_packet, max_packet * (burst + 1)),
+                };
+                self.input_write(slot, 1 + index, 0, interval(ep, speed) << 16);
+                self.input_write(
+                    slot,
+                    1 + index,
+                    1,
+                    EP_CERR | kind << 3 | burst << 8 | max_packet << 16,
+                );
+                self.input_write(slot, 1 + index, 2, pointer as u32);
+                self.input_write(slot, 1 + index, 3, (pointer >> 32) as u32);
+                self.input_write(slot, 1 + index, 4, average | payload << 16);
+                slot.endpoints[index] = Some(Endpoint { ring, td: None });
+                add |= 1 << index;
+                last = last.max(index as u32);
+            }
+            let dword = self.input_read(slot, 1, 0) & !(0x1f << 27);
+            self.input_write(slot, 1, 0, dword | last << 27);
+        }
+        self.context_command(TRB_CONFIGURE_ENDPOINT, slot, add)
+    }
+
+    fn set_hub(&self, slot: u8, ports: u8, ttt: u8, multi_tt: bool) -> Result<(), Error> {
+        {
+            let state = self.state.irqsave_lock();
+            let slot = state.slots.get(&slot).ok_or(code::ENODEV)?;
+            let dword = self.input_read(slot, 1, 0);
+            self.input_write(slot, 1, 0, dword | 1 << 26 | (multi_tt as u32) << 25);
+            let dword = self.input_read(slot, 1, 1) & 0x00ff_ffff;
+            self.input_write(slot, 1, 1, dword | (ports as u32) << 24);
+            let dword = self.input_read(slot, 1, 2) & !(0b11 << 16);
+            self.input_write(slot, 1, 2, dword | (ttt as u32 & 0b11) << 16);
+        }
+        self.context_command(TRB_CONFIGURE_ENDPOINT, slot, 1)
+    }
+
+    fn control(&self, slot: u8, setup: &SetupPacket, data: &mut [u8]) -> Result<usize, Error> {
+        let len = (setup.length as usize).min(data.len());
+        if len > MAX_TRB_LEN {
+            return Err(code::EINVAL);
+        }
+        let bounce = Dma::bounce(len);
+        let is_in = setup.is_in();
+        if !is_in {
+            bounce.copy_from(&data[..len]);
+        }
+        let setup = u64::from_le_bytes(setup.to_bytes());
+        self.submit(slot, 1, |ring| {
+            let stage = match (len, is_in) {
+                (0, _) => 0,
+                (_, true) => TRT_IN,
+                (_, false) => TRT_OUT,
+            };
+            ring.push(setup, 8, TRB_SETUP << 10 | TRB_IDT | stage);
+            let mut data_trb = None;
+            if len > 0 {
+                let dir = if is_in { TRB_DIR_IN } else { 0 };
+                data_trb =
+                    Some(ring.push(bounce.addr(), len as u32, TRB_DATA << 10 | TRB_ISP | dir));
+            }
+            // The status stage goes the other way, IN without data.
+            let dir = if len > 0 && is_in { 0 } else { TRB_DIR_IN };
+            let status = ring.push(0, 0, TRB_STATUS << 10 | TRB_IOC | dir);
+            Td::new(data_trb, len, status)
+        })?;
+        let moved = self.finish(slot, 1, CONTROL_TIMEOUT)?;
+        if is_in {
+            bounce.copy_to(&mut data[..moved]);
+        }
+        Ok(moved)
+    }
+
+    fn transfer_in(
+        &self,
+        slot: u8,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let bounce = Dma::bounce(buf.len().min(MAX_TRB_LEN));
+        let mut total = 0;
+        for chunk in buf.chunks_mut(MAX_TRB_LEN) {
+            let moved = self.normal(slot, dci(endpoint), &bounce, chunk.len(), timeout)?;
+            bounce.copy_to(&mut chunk[..moved]);
+            total += moved;
+            if moved < chunk.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn transfer_out(
+        &self,
+        slot: u8,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, Error> {
+        let bounce = Dma::bounce(buf.len().min(MAX_TRB_LEN));
+        let mut total = 0;
+        for chunk in buf.chunks(MAX_TRB_LEN) {
+            bounce.copy_from(chunk);
+            total += self.normal(slot, dci(endpoint), &bounce, chunk.len(), timeout)?;
+        }
+        Ok(total)
+    }
+}
+
+/// Sets up the xHCI controller whose registers are at `base`, and
+/// enumerates the devices on its root ports.
+pub fn probe(base: usize) -> Result<(), Error> {
+    let xhci = Arc::new(Xhci::new(base)?);
+    // The controller keeps using its memory, it must never be dropped.
+    CONTROLLERS.irqsave_lock().push(xhci.clone());
+    debug!("{}: {} ports", xhci.name, xhci.ports);
+    delay(POWER_ON_DELAY);
+    let controller: Arc<dyn HostController> = xhci.clone();
+    for port in 1..=xhci.ports {
+        let speed = match xhci.reset_port(port) {
+            Ok(Some(speed)) => speed,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("{}: failed to reset port {}: {:?}", xhci.name, port, e);
+                continue;
+            }
+        };
+        delay(PORT_RECOVERY);
+        let location = Location {
+            root_port: port,
+            route: 0,
+            depth: 0,
+            speed,
+            tt: None,
+        };
+        if let Err(e) = enumerate(&controller, location) {
+            warn!(
+                "{}: failed to enumerate the device on port {}: {:?}",
+                xhci.name, port, e
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    fn event(param: u64, code: u8, residual: u32) -> Trb {
+        Trb {
+            param,
+            status: (code as u32) << 24 | residual,
+            control: TRB_TRANSFER_EVENT << 10,
+        }
+    }
+
+    #[test]
+    fn test_xhci_rings() {
+        let mut ring = Ring::new();
+        let base = ring.trbs.addr();
+        assert_eq!(ring.dequeue_pointer(), base | 1);
+        for i in 0..RING_SIZE - 2 {
+            assert_eq!(
+                ring.push(0, 0, TRB_NORMAL << 10),
+                base + (i * TRB_SIZE) as u64
+            );
+        }
+        // The link is handed over chained, along with the TRB before it.
+        ring.push(0, 0, TRB_NORMAL << 10 | TRB_CHAIN);
+        let link = ring.trbs.read32((RING_SIZE - 1) * TRB_SIZE + 12);
+        assert_eq!(
+            link,
+            TRB_LINK << 10 | TRB_TOGGLE_CYCLE | TRB_CHAIN | TRB_CYCLE
+        );
+        assert_eq!(ring.dequeue_pointer(), base);
+        ring.push(0, 0, TRB_NORMAL << 10);
+        assert_eq!(ring.trbs.read32(12), TRB_NORMAL << 10);
+
+        // A control transfer whose data stage was short.
+        let mut td = Td::new(Some(0x1010), 64, 0x1020);
+        td.update(&event(0x1010, CC_SHORT_PACKET, 46));
+        assert_eq!(td.result, None);
+        td.update(&event(0x1020, CC_SUCCESS, 0));
+        assert_eq!(td.result, Some(Ok(18)));
+
+        let mut td = Td::new(Some(0x2000), 512, 0x2000);
+        td.update(&event(0x2000, CC_STALL, 512));
+        assert_eq!(td.result, Some(Err(code::EPIPE)));
+        let mut td = Td::new(Some(0x2000), 512, 0x2000);
+        td.update(&event(0x2000, CC_STOPPED, 500));
+        assert!(td.stopped && td.result.is_none());
+        assert_eq!(td.actual, 12);
+    }
+}
@@ -12,15 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::devices::block::init_virtio_block;
-use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+use crate::{allocator::page, devices::block::init_virtio_block};
+use alloc::alloc::handle_alloc_error;
 use core::{alloc::Layout, mem::size_of, ptr::NonNull};
-use flat_device_tree::Fdt;
+use flat_device_tree::{node::FdtNode, Fdt};
 use log::{debug, error, warn};
 use virtio_drivers::{
     device::blk::VirtIOBlk,
     transport::{
         mmio::{MmioError, MmioTransport, VirtIOHeader},
+        pci::{
+            bus::{
+                BarInfo, Cam, Command, ConfigurationAccess, DeviceFunction, MemoryBarType, MmioCam,
+                PciRoot,
+            },
+            virtio_device_type, PciTransport,
+        },
         DeviceType, DeviceTypeError, SomeTransport, Transport,
     },
     BufferDirection, Hal, PhysAddr, PAGE_SIZE,
@@ -29,8 +36,16 @@ use virtio_drivers::{
 use crate::devices::net::virtio_net_device::register_virtio_net_device;
 
 const VIRTIO_MMIO_COMPATIBLE: &str = "virtio,mmio";
+const PCI_ECAM_COMPATIBLE: &str = "pci-host-ecam-generic";
+// Space code of 32-bit memory in the first cell of a PCI address.
+const PCI_SPACE_MEM32: u32 = 0b10;
+// Class, subclass and programming interface of xHCI controllers.
+#[cfg(usb)]
+const PCI_CLASS_XHCI: (u8, u8, u8) = (0x0c, 0x03, 0x30);
+
 pub fn init_virtio(fdt: &Fdt) {
     find_virtio_mmio_devices(fdt);
+    find_virtio_pci_devices(fdt);
 }
 fn find_virtio_mmio_devices(fdt: &Fdt) {
     for node in fdt.all_nodes() {
@@ -79,6 +94,161 @@ fn find_virtio_mmio_devices(fdt: &Fdt) {
     }
 }
 
+// Devices behind a generic PCI host, such as `virtio-blk-pci`. Only bus 0
+// is scanned, which is where QEMU puts devices without a bridge.
+fn find_virtio_pci_devices(fdt: &Fdt) {
+    for node in fdt.all_nodes() {
+        let Some(compatible) = node.compatible() else {
+            continue;
+        };
+        if !compatible.all().any(|c| c == PCI_ECAM_COMPATIBLE) {
+            continue;
+        }
+        let Some(region) = node.reg().next() else {
+            warn!("PCI host {} missing region", node.name);
+            continue;
+        };
+        let base = region.starting_address as usize;
+        // Device memory is only mapped below 4 GiB, QEMU's aarch64 virt
+        // machine needs highmem=off to put its ECAM there.
+        if base + region.size.unwrap_or(0) > u32::MAX as usize {
+            warn!("Ignoring PCI host {} with ECAM at {:#x}", node.name, base);
+            continue;
+        }
+        let Some(mut window) = PciWindow::from_ranges(&node) else {
+            warn!("PCI host {} has no 32-bit memory window", node.name);
+            continue;
+        };
+        // SAFETY: device tree is correct, the ECAM region is mapped.
+        let mut root = PciRoot::new(unsafe { MmioCam::new(base as *mut u8, Cam::Ecam) });
+        for (device_function, info) in root.enumerate_bus(0) {
+            #[cfg(usb)]
+            if (info.class, info.subclass, info.prog_if) == PCI_CLASS_XHCI {
+                init_xhci(&mut root, &mut window, device_function);
+                continue;
+            }
+            let Some(device_type) = virtio_device_type(&info) else {
+                continue;
+            };
+            debug!(
+                "Found VirtIO PCI device {} with device type {:?}",
+                device_function, device_type
+            );
+            if !window.assign_bars(&mut root, device_function) {
+                warn!("No room for the BARs of PCI device {}", device_function);
+                continue;
+            }
+            match PciTransport::new::<VirtioHal, _>(&mut root, device_function) {
+                Ok(transport) => init_virtio_device(transport.into()),
+                Err(e) => warn!("Error creating VirtIO PCI transport: {:?}", e),
+            }
+        }
+    }
+}
+
+// Like VirtIO devices, xHCI controllers are found on the PCI bus of QEMU's
+// virt machines.
+#[cfg(usb)]
+fn init_xhci(
+    root: &mut PciRoot<impl ConfigurationAccess>,
+    window: &mut PciWindow,
+    device_function: DeviceFunction,
+) {
+    if !window.assign_bars(root, device_function) {
+        warn!("No room for the BARs of PCI device {}", device_function);
+        return;
+    }
+    let Ok([Some(BarInfo::Memory { address, .. }), ..]) = root.bars(device_function) else {
+        warn!("xHCI controller {} has no memory BAR", device_function);
+        return;
+    };
+    if let Err(e) = crate::devices::usb::xhci::probe(address as usize) {
+        warn!(
+            "Failed to set xHCI controller {} up: {:?}",
+            device_function, e
+        );
+    }
+}
+
+// The 32-bit memory window of a PCI host, from which BARs are assigned as
+// there is no firmware to do it.
+struct PciWindow {
+    next: u32,
+    end: u32,
+}
+
+impl PciWindow {
+    // Entries of `ranges` are a 3-cell PCI address, a 2-cell CPU address
+    // and a 2-cell size on the QEMU virt machines. BARs hold PCI addresses,
+    // so only a window mapped at the same CPU address can be used.
+    fn from_ranges(node: &FdtNode) -> Option<Self> {
+        let ranges = node.property("ranges")?;
+        let (start, size) = ranges
+            .value
+            .chunks_exact(28)
+            .filter_map(|range| {
+                let cell =
+                    |i: usize| u32::from_be_bytes(range[4 * i..4 * i + 4].try_into().unwrap());
+                let pci = (cell(1) as u64) << 32 | cell(2) as u64;
+                let cpu = (cell(3) as u64) << 32 | cell(4) as u64;
+                let size = (cell(5) as u64) << 32 | cell(6) as u64;
+                ((cell(0) >> 24) & 0b11 == PCI_SPACE_MEM32 && pci == cpu).then_some((cpu, size))
+            })
+            .max_by_key(|&(_, size)| size)?;
+        Some(Self {
+            next: u32::try_from(start).ok()?,
+            end: u32::try_from(start + size).ok()?,
+        })
+    }
+
+    // BARs are aligned to their size, which is a power of two.
+    fn alloc(&mut self, size: u64) -> Option<u32> {
+        let size = u32::try_from(size).ok()?;
+        let start = self.next.checked_next_multiple_of(size)?;
+        let end = start.checked_add(size)?;
+        if end > self.end {
+            return None;
+        }
+        self.next = end;
+        Some(start)
+    }
+
+    // Gives the memory BARs of the device addresses in the window and
+    // enables it. Returns false if they don't fit.
+    fn assign_bars(
+        &mut self,
+        root: &mut PciRoot<impl ConfigurationAccess>,
+        device_function: DeviceFunction,
+    ) -> bool {
+        let Ok(bars) = root.bars(device_function) else {
+            return false;
+        };
+        for (index, bar) in bars.into_iter().enumerate() {
+            let Some(BarInfo::Memory {
+                address_type, size, ..
+            }) = bar
+            else {
+                continue;
+            };
+            if size == 0 {
+                continue;
+            }
+            let Some(address) = self.alloc(size) else {
+                return false;
+            };
+            match address_type {
+                MemoryBarType::Width32 => root.set_bar_32(device_function, index as u8, address),
+                MemoryBarType::Width64 => {
+                    root.set_bar_64(device_function, index as u8, address.into())
+                }
+                _ => return false,
+            }
+        }
+        root.set_command(device_function, Command::MEMORY_SPACE | Command::BUS_MASTER);
+        true
+    }
+}
+
 fn init_virtio_device(transport: SomeTransport<'static>) {
     match transport.device_type() {
         DeviceType::Network => {
@@ -89,6 +259,10 @@ fn init_virtio_device(transport: SomeTransport<'static>) {
                 error!("Failed to init virtio blk, {:?}", e);
             }
         }
+        #[cfg(audio)]
+        DeviceType::Sound => crate::devices::audio::virtio::init(transport),
+        #[cfg(fb)]
+        DeviceType::GPU => crate::devices::fb::virtio::init(transport),
         t => {
             debug!("Ignoring unsupported VirtIO device type {:?}", t);
         }
@@ -102,19 +276,17 @@ pub struct VirtioHal;
 unsafe impl Hal for VirtioHal {
     fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
         assert!(pages > 0);
-        let layout = Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
-        let vaddr = unsafe { alloc_zeroed(layout) };
+        let vaddr = page::alloc_pages(pages, "virtio");
         if vaddr.is_null() {
-            handle_alloc_error(layout);
+            handle_alloc_error(Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap());
         }
         let paddr = virt_to_phys(vaddr as _);
         let vaddr = NonNull::new(vaddr).unwrap();
         (paddr, vaddr)
     }
 
-    unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
-        let layout = Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
-        dealloc(vaddr.as_ptr(), layout);
+    unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, _pages: usize) -> i32 {
+        page::free_pages(vaddr.as_ptr());
         0
     }
 
@@ -12,14 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::devices::{Device, DeviceClass, DeviceId, DeviceManager};
+use crate::{
+    devices::{devno::MEM_MAJOR, driver::InitLevel, Device, DeviceClass, DeviceId, DeviceManager},
+    error::Error,
+};
 use alloc::{string::String, sync::Arc};
-use embedded_io::ErrorKind;
 
 pub struct Zero;
 
+crate::driver_init!("zero", InitLevel::Device, init);
+
+fn init() -> Result<(), Error> {
+    Zero::register()
+}
+
 impl Zero {
-    pub fn register() -> Result<(), ErrorKind> {
+    pub fn register() -> Result<(), Error> {
         let zero = Arc::new(Zero);
         DeviceManager::get().register_device(String::from("zero"), zero)
     }
@@ -35,16 +43,16 @@ impl Device for Zero {
     }
 
     fn id(&self) -> DeviceId {
-        DeviceId::new(1, 5)
+        DeviceId::new(MEM_MAJOR, 5)
     }
 
-    fn read(&self, _pos: u64, buf: &mut [u8], _is_blocking: bool) -> Result<usize, ErrorKind> {
+    fn read(&self, _pos: u64, buf: &mut [u8], _is_blocking: bool) -> Result<usize, Error> {
         // Fill buffer with zeros
         buf.fill(0);
         Ok(buf.len())
     }
 
-    fn write(&self, _pos: u64, buf: &[u8], _is_blocking: bool) -> Result<usize, ErrorKind> {
+    fn write(&self, _pos: u64, buf: &[u8], _is_blocking: bool) -> Result<usize, Error> {
         // Always succeed, but discard the data
         Ok(buf.len())
     }
@@ -0,0 +1,249 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parallel NOR flash driven with the Intel/Sharp CFI command set, like
+//! the pflash of QEMU's virt machine.
+//!
+//! The flash is read through its mapping while in read array mode.
+//! Programming and erasing switch it to command mode, where reads return
+//! the status register, so they exclude reads and return to read array
+//! mode before letting them in again. Accesses are all 32 bits wide, the
+//! width of a bank of two 16-bit chips, and writes of parts of a word
+//! program the rest of it with ones.
+//!
+//! The flash backs littlefs as a [`BlockDevice`], or is registered as a
+//! block [`Device`] for the key-value store.
+
+#[cfg(kvstore)]
+use crate::devices::{devno, Device, DeviceClass, DeviceId};
+#[cfg(littlefs)]
+use crate::vfs::littlefs::{BlockDevice, Geometry};
+use crate::{
+    drivers::io::{Mmio, RegisterIo},
+    error::{code, Error},
+    sync::RwSleepLock,
+};
+#[cfg(kvstore)]
+use alloc::string::String;
+
+// Commands, repeated for each chip of the bank.
+const READ_ARRAY: u32 = 0x00ff_00ff;
+const CLEAR_STATUS: u32 = 0x0050_0050;
+const WORD_PROGRAM: u32 = 0x0040_0040;
+const BLOCK_ERASE: u32 = 0x0020_0020;
+const ERASE_CONFIRM: u32 = 0x00d0_00d0;
+
+// Status register bits.
+const STATUS_READY: u32 = 0x80;
+const STATUS_ERASE_ERROR: u32 = 0x20;
+const STATUS_PROGRAM_ERROR: u32 = 0x10;
+const STATUS_LOCKED: u32 = 0x02;
+
+const WORD: usize = 4;
+
+/// A CFI flash of `size` bytes mapped at a base address, erased in sectors
+/// of `sector_size` bytes.
+pub struct CfiFlash {
+    io: RwSleepLock<Mmio>,
+    size: usize,
+    sector_size: usize,
+}
+
+// Reads only load from the mapping, everything else is done under the
+// write lock.
+unsafe impl Sync for CfiFlash {}
+
+impl CfiFlash {
+    /// # Safety
+    ///
+    /// The `size` bytes at `base` must be the mapping of the flash, as
+    /// device memory, and nothing else may drive it.
+    pub unsafe fn new(base: usize, size: usize, sector_size: usize) -> Self {
+        let mut io = Mmio::new(base);
+        io.write32(0, READ_ARRAY);
+        Self {
+            io: RwSleepLock::new(io),
+            size,
+            sector_size,
+        }
+    }
+
+    fn check(&self, offset: usize, len: usize, unit: usize) -> Result<(), Error> {
+        let end = offset.checked_add(len).ok_or(code::EINVAL)?;
+        if end > self.size || offset % unit != 0 || len % unit != 0 {
+            return Err(code::EINVAL);
+        }
+        Ok(())
+    }
+
+    // Polls the status of the command just issued at `offset`, then goes
+    // back to read array mode.
+    fn finish(io: &mut Mmio, offset: usize, errors: u32) -> Result<(), Error> {
+        let status = loop {
+            let status = io.read32(offset);
+            if status & STATUS_READY != 0 {
+                break status;
+            }
+            core::hint::spin_loop();
+        };
+        if status & (errors | STATUS_LOCKED) != 0 {
+            io.write32(offset, CLEAR_STATUS);
+            io.write32(offset, READ_ARRAY);
+            return Err(code::EIO);
+        }
+        io.write32(offset, READ_ARRAY);
+        Ok(())
+    }
+
+    // Reads at `offset`, which needn't be aligned.
+    fn read_bytes(&self, offset: usize, buf: &mut [u8]) {
+        let io = self.io.read();
+        let end = offset + buf.len();
+        let mut at = offset;
+        while at < end {
+            let word = io.read32(at & !(WORD - 1)).to_le_bytes();
+            let from = at % WORD;
+            let n = (WORD - from).min(end - at);
+            buf[at - offset..at - offset + n].copy_from_slice(&word[from..from + n]);
+            at += n;
+        }
+    }
+
+    // Programs `data` at `offset`, which needn't be aligned. The other
+    // bytes of the words are programmed as ones, which leaves them as
+    // they are.
+    fn program(&self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let mut io = self.io.write();
+        let end = offset + data.len();
+        let mut at = offset;
+        while at < end {
+            let word_at = at & !(WORD - 1);
+            let from = at - word_at;
+            let n = (WORD - from).min(end - at);
+            let mut word = [0xff; WORD];
+            word[from..from + n].copy_from_slice(&data[at - offset..at - offset + n]);
+            io.write32(word_at, WORD_PROGRAM);
+            io.write32(word_at, u32::from_le_bytes(word));
+            Self::finish(&mut io, word_at, STATUS_PROGRAM_ERROR)?;
+            at += n;
+        }
+        Ok(())
+    }
+
+    fn erase_sectors(&self, offset: usize, len: usize) -> Result<(), Error> {
+        let mut io = self.io.write();
+        for sector in (offset..offset + len).step_by(self.sector_size) {
+            io.write32(sector, BLOCK_ERASE);
+            io.write32(sector, ERASE_CONFIRM);
+            Self::finish(&mut io, sector, STATUS_ERASE_ERROR)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(littlefs)]
+impl BlockDevice for CfiFlash {
+    fn geometry(&self) -> Geometry {
+        Geometry {
+            read_size: WORD,
+            prog_size: WORD,
+            erase_size: self.sector_size,
+            size: self.size,
+        }
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.check(offset, buf.len(), WORD)?;
+        self.read_bytes(offset, buf);
+        Ok(())
+    }
+
+    fn prog(&self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        self.check(offset, data.len(), WORD)?;
+        self.program(offset, data)
+    }
+
+    fn erase(&self, offset: usize, len: usize) -> Result<(), Error> {
+        self.check(offset, len, self.sector_size)?;
+        self.erase_sectors(offset, len)
+    }
+}
+
+/// A CFI flash registered as a block device, for users which erase it
+/// through [`Device::erase`] before writing, like the key-value store.
+/// Writes can only clear bits.
+#[cfg(kvstore)]
+pub struct CfiFlashDevice {
+    flash: CfiFlash,
+    name: String,
+    id: DeviceId,
+}
+
+#[cfg(kvstore)]
+impl CfiFlashDevice {
+    pub fn new(name: &str, flash: CfiFlash) -> Result<Self, Error> {
+        let major = devno::register_major(DeviceClass::Block, 0, "cfi-flash")?;
+        Ok(Self {
+            flash,
+            name: String::from(name),
+            id: devno::alloc_minor(DeviceClass::Block, major)?,
+        })
+    }
+
+    // Clamps an access at `pos` to the end of the flash.
+    fn span(&self, pos: u64, len: usize) -> (usize, usize) {
+        let pos = usize::try_from(pos)
+            .unwrap_or(usize::MAX)
+            .min(self.flash.size);
+        (pos, len.min(self.flash.size - pos))
+    }
+}
+
+#[cfg(kvstore)]
+impl Device for CfiFlashDevice {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Block
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn read(&self, pos: u64, buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let (pos, len) = self.span(pos, buf.len());
+        self.flash.read_bytes(pos, &mut buf[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        let (pos, len) = self.span(pos, buf.len());
+        self.flash.program(pos, &buf[..len])?;
+        Ok(len)
+    }
+
+    fn erase(&self, pos: u64, len: u64) -> Result<(), Error> {
+        let pos = usize::try_from(pos).map_err(|_| code::EINVAL)?;
+        let len = usize::try_from(len).map_err(|_| code::EINVAL)?;
+        self.flash.check(pos, len, self.flash.sector_size)?;
+        self.flash.erase_sectors(pos, len)
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
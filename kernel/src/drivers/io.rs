@@ -0,0 +1,206 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Register access for drivers.
+//!
+//! A driver describes its registers as [`Reg`]s, typed by the value they
+//! hold and placed at an offset from the start of the register block, and
+//! accesses them through a [`RegisterIo`] backend: [`Mmio`] for
+//! memory-mapped registers, or [`MockIo`] to run the driver against memory
+//! in tests.
+
+use alloc::vec::Vec;
+use core::{marker::PhantomData, mem::size_of, ptr::NonNull};
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+/// A block of 8 and 32-bit registers.
+pub trait RegisterIo: Send {
+    fn read8(&self, offset: usize) -> u8;
+    fn write8(&mut self, offset: usize, value: u8);
+    fn read32(&self, offset: usize) -> u32;
+    fn write32(&mut self, offset: usize, value: u32);
+}
+
+/// Memory-mapped registers, accessed with volatile loads and stores.
+#[derive(Debug)]
+pub struct Mmio {
+    base: NonNull<u8>,
+}
+
+// SAFETY: Mmio is the only handle to its register block.
+unsafe impl Send for Mmio {}
+
+impl Mmio {
+    /// # Safety
+    ///
+    /// `base` must be the address of a register block mapped as device
+    /// memory, with no other `Mmio` or reference aliasing it.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self {
+            base: NonNull::new_unchecked(base as *mut u8),
+        }
+    }
+}
+
+impl RegisterIo for Mmio {
+    fn read8(&self, offset: usize) -> u8 {
+        // SAFETY: The register block is valid per Mmio::new.
+        unsafe { self.base.add(offset).read_volatile() }
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        // SAFETY: The register block is valid per Mmio::new.
+        unsafe { self.base.add(offset).write_volatile(value) }
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        // SAFETY: The register block is valid per Mmio::new, and registers
+        // are naturally aligned.
+        unsafe { self.base.add(offset).cast::<u32>().read_volatile() }
+    }
+
+    fn write32(&mut self, offset: usize, value: u32) {
+        // SAFETY: The register block is valid per Mmio::new, and registers
+        // are naturally aligned.
+        unsafe { self.base.add(offset).cast::<u32>().write_volatile(value) }
+    }
+}
+
+/// Registers backed by memory, for running drivers in tests. Reads return
+/// the last value written or preset, and writes are logged so that tests
+/// can check the sequence a driver issues.
+#[derive(Debug, Default)]
+pub struct MockIo {
+    regs: Vec<u8>,
+    writes: Vec<(usize, u32)>,
+}
+
+impl MockIo {
+    pub fn new(size: usize) -> Self {
+        Self {
+            regs: alloc::vec![0; size],
+            writes: Vec::new(),
+        }
+    }
+
+    /// Sets a register without logging a write, e.g. a status register.
+    pub fn set8(&mut self, offset: usize, value: u8) {
+        self.regs[offset] = value;
+    }
+
+    pub fn set32(&mut self, offset: usize, value: u32) {
+        self.regs[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes issued so far, as (offset, value) pairs.
+    pub fn writes(&self) -> &[(usize, u32)] {
+        &self.writes
+    }
+
+    pub fn clear_writes(&mut self) {
+        self.writes.clear();
+    }
+}
+
+impl RegisterIo for MockIo {
+    fn read8(&self, offset: usize) -> u8 {
+        self.regs[offset]
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        self.set8(offset, value);
+        self.writes.push((offset, value as u32));
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.regs[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn write32(&mut self, offset: usize, value: u32) {
+        self.set32(offset, value);
+        self.writes.push((offset, value));
+    }
+}
+
+/// A register holding a `T`, which is 8 or 32 bits wide.
+#[derive(Debug)]
+pub struct Reg<T> {
+    offset: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for Reg<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Reg<T> {}
+
+impl<T: FromBytes + IntoBytes + Immutable> Reg<T> {
+    pub const fn at(offset: usize) -> Self {
+        assert!(size_of::<T>() == 1 || size_of::<T>() == 4);
+        Self {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn read(&self, io: &impl RegisterIo) -> T {
+        if size_of::<T>() == 1 {
+            T::read_from_bytes(&[io.read8(self.offset)]).unwrap()
+        } else {
+            T::read_from_bytes(&io.read32(self.offset).to_ne_bytes()).unwrap()
+        }
+    }
+
+    pub fn write(&self, io: &mut impl RegisterIo, value: T) {
+        let bytes = value.as_bytes();
+        if size_of::<T>() == 1 {
+            io.write8(self.offset, bytes[0]);
+        } else {
+            io.write32(self.offset, u32::from_ne_bytes(bytes.try_into().unwrap()));
+        }
+    }
+
+    /// Reads the register, lets `f` change the value, and writes it back.
+    pub fn modify(&self, io: &mut impl RegisterIo, f: impl FnOnce(&mut T)) {
+        let mut value = self.read(io);
+        f(&mut value);
+        self.write(io, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_mock_io_registers() {
+        const BYTE: Reg<u8> = Reg::at(1);
+        const WORD: Reg<u32> = Reg::at(4);
+        let mut io = MockIo::new(8);
+        io.set8(1, 0x5a);
+        assert_eq!(BYTE.read(&io), 0x5a);
+        WORD.write(&mut io, 0x1234_5678);
+        WORD.modify(&mut io, |v| *v |= 1);
+        assert_eq!(WORD.read(&io), 0x1234_5679);
+        assert_eq!(io.writes(), &[(4, 0x1234_5678), (4, 0x1234_5679)]);
+    }
+}
@@ -14,5 +14,10 @@
 
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+#[cfg(all(any(littlefs, kvstore), target_arch = "aarch64"))]
+pub(crate) mod cfi_flash;
 pub(crate) mod ic;
+pub(crate) mod io;
+#[cfg(target_arch = "aarch64")]
+pub(crate) mod pl031;
 pub(crate) mod uart;
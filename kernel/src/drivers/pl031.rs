@@ -0,0 +1,153 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ARM PL031 real-time clock, like the one of QEMU's virt machine.
+//!
+//! The PL031 counts seconds in a 32-bit register, which holds the Unix
+//! time, and setting the time loads the counter. The match interrupt isn't
+//! used.
+
+use crate::{
+    devices::{
+        devno,
+        rtc::{RtcTime, RTC_DEVICE_NAME, RTC_RD_TIME, RTC_SET_TIME},
+        Device, DeviceClass, DeviceId, DeviceManager,
+    },
+    drivers::io::RegisterIo,
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{string::String, sync::Arc};
+
+// Data, match, load and control registers, and the interrupt mask.
+const RTCDR: usize = 0x00;
+const RTCLR: usize = 0x08;
+const RTCCR: usize = 0x0c;
+const RTCIMSC: usize = 0x10;
+
+const RTCCR_START: u32 = 0x1;
+
+pub struct Pl031<I: RegisterIo> {
+    io: SpinLock<I>,
+    id: DeviceId,
+}
+
+impl<I: RegisterIo> Pl031<I> {
+    /// Starts the counter if it isn't running yet, leaving the time as it
+    /// is.
+    pub fn new(mut io: I, id: DeviceId) -> Self {
+        io.write32(RTCIMSC, 0);
+        if io.read32(RTCCR) & RTCCR_START == 0 {
+            io.write32(RTCCR, RTCCR_START);
+        }
+        Self {
+            io: SpinLock::new(io),
+            id,
+        }
+    }
+}
+
+/// Registers the PL031 at `io` as the RTC the wall clock is kept in sync
+/// with.
+pub fn register<I: RegisterIo + 'static>(io: I) -> Result<(), Error> {
+    let major = devno::register_major(DeviceClass::Char, 0, "rtc")?;
+    let rtc = Pl031::new(io, devno::alloc_minor(DeviceClass::Char, major)?);
+    DeviceManager::get().register_device(String::from(RTC_DEVICE_NAME), Arc::new(rtc))
+}
+
+impl<I: RegisterIo + 'static> Device for Pl031<I> {
+    fn name(&self) -> String {
+        String::from(RTC_DEVICE_NAME)
+    }
+
+    fn class(&self) -> DeviceClass {
+        DeviceClass::Char
+    }
+
+    fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    fn read(&self, _pos: u64, _buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        Err(code::ENOSYS)
+    }
+
+    fn write(&self, _pos: u64, _buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+        Err(code::ENOSYS)
+    }
+
+    fn ioctl(&self, request: u32, arg: usize) -> Result<(), Error> {
+        if RTC_RD_TIME.matches(request) {
+            let secs = self.io.irqsave_lock().read32(RTCDR);
+            return RTC_RD_TIME.copy_out(arg, &RtcTime::from_unix(secs as u64));
+        }
+        if RTC_SET_TIME.matches(request) {
+            // The counter runs out in 2106.
+            let secs = RTC_SET_TIME
+                .copy_in(arg)?
+                .to_unix()
+                .and_then(|secs| u32::try_from(secs).ok())
+                .ok_or(code::EINVAL)?;
+            self.io.irqsave_lock().write32(RTCLR, secs);
+            return Ok(());
+        }
+        Err(code::ENOSYS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{devices::rtc, drivers::io::MockIo};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_pl031() {
+        let mut io = MockIo::new(0x20);
+        io.set32(RTCDR, 1709210096);
+        let rtc = Pl031::new(io, DeviceId::new(0, 0));
+        assert_eq!(rtc.io.lock().writes(), [(RTCIMSC, 0), (RTCCR, RTCCR_START)]);
+        assert_eq!(rtc::read_time(&rtc), Ok(1709210096));
+
+        assert_eq!(rtc::set_time(&rtc, 2_000_000_000), Ok(()));
+        assert_eq!(rtc.io.lock().writes().last(), Some(&(RTCLR, 2_000_000_000)));
+        assert_eq!(rtc::set_time(&rtc, 1 << 32), Err(code::EINVAL));
+    }
+
+    // QEMU's PL031 starts at the time of the host.
+    #[cfg(target_board = "qemu_virt64_aarch64")]
+    #[test]
+    fn test_pl031_round_trip() {
+        use crate::time::realtime;
+
+        let dev = DeviceManager::get()
+            .get_char_device(RTC_DEVICE_NAME)
+            .unwrap();
+        let host = rtc::read_time(&*dev).unwrap();
+        assert!(host > 1_600_000_000);
+
+        let secs = 2_000_000_000;
+        assert_eq!(rtc::set_time(&*dev, secs), Ok(()));
+        let read = rtc::read_time(&*dev).unwrap();
+        assert!((secs..secs + 2).contains(&read));
+        assert_eq!(realtime::sync_from_rtc(), Ok(()));
+        let now = realtime::now().as_secs();
+        assert!((secs..secs + 2).contains(&now));
+
+        // Setting the clock updates the RTC too.
+        realtime::set(core::time::Duration::from_secs(host));
+        let read = rtc::read_time(&*dev).unwrap();
+        assert!((host..host + 2).contains(&read));
+    }
+}
@@ -18,20 +18,16 @@ use crate::{
     arch::irq,
     devices::{
         tty::{
-            serial::{SerialError, UartOps},
+            serial::{FifoLevels, ModemLines, SerialError, UartOps},
             termios::{Cflags, Termios},
         },
         DeviceRequest,
     },
+    drivers::io::{Mmio, Reg, RegisterIo},
 };
 use bitflags::bitflags;
-use core::{fmt, ptr::NonNull};
+use core::fmt;
 use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
-use safe_mmio::{
-    field, field_shared,
-    fields::{ReadPure, ReadPureWrite, ReadWrite, WriteOnly},
-    UniqueMmioPointer,
-};
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 // Register descriptions
@@ -188,61 +184,48 @@ bitflags! {
 /// Set all interrupts from bit 0 to 10
 pub const ALL_INTERRUPTS: Interrupts = Interrupts::from_bits_truncate(0x7FF);
 
-/// PL011 register map
-#[derive(Clone, Eq, FromBytes, Immutable, IntoBytes, KnownLayout, PartialEq)]
-#[repr(C, align(4))]
-pub struct PL011Registers {
-    /// 0x000: Data Register
-    uartdr: ReadWrite<u32>,
-    /// 0x004: Receive Status Register/SerialError Clear Register
-    uartrsr_ecr: ReadPureWrite<u32>,
-    /// 0x008 - 0x014
-    reserved_08: [u32; 4],
-    /// 0x018: Flag Register
-    uartfr: ReadPure<FlagsRegister>,
-    /// 0x01C
-    reserved_1c: u32,
-    /// 0x020: IrDA Low-Power Counter Register
-    uartilpr: ReadPureWrite<u32>,
-    /// 0x024: Integer Baud Rate Register
-    uartibrd: ReadPureWrite<u32>,
-    /// 0x028: Fractional Baud Rate Register
-    uartfbrd: ReadPureWrite<u32>,
-    /// 0x02C: Line Control Register
-    uartlcr_h: ReadPureWrite<LineControlRegister>,
-    /// 0x030: Control Register
-    uartcr: ReadPureWrite<ControlRegister>,
-    /// 0x034: Interrupt FIFO Level Select Register
-    uartifls: ReadPureWrite<u32>,
-    /// 0x038: Interrupt Mask Set/Clear Register
-    uartimsc: ReadPureWrite<Interrupts>,
-    /// 0x03C: Raw Interrupt Status Register
-    uartris: ReadPure<Interrupts>,
-    /// 0x040: Masked INterrupt Status Register
-    uartmis: ReadPure<Interrupts>,
-    /// 0x044: Interrupt Clear Register
-    uarticr: WriteOnly<Interrupts>,
-    /// 0x048: DMA control Register
-    uartdmacr: ReadPureWrite<u32>,
-    /// 0x04C - 0xFDC
-    reserved_4c: [u32; 997],
-    /// 0xFE0: UARTPeriphID0 Register
-    uartperiphid0: ReadPure<u32>,
-    /// 0xFE4: UARTPeriphID1 Register
-    uartperiphid1: ReadPure<u32>,
-    /// 0xFE8: UARTPeriphID2 Register
-    uartperiphid2: ReadPure<u32>,
-    /// 0xFEC: UARTPeriphID3 Register
-    uartperiphid3: ReadPure<u32>,
-    /// 0xFF0: UARTPCellID0 Register
-    uartpcellid0: ReadPure<u32>,
-    /// 0xFF4: UARTPCellID1 Register
-    uartpcellid1: ReadPure<u32>,
-    /// 0xFF8: UARTPCellID2 Register
-    uartpcellid2: ReadPure<u32>,
-    /// 0xFFC: UARTPCellID3 Register
-    uartpcellid3: ReadPure<u32>,
-}
+/// The SIR ENDEC only supports rates up to 115200 baud.
+pub const SIR_MAX_BAUD_RATE: u32 = 115200;
+
+/// Above this rate, the FIFO levels picked by default trade latency for
+/// fewer interrupts.
+pub const HIGH_SPEED_BAUD_RATE: u32 = 230400;
+
+/// The modem status lines which interrupt on changes.
+const MODEM_INTERRUPTS: Interrupts = Interrupts::CTSMI.union(Interrupts::DCDMI);
+
+// PL011 register map
+/// 0x000: Data Register
+const UARTDR: Reg<u32> = Reg::at(0x000);
+/// 0x004: Receive Status Register/SerialError Clear Register
+const UARTRSR_ECR: Reg<u32> = Reg::at(0x004);
+/// 0x018: Flag Register
+const UARTFR: Reg<FlagsRegister> = Reg::at(0x018);
+/// 0x024: Integer Baud Rate Register
+const UARTIBRD: Reg<u32> = Reg::at(0x024);
+/// 0x028: Fractional Baud Rate Register
+const UARTFBRD: Reg<u32> = Reg::at(0x028);
+/// 0x02C: Line Control Register
+const UARTLCR_H: Reg<LineControlRegister> = Reg::at(0x02c);
+/// 0x030: Control Register
+const UARTCR: Reg<ControlRegister> = Reg::at(0x030);
+/// 0x034: Interrupt FIFO Level Select Register
+const UARTIFLS: Reg<u32> = Reg::at(0x034);
+/// 0x038: Interrupt Mask Set/Clear Register
+const UARTIMSC: Reg<Interrupts> = Reg::at(0x038);
+/// 0x03C: Raw Interrupt Status Register
+const UARTRIS: Reg<Interrupts> = Reg::at(0x03c);
+/// 0x040: Masked INterrupt Status Register
+const UARTMIS: Reg<Interrupts> = Reg::at(0x040);
+/// 0x044: Interrupt Clear Register
+const UARTICR: Reg<Interrupts> = Reg::at(0x044);
+/// 0xFE0 - 0xFEC: UARTPeriphID0-3 Registers
+const UARTPERIPHID: [Reg<u32>; 4] = [
+    Reg::at(0xfe0),
+    Reg::at(0xfe4),
+    Reg::at(0xfe8),
+    Reg::at(0xfec),
+];
 
 /// RX/TX interrupt FIFO levels
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -254,6 +237,42 @@ pub enum FifoLevel {
     Bytes28 = 0b100,
 }
 
+impl FifoLevel {
+    /// Returns the highest level not above `bytes`, or the lowest one.
+    pub fn at_most(bytes: u32) -> Self {
+        match bytes {
+            ..8 => Self::Bytes4,
+            8..16 => Self::Bytes8,
+            16..24 => Self::Bytes16,
+            24..28 => Self::Bytes24,
+            _ => Self::Bytes28,
+        }
+    }
+
+    pub fn bytes(self) -> u32 {
+        match self {
+            Self::Bytes4 => 4,
+            Self::Bytes8 => 8,
+            Self::Bytes16 => 16,
+            Self::Bytes24 => 24,
+            Self::Bytes28 => 28,
+        }
+    }
+
+    /// Levels for `baud_rate` when none were requested. Up to
+    /// [`HIGH_SPEED_BAUD_RATE`] these are the reset levels, half full for
+    /// both FIFOs. Above it, RX waits until the FIFO is 3/4 full and TX
+    /// until it is 1/4 full, which still leaves 8 characters to the
+    /// interrupt handler.
+    pub fn defaults(baud_rate: u32) -> (Self, Self) {
+        if baud_rate > HIGH_SPEED_BAUD_RATE {
+            (Self::Bytes24, Self::Bytes8)
+        } else {
+            (Self::Bytes16, Self::Bytes16)
+        }
+    }
+}
+
 /// UART peripheral identification structure
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Identification {
@@ -279,18 +298,23 @@ impl Identification {
 }
 
 /// PL011 UART implementation
-pub struct Uart<'a> {
-    regs: UniqueMmioPointer<'a, PL011Registers>,
+pub struct Uart<I: RegisterIo = Mmio> {
+    regs: I,
 }
 
-impl<'a> Uart<'a> {
+impl<I: RegisterIo> Uart<I> {
     /// Creates new UART instance.
-    pub fn new(regs: UniqueMmioPointer<'a, PL011Registers>) -> Self {
+    pub fn new(regs: I) -> Self {
         Self { regs }
     }
 
     /// Configure and enable UART
     pub fn enable(&mut self, termios: &Termios, sysclk: u32) -> Result<(), SerialError> {
+        let sir = termios.cflag.contains(Cflags::CSIR);
+        if sir && termios.getospeed() > SIR_MAX_BAUD_RATE {
+            return Err(SerialError::InvalidParameter);
+        }
+
         // Baud rate
         let (uartibrd, uartfbrd) = Self::calculate_baud_rate_divisor(termios.getospeed(), sysclk)?;
 
@@ -313,24 +337,31 @@ impl<'a> Uart<'a> {
             LineControlRegister::STP2
         } else {
             LineControlRegister::empty()
-        };
+        } | LineControlRegister::FEN;
 
-        field!(self.regs, uartrsr_ecr).write(0);
-        field!(self.regs, uartcr).write(ControlRegister::empty());
+        UARTRSR_ECR.write(&mut self.regs, 0);
+        UARTCR.write(&mut self.regs, ControlRegister::empty());
 
-        field!(self.regs, uartibrd).write(uartibrd);
-        field!(self.regs, uartfbrd).write(uartfbrd);
-        field!(self.regs, uartlcr_h).write(line_control);
+        UARTIBRD.write(&mut self.regs, uartibrd);
+        UARTFBRD.write(&mut self.regs, uartfbrd);
+        UARTLCR_H.write(&mut self.regs, line_control);
 
-        field!(self.regs, uartcr)
-            .write(ControlRegister::RXE | ControlRegister::TXE | ControlRegister::UARTEN);
+        let control = ControlRegister::RXE | ControlRegister::TXE | ControlRegister::UARTEN;
+        UARTCR.write(
+            &mut self.regs,
+            if sir {
+                control | ControlRegister::SIREN
+            } else {
+                control
+            },
+        );
 
         Ok(()) // Refactor: no SerialError returned so far, could be independent from SerialError?
     }
 
     /// Disable UART
     pub fn disable(&mut self) {
-        field!(self.regs, uartcr).write(ControlRegister::empty());
+        UARTCR.write(&mut self.regs, ControlRegister::empty());
     }
 
     /// Check if receive FIFO is empty
@@ -359,8 +390,22 @@ impl<'a> Uart<'a> {
     }
 
     /// Reads and returns the flag register.
+    /// Returns the modem status lines which are asserted.
+    pub fn modem_lines(&self) -> ModemLines {
+        let flags = self.flags();
+        [
+            (FlagsRegister::CTS, ModemLines::CTS),
+            (FlagsRegister::DSR, ModemLines::DSR),
+            (FlagsRegister::DCD, ModemLines::DCD),
+            (FlagsRegister::RI, ModemLines::RI),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .fold(ModemLines::empty(), |lines, (_, line)| lines | line)
+    }
+
     fn flags(&self) -> FlagsRegister {
-        field_shared!(self.regs, uartfr).read()
+        UARTFR.read(&self.regs)
     }
 
     /// Non-blocking read of a single byte from the UART.
@@ -371,7 +416,7 @@ impl<'a> Uart<'a> {
             return Ok(None);
         }
 
-        let dr = field!(self.regs, uartdr).read();
+        let dr = UARTDR.read(&self.regs);
 
         let flags = DataRegister::from_bits_truncate(dr);
 
@@ -390,7 +435,7 @@ impl<'a> Uart<'a> {
 
     /// Non-blocking write of a single byte to the UART
     pub fn write_word(&mut self, word: u8) {
-        field!(self.regs, uartdr).write(word as u32);
+        UARTDR.write(&mut self.regs, word as u32);
     }
 
     pub fn try_write_data(&mut self, byte: u8) -> Result<(), SerialError> {
@@ -404,16 +449,7 @@ impl<'a> Uart<'a> {
 
     /// Read UART peripheral identification structure
     pub fn read_identification(&self) -> Identification {
-        // SAFETY: The caller of UniqueMmioPointer::new promised that it wraps a valid and unique
-        // register block.
-        let id: [u32; 4] = {
-            [
-                field_shared!(self.regs, uartperiphid0).read(),
-                field_shared!(self.regs, uartperiphid1).read(),
-                field_shared!(self.regs, uartperiphid2).read(),
-                field_shared!(self.regs, uartperiphid3).read(),
-            ]
-        };
+        let id = UARTPERIPHID.map(|reg| reg.read(&self.regs));
 
         Identification {
             part_number: (id[0] & 0xff) as u16 | ((id[1] & 0x0f) << 8) as u16,
@@ -447,45 +483,50 @@ impl<'a> Uart<'a> {
         Ok((ibrd, fbrd))
     }
 
+    fn divisor_to_baud_rate(ibrd: u32, fbrd: u32, sysclk: u32) -> u32 {
+        // baud_rate = sysclk / (16 * (ibrd + fbrd / 64))
+        (sysclk as u64 * 4 / ((ibrd as u64) << 6 | fbrd as u64)) as u32
+    }
+
     /// Sets trigger levels for RX and TX interrupts.
     /// The interrupts are generated when the fill level progresses through the trigger level.
     pub fn set_interrupt_fifo_levels(&mut self, rx_level: FifoLevel, tx_level: FifoLevel) {
         let fifo_levels = ((rx_level as u32) << 3) | tx_level as u32;
 
-        field!(self.regs, uartifls).write(fifo_levels);
+        UARTIFLS.write(&mut self.regs, fifo_levels);
     }
 
     /// Reads the raw interrupt status register.
     pub fn raw_interrupt_status(&self) -> Interrupts {
-        field_shared!(self.regs, uartris).read()
+        UARTRIS.read(&self.regs)
     }
 
     /// Reads the masked interrupt status register.
     pub fn masked_interrupt_status(&self) -> Interrupts {
-        field_shared!(self.regs, uartmis).read()
+        UARTMIS.read(&self.regs)
     }
 
     /// Returns the current set of interrupt masks.
     pub fn interrupt_masks(&self) -> Interrupts {
-        field_shared!(self.regs, uartimsc).read()
+        UARTIMSC.read(&self.regs)
     }
 
     /// Sets the interrupt masks.
     pub fn set_interrupt_masks(&mut self, masks: Interrupts) {
-        field!(self.regs, uartimsc).write(masks)
+        UARTIMSC.write(&mut self.regs, masks)
     }
 
     /// Clears the given set of interrupts.
     pub fn clear_interrupts(&mut self, interrupts: Interrupts) {
-        field!(self.regs, uarticr).write(interrupts)
+        UARTICR.write(&mut self.regs, interrupts)
     }
 }
 
 // SAFETY: An `&Uart` only allows operations which read registers, which can safely be done from
 // multiple threads simultaneously.
-unsafe impl Sync for Uart<'_> {}
+unsafe impl<I: RegisterIo> Sync for Uart<I> {}
 
-impl fmt::Write for Uart<'_> {
+impl<I: RegisterIo> fmt::Write for Uart<I> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for byte in s.as_bytes() {
             // Wait until there is room in the TX buffer.
@@ -496,39 +537,67 @@ impl fmt::Write for Uart<'_> {
     }
 }
 
-impl Drop for Uart<'_> {
+impl<I: RegisterIo> Drop for Uart<I> {
     fn drop(&mut self) {
         self.disable();
     }
 }
 
-pub struct Driver<'a> {
-    uart: Uart<'a>,
+pub struct Driver<I: RegisterIo = Mmio> {
+    uart: Uart<I>,
     clock: u32,
     irq: irq::IrqNumber,
+    // Levels set with TIOCSFIFO, 0 for the default of the baud rate.
+    requested_levels: FifoLevels,
+    levels: (FifoLevel, FifoLevel),
+    baud_rate: u32,
 }
 
-impl Driver<'_> {
+impl Driver {
     pub fn new(base_address: u64, clock: u32, irq: irq::IrqNumber) -> Self {
+        Self::with_io(unsafe { Mmio::new(base_address as usize) }, clock, irq)
+    }
+}
+
+impl<I: RegisterIo> Driver<I> {
+    /// Creates a driver for the registers behind `io`.
+    pub fn with_io(io: I, clock: u32, irq: irq::IrqNumber) -> Self {
         Self {
-            uart: Uart::new(unsafe {
-                UniqueMmioPointer::new(NonNull::new(base_address as *mut _).unwrap())
-            }),
+            uart: Uart::new(io),
             clock,
             irq,
+            requested_levels: FifoLevels::default(),
+            levels: FifoLevel::defaults(0),
+            baud_rate: 0,
         }
     }
 
-    pub fn enable(&mut self, termios: &Termios) {
-        self.uart.enable(termios, self.clock);
+    pub fn enable(&mut self, termios: &Termios) -> Result<(), SerialError> {
+        self.uart.enable(termios, self.clock)?;
+        self.baud_rate = termios.getospeed();
+        self.apply_fifo_levels();
+        Ok(())
+    }
+
+    // Programs the requested FIFO levels, or the defaults for the baud rate.
+    fn apply_fifo_levels(&mut self) {
+        let (rx, tx) = FifoLevel::defaults(self.baud_rate);
+        let pick = |requested, default| match requested {
+            0 => default,
+            bytes => FifoLevel::at_most(bytes),
+        };
+        let rx = pick(self.requested_levels.rx, rx);
+        let tx = pick(self.requested_levels.tx, tx);
+        self.uart.set_interrupt_fifo_levels(rx, tx);
+        self.levels = (rx, tx);
     }
 }
 
-impl ErrorType for Driver<'_> {
+impl<I: RegisterIo> ErrorType for Driver<I> {
     type Error = SerialError;
 }
 
-impl Write for Driver<'_> {
+impl<I: RegisterIo> Write for Driver<I> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         let mut count = 0;
         // write until the buffer is full
@@ -547,13 +616,13 @@ impl Write for Driver<'_> {
     }
 }
 
-impl WriteReady for Driver<'_> {
+impl<I: RegisterIo> WriteReady for Driver<I> {
     fn write_ready(&mut self) -> Result<bool, SerialError> {
         Ok(!self.uart.is_tx_fifo_full())
     }
 }
 
-impl Read for Driver<'_> {
+impl<I: RegisterIo> Read for Driver<I> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
         if buf.is_empty() {
             return Ok(0);
@@ -575,15 +644,15 @@ impl Read for Driver<'_> {
     }
 }
 
-impl ReadReady for Driver<'_> {
+impl<I: RegisterIo> ReadReady for Driver<I> {
     fn read_ready(&mut self) -> Result<bool, SerialError> {
         Ok(!self.uart.is_rx_fifo_empty())
     }
 }
 
-impl UartOps for Driver<'_> {
+impl<I: RegisterIo> UartOps for Driver<I> {
     fn setup(&mut self, termios: &Termios) -> Result<(), SerialError> {
-        self.enable(termios);
+        self.enable(termios)?;
         self.uart.clear_interrupts(ALL_INTERRUPTS);
         irq::enable_irq_with_priority(self.irq, 0, irq::Priority::Normal);
         Ok(())
@@ -621,6 +690,8 @@ impl UartOps for Driver<'_> {
         } else {
             masks &= !Interrupts::RXI;
         }
+        // The receive timeout flushes what is left below the RX level.
+        masks.set(Interrupts::RTI, enable);
         self.uart.set_interrupt_masks(masks);
     }
 
@@ -635,18 +706,51 @@ impl UartOps for Driver<'_> {
     }
 
     fn clear_rx_interrupt(&mut self) {
-        self.uart.clear_interrupts(Interrupts::RXI);
+        self.uart
+            .clear_interrupts(Interrupts::RXI | Interrupts::RTI);
     }
 
     fn clear_tx_interrupt(&mut self) {
         self.uart.clear_interrupts(Interrupts::TXI);
     }
 
+    fn closest_baud_rate(&self, baud_rate: u32) -> Result<u32, SerialError> {
+        let (ibrd, fbrd) = Uart::calculate_baud_rate_divisor(baud_rate, self.clock)?;
+        Ok(Uart::divisor_to_baud_rate(ibrd, fbrd, self.clock))
+    }
+
+    fn set_fifo_levels(&mut self, levels: FifoLevels) -> Result<(), SerialError> {
+        self.requested_levels = levels;
+        self.apply_fifo_levels();
+        Ok(())
+    }
+
+    fn fifo_levels(&self) -> FifoLevels {
+        FifoLevels {
+            rx: self.levels.0.bytes(),
+            tx: self.levels.1.bytes(),
+        }
+    }
+
+    fn modem_lines(&self) -> ModemLines {
+        self.uart.modem_lines()
+    }
+
+    fn set_modem_interrupt(&mut self, enable: bool) {
+        let mut masks = self.uart.interrupt_masks();
+        masks.set(MODEM_INTERRUPTS, enable);
+        self.uart.set_interrupt_masks(masks);
+    }
+
+    fn clear_modem_interrupt(&mut self) {
+        self.uart.clear_interrupts(MODEM_INTERRUPTS);
+    }
+
     fn ioctl(&mut self, request: u32, arg: usize) -> Result<(), SerialError> {
         match DeviceRequest::from(request) {
             DeviceRequest::Config => {
                 let termios = unsafe { *(arg as *const Termios) };
-                self.enable(&termios);
+                self.enable(&termios)?;
             }
             DeviceRequest::Close => {
                 self.uart.disable();
@@ -656,3 +760,89 @@ impl UartOps for Driver<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::io::MockIo;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_pl011_identification() {
+        let mut io = MockIo::new(0x1000);
+        for (reg, id) in UARTPERIPHID.iter().zip([0x11, 0x10, 0x34, 0x00]) {
+            io.set32(reg.offset(), id);
+        }
+        let uart = Uart::new(io);
+        let id = uart.read_identification();
+        assert_eq!(id.part_number, 0x11);
+        assert_eq!(id.designer, b'A');
+        assert_eq!(id.revision_number, 3);
+        assert!(id.is_valid());
+    }
+
+    #[test]
+    fn test_pl011_fifo_levels() {
+        let mut uart = Uart::new(MockIo::new(0x1000));
+        uart.set_interrupt_fifo_levels(FifoLevel::Bytes16, FifoLevel::Bytes8);
+        assert_eq!(uart.regs.writes(), &[(0x034, 0b010_001)]);
+    }
+
+    // The last value written to UARTIFLS.
+    fn fifo_levels_reg(driver: &Driver<MockIo>) -> Option<u32> {
+        let writes = driver.uart.regs.writes();
+        writes
+            .iter()
+            .rev()
+            .find(|(offset, _)| *offset == UARTIFLS.offset())
+            .map(|(_, value)| *value)
+    }
+
+    #[test]
+    fn test_pl011_fifo_levels_follow_baud_rate() {
+        let mut driver = Driver::with_io(MockIo::new(0x1000), 24_000_000, irq::IrqNumber::new(33));
+        let mut termios = Termios::default();
+        assert_eq!(driver.enable(&termios), Ok(()));
+        assert_eq!(fifo_levels_reg(&driver), Some(0b010_010));
+
+        termios.setispeed(921600);
+        termios.setospeed(921600);
+        assert_eq!(driver.enable(&termios), Ok(()));
+        assert_eq!(fifo_levels_reg(&driver), Some(0b011_001));
+
+        // A requested level is rounded down and kept across baud rate
+        // changes, the other one still follows the baud rate.
+        let levels = FifoLevels { rx: 12, tx: 0 };
+        assert_eq!(driver.set_fifo_levels(levels), Ok(()));
+        assert_eq!(fifo_levels_reg(&driver), Some(0b001_001));
+        termios.setospeed(115200);
+        assert_eq!(driver.enable(&termios), Ok(()));
+        assert_eq!(fifo_levels_reg(&driver), Some(0b001_010));
+        assert_eq!(driver.fifo_levels(), FifoLevels { rx: 8, tx: 16 });
+    }
+
+    #[test]
+    fn test_pl011_modem_lines() {
+        let mut io = MockIo::new(0x1000);
+        io.set32(
+            UARTFR.offset(),
+            (FlagsRegister::CTS | FlagsRegister::DCD).bits(),
+        );
+        let mut driver = Driver::with_io(io, 24_000_000, irq::IrqNumber::new(33));
+        assert_eq!(driver.modem_lines(), ModemLines::CTS | ModemLines::DCD);
+
+        driver.set_modem_interrupt(true);
+        assert_eq!(
+            driver.uart.interrupt_masks(),
+            Interrupts::CTSMI | Interrupts::DCDMI
+        );
+        driver.clear_modem_interrupt();
+        assert_eq!(
+            driver.uart.regs.writes().last(),
+            Some(&(
+                UARTICR.offset(),
+                (Interrupts::CTSMI | Interrupts::DCDMI).bits()
+            ))
+        );
+    }
+}
@@ -25,6 +25,7 @@ use crate::{
         DeviceManager, DeviceRequest,
     },
 };
+use blueos_kconfig::CMSDK_UART_TX_COALESCE;
 use core::hint::spin_loop;
 use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
 use tock_registers::{
@@ -33,6 +34,10 @@ use tock_registers::{
     registers::ReadWrite,
 };
 
+/// Baud rate from which TX interrupts are coalesced, see
+/// `CMSDK_UART_TX_COALESCE`.
+pub const TX_COALESCE_BAUD_RATE: u32 = 115200;
+
 register_bitfields! [
     u32,
 
@@ -135,7 +140,7 @@ impl Uart {
     /// clock: Uart clock in Hz.
     /// baud_rate: Baud rate.
     pub fn enable(&mut self, clock: u32, baud_rate: u32) {
-        let divisor = (clock << 2) / baud_rate;
+        let divisor = Self::baud_rate_divisor(clock, baud_rate);
 
         self.registers()
             .CTRL
@@ -144,6 +149,10 @@ impl Uart {
         self.registers().INTSTATUS.set(0xf);
     }
 
+    fn baud_rate_divisor(clock: u32, baud_rate: u32) -> u32 {
+        (clock << 2) / baud_rate
+    }
+
     pub fn disable(&mut self) {
         self.registers().CTRL.modify(
             CTRL::RXIRQEN::CLEAR + CTRL::RXEN::CLEAR + CTRL::TXIRQEN::CLEAR + CTRL::TXEN::CLEAR,
@@ -192,7 +201,8 @@ impl Uart {
     }
 
     /// Reads and returns a pending byte, or `None` if nothing has been
-    /// received.
+    /// received. An overrun is reported once, before the byte which was
+    /// kept; the UART doesn't detect framing or parity errors.
     pub fn read_data(&mut self) -> Result<Option<u8>, SerialError> {
         let state = self.registers().STATE.extract();
 
@@ -200,6 +210,8 @@ impl Uart {
             // no data
             Ok(None)
         } else if state.is_set(STATE::RXOR) {
+            // write 1 to clear
+            self.registers().STATE.write(STATE::RXOR::SET);
             Err(SerialError::Overrun)
         } else {
             let ch = self.registers().DATA.read(DATA::DATA) as u8;
@@ -265,6 +277,8 @@ pub struct Driver {
     clock: u32,
     rx_irq: irq::IrqNumber,
     tx_irq: irq::IrqNumber,
+    // Extra bytes a write waits to send once the TX buffer is full.
+    tx_coalesce: usize,
 }
 
 impl Driver {
@@ -287,23 +301,34 @@ impl Driver {
             clock,
             rx_irq,
             tx_irq,
+            tx_coalesce: 0,
         }
     }
 
     pub fn enable(&mut self, baud_rate: u32) {
         self.uart.enable(self.clock, baud_rate);
+        self.tx_coalesce = if baud_rate >= TX_COALESCE_BAUD_RATE {
+            CMSDK_UART_TX_COALESCE
+        } else {
+            0
+        };
     }
 }
 
 impl Write for Driver {
-    // write will block until all the data is transmitted
+    // Writes until the buffer is full, then waits for up to `tx_coalesce`
+    // more bytes to go out rather than for a TX interrupt each.
     fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
         assert!(!buf.is_empty());
         let mut count = 0;
-        // write until the buffer is full
         while count < buf.len() {
             match self.uart.try_write_data(buf[count]) {
                 Ok(_) => count += 1,
+                Err(_e) if count > 0 && count <= self.tx_coalesce => {
+                    while self.uart.is_tx_fifo_full() {
+                        spin_loop();
+                    }
+                }
                 Err(_e) => break,
             }
         }
@@ -411,6 +436,15 @@ impl UartOps for Driver {
         self.uart.clear_tx_interrupt();
     }
 
+    fn closest_baud_rate(&self, baud_rate: u32) -> Result<u32, SerialError> {
+        // BAUDDIV is 20 bits wide and has to be at least 16.
+        let divisor = Uart::baud_rate_divisor(self.clock, baud_rate);
+        if !(16..1 << 20).contains(&divisor) {
+            return Err(SerialError::InvalidParameter);
+        }
+        Ok((self.clock << 2) / divisor)
+    }
+
     fn ioctl(&mut self, request: u32, arg: usize) -> Result<(), SerialError> {
         match DeviceRequest::from(request) {
             DeviceRequest::Config => {
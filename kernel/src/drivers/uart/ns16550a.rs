@@ -23,19 +23,15 @@ use crate::{
         serial::{Serial, SerialError, UartOps},
         termios::Termios,
     },
-    sync::SpinLock,
+    drivers::io::{Mmio, Reg, RegisterIo},
+    sync::{KOnce, SpinLock},
     vfs::AccessMode,
 };
 use alloc::sync::Arc;
 use bitflags::bitflags;
-use core::{mem::MaybeUninit, ptr::NonNull};
+use core::mem::MaybeUninit;
 use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
-use safe_mmio::{
-    field, field_shared,
-    fields::{ReadPure, ReadPureWrite, ReadWrite, WriteOnly},
-    UniqueMmioPointer,
-};
-use spin::{Mutex, Once};
+use spin::Mutex;
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 #[repr(transparent)]
@@ -88,28 +84,22 @@ bitflags! {
     }
 }
 
-/// RISCV unknown register map
-#[derive(Clone, Eq, FromBytes, Immutable, IntoBytes, KnownLayout, PartialEq)]
-#[repr(C, align(4))]
-pub struct UartRegisters {
-    rhr_thr: ReadWrite<u8>, // u8 for compatibility
-    ier: ReadWrite<InterruptEnableRegister>,
-    fcr_isr: ReadWrite<FIFOControlRegister>, // share, but read-only for ISR, so can be labeled as FCR
-    lcr: ReadWrite<LineControlRegister>,
-    lsr: ReadWrite<LineStatusRegister>,
-}
+// RISCV unknown register map
+const RHR_THR: Reg<u8> = Reg::at(0); // u8 for compatibility
+const IER: Reg<InterruptEnableRegister> = Reg::at(1);
+const FCR_ISR: Reg<FIFOControlRegister> = Reg::at(2); // share, but read-only for ISR, so can be labeled as FCR
+const LCR: Reg<LineControlRegister> = Reg::at(3);
+const LSR: Reg<LineStatusRegister> = Reg::at(4);
 
 static UART_MUTEX: SpinLock<()> = SpinLock::new(());
 
-pub(crate) struct Uart<'a> {
-    regs: Mutex<UniqueMmioPointer<'a, UartRegisters>>,
+pub(crate) struct Uart<I: RegisterIo = Mmio> {
+    regs: Mutex<I>,
 }
 
-unsafe impl core::marker::Sync for Uart<'_> {} // Mutex added on UniqueMmioPointer, still care do not get raw pointer from UniqueMmioPointer
-
-impl<'a> Uart<'a> {
+impl<I: RegisterIo> Uart<I> {
     /// Creates new UART instance.
-    pub fn new(regs: UniqueMmioPointer<'a, UartRegisters>) -> Self {
+    pub fn new(regs: I) -> Self {
         Self {
             regs: Mutex::new(regs),
         }
@@ -119,21 +109,25 @@ impl<'a> Uart<'a> {
     pub(crate) fn init(&mut self) {
         let mut guard = self.regs.lock();
         // Disable interrupts.
-        field!(guard, ier).write(InterruptEnableRegister(0));
+        IER.write(&mut *guard, InterruptEnableRegister(0));
         // Special mode to set baud rate.
-        field!(guard, lcr).write(LineControlRegister::BAUD_LATCH);
+        LCR.write(&mut *guard, LineControlRegister::BAUD_LATCH);
         // LSB for baud rate of 38.4K.
-        field!(guard, rhr_thr).write(0x03);
+        RHR_THR.write(&mut *guard, 0x03);
         // MSB for baud rate of 38.4K.
-        field!(guard, ier).write(InterruptEnableRegister(0));
+        IER.write(&mut *guard, InterruptEnableRegister(0));
         // Leave set-baud mode, and set word length to 8 bits, no parity.
-        field!(guard, lcr).write(LineControlRegister::EIGHT_BITS);
+        LCR.write(&mut *guard, LineControlRegister::EIGHT_BITS);
         // Reset and enable FIFOs.
-        field!(guard, fcr_isr)
-            .write(FIFOControlRegister::FIFO_ENABLE | FIFOControlRegister::FIFO_CLEAR);
+        FCR_ISR.write(
+            &mut *guard,
+            FIFOControlRegister::FIFO_ENABLE | FIFOControlRegister::FIFO_CLEAR,
+        );
         // Enable transmit and receive interrupts.
-        field!(guard, ier)
-            .write(InterruptEnableRegister::TX_ENABLE | InterruptEnableRegister::RX_ENABLE);
+        IER.write(
+            &mut *guard,
+            InterruptEnableRegister::TX_ENABLE | InterruptEnableRegister::RX_ENABLE,
+        );
     }
 
     #[inline]
@@ -146,26 +140,22 @@ impl<'a> Uart<'a> {
     }
 }
 
-impl WriteReady for Uart<'_> {
+impl<I: RegisterIo> WriteReady for Uart<I> {
     fn write_ready(&mut self) -> Result<bool, SerialError> {
         let mut guard = self.regs.lock();
-        Ok(field!(guard, lsr)
-            .read()
-            .contains(LineStatusRegister::TX_IDLE))
+        Ok(LSR.read(&*guard).contains(LineStatusRegister::TX_IDLE))
     }
 }
 
-impl ReadReady for Uart<'_> {
+impl<I: RegisterIo> ReadReady for Uart<I> {
     fn read_ready(&mut self) -> Result<bool, SerialError> {
         let mut guard = self.regs.lock();
-        Ok(field!(guard, lsr)
-            .read()
-            .contains(LineStatusRegister::RX_READY))
+        Ok(LSR.read(&*guard).contains(LineStatusRegister::RX_READY))
     }
 }
 
 // This can be shared among all UartOps impl.
-impl Read for Uart<'_> {
+impl<I: RegisterIo> Read for Uart<I> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
         let _ = UART_MUTEX.irqsave_lock();
         while !self.read_ready()? {
@@ -182,7 +172,7 @@ impl Read for Uart<'_> {
     }
 }
 
-impl Write for Uart<'_> {
+impl<I: RegisterIo> Write for Uart<I> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
         let _ = UART_MUTEX.irqsave_lock();
         while !self.write_ready()? {
@@ -199,16 +189,16 @@ impl Write for Uart<'_> {
     fn flush(&mut self) -> Result<(), SerialError> {
         let _ = UART_MUTEX.irqsave_lock();
         let mut guard = self.regs.lock();
-        field!(guard, fcr_isr).write(FIFOControlRegister::FIFO_CLEAR);
+        FCR_ISR.write(&mut *guard, FIFOControlRegister::FIFO_CLEAR);
         Ok(())
     }
 }
 
-impl ErrorType for Uart<'_> {
+impl<I: RegisterIo> ErrorType for Uart<I> {
     type Error = SerialError;
 }
 
-impl UartOps for Uart<'_> {
+impl<I: RegisterIo> UartOps for Uart<I> {
     fn setup(&mut self, _: &Termios) -> Result<(), SerialError> {
         Ok(())
     }
@@ -218,20 +208,14 @@ impl UartOps for Uart<'_> {
     #[inline]
     fn read_byte(&mut self) -> Result<u8, SerialError> {
         let mut guard = self.regs.lock();
-        while !field!(guard, lsr)
-            .read()
-            .contains(LineStatusRegister::RX_READY)
-        {}
-        Ok(field!(guard, rhr_thr).read())
+        while !LSR.read(&*guard).contains(LineStatusRegister::RX_READY) {}
+        Ok(RHR_THR.read(&*guard))
     }
     #[inline]
     fn write_byte(&mut self, c: u8) -> Result<(), SerialError> {
         let mut guard = self.regs.lock();
-        while !field!(guard, lsr)
-            .read()
-            .contains(LineStatusRegister::TX_IDLE)
-        {}
-        field!(guard, rhr_thr).write(c);
+        while !LSR.read(&*guard).contains(LineStatusRegister::TX_IDLE) {}
+        RHR_THR.write(&mut *guard, c);
         Ok(())
     }
     fn write_str(&mut self, s: &str) -> Result<(), SerialError> {
@@ -252,14 +236,14 @@ mod tests {
     use super::*;
     // use super::super::*;
 
-    use crate::drivers::ic::plic::Plic;
+    use crate::drivers::{ic::plic::Plic, io::MockIo};
     use blueos_test_macro::test; //need this macro for custom test framework instead of std default
     const PLIC_BASE: usize = 0x0c00_0000;
-    const UART0_BASE: u32 = 0x1000_0000;
+    const UART0_BASE: usize = 0x1000_0000;
     const UART0_IRQ: IrqNumber = IrqNumber::new(10);
 
-    static UART0: Once<Arc<SpinLock<Uart>>> = Once::new();
-    static SERIAL0: Once<Arc<Serial>> = Once::new();
+    static UART0: KOnce<Arc<SpinLock<Uart>>> = KOnce::new();
+    static SERIAL0: KOnce<Arc<Serial>> = KOnce::new();
 
     static PLIC: Plic = Plic::new(PLIC_BASE);
 
@@ -280,9 +264,8 @@ mod tests {
                 );
 
                 UART0.call_once(|| {
-                    Arc::new(SpinLock::new(Uart::new(unsafe {
-                        UniqueMmioPointer::new(NonNull::new(UART0_BASE as *mut _).unwrap())
-                    }))) // according to base, not always uart0
+                    // according to base, not always uart0
+                    Arc::new(SpinLock::new(Uart::new(unsafe { Mmio::new(UART0_BASE) })))
                 });
 
                 SERIAL0.call_once(|| {
@@ -302,11 +285,41 @@ mod tests {
     #[test]
     fn test_uart_init() {
         uart_init(0);
-        let mut temp_uart = Uart::new(unsafe {
-            UniqueMmioPointer::new(NonNull::new(UART0_BASE as *mut _).unwrap())
-        });
+        let mut temp_uart = Uart::new(unsafe { Mmio::new(UART0_BASE) });
         let mut guard = temp_uart.regs.lock();
-        let read_lcr = field!(guard, lcr).read();
+        let read_lcr = LCR.read(&*guard);
         assert!(read_lcr.contains(LineControlRegister::EIGHT_BITS))
     }
+
+    #[test]
+    fn test_uart_init_sequence() {
+        let mut uart = Uart::new(MockIo::new(8));
+        uart.init();
+        let io = uart.regs.lock();
+        assert_eq!(
+            io.writes(),
+            &[
+                (1, 0),
+                (3, LineControlRegister::BAUD_LATCH.bits() as u32),
+                (0, 0x03),
+                (1, 0),
+                (3, LineControlRegister::EIGHT_BITS.bits() as u32),
+                (2, 0x07),
+                (1, 0x03),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uart_read_write_byte() {
+        let mut uart = Uart::new(MockIo::new(8));
+        uart.regs.lock().set8(
+            LSR.offset(),
+            LineStatusRegister::RX_READY.bits() | LineStatusRegister::TX_IDLE.bits(),
+        );
+        uart.regs.lock().set8(RHR_THR.offset(), b'x');
+        assert_eq!(uart.read_byte().unwrap(), b'x');
+        uart.write_byte(b'y').unwrap();
+        assert_eq!(uart.regs.lock().writes(), &[(0, b'y' as u32)]);
+    }
 }
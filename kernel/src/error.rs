@@ -12,41 +12,51 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! [`KError`], the error of every kernel subsystem: an errno, so that it
+//! reaches user space as is. Device drivers built on `embedded_io` convert
+//! from and to its `ErrorKind`, both ways without losing the kind.
+
 use alloc::alloc::{AllocError, LayoutError};
 use core::{ffi::CStr, num::TryFromIntError, str::Utf8Error};
+use embedded_io::ErrorKind;
 
 pub mod code {
     use libc;
-    pub const EOK: super::Error = super::Error(0);
-    pub const TRUE: super::Error = super::Error(1);
-    pub const FLASE: super::Error = super::Error(0);
-    pub const ERROR: super::Error = super::Error(-255);
-    pub const ETIMEDOUT: super::Error = super::Error(-libc::ETIMEDOUT);
-    pub const ENOSPC: super::Error = super::Error(-libc::ENOSPC);
-    pub const ENODATA: super::Error = super::Error(-libc::ENODATA);
-    pub const ENOMEM: super::Error = super::Error(-libc::ENOMEM);
-    pub const ENOSYS: super::Error = super::Error(-libc::ENOSYS);
-    pub const EBUSY: super::Error = super::Error(-libc::EBUSY);
-    pub const EIO: super::Error = super::Error(-libc::EIO);
-    pub const EINTR: super::Error = super::Error(-libc::EINTR);
-    pub const EINVAL: super::Error = super::Error(-libc::EINVAL);
-    pub const ENOENT: super::Error = super::Error(-libc::ENOENT);
-    pub const ENODEV: super::Error = super::Error(-libc::ENODEV);
-    pub const EPERM: super::Error = super::Error(-libc::EPERM);
-    pub const EAGAIN: super::Error = super::Error(-libc::EAGAIN);
-    pub const EBADF: super::Error = super::Error(-libc::EBADF);
-    pub const EEXIST: super::Error = super::Error(-libc::EEXIST);
-    pub const ENOTDIR: super::Error = super::Error(-libc::ENOTDIR);
-    pub const EISDIR: super::Error = super::Error(-libc::EISDIR);
-    pub const ENOTEMPTY: super::Error = super::Error(-libc::ENOTEMPTY);
-    pub const ENAMETOOLONG: super::Error = super::Error(-libc::ENAMETOOLONG);
-    pub const EACCES: super::Error = super::Error(-libc::EACCES);
-    pub const ESPIPE: super::Error = super::Error(-libc::ESPIPE);
-    pub const EOVERFLOW: super::Error = super::Error(-libc::EOVERFLOW);
-    pub const ELOOP: super::Error = super::Error(-libc::ELOOP);
-    pub const EXDEV: super::Error = super::Error(-libc::EXDEV);
-    pub const EILSEQ: super::Error = super::Error(-libc::EILSEQ);
-    pub const ENOTSUP: super::Error = super::Error(-libc::ENOTSUP);
+    pub const EOK: super::KError = super::KError(0);
+    pub const TRUE: super::KError = super::KError(1);
+    pub const FLASE: super::KError = super::KError(0);
+    pub const ERROR: super::KError = super::KError(-255);
+    pub const ETIMEDOUT: super::KError = super::KError(-libc::ETIMEDOUT);
+    pub const ENOSPC: super::KError = super::KError(-libc::ENOSPC);
+    pub const ENODATA: super::KError = super::KError(-libc::ENODATA);
+    pub const ENOMEM: super::KError = super::KError(-libc::ENOMEM);
+    pub const ENOSYS: super::KError = super::KError(-libc::ENOSYS);
+    pub const EBUSY: super::KError = super::KError(-libc::EBUSY);
+    pub const EIO: super::KError = super::KError(-libc::EIO);
+    pub const EINTR: super::KError = super::KError(-libc::EINTR);
+    pub const EINVAL: super::KError = super::KError(-libc::EINVAL);
+    pub const ENOENT: super::KError = super::KError(-libc::ENOENT);
+    pub const ENODEV: super::KError = super::KError(-libc::ENODEV);
+    pub const EPERM: super::KError = super::KError(-libc::EPERM);
+    pub const EAGAIN: super::KError = super::KError(-libc::EAGAIN);
+    pub const EBADF: super::KError = super::KError(-libc::EBADF);
+    pub const EEXIST: super::KError = super::KError(-libc::EEXIST);
+    pub const ENOTDIR: super::KError = super::KError(-libc::ENOTDIR);
+    pub const EISDIR: super::KError = super::KError(-libc::EISDIR);
+    pub const ENOTEMPTY: super::KError = super::KError(-libc::ENOTEMPTY);
+    pub const ENAMETOOLONG: super::KError = super::KError(-libc::ENAMETOOLONG);
+    pub const EACCES: super::KError = super::KError(-libc::EACCES);
+    pub const ESPIPE: super::KError = super::KError(-libc::ESPIPE);
+    pub const EOVERFLOW: super::KError = super::KError(-libc::EOVERFLOW);
+    pub const ELOOP: super::KError = super::KError(-libc::ELOOP);
+    pub const EXDEV: super::KError = super::KError(-libc::EXDEV);
+    pub const EILSEQ: super::KError = super::KError(-libc::EILSEQ);
+    pub const ENOTSUP: super::KError = super::KError(-libc::ENOTSUP);
+    pub const EFBIG: super::KError = super::KError(-libc::EFBIG);
+    pub const EPIPE: super::KError = super::KError(-libc::EPIPE);
+    pub const ENXIO: super::KError = super::KError(-libc::ENXIO);
+    pub const EBADMSG: super::KError = super::KError(-libc::EBADMSG);
+    pub const EMSGSIZE: super::KError = super::KError(-libc::EMSGSIZE);
 }
 
 const UNKNOW_STR: &CStr = c"EUNKNOW ";
@@ -77,15 +87,23 @@ const ELOOP_STR: &CStr = c"Too many symbolic links encountered";
 const EXDEV_STR: &CStr = c"Cross-device link";
 const EILSEQ_STR: &CStr = c"Invalid data";
 const ENOTSUP_STR: &CStr = c"Not supported";
+const EFBIG_STR: &CStr = c"File too large";
+const EPIPE_STR: &CStr = c"Broken pipe";
+const ENXIO_STR: &CStr = c"No such device or address";
+const EBADMSG_STR: &CStr = c"Bad message";
+const EMSGSIZE_STR: &CStr = c"Message too long";
 
+/// A negated errno.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct Error(i32);
-static mut ERRNO: Error = Error(0);
+pub struct KError(i32);
+/// The name most of the kernel uses for [`KError`].
+pub type Error = KError;
+static mut ERRNO: Error = KError(0);
 
-impl Error {
+impl KError {
     pub fn from_errno(errno: i32) -> Error {
-        Error(errno)
+        KError(errno)
     }
 
     pub fn to_errno(self) -> i32 {
@@ -121,11 +139,70 @@ impl Error {
             code::EXDEV => EXDEV_STR,
             code::EILSEQ => EILSEQ_STR,
             code::ENOTSUP => ENOTSUP_STR,
+            code::EFBIG => EFBIG_STR,
+            code::EPIPE => EPIPE_STR,
+            code::ENXIO => ENXIO_STR,
+            code::EBADMSG => EBADMSG_STR,
+            code::EMSGSIZE => EMSGSIZE_STR,
             _ => UNKNOW_STR,
         }
     }
 }
 
+// Each kind has its own errno, which converts back to it.
+const KINDS: &[(ErrorKind, i32)] = &[
+    (ErrorKind::Other, libc::EIO),
+    (ErrorKind::NotFound, libc::ENOENT),
+    (ErrorKind::PermissionDenied, libc::EACCES),
+    (ErrorKind::ConnectionRefused, libc::ECONNREFUSED),
+    (ErrorKind::ConnectionReset, libc::ECONNRESET),
+    (ErrorKind::ConnectionAborted, libc::ECONNABORTED),
+    (ErrorKind::NotConnected, libc::ENOTCONN),
+    (ErrorKind::AddrInUse, libc::EADDRINUSE),
+    (ErrorKind::AddrNotAvailable, libc::EADDRNOTAVAIL),
+    (ErrorKind::BrokenPipe, libc::EPIPE),
+    (ErrorKind::AlreadyExists, libc::EEXIST),
+    (ErrorKind::InvalidInput, libc::EINVAL),
+    // Malformed data, as opposed to invalid parameters.
+    (ErrorKind::InvalidData, libc::EBADMSG),
+    (ErrorKind::TimedOut, libc::ETIMEDOUT),
+    (ErrorKind::Interrupted, libc::EINTR),
+    (ErrorKind::Unsupported, libc::ENOSYS),
+    (ErrorKind::OutOfMemory, libc::ENOMEM),
+    // A write which couldn't write anything because the device is full.
+    (ErrorKind::WriteZero, libc::ENOSPC),
+];
+
+impl From<ErrorKind> for KError {
+    fn from(kind: ErrorKind) -> Self {
+        let errno = KINDS
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map_or(libc::EIO, |(_, errno)| *errno);
+        KError(-errno)
+    }
+}
+
+impl From<KError> for ErrorKind {
+    fn from(error: KError) -> Self {
+        if let Some((kind, _)) = KINDS.iter().find(|(_, errno)| -errno == error.0) {
+            return *kind;
+        }
+        match error {
+            code::EPERM => ErrorKind::PermissionDenied,
+            code::ENODEV | code::ENXIO => ErrorKind::NotFound,
+            code::ENOTSUP => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_io::Error for KError {
+    fn kind(&self) -> ErrorKind {
+        (*self).into()
+    }
+}
+
 impl From<AllocError> for Error {
     fn from(_: AllocError) -> Error {
         code::ENOMEM
@@ -162,10 +239,10 @@ impl From<core::convert::Infallible> for Error {
     }
 }
 
-impl<T> From<fatfs::Error<T>> for Error {
+impl<T: Into<Error>> From<fatfs::Error<T>> for Error {
     fn from(value: fatfs::Error<T>) -> Self {
         match value {
-            fatfs::Error::Io(_) => code::EIO,
+            fatfs::Error::Io(e) => e.into(),
             fatfs::Error::UnexpectedEof => code::EIO,
             fatfs::Error::WriteZero => code::EIO,
             fatfs::Error::InvalidInput => code::EINVAL,
@@ -182,7 +259,7 @@ impl<T> From<fatfs::Error<T>> for Error {
 }
 
 pub fn strerror(error: i32) -> *const core::ffi::c_char {
-    Error(error).name().as_ptr()
+    KError(error).name().as_ptr()
 }
 
 impl core::fmt::Display for Error {
@@ -192,3 +269,21 @@ impl core::fmt::Display for Error {
         write!(f, "Error({}): {}", self.0, err_msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_error_kind_round_trip() {
+        for (kind, errno) in KINDS {
+            let error = KError::from(*kind);
+            assert_eq!(error.to_errno(), -errno);
+            assert_eq!(ErrorKind::from(error), *kind);
+        }
+        assert_eq!(ErrorKind::from(code::EPERM), ErrorKind::PermissionDenied);
+        assert_eq!(ErrorKind::from(code::EAGAIN), ErrorKind::Other);
+        assert_eq!(KError::from(ErrorKind::Other), code::EIO);
+    }
+}
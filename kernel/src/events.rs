@@ -0,0 +1,88 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counters of kernel events too frequent to be logged one by one, like
+//! input dropped because a buffer is full. They are listed in /proc/events.
+//! A path counting one which also wants to log can do it under
+//! [`ratelimit!`](crate::ratelimit).
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// An event counted by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Received bytes dropped because there was no room for them.
+    RxDropped,
+    /// Heap allocations which failed.
+    AllocFailure,
+    /// System calls which returned EAGAIN.
+    Eagain,
+    /// Heap usage going above a watermark.
+    HeapWatermark,
+}
+
+impl Event {
+    pub const ALL: [Event; 4] = [
+        Event::RxDropped,
+        Event::AllocFailure,
+        Event::Eagain,
+        Event::HeapWatermark,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::RxDropped => "rx_dropped",
+            Event::AllocFailure => "alloc_failure",
+            Event::Eagain => "eagain",
+            Event::HeapWatermark => "heap_watermark",
+        }
+    }
+}
+
+// Atomics so that events can be counted from IRQs and from the allocator.
+static COUNTERS: [AtomicUsize; Event::ALL.len()] =
+    [const { AtomicUsize::new(0) }; Event::ALL.len()];
+
+/// Counts `n` occurrences of `event`.
+pub fn add(event: Event, n: usize) {
+    COUNTERS[event as usize].fetch_add(n, Ordering::Relaxed);
+}
+
+/// Counts one occurrence of `event`.
+pub fn count(event: Event) {
+    add(event, 1);
+}
+
+/// Number of occurrences of `event` since boot.
+pub fn get(event: Event) -> usize {
+    COUNTERS[event as usize].load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_event_counters() {
+        for (i, event) in Event::ALL.into_iter().enumerate() {
+            assert_eq!(event as usize, i);
+        }
+        // Other tests may count events concurrently.
+        let before = get(Event::RxDropped);
+        add(Event::RxDropped, 3);
+        count(Event::RxDropped);
+        assert!(get(Event::RxDropped) >= before + 4);
+    }
+}
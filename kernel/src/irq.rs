@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{arch, support::DisableInterruptGuard, time, types::Uint};
+use crate::{arch, crypto::random, support::DisableInterruptGuard, time, types::Uint};
 use blueos_kconfig::NUM_CORES;
 use core::sync::atomic::Ordering;
 
@@ -52,8 +52,14 @@ impl Drop for IrqTrace {
 }
 
 pub fn is_in_irq() -> bool {
+    nesting_depth() != 0
+}
+
+/// Number of interrupt handlers active on the current core, greater than 1
+/// when a handler has been preempted by a higher priority interrupt.
+pub fn nesting_depth() -> usize {
     let _dig = DisableInterruptGuard::new();
-    unsafe { IRQ_NESTING_COUNT[arch::current_cpu_id()] != 0 }
+    unsafe { IRQ_NESTING_COUNT[arch::current_cpu_id()] as usize }
 }
 
 #[inline]
@@ -75,6 +81,7 @@ unsafe fn decrement_nesting_count() -> usize {
 // This might be called from assembly code, use extern "C" here.
 pub extern "C" fn enter_irq() -> usize {
     let _dig = DisableInterruptGuard::new();
+    random::add_interrupt_randomness();
     #[cfg(procfs)]
     unsafe {
         irq_trace::PER_CPU_TRACE_INFO[arch::current_cpu_id()].on_enter();
@@ -83,11 +90,16 @@ pub extern "C" fn enter_irq() -> usize {
 }
 
 pub extern "C" fn leave_irq() -> usize {
-    let _dig = DisableInterruptGuard::new();
+    // Softirqs are accounted on their own, not as part of the handler.
     #[cfg(procfs)]
-    unsafe {
-        irq_trace::PER_CPU_TRACE_INFO[arch::current_cpu_id()].on_leave();
+    {
+        let _dig = DisableInterruptGuard::new();
+        unsafe {
+            irq_trace::PER_CPU_TRACE_INFO[arch::current_cpu_id()].on_leave();
+        }
     }
+    crate::softirq::irq_exit();
+    let _dig = DisableInterruptGuard::new();
     unsafe { decrement_nesting_count() - 1 }
 }
 
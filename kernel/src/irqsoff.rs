@@ -0,0 +1,190 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! IRQ-off latency tracer.
+//!
+//! Measures every window during which a core runs with local interrupts
+//! disabled through `disable_local_irq_save/enable_local_irq_restore`, and
+//! keeps the longest ones along with the code that opened them. Critical
+//! sections entered through `SpinLock::irqsave_lock` are attributed to the
+//! caller of the lock.
+//!
+//! Windows opened while interrupts are already disabled, e.g. in interrupt
+//! handlers, are part of the enclosing window and are not measured.
+
+use crate::{arch, sync::SpinLock, time};
+use blueos_kconfig::NUM_CORES;
+use core::{
+    fmt,
+    panic::Location,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of windows kept, the longest first.
+pub const MAX_IRQSOFF_RECORDS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IrqsOffRecord {
+    pub cpu: usize,
+    pub cycles: u64,
+    /// Where interrupts were disabled.
+    pub caller: &'static Location<'static>,
+}
+
+impl IrqsOffRecord {
+    pub fn duration(&self) -> Duration {
+        time::get_cycles_to_duration(self.cycles)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Window {
+    start: u64,
+    caller: Option<&'static Location<'static>>,
+    // The state returned by `disable_local_irq_save` when the window was
+    // opened from C, which is passed back to close it.
+    token: Option<usize>,
+}
+
+// Only accessed by the owning core with local interrupts disabled.
+static mut WINDOWS: [Window; NUM_CORES] = [const {
+    Window {
+        start: 0,
+        caller: None,
+        token: None,
+    }
+}; NUM_CORES];
+
+// The records are taken with plain `lock`, since `irqsave_lock` would
+// re-enter the tracer. Interrupts are always disabled when they are updated.
+static RECORDS: SpinLock<[Option<IrqsOffRecord>; MAX_IRQSOFF_RECORDS]> =
+    SpinLock::new([None; MAX_IRQSOFF_RECORDS]);
+// Shortest recorded window once the records are full, so that the common
+// short window doesn't take the lock.
+static THRESHOLD: AtomicU64 = AtomicU64::new(0);
+
+/// Called right after local interrupts have been disabled.
+#[inline]
+pub(crate) fn irqs_off(caller: &'static Location<'static>, token: Option<usize>) {
+    let cpu = arch::current_cpu_id();
+    let window = Window {
+        start: time::get_sys_cycles(),
+        caller: Some(caller),
+        token,
+    };
+    unsafe { WINDOWS[cpu] = window };
+}
+
+/// Called right before local interrupts may be enabled again.
+#[inline]
+pub(crate) fn irqs_on(token: Option<usize>) {
+    let now = time::get_sys_cycles();
+    let cpu = arch::current_cpu_id();
+    let window = unsafe { &mut WINDOWS[cpu] };
+    if window.token != token {
+        return;
+    }
+    let Some(caller) = window.caller.take() else {
+        return;
+    };
+    let cycles = now.saturating_sub(window.start);
+    if cycles <= THRESHOLD.load(Ordering::Relaxed) {
+        return;
+    }
+    record(IrqsOffRecord {
+        cpu,
+        cycles,
+        caller,
+    });
+}
+
+#[inline(never)]
+fn record(new: IrqsOffRecord) {
+    let mut records = RECORDS.lock();
+    let Some(pos) = records
+        .iter()
+        .position(|r| r.is_none_or(|r| r.cycles < new.cycles))
+    else {
+        return;
+    };
+    records.copy_within(pos..MAX_IRQSOFF_RECORDS - 1, pos + 1);
+    records[pos] = Some(new);
+    if let Some(last) = records[MAX_IRQSOFF_RECORDS - 1] {
+        THRESHOLD.store(last.cycles, Ordering::Relaxed);
+    }
+}
+
+/// Returns the longest windows recorded so far, the longest first.
+pub fn records() -> [Option<IrqsOffRecord>; MAX_IRQSOFF_RECORDS] {
+    let _dig = crate::support::DisableInterruptGuard::new();
+    *RECORDS.lock()
+}
+
+/// Writes the longest windows recorded so far, one per line with the core,
+/// the length in microseconds and where interrupts were disabled.
+pub fn dump(w: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(w, "{:>4} {:>10} CALLER", "CPU", "USECS")?;
+    for r in records().iter().flatten() {
+        writeln!(
+            w,
+            "{:>4} {:>10} {}",
+            r.cpu,
+            r.duration().as_micros(),
+            r.caller
+        )?;
+    }
+    Ok(())
+}
+
+pub fn reset() {
+    let _dig = crate::support::DisableInterruptGuard::new();
+    *RECORDS.lock() = [None; MAX_IRQSOFF_RECORDS];
+    THRESHOLD.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_irqsoff_records_are_sorted() {
+        reset();
+        {
+            let _dig = crate::support::DisableInterruptGuard::new();
+            for cycles in [3, 1, 4, 1, 5, 9, 2, 6, 5, 3] {
+                record(IrqsOffRecord {
+                    cpu: 0,
+                    cycles: cycles + u32::MAX as u64,
+                    caller: Location::caller(),
+                });
+            }
+        }
+        let records = records();
+        let cycles: [u64; MAX_IRQSOFF_RECORDS] =
+            core::array::from_fn(|i| records[i].unwrap().cycles - u32::MAX as u64);
+        assert_eq!(cycles, [9, 6, 5, 5, 4, 3, 3, 2]);
+
+        let mut out = String::new();
+        dump(&mut out).unwrap();
+        let caller = records[0].unwrap().caller.to_string();
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some(" CPU      USECS CALLER"));
+        assert_eq!(lines.clone().count(), MAX_IRQSOFF_RECORDS);
+        assert!(lines.all(|line| line.ends_with(&caller)));
+        reset();
+    }
+}
@@ -0,0 +1,635 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The kernel log ring, which keeps the latest lines printed by `kprintln!`
+//! and the `log` macros, so that they can be read back after the fact from
+//! /dev/kmsg or with [`klogctl`].
+//!
+//! Records are numbered, timestamped and carry a syslog level. They are
+//! packed in a fixed-size byte ring, the oldest being dropped to make room.
+//! Recording doesn't allocate and can be done from interrupt context.
+//!
+//! Consoles write the records out with [`write_records`], which hands them
+//! over as slices of the ring instead of copying them.
+
+use crate::{
+    error::{code, Error},
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    time,
+};
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const KLOG_BUF_SIZE: usize = blueos_kconfig::KLOG_BUF_SIZE;
+/// Longest text kept per record, the rest is cut.
+pub const KLOG_LINE_SIZE: usize = 256;
+
+pub const LOG_ERR: u8 = 3;
+pub const LOG_WARNING: u8 = 4;
+pub const LOG_INFO: u8 = 6;
+pub const LOG_DEBUG: u8 = 7;
+
+// klogctl actions, the same as Linux.
+pub const SYSLOG_ACTION_CLOSE: i32 = 0;
+pub const SYSLOG_ACTION_OPEN: i32 = 1;
+pub const SYSLOG_ACTION_READ: i32 = 2;
+pub const SYSLOG_ACTION_READ_ALL: i32 = 3;
+pub const SYSLOG_ACTION_READ_CLEAR: i32 = 4;
+pub const SYSLOG_ACTION_CLEAR: i32 = 5;
+pub const SYSLOG_ACTION_SIZE_UNREAD: i32 = 9;
+pub const SYSLOG_ACTION_SIZE_BUFFER: i32 = 10;
+
+// Text length, level and timestamp in milliseconds.
+const HEADER_SIZE: usize = 2 + 1 + 8;
+
+// Most slices handed to a console at once.
+const IOV_MAX: usize = 16;
+
+struct Storage(UnsafeCell<[u8; KLOG_BUF_SIZE]>);
+
+// SAFETY: Only accessed through RING, or through the records it has
+// pinned, which are kept as they are until they're unpinned.
+unsafe impl Sync for Storage {}
+
+static STORAGE: Storage = Storage(UnsafeCell::new([0; KLOG_BUF_SIZE]));
+static RING: SpinLock<LogRing> = SpinLock::new(LogRing::new(STORAGE.0.get().cast()));
+// Records dropped because the ring was full of pinned ones.
+static LOST: AtomicUsize = AtomicUsize::new(0);
+// Readers waiting for new records, only then are they woken up. Waking
+// takes scheduler locks, under which the scheduler may well log.
+static WAITERS: AtomicUsize = AtomicUsize::new(0);
+// Mirrors the next sequence number for waiters.
+static NEXT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// The log level of `level`, as used by the `log` crate.
+pub fn level_of(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => LOG_ERR,
+        log::Level::Warn => LOG_WARNING,
+        log::Level::Info => LOG_INFO,
+        log::Level::Debug | log::Level::Trace => LOG_DEBUG,
+    }
+}
+
+struct Header {
+    len: usize,
+    level: u8,
+    timestamp_ms: u64,
+}
+
+struct LogRing {
+    // KLOG_BUF_SIZE bytes.
+    buf: *mut u8,
+    // Offset of the oldest record.
+    head: usize,
+    used: usize,
+    first_seq: u64,
+    next_seq: u64,
+    // Where SYSLOG_ACTION_READ_ALL starts, moved by SYSLOG_ACTION_CLEAR.
+    clear_seq: u64,
+    // Where SYSLOG_ACTION_READ continues.
+    syslog_seq: u64,
+    // Number of write_records calls reading records without the lock, and
+    // the first record any of them has. It and the later ones aren't
+    // dropped while `pins` isn't 0.
+    pins: usize,
+    pinned: u64,
+}
+
+// SAFETY: `buf` is owned by the ring.
+unsafe impl Send for LogRing {}
+
+impl LogRing {
+    const fn new(buf: *mut u8) -> Self {
+        Self {
+            buf,
+            head: 0,
+            used: 0,
+            first_seq: 0,
+            next_seq: 0,
+            clear_seq: 0,
+            syslog_seq: 0,
+            pins: 0,
+            pinned: 0,
+        }
+    }
+
+    fn copy_in(&mut self, offset: usize, data: &[u8]) {
+        for (i, &b) in data.iter().enumerate() {
+            // SAFETY: In bounds, and only free space is written to, never
+            // pinned records.
+            unsafe { self.buf.add((offset + i) % KLOG_BUF_SIZE).write(b) };
+        }
+    }
+
+    fn copy_out(&self, offset: usize, data: &mut [u8]) {
+        for (i, b) in data.iter_mut().enumerate() {
+            // SAFETY: In bounds.
+            *b = unsafe { self.buf.add((offset + i) % KLOG_BUF_SIZE).read() };
+        }
+    }
+
+    // The text at `offset` as one slice, or two when it wraps around.
+    //
+    // SAFETY: The slices are only valid while the text is in the ring, the
+    // caller must have it pinned to use them without the lock.
+    unsafe fn text_slices<'a>(&self, offset: usize, len: usize) -> (&'a [u8], &'a [u8]) {
+        let start = offset % KLOG_BUF_SIZE;
+        let first = len.min(KLOG_BUF_SIZE - start);
+        (
+            slice::from_raw_parts(self.buf.add(start), first),
+            slice::from_raw_parts(self.buf, len - first),
+        )
+    }
+
+    fn header_at(&self, offset: usize) -> Header {
+        let mut raw = [0; HEADER_SIZE];
+        self.copy_out(offset, &mut raw);
+        Header {
+            len: u16::from_le_bytes([raw[0], raw[1]]) as usize,
+            level: raw[2],
+            timestamp_ms: u64::from_le_bytes(raw[3..].try_into().unwrap()),
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        let size = HEADER_SIZE + self.header_at(self.head).len;
+        self.head = (self.head + size) % KLOG_BUF_SIZE;
+        self.used -= size;
+        self.first_seq += 1;
+        self.clear_seq = self.clear_seq.max(self.first_seq);
+        self.syslog_seq = self.syslog_seq.max(self.first_seq);
+    }
+
+    // Returns false if the record was dropped, there being no room left
+    // without dropping pinned records.
+    fn push(&mut self, level: u8, timestamp_ms: u64, text: &[u8]) -> bool {
+        let text = &text[..text.len().min(KLOG_BUF_SIZE - HEADER_SIZE)];
+        let size = HEADER_SIZE + text.len();
+        while KLOG_BUF_SIZE - self.used < size {
+            if self.pins > 0 && self.first_seq >= self.pinned {
+                return false;
+            }
+            self.drop_oldest();
+        }
+        let mut raw = [0; HEADER_SIZE];
+        raw[..2].copy_from_slice(&(text.len() as u16).to_le_bytes());
+        raw[2] = level;
+        raw[3..].copy_from_slice(&timestamp_ms.to_le_bytes());
+        let tail = (self.head + self.used) % KLOG_BUF_SIZE;
+        self.copy_in(tail, &raw);
+        self.copy_in(tail + HEADER_SIZE, text);
+        self.used += size;
+        self.next_seq += 1;
+        true
+    }
+
+    // Calls `f` with the sequence number, header and text offset of each
+    // record from `seq` on.
+    fn for_each_from(&self, seq: u64, mut f: impl FnMut(u64, &Header, usize)) {
+        let mut offset = self.head;
+        for s in self.first_seq..self.next_seq {
+            let header = self.header_at(offset);
+            let text_offset = offset + HEADER_SIZE;
+            offset = (text_offset + header.len) % KLOG_BUF_SIZE;
+            if s >= seq {
+                f(s, &header, text_offset);
+            }
+        }
+    }
+}
+
+/// Where a record is formatted before it's pushed, the text is cut at a
+/// character boundary when longer than [`KLOG_LINE_SIZE`].
+struct Line {
+    buf: [u8; KLOG_LINE_SIZE],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(KLOG_LINE_SIZE - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Records a line at `level`, a trailing newline is dropped.
+pub fn record(level: u8, args: fmt::Arguments) {
+    let mut line = Line {
+        buf: [0; KLOG_LINE_SIZE],
+        len: 0,
+    };
+    let _ = line.write_fmt(args);
+    let text = line.buf[..line.len]
+        .strip_suffix(b"\n")
+        .unwrap_or(&line.buf[..line.len]);
+    let timestamp_ms = time::tick_get_millisecond() as u64;
+    let (pushed, next_seq) = {
+        let mut ring = RING.irqsave_lock();
+        (ring.push(level, timestamp_ms, text), ring.next_seq)
+    };
+    if !pushed {
+        LOST.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    // SeqCst against the waiters, either they see the new sequence number
+    // or they are seen.
+    NEXT_SEQ.store(next_seq as usize, Ordering::SeqCst);
+    if WAITERS.load(Ordering::SeqCst) != 0 {
+        let _ = atomic_wake(&NEXT_SEQ, usize::MAX);
+    }
+}
+
+// Formats into a slice, failing once it's full.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+// The dmesg prefix, "<6>[    1.234000] ".
+fn write_syslog_prefix(w: &mut impl Write, header: &Header) -> fmt::Result {
+    write!(
+        w,
+        "<{}>[{:5}.{:06}] ",
+        header.level,
+        header.timestamp_ms / 1000,
+        header.timestamp_ms % 1000 * 1000
+    )
+}
+
+fn syslog_size(header: &Header) -> usize {
+    let mut prefix = SliceWriter {
+        buf: &mut [0; 32],
+        len: 0,
+    };
+    let _ = write_syslog_prefix(&mut prefix, header);
+    prefix.len + header.len + 1
+}
+
+// Copies the records from `seq` on as dmesg lines, returns the bytes
+// written and the sequence number of the first record left out.
+fn read_syslog(ring: &LogRing, seq: u64, buf: &mut [u8]) -> (usize, u64) {
+    let mut w = SliceWriter { buf, len: 0 };
+    let mut next = seq.max(ring.first_seq);
+    let mut full = false;
+    ring.for_each_from(next, |s, header, text_offset| {
+        if full || w.buf.len() - w.len < syslog_size(header) {
+            full = true;
+            return;
+        }
+        let _ = write_syslog_prefix(&mut w, header);
+        ring.copy_out(text_offset, &mut w.buf[w.len..w.len + header.len]);
+        w.len += header.len;
+        w.buf[w.len] = b'\n';
+        w.len += 1;
+        next = s + 1;
+    });
+    (w.len, next)
+}
+
+/// Number of records dropped since boot as soon as they were logged, the
+/// ring being full of records a console was writing out.
+pub fn lost_records() -> usize {
+    LOST.load(Ordering::Relaxed)
+}
+
+/// The sequence number the next record will get.
+pub fn next_seq() -> u64 {
+    RING.irqsave_lock().next_seq
+}
+
+/// Where a console is in the log: the next record to write out, and how
+/// much of it was written already.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub seq: u64,
+    offset: usize,
+}
+
+impl Cursor {
+    pub const fn new(seq: u64) -> Self {
+        Self { seq, offset: 0 }
+    }
+
+    // Moves past `taken` bytes, newlines included.
+    fn advance(&mut self, ring: &LogRing, mut taken: usize) {
+        let seq = self.seq;
+        ring.for_each_from(seq, |_, header, _| {
+            let left = header.len + 1 - self.offset;
+            if taken >= left {
+                taken -= left;
+                self.seq += 1;
+                self.offset = 0;
+            } else {
+                self.offset += taken;
+                taken = 0;
+            }
+        });
+    }
+}
+
+/// Hands the records from `cursor` on to `write`, each followed by a
+/// newline, as slices of the ring rather than copies. `write` returns how
+/// many bytes it took and must not wait. It's called without the log
+/// locked, the records it's given are pinned meanwhile: records logged
+/// while there is no room left without dropping them are lost.
+///
+/// Returns how many records were dropped before the cursor got to them,
+/// and whether the cursor got to the end of the log rather than `write`
+/// taking less than it was given.
+pub fn write_records(cursor: &mut Cursor, mut write: impl FnMut(&[&[u8]]) -> usize) -> (u64, bool) {
+    let mut dropped = 0;
+    loop {
+        let mut iov: [&[u8]; IOV_MAX] = [&[]; IOV_MAX];
+        let mut n = 0;
+        let mut offered = 0;
+        {
+            let mut ring = RING.irqsave_lock();
+            if cursor.seq < ring.first_seq {
+                dropped += ring.first_seq - cursor.seq;
+                *cursor = Cursor::new(ring.first_seq);
+            }
+            if cursor.seq >= ring.next_seq {
+                return (dropped, true);
+            }
+            let r = &*ring;
+            r.for_each_from(cursor.seq, |s, header, text_offset| {
+                // A record takes up to 3 slices.
+                if n + 3 > IOV_MAX {
+                    return;
+                }
+                // SAFETY: Pinned below until `write` is done.
+                let (first, second) = unsafe { r.text_slices(text_offset, header.len) };
+                let mut skip = if s == cursor.seq { cursor.offset } else { 0 };
+                for part in [first, second, &b"\n"[..]] {
+                    if part.len() <= skip {
+                        skip -= part.len();
+                        continue;
+                    }
+                    iov[n] = &part[skip..];
+                    offered += part.len() - skip;
+                    n += 1;
+                    skip = 0;
+                }
+            });
+            ring.pinned = if ring.pins == 0 {
+                cursor.seq
+            } else {
+                ring.pinned.min(cursor.seq)
+            };
+            ring.pins += 1;
+        }
+        let taken = write(&iov[..n]).min(offered);
+        let mut ring = RING.irqsave_lock();
+        ring.pins -= 1;
+        cursor.advance(&ring, taken);
+        if taken < offered {
+            return (dropped, false);
+        }
+    }
+}
+
+/// Reads the record numbered `*seq` as a /dev/kmsg line,
+/// "level,seq,timestamp_us,-;text\n", and moves `*seq` past it. Records
+/// already dropped are skipped. With nothing new, waits for a record unless
+/// `nonblock`, then fails with EAGAIN. Fails with EINVAL if `buf` is too
+/// small for the record.
+pub fn read_record(seq: &mut u64, buf: &mut [u8], nonblock: bool) -> Result<usize, Error> {
+    loop {
+        let expected = NEXT_SEQ.load(Ordering::SeqCst);
+        {
+            let ring = RING.irqsave_lock();
+            let mut result = None;
+            let from = (*seq).max(ring.first_seq);
+            ring.for_each_from(from, |s, header, text_offset| {
+                if result.is_some() {
+                    return;
+                }
+                let mut w = SliceWriter {
+                    buf: &mut *buf,
+                    len: 0,
+                };
+                let ok = write!(
+                    w,
+                    "{},{},{},-;",
+                    header.level,
+                    s,
+                    header.timestamp_ms * 1000
+                )
+                .is_ok()
+                    && w.buf.len() - w.len > header.len;
+                if !ok {
+                    result = Some(Err(code::EINVAL));
+                    return;
+                }
+                ring.copy_out(text_offset, &mut w.buf[w.len..w.len + header.len]);
+                w.len += header.len;
+                w.buf[w.len] = b'\n';
+                result = Some(Ok((w.len + 1, s + 1)));
+            });
+            match result {
+                Some(Ok((len, next))) => {
+                    *seq = next;
+                    return Ok(len);
+                }
+                Some(Err(e)) => return Err(e),
+                None => *seq = from,
+            }
+        }
+        if nonblock {
+            return Err(code::EAGAIN);
+        }
+        WAITERS.fetch_add(1, Ordering::SeqCst);
+        let _ = atomic_wait(&NEXT_SEQ, expected, None);
+        WAITERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads or clears the log like the Linux `syslog` syscall. Returns the
+/// bytes read or the size asked for.
+pub fn klogctl(action: i32, buf: &mut [u8]) -> Result<usize, Error> {
+    match action {
+        SYSLOG_ACTION_CLOSE | SYSLOG_ACTION_OPEN => Ok(0),
+        SYSLOG_ACTION_READ => loop {
+            let expected = NEXT_SEQ.load(Ordering::SeqCst);
+            {
+                let mut ring = RING.irqsave_lock();
+                let seq = ring.syslog_seq;
+                let (len, next) = read_syslog(&ring, seq, buf);
+                if len > 0 || buf.is_empty() {
+                    ring.syslog_seq = next;
+                    return Ok(len);
+                }
+                if next < ring.next_seq {
+                    // The next record doesn't fit.
+                    return Err(code::EINVAL);
+                }
+            }
+            WAITERS.fetch_add(1, Ordering::SeqCst);
+            let _ = atomic_wait(&NEXT_SEQ, expected, None);
+            WAITERS.fetch_sub(1, Ordering::SeqCst);
+        },
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            let mut ring = RING.irqsave_lock();
+            // Only the latest records which fit are read.
+            let mut total = 0;
+            ring.for_each_from(ring.clear_seq, |_, header, _| total += syslog_size(header));
+            let mut seq = ring.clear_seq;
+            ring.for_each_from(ring.clear_seq, |s, header, _| {
+                if total > buf.len() {
+                    total -= syslog_size(header);
+                    seq = s + 1;
+                }
+            });
+            let (len, _) = read_syslog(&ring, seq, buf);
+            if action == SYSLOG_ACTION_READ_CLEAR {
+                ring.clear_seq = ring.next_seq;
+            }
+            Ok(len)
+        }
+        SYSLOG_ACTION_CLEAR => {
+            let mut ring = RING.irqsave_lock();
+            ring.clear_seq = ring.next_seq;
+            Ok(0)
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => {
+            let ring = RING.irqsave_lock();
+            let mut size = 0;
+            ring.for_each_from(ring.syslog_seq, |_, header, _| size += syslog_size(header));
+            Ok(size)
+        }
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(KLOG_BUF_SIZE),
+        _ => Err(code::EINVAL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::String, vec, vec::Vec};
+    use blueos_test_macro::test;
+
+    fn texts(ring: &LogRing) -> Vec<String> {
+        let mut texts = Vec::new();
+        ring.for_each_from(0, |_, header, text_offset| {
+            let mut text = vec![0; header.len];
+            ring.copy_out(text_offset, &mut text);
+            texts.push(String::from_utf8(text).unwrap());
+        });
+        texts
+    }
+
+    #[test]
+    fn test_log_ring_wraps() {
+        let mut storage = vec![0; KLOG_BUF_SIZE];
+        let mut ring = LogRing::new(storage.as_mut_ptr());
+        let line = [b'x'; 100];
+        let per_ring = KLOG_BUF_SIZE / (HEADER_SIZE + line.len());
+        for i in 0..3 * per_ring {
+            ring.push(LOG_INFO, i as u64, &line[..i % 10 + 90]);
+        }
+        assert_eq!(ring.next_seq, 3 * per_ring as u64);
+        assert!(ring.first_seq > 0);
+        assert!(ring.used <= KLOG_BUF_SIZE);
+        let texts = texts(&ring);
+        assert_eq!(texts.len() as u64, ring.next_seq - ring.first_seq);
+        for (seq, text) in (ring.first_seq..).zip(&texts) {
+            assert_eq!(text.len(), seq as usize % 10 + 90);
+        }
+        assert_eq!(ring.clear_seq, ring.first_seq);
+
+        // Pinned records aren't dropped, new ones are instead.
+        ring.pins = 1;
+        ring.pinned = ring.first_seq;
+        assert!(!ring.push(LOG_INFO, 0, &line));
+        ring.pins = 0;
+        assert!(ring.push(LOG_INFO, 0, &line));
+    }
+
+    #[test]
+    fn test_write_records() {
+        let mut cursor = Cursor::new(next_seq());
+        record(LOG_INFO, format_args!("written out {}\n", "whole"));
+        record(LOG_INFO, format_args!("written out {}\n", "bit by bit"));
+        // Takes 3 bytes at a time, like a console with little room.
+        let mut out = Vec::new();
+        loop {
+            let (dropped, done) = write_records(&mut cursor, |bufs| {
+                let mut taken = 0;
+                for buf in bufs {
+                    let n = buf.len().min(3 - taken);
+                    out.extend_from_slice(&buf[..n]);
+                    taken += n;
+                }
+                taken
+            });
+            assert_eq!(dropped, 0);
+            if done {
+                break;
+            }
+        }
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("written out whole\nwritten out bit by bit\n"));
+        assert_eq!(cursor.seq, next_seq());
+    }
+
+    #[test]
+    fn test_klogctl() {
+        assert_eq!(
+            klogctl(SYSLOG_ACTION_SIZE_BUFFER, &mut []),
+            Ok(KLOG_BUF_SIZE)
+        );
+        assert_eq!(klogctl(42, &mut []), Err(code::EINVAL));
+        record(LOG_WARNING, format_args!("klogctl test {}\n", 1));
+        let mut buf = vec![0; KLOG_BUF_SIZE];
+        let n = klogctl(SYSLOG_ACTION_READ_ALL, &mut buf).unwrap();
+        let all = core::str::from_utf8(&buf[..n]).unwrap();
+        let line = all
+            .lines()
+            .find(|l| l.ends_with("] klogctl test 1"))
+            .unwrap();
+        assert!(line.starts_with("<4>["));
+        assert!(klogctl(SYSLOG_ACTION_SIZE_UNREAD, &mut []).unwrap() > 0);
+
+        // Only the latest records are read when they don't all fit.
+        let n = klogctl(SYSLOG_ACTION_READ_ALL, &mut buf[..64]).unwrap();
+        assert!(n <= 64);
+
+        assert_eq!(klogctl(SYSLOG_ACTION_CLEAR, &mut []), Ok(0));
+        let n = klogctl(SYSLOG_ACTION_READ_ALL, &mut buf).unwrap();
+        let all = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(!all.contains("klogctl test 1"));
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent key-value store for device settings.
+//!
+//! The store is a log of records in a region of a block device, split in
+//! pages. Changes are appended to the active page as a transaction ending
+//! with a commit record, so a transaction torn by a power loss is ignored
+//! when the store is opened again. When the active page is full, the live
+//! entries are compacted into the next page, erased first, so that the
+//! pages are erased and written in turn. The store can therefore live on
+//! NOR flash, and spreads the wear over all its pages. Page headers and
+//! records are protected by a CRC32.
+//!
+//! The entries are also exposed as files in `/etc`.
+
+use crate::{
+    devices::{Device, DeviceManager},
+    error::{code, Error},
+    sync::RwSleepLock,
+};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use blueos_infra::crc::{crc32, crc32_update};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+pub const MAX_KEY_LEN: usize = 64;
+pub const MAX_VALUE_LEN: usize = u16::MAX as usize;
+
+const PAGE_MAGIC: u32 = u32::from_le_bytes(*b"BOSK");
+// Unwritten flash reads as all ones.
+const ERASED: u8 = 0xff;
+const KIND_SET: u8 = 1;
+const KIND_REMOVE: u8 = 2;
+const KIND_COMMIT: u8 = 3;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct PageHeader {
+    magic: u32,
+    seq: u32,
+    reserved: u32,
+    // Covers the fields above.
+    crc: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct RecordHeader {
+    kind: u8,
+    key_len: u8,
+    value_len: u16,
+    // Covers the fields above, the key and the value.
+    crc: u32,
+}
+
+const PAGE_HEADER_SIZE: usize = core::mem::size_of::<PageHeader>();
+const RECORD_HEADER_SIZE: usize = core::mem::size_of::<RecordHeader>();
+
+impl PageHeader {
+    fn new(seq: u32) -> Self {
+        let mut header = Self {
+            magic: PAGE_MAGIC,
+            seq,
+            reserved: 0,
+            crc: 0,
+        };
+        header.crc = crc32(&header.as_bytes()[..PAGE_HEADER_SIZE - 4]);
+        header
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == PAGE_MAGIC && self.crc == crc32(&self.as_bytes()[..PAGE_HEADER_SIZE - 4])
+    }
+}
+
+type Entries = BTreeMap<String, Vec<u8>>;
+type Op = (String, Option<Vec<u8>>);
+
+fn encode_record(buf: &mut Vec<u8>, kind: u8, key: &str, value: &[u8]) {
+    let mut header = RecordHeader {
+        kind,
+        key_len: key.len() as u8,
+        value_len: value.len() as u16,
+        crc: 0,
+    };
+    let crc = crc32(&header.as_bytes()[..RECORD_HEADER_SIZE - 4]);
+    let crc = crc32_update(crc, key.as_bytes());
+    header.crc = crc32_update(crc, value);
+    buf.extend_from_slice(header.as_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(value);
+}
+
+fn encode_transaction(ops: &[Op]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (key, value) in ops {
+        match value {
+            Some(value) => encode_record(&mut buf, KIND_SET, key, value),
+            None => encode_record(&mut buf, KIND_REMOVE, key, &[]),
+        }
+    }
+    encode_record(&mut buf, KIND_COMMIT, "", &[]);
+    buf
+}
+
+fn apply(entries: &mut Entries, ops: impl IntoIterator<Item = Op>) {
+    for (key, value) in ops {
+        match value {
+            Some(value) => entries.insert(key, value),
+            None => entries.remove(&key),
+        };
+    }
+}
+
+// Replays the records of a page. Returns the entries and the end of the
+// last committed transaction, and whether everything after it is erased.
+fn replay(page: &[u8]) -> (Entries, usize, bool) {
+    let mut entries = Entries::new();
+    let mut pending = Vec::new();
+    let mut pos = PAGE_HEADER_SIZE;
+    let mut committed = pos;
+    while pos + RECORD_HEADER_SIZE <= page.len() {
+        let raw = &page[pos..pos + RECORD_HEADER_SIZE];
+        if raw.iter().all(|&b| b == ERASED) {
+            break;
+        }
+        let header = RecordHeader::read_from_bytes(raw).unwrap();
+        let key_end = pos + RECORD_HEADER_SIZE + header.key_len as usize;
+        let end = key_end + header.value_len as usize;
+        if end > page.len() {
+            break;
+        }
+        let crc = crc32_update(
+            crc32(&raw[..RECORD_HEADER_SIZE - 4]),
+            &page[pos + RECORD_HEADER_SIZE..end],
+        );
+        if crc != header.crc {
+            break;
+        }
+        let Ok(key) = core::str::from_utf8(&page[pos + RECORD_HEADER_SIZE..key_end]) else {
+            break;
+        };
+        match header.kind {
+            KIND_SET => pending.push((String::from(key), Some(page[key_end..end].to_vec()))),
+            KIND_REMOVE => pending.push((String::from(key), None)),
+            KIND_COMMIT => {
+                apply(&mut entries, pending.drain(..));
+                committed = end;
+            }
+            _ => break,
+        }
+        pos = end;
+    }
+    let clean = page[committed..].iter().all(|&b| b == ERASED);
+    (entries, committed, clean)
+}
+
+/// The part of a block device used by the store.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub device: String,
+    /// Offset of the first page, in bytes.
+    pub offset: u64,
+    pub page_size: usize,
+    /// Number of pages, at least 2.
+    pub pages: usize,
+}
+
+struct Store {
+    dev: Arc<dyn Device>,
+    region: Region,
+    page: usize,
+    seq: u32,
+    // Where the next transaction goes in the active page.
+    pos: usize,
+    entries: Entries,
+}
+
+impl Store {
+    fn page_offset(&self, page: usize) -> u64 {
+        self.region.offset + (page * self.region.page_size) as u64
+    }
+
+    fn write_all(&self, pos: u64, buf: &[u8]) -> Result<(), Error> {
+        if self.dev.write(pos, buf, false)? != buf.len() {
+            return Err(code::EIO);
+        }
+        Ok(())
+    }
+
+    fn open(dev: Arc<dyn Device>, region: Region) -> Result<Self, Error> {
+        if region.pages < 2 || region.page_size < PAGE_HEADER_SIZE + RECORD_HEADER_SIZE {
+            return Err(code::EINVAL);
+        }
+        let mut store = Self {
+            dev,
+            region,
+            page: 0,
+            seq: 0,
+            pos: PAGE_HEADER_SIZE,
+            entries: Entries::new(),
+        };
+        let mut buf = vec![0u8; store.region.page_size];
+        let mut newest = None;
+        for page in 0..store.region.pages {
+            let mut header = PageHeader::new(0);
+            store
+                .dev
+                .read(store.page_offset(page), header.as_mut_bytes(), false)?;
+            // Sequence numbers wrap, the newest page is the one the others
+            // are behind.
+            if header.is_valid()
+                && newest.is_none_or(|(_, seq)| header.seq.wrapping_sub(seq) as i32 > 0)
+            {
+                newest = Some((page, header.seq));
+            }
+        }
+        let Some((page, seq)) = newest else {
+            log::info!("Formatting the key-value store");
+            // Start so that the first page used is page 0.
+            store.page = store.region.pages - 1;
+            store.compact(Entries::new())?;
+            return Ok(store);
+        };
+        if store.dev.read(store.page_offset(page), &mut buf, false)? != buf.len() {
+            return Err(code::EIO);
+        }
+        let (entries, committed, clean) = replay(&buf);
+        store.page = page;
+        store.seq = seq;
+        store.pos = committed;
+        store.entries = entries;
+        if !clean {
+            // Records can't be appended after a torn write, so start over
+            // from a clean page.
+            log::warn!("Dropping an incomplete transaction from the key-value store");
+            let entries = core::mem::take(&mut store.entries);
+            store.compact(entries)?;
+        }
+        Ok(store)
+    }
+
+    // Erases the next page and writes `entries` to it, it then becomes the
+    // active one. The header is written last, so the current page remains
+    // the active one until the new page is complete.
+    fn compact(&mut self, entries: Entries) -> Result<(), Error> {
+        let mut records = Vec::new();
+        for (key, value) in entries.iter() {
+            encode_record(&mut records, KIND_SET, key, value);
+        }
+        encode_record(&mut records, KIND_COMMIT, "", &[]);
+        if PAGE_HEADER_SIZE + records.len() > self.region.page_size {
+            return Err(code::ENOSPC);
+        }
+        let next = (self.page + 1) % self.region.pages;
+        self.dev
+            .erase(self.page_offset(next), self.region.page_size as u64)?;
+        self.write_all(self.page_offset(next) + PAGE_HEADER_SIZE as u64, &records)?;
+        let _ = self.dev.sync();
+        let seq = self.seq.wrapping_add(1);
+        self.write_all(self.page_offset(next), PageHeader::new(seq).as_bytes())?;
+        let _ = self.dev.sync();
+        self.page = next;
+        self.seq = seq;
+        self.pos = PAGE_HEADER_SIZE + records.len();
+        self.entries = entries;
+        Ok(())
+    }
+
+    fn commit(&mut self, ops: Vec<Op>) -> Result<(), Error> {
+        let records = encode_transaction(&ops);
+        if self.pos + records.len() > self.region.page_size {
+            let mut entries = self.entries.clone();
+            apply(&mut entries, ops);
+            return self.compact(entries);
+        }
+        self.write_all(self.page_offset(self.page) + self.pos as u64, &records)?;
+        let _ = self.dev.sync();
+        self.pos += records.len();
+        apply(&mut self.entries, ops);
+        Ok(())
+    }
+}
+
+// Held across the I/O of the store.
+static STORE: RwSleepLock<Option<Store>> = RwSleepLock::new(None);
+
+fn check_key(key: &str) -> Result<(), Error> {
+    if key.is_empty() || key.contains(['/', '\0']) || key == "." || key == ".." {
+        return Err(code::EINVAL);
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(code::ENAMETOOLONG);
+    }
+    Ok(())
+}
+
+/// Opens the store in `region`, formatting it if it holds no valid page.
+pub fn init(region: Region) -> Result<(), Error> {
+    let dev = DeviceManager::get()
+        .get_block_device(&region.device)
+        .ok_or(code::ENODEV)?;
+    let store = Store::open(dev, region)?;
+    *STORE.write() = Some(store);
+    Ok(())
+}
+
+pub fn is_ready() -> bool {
+    STORE.read().is_some()
+}
+
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    STORE.read().as_ref()?.entries.get(key).cloned()
+}
+
+pub fn contains(key: &str) -> bool {
+    STORE
+        .read()
+        .as_ref()
+        .is_some_and(|store| store.entries.contains_key(key))
+}
+
+pub fn keys() -> Vec<String> {
+    STORE
+        .read()
+        .as_ref()
+        .map_or_else(Vec::new, |store| store.entries.keys().cloned().collect())
+}
+
+pub fn set(key: &str, value: &[u8]) -> Result<(), Error> {
+    let mut tx = Transaction::new();
+    tx.set(key, value)?;
+    tx.commit()
+}
+
+/// Returns ENOENT if there is no such key.
+pub fn remove(key: &str) -> Result<(), Error> {
+    if !contains(key) {
+        return Err(code::ENOENT);
+    }
+    let mut tx = Transaction::new();
+    tx.remove(key)?;
+    tx.commit()
+}
+
+/// Changes applied all together, or not at all if power is lost before
+/// the commit completes.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    ops: Vec<Op>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<&mut Self, Error> {
+        check_key(key)?;
+        if value.len() > MAX_VALUE_LEN {
+            return Err(code::EOVERFLOW);
+        }
+        self.ops.push((String::from(key), Some(value.to_vec())));
+        Ok(self)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<&mut Self, Error> {
+        check_key(key)?;
+        self.ops.push((String::from(key), None));
+        Ok(self)
+    }
+
+    pub fn commit(self) -> Result<(), Error> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        STORE.write().as_mut().ok_or(code::ENODEV)?.commit(self.ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::ramdisk::RamDisk;
+    use blueos_test_macro::test;
+
+    const PAGE_SIZE: usize = 256;
+
+    fn open(disk: &Arc<RamDisk>) -> Store {
+        let region = Region {
+            device: String::from("ram"),
+            offset: 0,
+            page_size: PAGE_SIZE,
+            pages: 2,
+        };
+        Store::open(disk.clone(), region).unwrap()
+    }
+
+    fn set(key: &str, value: &[u8]) -> Vec<Op> {
+        vec![(String::from(key), Some(value.to_vec()))]
+    }
+
+    #[test]
+    fn test_kvstore_persistence() {
+        let disk = Arc::new(RamDisk::flash(2 * PAGE_SIZE));
+        let mut store = open(&disk);
+        store.commit(set("baud", b"115200")).unwrap();
+        store
+            .commit(vec![
+                (String::from("ip"), Some(b"10.0.0.2".to_vec())),
+                (String::from("baud"), None),
+            ])
+            .unwrap();
+        drop(store);
+
+        let mut store = open(&disk);
+        assert_eq!(store.entries.get("ip").unwrap(), b"10.0.0.2");
+        assert!(!store.entries.contains_key("baud"));
+
+        // A transaction torn before its commit record is dropped.
+        store.commit(set("ip", b"10.0.0.3")).unwrap();
+        let commit_at = store.pos - RECORD_HEADER_SIZE;
+        disk.data()[store.page_offset(store.page) as usize + commit_at] = 0;
+        let page = store.page;
+        drop(store);
+        let store = open(&disk);
+        assert_eq!(store.entries.get("ip").unwrap(), b"10.0.0.2");
+        // The store moved to a clean page.
+        assert_ne!(store.page, page);
+    }
+
+    #[test]
+    fn test_kvstore_compaction() {
+        let disk = Arc::new(RamDisk::flash(2 * PAGE_SIZE));
+        let mut store = open(&disk);
+        for i in 0..32u8 {
+            store.commit(set("counter", &[i; 8])).unwrap();
+        }
+        // Several pages have been filled.
+        assert!(store.seq > 2);
+        drop(store);
+        let mut store = open(&disk);
+        assert_eq!(store.entries.get("counter").unwrap(), &[31; 8]);
+
+        assert_eq!(
+            store.commit(set("big", &[0; PAGE_SIZE])).unwrap_err(),
+            code::ENOSPC
+        );
+    }
+
+    #[test]
+    fn test_kvstore_sequence_wraps() {
+        let disk = Arc::new(RamDisk::flash(2 * PAGE_SIZE));
+        let mut store = open(&disk);
+        store.seq = u32::MAX - 1;
+        for value in [1, 2] {
+            let mut entries = Entries::new();
+            entries.insert(String::from("boot"), vec![value]);
+            store.compact(entries).unwrap();
+        }
+        assert_eq!(store.seq, 0);
+        drop(store);
+        // The page numbered 0 comes after the one numbered u32::MAX.
+        let store = open(&disk);
+        assert_eq!(store.seq, 0);
+        assert_eq!(store.entries.get("boot").unwrap(), &[2]);
+    }
+}
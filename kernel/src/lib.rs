@@ -52,12 +52,23 @@ pub mod ffi {
     #[coverage(off)]
     #[no_mangle]
     pub extern "C" fn disable_local_irq_save() -> usize {
-        crate::arch::disable_local_irq_save()
+        #[cfg(irqsoff_tracer)]
+        let traced = crate::arch::local_irq_enabled();
+        let old = crate::arch::disable_local_irq_save();
+        // The tracer can't see through C callers, so their windows are all
+        // attributed to this function.
+        #[cfg(irqsoff_tracer)]
+        if traced {
+            crate::irqsoff::irqs_off(core::panic::Location::caller(), Some(old));
+        }
+        old
     }
 
     #[coverage(off)]
     #[no_mangle]
     pub extern "C" fn enable_local_irq_restore(val: usize) {
+        #[cfg(irqsoff_tracer)]
+        crate::irqsoff::irqs_on(Some(val));
         crate::arch::enable_local_irq_restore(val)
     }
 
@@ -75,6 +86,7 @@ pub mod ffi {
 
 pub mod allocator;
 pub(crate) mod arch;
+pub mod assert;
 pub mod asynk;
 pub(crate) mod boards;
 pub(crate) mod boot;
@@ -82,21 +94,40 @@ pub(crate) mod config;
 pub(crate) mod console;
 #[cfg(coverage)]
 pub mod coverage;
+pub mod crypto;
 pub(crate) mod devices;
 pub(crate) mod drivers;
 pub mod error;
+pub mod events;
 pub(crate) mod irq;
+#[cfg(irqsoff_tracer)]
+pub mod irqsoff;
+pub mod klog;
+#[cfg(kvstore)]
+pub mod kvstore;
 pub(crate) mod logger;
 pub mod net;
+pub mod panic;
+#[cfg(profiler)]
+pub mod profiler;
 pub mod scheduler;
+#[cfg(boot_script)]
+pub mod script;
+#[cfg(secure_boot)]
+pub mod secure_boot;
+pub mod softirq;
 pub mod support;
 pub mod sync;
 pub mod syscall_handlers;
 pub mod thread;
 pub(crate) mod time;
 pub mod types;
+#[cfg(firmware_update)]
+pub mod update;
 pub mod vfs;
+pub mod workqueue;
 
+pub use panic::handle_panic;
 pub use syscall_handlers as syscalls;
 
 #[macro_export]
@@ -326,7 +357,7 @@ mod tests {
     static SEMA: sync::semaphore::Semaphore = sync::semaphore::Semaphore::new(1);
 
     extern "C" fn test_semaphore() {
-        SEMA.acquire_notimeout();
+        SEMA.acquire_notimeout().unwrap();
         let n = unsafe { SEMA_COUNTER };
         unsafe { SEMA_COUNTER += 1 };
     }
@@ -341,7 +372,7 @@ mod tests {
         reset_and_queue_test_threads(test_semaphore, Some(test_semaphore_cleanup));
         let l = unsafe { TEST_THREADS.len() };
         loop {
-            SEMA.acquire_notimeout();
+            SEMA.acquire_notimeout().unwrap();
             let n = unsafe { SEMA_COUNTER };
             if n == l {
                 SEMA.release();
@@ -12,13 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{
-    arch, kprintln, scheduler, sync::SpinLock, thread::Thread, time::tick_get_millisecond,
-};
+use crate::{arch, devices::console, klog, scheduler, time::tick_get_millisecond};
 use log::{LevelFilter, Metadata, Record};
 
-static LOGGER_MUTEX: SpinLock<()> = SpinLock::new(());
-
 struct Logger;
 
 pub enum LogLevel {
@@ -60,18 +56,24 @@ impl log::Log for Logger {
         if !self.enabled(record.metadata()) {
             return;
         }
-        let timestamp = tick_get_millisecond();
-        let tid = scheduler::current_thread_id();
-        let cpu = arch::current_cpu_id();
-        let _guard = LOGGER_MUTEX.irqsave_lock();
-        kprintln!(
-            "[T:{:09} C:{} TH:0x{:x}][{}] {} ",
-            timestamp,
-            cpu,
-            tid,
-            record.level(),
-            record.args()
+        // Written out to the console from the log, in one piece.
+        klog::record(
+            klog::level_of(record.level()),
+            format_args!(
+                "[T:{:09} C:{} TH:0x{:x}][{}] {}",
+                tick_get_millisecond(),
+                arch::current_cpu_id(),
+                scheduler::current_thread_id(),
+                record.level(),
+                record.args()
+            ),
         );
+        // Telemetry allocates, it's skipped in interrupt context.
+        #[cfg(telemetry)]
+        if !crate::irq::is_in_irq() {
+            crate::net::telemetry::publish_log(record);
+        }
+        console::flush_log();
     }
 
     fn flush(&self) {}
@@ -16,7 +16,7 @@ use crate::{
     error::{code, Error},
     net::{
         connection_err::ConnectionError,
-        net_manager::NetworkManager,
+        net_manager::{self, NetworkManager},
         port_generator::PORT_GENERATOR,
         socket::{
             socket_err::SocketError, FnRecv, FnRecvWithEndpoint, FnSend, FnSendMsg, PosixSocket,
@@ -395,8 +395,8 @@ impl Connection {
     }
 
     pub fn handle_socket_msg(network_manager: Rc<RefCell<NetworkManager<'static>>>) -> bool {
-        // one msg at a time , TODO batch
-        if let Some(socket_request) = NETSTACK_QUEUE.dequeue() {
+        // Handle everything queued since the last poll
+        while let Some(socket_request) = NETSTACK_QUEUE.dequeue() {
             match socket_request {
                 Operation::Create {
                     socket_fd,
@@ -762,6 +762,7 @@ impl OperationIPCReply {
 
             ConnectionError::NetStackQueueFull
         })?;
+        net_manager::wake_net_stack();
 
         self.queue_and_wait_timeout(IPC_REPLY_TIMEOUT)
     }
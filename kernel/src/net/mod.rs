@@ -16,9 +16,12 @@ pub(crate) mod connection;
 pub(crate) mod connection_err;
 pub(crate) mod net_interface;
 pub(crate) mod net_manager;
+pub(crate) mod nic_device;
 pub(crate) mod port_generator;
 pub(crate) mod socket;
 pub mod syscalls;
+#[cfg(telemetry)]
+pub mod telemetry;
 
 use core::{
     net::{Ipv4Addr, SocketAddr},
@@ -346,7 +349,7 @@ impl From<Duration> for Timeval {
     fn from(duration: Duration) -> Timeval {
         let sec = duration.as_secs() as libc::time_t;
         let usec = duration.subsec_micros() as libc::suseconds_t;
-        debug_assert!(usec >= 0); // usec >= 0 always holds
+        crate::kassert!(usec >= 0); // usec >= 0 always holds
         Timeval {
             tv_sec: sec,
             tv_usec: usec,
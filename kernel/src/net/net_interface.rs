@@ -20,22 +20,18 @@ use core::{
 use alloc::{rc::Rc, string::String};
 use smoltcp::{
     iface::{Interface, PollResult, SocketHandle, SocketSet},
-    phy::Loopback,
     socket::AnySocket,
     time::{Duration, Instant},
     wire::IpAddress,
 };
 
-#[cfg(virtio)]
-use crate::devices::net::virtio_net_device::VirtIONetDevice;
+use crate::net::nic_device::NicDevice;
 
 // Use enum to keep all device in a vec or array
 // Why not use trait ?
 //      we have method using generic T like `get_socket_mut<T>` which is not allow in trait
 pub enum NetDevice {
-    Loopback(Loopback),
-    #[cfg(virtio)]
-    VirtioNetDevice(VirtIONetDevice),
+    Nic(NicDevice),
 }
 
 pub struct NetInterface<'a> {
@@ -91,22 +87,26 @@ impl<'a> NetInterface<'a> {
             .any(|cidr| cidr.contains_addr(&remote_addr))
     }
 
+    pub fn is_loopback(&self) -> bool {
+        match &*self.smoltcp_device.borrow() {
+            NetDevice::Nic(nic) => nic.is_loopback(),
+        }
+    }
+
+    /// Whether the device has received frames not polled yet.
+    pub fn can_receive(&self) -> bool {
+        match &*self.smoltcp_device.borrow() {
+            NetDevice::Nic(nic) => nic.can_receive(),
+        }
+    }
+
     pub fn poll(&mut self, timestamp: Instant) -> PollResult {
         match &mut *self.smoltcp_device.borrow_mut() {
-            NetDevice::Loopback(loopback) => self.smoltcp_interface.borrow_mut().poll(
+            NetDevice::Nic(nic) => self.smoltcp_interface.borrow_mut().poll(
                 timestamp,
-                loopback,
+                nic,
                 &mut self.smoltcp_socket_sets.borrow_mut(),
             ),
-
-            #[cfg(virtio)]
-            NetDevice::VirtioNetDevice(virt_ionet_device) => {
-                self.smoltcp_interface.borrow_mut().poll(
-                    timestamp,
-                    virt_ionet_device,
-                    &mut self.smoltcp_socket_sets.borrow_mut(),
-                )
-            }
         }
     }
 }
@@ -127,9 +127,10 @@ mod tests {
     // thread ,un comment this test case
     // #[test]
     fn test_net_interface_create() {
-        let net_interface = NetInterface::create_loopback_interface();
+        let lo = crate::devices::net::loopback::lo().unwrap();
+        let net_interface = NetInterface::create_nic_interface(lo);
 
-        assert!(net_interface.name.contains("Loopback"));
+        assert_eq!(net_interface.name, "lo");
         assert!(net_interface.contains_addr(IpAddress::v4(127, 0, 0, 1)));
     }
 }
@@ -12,11 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(virtio)]
-use crate::devices::net::virtio_net_device::net_dev_exist;
 use crate::{
     allocator,
     config::MAX_THREAD_PRIORITY,
+    devices::net::nics,
     net::{
         connection::Connection,
         net_interface::NetInterface,
@@ -24,6 +23,7 @@ use crate::{
         SocketDomain, SocketFd, SocketProtocol, SocketType,
     },
     scheduler,
+    sync::atomic_wait::{atomic_wait, atomic_wake},
     thread::{self, Builder as ThreadBuilder, Entry, Stack, SystemThreadStorage, ThreadNode},
     time::{tick_from_millisecond, tick_get_millisecond},
 };
@@ -35,7 +35,12 @@ use alloc::{
     vec::Vec,
 };
 use blueos_kconfig::NETWORK_STACK_SIZE;
-use core::{cell::RefCell, mem::MaybeUninit, time};
+use core::{
+    cell::RefCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+    time,
+};
 use smoltcp::{
     time::{Duration, Instant},
     wire::{IpAddress, IpEndpoint},
@@ -43,6 +48,16 @@ use smoltcp::{
 
 const DEFAULT_DELAY_TIME_IN_MILLIS: u64 = 100;
 
+// Bumped to wake the stack thread before its next poll is due.
+static NET_STACK_WAKER: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes the stack thread poll now, because a socket operation has been
+/// queued or a card has received frames. Can be called from interrupts.
+pub(crate) fn wake_net_stack() {
+    NET_STACK_WAKER.fetch_add(1, Ordering::Release);
+    let _ = atomic_wake(&NET_STACK_WAKER, 1);
+}
+
 pub struct NetworkManager<'a> {
     net_interfaces: Vec<Rc<RefCell<NetInterface<'a>>>>,
     socket_maps: BTreeMap<SocketFd, Rc<RefCell<dyn PosixSocket>>>,
@@ -63,29 +78,25 @@ where
     fn new() -> Self {
         let mut net_interfaces = Vec::new();
         let socket_maps = BTreeMap::new();
-        let mut default_interface = None;
-
-        // Add Loopback interface which always exist
-        let dev = NetInterface::create_loopback_interface();
-        let rc = Rc::new(RefCell::new(dev));
-        log::debug!("Add NetDevice : Loopback");
-
-        net_interfaces.push(rc.clone());
-        // Set loopback as default net interface
-        default_interface.replace(rc);
 
-        // Add other interfaces which may not exist
-        #[cfg(virtio)]
-        if net_dev_exist() {
-            let dev = NetInterface::create_virtio_device();
-            let rc = Rc::new(RefCell::new(dev));
-            net_interfaces.push(rc.clone());
-
-            // Using net interface other than loopback as default interface, later we need to setup default interface by net dev api
-            default_interface.replace(rc);
-            log::debug!("Add NetDevice : virtio-net");
+        // Add the network cards found at boot, lo included
+        for nic in nics() {
+            log::debug!("Add NetDevice : {}", nic.name());
+            if nic.bind(|_| wake_net_stack()).is_err() {
+                log::warn!("{} is bound to another stack", nic.name());
+                continue;
+            }
+            let dev = NetInterface::create_nic_interface(nic);
+            net_interfaces.push(Rc::new(RefCell::new(dev)));
         }
 
+        // Using the first card other than loopback as default interface, later we need to setup default interface by net dev api
+        let default_interface = net_interfaces
+            .iter()
+            .find(|interface| !interface.borrow().is_loopback())
+            .or(net_interfaces.first())
+            .cloned();
+
         Self {
             net_interfaces,
             socket_maps,
@@ -198,6 +209,9 @@ where
 
         // Loop for request finish
         while is_forever || tick_get_millisecond() < timeout {
+            // Wakeups from now on cut the next sleep short
+            let waker = NET_STACK_WAKER.load(Ordering::Acquire);
+
             // Step1 : poll smoltcp network stack
             {
                 let network_manager = network_manager.borrow();
@@ -232,6 +246,10 @@ where
                     .net_interfaces
                     .iter()
                     .map(|interface| {
+                        // Frames left by the last poll, like those sent on lo
+                        if interface.borrow().can_receive() {
+                            return 0;
+                        }
                         let Ok(millis_i64) = i64::try_from(tick_get_millisecond()) else {
                             log::error!("[NetworkManager]: Interface poll_delay get ms fail");
                             return DEFAULT_DELAY_TIME_IN_MILLIS;
@@ -252,8 +270,8 @@ where
                                 delay.millis()
                             }
                             None => {
-                                // Wait until there is a task before the next poll
-                                // TODO add trigger when enqueue task
+                                // Wait until there is a task before the next poll, cards
+                                // without interrupt are still polled now and then
                                 DEFAULT_DELAY_TIME_IN_MILLIS
                             }
                         }
@@ -262,12 +280,18 @@ where
                     .unwrap_or(DEFAULT_DELAY_TIME_IN_MILLIS);
 
                 // Warning!!! Need to yield or sleep for a while , or other threads may have no change to insert msg to NETSTACK_QUEUE
+                // The systick ends the sleep when smoltcp's next timer is due, socket
+                // operations and received frames end it earlier
                 if sleep_time == 0 {
                     scheduler::yield_me();
                 } else {
-                    scheduler::suspend_me_for(tick_from_millisecond(
-                        sleep_time.min(DEFAULT_DELAY_TIME_IN_MILLIS) as usize,
-                    ));
+                    let _ = atomic_wait(
+                        &NET_STACK_WAKER,
+                        waker,
+                        Some(tick_from_millisecond(
+                            sleep_time.min(DEFAULT_DELAY_TIME_IN_MILLIS) as usize,
+                        )),
+                    );
                 }
             }
         }
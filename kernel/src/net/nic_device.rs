@@ -0,0 +1,183 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Binds smoltcp to the network cards of [`devices::net`]. The loopback
+//! card gets the loopback addresses, other cards those of QEMU user
+//! networking.
+
+use core::cell::RefCell;
+
+use crate::{
+    devices::net::{Nic, ETHERNET_HEADER_SIZE},
+    net::net_interface::{NetDevice, NetInterface},
+    time::tick_get_millisecond,
+};
+use alloc::{rc::Rc, sync::Arc, vec, vec::Vec};
+use smoltcp::{
+    iface::{Config, Interface, SocketSet},
+    phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken},
+    time::Instant,
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address},
+};
+
+/// A network card as a smoltcp device.
+pub struct NicDevice {
+    nic: Arc<Nic>,
+}
+
+impl NetInterface<'_> {
+    pub fn create_nic_interface(nic: Arc<Nic>) -> Self {
+        let name = nic.name().into();
+        let mut inner = NicDevice { nic };
+        let socket_set = SocketSet::new(vec![]);
+        let config = Config::new(EthernetAddress(inner.nic.mac_address()).into());
+        let mut interface = Interface::new(
+            config,
+            &mut inner,
+            Instant::from_millis(i64::try_from(tick_get_millisecond()).unwrap_or(0)),
+        );
+
+        if inner.nic.is_loopback() {
+            configure_loopback(&mut interface, inner.nic.name());
+        } else {
+            configure_guest_network(&mut interface, inner.nic.name());
+        }
+
+        let device = Rc::new(RefCell::new(NetDevice::Nic(inner)));
+        let interface = Rc::new(RefCell::new(interface));
+        let socket_set = Rc::new(RefCell::new(socket_set));
+        NetInterface::new(name, device, interface, socket_set)
+    }
+}
+
+fn configure_loopback(interface: &mut Interface, name: &str) {
+    interface.update_ip_addrs(|ip_addrs| {
+        if ip_addrs
+            .push(IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8))
+            .is_err()
+        {
+            log::error!("Add ip v4 addrs to {} fail", name);
+        }
+        if ip_addrs
+            .push(IpCidr::new(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1), 128))
+            .is_err()
+        {
+            log::error!("Add ip v6 addrs to {} fail", name);
+        }
+    });
+}
+
+// Static guest IP and gateway of QEMU user networking.
+fn configure_guest_network(interface: &mut Interface, name: &str) {
+    interface.update_ip_addrs(|ip_addrs| {
+        // TODO config static ip by kconfig
+        if ip_addrs
+            .push(IpCidr::new(IpAddress::v4(10, 0, 2, 15), 24))
+            .is_err()
+        {
+            log::error!("Add ip addrs to {} fail", name);
+        }
+    });
+
+    // In QEMU user networking, the ipv4 gateway is 10.0.2.2
+    if interface
+        .routes_mut()
+        .add_default_ipv4_route(Ipv4Address::new(10, 0, 2, 2))
+        .is_err()
+    {
+        log::error!("Add default ipv4 route to {} fail", name);
+    }
+}
+
+impl NicDevice {
+    pub fn is_loopback(&self) -> bool {
+        self.nic.is_loopback()
+    }
+
+    /// Whether frames are waiting to be received.
+    pub fn can_receive(&self) -> bool {
+        self.nic.can_receive()
+    }
+}
+
+impl Device for NicDevice {
+    type RxToken<'a>
+        = NicRxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = NicTxToken
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        if !self.nic.can_receive() {
+            return None;
+        }
+        let mut frame = vec![0; ETHERNET_HEADER_SIZE + self.nic.mtu()];
+        let len = self.nic.receive(&mut frame).ok()?;
+        frame.truncate(len);
+        Some((
+            NicRxToken { frame },
+            NicTxToken {
+                nic: self.nic.clone(),
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.nic.can_transmit().then(|| NicTxToken {
+            nic: self.nic.clone(),
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = ETHERNET_HEADER_SIZE + self.nic.mtu();
+        caps.max_burst_size = Some(1);
+        caps.medium = Medium::Ethernet;
+        caps
+    }
+}
+
+pub struct NicRxToken {
+    frame: Vec<u8>,
+}
+
+impl RxToken for NicRxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.frame)
+    }
+}
+
+pub struct NicTxToken {
+    nic: Arc<Nic>,
+}
+
+impl TxToken for NicTxToken {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame);
+        if let Err(e) = self.nic.transmit(&frame) {
+            crate::kwarn_ratelimited!("{}: frame not sent: {:?}", self.nic.name(), e);
+        }
+        result
+    }
+}
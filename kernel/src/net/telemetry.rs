@@ -0,0 +1,507 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Telemetry publisher.
+//!
+//! Log, trace and health records are queued and pushed by a background
+//! thread to a collector, as CoAP non-confirmable POSTs over UDP (RFC 7252)
+//! or over TCP (RFC 8323). Each kind of record goes to its own path,
+//! `/log`, `/trace` or `/health`, with a plain text payload.
+//!
+//! Publishing never blocks the caller: when the queue is full the oldest
+//! record is dropped, and records which can't be sent are lost. The queue
+//! is allocated when the publisher starts, and log records are formatted
+//! into the record itself, so that publishing doesn't allocate.
+
+use crate::{
+    allocator,
+    error::{code, Error},
+    net::{self, SocketAddressV4, SocketAddressV6},
+    scheduler,
+    sync::SpinLock,
+    thread, time, vfs,
+};
+use alloc::{collections::VecDeque, format, string::String, vec::Vec};
+use blueos_kconfig::{TELEMETRY_HEALTH_PERIOD, TELEMETRY_INTERVAL_MS, TELEMETRY_QUEUE_SIZE};
+use core::{
+    ffi::{c_int, c_void},
+    fmt::{self, Write},
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering},
+};
+use log::LevelFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+// Longest text of a log record, the rest is cut.
+const LOG_TEXT_SIZE: usize = 128;
+
+// The text of a log record, cut at a character boundary.
+#[derive(Debug)]
+struct LogText {
+    buf: [u8; LOG_TEXT_SIZE],
+    len: usize,
+}
+
+impl LogText {
+    fn as_str(&self) -> &str {
+        // Only whole characters are written.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl Write for LogText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(LOG_TEXT_SIZE - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Record {
+    Log {
+        ms: usize,
+        level: log::Level,
+        text: LogText,
+    },
+    Trace {
+        ms: usize,
+        event: &'static str,
+        value: u64,
+    },
+    Health {
+        ms: usize,
+        heap_used: usize,
+        heap_total: usize,
+        threads: usize,
+    },
+}
+
+impl Record {
+    fn path(&self) -> &'static str {
+        match self {
+            Record::Log { .. } => "log",
+            Record::Trace { .. } => "trace",
+            Record::Health { .. } => "health",
+        }
+    }
+
+    fn payload(&self) -> String {
+        match self {
+            Record::Log { ms, level, text } => format!("{} {} {}", ms, level, text.as_str()),
+            Record::Trace { ms, event, value } => format!("{} {} {}", ms, event, value),
+            Record::Health {
+                ms,
+                heap_used,
+                heap_total,
+                threads,
+            } => format!(
+                "{} heap={}/{} threads={}",
+                ms, heap_used, heap_total, threads
+            ),
+        }
+    }
+}
+
+const COAP_VERSION: u8 = 1;
+const COAP_TYPE_NON: u8 = 1;
+const COAP_POST: u8 = 0x02;
+const COAP_OPTION_URI_PATH: u16 = 11;
+const COAP_OPTION_CONTENT_FORMAT: u16 = 12;
+const COAP_PAYLOAD_MARKER: u8 = 0xff;
+
+// Option deltas and lengths, as well as the RFC 8323 message length, use
+// the same extended encoding.
+fn coap_nibble(value: usize) -> (u8, Vec<u8>) {
+    match value {
+        0..13 => (value as u8, Vec::new()),
+        13..269 => (13, [(value - 13) as u8].into()),
+        269..65805 => (14, ((value - 269) as u16).to_be_bytes().into()),
+        _ => (15, ((value - 65805) as u32).to_be_bytes().into()),
+    }
+}
+
+// Options and payload, which are shared by both transports.
+fn coap_body(path: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(path.len() + payload.len() + 4);
+    let mut last = 0;
+    let mut option = |body: &mut Vec<u8>, number: u16, value: &[u8]| {
+        let (delta, delta_ext) = coap_nibble((number - last) as usize);
+        let (len, len_ext) = coap_nibble(value.len());
+        body.push((delta << 4) | len);
+        body.extend_from_slice(&delta_ext);
+        body.extend_from_slice(&len_ext);
+        body.extend_from_slice(value);
+        last = number;
+    };
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        option(&mut body, COAP_OPTION_URI_PATH, segment.as_bytes());
+    }
+    // text/plain, which is encoded as an empty value.
+    option(&mut body, COAP_OPTION_CONTENT_FORMAT, &[]);
+    if !payload.is_empty() {
+        body.push(COAP_PAYLOAD_MARKER);
+        body.extend_from_slice(payload);
+    }
+    body
+}
+
+fn coap_udp_message(message_id: u16, path: &str, payload: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::from([(COAP_VERSION << 6) | (COAP_TYPE_NON << 4), COAP_POST]);
+    msg.extend_from_slice(&message_id.to_be_bytes());
+    msg.extend(coap_body(path, payload));
+    msg
+}
+
+fn coap_tcp_message(path: &str, payload: &[u8]) -> Vec<u8> {
+    let body = coap_body(path, payload);
+    let (len, len_ext) = coap_nibble(body.len());
+    let mut msg = Vec::from([len << 4]);
+    msg.extend(len_ext);
+    msg.push(COAP_POST);
+    msg.extend(body);
+    msg
+}
+
+struct Collector {
+    addr: SocketAddr,
+    transport: Transport,
+}
+
+static COLLECTOR: SpinLock<Option<Collector>> = SpinLock::new(None);
+static QUEUE: SpinLock<VecDeque<Record>> = SpinLock::new(VecDeque::new());
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static PUBLISHER: AtomicUsize = AtomicUsize::new(0);
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Warn as usize);
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static MESSAGE_ID: AtomicU16 = AtomicU16::new(0);
+
+// The queue has room for TELEMETRY_QUEUE_SIZE records while running, so
+// this never allocates.
+fn enqueue(record: Record) {
+    let mut queue = QUEUE.irqsave_lock();
+    if queue.len() >= TELEMETRY_QUEUE_SIZE {
+        queue.pop_front();
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    queue.push_back(record);
+}
+
+/// Sets the most verbose level of log records which get published.
+pub fn set_log_level(level: LevelFilter) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Queues a log record, if the publisher is running and the record is at
+/// least as severe as the level set with [`set_log_level`].
+pub fn publish_log(record: &log::Record) {
+    if !RUNNING.load(Ordering::Relaxed)
+        || record.level() as usize > LOG_LEVEL.load(Ordering::Relaxed)
+        // The socket layer logs too, which would feed back into the queue.
+        || scheduler::current_thread_id() == PUBLISHER.load(Ordering::Relaxed)
+    {
+        return;
+    }
+    let mut text = LogText {
+        buf: [0; LOG_TEXT_SIZE],
+        len: 0,
+    };
+    let _ = text.write_fmt(*record.args());
+    enqueue(Record::Log {
+        ms: time::tick_get_millisecond(),
+        level: record.level(),
+        text,
+    });
+}
+
+/// Queues a trace event with a value, e.g. a counter or a latency.
+pub fn trace(event: &'static str, value: u64) {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return;
+    }
+    enqueue(Record::Trace {
+        ms: time::tick_get_millisecond(),
+        event,
+        value,
+    });
+}
+
+/// Queues a snapshot of the heap usage and of the number of threads.
+pub fn publish_health() {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return;
+    }
+    let heap = allocator::memory_info();
+    let mut threads = 0;
+    let mut it = thread::GlobalQueueVisitor::new();
+    while it.next().is_some() {
+        threads += 1;
+    }
+    drop(it);
+    enqueue(Record::Health {
+        ms: time::tick_get_millisecond(),
+        heap_used: heap.used,
+        heap_total: heap.total,
+        threads,
+    });
+}
+
+/// Returns the number of records dropped, because the queue was full or
+/// because they couldn't be sent.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+// Holds the socket used to reach the collector, and the address it was
+// opened for so that a new collector gets a new socket.
+struct Channel {
+    fd: c_int,
+    addr: SocketAddr,
+    transport: Transport,
+}
+
+impl Channel {
+    fn open(addr: SocketAddr, transport: Transport) -> Option<Self> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let type_ = match transport {
+            Transport::Udp => libc::SOCK_DGRAM,
+            Transport::Tcp => libc::SOCK_STREAM,
+        };
+        let fd = net::syscalls::socket(domain, type_, 0);
+        if fd < 0 {
+            return None;
+        }
+        let channel = Self {
+            fd,
+            addr,
+            transport,
+        };
+        if transport == Transport::Tcp
+            && with_sockaddr(&addr, |sa, len| net::syscalls::connect(fd, sa, len)) != 0
+        {
+            return None;
+        }
+        Some(channel)
+    }
+
+    fn send(&self, msg: &[u8]) -> bool {
+        let buf = msg.as_ptr() as *const c_void;
+        let sent = match self.transport {
+            Transport::Udp => with_sockaddr(&self.addr, |sa, len| {
+                net::syscalls::sendto(self.fd, buf, msg.len(), 0, sa, len)
+            }),
+            Transport::Tcp => net::syscalls::send(self.fd, buf, msg.len(), 0),
+        };
+        sent == msg.len() as isize
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        vfs::syscalls::close(self.fd);
+    }
+}
+
+fn with_sockaddr<R>(
+    addr: &SocketAddr,
+    f: impl FnOnce(*const libc::sockaddr, libc::socklen_t) -> R,
+) -> R {
+    match addr {
+        SocketAddr::V4(v4) => {
+            let sa = SocketAddressV4 {
+                sin_len: core::mem::size_of::<SocketAddressV4>() as u8,
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: v4.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(v4.ip().octets()),
+                },
+                sin_vport: 0,
+                sin_zero: [0; 6],
+            };
+            f(
+                &sa as *const _ as *const libc::sockaddr,
+                core::mem::size_of::<SocketAddressV4>() as libc::socklen_t,
+            )
+        }
+        SocketAddr::V6(v6) => {
+            let sa = SocketAddressV6 {
+                sin6_len: core::mem::size_of::<SocketAddressV6>() as u8,
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: v6.port().to_be(),
+                sin6_flowinfo: v6.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: v6.ip().octets(),
+                },
+                sin6_vport: 0,
+                sin6_scope_id: v6.scope_id(),
+            };
+            f(
+                &sa as *const _ as *const libc::sockaddr,
+                core::mem::size_of::<SocketAddressV6>() as libc::socklen_t,
+            )
+        }
+    }
+}
+
+fn encode(record: &Record, transport: Transport) -> Vec<u8> {
+    let payload = record.payload();
+    match transport {
+        Transport::Udp => {
+            let id = MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+            coap_udp_message(id, record.path(), payload.as_bytes())
+        }
+        Transport::Tcp => coap_tcp_message(record.path(), payload.as_bytes()),
+    }
+}
+
+// Sends the queued records, swapping the queue with `spare`, which is left
+// empty.
+fn flush(channel: &mut Option<Channel>, spare: &mut VecDeque<Record>) {
+    let target = COLLECTOR
+        .irqsave_lock()
+        .as_ref()
+        .map(|c| (c.addr, c.transport));
+    let Some((addr, transport)) = target else {
+        return;
+    };
+    if channel
+        .as_ref()
+        .is_some_and(|c| c.addr != addr || c.transport != transport)
+    {
+        *channel = None;
+    }
+    if channel.is_none() {
+        *channel = Channel::open(addr, transport);
+    }
+    // Take the whole queue at once, so that producers aren't held up while
+    // sending. The spare has as much room, so that they don't allocate.
+    core::mem::swap(&mut *QUEUE.irqsave_lock(), spare);
+    let sent = match channel.as_ref() {
+        Some(c) => spare
+            .iter()
+            .take_while(|record| c.send(&encode(record, transport)))
+            .count(),
+        None => 0,
+    };
+    if sent < spare.len() {
+        // A broken TCP connection is reopened on the next flush.
+        *channel = None;
+        DROPPED.fetch_add(spare.len() - sent, Ordering::Relaxed);
+    }
+    spare.clear();
+}
+
+fn publisher() {
+    let me = scheduler::current_thread_id();
+    // A publisher from before a quick stop and start notices it has been
+    // replaced and exits.
+    PUBLISHER.store(me, Ordering::Relaxed);
+    let ticks = time::tick_from_millisecond(TELEMETRY_INTERVAL_MS);
+    let mut channel = None;
+    let mut spare = VecDeque::with_capacity(TELEMETRY_QUEUE_SIZE);
+    let mut rounds = 0;
+    while RUNNING.load(Ordering::Relaxed) && PUBLISHER.load(Ordering::Relaxed) == me {
+        if TELEMETRY_HEALTH_PERIOD != 0 && rounds % TELEMETRY_HEALTH_PERIOD == 0 {
+            publish_health();
+        }
+        rounds += 1;
+        flush(&mut channel, &mut spare);
+        scheduler::suspend_me_for(ticks);
+    }
+    let _ = PUBLISHER.compare_exchange(me, 0, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// Starts publishing to `addr`. If the publisher is already running, only
+/// the collector is changed.
+pub fn start(addr: SocketAddr, transport: Transport) -> Result<(), Error> {
+    if addr.port() == 0 {
+        return Err(code::EINVAL);
+    }
+    *COLLECTOR.irqsave_lock() = Some(Collector { addr, transport });
+    // Make room in the queue before records are published to it. A queue
+    // left by a previous run already has it.
+    let mut queue = VecDeque::with_capacity(TELEMETRY_QUEUE_SIZE);
+    {
+        let mut current = QUEUE.irqsave_lock();
+        if current.capacity() < TELEMETRY_QUEUE_SIZE {
+            core::mem::swap(&mut *current, &mut queue);
+        }
+    }
+    drop(queue);
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        return Ok(());
+    }
+    if thread::spawn(publisher).is_none() {
+        RUNNING.store(false, Ordering::Release);
+        return Err(code::ENOMEM);
+    }
+    Ok(())
+}
+
+/// Stops publishing. Records still queued are discarded.
+pub fn stop() {
+    RUNNING.store(false, Ordering::Release);
+    *COLLECTOR.irqsave_lock() = None;
+    QUEUE.irqsave_lock().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_coap_encoding() {
+        let udp = coap_udp_message(0x1234, "log", b"hi");
+        assert_eq!(
+            udp,
+            [0x50, 0x02, 0x12, 0x34, 0xb3, b'l', b'o', b'g', 0x10, 0xff, b'h', b'i']
+        );
+
+        let tcp = coap_tcp_message("health", b"");
+        assert_eq!(
+            tcp,
+            [0x80, 0x02, 0xb6, b'h', b'e', b'a', b'l', b't', b'h', 0x10]
+        );
+
+        // Long segments need an extended length.
+        let path = "a".repeat(20);
+        let body = coap_body(&path, b"");
+        assert_eq!(&body[..2], &[0xbd, 20 - 13]);
+        assert_eq!(coap_nibble(300), (14, Vec::from([0, 31])));
+    }
+
+    #[test]
+    fn test_log_text() {
+        let mut text = LogText {
+            buf: [0; LOG_TEXT_SIZE],
+            len: 0,
+        };
+        let _ = write!(text, "{}{}", "a".repeat(LOG_TEXT_SIZE - 1), "é");
+        // The two bytes of the last character don't fit.
+        assert_eq!(text.as_str(), "a".repeat(LOG_TEXT_SIZE - 1));
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What the kernel does once it panics. The policy is picked by the
+//! PANIC_* kconfig choice and can be changed at runtime with [`set_policy`]:
+//!
+//! - [`PanicPolicy::Halt`] stops the system where it is, for a debugger.
+//! - [`PanicPolicy::Continue`] retires the panicking thread, the shell and
+//!   the other threads keep running. Panics in interrupt context, in kernel
+//!   threads or with preemption disabled halt instead, the state they left
+//!   behind can't be trusted.
+//! - [`PanicPolicy::Reboot`] resets the system.
+//!
+//! Whatever the policy, the message is printed on the early console and a
+//! short crash dump is handed to the board. Boards register [`PanicHooks`]
+//! to light an LED, keep the dump somewhere that survives a reset, or reboot
+//! through their watchdog.
+
+use crate::{
+    console, devices, irq, scheduler,
+    support::DisableInterruptGuard,
+    sync::KOnce,
+    thread::{Thread, ThreadKind, ThreadName},
+    time,
+};
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+/// Size of the crash dump, longer messages are truncated.
+pub const CRASH_DUMP_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PanicPolicy {
+    Halt = 0,
+    Continue = 1,
+    Reboot = 2,
+}
+
+impl PanicPolicy {
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Continue,
+            2 => Self::Reboot,
+            _ => Self::Halt,
+        }
+    }
+}
+
+/// Board callbacks run on panic, in interrupt-disabled context. They must
+/// not allocate, take locks or panic.
+#[derive(Default)]
+pub struct PanicHooks {
+    /// Signals the panic, e.g. by lighting an LED or raising a GPIO.
+    pub indicate: Option<fn()>,
+    /// Stores the crash dump, e.g. in RAM kept across resets or in flash.
+    pub save_dump: Option<fn(&[u8])>,
+    /// Resets the system, e.g. by letting the watchdog expire. Cortex-M
+    /// boards fall back to a system reset request.
+    pub reboot: Option<fn() -> !>,
+}
+
+const DEFAULT_POLICY: PanicPolicy = if cfg!(panic_reboot) {
+    PanicPolicy::Reboot
+} else if cfg!(panic_continue) {
+    PanicPolicy::Continue
+} else {
+    PanicPolicy::Halt
+};
+
+static POLICY: AtomicU8 = AtomicU8::new(DEFAULT_POLICY as u8);
+static HOOKS: KOnce<PanicHooks> = KOnce::new();
+// Set while a panic is handled, a panic in the meantime halts at once.
+static HANDLING: AtomicBool = AtomicBool::new(false);
+// Only touched by the owner of HANDLING.
+static mut DUMP: CrashDump = CrashDump::new();
+
+pub fn policy() -> PanicPolicy {
+    PanicPolicy::from_u8(POLICY.load(Ordering::Relaxed))
+}
+
+/// Changes what later panics do, returns the previous policy.
+pub fn set_policy(policy: PanicPolicy) -> PanicPolicy {
+    PanicPolicy::from_u8(POLICY.swap(policy as u8, Ordering::Relaxed))
+}
+
+/// Registers the board's panic hooks, only the first call has an effect.
+pub fn register_hooks(hooks: PanicHooks) {
+    HOOKS.call_once(|| hooks);
+}
+
+/// The crash dump, formatted into a fixed buffer so that it doesn't
+/// allocate.
+pub struct CrashDump {
+    buf: [u8; CRASH_DUMP_SIZE],
+    len: usize,
+}
+
+impl CrashDump {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; CRASH_DUMP_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Default for CrashDump {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for CrashDump {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(CRASH_DUMP_SIZE - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+fn thread_name(t: &Thread) -> ThreadName {
+    let name = t.name();
+    if name.is_empty() {
+        ThreadName::new(t.kind_to_str())
+    } else {
+        name
+    }
+}
+
+fn write_dump(dump: &mut CrashDump, info: &PanicInfo) {
+    dump.clear();
+    let _ = writeln!(dump, "panic: {}", info);
+    if scheduler::is_initialized() {
+        let t = scheduler::current_thread();
+        let _ = writeln!(dump, "thread: {} {}", Thread::id(&t), thread_name(&t));
+    }
+    let _ = writeln!(dump, "uptime: {} ms", time::tick_get_millisecond());
+}
+
+// Whether the panicking thread can be retired without taking the system
+// down with it.
+fn can_continue() -> bool {
+    if irq::is_in_irq() || !scheduler::is_initialized() {
+        return false;
+    }
+    let t = scheduler::current_thread();
+    t.kind() == ThreadKind::Normal && t.is_preemptable()
+}
+
+fn halt() -> ! {
+    let _guard = DisableInterruptGuard::new();
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Resets the system through the board hook, or the architecture when it
+/// has a way to. Returns if neither can.
+pub fn reboot() {
+    if let Some(reboot) = HOOKS.get().and_then(|hooks| hooks.reboot) {
+        reboot();
+    }
+    #[cfg(cortex_m)]
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Handles a panic according to the [policy](policy). Called by the
+/// `#[panic_handler]`.
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    let guard = DisableInterruptGuard::new();
+    if HANDLING.swap(true, Ordering::Acquire) {
+        let _ = console::EarlyConsole.write_fmt(format_args!("\nOops in panic: {}\n", info));
+        halt();
+    }
+    console::report_panic(info);
+    let hooks = HOOKS.get();
+    if let Some(indicate) = hooks.and_then(|hooks| hooks.indicate) {
+        indicate();
+    }
+    // SAFETY: HANDLING is owned.
+    let dump = unsafe { &mut *core::ptr::addr_of_mut!(DUMP) };
+    write_dump(dump, info);
+    if let Some(save_dump) = hooks.and_then(|hooks| hooks.save_dump) {
+        save_dump(dump.as_bytes());
+    }
+    match policy() {
+        PanicPolicy::Halt => halt(),
+        PanicPolicy::Reboot => {
+            reboot();
+            let _ = console::EarlyConsole.write_str("Reboot is not supported, halting\n");
+            halt()
+        }
+        PanicPolicy::Continue => {
+            if !can_continue() {
+                let _ = console::EarlyConsole.write_str("Can't retire the thread, halting\n");
+                halt();
+            }
+            let t = scheduler::current_thread();
+            let _ = console::EarlyConsole.write_fmt(format_args!(
+                "Retiring thread {}, the system keeps running\n",
+                Thread::id(&t)
+            ));
+            drop(t);
+            devices::console::leave_panic_mode();
+            HANDLING.store(false, Ordering::Release);
+            drop(guard);
+            scheduler::retire_me()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_panic_policy() {
+        let old = set_policy(PanicPolicy::Reboot);
+        assert_eq!(old, DEFAULT_POLICY);
+        assert_eq!(policy(), PanicPolicy::Reboot);
+        assert_eq!(set_policy(old), PanicPolicy::Reboot);
+        assert_eq!(policy(), old);
+    }
+
+    #[test]
+    fn test_crash_dump() {
+        let mut dump = CrashDump::new();
+        let _ = writeln!(dump, "thread: {}", 7);
+        assert_eq!(dump.as_bytes(), b"thread: 7\n");
+        for _ in 0..CRASH_DUMP_SIZE {
+            let _ = dump.write_str("xy");
+        }
+        assert_eq!(dump.as_bytes().len(), CRASH_DUMP_SIZE);
+        dump.clear();
+        assert!(dump.as_bytes().is_empty());
+    }
+}
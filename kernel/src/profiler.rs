@@ -0,0 +1,279 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sampling profiler.
+//!
+//! While running, each core records on every tick the address and the
+//! thread it interrupted into a ring of its own, the oldest samples being
+//! overwritten once it's full. [`dump`] ranks the threads and addresses
+//! sampled the most, then lists the samples, which /proc/profile shows.
+//!
+//! The profiler is started and stopped with the `p` magic SysRq key, or
+//! the `profile` command of boot scripts, so that a workload can be
+//! profiled on the device from its start.
+//!
+//! A tick which interrupted another interrupt handler on Cortex-M is
+//! recorded at address 0. In tickless mode no tick, and so no sample, is
+//! taken while a core idles.
+
+use crate::{
+    arch, scheduler,
+    support::DisableInterruptGuard,
+    sync::SpinLock,
+    thread::{Thread, ThreadName},
+    time,
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+use blueos_kconfig::{NUM_CORES, PROFILER_SAMPLES, TICKS_PER_SECOND};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Number of threads and of addresses ranked by [`dump`].
+pub const TOP_ENTRIES: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub tick: usize,
+    pub pc: usize,
+    pub tid: usize,
+    pub thread_name: ThreadName,
+}
+
+struct Ring {
+    samples: [Sample; PROFILER_SAMPLES],
+    // Samples taken since the last reset, the latest PROFILER_SAMPLES are
+    // kept.
+    taken: usize,
+}
+
+impl Ring {
+    fn kept(&self) -> &[Sample] {
+        &self.samples[..self.taken.min(PROFILER_SAMPLES)]
+    }
+}
+
+const EMPTY_SAMPLE: Sample = Sample {
+    tick: 0,
+    pc: 0,
+    tid: 0,
+    thread_name: ThreadName::EMPTY,
+};
+
+// The rings are taken with plain `lock` from the tick, which runs with
+// interrupts disabled, and from elsewhere under a `DisableInterruptGuard`.
+static RINGS: [SpinLock<Ring>; NUM_CORES] = [const {
+    SpinLock::new(Ring {
+        samples: [EMPTY_SAMPLE; PROFILER_SAMPLES],
+        taken: 0,
+    })
+}; NUM_CORES];
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Called by the tick handler of each core, with interrupts disabled.
+#[inline]
+pub(crate) fn sample() {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return;
+    }
+    let t = scheduler::current_thread();
+    let sample = Sample {
+        tick: time::get_sys_ticks(),
+        pc: arch::interrupted_pc().unwrap_or(0),
+        tid: Thread::id(&t),
+        thread_name: t.name(),
+    };
+    record(arch::current_cpu_id(), sample);
+}
+
+fn record(cpu: usize, sample: Sample) {
+    let mut ring = RINGS[cpu].lock();
+    let slot = ring.taken % PROFILER_SAMPLES;
+    ring.samples[slot] = sample;
+    ring.taken += 1;
+}
+
+pub(crate) fn init() {
+    #[cfg(magic_sysrq)]
+    let _ = crate::devices::tty::sysrq::register(crate::devices::tty::sysrq::SysrqAction {
+        key: b'p',
+        help: "start or stop the profiler",
+        handler: toggle,
+    });
+    #[cfg(boot_script)]
+    let _ = crate::script::register(crate::script::Command {
+        name: "profile",
+        help: "profile start|stop|reset - control the profiler",
+        handler: profile_command,
+    });
+}
+
+#[cfg(magic_sysrq)]
+fn toggle() {
+    if is_running() {
+        stop();
+    } else {
+        start();
+    }
+    crate::kearly_println!(
+        "Profiler {}",
+        if is_running() { "running" } else { "stopped" }
+    );
+}
+
+#[cfg(boot_script)]
+fn profile_command(args: &[&str]) -> Result<(), crate::error::Error> {
+    match args {
+        ["start"] => start(),
+        ["stop"] => stop(),
+        ["reset"] => reset(),
+        _ => return Err(crate::error::code::EINVAL),
+    }
+    Ok(())
+}
+
+pub fn start() {
+    RUNNING.store(true, Ordering::Relaxed);
+}
+
+pub fn stop() {
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Drops the samples taken so far.
+pub fn reset() {
+    let _dig = DisableInterruptGuard::new();
+    for ring in RINGS.iter() {
+        ring.lock().taken = 0;
+    }
+}
+
+/// Returns the samples kept for `cpu`, oldest first, and the number of
+/// samples taken since the last reset.
+pub fn samples(cpu: usize) -> (Vec<Sample>, usize) {
+    let _dig = DisableInterruptGuard::new();
+    let ring = RINGS[cpu].lock();
+    let kept = ring.kept();
+    let oldest = ring.taken % kept.len().max(1);
+    let mut samples = Vec::with_capacity(kept.len());
+    samples.extend_from_slice(&kept[oldest..]);
+    samples.extend_from_slice(&kept[..oldest]);
+    (samples, ring.taken)
+}
+
+// The `TOP_ENTRIES` keys counted the most, the most counted first.
+fn top<K: Copy + Ord>(counts: BTreeMap<K, usize>) -> Vec<(K, usize)> {
+    let mut counts: Vec<(K, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(TOP_ENTRIES);
+    counts
+}
+
+/// Writes the profile, a record per line, its type first:
+///
+/// ```text
+/// profile <ticks per second> <running>
+/// cpu <cpu> <samples taken> <samples kept>
+/// thread <samples> <tid> <name>
+/// pc <samples> <address>
+/// sample <cpu> <tick> <tid> <address>
+/// ```
+///
+/// Threads and addresses are ranked over the samples kept on all cores,
+/// and the samples listed oldest first for each core. Addresses are
+/// printed in hex, to be resolved against the kernel image.
+pub fn dump(w: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(w, "profile {} {}", TICKS_PER_SECOND, is_running() as u8)?;
+    let per_cpu: Vec<(Vec<Sample>, usize)> = (0..NUM_CORES).map(samples).collect();
+    let mut threads = BTreeMap::new();
+    let mut pcs = BTreeMap::new();
+    for (cpu, (samples, taken)) in per_cpu.iter().enumerate() {
+        writeln!(w, "cpu {} {} {}", cpu, taken, samples.len())?;
+        for sample in samples {
+            *threads.entry((sample.tid, sample.thread_name)).or_insert(0) += 1;
+            *pcs.entry(sample.pc).or_insert(0) += 1;
+        }
+    }
+    for ((tid, name), count) in top(threads) {
+        writeln!(w, "thread {} {} {}", count, tid, name)?;
+    }
+    for (pc, count) in top(pcs) {
+        writeln!(w, "pc {} {:#x}", count, pc)?;
+    }
+    for (cpu, (samples, _)) in per_cpu.iter().enumerate() {
+        for sample in samples {
+            writeln!(
+                w,
+                "sample {} {} {} {:#x}",
+                cpu, sample.tick, sample.tid, sample.pc
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_profiler_dump() {
+        stop();
+        reset();
+        let dig = DisableInterruptGuard::new();
+        for tick in 0..PROFILER_SAMPLES + 2 {
+            let pc = if tick % 2 == 0 { 0x1000 } else { 0x2000 + tick };
+            record(
+                0,
+                Sample {
+                    tick,
+                    pc,
+                    tid: 7,
+                    thread_name: ThreadName::new("hot"),
+                },
+            );
+        }
+        drop(dig);
+        let (samples, taken) = samples(0);
+        assert_eq!(taken, PROFILER_SAMPLES + 2);
+        assert_eq!(samples.len(), PROFILER_SAMPLES);
+        assert_eq!(samples[0].tick, 2);
+        assert_eq!(samples[PROFILER_SAMPLES - 1].tick, PROFILER_SAMPLES + 1);
+
+        let mut out = String::new();
+        dump(&mut out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next(),
+            Some(alloc::format!("profile {} 0", TICKS_PER_SECOND).as_str())
+        );
+        assert!(out.contains(&alloc::format!(
+            "cpu 0 {} {}\n",
+            PROFILER_SAMPLES + 2,
+            PROFILER_SAMPLES
+        )));
+        assert!(out.contains(&alloc::format!("thread {} 7 hot\n", PROFILER_SAMPLES)));
+        let even_ticks = (PROFILER_SAMPLES + 1) / 2;
+        assert!(out.contains(&alloc::format!("pc {} 0x1000\n", even_ticks)));
+        assert!(out.contains("sample 0 2 7 0x1000\n"));
+        reset();
+    }
+}
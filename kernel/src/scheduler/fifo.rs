@@ -13,7 +13,11 @@
 // limitations under the License.
 
 extern crate alloc;
-use crate::{support, thread, thread::ThreadNode, types::Uint};
+use crate::{
+    support, thread,
+    thread::ThreadNode,
+    types::{ThreadPriority, Uint},
+};
 use alloc::collections::LinkedList;
 use core::{cell::LazyCell, ops::DerefMut};
 use spin::Mutex;
@@ -41,3 +45,9 @@ pub fn queue_ready_thread(old_state: Uint, t: ThreadNode) -> bool {
     rq.push_back(t);
     true
 }
+
+/// Priorities don't matter to the FIFO scheduler, so the thread stays where
+/// it is.
+pub fn set_thread_priority(t: &ThreadNode, priority: ThreadPriority) {
+    t.lock().set_priority(priority);
+}
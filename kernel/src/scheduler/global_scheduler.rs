@@ -12,73 +12,301 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Per-CPU ready tables.
+//!
+//! Threads are queued on the table of the core which makes them ready, so
+//! that wakeups and ticks only take the local lock. A core picks the highest
+//! priority thread among all tables, using the per-CPU hints to avoid
+//! locking the remote ones. When the best thread is on a remote table, it
+//! is migrated with both locks held, always taken in ascending CPU order.
+//! Each thread records the table and queue it's on, so that changing its
+//! priority only locks that table.
+
 use crate::{
+    arch,
     config::MAX_THREAD_PRIORITY,
-    sync::spinlock::SpinLock,
+    sync::spinlock::{SpinLock, SpinLockGuard},
     thread,
     thread::{Thread, ThreadNode},
     types::{ArcList, ThreadPriority, Uint},
 };
-use core::mem::MaybeUninit;
+use blueos_kconfig::NUM_CORES;
+use core::{
+    mem::MaybeUninit,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
 
-static mut READY_TABLE: MaybeUninit<SpinLock<ReadyTable>> = MaybeUninit::zeroed();
+static mut READY_TABLES: [MaybeUninit<SpinLock<ReadyTable>>; NUM_CORES] =
+    [const { MaybeUninit::zeroed() }; NUM_CORES];
+static HIGHEST_PRIORITIES: [AtomicU32; NUM_CORES] =
+    [const { AtomicU32::new(NO_PRIORITY) }; NUM_CORES];
+const NO_PRIORITY: u32 = u32::MAX;
 
 type ReadyTableBitFields = u32;
 
-#[allow(clippy::assertions_on_constants)]
+const WORD_BITS: usize = ReadyTableBitFields::BITS as usize;
+const NUM_PRIORITIES: usize = MAX_THREAD_PRIORITY as usize + 1;
+const NUM_WORDS: usize = NUM_PRIORITIES.div_ceil(WORD_BITS);
+
 pub(super) fn init() {
-    assert!(ReadyTableBitFields::BITS >= ThreadPriority::BITS);
-    unsafe { READY_TABLE.write(SpinLock::new(ReadyTable::default())) };
-    let mut w = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
-    for i in 0..(MAX_THREAD_PRIORITY + 1) as usize {
-        w.tables[i].init();
+    const { assert!(NUM_WORDS <= WORD_BITS) };
+    for cpu in 0..NUM_CORES {
+        unsafe { READY_TABLES[cpu].write(SpinLock::new(ReadyTable::default())) };
+    }
+    for table in ready_tables().tables {
+        table.irqsave_lock().init();
+    }
+}
+
+#[inline]
+fn ready_tables() -> ReadyTables<'static> {
+    // SAFETY: The tables are initialized by init, before the scheduler
+    // runs, and MaybeUninit has the layout of what it holds.
+    let tables = unsafe {
+        &*(core::ptr::addr_of!(READY_TABLES) as *const [SpinLock<ReadyTable>; NUM_CORES])
+    };
+    ReadyTables {
+        tables,
+        highest: &HIGHEST_PRIORITIES,
+    }
+}
+
+/// Two-level bitmap of the non-empty ready queues. Finding the highest
+/// priority, i.e. the lowest set bit, takes two CTZ whatever the number of
+/// priorities.
+#[derive(Debug, Default)]
+struct PriorityBitmap {
+    // Bit i is set if words[i] is not 0.
+    groups: ReadyTableBitFields,
+    words: [ReadyTableBitFields; NUM_WORDS],
+}
+
+impl PriorityBitmap {
+    #[inline]
+    fn set(&mut self, priority: u32) {
+        let (word, bit) = Self::position(priority);
+        self.words[word] |= 1 << bit;
+        self.groups |= 1 << word;
+    }
+
+    #[inline]
+    fn clear(&mut self, priority: u32) {
+        let (word, bit) = Self::position(priority);
+        self.words[word] &= !(1 << bit);
+        if self.words[word] == 0 {
+            self.groups &= !(1 << word);
+        }
+    }
+
+    #[inline]
+    fn highest(&self) -> Option<u32> {
+        if self.groups == 0 {
+            return None;
+        }
+        let word = self.groups.trailing_zeros();
+        Some(word * WORD_BITS as u32 + self.words[word as usize].trailing_zeros())
+    }
+
+    #[inline]
+    fn position(priority: u32) -> (usize, usize) {
+        (priority as usize / WORD_BITS, priority as usize % WORD_BITS)
     }
 }
 
 #[derive(Debug, Default)]
 struct ReadyTable {
-    active_tables: ReadyTableBitFields,
-    tables: [ArcList<Thread, thread::OffsetOfSchedNode>; (MAX_THREAD_PRIORITY + 1) as usize],
+    active_tables: PriorityBitmap,
+    tables: [ArcList<Thread, thread::OffsetOfSchedNode>; NUM_PRIORITIES],
 }
 
 impl ReadyTable {
+    // Must be called once the table is at its final address.
+    fn init(&mut self) {
+        for q in self.tables.iter_mut() {
+            q.init();
+        }
+    }
+
     #[inline]
     fn clear_active_queue(&mut self, bit: u32) -> &mut Self {
-        self.active_tables &= !(1 << bit);
+        self.active_tables.clear(bit);
         self
     }
 
     #[inline]
     fn set_active_queue(&mut self, bit: u32) -> &mut Self {
-        self.active_tables |= 1 << bit;
+        self.active_tables.set(bit);
         self
     }
 
     #[inline]
-    fn highest_active(&self) -> u32 {
-        self.active_tables.trailing_zeros()
+    fn highest_active(&self) -> Option<u32> {
+        self.active_tables.highest()
+    }
+
+    fn push(&mut self, t: ThreadNode, priority: ThreadPriority) {
+        self.tables[priority as usize].push_back(t);
+        self.set_active_queue(priority as u32);
+    }
+
+    fn pop_highest(&mut self) -> Option<ThreadNode> {
+        let highest_active = self.highest_active()?;
+        let q = &mut self.tables[highest_active as usize];
+        let next = q.pop_front();
+        assert!(next.is_some());
+        if q.is_empty() {
+            self.clear_active_queue(highest_active);
+        }
+        next
+    }
+
+    // Takes `t` off the queue of `priority`.
+    fn remove(&mut self, t: &ThreadNode, priority: ThreadPriority) {
+        let mut node = t.clone();
+        let detached = ArcList::<Thread, thread::OffsetOfSchedNode>::detach(&mut node);
+        assert!(detached);
+        if self.tables[priority as usize].is_empty() {
+            self.clear_active_queue(priority as u32);
+        }
+    }
+
+    #[inline]
+    fn publish(&self, hint: &AtomicU32) {
+        hint.store(
+            self.highest_active().unwrap_or(NO_PRIORITY),
+            Ordering::Relaxed,
+        );
     }
 }
 
-pub fn next_ready_thread() -> Option<ThreadNode> {
-    let mut tbl = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
-    let highest_active = tbl.highest_active();
+// The ready tables of the cores, and the highest priority queued on each,
+// NO_PRIORITY if it's empty. A hint is only updated with its table locked.
+struct ReadyTables<'a> {
+    tables: &'a [SpinLock<ReadyTable>],
+    highest: &'a [AtomicU32],
+}
+
+impl ReadyTables<'_> {
+    // Locks the tables of two different cores, lower CPU id first, so that
+    // two cores migrating threads between each other can't deadlock.
+    fn lock_pair(
+        &self,
+        a: usize,
+        b: usize,
+    ) -> (SpinLockGuard<'_, ReadyTable>, SpinLockGuard<'_, ReadyTable>) {
+        assert_ne!(a, b);
+        if a < b {
+            let a = self.tables[a].irqsave_lock();
+            (a, self.tables[b].irqsave_lock())
+        } else {
+            let b = self.tables[b].irqsave_lock();
+            (self.tables[a].irqsave_lock(), b)
+        }
+    }
+
+    // Returns the core whose table holds the highest priority thread,
+    // preferring the local one on ties.
+    fn best_cpu(&self, me: usize) -> Option<usize> {
+        let mut best = (self.highest[me].load(Ordering::Relaxed), me);
+        for cpu in (0..self.tables.len()).filter(|&cpu| cpu != me) {
+            let priority = self.highest[cpu].load(Ordering::Relaxed);
+            if priority < best.0 {
+                best = (priority, cpu);
+            }
+        }
+        (best.0 != NO_PRIORITY).then_some(best.1)
+    }
+
+    fn pop(&self, tbl: &mut ReadyTable, cpu: usize) -> Option<ThreadNode> {
+        let next = tbl.pop_highest();
+        if let Some(t) = next.as_ref() {
+            t.set_ready_queue(None);
+        }
+        tbl.publish(&self.highest[cpu]);
+        next
+    }
+
+    fn next(&self, me: usize) -> Option<ThreadNode> {
+        // A hint can be stale, in which case the tables which were locked
+        // are published again and we look once more, until all tables are
+        // empty.
+        loop {
+            let cpu = self.best_cpu(me)?;
+
+            #[cfg(debugging_scheduler)]
+            crate::trace!("next_ready_thread from the table of C#{}", cpu);
+            let next = if cpu == me {
+                self.pop(&mut self.tables[me].irqsave_lock(), me)
+            } else {
+                let (mut local, mut remote) = self.lock_pair(me, cpu);
+                match (local.highest_active(), remote.highest_active()) {
+                    (Some(l), Some(r)) if r < l => self.pop(&mut remote, cpu),
+                    (None, Some(_)) => self.pop(&mut remote, cpu),
+                    _ => {
+                        remote.publish(&self.highest[cpu]);
+                        self.pop(&mut local, me)
+                    }
+                }
+            };
+            if next.is_some() {
+                return next;
+            }
+        }
+    }
+
+    fn push(&self, cpu: usize, t: ThreadNode) {
+        let mut tbl = self.tables[cpu].irqsave_lock();
+        // Claim the thread before reading its priority, which pairs with
+        // the fence in set_priority: either we read the new priority, or
+        // it finds the thread here and moves it. The queue is only read
+        // with the table locked, so it's filled in below.
+        t.set_ready_queue(Some((cpu, 0)));
+        fence(Ordering::SeqCst);
+        let priority = t.priority();
+        assert!(priority <= MAX_THREAD_PRIORITY);
+        t.set_ready_queue(Some((cpu, priority)));
+        tbl.push(t, priority);
+        tbl.publish(&self.highest[cpu]);
 
-    #[cfg(debugging_scheduler)]
-    {
-        use crate::arch;
-        crate::trace!("next_ready_thread highest_active {}", highest_active);
+        #[cfg(debugging_scheduler)]
+        {
+            crate::trace!(
+                "add pri {} get highest pri {:?}",
+                priority,
+                tbl.highest_active()
+            );
+        }
     }
-    if highest_active > MAX_THREAD_PRIORITY as u32 {
-        return None;
+
+    fn set_priority(&self, t: &ThreadNode, priority: ThreadPriority) {
+        t.lock().set_priority(priority);
+        fence(Ordering::SeqCst);
+        // The thread can be popped or migrated until its table is locked,
+        // so look where it is again once it is.
+        while let Some((cpu, _)) = t.ready_queue() {
+            let mut tbl = self.tables[cpu].irqsave_lock();
+            let Some((owner, old)) = t.ready_queue() else {
+                return;
+            };
+            if owner != cpu {
+                continue;
+            }
+            if old != priority {
+                tbl.remove(t, old);
+                tbl.push(t.clone(), priority);
+                t.set_ready_queue(Some((cpu, priority)));
+                tbl.publish(&self.highest[cpu]);
+            }
+            return;
+        }
     }
-    let q = &mut tbl.tables[highest_active as usize];
-    let next = q.pop_front();
-    assert!(next.is_some());
-    if q.is_empty() {
-        tbl.clear_active_queue(highest_active);
+}
+
+pub fn next_ready_thread() -> Option<ThreadNode> {
+    let next = ready_tables().next(arch::current_cpu_id());
+    if let Some(t) = next.as_ref() {
+        assert!(t.validate_saved_sp());
     }
-    assert!(next.as_ref().unwrap().validate_saved_sp());
     next
 }
 
@@ -89,21 +317,141 @@ pub fn queue_ready_thread(old_state: Uint, t: ThreadNode) -> bool {
         return false;
     }
     assert!(t.validate_saved_sp());
-    let mut tbl = unsafe { READY_TABLE.assume_init_ref().irqsave_lock() };
-    let priority = t.priority();
+    // Any table is fine if we get preempted and migrated here.
+    ready_tables().push(arch::current_cpu_id(), t);
+    true
+}
+
+/// Changes the priority of `t`, moving it to the right queue if it's ready.
+pub fn set_thread_priority(t: &ThreadNode, priority: ThreadPriority) {
     assert!(priority <= MAX_THREAD_PRIORITY);
-    let q = &mut tbl.tables[priority as usize];
-    q.push_back(t.clone());
-    tbl.set_active_queue(priority as u32);
-
-    #[cfg(debugging_scheduler)]
-    {
-        use crate::arch;
-        crate::trace!(
-            "add pri {} get highest pri {}",
-            priority,
-            tbl.highest_active()
+    ready_tables().set_priority(t, priority);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scheduler, thread::Builder, types::Arc};
+    use alloc::{boxed::Box, vec::Vec};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_priority_bitmap() {
+        let mut bitmap = PriorityBitmap::default();
+        assert_eq!(bitmap.highest(), None);
+        bitmap.set(MAX_THREAD_PRIORITY as u32);
+        assert_eq!(bitmap.highest(), Some(MAX_THREAD_PRIORITY as u32));
+        bitmap.set(3);
+        bitmap.set(1);
+        assert_eq!(bitmap.highest(), Some(1));
+        bitmap.clear(1);
+        assert_eq!(bitmap.highest(), Some(3));
+        bitmap.clear(3);
+        bitmap.clear(MAX_THREAD_PRIORITY as u32);
+        assert_eq!(bitmap.highest(), None);
+    }
+
+    extern "C" fn noop() {}
+
+    // Two tables apart from the scheduler's, as on a dual core.
+    fn with_tables(f: impl FnOnce(&ReadyTables, &[ThreadNode])) {
+        let tables: Box<[SpinLock<ReadyTable>]> = (0..2)
+            .map(|_| SpinLock::new(ReadyTable::default()))
+            .collect();
+        for table in tables.iter() {
+            table.irqsave_lock().init();
+        }
+        let highest = [const { AtomicU32::new(NO_PRIORITY) }; 2];
+        let threads: Vec<ThreadNode> = (0..2)
+            .map(|_| Builder::new(thread::Entry::C(noop)).build())
+            .collect();
+        f(
+            &ReadyTables {
+                tables: &tables,
+                highest: &highest,
+            },
+            &threads,
         );
+        // Let the scheduler run them to retirement.
+        for t in threads {
+            assert!(scheduler::queue_ready_thread(thread::CREATED, t));
+        }
+    }
+
+    #[test]
+    fn test_ready_tables_migration() {
+        with_tables(|tables, threads| {
+            let (low, high) = (&threads[0], &threads[1]);
+            tables.set_priority(low, 6);
+            tables.set_priority(high, 2);
+            tables.push(0, low.clone());
+            tables.push(1, high.clone());
+            assert_eq!(high.ready_queue(), Some((1, 2)));
+            assert_eq!(tables.highest[1].load(Ordering::Relaxed), 2);
+
+            // The remote thread comes first, and leaves its table.
+            let next = tables.next(0).unwrap();
+            assert!(Arc::is(&next, high));
+            assert_eq!(high.ready_queue(), None);
+            assert_eq!(tables.highest[1].load(Ordering::Relaxed), NO_PRIORITY);
+            let next = tables.next(0).unwrap();
+            assert!(Arc::is(&next, low));
+            assert!(tables.next(0).is_none());
+            assert!(tables.next(1).is_none());
+        });
+    }
+
+    #[test]
+    fn test_ready_tables_stale_hints() {
+        with_tables(|tables, threads| {
+            let t = &threads[0];
+            tables.set_priority(t, 3);
+            tables.push(1, t.clone());
+            // Hints claiming a better thread on an empty table, local or
+            // remote, don't hide the one queued.
+            tables.highest[0].store(1, Ordering::Relaxed);
+            let next = tables.next(0).unwrap();
+            assert!(Arc::is(&next, t));
+            assert_eq!(tables.highest[0].load(Ordering::Relaxed), NO_PRIORITY);
+
+            tables.push(0, t.clone());
+            tables.highest[1].store(1, Ordering::Relaxed);
+            let next = tables.next(0).unwrap();
+            assert!(Arc::is(&next, t));
+            assert_eq!(tables.highest[1].load(Ordering::Relaxed), NO_PRIORITY);
+            assert!(tables.next(0).is_none());
+        });
+    }
+
+    #[test]
+    fn test_ready_tables_set_priority() {
+        with_tables(|tables, threads| {
+            let (a, b) = (&threads[0], &threads[1]);
+            tables.set_priority(a, 5);
+            tables.set_priority(b, 5);
+            tables.push(1, a.clone());
+            tables.push(1, b.clone());
+            assert_eq!(a.ready_queue(), Some((1, 5)));
+
+            // Moved within its own table, ahead of the other thread.
+            tables.set_priority(b, 2);
+            assert_eq!(b.priority(), 2);
+            assert_eq!(b.ready_queue(), Some((1, 2)));
+            assert_eq!(tables.highest[1].load(Ordering::Relaxed), 2);
+            let next = tables.next(0).unwrap();
+            assert!(Arc::is(&next, b));
+            assert_eq!(tables.highest[1].load(Ordering::Relaxed), 5);
+
+            // The queue of the old priority is cleared once empty.
+            tables.set_priority(a, 6);
+            assert_eq!(tables.highest[1].load(Ordering::Relaxed), 6);
+            assert!(Arc::is(&tables.next(1).unwrap(), a));
+
+            // Only the priority changes for a thread on no table.
+            tables.set_priority(a, 4);
+            assert_eq!(a.priority(), 4);
+            assert_eq!(a.ready_queue(), None);
+            assert!(tables.next(0).is_none());
+        });
     }
-    true
 }
@@ -18,7 +18,7 @@ use crate::{
     support::DisableInterruptGuard,
     sync::SpinLockGuard,
     thread,
-    thread::{Entry, GlobalQueueVisitor, Thread, ThreadNode},
+    thread::{Entry, GlobalQueueVisitor, Thread, ThreadNode, WaitReason},
     time::{self, timer::Timer, WAITING_FOREVER},
     types::{Arc, IlistHead},
 };
@@ -46,12 +46,21 @@ pub(crate) use wait_queue::*;
 pub(crate) static mut RUNNING_THREADS: [MaybeUninit<ThreadNode>; NUM_CORES] =
     [const { MaybeUninit::zeroed() }; NUM_CORES];
 
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
 pub(crate) fn init() {
     idle::init_idle_threads();
     #[cfg(scheduler = "global")]
     global_scheduler::init();
     #[cfg(scheduler = "fifo")]
     fifo::init();
+    INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Whether [`current_thread`] can be called, i.e. each core has at least
+/// its idle thread.
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Acquire)
 }
 
 pub(crate) struct ContextSwitchHookHolder<'a> {
@@ -219,6 +228,8 @@ pub(crate) extern "C" fn yield_me_and_return_next_sp(old_sp: usize) -> usize {
 }
 
 pub fn retire_me() -> ! {
+    // Cells the thread was initializing can't be completed any more.
+    crate::sync::once::poison_running();
     let next = next_ready_thread().map_or_else(|| idle::current_idle_thread().clone(), |v| v);
     let to_sp = next.saved_sp();
 
@@ -253,7 +264,7 @@ pub fn yield_me() {
 fn yield_unconditionally() {
     assert!(arch::local_irq_enabled());
     let Some(next) = next_ready_thread() else {
-        arch::idle();
+        time::idle();
         return;
     };
     let to_sp = next.saved_sp();
@@ -290,6 +301,7 @@ pub(crate) fn suspend_me_for(tick: usize) {
     let next = next_ready_thread().map_or_else(|| idle::current_idle_thread().clone(), |v| v);
     let to_sp = next.saved_sp();
     let old = current_thread();
+    old.start_waiting(WaitReason::Sleep);
     let from_sp_ptr = old.saved_sp_ptr();
     let mut hook_holder = ContextSwitchHookHolder::new(next);
     hook_holder.set_pending_thread(old.clone());
@@ -325,7 +337,11 @@ pub(crate) fn suspend_me_for(tick: usize) {
     assert!(arch::local_irq_enabled());
 }
 
-pub(crate) fn suspend_me_with_timeout(mut w: SpinLockGuard<'_, WaitQueue>, ticks: usize) -> bool {
+pub(crate) fn suspend_me_with_timeout(
+    mut w: SpinLockGuard<'_, WaitQueue>,
+    ticks: usize,
+    reason: WaitReason,
+) -> bool {
     assert!(ticks != 0);
     #[cfg(debugging_scheduler)]
     crate::trace!(
@@ -342,6 +358,7 @@ pub(crate) fn suspend_me_with_timeout(mut w: SpinLockGuard<'_, WaitQueue>, ticks
     // FIXME: Ideally, we should defer state transfer to context switch hook.
     let to_sp = next.saved_sp();
     let old = current_thread();
+    old.start_waiting(reason);
     let from_sp_ptr = old.saved_sp_ptr();
     let entry = Arc::new(WaitEntry {
         wait_node: IlistHead::<WaitEntry, OffsetOfWait>::new(),
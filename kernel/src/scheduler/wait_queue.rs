@@ -13,8 +13,9 @@
 // limitations under the License.
 
 use crate::{
+    scheduler,
     sync::SpinLockGuard,
-    thread::ThreadNode,
+    thread::{self, ThreadNode},
     types::{impl_simple_intrusive_adapter, ArcList, IlistHead},
 };
 
@@ -70,3 +71,27 @@ impl<'a, const N: usize> WaitQueueGuardDropper<'a, N> {
 }
 
 pub(crate) type DefaultWaitQueueGuardDropper<'a> = WaitQueueGuardDropper<'a, 2>;
+
+/// Readies the first thread still waiting in `w`. Entries whose thread has
+/// timed out already are dropped. Returns false if there was none.
+pub(crate) fn wake_one(w: &mut WaitQueue) -> bool {
+    while let Some(next) = w.pop_front() {
+        let t = next.thread.clone();
+        if let Some(timer) = &t.timer {
+            timer.stop();
+        }
+        if scheduler::queue_ready_thread(thread::SUSPENDED, t) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Readies all the threads waiting in `w` and returns how many there were.
+pub(crate) fn wake_all(w: &mut WaitQueue) -> usize {
+    let mut woken = 0;
+    while wake_one(w) {
+        woken += 1;
+    }
+    woken
+}
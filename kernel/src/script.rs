@@ -0,0 +1,304 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Command scripts, run at boot.
+//!
+//! Once the VFS is up, a thread runs the script at the BOOT_SCRIPT_PATH
+//! kconfig, `/etc/rc.local` by default, alongside the apps. Bring-up and
+//! test sequences can so be stored with the image rather than typed over
+//! the serial console at each boot. There's no script to run if the file
+//! doesn't exist.
+//!
+//! A script holds a command per line, its words separated by blanks.
+//! Double quotes keep blanks in a word, and a word starting with `#`
+//! starts a comment. A failing command is reported along with its line
+//! and ends the script, unless the line starts with `-`.
+//!
+//! With `SECURE_BOOT`, scripts are artifacts like any other: they're
+//! measured, and refused if their signature doesn't check out, see
+//! [`crate::secure_boot::verify`].
+//!
+//! Drivers and tests add commands to the built-in ones with [`register`].
+
+#[cfg(secure_boot)]
+use crate::secure_boot::{self, ArtifactKind};
+use crate::{
+    error::{code, Error},
+    kprintln,
+    sync::SpinLock,
+    thread, time,
+    vfs::path,
+};
+use alloc::{string::String, vec::Vec};
+use core::{str, time::Duration};
+use log::{debug, warn};
+
+/// Maximum number of commands registered on top of the built-in ones.
+pub const MAX_COMMANDS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// Called with the words after the name.
+    pub handler: fn(&[&str]) -> Result<(), Error>,
+}
+
+static BUILTIN_COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "show this help",
+        handler: help,
+    },
+    Command {
+        name: "echo",
+        help: "echo WORD... - print the words",
+        handler: echo,
+    },
+    Command {
+        name: "sleep",
+        help: "sleep MS - sleep for MS milliseconds",
+        handler: sleep,
+    },
+    Command {
+        name: "cat",
+        help: "cat PATH - print the file at PATH",
+        handler: cat,
+    },
+    Command {
+        name: "write",
+        help: "write PATH WORD... - write the words, and a newline, to the file at PATH",
+        handler: write,
+    },
+    #[cfg(magic_sysrq)]
+    Command {
+        name: "sysrq",
+        help: "sysrq KEY - run the magic SysRq action bound to KEY",
+        handler: sysrq,
+    },
+];
+
+static COMMANDS: SpinLock<[Option<Command>; MAX_COMMANDS]> = SpinLock::new([None; MAX_COMMANDS]);
+
+pub fn register(command: Command) -> Result<(), Error> {
+    if find(command.name).is_some() {
+        return Err(code::EEXIST);
+    }
+    let mut commands = COMMANDS.irqsave_lock();
+    let Some(slot) = commands.iter_mut().find(|slot| slot.is_none()) else {
+        return Err(code::ENOSPC);
+    };
+    *slot = Some(command);
+    Ok(())
+}
+
+fn find(name: &str) -> Option<Command> {
+    if let Some(command) = BUILTIN_COMMANDS.iter().find(|command| command.name == name) {
+        return Some(*command);
+    }
+    COMMANDS
+        .irqsave_lock()
+        .iter()
+        .flatten()
+        .find(|command| command.name == name)
+        .copied()
+}
+
+// Fails with EINVAL if a quote isn't closed.
+fn split_words(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            '#' if !quoted && word.is_none() => break,
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err(code::EINVAL);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Runs the command on `line`. Fails with ENOENT if there's no such
+/// command.
+pub fn run_line(line: &str) -> Result<(), Error> {
+    let words = split_words(line)?;
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let Some((name, args)) = words.split_first() else {
+        return Ok(());
+    };
+    let command = find(name).ok_or(code::ENOENT)?;
+    (command.handler)(args)
+}
+
+/// Runs the commands of the script at `path`, see the module doc.
+pub fn run_script(path: &str) -> Result<(), Error> {
+    let script = read_file(path)?;
+    #[cfg(secure_boot)]
+    let script = secure_boot::verify(ArtifactKind::Script, path, &script)?;
+    let script = str::from_utf8(&script).map_err(|_| code::EINVAL)?;
+    for (n, line) in script.lines().enumerate() {
+        let (line, may_fail) = match line.trim_start().strip_prefix('-') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if let Err(e) = run_line(line) {
+            kprintln!("{}:{}: {}: {}", path, n + 1, line.trim(), e);
+            if !may_fail {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn spawn_boot_script() {
+    let path = blueos_kconfig::BOOT_SCRIPT_PATH;
+    if path::lookup_path(path).is_none() {
+        debug!("No boot script at '{}'", path);
+        return;
+    }
+    let spawned = thread::spawn(move || {
+        if let Err(e) = run_script(path) {
+            warn!("Boot script '{}' failed: {}", path, e);
+        }
+    });
+    if spawned.is_none() {
+        warn!("Failed to spawn the boot script thread");
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    let file = path::open_file(path, libc::O_RDONLY, 0)?;
+    let mut content = Vec::new();
+    let mut buf = [0; 256];
+    let result = loop {
+        match file.read(&mut buf) {
+            Ok(0) => break Ok(content),
+            Ok(n) => content.extend_from_slice(&buf[..n]),
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = file.close();
+    result
+}
+
+fn help(_args: &[&str]) -> Result<(), Error> {
+    let commands = *COMMANDS.irqsave_lock();
+    for command in BUILTIN_COMMANDS.iter().chain(commands.iter().flatten()) {
+        kprintln!("{:<8} {}", command.name, command.help);
+    }
+    Ok(())
+}
+
+fn echo(args: &[&str]) -> Result<(), Error> {
+    kprintln!("{}", args.join(" "));
+    Ok(())
+}
+
+fn sleep(args: &[&str]) -> Result<(), Error> {
+    let [ms] = args else {
+        return Err(code::EINVAL);
+    };
+    let ms = ms.parse().map_err(|_| code::EINVAL)?;
+    time::sleep(Duration::from_millis(ms));
+    Ok(())
+}
+
+fn cat(args: &[&str]) -> Result<(), Error> {
+    let [path] = args else {
+        return Err(code::EINVAL);
+    };
+    let content = read_file(path)?;
+    for line in String::from_utf8_lossy(&content).lines() {
+        kprintln!("{}", line);
+    }
+    Ok(())
+}
+
+fn write(args: &[&str]) -> Result<(), Error> {
+    let Some((path, words)) = args.split_first() else {
+        return Err(code::EINVAL);
+    };
+    let mut content = words.join(" ");
+    content.push('\n');
+    let file = path::open_file(path, libc::O_WRONLY, 0)?;
+    let mut content = content.as_bytes();
+    let result = loop {
+        match file.write(content) {
+            Ok(n) if n == content.len() => break Ok(()),
+            Ok(0) => break Err(code::EIO),
+            Ok(n) => content = &content[n..],
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = file.close();
+    result
+}
+
+#[cfg(magic_sysrq)]
+fn sysrq(args: &[&str]) -> Result<(), Error> {
+    let [key] = args else {
+        return Err(code::EINVAL);
+    };
+    let &[key] = key.as_bytes() else {
+        return Err(code::EINVAL);
+    };
+    crate::devices::tty::sysrq::handle(key);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count(args: &[&str]) -> Result<(), Error> {
+        assert_eq!(args, ["a b", "c", ""]);
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_line() {
+        let command = Command {
+            name: "testcount",
+            help: "count the calls",
+            handler: count,
+        };
+        register(command).unwrap();
+        assert_eq!(register(command), Err(code::EEXIST));
+
+        run_line("  testcount \"a b\" c \"\" # comment").unwrap();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        run_line("# comment").unwrap();
+        run_line("").unwrap();
+        assert_eq!(run_line("testcount \"a b"), Err(code::EINVAL));
+        assert_eq!(run_line("nosuchcommand"), Err(code::ENOENT));
+        assert_eq!(run_line("sleep 1 2"), Err(code::EINVAL));
+        run_line("sleep 1").unwrap();
+        assert_eq!(run_script("/nosuchscript"), Err(code::ENOENT));
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification and measurement of loaded artifacts.
+//!
+//! An artifact (the boot script, a firmware image written by an update)
+//! is signed by appending a [`SignatureTrailer`] holding an Ed25519
+//! signature of the payload. Signatures are checked against a keyring of public keys which
+//! the board fills in at boot and then seals.
+//!
+//! Every artifact passed to [`verify`] is also measured: its SHA-256 is
+//! recorded in a log and folded into a register, so that the chain of
+//! loaded artifacts can be attested later. With `SECURE_BOOT_LOCKDOWN`,
+//! artifacts which aren't signed by a trusted key are refused.
+
+use crate::{
+    crypto::{ed25519, sha256, Digest, Sha256},
+    error::{code, Error},
+    sync::RwSleepLock,
+};
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+pub const TRAILER_MAGIC: u32 = u32::from_le_bytes(*b"BOSS");
+
+/// Appended to a signed artifact.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct SignatureTrailer {
+    pub magic: u32,
+    /// [`key_id`] of the signing key.
+    pub key_id: u32,
+    /// Ed25519 signature of the payload preceding the trailer.
+    pub signature: [u8; ed25519::SIGNATURE_SIZE],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Script,
+    Firmware,
+}
+
+impl ArtifactKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Script => "script",
+            Self::Firmware => "firmware",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Signed by a key of the keyring.
+    Verified,
+    /// Has no signature trailer.
+    Unsigned,
+    /// Signed by a key which isn't in the keyring.
+    UnknownKey,
+    /// The signature doesn't match the payload.
+    BadSignature,
+}
+
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub kind: ArtifactKind,
+    pub name: String,
+    /// SHA-256 of the whole artifact, trailer included.
+    pub digest: [u8; sha256::DIGEST_SIZE],
+    pub verdict: Verdict,
+}
+
+type PublicKey = [u8; ed25519::PUBLIC_KEY_SIZE];
+
+struct State {
+    // Along with their key_id.
+    keys: Vec<(u32, PublicKey)>,
+    log: Vec<Measurement>,
+    register: [u8; sha256::DIGEST_SIZE],
+}
+
+// Only taken by threads loading artifacts.
+static STATE: RwSleepLock<State> = RwSleepLock::new(State {
+    keys: Vec::new(),
+    log: Vec::new(),
+    register: [0; sha256::DIGEST_SIZE],
+});
+static SEALED: AtomicBool = AtomicBool::new(false);
+
+/// Identifies a public key in a [`SignatureTrailer`]: the first bytes of
+/// its SHA-256.
+pub fn key_id(public_key: &PublicKey) -> u32 {
+    let digest = Sha256::digest(public_key);
+    u32::from_le_bytes(digest[..4].try_into().unwrap())
+}
+
+/// Adds a trusted key. Fails once the keyring is sealed.
+pub fn add_key(public_key: &PublicKey) -> Result<(), Error> {
+    let id = key_id(public_key);
+    let mut state = STATE.write();
+    if SEALED.load(Ordering::Acquire) {
+        return Err(code::EPERM);
+    }
+    if state.keys.iter().any(|(_, key)| key == public_key) {
+        return Err(code::EEXIST);
+    }
+    state.keys.push((id, *public_key));
+    Ok(())
+}
+
+// Copies the key `id` out of the keyring, so that signatures are checked
+// without holding the lock.
+fn find_key(id: u32) -> Option<PublicKey> {
+    STATE
+        .read()
+        .keys
+        .iter()
+        .find(|(key_id, _)| *key_id == id)
+        .map(|(_, key)| *key)
+}
+
+/// Prevents any further key from being added.
+pub fn seal_keyring() {
+    SEALED.store(true, Ordering::Release);
+}
+
+pub fn is_sealed() -> bool {
+    SEALED.load(Ordering::Acquire)
+}
+
+pub const fn is_lockdown() -> bool {
+    cfg!(secure_boot_lockdown)
+}
+
+// Splits `data` into its payload and trailer, if it has one.
+fn split_trailer(data: &[u8]) -> (&[u8], Option<SignatureTrailer>) {
+    let size = core::mem::size_of::<SignatureTrailer>();
+    if data.len() < size {
+        return (data, None);
+    }
+    let (payload, tail) = data.split_at(data.len() - size);
+    match SignatureTrailer::read_from_bytes(tail) {
+        Ok(trailer) if trailer.magic == TRAILER_MAGIC => (payload, Some(trailer)),
+        _ => (data, None),
+    }
+}
+
+// Checks `trailer` against `key`, the key it names if it's trusted.
+fn check(key: Option<&PublicKey>, payload: &[u8], trailer: Option<&SignatureTrailer>) -> Verdict {
+    let Some(trailer) = trailer else {
+        return Verdict::Unsigned;
+    };
+    let Some(key) = key else {
+        return Verdict::UnknownKey;
+    };
+    if ed25519::verify(key, payload, &trailer.signature) {
+        Verdict::Verified
+    } else {
+        Verdict::BadSignature
+    }
+}
+
+/// Measures an artifact about to be loaded and checks its signature.
+/// Returns the payload to load, without the trailer.
+///
+/// An artifact whose signature doesn't match is always refused. One which
+/// isn't signed by a trusted key is only refused in lockdown.
+pub fn verify<'a>(kind: ArtifactKind, name: &str, data: &'a [u8]) -> Result<&'a [u8], Error> {
+    let digest = Sha256::digest(data);
+    let (payload, trailer) = split_trailer(data);
+    let key = trailer
+        .as_ref()
+        .and_then(|trailer| find_key(trailer.key_id));
+    let verdict = check(key.as_ref(), payload, trailer.as_ref());
+    let measurement = Measurement {
+        kind,
+        name: String::from(name),
+        digest,
+        verdict,
+    };
+    let mut state = STATE.write();
+    let mut hasher = Sha256::new();
+    hasher.update(&state.register);
+    hasher.update(&digest);
+    state.register = hasher.finish();
+    state.log.push(measurement);
+    drop(state);
+    match verdict {
+        Verdict::Verified => Ok(payload),
+        Verdict::BadSignature => {
+            log::error!("Bad signature on {} {}", kind.as_str(), name);
+            Err(code::EILSEQ)
+        }
+        _ if is_lockdown() => {
+            log::error!("Refusing untrusted {} {}", kind.as_str(), name);
+            Err(code::EPERM)
+        }
+        _ => {
+            log::warn!("Loading untrusted {} {}", kind.as_str(), name);
+            Ok(payload)
+        }
+    }
+}
+
+/// Returns the measurement log, in load order.
+pub fn measurements() -> Vec<Measurement> {
+    STATE.read().log.clone()
+}
+
+/// Returns the register every measurement is folded into, as
+/// `register = SHA-256(register || digest)`, starting from zeros.
+pub fn register() -> [u8; sha256::DIGEST_SIZE] {
+    STATE.read().register
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::rfc8032::{PUBLIC_KEY, SIGNATURE};
+    use blueos_test_macro::test;
+
+    fn signed(payload: u8) -> Vec<u8> {
+        let trailer = SignatureTrailer {
+            magic: TRAILER_MAGIC,
+            key_id: key_id(&PUBLIC_KEY),
+            signature: SIGNATURE,
+        };
+        let mut data = alloc::vec![payload];
+        data.extend_from_slice(trailer.as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_check_signature() {
+        let key = Some(&PUBLIC_KEY);
+        let data = signed(0x72);
+        let (payload, trailer) = split_trailer(&data);
+        assert_eq!(payload, &[0x72]);
+        assert_eq!(check(key, payload, trailer.as_ref()), Verdict::Verified);
+        assert_eq!(check(None, payload, trailer.as_ref()), Verdict::UnknownKey);
+
+        let data = signed(0x73);
+        let (payload, trailer) = split_trailer(&data);
+        assert_eq!(check(key, payload, trailer.as_ref()), Verdict::BadSignature);
+
+        let (payload, trailer) = split_trailer(&[0x72]);
+        assert!(trailer.is_none());
+        assert_eq!(check(key, payload, None), Verdict::Unsigned);
+    }
+}
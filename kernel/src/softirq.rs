@@ -0,0 +1,239 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Softirqs, the deferred part of interrupt handlers which can't wait for
+//! a thread.
+//!
+//! A handler raises a softirq on its core, which sets a bit of the
+//! pending bitmap of the core. When the outermost handler returns, before
+//! any thread resumes, the pending softirqs of the core run, lowest number
+//! first, still in interrupt context: they can't sleep. Softirqs raised
+//! meanwhile run in the same pass, up to [`MAX_ROUNDS`] times, after which
+//! they're left for the next interrupt. Raised outside interrupt context,
+//! they run right away, with interrupts disabled.
+//!
+//! The network receive softirq runs the receive callbacks of the cards
+//! which interrupted. Tasklets are [`Work`]s run by the tasklet softirq of
+//! the core they were scheduled on, for drivers which don't need a softirq
+//! of their own. Work which may sleep goes to a
+//! [`workqueue`](crate::workqueue) instead.
+//!
+//! /proc/softirqs counts the softirqs run on each core, and /proc/stat
+//! shows the time spent running them.
+
+use crate::{
+    arch, devices, irq,
+    support::DisableInterruptGuard,
+    sync::SpinLock,
+    time,
+    types::{Arc, ArcList},
+    workqueue::{Work, WorkNode},
+};
+use blueos_kconfig::NUM_CORES;
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// Softirqs, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Softirq {
+    NetRx,
+    Tasklet,
+}
+
+pub const NR_SOFTIRQS: usize = 2;
+
+const NAMES: [&str; NR_SOFTIRQS] = ["NET_RX", "TASKLET"];
+const HANDLERS: [fn(); NR_SOFTIRQS] = [devices::net::rx_action, run_tasklets];
+
+/// Number of passes over the pending softirqs on an interrupt exit.
+pub const MAX_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    runs: [usize; NR_SOFTIRQS],
+    cycles: u64,
+}
+
+static PENDING: [AtomicUsize; NUM_CORES] = [const { AtomicUsize::new(0) }; NUM_CORES];
+static RUNNING: [AtomicBool; NUM_CORES] = [const { AtomicBool::new(false) }; NUM_CORES];
+static STATS: [SpinLock<Stats>; NUM_CORES] = [const {
+    SpinLock::new(Stats {
+        runs: [0; NR_SOFTIRQS],
+        cycles: 0,
+    })
+}; NUM_CORES];
+static TASKLETS: [SpinLock<ArcList<Work, WorkNode>>; NUM_CORES] =
+    [const { SpinLock::new(ArcList::new()) }; NUM_CORES];
+// Tasklets are only scheduled once their lists are initialized.
+static READY: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn init() {
+    for tasklets in TASKLETS.iter() {
+        tasklets.irqsave_lock().init();
+    }
+    READY.store(true, Ordering::Release);
+}
+
+/// Marks `nr` pending on the current core.
+pub fn raise(nr: Softirq) {
+    let _dig = DisableInterruptGuard::new();
+    PENDING[arch::current_cpu_id()].fetch_or(1 << nr as usize, Ordering::Relaxed);
+    if !irq::is_in_irq() {
+        run_pending();
+    }
+}
+
+/// Called when a handler returns, runs the pending softirqs if it's the
+/// outermost one.
+pub(crate) fn irq_exit() {
+    if irq::nesting_depth() == 1 && PENDING[arch::current_cpu_id()].load(Ordering::Relaxed) != 0 {
+        run_pending();
+    }
+}
+
+fn run_pending() {
+    let cpu = arch::current_cpu_id();
+    // Softirqs raised by a softirq run in the loop below.
+    if RUNNING[cpu].swap(true, Ordering::Acquire) {
+        return;
+    }
+    for _ in 0..MAX_ROUNDS {
+        let pending = PENDING[cpu].swap(0, Ordering::Acquire);
+        if pending == 0 {
+            break;
+        }
+        let start = time::get_sys_cycles();
+        for (nr, handler) in HANDLERS.iter().enumerate() {
+            if pending & (1 << nr) != 0 {
+                handler();
+            }
+        }
+        let cycles = time::get_sys_cycles().saturating_sub(start);
+        let mut stats = STATS[cpu].irqsave_lock();
+        stats.cycles += cycles;
+        for (nr, runs) in stats.runs.iter_mut().enumerate() {
+            if pending & (1 << nr) != 0 {
+                *runs += 1;
+            }
+        }
+    }
+    RUNNING[cpu].store(false, Ordering::Release);
+}
+
+/// Queues `work` on the current core, to run in its tasklet softirq.
+/// Returns false if it's pending already, or if tasklets can't be
+/// scheduled yet. Can be called from interrupt context.
+pub fn schedule_tasklet(work: &Arc<Work>) -> bool {
+    if !READY.load(Ordering::Acquire) || !work.set_pending() {
+        return false;
+    }
+    // The work must be queued on the core the softirq is raised on.
+    let _dig = DisableInterruptGuard::new();
+    TASKLETS[arch::current_cpu_id()]
+        .irqsave_lock()
+        .push_back(work.clone());
+    raise(Softirq::Tasklet);
+    true
+}
+
+fn run_tasklets() {
+    let tasklets = &TASKLETS[arch::current_cpu_id()];
+    loop {
+        let work = tasklets.irqsave_lock().pop_front();
+        match work {
+            Some(work) => work.run(),
+            None => break,
+        }
+    }
+}
+
+/// Cycles spent running softirqs on `cpu`.
+pub fn cycles(cpu: usize) -> u64 {
+    STATS[cpu].irqsave_lock().cycles
+}
+
+/// Writes the number of times each softirq ran on each core, a softirq
+/// per line as in /proc/softirqs.
+pub fn dump(w: &mut impl fmt::Write) -> fmt::Result {
+    let stats: [Stats; NUM_CORES] = core::array::from_fn(|cpu| *STATS[cpu].irqsave_lock());
+    write!(w, "{:>12}", "")?;
+    for cpu in 0..NUM_CORES {
+        write!(w, " {:>10}", alloc::format!("CPU{}", cpu))?;
+    }
+    writeln!(w)?;
+    for (nr, name) in NAMES.iter().enumerate() {
+        write!(w, "{:>12}", alloc::format!("{}:", name))?;
+        for stat in stats.iter() {
+            write!(w, " {:>10}", stat.runs[nr])?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::static_arc;
+    use alloc::string::String;
+    use blueos_test_macro::test;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    static_arc! {
+        TASKLET(Work, Work::new(count)),
+    }
+    static_arc! {
+        SCHEDULING_TASKLET(Work, Work::new(schedule_count)),
+    }
+
+    fn count() {
+        RUNS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn schedule_count() {
+        count();
+        assert!(schedule_tasklet(&TASKLET));
+        // Runs once this one returns, not right away.
+        assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_softirq() {
+        let runs = |nr: Softirq| {
+            let _dig = DisableInterruptGuard::new();
+            STATS[arch::current_cpu_id()].irqsave_lock().runs[nr as usize]
+        };
+
+        let net_rx_runs = runs(Softirq::NetRx);
+        raise(Softirq::NetRx);
+        assert_eq!(runs(Softirq::NetRx), net_rx_runs + 1);
+
+        let tasklet_runs = runs(Softirq::Tasklet);
+        assert!(schedule_tasklet(&SCHEDULING_TASKLET));
+        assert_eq!(RUNS.load(Ordering::Relaxed), 2);
+        assert!(!TASKLET.is_pending());
+        assert!(!SCHEDULING_TASKLET.is_pending());
+        // Scheduling from a tasklet raises the softirq again, for a pass
+        // which finds the list empty.
+        assert_eq!(runs(Softirq::Tasklet), tasklet_runs + 2);
+
+        let mut out = String::new();
+        dump(&mut out).unwrap();
+        assert!(out.starts_with(&alloc::format!("{:>12} {:>10}", "", "CPU0")));
+        assert!(out.contains("     NET_RX:"));
+    }
+}
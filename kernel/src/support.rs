@@ -14,9 +14,8 @@
 
 use crate::{
     arch,
-    sync::spinlock::{SpinLock, SpinLockGuard},
     thread::ThreadNode,
-    types::{Arc, ArcList, AtomicUint, IntrusiveAdapter, Uint},
+    types::{Arc, Uint},
 };
 use core::{
     mem::MaybeUninit,
@@ -27,13 +26,25 @@ use core::{
 #[derive(Debug)]
 pub(crate) struct DisableInterruptGuard {
     old: usize,
+    #[cfg(irqsoff_tracer)]
+    traced: bool,
 }
 
 impl DisableInterruptGuard {
     #[inline]
+    #[cfg_attr(irqsoff_tracer, track_caller)]
     pub fn new() -> Self {
+        #[cfg(irqsoff_tracer)]
+        let traced = arch::local_irq_enabled();
+        let old = arch::disable_local_irq_save();
+        #[cfg(irqsoff_tracer)]
+        if traced {
+            crate::irqsoff::irqs_off(core::panic::Location::caller(), None);
+        }
         Self {
-            old: arch::disable_local_irq_save(),
+            old,
+            #[cfg(irqsoff_tracer)]
+            traced,
         }
     }
 }
@@ -41,6 +52,10 @@ impl DisableInterruptGuard {
 impl Drop for DisableInterruptGuard {
     #[inline]
     fn drop(&mut self) {
+        #[cfg(irqsoff_tracer)]
+        if self.traced {
+            crate::irqsoff::irqs_on(None);
+        }
         arch::enable_local_irq_restore(self.old);
     }
 }
@@ -270,53 +285,6 @@ impl Drop for PerCpuVarAccessGuard {
     }
 }
 
-pub(crate) struct ArcBufferingQueue<T: Sized, A: IntrusiveAdapter, const N: usize> {
-    queues: [SpinLock<ArcList<T, A>>; N],
-    active: AtomicUint,
-}
-
-impl<T: Sized, A: IntrusiveAdapter, const N: usize> Init for ArcBufferingQueue<T, A, N> {
-    fn init(&mut self) -> bool {
-        self.init_queues() == N
-    }
-}
-
-impl<T: Sized, A: IntrusiveAdapter, const N: usize> ArcBufferingQueue<T, A, N> {
-    #[inline]
-    pub const fn new() -> Self {
-        Self {
-            queues: [const { SpinLock::new(ArcList::new()) }; N],
-            active: AtomicUint::new(0),
-        }
-    }
-
-    pub fn init_queues(&self) -> usize {
-        let mut res = 0;
-        for i in 0..N {
-            if self.queues[i].lock().init() {
-                res += 1;
-            }
-        }
-        res
-    }
-
-    pub type WorkList = ArcList<T, A>;
-
-    #[inline]
-    #[allow(clippy::unnecessary_cast)]
-    pub fn advance_active_queue(&self) -> SpinLockGuard<'_, ArcList<T, A>> {
-        let i = self.active.fetch_add(1, Ordering::AcqRel) as usize;
-        self.queues[i % N].irqsave_lock()
-    }
-
-    #[inline]
-    #[allow(clippy::unnecessary_cast)]
-    pub fn get_active_queue(&self) -> SpinLockGuard<'_, ArcList<T, A>> {
-        let i = self.active.load(Ordering::Acquire) as usize;
-        self.queues[i % N].irqsave_lock()
-    }
-}
-
 pub trait Init {
     fn init(&mut self) -> bool;
 }
@@ -19,7 +19,7 @@ use crate::{
     scheduler, static_arc, support,
     sync::SpinLock,
     thread,
-    thread::{Thread, ThreadNode},
+    thread::{Thread, ThreadNode, WaitReason},
     time::WAITING_FOREVER,
     trace,
     types::{
@@ -115,12 +115,12 @@ pub fn atomic_wait(atom: &AtomicUsize, val: usize, timeout: Option<usize>) -> Re
         addr
     );
     if let Some(timeout) = timeout {
-        let res = scheduler::suspend_me_with_timeout(we, timeout);
+        let res = scheduler::suspend_me_with_timeout(we, timeout, WaitReason::Futex(addr));
         if res {
             return Err(code::ETIMEDOUT);
         }
     } else {
-        let _ = scheduler::suspend_me_with_timeout(we, WAITING_FOREVER);
+        let _ = scheduler::suspend_me_with_timeout(we, WAITING_FOREVER, WaitReason::Futex(addr));
     }
     Ok(())
 }
@@ -0,0 +1,93 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Mutex, SpinLock};
+use crate::{
+    error::{code, Error},
+    irq, scheduler,
+    scheduler::WaitQueue,
+    thread::WaitReason,
+    time::WAITING_FOREVER,
+};
+
+/// A condition variable, used along with a [`Mutex`].
+#[derive(Debug)]
+pub struct Condvar {
+    pending: SpinLock<WaitQueue>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            pending: SpinLock::new(WaitQueue::new()),
+        }
+    }
+
+    pub fn init(&self) -> bool {
+        self.pending.irqsave_lock().init()
+    }
+
+    /// Unlocks `mutex`, which must be owned by the current thread, waits to
+    /// be notified and locks `mutex` again.
+    pub fn wait(&self, mutex: &Mutex) {
+        let result = self.wait_timeout(mutex, WAITING_FOREVER);
+        crate::kassert!(result.is_ok());
+    }
+
+    /// Fails with ETIMEDOUT if no notification came within `ticks`. The
+    /// mutex is locked again in both cases.
+    pub fn wait_timeout(&self, mutex: &Mutex, ticks: usize) -> Result<(), Error> {
+        assert!(!irq::is_in_irq());
+        let w = self.pending.irqsave_lock();
+        // Notifiers take the pending lock, so none can be missed between
+        // releasing the mutex and going to sleep.
+        let ok = mutex.release();
+        assert!(ok, "Condvar waited without owning the mutex");
+        let timed_out = scheduler::suspend_me_with_timeout(
+            w,
+            ticks,
+            WaitReason::Sync(self as *const _ as usize),
+        );
+        mutex.lock();
+        if timed_out {
+            return Err(code::ETIMEDOUT);
+        }
+        Ok(())
+    }
+
+    pub fn notify_one(&self) -> bool {
+        let woken = scheduler::wake_one(&mut self.pending.irqsave_lock());
+        if woken {
+            scheduler::yield_me_now_or_later();
+        }
+        woken
+    }
+
+    pub fn notify_all(&self) -> usize {
+        let woken = scheduler::wake_all(&mut self.pending.irqsave_lock());
+        if woken != 0 {
+            scheduler::yield_me_now_or_later();
+        }
+        woken
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl !Send for Condvar {}
+unsafe impl Sync for Condvar {}
@@ -18,12 +18,18 @@ use crate::{
     irq,
     scheduler::{self, WaitQueue},
     thread,
-    thread::Thread,
-    time::WAITING_FOREVER,
+    thread::{Thread, WaitReason},
+    time::{syscalls::reltime_to_ticks, WAITING_FOREVER},
     types::ArcList,
+    vfs::syscalls::Timespec,
 };
+use alloc::boxed::Box;
 use bitflags::bitflags;
-use core::cell::Cell;
+use core::{
+    cell::Cell,
+    ffi::c_int,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 type ThreadList = ArcList<Thread, thread::OffsetOfSchedNode>;
 
 bitflags! {
@@ -35,6 +41,16 @@ bitflags! {
     }
 }
 
+// Whether `flags` satisfy a waiter for `mask` in `mode`. Waiting for any
+// flag of an empty mask waits for any flag at all.
+fn is_satisfied(flags: u32, mask: u32, mode: EventFlagsMode) -> bool {
+    if mode.contains(EventFlagsMode::ANY) {
+        flags & mask != 0 || mask == 0 && flags != 0
+    } else {
+        mode.contains(EventFlagsMode::ALL) && flags & mask == mask
+    }
+}
+
 #[derive(Debug)]
 pub struct EventFlags {
     // We let the Spinlock protect the whole EventFlags.
@@ -73,13 +89,10 @@ impl EventFlags {
         let mut w = self.pending.irqsave_lock();
         let new_flags = self.flags.get() | flags;
         for mut entry in w.iter() {
-            let mut thread = entry.thread.clone();
+            let thread = entry.thread.clone();
             let event_mask = thread.event_flags_mask();
             let event_mode = thread.event_flags_mode();
-            if event_mode.contains(EventFlagsMode::ANY)
-                && (event_mask & flags != 0 || event_mask == 0)
-                || event_mode.contains(EventFlagsMode::ALL) && event_mask & flags == event_mask
-            {
+            if is_satisfied(new_flags, event_mask, event_mode) {
                 WaitQueue::detach(&mut entry);
                 thread_list.push_back(thread);
             }
@@ -94,6 +107,8 @@ impl EventFlags {
             if !thread.event_flags_mode().contains(EventFlagsMode::NO_CLEAR) {
                 clear_flags |= thread.event_flags_mask();
             }
+            // Tells the waiter the flags which woke it up.
+            thread.lock().set_event_flags_mask(new_flags);
             scheduler::queue_ready_thread(thread::SUSPENDED, thread);
         }
 
@@ -130,18 +145,9 @@ impl EventFlags {
         }
 
         let mut w = self.pending.irqsave_lock();
-        let mut event_get = false;
         let event_flags = self.flags.get();
-        if mode.contains(EventFlagsMode::ANY) {
-            if event_flags & flags != 0 || flags == 0 && event_flags != 0 {
-                event_get = true;
-            }
-        } else if mode.contains(EventFlagsMode::ALL) && event_flags & flags == flags {
-            event_get = true;
-        }
-
         let current_thread = scheduler::current_thread();
-        if event_get {
+        if is_satisfied(event_flags, flags, mode) {
             {
                 let mut locked_thread = current_thread.lock();
                 locked_thread.set_event_flags_mask(event_flags & flags);
@@ -162,13 +168,17 @@ impl EventFlags {
             locked_thread.set_event_flags_mask(flags);
             locked_thread.set_event_flags_mode(mode);
         }
-        let timed_out = scheduler::suspend_me_with_timeout(w, timeout);
+        let timed_out = scheduler::suspend_me_with_timeout(
+            w,
+            timeout,
+            WaitReason::Sync(self as *const _ as usize),
+        );
         if timed_out {
             return Err(code::ETIMEDOUT);
         }
 
-        // The flags are cleared by the thread when it is resumed.
-        Ok(event_flags)
+        // The flags were cleared by the thread which set them.
+        Ok(current_thread.event_flags_mask())
     }
 
     pub fn reset(&self) {
@@ -179,16 +189,187 @@ impl EventFlags {
             if let Some(timer) = &thread.timer {
                 timer.stop();
             }
+            thread.lock().set_event_flags_mask(0);
             scheduler::queue_ready_thread(thread::SUSPENDED, thread);
         }
         drop(w);
         scheduler::yield_me_now_or_later();
     }
+
+    fn has_waiters(&self) -> bool {
+        !self.pending.irqsave_lock().is_empty()
+    }
 }
 
 impl !Send for EventFlags {}
 unsafe impl Sync for EventFlags {}
 
+// C API, for code ported from RTOSes with event groups. The functions map
+// onto FreeRTOS' `xEventGroup*`, and return an errno value like the
+// `sem_*` functions.
+
+pub const EVENT_FLAGS_ANY: c_int = EventFlagsMode::ANY.bits() as c_int;
+pub const EVENT_FLAGS_ALL: c_int = EventFlagsMode::ALL.bits() as c_int;
+pub const EVENT_FLAGS_NO_CLEAR: c_int = EventFlagsMode::NO_CLEAR.bits() as c_int;
+
+/// `event_flags_t`, which holds a pointer to the kernel object allocated
+/// by [`event_flags_init`].
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct EventFlagsHandle {
+    inner: AtomicUsize,
+}
+
+fn event_flags<'a>(ev: *mut EventFlagsHandle) -> Result<&'a EventFlags, c_int> {
+    let ev = unsafe { ev.as_ref() }.ok_or(libc::EINVAL)?;
+    match ev.inner.load(Ordering::Acquire) {
+        0 => Err(libc::EINVAL),
+        p => Ok(unsafe { &*(p as *const EventFlags) }),
+    }
+}
+
+// Stores `value` at `out` unless it's null.
+fn store(out: *mut u32, value: u32) {
+    if let Some(out) = unsafe { out.as_mut() } {
+        *out = value;
+    }
+}
+
+pub fn event_flags_init(ev: *mut EventFlagsHandle, flags: u32) -> c_int {
+    let Some(ev) = (unsafe { ev.as_mut() }) else {
+        return libc::EINVAL;
+    };
+    // The wait queue is only initialized once the object has reached its
+    // final address.
+    let new = Box::into_raw(Box::new(EventFlags::new()));
+    let ok = unsafe { &*new }.init(flags);
+    crate::kassert!(ok);
+    ev.inner.store(new as usize, Ordering::Release);
+    0
+}
+
+/// Fails with EBUSY if threads wait for the flags.
+pub fn event_flags_destroy(ev: *mut EventFlagsHandle) -> c_int {
+    match event_flags(ev) {
+        Ok(flags) if flags.has_waiters() => libc::EBUSY,
+        Ok(_) => {
+            let p = unsafe { &*ev }.inner.swap(0, Ordering::AcqRel);
+            drop(unsafe { Box::from_raw(p as *mut EventFlags) });
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+/// Sets `flags`, and stores the flags once the waiters they satisfy are
+/// woken up at `value`, unless it's null.
+pub fn event_flags_set(ev: *mut EventFlagsHandle, flags: u32, value: *mut u32) -> c_int {
+    let ev = match event_flags(ev) {
+        Ok(ev) => ev,
+        Err(e) => return e,
+    };
+    match ev.set(flags) {
+        Ok(new) => {
+            store(value, new);
+            0
+        }
+        Err(e) => -e.to_errno(),
+    }
+}
+
+/// Clears `flags`, and stores the flags before at `old`, unless it's null.
+pub fn event_flags_clear(ev: *mut EventFlagsHandle, flags: u32, old: *mut u32) -> c_int {
+    match event_flags(ev) {
+        Ok(ev) => {
+            store(old, ev.clear(flags));
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+pub fn event_flags_get(ev: *mut EventFlagsHandle, value: *mut u32) -> c_int {
+    if value.is_null() {
+        return libc::EINVAL;
+    }
+    match event_flags(ev) {
+        Ok(ev) => {
+            store(value, ev.get());
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+/// Waits for any or all of `flags`, depending on `options`, for up to
+/// `reltime`, or forever if it's null. The flags waited for are cleared
+/// unless `options` has [`EVENT_FLAGS_NO_CLEAR`]. Stores the flags which
+/// satisfied the wait at `value`, unless it's null. Fails with ETIMEDOUT.
+pub fn event_flags_wait(
+    ev: *mut EventFlagsHandle,
+    flags: u32,
+    options: c_int,
+    reltime: *const Timespec,
+    value: *mut u32,
+) -> c_int {
+    let ev = match event_flags(ev) {
+        Ok(ev) => ev,
+        Err(e) => return e,
+    };
+    let Some(mode) = EventFlagsMode::from_bits(options as u8) else {
+        return libc::EINVAL;
+    };
+    if mode.contains(EventFlagsMode::ANY) == mode.contains(EventFlagsMode::ALL) {
+        return libc::EINVAL;
+    }
+    let timeout = if reltime.is_null() {
+        WAITING_FOREVER
+    } else {
+        match reltime_to_ticks(reltime) {
+            Ok(ticks) => ticks,
+            Err(e) => return -e,
+        }
+    };
+    match ev.wait(flags, mode, timeout) {
+        Ok(flags) => {
+            store(value, flags);
+            0
+        }
+        Err(e) => -e.to_errno(),
+    }
+}
+
+mod ffi {
+    use super::*;
+
+    macro_rules! export {
+        ($($name:ident($($arg:ident: $argty:ty),*);)*) => {
+            $(
+                #[no_mangle]
+                #[linkage = "weak"]
+                pub extern "C" fn $name($($arg: $argty),*) -> c_int {
+                    super::$name($($arg),*)
+                }
+            )*
+        };
+    }
+
+    export! {
+        event_flags_init(ev: *mut EventFlagsHandle, flags: u32);
+        event_flags_destroy(ev: *mut EventFlagsHandle);
+        event_flags_set(ev: *mut EventFlagsHandle, flags: u32, value: *mut u32);
+        event_flags_clear(ev: *mut EventFlagsHandle, flags: u32, old: *mut u32);
+        event_flags_get(ev: *mut EventFlagsHandle, value: *mut u32);
+        event_flags_wait(
+            ev: *mut EventFlagsHandle,
+            flags: u32,
+            options: c_int,
+            reltime: *const Timespec,
+            value: *mut u32
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -514,4 +695,70 @@ mod tests {
         event_flags.clear(0x05); // Clear 0x01 and 0x04 (0x04 doesn't exist)
         assert_eq!(event_flags.get(), 0x02); // Only 0x02 remains
     }
+
+    static ALL_FLAGS: EventFlags = EventFlags::new();
+
+    #[test]
+    fn test_event_flags_wait_all_across_sets() {
+        use core::sync::atomic::AtomicU32;
+        static WOKEN_BY: AtomicU32 = AtomicU32::new(0);
+
+        ALL_FLAGS.init(0);
+        let waiter = thread::spawn(|| {
+            let flags = ALL_FLAGS.wait(0x03, EventFlagsMode::ALL, WAITING_FOREVER);
+            WOKEN_BY.store(flags.unwrap(), Ordering::Release);
+        })
+        .unwrap();
+        while waiter.state() != thread::SUSPENDED {
+            scheduler::yield_me();
+        }
+        // The flags set earlier count towards the wait.
+        assert_eq!(ALL_FLAGS.set(0x01), Ok(0x01));
+        assert_eq!(ALL_FLAGS.set(0x06), Ok(0x04));
+        while WOKEN_BY.load(Ordering::Acquire) == 0 {
+            scheduler::yield_me();
+        }
+        assert_eq!(WOKEN_BY.load(Ordering::Acquire), 0x07);
+    }
+
+    #[test]
+    fn test_event_flags_c_api() {
+        let mut ev = EventFlagsHandle::default();
+        let mut value = 0;
+        assert_eq!(event_flags_get(&mut ev, &mut value), libc::EINVAL);
+        assert_eq!(event_flags_init(&mut ev, 0x10), 0);
+        assert_eq!(event_flags_set(&mut ev, 0x01, &mut value), 0);
+        assert_eq!(value, 0x11);
+
+        let poll = Timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let all = EVENT_FLAGS_ALL;
+        assert_eq!(
+            event_flags_wait(&mut ev, 0x03, all, &poll, &mut value),
+            libc::ETIMEDOUT
+        );
+        let options = EVENT_FLAGS_ANY | EVENT_FLAGS_ALL;
+        assert_eq!(
+            event_flags_wait(&mut ev, 0x03, options, &poll, &mut value),
+            libc::EINVAL
+        );
+        let options = EVENT_FLAGS_ANY | EVENT_FLAGS_NO_CLEAR;
+        assert_eq!(
+            event_flags_wait(&mut ev, 0x03, options, &poll, &mut value),
+            0
+        );
+        assert_eq!(value, 0x11);
+        assert_eq!(
+            event_flags_wait(&mut ev, 0x01, EVENT_FLAGS_ANY, &poll, &mut value),
+            0
+        );
+        assert_eq!(event_flags_clear(&mut ev, 0x30, &mut value), 0);
+        assert_eq!(value, 0x10);
+        assert_eq!(event_flags_get(&mut ev, &mut value), 0);
+        assert_eq!(value, 0);
+        assert_eq!(event_flags_destroy(&mut ev), 0);
+        assert_eq!(event_flags_destroy(&mut ev), libc::EINVAL);
+    }
 }
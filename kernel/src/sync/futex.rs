@@ -0,0 +1,392 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C API for futexes, compatible with the Linux `futex` syscall.
+//!
+//! A futex is a 32-bit word in user memory, which user-level locks and
+//! condition variables wait on when contended. [`futex`] supports
+//! `FUTEX_WAIT` and `FUTEX_WAKE`, `FUTEX_REQUEUE` and `FUTEX_CMP_REQUEUE` to
+//! move the waiters of a condition variable over to its mutex, and
+//! `FUTEX_WAIT_BITSET` and `FUTEX_WAKE_BITSET` to wake only the waiters
+//! whose bitset intersects the one passed. All threads share the address
+//! space, so `FUTEX_PRIVATE_FLAG` makes no difference.
+//!
+//! The waiters of a word are queued by bitset. Waiters with the same bitset
+//! are woken in the order they came, waiters with different ones in no
+//! particular order.
+
+use crate::{
+    scheduler::{self, WaitQueue},
+    sync::SpinLock,
+    thread::WaitReason,
+    time::{
+        self, duration_to_ticks,
+        syscalls::{abstime_to_ticks, reltime_to_ticks},
+        WAITING_FOREVER,
+    },
+    types::Arc,
+    vfs::syscalls::Timespec,
+};
+use alloc::collections::BTreeMap;
+use core::{
+    ffi::c_int,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+pub const FUTEX_WAIT: c_int = 0;
+pub const FUTEX_WAKE: c_int = 1;
+pub const FUTEX_REQUEUE: c_int = 3;
+pub const FUTEX_CMP_REQUEUE: c_int = 4;
+pub const FUTEX_WAIT_BITSET: c_int = 9;
+pub const FUTEX_WAKE_BITSET: c_int = 10;
+pub const FUTEX_PRIVATE_FLAG: c_int = 128;
+pub const FUTEX_CLOCK_REALTIME: c_int = 256;
+
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+const FUTEX_CMD_MASK: c_int = !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+const NANOS_PER_SEC: libc::c_long = 1_000_000_000;
+
+type Queue = Arc<SpinLock<WaitQueue>>;
+
+// The waiters, by the address of the word and their bitset. Queues are
+// dropped once empty.
+static QUEUES: SpinLock<BTreeMap<(usize, u32), Queue>> = SpinLock::new(BTreeMap::new());
+
+fn word<'a>(uaddr: *const u32) -> Result<&'a AtomicU32, c_int> {
+    if uaddr.is_null() {
+        return Err(-libc::EFAULT);
+    }
+    if !uaddr.is_aligned() {
+        return Err(-libc::EINVAL);
+    }
+    Ok(unsafe { &*(uaddr as *const AtomicU32) })
+}
+
+// The ticks to wait for, `WAITING_FOREVER` without a timeout.
+fn timeout_ticks(timeout: *const Timespec, absolute: bool, realtime: bool) -> Result<usize, c_int> {
+    if timeout.is_null() {
+        return Ok(WAITING_FOREVER);
+    }
+    if !absolute {
+        return reltime_to_ticks(timeout);
+    }
+    if realtime {
+        return abstime_to_ticks(timeout);
+    }
+    let deadline = unsafe { timeout.read() };
+    if deadline.tv_sec < 0 || !(0..NANOS_PER_SEC).contains(&deadline.tv_nsec) {
+        return Err(-libc::EINVAL);
+    }
+    Ok(duration_to_ticks(
+        Duration::from(deadline).saturating_sub(time::get_uptime()),
+    ))
+}
+
+fn queue_of(queues: &mut BTreeMap<(usize, u32), Queue>, key: (usize, u32)) -> Queue {
+    queues
+        .entry(key)
+        .or_insert_with(|| {
+            let queue = Arc::new(SpinLock::new(WaitQueue::new()));
+            queue.irqsave_lock().init();
+            queue
+        })
+        .clone()
+}
+
+// Calls `f` with the bitset and the waiters of each queue of `addr`, until
+// it returns false. Drops the queues it empties.
+fn visit(
+    queues: &mut BTreeMap<(usize, u32), Queue>,
+    addr: usize,
+    mut f: impl FnMut(u32, &mut WaitQueue) -> bool,
+) {
+    let mut from = 0;
+    while let Some((&key, queue)) = queues.range((addr, from)..=(addr, u32::MAX)).next() {
+        let (more, empty) = {
+            let mut w = queue.irqsave_lock();
+            let more = f(key.1, &mut w);
+            (more, w.is_empty())
+        };
+        if empty {
+            queues.remove(&key);
+        }
+        match key.1.checked_add(1) {
+            Some(next) if more => from = next,
+            _ => return,
+        }
+    }
+}
+
+fn wait(word: &AtomicU32, val: u32, bitset: u32, ticks: usize) -> c_int {
+    let addr = word as *const _ as usize;
+    let mut queues = QUEUES.irqsave_lock();
+    if word.load(Ordering::SeqCst) != val {
+        return -libc::EAGAIN;
+    }
+    if ticks == 0 {
+        return -libc::ETIMEDOUT;
+    }
+    let queue = queue_of(&mut queues, (addr, bitset));
+    let mut w = queue.irqsave_lock();
+    w.take_irq_guard(&mut queues);
+    drop(queues);
+    if !scheduler::suspend_me_with_timeout(w, ticks, WaitReason::Futex(addr)) {
+        return 0;
+    }
+    // Take the entry of the thread out of the queue it's in, which is
+    // another word's if it has been requeued, so that it isn't counted by
+    // a later wake of that word.
+    let current = scheduler::current_thread();
+    let mut queues = QUEUES.irqsave_lock();
+    let found = queues
+        .iter()
+        .filter(|(key, _)| key.1 == bitset)
+        .find_map(|(&key, queue)| {
+            let w = queue.irqsave_lock();
+            let mut entry = w.iter().find(|entry| Arc::is(&entry.thread, &current))?;
+            WaitQueue::detach(&mut entry);
+            Some((key, w.is_empty()))
+        });
+    if let Some((key, true)) = found {
+        queues.remove(&key);
+    }
+    -libc::ETIMEDOUT
+}
+
+fn wake(word: &AtomicU32, count: usize, mask: u32) -> c_int {
+    let addr = word as *const _ as usize;
+    let mut woken = 0;
+    visit(&mut QUEUES.irqsave_lock(), addr, |bitset, w| {
+        if bitset & mask != 0 {
+            while woken < count && scheduler::wake_one(w) {
+                woken += 1;
+            }
+        }
+        woken < count
+    });
+    if woken > 0 {
+        scheduler::yield_me_now_or_later();
+    }
+    woken as c_int
+}
+
+// Wakes `count` waiters of `word`, then moves `most` of the others over to
+// `target`. Returns how many were woken and how many moved.
+fn requeue(
+    word: &AtomicU32,
+    count: usize,
+    target: &AtomicU32,
+    most: usize,
+    expected: Option<u32>,
+) -> Result<(usize, usize), c_int> {
+    let addr = word as *const _ as usize;
+    let target = target as *const _ as usize;
+    let mut queues = QUEUES.irqsave_lock();
+    if expected.is_some_and(|val| word.load(Ordering::SeqCst) != val) {
+        return Err(-libc::EAGAIN);
+    }
+    let mut woken = 0;
+    visit(&mut queues, addr, |_, w| {
+        while woken < count && scheduler::wake_one(w) {
+            woken += 1;
+        }
+        woken < count
+    });
+    let mut moved = 0;
+    let mut from = 0;
+    // The waiters stay where they are if the words are the same.
+    while moved < most && target != addr {
+        let Some((&key, queue)) = queues.range((addr, from)..=(addr, u32::MAX)).next() else {
+            break;
+        };
+        let queue = queue.clone();
+        let to = queue_of(&mut queues, (target, key.1));
+        let empty = {
+            let mut w = queue.irqsave_lock();
+            let mut to = to.irqsave_lock();
+            while moved < most {
+                let Some(entry) = w.pop_front() else {
+                    break;
+                };
+                to.push_back(entry);
+                moved += 1;
+            }
+            w.is_empty()
+        };
+        if empty {
+            queues.remove(&key);
+        }
+        let Some(next) = key.1.checked_add(1) else {
+            break;
+        };
+        from = next;
+    }
+    drop(queues);
+    if woken > 0 {
+        scheduler::yield_me_now_or_later();
+    }
+    Ok((woken, moved))
+}
+
+/// Operates on the futex at `uaddr`. `timeout` is relative for
+/// `FUTEX_WAIT`, and absolute for `FUTEX_WAIT_BITSET`, on `CLOCK_MONOTONIC`
+/// unless `FUTEX_CLOCK_REALTIME` is set. The requeue operations take the
+/// most waiters to move in its place instead. Returns 0 for the waits, the
+/// number of waiters woken for the others, plus those moved for
+/// `FUTEX_CMP_REQUEUE`, or a negative errno.
+pub fn futex(
+    uaddr: *const u32,
+    futex_op: c_int,
+    val: u32,
+    timeout: *const Timespec,
+    uaddr2: *const u32,
+    val3: u32,
+) -> c_int {
+    let word = match word(uaddr) {
+        Ok(word) => word,
+        Err(e) => return e,
+    };
+    let cmd = futex_op & FUTEX_CMD_MASK;
+    let realtime = futex_op & FUTEX_CLOCK_REALTIME != 0;
+    if realtime && cmd != FUTEX_WAIT && cmd != FUTEX_WAIT_BITSET {
+        return -libc::ENOSYS;
+    }
+    match cmd {
+        FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+            let bitset = if cmd == FUTEX_WAIT {
+                FUTEX_BITSET_MATCH_ANY
+            } else {
+                val3
+            };
+            if bitset == 0 {
+                return -libc::EINVAL;
+            }
+            match timeout_ticks(timeout, cmd == FUTEX_WAIT_BITSET, realtime) {
+                Ok(ticks) => wait(word, val, bitset, ticks),
+                Err(e) => e,
+            }
+        }
+        FUTEX_WAKE => wake(word, val as usize, FUTEX_BITSET_MATCH_ANY),
+        FUTEX_WAKE_BITSET if val3 == 0 => -libc::EINVAL,
+        FUTEX_WAKE_BITSET => wake(word, val as usize, val3),
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            let target = match word(uaddr2) {
+                Ok(target) => target,
+                Err(e) => return e,
+            };
+            let most = timeout as usize as u32 as usize;
+            let expected = (cmd == FUTEX_CMP_REQUEUE).then_some(val3);
+            match requeue(word, val as usize, target, most, expected) {
+                Ok((woken, _)) if cmd == FUTEX_REQUEUE => woken as c_int,
+                Ok((woken, moved)) => (woken + moved) as c_int,
+                Err(e) => e,
+            }
+        }
+        _ => -libc::ENOSYS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thread;
+    use blueos_test_macro::test;
+    use core::{ptr, sync::atomic::AtomicBool};
+
+    static A: AtomicU32 = AtomicU32::new(0);
+    static B: AtomicU32 = AtomicU32::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    fn op(uaddr: &AtomicU32, futex_op: c_int, val: u32, most: usize, val3: u32) -> c_int {
+        futex(
+            uaddr.as_ptr(),
+            futex_op,
+            val,
+            most as *const Timespec,
+            B.as_ptr(),
+            val3,
+        )
+    }
+
+    #[test]
+    fn test_futex() {
+        assert_eq!(op(&A, FUTEX_WAIT, 1, 0, 0), -libc::EAGAIN);
+        let timeout = Timespec {
+            tv_sec: 0,
+            tv_nsec: 1_000_000,
+        };
+        let ret = futex(A.as_ptr(), FUTEX_WAIT, 0, &timeout, ptr::null(), 0);
+        assert_eq!(ret, -libc::ETIMEDOUT);
+        assert!(QUEUES.irqsave_lock().is_empty());
+        assert_eq!(op(&A, FUTEX_WAIT_BITSET, 0, 0, 0), -libc::EINVAL);
+        assert_eq!(op(&A, FUTEX_WAKE, 1, 0, 0), 0);
+
+        let t = thread::spawn(|| {
+            let ret = futex(
+                A.as_ptr(),
+                FUTEX_WAIT_BITSET,
+                0,
+                ptr::null(),
+                ptr::null(),
+                1,
+            );
+            assert_eq!(ret, 0);
+            DONE.store(true, Ordering::Release);
+        })
+        .unwrap();
+        while t.state() != thread::SUSPENDED {
+            scheduler::yield_me();
+        }
+        // Not woken by a disjoint bitset, nor requeued if the word changed.
+        assert_eq!(op(&A, FUTEX_WAKE_BITSET, 1, 0, 2), 0);
+        assert_eq!(op(&A, FUTEX_CMP_REQUEUE, 0, 1, 1), -libc::EAGAIN);
+        assert_eq!(op(&A, FUTEX_CMP_REQUEUE, 0, 1, 0), 1);
+        assert_eq!(op(&A, FUTEX_WAKE, 1, 0, 0), 0);
+        assert!(!DONE.load(Ordering::Acquire));
+        assert_eq!(op(&B, FUTEX_WAKE | FUTEX_PRIVATE_FLAG, 1, 0, 0), 1);
+        while !DONE.load(Ordering::Acquire) {
+            scheduler::yield_me();
+        }
+        assert!(QUEUES.irqsave_lock().is_empty());
+    }
+
+    #[test]
+    fn test_futex_requeue_timeout() {
+        static C: AtomicU32 = AtomicU32::new(0);
+        static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+        let t = thread::spawn(|| {
+            let timeout = Timespec {
+                tv_sec: 0,
+                tv_nsec: 50_000_000,
+            };
+            let ret = futex(C.as_ptr(), FUTEX_WAIT, 0, &timeout, ptr::null(), 0);
+            assert_eq!(ret, -libc::ETIMEDOUT);
+            TIMED_OUT.store(true, Ordering::Release);
+        })
+        .unwrap();
+        while t.state() != thread::SUSPENDED {
+            scheduler::yield_me();
+        }
+        assert_eq!(op(&C, FUTEX_CMP_REQUEUE, 0, 1, 0), 1);
+        while !TIMED_OUT.load(Ordering::Acquire) {
+            scheduler::yield_me();
+        }
+        // The waiter left the queue of B it was moved to.
+        assert!(QUEUES.irqsave_lock().is_empty());
+        assert_eq!(op(&B, FUTEX_WAKE, 1, 0, 0), 0);
+    }
+}
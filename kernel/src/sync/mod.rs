@@ -14,6 +14,18 @@
 
 pub mod atomic_wait;
 pub use atomic_wait::{atomic_wait, atomic_wake};
+pub mod condvar;
+pub use condvar::Condvar;
+pub mod futex;
+pub mod mqueue;
+pub mod mutex;
+pub use mutex::Mutex;
+pub mod once;
+pub use once::{KOnce, Lazy};
+pub mod posix;
+pub mod rcu;
+pub mod rwlock;
+pub use rwlock::RwSleepLock;
 pub mod semaphore;
 pub mod spinlock;
 pub use semaphore::Semaphore;
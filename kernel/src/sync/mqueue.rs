@@ -0,0 +1,548 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Message queues, and their C API, compatible with POSIX message queues.
+//!
+//! A [`MessageQueue`] holds up to a fixed number of messages of up to a
+//! fixed size, its memory is allocated once when it's created. Messages
+//! are received highest priority first, and in the order they were sent
+//! within a priority.
+//!
+//! In the C API, queues are named, and `mqd_t` descriptors are numbers of
+//! their own rather than file descriptors, to be closed with [`mq_close`].
+//! An unlinked queue lives on until its last descriptor is closed. There
+//! are no permissions, `mode` is ignored, and neither is `mq_notify`
+//! supported. The functions return a negative errno on failure.
+
+use super::{atomic_wait, atomic_wake, RwSleepLock, SpinLock};
+use crate::{
+    error::{code, Error},
+    irq, time,
+    time::syscalls::abstime_to_ticks,
+    vfs::syscalls::Timespec,
+};
+use alloc::{
+    boxed::Box,
+    collections::{btree_map::Entry, BTreeMap, VecDeque},
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    ffi::{c_char, c_int, c_long, c_size_t, c_ssize_t, c_uint, CStr},
+    ptr, slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Priorities range from 0 to `MQ_PRIO_MAX - 1`.
+pub const MQ_PRIO_MAX: u32 = 32768;
+/// Attributes of queues created without any.
+pub const MQ_DEFAULT_MAXMSG: usize = 8;
+pub const MQ_DEFAULT_MSGSIZE: usize = 64;
+/// Maximum size of the messages of a queue put together.
+pub const MQ_MAX_BYTES: usize = 64 * 1024;
+
+const NAME_MAX: usize = 255;
+
+struct Header {
+    prio: u32,
+    len: usize,
+    slot: usize,
+}
+
+struct Messages {
+    slots: Box<[u8]>,
+    free: Vec<usize>,
+    queued: VecDeque<Header>,
+}
+
+pub struct MessageQueue {
+    msg_size: usize,
+    capacity: usize,
+    messages: SpinLock<Messages>,
+    // Bumped at each message sent, receivers sleep on it while the queue
+    // is empty.
+    sent: AtomicUsize,
+    // Bumped at each message received, senders sleep on it while the queue
+    // is full.
+    received: AtomicUsize,
+}
+
+// Waits for `seq` to move on from `seen`, or for the tick count to reach
+// `deadline`.
+fn wait(seq: &AtomicUsize, seen: usize, deadline: Option<usize>) -> Result<(), Error> {
+    let timeout = match deadline {
+        None => None,
+        Some(deadline) => {
+            let now = time::get_sys_ticks();
+            if now >= deadline {
+                return Err(code::ETIMEDOUT);
+            }
+            Some(deadline - now)
+        }
+    };
+    match atomic_wait(seq, seen, timeout) {
+        Err(e) if e == code::ETIMEDOUT => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl MessageQueue {
+    /// Creates a queue of `capacity` messages of up to `msg_size` bytes.
+    pub fn new(msg_size: usize, capacity: usize) -> Result<Self, Error> {
+        match msg_size.checked_mul(capacity) {
+            Some(0) | None => return Err(code::EINVAL),
+            Some(bytes) if bytes > MQ_MAX_BYTES => return Err(code::EINVAL),
+            Some(_) => {}
+        }
+        Ok(Self {
+            msg_size,
+            capacity,
+            messages: SpinLock::new(Messages {
+                slots: vec![0; msg_size * capacity].into_boxed_slice(),
+                free: (0..capacity).rev().collect(),
+                queued: VecDeque::with_capacity(capacity),
+            }),
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn msg_size(&self) -> usize {
+        self.msg_size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of messages queued.
+    pub fn len(&self) -> usize {
+        self.messages.irqsave_lock().queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queues `msg`. Fails with EMSGSIZE if it's too long, and with EAGAIN
+    /// if the queue is full. Doesn't sleep.
+    pub fn try_send(&self, msg: &[u8], prio: u32) -> Result<(), Error> {
+        if msg.len() > self.msg_size {
+            return Err(code::EMSGSIZE);
+        }
+        if prio >= MQ_PRIO_MAX {
+            return Err(code::EINVAL);
+        }
+        {
+            let mut messages = self.messages.irqsave_lock();
+            let Some(slot) = messages.free.pop() else {
+                return Err(code::EAGAIN);
+            };
+            let start = slot * self.msg_size;
+            messages.slots[start..start + msg.len()].copy_from_slice(msg);
+            let at = messages
+                .queued
+                .iter()
+                .position(|header| header.prio < prio)
+                .unwrap_or(messages.queued.len());
+            messages.queued.insert(
+                at,
+                Header {
+                    prio,
+                    len: msg.len(),
+                    slot,
+                },
+            );
+        }
+        self.sent.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.sent, 1);
+        Ok(())
+    }
+
+    /// Queues `msg`, waiting for room in the queue for up to `timeout`
+    /// ticks, or forever without one. Fails with ETIMEDOUT when the time is
+    /// up.
+    pub fn send(&self, msg: &[u8], prio: u32, timeout: Option<usize>) -> Result<(), Error> {
+        assert!(!irq::is_in_irq());
+        let deadline = timeout.map(|ticks| time::get_sys_ticks().saturating_add(ticks));
+        loop {
+            let received = self.received.load(Ordering::Acquire);
+            match self.try_send(msg, prio) {
+                Err(e) if e == code::EAGAIN => wait(&self.received, received, deadline)?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Takes the first message into `buf`, and returns its length and
+    /// priority. Fails with EMSGSIZE if `buf` is shorter than the message
+    /// size of the queue, and with EAGAIN if it's empty. Doesn't sleep.
+    pub fn try_receive(&self, buf: &mut [u8]) -> Result<(usize, u32), Error> {
+        if buf.len() < self.msg_size {
+            return Err(code::EMSGSIZE);
+        }
+        let (len, prio) = {
+            let mut messages = self.messages.irqsave_lock();
+            let Some(header) = messages.queued.pop_front() else {
+                return Err(code::EAGAIN);
+            };
+            let start = header.slot * self.msg_size;
+            buf[..header.len].copy_from_slice(&messages.slots[start..start + header.len]);
+            messages.free.push(header.slot);
+            (header.len, header.prio)
+        };
+        self.received.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.received, 1);
+        Ok((len, prio))
+    }
+
+    /// Takes the first message into `buf`, waiting for one for up to
+    /// `timeout` ticks, or forever without one. Fails with ETIMEDOUT when
+    /// the time is up.
+    pub fn receive(&self, buf: &mut [u8], timeout: Option<usize>) -> Result<(usize, u32), Error> {
+        assert!(!irq::is_in_irq());
+        let deadline = timeout.map(|ticks| time::get_sys_ticks().saturating_add(ticks));
+        loop {
+            let sent = self.sent.load(Ordering::Acquire);
+            match self.try_receive(buf) {
+                Err(e) if e == code::EAGAIN => wait(&self.sent, sent, deadline)?,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Attributes of a queue, `mq_flags` being those of a descriptor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MqAttr {
+    pub mq_flags: c_long,
+    pub mq_maxmsg: c_long,
+    pub mq_msgsize: c_long,
+    pub mq_curmsgs: c_long,
+    pub reserved: [c_long; 4],
+}
+
+struct Descriptor {
+    queue: Arc<MessageQueue>,
+    flags: c_int,
+}
+
+// Only taken by threads, which allocate under them, so they are sleeping
+// locks.
+static QUEUES: RwSleepLock<BTreeMap<String, Arc<MessageQueue>>> = RwSleepLock::new(BTreeMap::new());
+static DESCRIPTORS: RwSleepLock<BTreeMap<c_int, Descriptor>> = RwSleepLock::new(BTreeMap::new());
+
+// Names are a slash followed by up to NAME_MAX other characters.
+fn queue_name(name: *const c_char) -> Result<String, c_int> {
+    if name.is_null() {
+        return Err(-libc::EFAULT);
+    }
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_str()
+        .map_err(|_| -libc::EINVAL)?;
+    let Some(rest) = name.strip_prefix('/') else {
+        return Err(-libc::EINVAL);
+    };
+    if rest.is_empty() || rest.contains('/') {
+        return Err(-libc::EINVAL);
+    }
+    if rest.len() > NAME_MAX {
+        return Err(-libc::ENAMETOOLONG);
+    }
+    Ok(String::from(name))
+}
+
+fn descriptor(mqdes: c_int) -> Result<(Arc<MessageQueue>, c_int), c_int> {
+    DESCRIPTORS
+        .read()
+        .get(&mqdes)
+        .map(|d| (d.queue.clone(), d.flags))
+        .ok_or(-libc::EBADF)
+}
+
+// The ticks to wait for, none without a deadline.
+fn timeout_ticks(abstime: *const Timespec) -> Result<Option<usize>, c_int> {
+    if abstime.is_null() {
+        return Ok(None);
+    }
+    abstime_to_ticks(abstime).map(Some)
+}
+
+/// Opens the queue called `name`, creating it with `O_CREAT`, with the
+/// attributes at `attr` or the default ones. Returns its descriptor.
+pub fn mq_open(name: *const c_char, oflag: c_int, _mode: c_uint, attr: *const MqAttr) -> c_int {
+    let name = match queue_name(name) {
+        Ok(name) => name,
+        Err(e) => return e,
+    };
+    let accmode = oflag & libc::O_ACCMODE;
+    if accmode != libc::O_RDONLY && accmode != libc::O_WRONLY && accmode != libc::O_RDWR {
+        return -libc::EINVAL;
+    }
+    let exclusive = oflag & (libc::O_CREAT | libc::O_EXCL) == libc::O_CREAT | libc::O_EXCL;
+    let existing = QUEUES.read().get(&name).cloned();
+    let queue = match existing {
+        Some(_) if exclusive => return -libc::EEXIST,
+        Some(queue) => queue,
+        None if oflag & libc::O_CREAT == 0 => return -libc::ENOENT,
+        None => {
+            // The queue is created without the lock, then only added if no
+            // other thread added one in the meantime.
+            let (msg_size, capacity) = match unsafe { attr.as_ref() } {
+                None => (MQ_DEFAULT_MSGSIZE, MQ_DEFAULT_MAXMSG),
+                Some(attr) if attr.mq_msgsize <= 0 || attr.mq_maxmsg <= 0 => {
+                    return -libc::EINVAL;
+                }
+                Some(attr) => (attr.mq_msgsize as usize, attr.mq_maxmsg as usize),
+            };
+            let queue = match MessageQueue::new(msg_size, capacity) {
+                Ok(queue) => Arc::new(queue),
+                Err(e) => return e.to_errno(),
+            };
+            match QUEUES.write().entry(name) {
+                Entry::Occupied(_) if exclusive => return -libc::EEXIST,
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => entry.insert(queue).clone(),
+            }
+        }
+    };
+    let mut descriptors = DESCRIPTORS.write();
+    let Some(mqdes) = (0..c_int::MAX).find(|id| !descriptors.contains_key(id)) else {
+        return -libc::EMFILE;
+    };
+    descriptors.insert(
+        mqdes,
+        Descriptor {
+            queue,
+            flags: oflag & (libc::O_ACCMODE | libc::O_NONBLOCK),
+        },
+    );
+    mqdes
+}
+
+pub fn mq_close(mqdes: c_int) -> c_int {
+    match DESCRIPTORS.write().remove(&mqdes) {
+        Some(_) => 0,
+        None => -libc::EBADF,
+    }
+}
+
+/// Removes the name of a queue, which is freed once its last descriptor
+/// is closed.
+pub fn mq_unlink(name: *const c_char) -> c_int {
+    let name = match queue_name(name) {
+        Ok(name) => name,
+        Err(e) => return e,
+    };
+    match QUEUES.write().remove(&name) {
+        Some(_) => 0,
+        None => -libc::ENOENT,
+    }
+}
+
+/// Sends a message, waiting for room in the queue until `abstime` on
+/// `CLOCK_REALTIME`, or forever if it's null, unless the descriptor is
+/// non-blocking.
+pub fn mq_timedsend(
+    mqdes: c_int,
+    msg_ptr: *const c_char,
+    msg_len: c_size_t,
+    msg_prio: c_uint,
+    abstime: *const Timespec,
+) -> c_int {
+    let (queue, flags) = match descriptor(mqdes) {
+        Ok(d) => d,
+        Err(e) => return e,
+    };
+    if flags & libc::O_ACCMODE == libc::O_RDONLY {
+        return -libc::EBADF;
+    }
+    let msg = match msg_len {
+        0 => &[][..],
+        _ if msg_ptr.is_null() => return -libc::EFAULT,
+        _ => unsafe { slice::from_raw_parts(msg_ptr as *const u8, msg_len) },
+    };
+    let result = match queue.try_send(msg, msg_prio) {
+        Err(e) if e == code::EAGAIN && flags & libc::O_NONBLOCK == 0 => {
+            match timeout_ticks(abstime) {
+                Ok(timeout) => queue.send(msg, msg_prio, timeout),
+                Err(e) => return e,
+            }
+        }
+        result => result,
+    };
+    result.map_or_else(|e| e.to_errno(), |_| 0)
+}
+
+/// Receives the first message, waiting for one until `abstime` on
+/// `CLOCK_REALTIME`, or forever if it's null, unless the descriptor is
+/// non-blocking. Returns its length.
+pub fn mq_timedreceive(
+    mqdes: c_int,
+    msg_ptr: *mut c_char,
+    msg_len: c_size_t,
+    msg_prio: *mut c_uint,
+    abstime: *const Timespec,
+) -> c_ssize_t {
+    let (queue, flags) = match descriptor(mqdes) {
+        Ok(d) => d,
+        Err(e) => return e as c_ssize_t,
+    };
+    if flags & libc::O_ACCMODE == libc::O_WRONLY {
+        return -libc::EBADF as c_ssize_t;
+    }
+    if msg_ptr.is_null() {
+        return -libc::EFAULT as c_ssize_t;
+    }
+    let buf = unsafe { slice::from_raw_parts_mut(msg_ptr as *mut u8, msg_len) };
+    let result = match queue.try_receive(buf) {
+        Err(e) if e == code::EAGAIN && flags & libc::O_NONBLOCK == 0 => {
+            match timeout_ticks(abstime) {
+                Ok(timeout) => queue.receive(buf, timeout),
+                Err(e) => return e as c_ssize_t,
+            }
+        }
+        result => result,
+    };
+    match result {
+        Ok((len, prio)) => {
+            if !msg_prio.is_null() {
+                unsafe { msg_prio.write(prio) };
+            }
+            len as c_ssize_t
+        }
+        Err(e) => e.to_errno() as c_ssize_t,
+    }
+}
+
+/// Stores the attributes of the queue at `oldattr` if it isn't null, then
+/// sets the `O_NONBLOCK` flag of the descriptor from `newattr` if it isn't
+/// null. The other attributes can't be changed.
+pub fn mq_getsetattr(mqdes: c_int, newattr: *const MqAttr, oldattr: *mut MqAttr) -> c_int {
+    let mut descriptors = DESCRIPTORS.write();
+    let Some(d) = descriptors.get_mut(&mqdes) else {
+        return -libc::EBADF;
+    };
+    if !oldattr.is_null() {
+        let attr = MqAttr {
+            mq_flags: (d.flags & libc::O_NONBLOCK) as c_long,
+            mq_maxmsg: d.queue.capacity() as c_long,
+            mq_msgsize: d.queue.msg_size() as c_long,
+            mq_curmsgs: d.queue.len() as c_long,
+            ..Default::default()
+        };
+        unsafe { ptr::write(oldattr, attr) };
+    }
+    if let Some(attr) = unsafe { newattr.as_ref() } {
+        d.flags = (d.flags & !libc::O_NONBLOCK) | (attr.mq_flags as c_int & libc::O_NONBLOCK);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thread;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_message_queue() {
+        assert!(MessageQueue::new(0, 1).is_err());
+        let queue = MessageQueue::new(4, 2).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(queue.try_send(b"hello", 0), Err(code::EMSGSIZE));
+        queue.try_send(b"low", 1).unwrap();
+        queue.try_send(b"high", 2).unwrap();
+        assert_eq!(queue.try_send(b"full", 3), Err(code::EAGAIN));
+        assert_eq!(queue.send(b"full", 3, Some(1)), Err(code::ETIMEDOUT));
+        assert_eq!(queue.try_receive(&mut buf[..3]), Err(code::EMSGSIZE));
+        assert_eq!(queue.try_receive(&mut buf), Ok((4, 2)));
+        assert_eq!(&buf, b"high");
+        assert_eq!(queue.receive(&mut buf, Some(1)), Ok((3, 1)));
+        assert_eq!(&buf[..3], b"low");
+        assert_eq!(queue.receive(&mut buf, Some(1)), Err(code::ETIMEDOUT));
+
+        // A receiver sleeping on the empty queue gets the next message.
+        let queue = Arc::new(queue);
+        let sender = queue.clone();
+        thread::spawn(move || sender.send(b"ping", 0, None).unwrap()).unwrap();
+        assert_eq!(queue.receive(&mut buf, None), Ok((4, 0)));
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn test_mq_c_api() {
+        let name = c"/testmq";
+        let attr = MqAttr {
+            mq_maxmsg: 1,
+            mq_msgsize: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            mq_open(name.as_ptr(), libc::O_RDWR, 0, &attr),
+            -libc::ENOENT
+        );
+        assert_eq!(
+            mq_open(c"noslash".as_ptr(), libc::O_RDWR, 0, &attr),
+            -libc::EINVAL
+        );
+        let mqd = mq_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0, &attr);
+        assert!(mqd >= 0);
+        let flags = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL;
+        assert_eq!(mq_open(name.as_ptr(), flags, 0, &attr), -libc::EEXIST);
+        let reader = mq_open(
+            name.as_ptr(),
+            libc::O_RDONLY | libc::O_NONBLOCK,
+            0,
+            ptr::null(),
+        );
+        assert!(reader >= 0);
+
+        let msg = b"message";
+        let send = |mqd| mq_timedsend(mqd, msg.as_ptr() as _, msg.len(), 7, ptr::null());
+        assert_eq!(send(reader), -libc::EBADF);
+        assert_eq!(send(mqd), 0);
+        let mut attr = MqAttr::default();
+        assert_eq!(mq_getsetattr(mqd, ptr::null(), &mut attr), 0);
+        assert_eq!(
+            (attr.mq_maxmsg, attr.mq_msgsize, attr.mq_curmsgs),
+            (1, 8, 1)
+        );
+
+        let mut buf = [0 as c_char; 8];
+        let mut prio = 0;
+        let receive = |buf: &mut [c_char], prio: *mut c_uint| {
+            mq_timedreceive(reader, buf.as_mut_ptr(), buf.len(), prio, ptr::null())
+        };
+        assert_eq!(
+            receive(&mut buf[..4], &mut prio),
+            -libc::EMSGSIZE as c_ssize_t
+        );
+        assert_eq!(receive(&mut buf, &mut prio), msg.len() as c_ssize_t);
+        assert_eq!(prio, 7);
+        assert_eq!(receive(&mut buf, &mut prio), -libc::EAGAIN as c_ssize_t);
+
+        assert_eq!(mq_unlink(name.as_ptr()), 0);
+        assert_eq!(mq_unlink(name.as_ptr()), -libc::ENOENT);
+        // The queue outlives its name.
+        assert_eq!(send(mqd), 0);
+        assert_eq!(mq_close(mqd), 0);
+        assert_eq!(mq_close(mqd), -libc::EBADF);
+        assert_eq!(receive(&mut buf, &mut prio), msg.len() as c_ssize_t);
+        assert_eq!(mq_close(reader), 0);
+    }
+}
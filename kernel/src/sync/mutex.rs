@@ -0,0 +1,193 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::SpinLock;
+use crate::{
+    error::{code, Error},
+    irq, scheduler,
+    scheduler::WaitQueue,
+    thread::{Thread, ThreadNode, WaitReason},
+    time::WAITING_FOREVER,
+    types::ThreadPriority,
+};
+use core::cell::Cell;
+
+/// A sleeping mutex, which isn't bound to the data it protects.
+///
+/// With priority inheritance, a thread blocking on the mutex lends its
+/// priority to the owner until the owner unlocks it. Only the priority the
+/// owner had when it locked the mutex is restored, so inheritance doesn't
+/// chain through several mutexes.
+#[derive(Debug)]
+pub struct Mutex {
+    owner: Cell<Option<ThreadNode>>,
+    owner_priority: Cell<ThreadPriority>,
+    inherit: bool,
+    // We let the Spinlock protect the whole mutex.
+    pending: SpinLock<WaitQueue>,
+}
+
+impl Mutex {
+    pub const fn const_new(inherit: bool) -> Self {
+        Self {
+            owner: Cell::new(None),
+            owner_priority: Cell::new(0),
+            inherit,
+            pending: SpinLock::new(WaitQueue::new()),
+        }
+    }
+
+    pub const fn new() -> Self {
+        Self::const_new(false)
+    }
+
+    pub const fn with_priority_inheritance() -> Self {
+        Self::const_new(true)
+    }
+
+    pub fn init(&self) -> bool {
+        self.pending.irqsave_lock().init()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        let _w = self.pending.irqsave_lock();
+        let owner = self.owner.take();
+        let locked = owner.is_some();
+        self.owner.set(owner);
+        locked
+    }
+
+    /// Returns true if the current thread owns the mutex.
+    pub fn is_owned(&self) -> bool {
+        let _w = self.pending.irqsave_lock();
+        let owner = self.owner.take();
+        let owned = owner
+            .as_ref()
+            .is_some_and(|t| Thread::id(t) == scheduler::current_thread_id());
+        self.owner.set(owner);
+        owned
+    }
+
+    // Must be called with the pending lock held.
+    fn take_ownership(&self, current: &ThreadNode) -> bool {
+        let owner = self.owner.take();
+        if owner.is_some() {
+            self.owner.set(owner);
+            return false;
+        }
+        self.owner_priority.set(current.priority());
+        self.owner.set(Some(current.clone()));
+        true
+    }
+
+    /// Fails with EBUSY if the mutex is locked.
+    pub fn try_lock(&self) -> Result<(), Error> {
+        let _w = self.pending.irqsave_lock();
+        if !self.take_ownership(&scheduler::current_thread()) {
+            return Err(code::EBUSY);
+        }
+        Ok(())
+    }
+
+    pub fn lock(&self) {
+        let result = self.lock_timeout(WAITING_FOREVER);
+        crate::kassert!(result.is_ok());
+    }
+
+    /// Fails with ETIMEDOUT if the mutex couldn't be taken within `ticks`.
+    pub fn lock_timeout(&self, ticks: usize) -> Result<(), Error> {
+        assert!(!irq::is_in_irq());
+        let current = scheduler::current_thread();
+        let mut w = self.pending.irqsave_lock();
+        while !self.take_ownership(&current) {
+            if let Some(owner) = self.owner.take() {
+                if self.inherit && current.priority() < owner.priority() {
+                    scheduler::set_thread_priority(&owner, current.priority());
+                }
+                self.owner.set(Some(owner));
+            }
+            let timed_out = scheduler::suspend_me_with_timeout(
+                w,
+                ticks,
+                WaitReason::Sync(self as *const _ as usize),
+            );
+            w = self.pending.irqsave_lock();
+            if timed_out {
+                if !self.take_ownership(&current) {
+                    return Err(code::ETIMEDOUT);
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Releases the mutex without rescheduling. Returns false if the current
+    // thread isn't the owner.
+    pub(crate) fn release(&self) -> bool {
+        let mut w = self.pending.irqsave_lock();
+        let Some(owner) = self.owner.take() else {
+            return false;
+        };
+        if Thread::id(&owner) != scheduler::current_thread_id() {
+            self.owner.set(Some(owner));
+            return false;
+        }
+        if self.inherit && owner.priority() != self.owner_priority.get() {
+            scheduler::set_thread_priority(&owner, self.owner_priority.get());
+        }
+        scheduler::wake_one(&mut w);
+        true
+    }
+
+    /// Fails with EPERM if the current thread isn't the owner.
+    pub fn unlock(&self) -> Result<(), Error> {
+        if !self.release() {
+            return Err(code::EPERM);
+        }
+        scheduler::yield_me_now_or_later();
+        Ok(())
+    }
+}
+
+impl Default for Mutex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl !Send for Mutex {}
+unsafe impl Sync for Mutex {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_mutex_lock_unlock() {
+        let mutex = Mutex::with_priority_inheritance();
+        mutex.init();
+        assert!(!mutex.is_locked());
+        mutex.lock();
+        assert!(mutex.is_locked());
+        assert!(mutex.is_owned());
+        assert_eq!(mutex.try_lock(), Err(code::EBUSY));
+        assert_eq!(mutex.lock_timeout(1), Err(code::ETIMEDOUT));
+        assert_eq!(mutex.unlock(), Ok(()));
+        assert_eq!(mutex.unlock(), Err(code::EPERM));
+        assert_eq!(mutex.try_lock(), Ok(()));
+        assert_eq!(mutex.unlock(), Ok(()));
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{arch, support::DisableInterruptGuard};
+use blueos_kconfig::NUM_CORES;
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    mem::MaybeUninit,
+    ops::Deref,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+const POISONED: u8 = 3;
+const NO_OWNER: usize = usize::MAX;
+
+// The initializers running on each core, innermost first. Interrupts are
+// disabled while they run, so they all belong to the current thread.
+static RUNNING_INITS: [AtomicPtr<RunningInit>; NUM_CORES] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; NUM_CORES];
+
+// Lives on the stack of an initializer, and poisons its cell unless it
+// completed, when it unwinds.
+struct RunningInit {
+    state: *const AtomicU8,
+    outer: *mut RunningInit,
+    cpu: usize,
+}
+
+impl Drop for RunningInit {
+    fn drop(&mut self) {
+        RUNNING_INITS[self.cpu].store(self.outer, Ordering::Relaxed);
+        // SAFETY: The cell outlives its initializer.
+        let state = unsafe { &*self.state };
+        let _ = state.compare_exchange(RUNNING, POISONED, Ordering::Release, Ordering::Relaxed);
+    }
+}
+
+/// Poisons the cells the current thread is initializing, as it's retiring
+/// and won't complete them.
+pub(crate) fn poison_running() {
+    let _guard = DisableInterruptGuard::new();
+    let mut init = RUNNING_INITS[arch::current_cpu_id()].swap(ptr::null_mut(), Ordering::Relaxed);
+    while !init.is_null() {
+        // SAFETY: The initializers are still on the stack of the thread.
+        let running = unsafe { &*init };
+        unsafe { &*running.state }.store(POISONED, Ordering::Release);
+        init = running.outer;
+    }
+}
+
+/// A cell which is written exactly once, usable from any core and from
+/// interrupt context.
+///
+/// Unlike `spin::Once`, the initializer runs with local interrupts disabled,
+/// so an interrupt handler on the initializing core can never spin on an
+/// initialization it has preempted. Recursive initialization from the
+/// initializer itself is detected and panics instead of deadlocking.
+///
+/// A cell whose initializer panics, or whose thread retires from it, is
+/// poisoned: every later attempt to initialize it panics.
+pub struct KOnce<T> {
+    state: AtomicU8,
+    owner: AtomicUsize,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for KOnce<T> {}
+unsafe impl<T: Send> Send for KOnce<T> {}
+
+impl<T> KOnce<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+            owner: AtomicUsize::new(NO_OWNER),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.state.load(Ordering::Acquire) == POISONED
+    }
+
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The caller must make sure the cell has been initialized.
+    #[inline]
+    pub unsafe fn get_unchecked(&self) -> &T {
+        (*self.data.get()).assume_init_ref()
+    }
+
+    /// Runs `f` if the cell is uninitialized and returns the stored value.
+    /// If another core is running the initializer, waits for it to finish.
+    /// Panics if the cell is poisoned.
+    pub fn call_once<F: FnOnce() -> T>(&self, f: F) -> &T {
+        if let Some(val) = self.get() {
+            return val;
+        }
+        self.call_once_slow(f)
+    }
+
+    #[cold]
+    fn call_once_slow<F: FnOnce() -> T>(&self, f: F) -> &T {
+        let _guard = DisableInterruptGuard::new();
+        let cpu = arch::current_cpu_id();
+        loop {
+            match self.state.compare_exchange(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    self.owner.store(cpu, Ordering::Relaxed);
+                    let mut running = RunningInit {
+                        state: &self.state,
+                        outer: RUNNING_INITS[cpu].load(Ordering::Relaxed),
+                        cpu,
+                    };
+                    RUNNING_INITS[cpu].store(&mut running, Ordering::Relaxed);
+                    unsafe { (*self.data.get()).write(f()) };
+                    self.owner.store(NO_OWNER, Ordering::Relaxed);
+                    self.state.store(COMPLETE, Ordering::Release);
+                    drop(running);
+                    return unsafe { self.get_unchecked() };
+                }
+                Err(COMPLETE) => return unsafe { self.get_unchecked() },
+                Err(POISONED) => panic!("KOnce instance has previously been poisoned"),
+                Err(_) => {
+                    // Interrupts are disabled while the initializer runs, so
+                    // the only way to observe our own core here is recursion.
+                    assert_ne!(
+                        self.owner.load(Ordering::Relaxed),
+                        cpu,
+                        "KOnce initialized recursively"
+                    );
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+impl<T> Default for KOnce<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for KOnce<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            unsafe { self.data.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for KOnce<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(val) => f.debug_tuple("KOnce").field(val).finish(),
+            None if self.is_poisoned() => f.write_str("KOnce(<poisoned>)"),
+            None => f.write_str("KOnce(<uninit>)"),
+        }
+    }
+}
+
+/// A value which is initialized on first access, built on [`KOnce`].
+pub struct Lazy<T, F = fn() -> T> {
+    once: KOnce<T>,
+    init: Cell<Option<F>>,
+}
+
+// The initializer is only taken by the core which wins the KOnce race.
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: KOnce::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("Lazy instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_konce_call_once() {
+        let once = KOnce::new();
+        assert!(once.get().is_none());
+        assert_eq!(*once.call_once(|| 42usize), 42);
+        assert_eq!(*once.call_once(|| 7usize), 42);
+        assert_eq!(once.get(), Some(&42));
+        assert!(once.is_completed());
+    }
+
+    #[test]
+    fn test_konce_poison() {
+        let _guard = DisableInterruptGuard::new();
+        let cpu = arch::current_cpu_id();
+
+        // An initializer unwinding.
+        let once: KOnce<usize> = KOnce::new();
+        once.state.store(RUNNING, Ordering::Relaxed);
+        drop(RunningInit {
+            state: &once.state,
+            outer: RUNNING_INITS[cpu].load(Ordering::Relaxed),
+            cpu,
+        });
+        assert!(once.is_poisoned());
+        assert!(once.get().is_none());
+        assert_eq!(alloc::format!("{:?}", once), "KOnce(<poisoned>)");
+
+        // Its thread retiring, which never returns to the initializers.
+        let inner: KOnce<usize> = KOnce::new();
+        let outer: KOnce<usize> = KOnce::new();
+        outer.state.store(RUNNING, Ordering::Relaxed);
+        inner.state.store(RUNNING, Ordering::Relaxed);
+        let mut outer_init = RunningInit {
+            state: &outer.state,
+            outer: ptr::null_mut(),
+            cpu,
+        };
+        let mut inner_init = RunningInit {
+            state: &inner.state,
+            outer: &mut outer_init,
+            cpu,
+        };
+        RUNNING_INITS[cpu].store(&mut inner_init, Ordering::Relaxed);
+        poison_running();
+        assert!(inner.is_poisoned());
+        assert!(outer.is_poisoned());
+        assert!(RUNNING_INITS[cpu].load(Ordering::Relaxed).is_null());
+        core::mem::forget(inner_init);
+        core::mem::forget(outer_init);
+    }
+
+    #[test]
+    fn test_lazy_deref() {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        static LAZY: Lazy<usize> = Lazy::new(|| COUNT.fetch_add(1, Ordering::Relaxed) + 10);
+        assert_eq!(*LAZY, 10);
+        assert_eq!(*LAZY, 10);
+        assert_eq!(COUNT.load(Ordering::Relaxed), 1);
+    }
+}
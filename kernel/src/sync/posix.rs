@@ -0,0 +1,572 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C API for semaphores, mutexes and condition variables, compatible with
+//! POSIX.
+//!
+//! `sem_t`, `pthread_mutex_t` and `pthread_cond_t` hold a single pointer to
+//! the kernel object, which is allocated by the `*_init` functions, or on
+//! first use for objects set up with the static initializers, which are all
+//! zeroes. Like the pthread functions, the `sem_*` functions return an errno
+//! value instead of setting `errno`.
+
+use super::{Condvar, Mutex, Semaphore};
+use crate::{
+    error::Error,
+    time::{syscalls as time_syscalls, WAITING_FOREVER},
+    types::Int,
+    vfs::syscalls::Timespec,
+};
+use alloc::boxed::Box;
+use core::{
+    cell::Cell,
+    ffi::{c_int, c_uint},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libc;
+
+pub const PTHREAD_MUTEX_NORMAL: c_int = 0;
+pub const PTHREAD_MUTEX_RECURSIVE: c_int = 1;
+pub const PTHREAD_MUTEX_ERRORCHECK: c_int = 2;
+pub const PTHREAD_MUTEX_DEFAULT: c_int = PTHREAD_MUTEX_NORMAL;
+
+pub const PTHREAD_PRIO_NONE: c_int = 0;
+pub const PTHREAD_PRIO_INHERIT: c_int = 1;
+
+pub const SEM_VALUE_MAX: c_uint = Int::MAX as c_uint;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Sem {
+    inner: AtomicUsize,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct PthreadMutex {
+    inner: AtomicUsize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PthreadMutexAttr {
+    pub kind: c_int,
+    pub protocol: c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct PthreadCond {
+    inner: AtomicUsize,
+}
+
+/// Attributes of condition variables aren't supported, only the pointer
+/// is checked.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PthreadCondAttr {
+    pub reserved: c_int,
+}
+
+trait Object: Sized {
+    fn init(&self) -> bool;
+}
+
+impl Object for Semaphore {
+    fn init(&self) -> bool {
+        Semaphore::init(self)
+    }
+}
+
+impl Object for PosixMutex {
+    fn init(&self) -> bool {
+        self.mutex.init()
+    }
+}
+
+impl Object for Condvar {
+    fn init(&self) -> bool {
+        Condvar::init(self)
+    }
+}
+
+// The wait queues are only initialized once the object has reached its
+// final address.
+fn publish<T: Object>(slot: &AtomicUsize, object: T) -> &T {
+    let new = Box::into_raw(Box::new(object));
+    let ok = unsafe { &*new }.init();
+    crate::kassert!(ok);
+    match slot.compare_exchange(0, new as usize, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => unsafe { &*new },
+        // Statically initialized object, which someone else has set up.
+        Err(cur) => {
+            drop(unsafe { Box::from_raw(new) });
+            unsafe { &*(cur as *const T) }
+        }
+    }
+}
+
+fn get<T>(slot: &AtomicUsize) -> Option<&T> {
+    match slot.load(Ordering::Acquire) {
+        0 => None,
+        p => Some(unsafe { &*(p as *const T) }),
+    }
+}
+
+fn destroy<T>(slot: &AtomicUsize) {
+    let p = slot.swap(0, Ordering::AcqRel);
+    if p != 0 {
+        drop(unsafe { Box::from_raw(p as *mut T) });
+    }
+}
+
+fn timeout_ticks(abstime: *const Timespec) -> Result<usize, c_int> {
+    time_syscalls::abstime_to_ticks(abstime).map_err(|e| -e)
+}
+
+pub fn sem_init(sem: *mut Sem, _pshared: c_int, value: c_uint) -> c_int {
+    if sem.is_null() || value > SEM_VALUE_MAX {
+        return libc::EINVAL;
+    }
+    let sem = unsafe {
+        sem.write(Sem::default());
+        &*sem
+    };
+    publish(&sem.inner, Semaphore::new(value as Int));
+    0
+}
+
+pub fn sem_destroy(sem: *mut Sem) -> c_int {
+    let Some(sem) = (unsafe { sem.as_ref() }) else {
+        return libc::EINVAL;
+    };
+    destroy::<Semaphore>(&sem.inner);
+    0
+}
+
+// The POSIX return value of `result`, 0 or a positive errno.
+fn errno(result: Result<(), Error>) -> c_int {
+    result.map_or_else(|e| -e.to_errno(), |_| 0)
+}
+
+fn semaphore<'a>(sem: *mut Sem) -> Result<&'a Semaphore, c_int> {
+    let sem = unsafe { sem.as_ref() }.ok_or(libc::EINVAL)?;
+    get(&sem.inner).ok_or(libc::EINVAL)
+}
+
+pub fn sem_wait(sem: *mut Sem) -> c_int {
+    match semaphore(sem) {
+        Ok(sem) => errno(sem.acquire_notimeout()),
+        Err(e) => e,
+    }
+}
+
+pub fn sem_trywait(sem: *mut Sem) -> c_int {
+    match semaphore(sem) {
+        Ok(sem) => errno(sem.try_acquire()),
+        Err(e) => e,
+    }
+}
+
+pub fn sem_timedwait(sem: *mut Sem, abstime: *const Timespec) -> c_int {
+    let sem = match semaphore(sem) {
+        Ok(sem) => sem,
+        Err(e) => return e,
+    };
+    if sem.try_acquire().is_ok() {
+        return 0;
+    }
+    match timeout_ticks(abstime) {
+        Ok(0) => libc::ETIMEDOUT,
+        Ok(ticks) => errno(sem.acquire_timeout(ticks)),
+        Err(e) => e,
+    }
+}
+
+pub fn sem_post(sem: *mut Sem) -> c_int {
+    match semaphore(sem) {
+        Ok(sem) if sem.value() as c_uint >= SEM_VALUE_MAX => libc::EOVERFLOW,
+        Ok(sem) => {
+            sem.release();
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+pub fn sem_getvalue(sem: *mut Sem, value: *mut c_int) -> c_int {
+    if value.is_null() {
+        return libc::EINVAL;
+    }
+    match semaphore(sem) {
+        Ok(sem) => {
+            unsafe { value.write(sem.value() as c_int) };
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+#[derive(Debug)]
+struct PosixMutex {
+    mutex: Mutex,
+    kind: c_int,
+    // Only touched by the owner.
+    recursion: Cell<usize>,
+}
+
+impl PosixMutex {
+    fn new(attr: PthreadMutexAttr) -> Self {
+        Self {
+            mutex: Mutex::const_new(attr.protocol == PTHREAD_PRIO_INHERIT),
+            kind: attr.kind,
+            recursion: Cell::new(0),
+        }
+    }
+
+    // Handles relocking by the owner. Returns None if the mutex has to be
+    // locked.
+    fn relock(&self) -> Option<c_int> {
+        if self.kind == PTHREAD_MUTEX_NORMAL || !self.mutex.is_owned() {
+            return None;
+        }
+        if self.kind == PTHREAD_MUTEX_ERRORCHECK {
+            return Some(libc::EDEADLK);
+        }
+        self.recursion.set(self.recursion.get() + 1);
+        Some(0)
+    }
+}
+
+fn mutex<'a>(mutex: *mut PthreadMutex) -> Result<&'a PosixMutex, c_int> {
+    let mutex = unsafe { mutex.as_ref() }.ok_or(libc::EINVAL)?;
+    Ok(get(&mutex.inner)
+        .unwrap_or_else(|| publish(&mutex.inner, PosixMutex::new(PthreadMutexAttr::default()))))
+}
+
+pub fn pthread_mutexattr_init(attr: *mut PthreadMutexAttr) -> c_int {
+    if attr.is_null() {
+        return libc::EINVAL;
+    }
+    unsafe { attr.write(PthreadMutexAttr::default()) };
+    0
+}
+
+pub fn pthread_mutexattr_settype(attr: *mut PthreadMutexAttr, kind: c_int) -> c_int {
+    let Some(attr) = (unsafe { attr.as_mut() }) else {
+        return libc::EINVAL;
+    };
+    if !(PTHREAD_MUTEX_NORMAL..=PTHREAD_MUTEX_ERRORCHECK).contains(&kind) {
+        return libc::EINVAL;
+    }
+    attr.kind = kind;
+    0
+}
+
+pub fn pthread_mutexattr_setprotocol(attr: *mut PthreadMutexAttr, protocol: c_int) -> c_int {
+    let Some(attr) = (unsafe { attr.as_mut() }) else {
+        return libc::EINVAL;
+    };
+    match protocol {
+        PTHREAD_PRIO_NONE | PTHREAD_PRIO_INHERIT => {
+            attr.protocol = protocol;
+            0
+        }
+        _ => libc::ENOTSUP,
+    }
+}
+
+pub fn pthread_mutex_init(mutex: *mut PthreadMutex, attr: *const PthreadMutexAttr) -> c_int {
+    if mutex.is_null() {
+        return libc::EINVAL;
+    }
+    let attr = unsafe { attr.as_ref() }.copied().unwrap_or_default();
+    let mutex = unsafe {
+        mutex.write(PthreadMutex::default());
+        &*mutex
+    };
+    publish(&mutex.inner, PosixMutex::new(attr));
+    0
+}
+
+pub fn pthread_mutex_destroy(mutex: *mut PthreadMutex) -> c_int {
+    let Some(mutex) = (unsafe { mutex.as_ref() }) else {
+        return libc::EINVAL;
+    };
+    if get::<PosixMutex>(&mutex.inner).is_some_and(|m| m.mutex.is_locked()) {
+        return libc::EBUSY;
+    }
+    destroy::<PosixMutex>(&mutex.inner);
+    0
+}
+
+pub fn pthread_mutex_lock(m: *mut PthreadMutex) -> c_int {
+    let m = match mutex(m) {
+        Ok(m) => m,
+        Err(e) => return e,
+    };
+    if let Some(ret) = m.relock() {
+        return ret;
+    }
+    m.mutex.lock();
+    0
+}
+
+pub fn pthread_mutex_trylock(m: *mut PthreadMutex) -> c_int {
+    let m = match mutex(m) {
+        Ok(m) => m,
+        Err(e) => return e,
+    };
+    if m.kind == PTHREAD_MUTEX_RECURSIVE && m.relock().is_some() {
+        return 0;
+    }
+    errno(m.mutex.try_lock())
+}
+
+pub fn pthread_mutex_timedlock(m: *mut PthreadMutex, abstime: *const Timespec) -> c_int {
+    let m = match mutex(m) {
+        Ok(m) => m,
+        Err(e) => return e,
+    };
+    if let Some(ret) = m.relock() {
+        return ret;
+    }
+    if m.mutex.try_lock().is_ok() {
+        return 0;
+    }
+    match timeout_ticks(abstime) {
+        Ok(0) => libc::ETIMEDOUT,
+        Ok(ticks) => errno(m.mutex.lock_timeout(ticks)),
+        Err(e) => e,
+    }
+}
+
+pub fn pthread_mutex_unlock(m: *mut PthreadMutex) -> c_int {
+    let m = match mutex(m) {
+        Ok(m) => m,
+        Err(e) => return e,
+    };
+    if m.kind == PTHREAD_MUTEX_RECURSIVE && m.mutex.is_owned() && m.recursion.get() != 0 {
+        m.recursion.set(m.recursion.get() - 1);
+        return 0;
+    }
+    errno(m.mutex.unlock())
+}
+
+fn cond<'a>(cond: *mut PthreadCond) -> Result<&'a Condvar, c_int> {
+    let cond = unsafe { cond.as_ref() }.ok_or(libc::EINVAL)?;
+    Ok(get(&cond.inner).unwrap_or_else(|| publish(&cond.inner, Condvar::new())))
+}
+
+pub fn pthread_cond_init(cond: *mut PthreadCond, _attr: *const PthreadCondAttr) -> c_int {
+    if cond.is_null() {
+        return libc::EINVAL;
+    }
+    let cond = unsafe {
+        cond.write(PthreadCond::default());
+        &*cond
+    };
+    publish(&cond.inner, Condvar::new());
+    0
+}
+
+pub fn pthread_cond_destroy(cond: *mut PthreadCond) -> c_int {
+    let Some(cond) = (unsafe { cond.as_ref() }) else {
+        return libc::EINVAL;
+    };
+    destroy::<Condvar>(&cond.inner);
+    0
+}
+
+fn cond_wait(c: *mut PthreadCond, m: *mut PthreadMutex, ticks: usize) -> c_int {
+    let (c, m) = match (cond(c), mutex(m)) {
+        (Ok(c), Ok(m)) => (c, m),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    if !m.mutex.is_owned() {
+        return libc::EPERM;
+    }
+    // The recursion count belongs to the owner, who gets it back along
+    // with the mutex.
+    let recursion = m.recursion.replace(0);
+    let result = c.wait_timeout(&m.mutex, ticks);
+    m.recursion.set(recursion);
+    errno(result)
+}
+
+pub fn pthread_cond_wait(cond: *mut PthreadCond, mutex: *mut PthreadMutex) -> c_int {
+    cond_wait(cond, mutex, WAITING_FOREVER)
+}
+
+pub fn pthread_cond_timedwait(
+    cond: *mut PthreadCond,
+    mutex: *mut PthreadMutex,
+    abstime: *const Timespec,
+) -> c_int {
+    match timeout_ticks(abstime) {
+        Ok(0) => libc::ETIMEDOUT,
+        Ok(ticks) => cond_wait(cond, mutex, ticks),
+        Err(e) => e,
+    }
+}
+
+pub fn pthread_cond_signal(c: *mut PthreadCond) -> c_int {
+    match cond(c) {
+        Ok(c) => {
+            c.notify_one();
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+pub fn pthread_cond_broadcast(c: *mut PthreadCond) -> c_int {
+    match cond(c) {
+        Ok(c) => {
+            c.notify_all();
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+mod ffi {
+    use super::*;
+
+    macro_rules! export {
+        ($($name:ident($($arg:ident: $argty:ty),*);)*) => {
+            $(
+                #[no_mangle]
+                #[linkage = "weak"]
+                pub extern "C" fn $name($($arg: $argty),*) -> c_int {
+                    super::$name($($arg),*)
+                }
+            )*
+        };
+    }
+
+    export! {
+        sem_init(sem: *mut Sem, pshared: c_int, value: c_uint);
+        sem_destroy(sem: *mut Sem);
+        sem_wait(sem: *mut Sem);
+        sem_trywait(sem: *mut Sem);
+        sem_timedwait(sem: *mut Sem, abstime: *const Timespec);
+        sem_post(sem: *mut Sem);
+        sem_getvalue(sem: *mut Sem, value: *mut c_int);
+        pthread_mutexattr_init(attr: *mut PthreadMutexAttr);
+        pthread_mutexattr_settype(attr: *mut PthreadMutexAttr, kind: c_int);
+        pthread_mutexattr_setprotocol(attr: *mut PthreadMutexAttr, protocol: c_int);
+        pthread_mutex_init(mutex: *mut PthreadMutex, attr: *const PthreadMutexAttr);
+        pthread_mutex_destroy(mutex: *mut PthreadMutex);
+        pthread_mutex_lock(mutex: *mut PthreadMutex);
+        pthread_mutex_trylock(mutex: *mut PthreadMutex);
+        pthread_mutex_timedlock(mutex: *mut PthreadMutex, abstime: *const Timespec);
+        pthread_mutex_unlock(mutex: *mut PthreadMutex);
+        pthread_cond_init(cond: *mut PthreadCond, attr: *const PthreadCondAttr);
+        pthread_cond_destroy(cond: *mut PthreadCond);
+        pthread_cond_wait(cond: *mut PthreadCond, mutex: *mut PthreadMutex);
+        pthread_cond_timedwait(
+            cond: *mut PthreadCond,
+            mutex: *mut PthreadMutex,
+            abstime: *const Timespec
+        );
+        pthread_cond_signal(cond: *mut PthreadCond);
+        pthread_cond_broadcast(cond: *mut PthreadCond);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+    use core::time::Duration;
+
+    #[test]
+    fn test_sem() {
+        let mut sem = Sem::default();
+        assert_eq!(sem_trywait(&mut sem), libc::EINVAL);
+        assert_eq!(sem_init(&mut sem, 0, 0), 0);
+        assert_eq!(sem_trywait(&mut sem), libc::EAGAIN);
+        assert_eq!(sem_post(&mut sem), 0);
+        let mut value = 0;
+        assert_eq!(sem_getvalue(&mut sem, &mut value), 0);
+        assert_eq!(value, 1);
+        assert_eq!(sem_wait(&mut sem), 0);
+        let deadline = Timespec::from(Duration::ZERO);
+        assert_eq!(sem_timedwait(&mut sem, &deadline), libc::ETIMEDOUT);
+        assert_eq!(sem_destroy(&mut sem), 0);
+    }
+
+    #[test]
+    fn test_pthread_mutex() {
+        // Statically initialized.
+        let mut mutex = PthreadMutex::default();
+        assert_eq!(pthread_mutex_lock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_trylock(&mut mutex), libc::EBUSY);
+        assert_eq!(pthread_mutex_destroy(&mut mutex), libc::EBUSY);
+        assert_eq!(pthread_mutex_unlock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_unlock(&mut mutex), libc::EPERM);
+        assert_eq!(pthread_mutex_destroy(&mut mutex), 0);
+
+        let mut attr = PthreadMutexAttr::default();
+        assert_eq!(pthread_mutexattr_init(&mut attr), 0);
+        assert_eq!(
+            pthread_mutexattr_settype(&mut attr, PTHREAD_MUTEX_RECURSIVE),
+            0
+        );
+        assert_eq!(
+            pthread_mutexattr_setprotocol(&mut attr, PTHREAD_PRIO_INHERIT),
+            0
+        );
+        assert_eq!(pthread_mutexattr_setprotocol(&mut attr, 2), libc::ENOTSUP);
+        assert_eq!(pthread_mutex_init(&mut mutex, &attr), 0);
+        assert_eq!(pthread_mutex_lock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_lock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_unlock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_unlock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_unlock(&mut mutex), libc::EPERM);
+        assert_eq!(pthread_mutex_destroy(&mut mutex), 0);
+
+        assert_eq!(
+            pthread_mutexattr_settype(&mut attr, PTHREAD_MUTEX_ERRORCHECK),
+            0
+        );
+        assert_eq!(pthread_mutex_init(&mut mutex, &attr), 0);
+        assert_eq!(pthread_mutex_lock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_lock(&mut mutex), libc::EDEADLK);
+        assert_eq!(pthread_mutex_unlock(&mut mutex), 0);
+        assert_eq!(pthread_mutex_destroy(&mut mutex), 0);
+    }
+
+    #[test]
+    fn test_pthread_cond_timedwait() {
+        let mut mutex = PthreadMutex::default();
+        let mut cond = PthreadCond::default();
+        let deadline = Timespec::from(crate::time::get_uptime() + Duration::from_millis(10));
+        assert_eq!(
+            pthread_cond_timedwait(&mut cond, &mut mutex, &deadline),
+            libc::EPERM
+        );
+        assert_eq!(pthread_mutex_lock(&mut mutex), 0);
+        assert_eq!(
+            pthread_cond_timedwait(&mut cond, &mut mutex, &deadline),
+            libc::ETIMEDOUT
+        );
+        // The mutex is held again after the timeout.
+        assert_eq!(pthread_mutex_unlock(&mut mutex), 0);
+        assert_eq!(pthread_cond_signal(&mut cond), 0);
+        assert_eq!(pthread_cond_destroy(&mut cond), 0);
+        assert_eq!(pthread_mutex_destroy(&mut mutex), 0);
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-copy-update, for data read far more often than it's written.
+//!
+//! Readers take no lock: within a [`read_lock`] section they load the
+//! pointers to the shared data and use what they point to. A writer
+//! publishes a new version by swapping a pointer, then calls
+//! [`synchronize`] before freeing the old one, which waits for the
+//! sections which may still use it to end.
+//!
+//! Each core counts its readers, in two counters. Readers enter on the
+//! counter of the current epoch, and a writer flips the epoch then waits
+//! for the counters of the previous one to drain, so that a steady stream
+//! of readers can't hold it up. Read sections run with interrupts
+//! disabled, they must be short and can't sleep.
+
+use super::SpinLock;
+use crate::{arch, support::DisableInterruptGuard};
+use blueos_kconfig::NUM_CORES;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+static READERS: [[AtomicUsize; 2]; NUM_CORES] =
+    [const { [const { AtomicUsize::new(0) }; 2] }; NUM_CORES];
+// Writers flip the epoch one at a time.
+static SYNC_LOCK: SpinLock<()> = SpinLock::new(());
+
+pub struct RcuReadGuard {
+    readers: &'static AtomicUsize,
+    _dig: DisableInterruptGuard,
+}
+
+/// Starts a read section, which lasts until the guard is dropped.
+pub fn read_lock() -> RcuReadGuard {
+    let dig = DisableInterruptGuard::new();
+    let cpu = arch::current_cpu_id();
+    loop {
+        let epoch = EPOCH.load(Ordering::SeqCst);
+        let readers = &READERS[cpu][epoch & 1];
+        readers.fetch_add(1, Ordering::SeqCst);
+        // Had the epoch flipped in between, a writer may have already seen
+        // the counter empty.
+        if EPOCH.load(Ordering::SeqCst) == epoch {
+            return RcuReadGuard { readers, _dig: dig };
+        }
+        readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits for the read sections started before the call to end. Must not be
+/// called from a read section.
+pub fn synchronize() {
+    let _guard = SYNC_LOCK.irqsave_lock();
+    let epoch = EPOCH.fetch_add(1, Ordering::SeqCst);
+    for readers in READERS.iter() {
+        while readers[epoch & 1].load(Ordering::SeqCst) != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_rcu_read_lock() {
+        let epoch = EPOCH.load(Ordering::SeqCst);
+        let guard = read_lock();
+        // Interrupts are off, nothing else reads on this core.
+        assert_eq!(guard.readers.load(Ordering::SeqCst), 1);
+        drop(guard);
+        synchronize();
+        assert!(EPOCH.load(Ordering::SeqCst) > epoch);
+        let guard = read_lock();
+        assert_eq!(guard.readers.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{atomic_wait, atomic_wake};
+use crate::{irq, support::DisableInterruptGuard};
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// The state holds the number of readers in the low bits, then the number of
+// writers waiting, a flag telling whether readers wait and the writer flag.
+// Threads sleep on the state, until it changes.
+const READER: usize = 1;
+const READERS_MASK: usize = 0xffff;
+const WRITER_WAITING: usize = 1 << 16;
+const WRITERS_WAITING_MASK: usize = 0x3fff << 16;
+const READERS_WAITING: usize = 1 << 30;
+const WRITER: usize = 1 << 31;
+
+/// A sleeping reader-writer lock, for critical sections too long to spin
+/// on.
+///
+/// Writers are preferred: once a writer waits, new readers wait for it to
+/// be done, so that a stream of readers can't starve writers. Taking the
+/// lock may sleep, which isn't allowed in interrupt context, but
+/// [`try_read`](Self::try_read) and [`try_write`](Self::try_write) never
+/// sleep, and guards can be dropped anywhere.
+///
+/// The `irqsave_` variants also keep local interrupts disabled for as long
+/// as their guard lives, like [`SpinLock::irqsave_lock`]. Interrupts are
+/// disabled once the lock is taken, so that waiting for it doesn't keep
+/// them disabled, and restored once it's released.
+///
+/// [`SpinLock::irqsave_lock`]: super::SpinLock::irqsave_lock
+pub struct RwSleepLock<T: ?Sized> {
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+// The lock is released in `drop`, before the fields are dropped, which
+// restores interrupts.
+pub struct RwSleepLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwSleepLock<T>,
+    irq_guard: Option<DisableInterruptGuard>,
+}
+
+pub struct RwSleepLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwSleepLock<T>,
+    irq_guard: Option<DisableInterruptGuard>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwSleepLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwSleepLock<T> {}
+
+impl<T> RwSleepLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwSleepLock<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    // Takes a read lock, unless it's written or a writer waits. Returns the
+    // state seen otherwise.
+    fn try_read_from(&self, mut state: usize) -> Result<(), usize> {
+        while state & (WRITER | WRITERS_WAITING_MASK) == 0 {
+            assert_ne!(state & READERS_MASK, READERS_MASK, "Too many readers");
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(actual) => state = actual,
+            }
+        }
+        Err(state)
+    }
+
+    pub fn try_read(&self) -> Option<RwSleepLockReadGuard<'_, T>> {
+        self.try_read_from(self.state.load(Ordering::Relaxed))
+            .ok()
+            .map(|_| RwSleepLockReadGuard {
+                lock: self,
+                irq_guard: None,
+            })
+    }
+
+    #[cfg_attr(irqsoff_tracer, track_caller)]
+    pub fn try_irqsave_read(&self) -> Option<RwSleepLockReadGuard<'_, T>> {
+        let irq_guard = DisableInterruptGuard::new();
+        let mut guard = self.try_read()?;
+        guard.irq_guard = Some(irq_guard);
+        Some(guard)
+    }
+
+    pub fn read(&self) -> RwSleepLockReadGuard<'_, T> {
+        assert!(!irq::is_in_irq());
+        let mut state = self.state.load(Ordering::Relaxed);
+        while let Err(actual) = self.try_read_from(state) {
+            state = actual;
+            if state & READERS_WAITING == 0 {
+                if let Err(actual) = self.state.compare_exchange_weak(
+                    state,
+                    state | READERS_WAITING,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = actual;
+                    continue;
+                }
+                state |= READERS_WAITING;
+            }
+            let _ = atomic_wait(&self.state, state, None);
+            state = self.state.load(Ordering::Relaxed);
+        }
+        RwSleepLockReadGuard {
+            lock: self,
+            irq_guard: None,
+        }
+    }
+
+    #[cfg_attr(irqsoff_tracer, track_caller)]
+    pub fn irqsave_read(&self) -> RwSleepLockReadGuard<'_, T> {
+        let mut guard = self.read();
+        guard.irq_guard = Some(DisableInterruptGuard::new());
+        guard
+    }
+
+    pub fn try_write(&self) -> Option<RwSleepLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwSleepLockWriteGuard {
+                lock: self,
+                irq_guard: None,
+            })
+    }
+
+    #[cfg_attr(irqsoff_tracer, track_caller)]
+    pub fn try_irqsave_write(&self) -> Option<RwSleepLockWriteGuard<'_, T>> {
+        let irq_guard = DisableInterruptGuard::new();
+        let mut guard = self.try_write()?;
+        guard.irq_guard = Some(irq_guard);
+        Some(guard)
+    }
+
+    pub fn write(&self) -> RwSleepLockWriteGuard<'_, T> {
+        assert!(!irq::is_in_irq());
+        if let Some(guard) = self.try_write() {
+            return guard;
+        }
+        let mut state = self.state.fetch_add(WRITER_WAITING, Ordering::Relaxed) + WRITER_WAITING;
+        loop {
+            if state & (WRITER | READERS_MASK) == 0 {
+                match self.state.compare_exchange_weak(
+                    state,
+                    (state - WRITER_WAITING) | WRITER,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        return RwSleepLockWriteGuard {
+                            lock: self,
+                            irq_guard: None,
+                        }
+                    }
+                    Err(actual) => {
+                        state = actual;
+                        continue;
+                    }
+                }
+            }
+            let _ = atomic_wait(&self.state, state, None);
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    #[cfg_attr(irqsoff_tracer, track_caller)]
+    pub fn irqsave_write(&self) -> RwSleepLockWriteGuard<'_, T> {
+        let mut guard = self.write();
+        guard.irq_guard = Some(DisableInterruptGuard::new());
+        guard
+    }
+
+    fn read_unlock(&self) {
+        let state = self.state.fetch_sub(READER, Ordering::Release);
+        // Readers only wait while a writer does.
+        if state & READERS_MASK == READER && state & WRITERS_WAITING_MASK != 0 {
+            let _ = atomic_wake(&self.state, usize::MAX);
+        }
+    }
+
+    fn write_unlock(&self) {
+        let state = self
+            .state
+            .fetch_and(!(WRITER | READERS_WAITING), Ordering::Release);
+        // Everybody is woken, readers go back to sleep if a writer waits.
+        if state & (READERS_WAITING | WRITERS_WAITING_MASK) != 0 {
+            let _ = atomic_wake(&self.state, usize::MAX);
+        }
+    }
+}
+
+impl<T: Default> Default for RwSleepLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for RwSleepLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSleepLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<T: ?Sized> Deref for RwSleepLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwSleepLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwSleepLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arch, scheduler, thread};
+    use blueos_test_macro::test;
+
+    static LOCK: RwSleepLock<usize> = RwSleepLock::new(0);
+
+    #[test]
+    fn test_rwlock_writer_preference() {
+        let first = LOCK.read();
+        let second = LOCK.try_read().unwrap();
+        assert!(LOCK.try_write().is_none());
+
+        let writer = thread::spawn(|| {
+            *LOCK.write() += 1;
+        })
+        .unwrap();
+        while writer.state() != thread::SUSPENDED {
+            scheduler::yield_me();
+        }
+        // The writer waits, new readers wait for it.
+        assert!(LOCK.try_read().is_none());
+        drop(first);
+        drop(second);
+        assert_eq!(*LOCK.read(), 1);
+
+        let mut guard = LOCK.write();
+        *guard += 1;
+        assert!(LOCK.try_read().is_none());
+        drop(guard);
+        assert_eq!(*LOCK.try_read().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_rwlock_irqsave() {
+        let lock = RwSleepLock::new(0);
+        let reader = lock.irqsave_read();
+        assert!(!arch::local_irq_enabled());
+        let other = lock.try_read().unwrap();
+        assert!(lock.try_irqsave_write().is_none());
+        drop(other);
+        drop(reader);
+        assert!(arch::local_irq_enabled());
+
+        let mut writer = lock.irqsave_write();
+        *writer += 1;
+        assert!(!arch::local_irq_enabled());
+        assert!(lock.try_irqsave_read().is_none());
+        drop(writer);
+        assert!(arch::local_irq_enabled());
+        assert_eq!(*lock.try_irqsave_read().unwrap(), 1);
+    }
+}
@@ -14,7 +14,13 @@
 
 use super::SpinLock;
 use crate::{
-    irq, scheduler, scheduler::WaitQueue, thread, thread::Thread, time::WAITING_FOREVER, types::Int,
+    error::{code, Error},
+    irq, scheduler,
+    scheduler::WaitQueue,
+    thread,
+    thread::{Thread, WaitReason},
+    time::WAITING_FOREVER,
+    types::Int,
 };
 use core::cell::Cell;
 
@@ -27,7 +33,8 @@ pub struct Semaphore {
 
 impl Semaphore {
     pub const fn const_new(counter: Int) -> Self {
-        debug_assert!(counter >= 1, "Init resources should not be zero");
+        // kassert! can't be evaluated in a const fn.
+        assert!(counter >= 0, "Init resources should not be negative");
         Self {
             counter: Cell::new(counter),
             pending: SpinLock::new(WaitQueue::new()),
@@ -42,18 +49,24 @@ impl Semaphore {
         self.pending.irqsave_lock().init()
     }
 
-    pub fn try_acquire(&self) -> bool {
+    pub fn value(&self) -> Int {
+        let _w = self.pending.irqsave_lock();
+        self.counter.get()
+    }
+
+    /// Fails with EAGAIN if the counter is zero.
+    pub fn try_acquire(&self) -> Result<(), Error> {
         let w = self.pending.irqsave_lock();
         let old = self.counter.get();
         if old <= 0 {
-            return false;
+            return Err(code::EAGAIN);
         }
         self.counter.set(old - 1);
-        true
+        Ok(())
     }
 
     #[inline(never)]
-    pub fn acquire_notimeout(&self) -> bool {
+    pub fn acquire_notimeout(&self) -> Result<(), Error> {
         assert!(!irq::is_in_irq());
         let mut w = self.pending.irqsave_lock();
         loop {
@@ -69,7 +82,11 @@ impl Semaphore {
                 );
             }
             if old == 0 {
-                let _ = scheduler::suspend_me_with_timeout(w, WAITING_FOREVER);
+                let _ = scheduler::suspend_me_with_timeout(
+                    w,
+                    WAITING_FOREVER,
+                    WaitReason::Sync(self as *const _ as usize),
+                );
                 w = self.pending.irqsave_lock();
                 continue;
             } else {
@@ -77,10 +94,11 @@ impl Semaphore {
                 break;
             }
         }
-        true
+        Ok(())
     }
 
-    pub fn acquire_timeout(&self, t: usize) -> bool {
+    /// Fails with ETIMEDOUT if the counter stays zero for `t` ticks.
+    pub fn acquire_timeout(&self, t: usize) -> Result<(), Error> {
         assert!(!irq::is_in_irq());
         let w = self.pending.irqsave_lock();
         let old = self.counter.get();
@@ -94,15 +112,19 @@ impl Semaphore {
             );
         }
         if old == 0 {
-            let _ = scheduler::suspend_me_with_timeout(w, t);
-            return self.try_acquire();
+            let _ = scheduler::suspend_me_with_timeout(
+                w,
+                t,
+                WaitReason::Sync(self as *const _ as usize),
+            );
+            return self.try_acquire().map_err(|_| code::ETIMEDOUT);
         } else {
             self.counter.set(old - 1);
         }
-        true
+        Ok(())
     }
 
-    pub fn acquire(&self, timeout: Option<usize>) -> bool {
+    pub fn acquire(&self, timeout: Option<usize>) -> Result<(), Error> {
         let Some(t) = timeout else {
             return self.acquire_notimeout();
         };
@@ -202,16 +224,16 @@ mod tests {
 
         // Test successful acquisition
         let result = semaphore.try_acquire();
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 2);
 
         // Test multiple successful acquisitions
         let result2 = semaphore.try_acquire();
-        assert!(result2);
+        assert_eq!(result2, Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
 
         let result3 = semaphore.try_acquire();
-        assert!(result3);
+        assert_eq!(result3, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
     }
 
@@ -222,12 +244,12 @@ mod tests {
 
         // Acquire the only available resource
         let result = semaphore.try_acquire();
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
 
         // Try to acquire when counter is 0
         let result2 = semaphore.try_acquire();
-        assert!(!result2);
+        assert_eq!(result2, Err(code::EAGAIN));
         assert_eq!(semaphore.counter.get(), 0);
     }
 
@@ -238,12 +260,12 @@ mod tests {
 
         // Test successful acquisition without timeout
         let result = semaphore.acquire_notimeout();
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
 
         // Test second acquisition
         let result2 = semaphore.acquire_notimeout();
-        assert!(result2);
+        assert_eq!(result2, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
     }
 
@@ -254,12 +276,12 @@ mod tests {
 
         // Test successful acquisition with timeout
         let result = semaphore.acquire_timeout(100);
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
 
         // Test second acquisition
         let result2 = semaphore.acquire_timeout(100);
-        assert!(result2);
+        assert_eq!(result2, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
     }
 
@@ -270,7 +292,7 @@ mod tests {
 
         // Test acquire with None timeout (should call acquire_notimeout)
         let result = semaphore.acquire(None);
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
     }
 
@@ -281,7 +303,7 @@ mod tests {
 
         // Test acquire with Some timeout (should call acquire_timeout)
         let result = semaphore.acquire(Some(100));
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
     }
 
@@ -306,7 +328,7 @@ mod tests {
 
         // Acquire the resource
         let result = semaphore.try_acquire();
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
 
         // Release the resource
@@ -315,7 +337,7 @@ mod tests {
 
         // Should be able to acquire again
         let result2 = semaphore.try_acquire();
-        assert!(result2);
+        assert_eq!(result2, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
     }
 
@@ -326,14 +348,14 @@ mod tests {
 
         // Complete cycle: acquire -> release -> acquire
         let result1 = semaphore.try_acquire();
-        assert!(result1);
+        assert_eq!(result1, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
 
         semaphore.release();
         assert_eq!(semaphore.counter.get(), 1);
 
         let result2 = semaphore.try_acquire();
-        assert!(result2);
+        assert_eq!(result2, Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
     }
 
@@ -343,17 +365,17 @@ mod tests {
         semaphore.init();
 
         // Multiple acquire operations
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 2);
 
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
 
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
 
         // Try to acquire when empty
-        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Err(code::EAGAIN));
         assert_eq!(semaphore.counter.get(), 0);
 
         // Release operations
@@ -364,7 +386,7 @@ mod tests {
         assert_eq!(semaphore.counter.get(), 2);
 
         // Should be able to acquire again
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
     }
 
@@ -376,7 +398,7 @@ mod tests {
 
         // Should be able to acquire
         let result = semaphore.try_acquire();
-        assert!(result);
+        assert_eq!(result, Ok(()));
         assert_eq!(semaphore.counter.get(), 9);
 
         // Test release to maximum
@@ -391,15 +413,15 @@ mod tests {
 
         // Simulate concurrent access pattern
         // Thread 1: acquire
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 1);
 
         // Thread 2: acquire
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
 
         // Thread 3: try to acquire (should fail)
-        assert!(!semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Err(code::EAGAIN));
         assert_eq!(semaphore.counter.get(), 0);
 
         // Thread 1: release
@@ -407,7 +429,7 @@ mod tests {
         assert_eq!(semaphore.counter.get(), 1);
 
         // Thread 3: should be able to acquire now
-        assert!(semaphore.try_acquire());
+        assert_eq!(semaphore.try_acquire(), Ok(()));
         assert_eq!(semaphore.counter.get(), 0);
     }
 }
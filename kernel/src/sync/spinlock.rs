@@ -68,7 +68,14 @@ impl<T> SpinLock<T> {
     }
 }
 
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
 impl<T: ?Sized> SpinLock<T> {
+    #[cfg_attr(irqsoff_tracer, track_caller)]
     pub fn try_irqsave_lock(&self) -> Option<SpinLockGuard<'_, T>> {
         let irq_guard = DisableInterruptGuard::new();
         compiler_fence(Ordering::SeqCst);
@@ -78,6 +85,7 @@ impl<T: ?Sized> SpinLock<T> {
         Some(guard)
     }
 
+    #[cfg_attr(irqsoff_tracer, track_caller)]
     pub fn irqsave_lock(&self) -> SpinLockGuard<'_, T> {
         loop {
             let Some(l) = self.try_irqsave_lock() else {
@@ -141,6 +149,7 @@ impl<T: Sized, A: IntrusiveAdapter> ISpinLock<T, A> {
     }
 
     #[inline]
+    #[cfg_attr(irqsoff_tracer, track_caller)]
     pub fn irqsave_lock(&self) -> SpinLockGuard<'_, T> {
         let irq_guard = DisableInterruptGuard::new();
         compiler_fence(Ordering::SeqCst);
@@ -16,21 +16,23 @@ extern crate alloc;
 use core::ffi::{c_size_t, c_ssize_t};
 
 use crate::{
-    arch, asynk, net, scheduler,
-    sync::atomic_wait as futex,
+    arch, asynk,
+    crypto::random,
+    klog, net, scheduler,
+    sync::{atomic_wait, futex as sync_futex, mqueue},
     thread::{self, Builder, Entry, Stack, Thread, ThreadNode},
-    time,
-    vfs::syscalls as vfs_syscalls,
+    time::{self, posix_timer, syscalls as time_syscalls},
+    vfs::{epoll::EpollEvent, syscalls as vfs_syscalls},
 };
 use alloc::boxed::Box;
 use blueos_header::{
     syscalls::NR,
-    thread::{ExitArgs, SpawnArgs},
+    thread::{ExitArgs, SpawnArgs, ThreadInfo},
 };
 use core::sync::atomic::AtomicUsize;
 use libc::{
-    addrinfo, c_char, c_int, c_ulong, c_void, clockid_t, mode_t, msghdr, off_t, sigset_t, size_t,
-    sockaddr, socklen_t, timespec, EINVAL,
+    addrinfo, c_char, c_int, c_uint, c_ulong, c_void, clockid_t, dev_t, mode_t, msghdr, nfds_t,
+    off_t, pollfd, sigset_t, size_t, sockaddr, socklen_t, timespec, EINVAL,
 };
 
 #[repr(C)]
@@ -40,7 +42,7 @@ pub struct Context {
     pub args: [usize; 6],
 }
 
-pub use crate::vfs::syscalls::{Stat, Statfs as StatFs};
+pub use crate::vfs::syscalls::{Stat, Statfs as StatFs, Timespec};
 /// this signal data structure will be used in signal handling
 /// now add attributes to disable warnings
 /// copy from librs/signal/mod.rs
@@ -81,11 +83,15 @@ pub struct sigaction {
 macro_rules! syscall_table {
     ($(($nr:tt, $mod:ident),)*) => {
         pub(crate) fn dispatch_syscall(ctx: &Context) -> usize {
-            match ctx.nr {
+            let ret = match ctx.nr {
                 $(val if val == NR::$nr as usize =>
-                    return $crate::syscalls::$mod::handle_context(ctx) as usize,)*
+                    $crate::syscalls::$mod::handle_context(ctx) as usize,)*
                 _ => return usize::MAX,
+            };
+            if ret as isize == -(libc::EAGAIN as isize) {
+                $crate::events::count($crate::events::Event::Eagain);
             }
+            ret
         }
 
         #[macro_export]
@@ -164,7 +170,7 @@ atomic_wait(addr: usize, val: usize, timeout: *const timespec) -> c_long {
     };
     let ptr = addr as *const AtomicUsize;
     let atom = unsafe { &*ptr };
-    futex::atomic_wait(atom, val, timeout).map_or_else(|e|e.to_errno() as c_long, |_| 0)
+    atomic_wait::atomic_wait(atom, val, timeout).map_or_else(|e|e.to_errno() as c_long, |_| 0)
 });
 
 define_syscall_handler!(
@@ -172,16 +178,128 @@ atomic_wake(addr: usize, count: *mut usize) -> c_long {
     let how_many = unsafe { *count };
     let ptr = addr as *const AtomicUsize;
     let atom = unsafe { &*ptr };
-    futex::atomic_wake(atom, how_many).map_or_else(|_| -1, |woken| {
+    atomic_wait::atomic_wake(atom, how_many).map_or_else(|_| -1, |woken| {
         unsafe { *count = woken };
         0
     })
 });
 
-// Only for posix testsuite, we need to implement a stub for clock_gettime
 define_syscall_handler!(
-    clock_gettime(_clk_id: clockid_t, tp: *mut timespec) -> c_long {
-        0
+    futex(
+        uaddr: *const u32,
+        futex_op: c_int,
+        val: u32,
+        timeout: *const timespec,
+        uaddr2: *const u32,
+        val3: u32
+    ) -> c_long {
+        sync_futex::futex(uaddr, futex_op, val, timeout as *const Timespec, uaddr2, val3) as c_long
+});
+
+define_syscall_handler!(
+    mq_open(name: *const c_char, oflag: c_int, mode: mode_t, attr: *const mqueue::MqAttr) -> c_long {
+        mqueue::mq_open(name, oflag, mode as c_uint, attr) as c_long
+});
+
+define_syscall_handler!(
+    mq_close(mqdes: c_int) -> c_long {
+        mqueue::mq_close(mqdes) as c_long
+});
+
+define_syscall_handler!(
+    mq_unlink(name: *const c_char) -> c_long {
+        mqueue::mq_unlink(name) as c_long
+});
+
+define_syscall_handler!(
+    mq_timedsend(
+        mqdes: c_int,
+        msg_ptr: *const c_char,
+        msg_len: size_t,
+        msg_prio: c_uint,
+        abs_timeout: *const timespec
+    ) -> c_long {
+        mqueue::mq_timedsend(mqdes, msg_ptr, msg_len, msg_prio, abs_timeout as *const Timespec) as c_long
+});
+
+define_syscall_handler!(
+    mq_timedreceive(
+        mqdes: c_int,
+        msg_ptr: *mut c_char,
+        msg_len: size_t,
+        msg_prio: *mut c_uint,
+        abs_timeout: *const timespec
+    ) -> c_long {
+        mqueue::mq_timedreceive(mqdes, msg_ptr, msg_len, msg_prio, abs_timeout as *const Timespec) as c_long
+});
+
+define_syscall_handler!(
+    mq_getsetattr(
+        mqdes: c_int,
+        newattr: *const mqueue::MqAttr,
+        oldattr: *mut mqueue::MqAttr
+    ) -> c_long {
+        mqueue::mq_getsetattr(mqdes, newattr, oldattr) as c_long
+});
+
+define_syscall_handler!(
+    clock_gettime(clk_id: clockid_t, tp: *mut timespec) -> c_long {
+        time_syscalls::clock_gettime(clk_id, tp as *mut Timespec) as c_long
+});
+
+define_syscall_handler!(
+    clock_settime(clk_id: clockid_t, tp: *const timespec) -> c_long {
+        time_syscalls::clock_settime(clk_id, tp as *const Timespec) as c_long
+});
+
+define_syscall_handler!(
+    timer_create(clk_id: clockid_t, sevp: *const posix_timer::SigEvent, timerid: *mut c_int) -> c_long {
+        posix_timer::timer_create(clk_id, sevp, timerid) as c_long
+});
+
+define_syscall_handler!(
+    timer_settime(
+        timerid: c_int,
+        flags: c_int,
+        new_value: *const posix_timer::ITimerSpec,
+        old_value: *mut posix_timer::ITimerSpec
+    ) -> c_long {
+        posix_timer::timer_settime(timerid, flags, new_value, old_value) as c_long
+});
+
+define_syscall_handler!(
+    timer_gettime(timerid: c_int, curr_value: *mut posix_timer::ITimerSpec) -> c_long {
+        posix_timer::timer_gettime(timerid, curr_value) as c_long
+});
+
+define_syscall_handler!(
+    timer_getoverrun(timerid: c_int) -> c_long {
+        posix_timer::timer_getoverrun(timerid) as c_long
+});
+
+define_syscall_handler!(
+    timer_delete(timerid: c_int) -> c_long {
+        posix_timer::timer_delete(timerid) as c_long
+});
+
+define_syscall_handler!(
+    clock_getres(clk_id: clockid_t, res: *mut timespec) -> c_long {
+        time_syscalls::clock_getres(clk_id, res as *mut Timespec) as c_long
+});
+
+define_syscall_handler!(
+    gettimeofday(tv: *mut net::Timeval, tz: *mut c_void) -> c_long {
+        time_syscalls::gettimeofday(tv, tz) as c_long
+});
+
+define_syscall_handler!(
+    settimeofday(tv: *const net::Timeval, tz: *const c_void) -> c_long {
+        time_syscalls::settimeofday(tv, tz) as c_long
+});
+
+define_syscall_handler!(
+    adjtime(delta: *const net::Timeval, olddelta: *mut net::Timeval) -> c_long {
+        time_syscalls::adjtime(delta, olddelta) as c_long
 });
 
 define_syscall_handler!(
@@ -224,6 +342,42 @@ define_syscall_handler!(
     }
 );
 
+define_syscall_handler!(
+    splice(fd_in: c_int, fd_out: c_int, len: size_t) -> isize {
+        vfs_syscalls::splice(fd_in, fd_out, len as usize)
+    }
+);
+
+define_syscall_handler!(
+    poll(fds: *mut pollfd, nfds: nfds_t, timeout: c_int) -> c_int {
+        vfs_syscalls::poll(fds, nfds as usize, timeout)
+    }
+);
+
+define_syscall_handler!(
+    ppoll(fds: *mut pollfd, nfds: nfds_t, tmo: *const Timespec, sigmask: *const sigset_t) -> c_int {
+        vfs_syscalls::ppoll(fds, nfds as usize, tmo, sigmask)
+    }
+);
+
+define_syscall_handler!(
+    epoll_create1(flags: c_int) -> c_int {
+        vfs_syscalls::epoll_create1(flags)
+    }
+);
+
+define_syscall_handler!(
+    epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *const EpollEvent) -> c_int {
+        vfs_syscalls::epoll_ctl(epfd, op, fd, event)
+    }
+);
+
+define_syscall_handler!(
+    epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int {
+        vfs_syscalls::epoll_wait(epfd, events, maxevents, timeout)
+    }
+);
+
 define_syscall_handler!(
     lseek(fildes: c_int, offset: usize, whence: c_int) -> c_int {
         vfs_syscalls::lseek(fildes, offset as i64, whence) as c_int
@@ -263,6 +417,44 @@ define_syscall_handler!(sched_yield() -> c_long {
     scheduler::yield_me();
     0
 });
+
+// Returns the number of threads, of which at most `count` are copied to
+// `infos`.
+define_syscall_handler!(sched_getinfo(infos: *mut ThreadInfo, count: usize) -> c_long {
+    if infos.is_null() {
+        return thread::collect_info(&mut []) as c_long;
+    }
+    let infos = unsafe { core::slice::from_raw_parts_mut(infos, count) };
+    thread::collect_info(infos) as c_long
+});
+define_syscall_handler!(
+    getrandom(buf: *mut c_void, buflen: size_t, flags: c_uint) -> isize {
+        if buf.is_null() {
+            return -libc::EFAULT as isize;
+        }
+        let buf = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, buflen) };
+        match random::getrandom(buf, flags) {
+            Ok(n) => n as isize,
+            Err(e) => e.to_errno() as isize,
+        }
+    }
+);
+define_syscall_handler!(
+    syslog(action: c_int, buf: *mut c_char, len: c_int) -> c_int {
+        if len < 0 {
+            return -EINVAL;
+        }
+        let buf: &mut [u8] = if buf.is_null() {
+            &mut []
+        } else {
+            unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, len as usize) }
+        };
+        match klog::klogctl(action, buf) {
+            Ok(n) => n as c_int,
+            Err(e) => e.to_errno(),
+        }
+    }
+);
 define_syscall_handler!(
     rmdir(path: *const c_char) -> c_int {
         vfs_syscalls::rmdir(path)
@@ -278,11 +470,31 @@ define_syscall_handler!(
         vfs_syscalls::unlink(path)
     }
 );
+define_syscall_handler!(
+    rename(oldpath: *const c_char, newpath: *const c_char) -> c_int {
+        vfs_syscalls::rename(oldpath, newpath)
+    }
+);
 define_syscall_handler!(
     fcntl(fildes: c_int, cmd: c_int, arg: usize) -> c_int {
         vfs_syscalls::fcntl(fildes, cmd, arg)
     }
 );
+define_syscall_handler!(
+    dup(fd: c_int) -> c_int {
+        vfs_syscalls::dup(fd)
+    }
+);
+define_syscall_handler!(
+    dup2(fd: c_int, new_fd: c_int) -> c_int {
+        vfs_syscalls::dup2(fd, new_fd)
+    }
+);
+define_syscall_handler!(
+    dup3(fd: c_int, new_fd: c_int, flags: c_int) -> c_int {
+        vfs_syscalls::dup3(fd, new_fd, flags)
+    }
+);
 define_syscall_handler!(
     stat(path: *const c_char, buf: *mut c_char) -> c_int {
         vfs_syscalls::stat(path, buf as *mut Stat) as c_int
@@ -294,11 +506,46 @@ define_syscall_handler!(
         vfs_syscalls::fstat(fd, buf as *mut Stat) as c_int
     }
 );
+define_syscall_handler!(
+    lstat(path: *const c_char, buf: *mut c_char) -> c_int {
+        vfs_syscalls::lstat(path, buf as *mut Stat)
+    }
+);
+define_syscall_handler!(
+    symlink(target: *const c_char, link_path: *const c_char) -> c_int {
+        vfs_syscalls::symlink(target, link_path)
+    }
+);
+define_syscall_handler!(
+    readlink(path: *const c_char, buf: *mut c_char, size: size_t) -> isize {
+        vfs_syscalls::readlink(path, buf, size as usize)
+    }
+);
 define_syscall_handler!(
     mkdir(path: *const c_char, mode: mode_t) -> c_int {
         vfs_syscalls::mkdir(path, mode)
     }
 );
+define_syscall_handler!(
+    mkfifo(path: *const c_char, mode: mode_t) -> c_int {
+        vfs_syscalls::mkfifo(path, mode)
+    }
+);
+define_syscall_handler!(
+    mknod(path: *const c_char, mode: mode_t, dev: dev_t) -> c_int {
+        vfs_syscalls::mknod(path, mode, dev)
+    }
+);
+define_syscall_handler!(
+    pipe(fds: *mut c_int) -> c_int {
+        vfs_syscalls::pipe(fds)
+    }
+);
+define_syscall_handler!(
+    pipe2(fds: *mut c_int, flags: c_int) -> c_int {
+        vfs_syscalls::pipe2(fds, flags)
+    }
+);
 define_syscall_handler!(
     statfs(path: *const c_char, buf: *mut c_char) -> c_int {
         vfs_syscalls::statfs(path, buf as *mut StatFs) as c_int
@@ -316,16 +563,47 @@ define_syscall_handler!(
         vfs_syscalls::getdents(fd, buf as *mut u8, size as usize) as isize
     }
 );
+
+define_syscall_handler!(
+    getdents64(fd: c_int, buf: *mut c_void, size: usize) -> isize {
+        vfs_syscalls::getdents64(fd, buf as *mut u8, size) as isize
+    }
+);
 define_syscall_handler!(
     chdir(path: *const c_char) -> c_int {
         vfs_syscalls::chdir(path)
     }
 );
+define_syscall_handler!(
+    mmap(addr: *mut c_void, len: size_t, prot: c_int, flags: c_int, fd: c_int, offset: off_t) -> isize {
+        vfs_syscalls::mmap(addr, len as usize, prot, flags, fd, offset)
+    }
+);
+define_syscall_handler!(
+    munmap(addr: *mut c_void, len: size_t) -> c_int {
+        vfs_syscalls::munmap(addr, len as usize)
+    }
+);
+define_syscall_handler!(
+    msync(addr: *mut c_void, len: size_t, flags: c_int) -> c_int {
+        vfs_syscalls::msync(addr, len as usize, flags)
+    }
+);
+define_syscall_handler!(
+    chroot(path: *const c_char) -> c_int {
+        vfs_syscalls::chroot(path)
+    }
+);
 define_syscall_handler!(
     getcwd(buf: *mut c_char, size: size_t) -> c_int {
         vfs_syscalls::getcwd(buf, size as usize) as c_int
     }
 );
+define_syscall_handler!(
+    realpath(path: *const c_char, buf: *mut c_char, size: size_t) -> c_int {
+        vfs_syscalls::realpath(path, buf, size as usize)
+    }
+);
 define_syscall_handler!(
     ftruncate(fd: c_int, length: off_t) -> c_int {
         vfs_syscalls::ftruncate(fd, length)
@@ -511,25 +789,12 @@ define_syscall_handler!(
 
 define_syscall_handler!(
     sys_clock_nanosleep(
-        clock_id: i32,
-        flags: i32,
+        clock_id: clockid_t,
+        flags: c_int,
         rqtp: *const timespec,
         rmtp: *mut timespec
     ) -> c_int {
-        // TODO: Valid Clock Id
-
-        // TODO: Valid rqtp
-
-        // TODO: Implement absolute time sleep
-        let duration = timespec {
-            tv_sec: unsafe { rqtp.read().tv_sec },
-            tv_nsec: unsafe { rqtp.read().tv_nsec },
-        };
-
-        // TODO: Implement tv_nsec
-        let ticks = blueos_kconfig::TICKS_PER_SECOND * duration.tv_sec as usize;
-        scheduler::suspend_me_for(ticks);
-        0
+        time_syscalls::clock_nanosleep(clock_id, flags, rqtp as *const Timespec, rmtp as *mut Timespec)
     }
 );
 
@@ -541,7 +806,6 @@ syscall_table! {
     (ExitThread, exit_thread),
     (AtomicWake, atomic_wake),
     (AtomicWait, atomic_wait),
-    // For test only
     (ClockGetTime, clock_gettime),
     (AllocMem, alloc_mem),
     (FreeMem, free_mem),
@@ -590,6 +854,49 @@ syscall_table! {
     (GetAddrinfo,getaddrinfo),
     (FreeAddrinfo,freeaddrinfo),
     (NanoSleep,sys_clock_nanosleep),
+    (Splice, splice),
+    (ClockGetRes, clock_getres),
+    (GetTimeOfDay, gettimeofday),
+    (SetTimeOfDay, settimeofday),
+    (AdjTime, adjtime),
+    (Poll, poll),
+    (Ppoll, ppoll),
+    (EpollCreate1, epoll_create1),
+    (EpollCtl, epoll_ctl),
+    (EpollWait, epoll_wait),
+    (GetDents64, getdents64),
+    (Rename, rename),
+    (Realpath, realpath),
+    (Symlink, symlink),
+    (Readlink, readlink),
+    (Lstat, lstat),
+    (Dup, dup),
+    (Dup2, dup2),
+    (Dup3, dup3),
+    (Chroot, chroot),
+    (Mmap, mmap),
+    (Munmap, munmap),
+    (Msync, msync),
+    (SchedGetInfo, sched_getinfo),
+    (Pipe, pipe),
+    (Pipe2, pipe2),
+    (Mkfifo, mkfifo),
+    (GetRandom, getrandom),
+    (Mknod, mknod),
+    (Syslog, syslog),
+    (ClockSetTime, clock_settime),
+    (TimerCreate, timer_create),
+    (TimerSetTime, timer_settime),
+    (TimerGetTime, timer_gettime),
+    (TimerGetOverrun, timer_getoverrun),
+    (TimerDelete, timer_delete),
+    (Futex, futex),
+    (MqOpen, mq_open),
+    (MqClose, mq_close),
+    (MqUnlink, mq_unlink),
+    (MqTimedSend, mq_timedsend),
+    (MqTimedReceive, mq_timedreceive),
+    (MqGetSetAttr, mq_getsetattr),
 }
 
 // Begin syscall modules.
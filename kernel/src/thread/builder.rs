@@ -87,6 +87,7 @@ pub struct Builder {
     stack: Option<Stack>,
     entry: Entry,
     priority: ThreadPriority,
+    name: &'static str,
 }
 
 impl Builder {
@@ -95,6 +96,7 @@ impl Builder {
             stack: None,
             entry,
             priority: config::MAX_THREAD_PRIORITY / 2,
+            name: "",
         }
     }
 
@@ -104,6 +106,12 @@ impl Builder {
         self
     }
 
+    #[inline]
+    pub fn set_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
     #[inline]
     pub fn set_stack(mut self, stack: Stack) -> Self {
         self.stack = Some(stack);
@@ -119,6 +127,7 @@ impl Builder {
         );
         w.init(stack, self.entry);
         w.set_priority(self.priority);
+        w.set_name(self.name);
         drop(w);
         GlobalQueueVisitor::add(thread.clone());
 
@@ -0,0 +1,119 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of the threads for `ps` and deadlock diagnostics, see the
+//! `SchedGetInfo` syscall.
+
+use super::{GlobalQueueVisitor, Thread, ThreadNode, WaitReason, SUSPENDED};
+use crate::time;
+use blueos_header::thread::{ThreadInfo, WAIT_ASYNC, WAIT_FUTEX, WAIT_NONE, WAIT_SLEEP, WAIT_SYNC};
+
+// Copies as much of `s` as fits, the rest of `dst` is left zeroed.
+fn copy_name(dst: &mut [u8], s: &str) {
+    let len = s.len().min(dst.len());
+    dst[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+fn info_of(t: &ThreadNode, now_ms: usize) -> ThreadInfo {
+    let mut info = ThreadInfo {
+        tid: Thread::id(t),
+        state: t.state() as u32,
+        priority: t.priority() as u32,
+        ..Default::default()
+    };
+    let name = t.name();
+    let name = if name.is_empty() {
+        t.kind_to_str()
+    } else {
+        &name
+    };
+    copy_name(&mut info.name, name);
+    if t.state() != SUSPENDED {
+        return info;
+    }
+    let wait = t.wait_info();
+    (info.wait_reason, info.wait_addr) = match wait.reason {
+        WaitReason::None => (WAIT_NONE, 0),
+        WaitReason::Futex(addr) => (WAIT_FUTEX, addr),
+        WaitReason::Sync(addr) => (WAIT_SYNC, addr),
+        WaitReason::Sleep => (WAIT_SLEEP, 0),
+        WaitReason::Async => (WAIT_ASYNC, 0),
+    };
+    if let Some(device) = wait.device {
+        copy_name(&mut info.wait_device, device);
+    }
+    info.wait_ms = now_ms.saturating_sub(wait.since_ms) as u64;
+    info
+}
+
+/// Fills `infos` with the threads alive, and returns how many there are,
+/// which may be more than `infos` holds.
+pub fn collect_info(infos: &mut [ThreadInfo]) -> usize {
+    let now_ms = time::tick_get_millisecond();
+    let mut n = 0;
+    let mut it = GlobalQueueVisitor::new();
+    while let Some(t) = it.next() {
+        if let Some(info) = infos.get_mut(n) {
+            *info = info_of(&t, now_ms);
+        }
+        n += 1;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scheduler,
+        sync::{atomic_wait, atomic_wake},
+        thread,
+    };
+    use alloc::{vec, vec::Vec};
+    use blueos_test_macro::test;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static FUTEX: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_collect_info() {
+        let t = thread::spawn(|| {
+            let _wait = thread::wait_on_device("test");
+            while FUTEX.load(Ordering::Acquire) == 0 {
+                let _ = atomic_wait(&FUTEX, 0, None);
+            }
+        })
+        .unwrap();
+        let tid = Thread::id(&t);
+        while t.state() != SUSPENDED {
+            scheduler::yield_me();
+        }
+
+        let n = collect_info(&mut []);
+        assert!(n >= 2);
+        let mut infos: Vec<ThreadInfo> = vec![ThreadInfo::default(); n + 4];
+        let n = collect_info(&mut infos);
+        let info = infos[..n].iter().find(|info| info.tid == tid).unwrap();
+        assert_eq!(info.state, SUSPENDED as u32);
+        assert_eq!(info.wait_reason, WAIT_FUTEX);
+        assert_eq!(info.wait_addr, &FUTEX as *const _ as usize);
+        assert_eq!(&info.wait_device[..5], b"test\0");
+        let me = Thread::id(&scheduler::current_thread());
+        let info = infos[..n].iter().find(|info| info.tid == me).unwrap();
+        assert_eq!(info.wait_reason, WAIT_NONE);
+
+        FUTEX.store(1, Ordering::Release);
+        let _ = atomic_wake(&FUTEX, 1);
+    }
+}
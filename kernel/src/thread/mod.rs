@@ -18,20 +18,30 @@ use crate::sync::event_flags::EventFlagsMode;
 use crate::{
     arch, config, debug, scheduler,
     support::{Region, RegionalObjectBuilder},
-    sync::{ISpinLock, SpinLockGuard},
+    sync::{ISpinLock, SpinLock, SpinLockGuard},
     thread::builder::GlobalQueue,
-    time::timer::Timer,
+    time::{self, timer::Timer},
     types::{
         impl_simple_intrusive_adapter, Arc, AtomicUint, IlistHead, ThreadPriority, Uint,
         UniqueListHead,
     },
 };
 use alloc::boxed::Box;
-use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use core::{
+    fmt,
+    ops::Deref,
+    sync::atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering},
+};
 
 mod builder;
+mod info;
+mod pool;
 mod posix;
+pub mod pthread;
 pub use builder::*;
+pub use info::collect_info;
+pub use pool::ThreadPool;
+pub(crate) use pool::{Jobs, Workers};
 use posix::*;
 
 pub type ThreadNode = Arc<Thread>;
@@ -57,6 +67,7 @@ pub enum ThreadKind {
     Idle,
     #[default]
     Normal,
+    Reclaimer,
     #[cfg(soft_timer)]
     SoftTimer,
 }
@@ -102,6 +113,103 @@ pub const READY: Uint = 1;
 pub const RUNNING: Uint = 2;
 pub const SUSPENDED: Uint = 3;
 pub const RETIRED: Uint = 4;
+// Not on a ready queue, see Thread::ready_queue.
+const NOT_QUEUED: u32 = u32::MAX;
+
+/// What a suspended thread waits for.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum WaitReason {
+    #[default]
+    None,
+    /// A futex, by address.
+    Futex(usize),
+    /// A semaphore, mutex, condition variable or event flags, by address.
+    Sync(usize),
+    /// The end of a sleep.
+    Sleep,
+    /// A future run by the async poller.
+    Async,
+}
+
+/// Longest name a thread can have, in bytes. Longer names are truncated.
+pub const THREAD_NAME_LEN: usize = 15;
+
+/// Name of a thread, kept inline since pthreads can rename a thread at any
+/// time.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadName {
+    buf: [u8; THREAD_NAME_LEN],
+    len: u8,
+}
+
+impl ThreadName {
+    pub const EMPTY: Self = Self {
+        buf: [0; THREAD_NAME_LEN],
+        len: 0,
+    };
+
+    pub fn new(name: &str) -> Self {
+        let mut len = name.len().min(THREAD_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut buf = [0; THREAD_NAME_LEN];
+        buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Self {
+            buf,
+            len: len as u8,
+        }
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        // SAFETY: new only keeps whole characters.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len as usize]) }
+    }
+}
+
+impl Deref for ThreadName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for ThreadName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// The last wait of a thread, which is the current one while the thread is
+/// suspended.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WaitInfo {
+    pub reason: WaitReason,
+    /// Device the wait is for, set by its driver with [`wait_on_device`].
+    pub device: Option<&'static str>,
+    /// Time the wait began at, in milliseconds since boot.
+    pub since_ms: usize,
+}
+
+/// Reports the waits of the current thread as waits on `device` until the
+/// returned guard is dropped.
+pub fn wait_on_device(device: &'static str) -> DeviceWaitGuard {
+    let t = scheduler::current_thread();
+    t.wait.irqsave_lock().device = Some(device);
+    DeviceWaitGuard { t }
+}
+
+pub struct DeviceWaitGuard {
+    t: ThreadNode,
+}
+
+impl Drop for DeviceWaitGuard {
+    fn drop(&mut self) {
+        self.t.wait.irqsave_lock().device = None;
+    }
+}
 
 // ThreadStats is protected by thread scheduler.
 #[derive(Debug, Default)]
@@ -141,9 +249,14 @@ pub struct Thread {
     // Cleanup function will be invoked when retiring.
     cleanup: Option<Entry>,
     kind: ThreadKind,
+    // Only locked to copy or update it, never with another lock taken.
+    name: SpinLock<ThreadName>,
     stack: Stack,
     saved_sp: usize,
     priority: ThreadPriority,
+    // Core and priority of the ready queue holding the thread, packed by
+    // set_ready_queue. Only changed with that core's ready table locked.
+    ready_queue: AtomicU32,
     state: AtomicUint,
     preempt_count: AtomicUint,
     #[cfg(robin_scheduler)]
@@ -155,6 +268,8 @@ pub struct Thread {
     lock: ISpinLock<Thread, OffsetOfLock>,
     posix_compat: Option<PosixCompat>,
     stats: ThreadStats,
+    // Only locked to copy or update it, never with another lock taken.
+    wait: SpinLock<WaitInfo>,
     #[cfg(event_flags)]
     event_flags_mode: EventFlagsMode,
     #[cfg(event_flags)]
@@ -240,17 +355,42 @@ impl Thread {
         }
     }
 
+    #[inline]
+    pub fn wait_info(&self) -> WaitInfo {
+        *self.wait.irqsave_lock()
+    }
+
+    /// Records that the thread is about to be suspended for `reason`.
+    pub(crate) fn start_waiting(&self, reason: WaitReason) {
+        let mut wait = self.wait.irqsave_lock();
+        wait.reason = reason;
+        wait.since_ms = time::tick_get_millisecond();
+    }
+
     #[inline]
     pub fn kind(&self) -> ThreadKind {
         self.kind
     }
 
+    /// Name given by the thread's builder or by `pthread_setname_np`,
+    /// empty if none was.
+    #[inline]
+    pub fn name(&self) -> ThreadName {
+        *self.name.irqsave_lock()
+    }
+
+    #[inline]
+    pub fn set_name(&self, name: &str) {
+        *self.name.irqsave_lock() = ThreadName::new(name);
+    }
+
     #[inline]
     pub fn kind_to_str(&self) -> &str {
         match self.kind {
             ThreadKind::AsyncPoller => "async_poller",
             ThreadKind::Idle => "idle",
             ThreadKind::Normal => "normal",
+            ThreadKind::Reclaimer => "reclaimer",
             #[cfg(soft_timer)]
             ThreadKind::SoftTimer => "soft_timer",
         }
@@ -321,13 +461,20 @@ impl Thread {
             global: UniqueListHead::new(),
             saved_sp: 0,
             priority: 0,
+            ready_queue: AtomicU32::new(NOT_QUEUED),
             preempt_count: AtomicUint::new(0),
             posix_compat: None,
             stats: ThreadStats::new(),
+            wait: SpinLock::new(WaitInfo {
+                reason: WaitReason::None,
+                device: None,
+                since_ms: 0,
+            }),
             timer: None,
             #[cfg(robin_scheduler)]
             robin_count: AtomicI32::new(0),
             kind,
+            name: SpinLock::new(ThreadName::EMPTY),
             #[cfg(event_flags)]
             event_flags_mode: EventFlagsMode::empty(),
             #[cfg(event_flags)]
@@ -417,6 +564,21 @@ impl Thread {
         self.priority
     }
 
+    /// Core and priority of the ready queue holding the thread, if any.
+    #[inline]
+    pub(crate) fn ready_queue(&self) -> Option<(usize, ThreadPriority)> {
+        let packed = self.ready_queue.load(Ordering::SeqCst);
+        (packed != NOT_QUEUED).then(|| ((packed >> 16) as usize, packed as u16 as ThreadPriority))
+    }
+
+    #[inline]
+    pub(crate) fn set_ready_queue(&self, queue: Option<(usize, ThreadPriority)>) {
+        let packed = queue.map_or(NOT_QUEUED, |(cpu, priority)| {
+            ((cpu as u32) << 16) | priority as u32
+        });
+        self.ready_queue.store(packed, Ordering::SeqCst);
+    }
+
     #[inline]
     pub fn disable_preempt(&self) -> bool {
         self.preempt_count.fetch_add(1, Ordering::Acquire) == 0
@@ -0,0 +1,274 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Builder, Entry};
+use crate::{
+    error::{code, Error},
+    scheduler,
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    thread,
+    types::{Arc, ThreadPriority},
+};
+use alloc::{boxed::Box, collections::VecDeque};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The queue a set of [`Workers`] takes its jobs from.
+pub(crate) trait Jobs: 'static {
+    fn workers(&self) -> &Workers;
+    /// Takes the next job and runs it, returns false if there was none.
+    fn run_next(&self) -> bool;
+}
+
+/// Worker threads running the jobs of a queue, shared by [`ThreadPool`]
+/// and work queues. Idle workers sleep until a job is queued.
+pub(crate) struct Workers {
+    // Bumped when a job is queued or the workers stopped, idle workers
+    // sleep on it.
+    queued: AtomicUsize,
+    stopped: AtomicBool,
+    running: AtomicUsize,
+}
+
+impl Workers {
+    pub(crate) const fn new() -> Self {
+        Self {
+            queued: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts `count` threads named `name`, at `priority`, running the jobs
+    /// of `jobs`.
+    pub(crate) fn start<J: Jobs>(
+        jobs: &Arc<J>,
+        name: &'static str,
+        priority: ThreadPriority,
+        count: usize,
+    ) {
+        for _ in 0..count {
+            let queue = jobs.clone();
+            let t = Builder::new(Entry::Closure(Box::new(move || Self::run(&*queue))))
+                .set_priority(priority)
+                .set_name(name)
+                .build();
+            jobs.workers().running.fetch_add(1, Ordering::Relaxed);
+            let ok = scheduler::queue_ready_thread(thread::CREATED, t);
+            crate::kassert!(ok, "worker can't be queued");
+        }
+    }
+
+    fn run<J: Jobs>(jobs: &J) {
+        let this = jobs.workers();
+        loop {
+            let queued = this.queued.load(Ordering::Acquire);
+            if jobs.run_next() {
+                continue;
+            }
+            if this.is_stopped() {
+                break;
+            }
+            let _ = atomic_wait(&this.queued, queued, None);
+        }
+        this.running.fetch_sub(1, Ordering::Release);
+        let _ = atomic_wake(&this.running, usize::MAX);
+    }
+
+    /// Wakes a worker to take the job just queued. Can be called from
+    /// interrupt context.
+    pub(crate) fn notify(&self) {
+        self.queued.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.queued, 1);
+    }
+
+    /// Lets the workers exit once they find the queue empty.
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    /// Waits for the workers to exit, after [`stop`](Self::stop).
+    pub(crate) fn join(&self) {
+        self.queued.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.queued, usize::MAX);
+        loop {
+            let running = self.running.load(Ordering::Acquire);
+            if running == 0 {
+                return;
+            }
+            let _ = atomic_wait(&self.running, running, None);
+        }
+    }
+}
+
+struct Shared {
+    jobs: SpinLock<VecDeque<Job>>,
+    capacity: usize,
+    // Bumped when a job is taken, submitters sleep on it while the queue is
+    // full.
+    taken: AtomicUsize,
+    workers: Workers,
+}
+
+// Jobs are only moved in and out of the queue, under its lock, and never
+// shared.
+unsafe impl Sync for Shared {}
+
+impl Jobs for Shared {
+    fn workers(&self) -> &Workers {
+        &self.workers
+    }
+
+    fn run_next(&self) -> bool {
+        let Some(job) = self.jobs.irqsave_lock().pop_front() else {
+            return false;
+        };
+        self.taken.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.taken, 1);
+        job();
+        true
+    }
+}
+
+impl Shared {
+    // Gives the job back if the queue is full.
+    fn push(&self, job: Job) -> Result<(), (Job, Error)> {
+        {
+            let mut jobs = self.jobs.irqsave_lock();
+            if self.workers.is_stopped() {
+                return Err((job, code::EPIPE));
+            }
+            if jobs.len() == self.capacity {
+                return Err((job, code::EAGAIN));
+            }
+            jobs.push_back(job);
+        }
+        self.workers.notify();
+        Ok(())
+    }
+}
+
+/// A fixed number of worker threads running the jobs of a bounded queue,
+/// for drivers to do work in the background without creating a thread per
+/// job.
+///
+/// Jobs run in the order they were queued, each to completion. Dropping
+/// the pool shuts it down.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+}
+
+impl ThreadPool {
+    /// Starts `workers` threads named `name`, at the priority of the
+    /// current thread, taking jobs from a queue of `capacity` jobs.
+    pub fn new(name: &'static str, workers: usize, capacity: usize) -> Result<Self, Error> {
+        if workers == 0 || capacity == 0 {
+            return Err(code::EINVAL);
+        }
+        let shared = Arc::new(Shared {
+            jobs: SpinLock::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            taken: AtomicUsize::new(0),
+            workers: Workers::new(),
+        });
+        let priority = scheduler::current_thread().priority();
+        Workers::start(&shared, name, priority, workers);
+        Ok(Self { shared })
+    }
+
+    /// Queues `job`. Fails with EAGAIN if the queue is full, and with EPIPE
+    /// if the pool has been shut down.
+    pub fn try_execute<F>(&self, job: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(Box::new(job)).map_err(|(_, e)| e)
+    }
+
+    /// Queues `job`, waiting for room in the queue. Fails with EPIPE if the
+    /// pool has been shut down.
+    pub fn execute<F>(&self, job: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut job: Job = Box::new(job);
+        loop {
+            let taken = self.shared.taken.load(Ordering::Acquire);
+            match self.shared.push(job) {
+                Ok(()) => return Ok(()),
+                Err((back, e)) if e == code::EAGAIN => job = back,
+                Err((_, e)) => return Err(e),
+            }
+            let _ = atomic_wait(&self.shared.taken, taken, None);
+        }
+    }
+
+    /// Stops taking jobs, and waits for the workers to run the queued ones
+    /// and exit. Must not be called from a job.
+    pub fn shutdown(&self) {
+        {
+            let _jobs = self.shared.jobs.irqsave_lock();
+            self.shared.workers.stop();
+        }
+        self.shared.workers.join();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    static RAN: AtomicUsize = AtomicUsize::new(0);
+    static GATE: AtomicUsize = AtomicUsize::new(0);
+
+    fn wait_for_gate() {
+        while GATE.load(Ordering::Acquire) == 0 {
+            let _ = atomic_wait(&GATE, 0, None);
+        }
+        RAN.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_thread_pool() {
+        assert!(ThreadPool::new("pool", 0, 1).is_err());
+        let pool = ThreadPool::new("pool", 1, 2).unwrap();
+        pool.execute(wait_for_gate).unwrap();
+        // The worker takes the first job, then the queue fills up.
+        while pool.shared.taken.load(Ordering::Acquire) == 0 {
+            scheduler::yield_me();
+        }
+        pool.try_execute(wait_for_gate).unwrap();
+        pool.try_execute(wait_for_gate).unwrap();
+        assert_eq!(pool.try_execute(wait_for_gate), Err(code::EAGAIN));
+
+        GATE.store(1, Ordering::Release);
+        let _ = atomic_wake(&GATE, usize::MAX);
+        pool.execute(wait_for_gate).unwrap();
+        pool.shutdown();
+        assert_eq!(RAN.load(Ordering::Relaxed), 4);
+        assert_eq!(pool.try_execute(wait_for_gate), Err(code::EPIPE));
+    }
+}
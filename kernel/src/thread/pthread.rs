@@ -0,0 +1,402 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C API for threads, compatible with pthreads.
+//!
+//! A `pthread_t` is the kernel thread id, so `pthread_self` also works in
+//! threads which were not created through this API, and they can be named
+//! too, though only the ones created by [`pthread_create`] can be joined or
+//! detached. Names are the ones of the threads, shown in `/proc`. Like
+//! pthreads, the functions return an errno value instead of setting `errno`.
+
+use crate::{
+    allocator, scheduler,
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    thread::{
+        self, Builder, Entry, GlobalQueueVisitor, Stack, Thread, ThreadNode, THREAD_NAME_LEN,
+    },
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use core::{
+    ffi::{c_char, c_int, c_void, CStr},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use libc;
+
+pub type PthreadT = usize;
+pub type StartRoutine = extern "C" fn(*mut c_void) -> *mut c_void;
+
+pub const PTHREAD_CREATE_JOINABLE: c_int = 0;
+pub const PTHREAD_CREATE_DETACHED: c_int = 1;
+/// Smallest stack accepted by [`pthread_attr_setstacksize`].
+pub const PTHREAD_STACK_MIN: usize = 2048;
+/// Maximum length of a thread name, including the terminating NUL.
+pub const PTHREAD_NAME_LEN: usize = THREAD_NAME_LEN + 1;
+
+const STACK_ALIGN: usize = 16;
+
+/// Thread attributes. Only the stack size and the detach state are
+/// supported, a stack size of 0 selects the default stack.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PthreadAttr {
+    pub stack_size: usize,
+    pub detach_state: c_int,
+}
+
+const RUNNING: usize = 0;
+const EXITED: usize = 1;
+
+#[derive(Debug)]
+struct Pthread {
+    // Waited on by the joiner.
+    state: AtomicUsize,
+    retval: AtomicUsize,
+    // Both are only changed with the registry locked.
+    detached: AtomicBool,
+    joining: AtomicBool,
+}
+
+impl Pthread {
+    fn new(detached: bool) -> Self {
+        Self {
+            state: AtomicUsize::new(RUNNING),
+            retval: AtomicUsize::new(0),
+            detached: AtomicBool::new(detached),
+            joining: AtomicBool::new(false),
+        }
+    }
+}
+
+// Threads created by pthread_create, until they are joined, or until they
+// exit if detached.
+static PTHREADS: SpinLock<BTreeMap<PthreadT, Arc<Pthread>>> = SpinLock::new(BTreeMap::new());
+
+fn find_thread(thread: PthreadT) -> Option<ThreadNode> {
+    let mut it = GlobalQueueVisitor::new();
+    while let Some(t) = it.next() {
+        if Thread::id(&t) == thread {
+            return Some(t);
+        }
+    }
+    None
+}
+
+fn exit(pthread: &Pthread, retval: *mut c_void) {
+    pthread.retval.store(retval as usize, Ordering::Relaxed);
+    {
+        let mut pthreads = PTHREADS.irqsave_lock();
+        pthread.state.store(EXITED, Ordering::Release);
+        if pthread.detached.load(Ordering::Relaxed) {
+            pthreads.remove(&scheduler::current_thread_id());
+            return;
+        }
+    }
+    let _ = atomic_wake(&pthread.state, usize::MAX);
+}
+
+pub fn pthread_attr_init(attr: *mut PthreadAttr) -> c_int {
+    if attr.is_null() {
+        return libc::EINVAL;
+    }
+    unsafe { attr.write(PthreadAttr::default()) };
+    0
+}
+
+pub fn pthread_attr_setstacksize(attr: *mut PthreadAttr, stack_size: usize) -> c_int {
+    if attr.is_null() || stack_size < PTHREAD_STACK_MIN {
+        return libc::EINVAL;
+    }
+    unsafe { (*attr).stack_size = stack_size };
+    0
+}
+
+pub fn pthread_attr_setdetachstate(attr: *mut PthreadAttr, detach_state: c_int) -> c_int {
+    if attr.is_null()
+        || (detach_state != PTHREAD_CREATE_JOINABLE && detach_state != PTHREAD_CREATE_DETACHED)
+    {
+        return libc::EINVAL;
+    }
+    unsafe { (*attr).detach_state = detach_state };
+    0
+}
+
+pub fn pthread_create(
+    thread: *mut PthreadT,
+    attr: *const PthreadAttr,
+    start_routine: Option<StartRoutine>,
+    arg: *mut c_void,
+) -> c_int {
+    let Some(start_routine) = start_routine else {
+        return libc::EINVAL;
+    };
+    if thread.is_null() {
+        return libc::EINVAL;
+    }
+    let attr = if attr.is_null() {
+        PthreadAttr::default()
+    } else {
+        unsafe { *attr }
+    };
+    let pthread = Arc::new(Pthread::new(attr.detach_state == PTHREAD_CREATE_DETACHED));
+
+    let this = pthread.clone();
+    let arg = arg as usize;
+    let mut builder = Builder::new(Entry::Closure(Box::new(move || {
+        let retval = start_routine(arg as *mut c_void);
+        exit(&this, retval);
+    })));
+    let stack_base = if attr.stack_size != 0 {
+        let base = allocator::malloc_align(attr.stack_size, STACK_ALIGN);
+        if base.is_null() {
+            return libc::EAGAIN;
+        }
+        builder = builder.set_stack(Stack::Raw {
+            base: base as usize,
+            size: attr.stack_size,
+        });
+        Some(base as usize)
+    } else {
+        None
+    };
+    let t = builder.build();
+    if let Some(base) = stack_base {
+        // Cleanups run once we have switched away from the retired thread.
+        t.lock().set_cleanup(Entry::Closure(Box::new(move || {
+            allocator::free(base as *mut u8)
+        })));
+    }
+    let id = Thread::id(&t);
+    PTHREADS.irqsave_lock().insert(id, pthread);
+    unsafe { thread.write(id) };
+    let ok = scheduler::queue_ready_thread(thread::CREATED, t);
+    assert!(ok);
+    0
+}
+
+pub fn pthread_join(thread: PthreadT, retval: *mut *mut c_void) -> c_int {
+    if thread == pthread_self() {
+        return libc::EDEADLK;
+    }
+    let pthread = {
+        let pthreads = PTHREADS.irqsave_lock();
+        let Some(pthread) = pthreads.get(&thread) else {
+            return libc::ESRCH;
+        };
+        if pthread.detached.load(Ordering::Relaxed) || pthread.joining.load(Ordering::Relaxed) {
+            return libc::EINVAL;
+        }
+        pthread.joining.store(true, Ordering::Relaxed);
+        pthread.clone()
+    };
+    while pthread.state.load(Ordering::Acquire) == RUNNING {
+        let _ = atomic_wait(&pthread.state, RUNNING, None);
+    }
+    if !retval.is_null() {
+        unsafe { retval.write(pthread.retval.load(Ordering::Relaxed) as *mut c_void) };
+    }
+    PTHREADS.irqsave_lock().remove(&thread);
+    0
+}
+
+pub fn pthread_detach(thread: PthreadT) -> c_int {
+    let mut pthreads = PTHREADS.irqsave_lock();
+    let Some(pthread) = pthreads.get(&thread) else {
+        return libc::ESRCH;
+    };
+    if pthread.detached.load(Ordering::Relaxed) || pthread.joining.load(Ordering::Relaxed) {
+        return libc::EINVAL;
+    }
+    if pthread.state.load(Ordering::Acquire) == EXITED {
+        pthreads.remove(&thread);
+    } else {
+        pthread.detached.store(true, Ordering::Relaxed);
+    }
+    0
+}
+
+pub fn pthread_self() -> PthreadT {
+    scheduler::current_thread_id()
+}
+
+pub fn pthread_setname_np(thread: PthreadT, name: *const c_char) -> c_int {
+    if name.is_null() {
+        return libc::EINVAL;
+    }
+    let Ok(name) = unsafe { CStr::from_ptr(name) }.to_str() else {
+        return libc::EINVAL;
+    };
+    if name.len() >= PTHREAD_NAME_LEN {
+        return libc::ERANGE;
+    }
+    let Some(t) = find_thread(thread) else {
+        return libc::ESRCH;
+    };
+    t.set_name(name);
+    0
+}
+
+pub fn pthread_getname_np(thread: PthreadT, name: *mut c_char, len: usize) -> c_int {
+    if name.is_null() {
+        return libc::EINVAL;
+    }
+    let Some(t) = find_thread(thread) else {
+        return libc::ESRCH;
+    };
+    let buf = t.name();
+    let n = buf.len();
+    if len <= n {
+        return libc::ERANGE;
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(buf.as_ptr(), name as *mut u8, n);
+        name.add(n).write(0);
+    }
+    0
+}
+
+mod ffi {
+    use super::*;
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_attr_init(attr: *mut PthreadAttr) -> c_int {
+        super::pthread_attr_init(attr)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_attr_setstacksize(
+        attr: *mut PthreadAttr,
+        stack_size: usize,
+    ) -> c_int {
+        super::pthread_attr_setstacksize(attr, stack_size)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_attr_setdetachstate(
+        attr: *mut PthreadAttr,
+        detach_state: c_int,
+    ) -> c_int {
+        super::pthread_attr_setdetachstate(attr, detach_state)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_create(
+        thread: *mut PthreadT,
+        attr: *const PthreadAttr,
+        start_routine: Option<StartRoutine>,
+        arg: *mut c_void,
+    ) -> c_int {
+        super::pthread_create(thread, attr, start_routine, arg)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_join(thread: PthreadT, retval: *mut *mut c_void) -> c_int {
+        super::pthread_join(thread, retval)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_detach(thread: PthreadT) -> c_int {
+        super::pthread_detach(thread)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_self() -> PthreadT {
+        super::pthread_self()
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_setname_np(thread: PthreadT, name: *const c_char) -> c_int {
+        super::pthread_setname_np(thread, name)
+    }
+
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn pthread_getname_np(thread: PthreadT, name: *mut c_char, len: usize) -> c_int {
+        super::pthread_getname_np(thread, name, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    extern "C" fn double(arg: *mut c_void) -> *mut c_void {
+        (arg as usize * 2) as *mut c_void
+    }
+
+    #[test]
+    fn test_pthread_join() {
+        let mut t: PthreadT = 0;
+        assert_eq!(
+            pthread_create(&mut t, core::ptr::null(), Some(double), 21 as *mut c_void),
+            0
+        );
+        let mut retval = core::ptr::null_mut();
+        assert_eq!(pthread_join(t, &mut retval), 0);
+        assert_eq!(retval as usize, 42);
+        assert_eq!(pthread_join(t, &mut retval), libc::ESRCH);
+        assert_eq!(pthread_join(pthread_self(), &mut retval), libc::EDEADLK);
+    }
+
+    #[test]
+    fn test_pthread_setname() {
+        let me = scheduler::current_thread();
+        let old = me.name();
+        assert_eq!(pthread_setname_np(pthread_self(), c"worker".as_ptr()), 0);
+        assert_eq!(&*me.name(), "worker");
+        let mut name = [0 as c_char; PTHREAD_NAME_LEN];
+        assert_eq!(
+            pthread_getname_np(pthread_self(), name.as_mut_ptr(), name.len()),
+            0
+        );
+        assert_eq!(unsafe { CStr::from_ptr(name.as_ptr()) }, c"worker");
+        assert_eq!(
+            pthread_getname_np(pthread_self(), name.as_mut_ptr(), 6),
+            libc::ERANGE
+        );
+        assert_eq!(
+            pthread_setname_np(pthread_self(), c"a very long thread name".as_ptr()),
+            libc::ERANGE
+        );
+        assert_eq!(pthread_setname_np(0, c"worker".as_ptr()), libc::ESRCH);
+        me.set_name(&old);
+    }
+
+    #[test]
+    fn test_pthread_detach() {
+        let mut attr = PthreadAttr::default();
+        assert_eq!(pthread_attr_init(&mut attr), 0);
+        assert_eq!(pthread_attr_setstacksize(&mut attr, 1), libc::EINVAL);
+        assert_eq!(pthread_attr_setstacksize(&mut attr, 8192), 0);
+        let mut t: PthreadT = 0;
+        assert_eq!(
+            pthread_create(&mut t, &attr, Some(double), core::ptr::null_mut()),
+            0
+        );
+        assert_eq!(pthread_detach(t), 0);
+        // The thread may have exited and been reaped already.
+        let err = pthread_join(t, core::ptr::null_mut());
+        assert!(err == libc::EINVAL || err == libc::ESRCH);
+        assert_eq!(pthread_detach(pthread_self()), libc::ESRCH);
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::{
+    ops::{Add, Sub},
+    time::Duration,
+};
+
+/// A point in time, measured from boot by the cycle counter. Unlike the
+/// wall clock, it's never stepped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(super::get_uptime())
+    }
+
+    /// The instant `uptime` after boot, as `CLOCK_MONOTONIC` reads it.
+    pub const fn from_uptime(uptime: Duration) -> Self {
+        Self(uptime)
+    }
+
+    /// Time from `earlier` to this instant, zero if `earlier` is later.
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+/// Saturates, so that a timeout of [`Duration::MAX`] means forever.
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Self(self.0.saturating_add(duration))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_sleep_until() {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(15);
+        time::sleep_until(deadline);
+        assert!(Instant::now() >= deadline);
+        assert!(Instant::now() - start >= Duration::from_millis(15));
+
+        // Deadlines in the past return at once.
+        time::sleep_until(start);
+        assert_eq!(start - deadline, Duration::ZERO);
+        assert_eq!(deadline - start, Duration::from_millis(15));
+        assert_eq!(start + Duration::MAX, Instant::from_uptime(Duration::MAX));
+    }
+}
@@ -12,15 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod instant;
+pub mod posix_timer;
+pub mod realtime;
+pub mod syscalls;
 pub(crate) mod systick;
 pub(crate) mod timer;
 
-use crate::{arch, boards, scheduler, support::DisableInterruptGuard, thread::Thread};
+use crate::{
+    arch, boards, crypto::random, scheduler, support::DisableInterruptGuard, thread::Thread,
+};
 use blueos_kconfig::TICKS_PER_SECOND;
+use core::time::Duration;
+pub use instant::Instant;
 use systick::SYSTICK;
 
 pub const WAITING_FOREVER: usize = usize::MAX;
 
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
 pub fn systick_init(sys_clock: u32) -> bool {
     SYSTICK.init(sys_clock, TICKS_PER_SECOND as u32)
 }
@@ -33,7 +43,12 @@ pub fn get_sys_cycles() -> u64 {
     SYSTICK.get_cycles()
 }
 
-pub(crate) fn get_cycles_to_duration(cycles: u64) -> core::time::Duration {
+/// Time elapsed since boot, at the resolution of the cycle counter.
+pub fn get_uptime() -> Duration {
+    get_cycles_to_duration(get_sys_cycles())
+}
+
+pub(crate) fn get_cycles_to_duration(cycles: u64) -> Duration {
     boards::get_cycles_to_duration(cycles)
 }
 
@@ -47,19 +62,99 @@ pub fn reset_systick() {
 
 pub extern "C" fn handle_tick_increment() {
     let _guard = DisableInterruptGuard::new();
-    let mut need_schedule = false;
-    // FIXME: aarch64 and riscv64 need to be supported
-    if arch::current_cpu_id() == 0 {
-        let ticks = SYSTICK.increment_ticks();
-        need_schedule = timer::check_hard_timer(ticks);
-    }
-    need_schedule = scheduler::handle_tick_increment(1) || need_schedule;
-    SYSTICK.reset_counter();
+    #[cfg(profiler)]
+    crate::profiler::sample();
+    #[cfg(not(tickless))]
+    let need_schedule = {
+        let mut need_schedule = false;
+        // FIXME: aarch64 and riscv64 need to be supported
+        if arch::current_cpu_id() == 0 {
+            let ticks = SYSTICK.increment_ticks();
+            need_schedule = timer::check_hard_timer(ticks);
+            random::add_interrupt_randomness();
+        }
+        need_schedule = scheduler::handle_tick_increment(1) || need_schedule;
+        SYSTICK.reset_counter();
+        need_schedule
+    };
+    #[cfg(tickless)]
+    let need_schedule = {
+        random::add_interrupt_randomness();
+        catch_up_ticks()
+    };
     if need_schedule {
         scheduler::yield_me_now_or_later();
     }
 }
 
+// Accounts for the ticks elapsed since the last call and arms the timer
+// for the next one. Interrupts must be disabled.
+#[cfg(tickless)]
+fn catch_up_ticks() -> bool {
+    let elapsed = SYSTICK.sync_ticks();
+    let mut need_schedule = false;
+    if elapsed > 0 {
+        need_schedule = timer::check_hard_timer(get_sys_ticks());
+        need_schedule = scheduler::handle_tick_increment(elapsed) || need_schedule;
+    }
+    SYSTICK.set_timeout_ticks(1);
+    need_schedule
+}
+
+/// Waits for an interrupt when there's nothing to run.
+///
+/// In tickless mode the tick is stopped until the nearest timer deadline,
+/// and the ticks missed are accounted for once an interrupt, of the timer
+/// or not, wakes the CPU up.
+pub(crate) fn idle() {
+    #[cfg(not(tickless))]
+    arch::idle();
+    #[cfg(tickless)]
+    {
+        // The interrupt waking us up is handled once the tick has caught
+        // up, so that it sees the right time.
+        let _guard = DisableInterruptGuard::new();
+        let ticks = get_sys_ticks();
+        let next = timer::get_next_timer_ticks();
+        if next > ticks.saturating_add(1) {
+            SYSTICK.set_timeout_ticks(next - ticks);
+        }
+        arch::idle();
+        // Threads woken up are picked by the caller.
+        let _ = catch_up_ticks();
+    }
+}
+
+/// Converts `duration` to ticks, rounding up so that a wait never ends
+/// early. Durations too long to wait for become `WAITING_FOREVER - 1`.
+pub(crate) fn duration_to_ticks(duration: Duration) -> usize {
+    let ticks = (duration.as_nanos() * TICKS_PER_SECOND as u128).div_ceil(NANOS_PER_SEC);
+    ticks.min((WAITING_FOREVER - 1) as u128) as usize
+}
+
+/// Sleeps until `deadline`, returning at once if it has passed. The
+/// thread sleeps again if it's woken up early.
+pub fn sleep_until(deadline: Instant) {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        // The current tick has partially elapsed already.
+        let ticks = duration_to_ticks(remaining).saturating_add(1);
+        scheduler::suspend_me_for(ticks.min(WAITING_FOREVER - 1));
+    }
+}
+
+/// Sleeps for at least `duration`. A zero duration yields the CPU.
+pub fn sleep(duration: Duration) {
+    if duration.is_zero() {
+        scheduler::yield_me();
+        return;
+    }
+    sleep_until(Instant::now() + duration);
+}
+
 pub fn tick_from_millisecond(ms: usize) -> usize {
     #[cfg(has_fpu)]
     {
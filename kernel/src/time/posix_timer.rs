@@ -0,0 +1,404 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C API for interval timers, compatible with POSIX timers.
+//!
+//! Timers run on the hard timer wheel, so they expire at tick accuracy
+//! whatever the load. A `SIGEV_THREAD` timer has its own notifier thread,
+//! which runs at the priority of the thread creating the timer and calls
+//! the notify function once per expiry, expiries it falls behind on being
+//! counted as overruns. A `SIGEV_NONE` timer only counts down, to be read
+//! with [`timer_gettime`]. There are no signals to deliver, so
+//! `SIGEV_SIGNAL` isn't supported.
+//!
+//! An absolute `CLOCK_REALTIME` expiry is turned into a delay when the
+//! timer is set, steps of the wall clock after that don't move it.
+
+use crate::{
+    scheduler,
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    thread::{self, Builder, Entry},
+    time::{self, duration_to_ticks, realtime, syscalls::TIMER_ABSTIME, timer::Timer},
+    types,
+    vfs::syscalls::Timespec,
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use blueos_kconfig::TICKS_PER_SECOND;
+use core::{
+    ffi::{c_int, c_void},
+    mem,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use libc::clockid_t;
+
+pub const SIGEV_SIGNAL: c_int = 0;
+pub const SIGEV_NONE: c_int = 1;
+pub const SIGEV_THREAD: c_int = 2;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union SigVal {
+    pub sival_int: c_int,
+    pub sival_ptr: *mut c_void,
+}
+
+crate::static_assert!(mem::size_of::<SigVal>() == mem::size_of::<usize>());
+
+pub type NotifyFunction = extern "C" fn(SigVal);
+
+/// How a timer notifies its expiries. The thread attributes are ignored.
+#[repr(C)]
+pub struct SigEvent {
+    pub sigev_value: SigVal,
+    pub sigev_signo: c_int,
+    pub sigev_notify: c_int,
+    pub sigev_notify_function: Option<NotifyFunction>,
+    pub sigev_notify_attributes: *mut c_void,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ITimerSpec {
+    pub it_interval: Timespec,
+    pub it_value: Timespec,
+}
+
+struct Armed {
+    timer: types::Arc<Timer>,
+    interval: Duration,
+}
+
+struct PosixTimer {
+    clock: clockid_t,
+    // Bumped at each expiry, waited on by the notifier thread.
+    expirations: AtomicUsize,
+    overrun: AtomicUsize,
+    deleted: AtomicBool,
+    armed: SpinLock<Option<Armed>>,
+}
+
+impl PosixTimer {
+    fn expire(&self) {
+        self.expirations.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.expirations, 1);
+    }
+
+    fn disarm(&self) -> ITimerSpec {
+        let old = self.armed.irqsave_lock().take();
+        let spec = spec_of(old.as_ref());
+        if let Some(armed) = old {
+            armed.timer.stop();
+        }
+        spec
+    }
+
+    fn arm(self: &Arc<Self>, value: Duration, interval: Duration) {
+        let this = Arc::downgrade(self);
+        let callback = Box::new(move || {
+            if let Some(this) = this.upgrade() {
+                this.expire();
+            }
+        });
+        let interval_ticks = duration_to_ticks(interval);
+        let timer = if interval_ticks == 0 {
+            Timer::new_hard_oneshot(interval_ticks, callback)
+        } else {
+            Timer::new_hard_periodic(interval_ticks, callback)
+        };
+        timer.start_after(duration_to_ticks(value).max(1));
+        *self.armed.irqsave_lock() = Some(Armed { timer, interval });
+    }
+
+    fn notify(&self, function: NotifyFunction, value: usize) {
+        let mut delivered = 0;
+        loop {
+            let expirations = self.expirations.load(Ordering::Acquire);
+            if self.deleted.load(Ordering::Acquire) {
+                return;
+            }
+            if expirations == delivered {
+                let _ = atomic_wait(&self.expirations, expirations, None);
+                continue;
+            }
+            self.overrun
+                .store(expirations.wrapping_sub(delivered) - 1, Ordering::Relaxed);
+            delivered = expirations;
+            function(unsafe { mem::transmute::<usize, SigVal>(value) });
+        }
+    }
+}
+
+static TIMERS: SpinLock<BTreeMap<c_int, Arc<PosixTimer>>> = SpinLock::new(BTreeMap::new());
+
+fn find(timerid: c_int) -> Option<Arc<PosixTimer>> {
+    TIMERS.irqsave_lock().get(&timerid).cloned()
+}
+
+fn ticks_to_duration(ticks: usize) -> Duration {
+    let nanos = ticks as u128 * NANOS_PER_SEC as u128 / TICKS_PER_SECOND as u128;
+    Duration::new(
+        (nanos / NANOS_PER_SEC as u128) as u64,
+        (nanos % NANOS_PER_SEC as u128) as u32,
+    )
+}
+
+fn spec_of(armed: Option<&Armed>) -> ITimerSpec {
+    let (value, interval) = match armed.filter(|armed| armed.timer.is_activated()) {
+        Some(armed) => {
+            let remaining = armed
+                .timer
+                .timeout_ticks()
+                .saturating_sub(time::get_sys_ticks());
+            // Never report a timer which is still armed as disarmed.
+            let value = ticks_to_duration(remaining).max(Duration::from_nanos(1));
+            (value, armed.interval)
+        }
+        None => (Duration::ZERO, Duration::ZERO),
+    };
+    ITimerSpec {
+        it_interval: Timespec::from(interval),
+        it_value: Timespec::from(value),
+    }
+}
+
+fn to_duration(ts: &Timespec) -> Option<Duration> {
+    if ts.tv_sec < 0 || !(0..NANOS_PER_SEC as libc::c_long).contains(&ts.tv_nsec) {
+        return None;
+    }
+    Some(Duration::from(*ts))
+}
+
+pub fn timer_create(clk_id: clockid_t, sevp: *const SigEvent, timerid: *mut c_int) -> c_int {
+    if clk_id != libc::CLOCK_REALTIME && clk_id != libc::CLOCK_MONOTONIC {
+        return -libc::EINVAL;
+    }
+    // Without an event, POSIX asks for SIGALRM.
+    if sevp.is_null() {
+        return -libc::ENOTSUP;
+    }
+    if timerid.is_null() {
+        return -libc::EFAULT;
+    }
+    let sev = unsafe { &*sevp };
+    let notifier = match sev.sigev_notify {
+        SIGEV_NONE => None,
+        SIGEV_THREAD => {
+            let Some(function) = sev.sigev_notify_function else {
+                return -libc::EINVAL;
+            };
+            let value = unsafe { mem::transmute::<SigVal, usize>(sev.sigev_value) };
+            Some((function, value))
+        }
+        SIGEV_SIGNAL => return -libc::ENOTSUP,
+        _ => return -libc::EINVAL,
+    };
+    let timer = Arc::new(PosixTimer {
+        clock: clk_id,
+        expirations: AtomicUsize::new(0),
+        overrun: AtomicUsize::new(0),
+        deleted: AtomicBool::new(false),
+        armed: SpinLock::new(None),
+    });
+    if let Some((function, value)) = notifier {
+        let this = timer.clone();
+        let t = Builder::new(Entry::Closure(Box::new(move || {
+            this.notify(function, value)
+        })))
+        .set_priority(scheduler::current_thread().priority())
+        .set_name("timer")
+        .build();
+        if !scheduler::queue_ready_thread(thread::CREATED, t) {
+            return -libc::EAGAIN;
+        }
+    }
+    let id = {
+        let mut timers = TIMERS.irqsave_lock();
+        let id = (0..c_int::MAX)
+            .find(|id| !timers.contains_key(id))
+            .unwrap_or(c_int::MAX);
+        timers.insert(id, timer);
+        id
+    };
+    unsafe { timerid.write(id) };
+    0
+}
+
+/// Arms the timer to expire after `new_value.it_value`, or at it with
+/// [`TIMER_ABSTIME`], and then every `it_interval`. A zero `it_value`
+/// disarms it.
+pub fn timer_settime(
+    timerid: c_int,
+    flags: c_int,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> c_int {
+    let Some(timer) = find(timerid) else {
+        return -libc::EINVAL;
+    };
+    if new_value.is_null() {
+        return -libc::EFAULT;
+    }
+    let new_value = unsafe { new_value.read() };
+    let (Some(mut value), Some(interval)) = (
+        to_duration(&new_value.it_value),
+        to_duration(&new_value.it_interval),
+    ) else {
+        return -libc::EINVAL;
+    };
+    let old = timer.disarm();
+    if !old_value.is_null() {
+        unsafe { old_value.write(old) };
+    }
+    if value.is_zero() {
+        return 0;
+    }
+    if flags & TIMER_ABSTIME != 0 {
+        let now = if timer.clock == libc::CLOCK_REALTIME {
+            realtime::now()
+        } else {
+            time::get_uptime()
+        };
+        // Expired already, expire as soon as possible.
+        value = value.saturating_sub(now).max(Duration::from_nanos(1));
+    }
+    timer.arm(value, interval);
+    0
+}
+
+/// Reads the time to the next expiry and the interval, both zero if the
+/// timer is disarmed.
+pub fn timer_gettime(timerid: c_int, curr_value: *mut ITimerSpec) -> c_int {
+    let Some(timer) = find(timerid) else {
+        return -libc::EINVAL;
+    };
+    if curr_value.is_null() {
+        return -libc::EFAULT;
+    }
+    let spec = spec_of(timer.armed.irqsave_lock().as_ref());
+    unsafe { curr_value.write(spec) };
+    0
+}
+
+/// The expiries missed before the last notification, always zero for
+/// `SIGEV_NONE` timers.
+pub fn timer_getoverrun(timerid: c_int) -> c_int {
+    let Some(timer) = find(timerid) else {
+        return -libc::EINVAL;
+    };
+    timer
+        .overrun
+        .load(Ordering::Relaxed)
+        .min(c_int::MAX as usize) as c_int
+}
+
+pub fn timer_delete(timerid: c_int) -> c_int {
+    let Some(timer) = TIMERS.irqsave_lock().remove(&timerid) else {
+        return -libc::EINVAL;
+    };
+    timer.disarm();
+    timer.deleted.store(true, Ordering::Release);
+    // Retire the notifier thread.
+    timer.expire();
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    static NOTIFIED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn on_expiry(value: SigVal) {
+        let notified = unsafe { &*(value.sival_ptr as *const AtomicUsize) };
+        notified.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_posix_timer() {
+        let sev = SigEvent {
+            sigev_value: SigVal {
+                sival_ptr: &NOTIFIED as *const AtomicUsize as *mut c_void,
+            },
+            sigev_signo: 0,
+            sigev_notify: SIGEV_THREAD,
+            sigev_notify_function: Some(on_expiry),
+            sigev_notify_attributes: core::ptr::null_mut(),
+        };
+        let mut id = -1;
+        assert_eq!(timer_create(libc::CLOCK_MONOTONIC, &sev, &mut id), 0);
+        let spec = ITimerSpec {
+            it_interval: Timespec::from(Duration::from_millis(5)),
+            it_value: Timespec::from(Duration::from_millis(10)),
+        };
+        assert_eq!(timer_settime(id, 0, &spec, core::ptr::null_mut()), 0);
+        let mut curr = ITimerSpec {
+            it_interval: Timespec::from(Duration::ZERO),
+            it_value: Timespec::from(Duration::ZERO),
+        };
+        assert_eq!(timer_gettime(id, &mut curr), 0);
+        assert!(Duration::from(curr.it_value) <= Duration::from_millis(10));
+        assert!(!Duration::from(curr.it_value).is_zero());
+        assert_eq!(Duration::from(curr.it_interval), Duration::from_millis(5));
+        time::sleep(Duration::from_millis(32));
+        assert!(NOTIFIED.load(Ordering::Relaxed) >= 3);
+
+        // Disarmed, it stays quiet.
+        let zero = ITimerSpec {
+            it_interval: Timespec::from(Duration::ZERO),
+            it_value: Timespec::from(Duration::ZERO),
+        };
+        assert_eq!(timer_settime(id, 0, &zero, &mut curr), 0);
+        assert_eq!(Duration::from(curr.it_interval), Duration::from_millis(5));
+        time::sleep(Duration::from_millis(2));
+        let notified = NOTIFIED.load(Ordering::Relaxed);
+        time::sleep(Duration::from_millis(15));
+        assert_eq!(NOTIFIED.load(Ordering::Relaxed), notified);
+        assert_eq!(timer_delete(id), 0);
+        assert_eq!(timer_delete(id), -libc::EINVAL);
+
+        // A one-shot timer only counts down.
+        let sev = SigEvent {
+            sigev_notify: SIGEV_NONE,
+            ..sev
+        };
+        assert_eq!(timer_create(libc::CLOCK_REALTIME, &sev, &mut id), 0);
+        let deadline = realtime::now() + Duration::from_millis(10);
+        let spec = ITimerSpec {
+            it_interval: Timespec::from(Duration::ZERO),
+            it_value: Timespec::from(deadline),
+        };
+        assert_eq!(timer_settime(id, TIMER_ABSTIME, &spec, &mut curr), 0);
+        assert!(Duration::from(curr.it_value).is_zero());
+        assert_eq!(timer_gettime(id, &mut curr), 0);
+        assert!(!Duration::from(curr.it_value).is_zero());
+        time::sleep(Duration::from_millis(15));
+        assert_eq!(timer_gettime(id, &mut curr), 0);
+        assert!(Duration::from(curr.it_value).is_zero());
+        assert_eq!(timer_getoverrun(id), 0);
+        assert_eq!(timer_delete(id), 0);
+
+        let sev = SigEvent {
+            sigev_notify: SIGEV_SIGNAL,
+            ..sev
+        };
+        assert_eq!(
+            timer_create(libc::CLOCK_MONOTONIC, &sev, &mut id),
+            -libc::ENOTSUP
+        );
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time of day.
+//!
+//! The wall clock is the uptime plus an offset. [`set`] steps the offset,
+//! while [`adjust`] slews it at [`SLEW_RATE_PPM`], so that the clock neither
+//! jumps nor goes backwards while it's being disciplined. The RTC is
+//! updated with the new time whenever the clock is changed.
+
+use crate::{
+    devices::{rtc, DeviceManager},
+    error::{code, Error},
+    sync::SpinLock,
+    time,
+};
+use core::time::Duration;
+
+/// Rate at which adjustments are applied, the same as Linux's `adjtime`.
+pub const SLEW_RATE_PPM: u64 = 500;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+#[derive(Debug)]
+struct Clock {
+    // Nanoseconds, which last until 2262.
+    offset: i64,
+    // Adjustment left to apply when slew_start was reached, and the uptime
+    // it started from.
+    slew: i64,
+    slew_start: u64,
+}
+
+impl Clock {
+    const fn new() -> Self {
+        Self {
+            offset: 0,
+            slew: 0,
+            slew_start: 0,
+        }
+    }
+
+    fn slewed(&self, uptime: u64) -> i64 {
+        let elapsed = uptime.saturating_sub(self.slew_start) as u128;
+        let max = (elapsed * SLEW_RATE_PPM as u128 / 1_000_000).min(i64::MAX as u128) as i64;
+        self.slew.clamp(-max, max)
+    }
+
+    fn now(&self, uptime: u64) -> i64 {
+        uptime as i64 + self.offset + self.slewed(uptime)
+    }
+
+    fn remaining(&self, uptime: u64) -> i64 {
+        self.slew - self.slewed(uptime)
+    }
+
+    fn step(&mut self, uptime: u64, now: i64) {
+        self.offset = now - uptime as i64;
+        self.slew = 0;
+    }
+
+    // Starts a new adjustment from `uptime` and returns what was left of
+    // the previous one.
+    fn adjust(&mut self, uptime: u64, delta: i64) -> i64 {
+        let slewed = self.slewed(uptime);
+        let remaining = self.slew - slewed;
+        self.offset += slewed;
+        self.slew = delta;
+        self.slew_start = uptime;
+        remaining
+    }
+}
+
+static CLOCK: SpinLock<Clock> = SpinLock::new(Clock::new());
+
+fn uptime_nanos() -> u64 {
+    time::get_uptime().as_nanos() as u64
+}
+
+fn nanos_to_duration(nanos: i64) -> Duration {
+    Duration::from_nanos(nanos.max(0) as u64)
+}
+
+/// Time since the Unix epoch.
+pub fn now() -> Duration {
+    let now = CLOCK.irqsave_lock().now(uptime_nanos());
+    nanos_to_duration(now)
+}
+
+/// Steps the clock to `now`.
+pub fn set(now: Duration) {
+    let now = now.as_nanos().min(i64::MAX as u128) as i64;
+    CLOCK.irqsave_lock().step(uptime_nanos(), now);
+    sync_to_rtc(nanos_to_duration(now));
+}
+
+/// Slews the clock by `delta` nanoseconds, replacing any adjustment in
+/// progress. Returns the part of the previous adjustment which wasn't
+/// applied yet.
+pub fn adjust(delta: i64) -> i64 {
+    let (remaining, target) = {
+        let mut clock = CLOCK.irqsave_lock();
+        let uptime = uptime_nanos();
+        let remaining = clock.adjust(uptime, delta);
+        (remaining, clock.now(uptime) + delta)
+    };
+    // The RTC gets the time we're converging to.
+    sync_to_rtc(nanos_to_duration(target));
+    remaining
+}
+
+/// Returns the part of the current adjustment which wasn't applied yet.
+pub fn pending_adjustment() -> i64 {
+    CLOCK.irqsave_lock().remaining(uptime_nanos())
+}
+
+/// Sets the clock from the RTC, if there is one.
+pub fn sync_from_rtc() -> Result<(), Error> {
+    let dev = DeviceManager::get()
+        .get_char_device(rtc::RTC_DEVICE_NAME)
+        .ok_or(code::ENOENT)?;
+    let secs = rtc::read_time(&*dev)?;
+    let now = (secs as i64).saturating_mul(NANOS_PER_SEC);
+    CLOCK.irqsave_lock().step(uptime_nanos(), now);
+    Ok(())
+}
+
+fn sync_to_rtc(now: Duration) {
+    let Some(dev) = DeviceManager::get().get_char_device(rtc::RTC_DEVICE_NAME) else {
+        return;
+    };
+    if let Err(e) = rtc::set_time(&*dev, now.as_secs()) {
+        log::warn!("Failed to update the RTC: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_clock_slew() {
+        let mut clock = Clock::new();
+        clock.step(1000, 5000);
+        assert_eq!(clock.now(2000), 6000);
+
+        // 1ms at 500ppm takes 2s.
+        assert_eq!(clock.adjust(2000, 1_000_000), 0);
+        assert_eq!(clock.now(2000), 6000);
+        assert_eq!(clock.now(1_000_002_000), 1_000_006_000 + 500_000);
+        assert_eq!(clock.remaining(1_000_002_000), 500_000);
+        assert_eq!(clock.now(3_000_002_000), 3_000_006_000 + 1_000_000);
+        assert_eq!(clock.remaining(3_000_002_000), 0);
+
+        // Slewing backwards still moves forwards.
+        assert_eq!(clock.adjust(3_000_002_000, -1_000_000), 0);
+        let before = clock.now(3_000_002_000);
+        let after = clock.now(3_000_002_001);
+        assert!(after >= before);
+        assert_eq!(
+            clock.adjust(4_000_002_000, 0),
+            -500_000,
+            "half of the adjustment is left"
+        );
+    }
+}
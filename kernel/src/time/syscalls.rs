@@ -0,0 +1,252 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C API for clocks and sleeping
+use crate::{
+    net::Timeval,
+    time::{self, duration_to_ticks, realtime, Instant},
+    vfs::syscalls::Timespec,
+};
+use core::{
+    ffi::{c_int, c_uint, c_void},
+    time::Duration,
+};
+use libc::clockid_t;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+const USECS_PER_SEC: i64 = 1_000_000;
+/// `clock_nanosleep` flag, the request is an absolute time.
+pub const TIMER_ABSTIME: c_int = 1;
+
+fn clock_now(clk_id: clockid_t) -> Option<Duration> {
+    match clk_id {
+        libc::CLOCK_REALTIME => Some(realtime::now()),
+        libc::CLOCK_MONOTONIC => Some(time::get_uptime()),
+        _ => None,
+    }
+}
+
+/// Converts an absolute `CLOCK_REALTIME` timeout to a number of ticks from
+/// now, 0 if it has expired. Returns a negative errno if it's invalid.
+pub(crate) fn abstime_to_ticks(abstime: *const Timespec) -> Result<usize, c_int> {
+    if abstime.is_null() {
+        return Err(-libc::EFAULT);
+    }
+    let abstime = unsafe { abstime.read() };
+    if abstime.tv_sec < 0 || !(0..NANOS_PER_SEC as libc::c_long).contains(&abstime.tv_nsec) {
+        return Err(-libc::EINVAL);
+    }
+    Ok(duration_to_ticks(
+        Duration::from(abstime).saturating_sub(realtime::now()),
+    ))
+}
+
+/// Converts a relative timeout to a number of ticks. Returns a negative
+/// errno if it's invalid.
+pub(crate) fn reltime_to_ticks(reltime: *const Timespec) -> Result<usize, c_int> {
+    if reltime.is_null() {
+        return Err(-libc::EFAULT);
+    }
+    let reltime = unsafe { reltime.read() };
+    if reltime.tv_sec < 0 || !(0..NANOS_PER_SEC as libc::c_long).contains(&reltime.tv_nsec) {
+        return Err(-libc::EINVAL);
+    }
+    Ok(duration_to_ticks(Duration::from(reltime)))
+}
+
+pub fn clock_gettime(clk_id: clockid_t, tp: *mut Timespec) -> c_int {
+    let Some(now) = clock_now(clk_id) else {
+        return -libc::EINVAL;
+    };
+    if tp.is_null() {
+        return -libc::EFAULT;
+    }
+    unsafe { tp.write(Timespec::from(now)) };
+    0
+}
+
+pub fn clock_getres(clk_id: clockid_t, res: *mut Timespec) -> c_int {
+    if clock_now(clk_id).is_none() {
+        return -libc::EINVAL;
+    }
+    // POSIX allows a null res.
+    if !res.is_null() {
+        let resolution = time::get_cycles_to_duration(1).max(Duration::from_nanos(1));
+        unsafe { res.write(Timespec::from(resolution)) };
+    }
+    0
+}
+
+/// Steps `CLOCK_REALTIME`, the only clock which can be set.
+pub fn clock_settime(clk_id: clockid_t, tp: *const Timespec) -> c_int {
+    if clk_id != libc::CLOCK_REALTIME {
+        return -libc::EINVAL;
+    }
+    if tp.is_null() {
+        return -libc::EFAULT;
+    }
+    let tp = unsafe { tp.read() };
+    if tp.tv_sec < 0 || !(0..NANOS_PER_SEC as libc::c_long).contains(&tp.tv_nsec) {
+        return -libc::EINVAL;
+    }
+    realtime::set(Duration::from(tp));
+    0
+}
+
+pub fn clock_nanosleep(
+    clk_id: clockid_t,
+    flags: c_int,
+    req: *const Timespec,
+    rem: *mut Timespec,
+) -> c_int {
+    let Some(now) = clock_now(clk_id) else {
+        return -libc::EINVAL;
+    };
+    if req.is_null() {
+        return -libc::EFAULT;
+    }
+    let req = unsafe { req.read() };
+    if req.tv_sec < 0 || !(0..NANOS_PER_SEC as libc::c_long).contains(&req.tv_nsec) {
+        return -libc::EINVAL;
+    }
+    let req = Duration::from(req);
+    if flags & TIMER_ABSTIME == 0 {
+        let deadline = Instant::now() + req;
+        time::sleep(req);
+        // What's left of the sleep, which stays zero as long as there are
+        // no signals to interrupt it.
+        if !rem.is_null() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            unsafe { rem.write(Timespec::from(remaining)) };
+        }
+    } else if clk_id == libc::CLOCK_MONOTONIC {
+        time::sleep_until(Instant::from_uptime(req));
+    } else {
+        // The wall clock may be stepped while we sleep, so it's read again
+        // once we wake up.
+        let mut now = now;
+        while now < req {
+            time::sleep(req - now);
+            now = realtime::now();
+        }
+    }
+    0
+}
+
+pub fn nanosleep(req: *const Timespec, rem: *mut Timespec) -> c_int {
+    clock_nanosleep(libc::CLOCK_MONOTONIC, 0, req, rem)
+}
+
+pub fn usleep(usec: c_uint) -> c_int {
+    time::sleep(Duration::from_micros(usec as u64));
+    0
+}
+
+/// The obsolete timezone argument is ignored.
+pub fn gettimeofday(tv: *mut Timeval, _tz: *mut c_void) -> c_int {
+    if !tv.is_null() {
+        unsafe { tv.write(Timeval::from(realtime::now())) };
+    }
+    0
+}
+
+/// Steps the clock. Prefer [`adjtime`] for small corrections.
+pub fn settimeofday(tv: *const Timeval, _tz: *const c_void) -> c_int {
+    if tv.is_null() {
+        return -libc::EFAULT;
+    }
+    let tv = unsafe { tv.read() };
+    if tv.tv_sec < 0 || !(0..USECS_PER_SEC as libc::suseconds_t).contains(&tv.tv_usec) {
+        return -libc::EINVAL;
+    }
+    realtime::set(Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000));
+    0
+}
+
+/// Slews the clock by `delta`, or only reports the adjustment in progress
+/// if `delta` is null.
+pub fn adjtime(delta: *const Timeval, olddelta: *mut Timeval) -> c_int {
+    let remaining = if delta.is_null() {
+        realtime::pending_adjustment()
+    } else {
+        let delta = unsafe { delta.read() };
+        let usecs = (delta.tv_sec as i64)
+            .checked_mul(USECS_PER_SEC)
+            .and_then(|us| us.checked_add(delta.tv_usec as i64));
+        let Some(nanos) = usecs.and_then(|us| us.checked_mul(1000)) else {
+            return -libc::EINVAL;
+        };
+        realtime::adjust(nanos)
+    };
+    if !olddelta.is_null() {
+        let usecs = remaining / 1000;
+        unsafe {
+            olddelta.write(Timeval {
+                tv_sec: (usecs / USECS_PER_SEC) as libc::time_t,
+                tv_usec: (usecs % USECS_PER_SEC) as libc::suseconds_t,
+            })
+        };
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_duration_to_ticks() {
+        use blueos_kconfig::TICKS_PER_SECOND;
+
+        assert_eq!(duration_to_ticks(Duration::ZERO), 0);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(1)), 1);
+        assert_eq!(
+            duration_to_ticks(Duration::from_secs(3)),
+            3 * TICKS_PER_SECOND
+        );
+        assert_eq!(duration_to_ticks(Duration::MAX), time::WAITING_FOREVER - 1);
+    }
+
+    #[test]
+    fn test_clock_gettime() {
+        let mut a = Timespec::from(Duration::ZERO);
+        let mut b = Timespec::from(Duration::ZERO);
+        assert_eq!(clock_gettime(libc::CLOCK_MONOTONIC, &mut a), 0);
+        let req = Timespec::from(Duration::from_millis(10));
+        assert_eq!(nanosleep(&req, core::ptr::null_mut()), 0);
+        assert_eq!(clock_gettime(libc::CLOCK_MONOTONIC, &mut b), 0);
+        assert!(Duration::from(b) - Duration::from(a) >= Duration::from_millis(10));
+
+        assert_eq!(clock_gettime(-1, &mut a), -libc::EINVAL);
+        assert_eq!(clock_getres(libc::CLOCK_REALTIME, &mut a), 0);
+        assert!(Duration::from(a) > Duration::ZERO);
+        let bad = Timespec {
+            tv_sec: 0,
+            tv_nsec: NANOS_PER_SEC as libc::c_long,
+        };
+        assert_eq!(nanosleep(&bad, core::ptr::null_mut()), -libc::EINVAL);
+        assert_eq!(clock_settime(libc::CLOCK_REALTIME, &bad), -libc::EINVAL);
+        assert_eq!(clock_settime(libc::CLOCK_MONOTONIC, &b), -libc::EINVAL);
+
+        assert_eq!(clock_gettime(libc::CLOCK_REALTIME, &mut a), 0);
+        let later = Timespec::from(Duration::from(a) + Duration::from_secs(60));
+        assert_eq!(clock_settime(libc::CLOCK_REALTIME, &later), 0);
+        assert_eq!(clock_gettime(libc::CLOCK_REALTIME, &mut b), 0);
+        assert!(Duration::from(b) >= Duration::from(later));
+        assert!(Duration::from(b) - Duration::from(later) < Duration::from_secs(1));
+        let back = Timespec::from(Duration::from(b) - Duration::from_secs(60));
+        assert_eq!(clock_settime(libc::CLOCK_REALTIME, &back), 0);
+    }
+}
@@ -68,3 +68,22 @@ impl Systick {
         CNTP_TVAL_EL0.set(self.get_step() as u64);
     }
 }
+
+#[cfg(tickless)]
+impl Systick {
+    fn hw_ticks(&self) -> usize {
+        (self.get_cycles() / self.get_step() as u64) as usize
+    }
+
+    /// Interrupts at the start of tick `get_tick() + ticks`, or as late as
+    /// TVAL reaches.
+    pub fn set_timeout_ticks(&self, ticks: usize) {
+        let deadline =
+            (self.get_tick().saturating_add(ticks) as u64).saturating_mul(self.get_step() as u64);
+        // TVAL is a signed 32-bit count.
+        let delta = deadline
+            .saturating_sub(self.get_cycles())
+            .clamp(1, i32::MAX as u64);
+        CNTP_TVAL_EL0.set(delta);
+    }
+}
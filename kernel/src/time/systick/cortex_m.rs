@@ -13,19 +13,35 @@
 // limitations under the License.
 
 use crate::arch::irq::IRQ_PRIORITY_FOR_SCHEDULER;
+#[cfg(tickless)]
+use crate::sync::SpinLock;
+#[cfg(tickless)]
+use cortex_m::peripheral::SCB;
 use cortex_m::{
     peripheral::{scb::SystemHandler, syst::SystClkSource, SYST},
     Peripherals,
 };
 
 pub const SYSTICK_IRQ_NUM: IrqNumber = IrqNumber::new(14);
+const SYST_COUNTER_MASK: u32 = 0x00ff_ffff;
+
+// SysTick only counts down through its reload value, so the cycles of the
+// periods already elapsed are accumulated here. A period is reload + 1
+// cycles.
+#[cfg(tickless)]
+struct Periods {
+    start: u64,
+    len: u32,
+}
+
+#[cfg(tickless)]
+static PERIODS: SpinLock<Periods> = SpinLock::new(Periods { start: 0, len: 0 });
 
 impl Systick {
     pub fn init(&self, sys_clock: u32, tick_per_second: u32) -> bool {
         let mut scb = unsafe { Peripherals::steal() };
 
         let reload = sys_clock / tick_per_second;
-        const SYST_COUNTER_MASK: u32 = 0x00ff_ffff;
         if reload > SYST_COUNTER_MASK {
             return false;
         }
@@ -38,6 +54,10 @@ impl Systick {
             scb.SCB
                 .set_priority(SystemHandler::SysTick, IRQ_PRIORITY_FOR_SCHEDULER);
         }
+        #[cfg(tickless)]
+        {
+            PERIODS.irqsave_lock().len = reload + 1;
+        }
         scb.SYST.set_clock_source(SystClkSource::Core);
         scb.SYST.set_reload(reload);
         scb.SYST.clear_current();
@@ -46,6 +66,7 @@ impl Systick {
         true
     }
 
+    #[cfg(not(tickless))]
     pub fn get_cycles(&self) -> u64 {
         let step = self.get_step() as u64;
         let current = step - SYST::get_current() as u64;
@@ -57,3 +78,49 @@ impl Systick {
         // no need to reset counter
     }
 }
+
+#[cfg(tickless)]
+impl Systick {
+    // Returns the cycles elapsed since boot, adding the period which just
+    // ended to `periods` if the counter wrapped.
+    fn cycles_locked(periods: &mut Periods) -> u64 {
+        // SAFETY: COUNTFLAG is only read here, with PERIODS locked.
+        let mut syst = unsafe { Peripherals::steal() }.SYST;
+        let mut current = SYST::get_current();
+        if syst.has_wrapped() {
+            periods.start += periods.len as u64;
+            current = SYST::get_current();
+        }
+        periods.start + (periods.len as u64).saturating_sub(current as u64 + 1)
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        Self::cycles_locked(&mut PERIODS.irqsave_lock())
+    }
+
+    fn hw_ticks(&self) -> usize {
+        (self.get_cycles() / self.get_step() as u64) as usize
+    }
+
+    /// Interrupts at the start of tick `get_tick() + ticks`, or as late as
+    /// the 24-bit counter reaches.
+    pub fn set_timeout_ticks(&self, ticks: usize) {
+        let mut periods = PERIODS.irqsave_lock();
+        let now = Self::cycles_locked(&mut periods);
+        let deadline =
+            (self.get_tick().saturating_add(ticks) as u64).saturating_mul(self.get_step() as u64);
+        let len = deadline
+            .saturating_sub(now)
+            .clamp(2, SYST_COUNTER_MASK as u64 + 1) as u32;
+        // SAFETY: the counter is only reprogrammed here, with PERIODS
+        // locked.
+        let mut syst = unsafe { Peripherals::steal() }.SYST;
+        syst.set_reload(len - 1);
+        // Restarts the count from the new reload value.
+        syst.clear_current();
+        periods.start = now;
+        periods.len = len;
+        // A wrap before the reload was accounted for above.
+        SCB::clear_pendst();
+    }
+}
@@ -66,3 +66,13 @@ impl Systick {
         self.tick.fetch_add(1, Ordering::Relaxed) + 1
     }
 }
+
+#[cfg(tickless)]
+impl Systick {
+    /// Catches the tick count up with the hardware counter, returning the
+    /// number of ticks elapsed since it was last updated.
+    pub fn sync_ticks(&self) -> usize {
+        let now = self.hw_ticks();
+        now.saturating_sub(self.tick.fetch_max(now, Ordering::Relaxed))
+    }
+}
@@ -20,6 +20,14 @@ static BOOT_CYCLE_COUNT: Once<u64> = Once::new();
 fn get_boot_cycle_count() -> u64 {
     *BOOT_CYCLE_COUNT.call_once(|| boards::current_cycles() as u64)
 }
+// The tick follows the timer rather than the cycle counter, which may not
+// run at a fixed rate.
+#[cfg(tickless)]
+static BOOT_NANOS: Once<u64> = Once::new();
+#[cfg(tickless)]
+fn get_boot_nanos() -> u64 {
+    *BOOT_NANOS.call_once(boards::current_nanos)
+}
 
 impl Systick {
     pub fn init(&self, _sys_clock: u32, tick_per_second: u32) -> bool {
@@ -30,6 +38,8 @@ impl Systick {
         }
         boards::set_timeout_after(step);
         let _ = get_boot_cycle_count();
+        #[cfg(tickless)]
+        let _ = get_boot_nanos();
         true
     }
 
@@ -41,3 +51,18 @@ impl Systick {
         boards::set_timeout_after(self.get_step());
     }
 }
+
+#[cfg(tickless)]
+impl Systick {
+    fn hw_ticks(&self) -> usize {
+        let nanos = boards::current_nanos().saturating_sub(get_boot_nanos());
+        (nanos / self.get_step() as u64) as usize
+    }
+
+    /// Interrupts at the start of tick `get_tick() + ticks`.
+    pub fn set_timeout_ticks(&self, ticks: usize) {
+        let deadline =
+            (self.get_tick().saturating_add(ticks) as u64).saturating_mul(self.get_step() as u64);
+        boards::set_timeout_at(get_boot_nanos().saturating_add(deadline));
+    }
+}
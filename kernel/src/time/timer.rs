@@ -12,9 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Timers on hierarchical wheels.
+//!
+//! Each wheel has [`TIMER_WHEEL_LEVELS`] levels of [`TIMER_WHEEL_SIZE`]
+//! slots. A slot of level 0 holds the timers of one tick, a slot of level
+//! `n` those of `TIMER_WHEEL_SIZE^n` ticks, which are spread over the level
+//! below when the wheel gets to the slot. Timers further away than the
+//! wheel reaches wait on an overflow list, put back on it each time it has
+//! turned fully. Starting and stopping a timer is O(1), and so is each
+//! tick, whatever the number of timers.
+//!
+//! Hard timers run their callback in the tick interrupt. Soft timers run
+//! it in the soft timer thread, where it may sleep or allocate. That thread
+//! yields once it has run callbacks for SOFT_TIMER_SLICE_MS in a row, so a
+//! burst of timers doesn't starve threads of the same priority. Each timer
+//! may also be given a runtime budget, callbacks running over it are
+//! counted in [`TimerStats`] and logged.
+
 use crate::{
     boards, config, scheduler, sync, thread,
-    time::get_sys_ticks,
+    time::{get_cycles_to_duration, get_sys_cycles, get_sys_ticks},
     types::{impl_simple_intrusive_adapter, Arc, ArcList, AtomicIlistHead as IlistHead},
 };
 use alloc::boxed::Box;
@@ -22,13 +39,19 @@ use bitflags::bitflags;
 use core::{
     cmp, fmt,
     mem::MaybeUninit,
-    sync::atomic::{AtomicU32, Ordering},
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    time::Duration,
 };
 use log::warn;
 use sync::spinlock::SpinLock;
 use thread::{Entry, SystemThreadStorage, Thread, ThreadKind, ThreadNode};
 
-const TIMER_WHEEL_SIZE: u32 = 32;
+const TIMER_WHEEL_BITS: u32 = 5;
+const TIMER_WHEEL_SIZE: usize = 1 << TIMER_WHEEL_BITS;
+const TIMER_WHEEL_MASK: usize = TIMER_WHEEL_SIZE - 1;
+const TIMER_WHEEL_LEVELS: usize = 4;
+// Ticks ahead the wheel reaches.
+const TIMER_WHEEL_SPAN: usize = 1 << (TIMER_WHEEL_BITS as usize * TIMER_WHEEL_LEVELS);
 
 static HARD_TIMER_WHEEL: TimerWheel = TimerWheel::const_new();
 #[cfg(soft_timer)]
@@ -38,25 +61,27 @@ static mut SOFT_TIMER_THREAD: MaybeUninit<ThreadNode> = MaybeUninit::zeroed();
 #[cfg(soft_timer)]
 static SOFT_TIMER_THREAD_STACK: SystemThreadStorage =
     SystemThreadStorage::const_new(ThreadKind::SoftTimer);
+// Bumped whenever the soft timer wheel changes. The soft timer thread waits
+// on it rather than sleeping, so that it's only woken up to rescan the
+// wheel, and not in the middle of a callback which sleeps.
+#[cfg(soft_timer)]
+static SOFT_TIMER_SEQ: AtomicUsize = AtomicUsize::new(0);
+#[cfg(soft_timer)]
+const SOFT_TIMER_SLICE: Duration =
+    Duration::from_millis(blueos_kconfig::SOFT_TIMER_SLICE_MS as u64);
 
 #[cfg(soft_timer)]
 extern "C" fn run_soft_timer() {
     loop {
+        let seq = SOFT_TIMER_SEQ.load(Ordering::Acquire);
         let next_timeout = SOFT_TIMER_WHEEL.next_timeout();
-        if next_timeout != usize::MAX {
-            let ct = get_sys_ticks();
-            let wait_time = next_timeout.saturating_sub(ct);
-            if wait_time > 0 {
-                scheduler::suspend_me_for(wait_time);
-                let wakeup_ct = get_sys_ticks();
-                if wakeup_ct < next_timeout {
-                    continue;
-                }
-            }
-            SOFT_TIMER_WHEEL.check_timer(next_timeout);
-        } else {
-            scheduler::suspend_me_for(super::WAITING_FOREVER);
+        let ct = get_sys_ticks();
+        if next_timeout <= ct {
+            SOFT_TIMER_WHEEL.check_timer(ct, Some(SOFT_TIMER_SLICE));
+            continue;
         }
+        let timeout = (next_timeout != usize::MAX).then(|| next_timeout - ct);
+        let _ = sync::atomic_wait(&SOFT_TIMER_SEQ, seq, timeout);
     }
 }
 
@@ -74,21 +99,151 @@ pub fn system_timer_init() {
             ThreadKind::SoftTimer,
         );
         let ok = scheduler::queue_ready_thread(thread::CREATED, th);
-        debug_assert!(ok);
+        crate::kassert!(ok);
     }
 }
 
+#[cfg(soft_timer)]
 fn wakeup_soft_timer_thread() {
-    let th = unsafe { SOFT_TIMER_THREAD.assume_init_ref() };
-    if let Some(timer) = &th.timer {
-        timer.stop();
+    SOFT_TIMER_SEQ.fetch_add(1, Ordering::Release);
+    let _ = sync::atomic_wake(&SOFT_TIMER_SEQ, 1);
+}
+
+fn level_shift(level: usize) -> u32 {
+    TIMER_WHEEL_BITS * level as u32
+}
+
+struct Wheel {
+    // The next tick to run, timers expired before it run then.
+    clk: usize,
+    slots: [[WheelTimerList; TIMER_WHEEL_SIZE]; TIMER_WHEEL_LEVELS],
+    // One bit per slot which may hold timers. Bits of the slots emptied by
+    // remove_timer are cleared when next_event comes across them.
+    pending: [u32; TIMER_WHEEL_LEVELS],
+    overflow: WheelTimerList,
+}
+
+impl Wheel {
+    const fn const_new() -> Self {
+        Self {
+            clk: 0,
+            slots: [const { [const { WheelTimerList::const_new() }; TIMER_WHEEL_SIZE] };
+                TIMER_WHEEL_LEVELS],
+            pending: [0; TIMER_WHEEL_LEVELS],
+            overflow: WheelTimerList::const_new(),
+        }
+    }
+
+    fn init(&mut self) {
+        for slot in self.slots.iter_mut().flatten() {
+            let ok = slot.init();
+            crate::kassert!(ok);
+        }
+        let ok = self.overflow.init();
+        crate::kassert!(ok);
+    }
+
+    // Puts `timer` on the slot of the lowest level `expires` is in reach of.
+    fn place(&mut self, timer: Arc<Timer>, expires: usize) {
+        let expires = cmp::max(expires, self.clk);
+        let delta = expires - self.clk;
+        for level in 0..TIMER_WHEEL_LEVELS {
+            if delta >> level_shift(level + 1) == 0 {
+                let index = (expires >> level_shift(level)) & TIMER_WHEEL_MASK;
+                self.slots[level][index].push_back(timer);
+                self.pending[level] |= 1 << index;
+                return;
+            }
+        }
+        self.overflow.push_back(timer);
+    }
+
+    // Puts the timers of `list` back on the wheel, from where it is now.
+    fn reschedule(&mut self, list: &mut WheelTimerList) {
+        while let Some(timer) = list.pop_front() {
+            let expires = timer.timeout_ticks();
+            self.place(timer, expires);
+        }
+    }
+
+    // Returns the first tick from clk on at which a timer expires or a slot
+    // is spread over the level below, usize::MAX if there's no timer.
+    fn next_event(&mut self) -> usize {
+        let mut next = usize::MAX;
+        for level in 0..TIMER_WHEEL_LEVELS {
+            let shift = level_shift(level);
+            // The first slot the wheel hasn't got to. A slot of level 0 holds
+            // the timers of one tick, the one clk is in is yet to run. The
+            // one of higher levels was spread already, unless clk is at its
+            // start.
+            let start = self.clk.div_ceil(1 << shift);
+            loop {
+                let ahead = self.pending[level].rotate_right((start & TIMER_WHEEL_MASK) as u32);
+                if ahead == 0 {
+                    break;
+                }
+                let slot = start.saturating_add(ahead.trailing_zeros() as usize);
+                let index = slot & TIMER_WHEEL_MASK;
+                if self.slots[level][index].is_empty() {
+                    self.pending[level] &= !(1 << index);
+                    continue;
+                }
+                next = cmp::min(next, slot.saturating_mul(1 << shift));
+                break;
+            }
+        }
+        if !self.overflow.is_empty() {
+            let turn = self.clk.div_ceil(TIMER_WHEEL_SPAN);
+            next = cmp::min(next, turn.saturating_mul(TIMER_WHEEL_SPAN));
+        }
+        next
+    }
+
+    // Turns the wheel up to `now`, moving the timers expired meanwhile to
+    // `expired`. Ticks at which nothing happens are skipped.
+    fn advance(&mut self, now: usize, expired: &mut WheelTimerList) {
+        let mut list = WheelTimerList::new();
+        list.init();
+        loop {
+            let tick = self.next_event();
+            if tick > now {
+                break;
+            }
+            self.clk = tick;
+            if tick % TIMER_WHEEL_SPAN == 0 {
+                while let Some(timer) = self.overflow.pop_front() {
+                    list.push_back(timer);
+                }
+                self.reschedule(&mut list);
+            }
+            for level in (1..TIMER_WHEEL_LEVELS).rev() {
+                let shift = level_shift(level);
+                if tick & ((1 << shift) - 1) != 0 {
+                    continue;
+                }
+                let index = (tick >> shift) & TIMER_WHEEL_MASK;
+                while let Some(timer) = self.slots[level][index].pop_front() {
+                    list.push_back(timer);
+                }
+                self.pending[level] &= !(1 << index);
+                self.reschedule(&mut list);
+            }
+            let index = tick & TIMER_WHEEL_MASK;
+            while let Some(timer) = self.slots[0][index].pop_front() {
+                expired.push_back(timer);
+            }
+            self.pending[0] &= !(1 << index);
+            let Some(next) = tick.checked_add(1) else {
+                return;
+            };
+            self.clk = next;
+        }
+        self.clk = cmp::max(self.clk, now.saturating_add(1));
     }
-    let ok = scheduler::queue_ready_thread(thread::SUSPENDED, th.clone());
-    debug_assert!(ok);
 }
 
 struct TimerWheel {
-    wheel: SpinLock<[WheelTimerList; TIMER_WHEEL_SIZE as usize]>,
+    wheel: SpinLock<Wheel>,
 }
 
 unsafe impl Sync for TimerWheel {}
@@ -96,37 +251,29 @@ unsafe impl Sync for TimerWheel {}
 impl TimerWheel {
     const fn const_new() -> Self {
         Self {
-            wheel: SpinLock::const_new(
-                [const { WheelTimerList::const_new() }; TIMER_WHEEL_SIZE as usize],
-            ),
+            wheel: SpinLock::const_new(Wheel::const_new()),
         }
     }
 
     fn init(&self) {
-        let mut wheel = self.wheel.irqsave_lock();
-        for i in 0..wheel.len() {
-            let ok = wheel[i].init();
-            debug_assert!(ok);
-        }
+        self.wheel.irqsave_lock().init();
     }
 
-    fn add_timer(&self, mut timer: Arc<Timer>, timeout_ticks: usize) {
-        let mut wheel = self.wheel.irqsave_lock();
-        let cursor = timeout_ticks & (TIMER_WHEEL_SIZE as usize - 1);
-        let it = wheel[cursor].iter();
-        for mut t in it {
-            if t.timeout_ticks() > timeout_ticks {
-                WheelTimerList::insert_before(
-                    unsafe { WheelTimerList::list_head_of_mut_unchecked(&mut t) },
-                    timer,
-                );
-                return;
+    fn add_timer(&self, timer: Arc<Timer>, timeout_ticks: usize) {
+        #[cfg(soft_timer)]
+        let is_soft = timer.is_soft();
+        {
+            let mut wheel = self.wheel.irqsave_lock();
+            // The soft timer wheel only turns when it has timers, catch up
+            // so that new ones are placed from now.
+            if wheel.next_event() == usize::MAX {
+                wheel.clk = cmp::max(wheel.clk, get_sys_ticks());
             }
+            wheel.place(timer, timeout_ticks);
         }
-        wheel[cursor].push_back(timer.clone());
         #[cfg(soft_timer)]
         {
-            if timer.is_soft() {
+            if is_soft {
                 wakeup_soft_timer_thread();
             }
         }
@@ -144,43 +291,36 @@ impl TimerWheel {
         }
     }
 
+    // Returns the tick the wheel has to be checked at next, which is never
+    // after the next timeout but may be before it.
     fn next_timeout(&self) -> usize {
-        let mut next_timeout_tick = usize::MAX;
-        let wheel = self.wheel.irqsave_lock();
-        for i in 0..TIMER_WHEEL_SIZE as usize {
-            if let Some(timer) = wheel[i].front() {
-                let timeout_ticks = timer.timeout_ticks();
-                if timeout_ticks < next_timeout_tick {
-                    next_timeout_tick = timeout_ticks;
-                }
-            }
-        }
-        next_timeout_tick
+        self.wheel.irqsave_lock().next_event()
     }
 
-    fn check_timer(&self, current_ticks: usize) -> bool {
+    // Runs the timers expired up to `current_ticks`. With a `slice`, the
+    // current thread yields whenever it has run callbacks for that long.
+    fn check_timer(&self, current_ticks: usize, slice: Option<Duration>) -> bool {
         let mut need_reschedule = false;
-        let cursor = current_ticks & (TIMER_WHEEL_SIZE as usize - 1);
         let mut task_list = WheelTimerList::new();
         task_list.init();
-        {
-            let wheel = self.wheel.irqsave_lock();
-            let mut iter = wheel[cursor].iter();
-            for mut timer in iter {
-                if timer.timeout_ticks() > current_ticks {
-                    break;
-                }
-                WheelTimerList::detach(&mut timer);
-                task_list.push_back(timer);
-            }
-        }
+        self.wheel
+            .irqsave_lock()
+            .advance(current_ticks, &mut task_list);
 
+        let mut slice_start = get_sys_cycles();
         while let Some(timer) = task_list.pop_front() {
             timer.run();
             need_reschedule = true;
             if timer.is_periodic() {
                 timer.start();
             }
+            if let Some(slice) = slice {
+                let now = get_sys_cycles();
+                if get_cycles_to_duration(now.saturating_sub(slice_start)) >= slice {
+                    scheduler::yield_me();
+                    slice_start = get_sys_cycles();
+                }
+            }
         }
         need_reschedule
     }
@@ -205,10 +345,21 @@ pub struct Timer {
     inner: SpinLock<Inner>,
 }
 
+/// Runtime accounting of a timer's callback.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimerStats {
+    pub runs: usize,
+    /// Runs which took longer than the budget.
+    pub overruns: usize,
+    pub max_runtime: Duration,
+}
+
 struct Inner {
     interval: usize,
     timeout_ticks: usize,
     callback: Option<Box<dyn Fn() + Send + Sync>>,
+    budget: Option<Duration>,
+    stats: TimerStats,
 }
 
 impl fmt::Debug for Inner {
@@ -252,6 +403,8 @@ impl Timer {
                 interval,
                 timeout_ticks: 0,
                 callback: Some(callback),
+                budget: None,
+                stats: TimerStats::default(),
             }),
         })
     }
@@ -276,6 +429,16 @@ impl Timer {
         self.inner.irqsave_lock().callback = Some(callback);
     }
 
+    /// Sets how long the callback is expected to run at most, `None` for no
+    /// limit. Runs over it are counted as overruns.
+    pub fn set_budget(&self, budget: Option<Duration>) {
+        self.inner.irqsave_lock().budget = budget;
+    }
+
+    pub fn stats(&self) -> TimerStats {
+        self.inner.irqsave_lock().stats
+    }
+
     pub fn start(&self) {
         #[cfg(soft_timer)]
         let is_soft = self.is_soft();
@@ -328,6 +491,13 @@ impl Timer {
     }
 
     pub fn start_new_interval(&self, interval: usize) {
+        self.inner.irqsave_lock().interval = interval;
+        self.start_after(interval);
+    }
+
+    /// Starts the timer to expire after `delay` ticks rather than after its
+    /// interval. A periodic timer goes on with its interval from then.
+    pub fn start_after(&self, delay: usize) {
         #[cfg(soft_timer)]
         let is_soft = self.is_soft();
 
@@ -350,8 +520,7 @@ impl Timer {
         }
 
         let mut inner = self.inner.irqsave_lock();
-        inner.interval = interval;
-        inner.timeout_ticks = get_sys_ticks().saturating_add(interval);
+        inner.timeout_ticks = get_sys_ticks().saturating_add(delay);
         self.flags
             .fetch_or(TimerFlags::ACTIVATED.bits(), Ordering::Relaxed);
 
@@ -441,23 +610,42 @@ impl Timer {
 
     // this function can only used in check_timer and tests
     pub fn run(&self) {
-        if self.is_activated() {
-            self.flags
-                .fetch_and(!TimerFlags::ACTIVATED.bits(), Ordering::Relaxed);
-            let mut inner = self.inner.irqsave_lock();
-            if let Some(callback) = inner.callback.take() {
-                callback();
-                if self.is_periodic() {
-                    inner.callback = Some(callback);
-                }
-            }
+        if !self.is_activated() {
+            return;
+        }
+        self.flags
+            .fetch_and(!TimerFlags::ACTIVATED.bits(), Ordering::Relaxed);
+        // The callback runs unlocked, soft timer callbacks may sleep.
+        let Some(callback) = self.inner.irqsave_lock().callback.take() else {
+            return;
+        };
+        let start = get_sys_cycles();
+        callback();
+        let runtime = get_cycles_to_duration(get_sys_cycles().saturating_sub(start));
+        let mut inner = self.inner.irqsave_lock();
+        inner.stats.runs += 1;
+        inner.stats.max_runtime = cmp::max(inner.stats.max_runtime, runtime);
+        let budget = inner.budget.filter(|&budget| runtime > budget);
+        if budget.is_some() {
+            inner.stats.overruns += 1;
+        }
+        // Unless it was replaced meanwhile.
+        if self.is_periodic() && inner.callback.is_none() {
+            inner.callback = Some(callback);
+        }
+        drop(inner);
+        if let Some(budget) = budget {
+            warn!(
+                "timer callback ran for {:?}, over its {:?} budget",
+                runtime, budget
+            );
         }
     }
 }
 
 // used for systick
 pub(crate) fn check_hard_timer(tick: usize) -> bool {
-    HARD_TIMER_WHEEL.check_timer(tick)
+    HARD_TIMER_WHEEL.check_timer(tick, None)
 }
 // used for tickless
 pub(crate) fn get_next_timer_ticks() -> usize {
@@ -979,6 +1167,52 @@ mod tests {
         assert_eq!(SOFT_TIMER_WHEEL.next_timeout(), usize::MAX);
     }
 
+    #[cfg(soft_timer)]
+    #[test]
+    fn test_soft_timer_sleeping_callback() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let c = counter.clone();
+        let timer = Timer::new_soft_oneshot(
+            1,
+            Box::new(move || {
+                // Thread context, allocating and sleeping are fine.
+                let buf = alloc::vec![0u8; 64];
+                scheduler::suspend_me_for(3);
+                c.fetch_add(buf.len(), Ordering::Relaxed);
+            }),
+        );
+        timer.set_budget(Some(Duration::from_millis(1)));
+        // Started while the callback above sleeps, and must not cut it short.
+        let counter2 = Arc::new(AtomicUsize::new(0));
+        let other = Timer::new_soft_oneshot(2, create_test_callback(counter2.clone()));
+        timer.start();
+        scheduler::suspend_me_for(2);
+        other.start();
+        scheduler::suspend_me_for(10);
+        assert_eq!(counter.load(Ordering::Relaxed), 64);
+        assert_eq!(counter2.load(Ordering::Relaxed), 1);
+        let stats = timer.stats();
+        assert_eq!(stats.runs, 1);
+        assert_eq!(stats.overruns, 1);
+        assert!(stats.max_runtime > Duration::from_millis(1));
+        assert_eq!(other.stats().overruns, 0);
+    }
+
+    #[test]
+    fn test_timer_budget() {
+        let timer = Timer::new_hard_periodic(10, Box::new(|| {}));
+        timer.set_budget(Some(Duration::from_secs(1)));
+        timer.start();
+        timer.run();
+        timer.start();
+        timer.run();
+        let stats = timer.stats();
+        assert_eq!(stats.runs, 2);
+        assert_eq!(stats.overruns, 0);
+        assert!(stats.max_runtime < Duration::from_secs(1));
+        timer.stop();
+    }
+
     #[test]
     fn test_timer_interval_changes() {
         let counter = Arc::new(AtomicUsize::new(0));
@@ -1097,4 +1331,40 @@ mod tests {
 
         timer1.stop();
     }
+
+    #[test]
+    fn test_timer_wheel_levels() {
+        let mut wheel = Box::new(Wheel::const_new());
+        wheel.init();
+        let base = 1000;
+        wheel.clk = base;
+        let mut timers = Vec::new();
+        for delta in [1, 31, 32, 33, 1000, 40000, TIMER_WHEEL_SPAN + 5] {
+            let timer = Timer::new_hard_oneshot(delta, Box::new(|| {}));
+            timer.inner.irqsave_lock().timeout_ticks = base + delta;
+            wheel.place(timer.clone(), base + delta);
+            timers.push(timer);
+        }
+        // Already expired, runs on the next tick.
+        let late = Timer::new_hard_oneshot(0, Box::new(|| {}));
+        late.inner.irqsave_lock().timeout_ticks = base - 10;
+        wheel.place(late.clone(), base - 10);
+        assert_eq!(wheel.next_event(), base);
+
+        let mut expired = WheelTimerList::new();
+        expired.init();
+        wheel.advance(base, &mut expired);
+        assert!(Arc::is(&expired.pop_front().unwrap(), &late));
+        assert!(expired.is_empty());
+        for timer in timers {
+            let expires = timer.timeout_ticks();
+            wheel.advance(expires - 1, &mut expired);
+            assert!(expired.is_empty());
+            assert!(wheel.next_event() <= expires);
+            wheel.advance(expires, &mut expired);
+            assert!(Arc::is(&expired.pop_front().unwrap(), &timer));
+            assert!(expired.is_empty());
+        }
+        assert_eq!(wheel.next_event(), usize::MAX);
+    }
 }
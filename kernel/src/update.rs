@@ -0,0 +1,441 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A/B firmware updates.
+//!
+//! A storage device holds two image slots and a [`BootControl`] block. A
+//! new image is written to the slot which isn't active, verified against
+//! the SHA-256 digest in its [`ImageHeader`], then marked as pending. The
+//! header may carry a CRC-32 of itself, so that a torn header is rejected
+//! before the image is read. With `SECURE_BOOT`, the image may be followed
+//! by a [`SignatureTrailer`] signing the header, and so the image through
+//! its digest; the slot is refused unless [`secure_boot::verify`] accepts
+//! it.
+//!
+//! Picking the slot to boot is up to the boot loader which runs before the
+//! kernel, and isn't part of it: it calls [`BootControl::select`] on the
+//! control block and stores it back, so that a pending slot is only tried
+//! a limited number of times. Once the new image works it calls
+//! [`confirm`] to make it the active slot. If it never does, the boot
+//! loader falls back to the previous image.
+
+#[cfg(secure_boot)]
+use crate::secure_boot::{self, ArtifactKind, SignatureTrailer};
+use crate::{
+    crypto::{self, sha256, Digest, Sha256},
+    devices::{Device, DeviceManager},
+    error::{code, Error},
+    sync::{KOnce, RwSleepLock},
+};
+use alloc::{string::String, sync::Arc, vec};
+use blueos_infra::crc::crc32;
+use blueos_kconfig::FIRMWARE_UPDATE_TRIES;
+use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes, KnownLayout};
+
+pub const IMAGE_MAGIC: u32 = u32::from_le_bytes(*b"BOSI");
+pub const CONTROL_MAGIC: u32 = u32::from_le_bytes(*b"BOSC");
+/// Value of [`BootControl::pending`] when no update is pending.
+pub const NO_SLOT: u8 = 0xff;
+pub const NUM_SLOTS: usize = 2;
+
+// Size of the reads used to verify an image.
+const CHUNK_SIZE: usize = 512;
+
+/// Header at the start of each slot, followed by the image itself.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct ImageHeader {
+    pub magic: u32,
+    pub version: u32,
+    /// Size of the image following the header, in bytes.
+    pub image_size: u32,
+    /// CRC-32 of the header with this field zeroed, or 0 if the header
+    /// isn't checked.
+    pub header_crc: u32,
+    /// SHA-256 of the image.
+    pub digest: [u8; sha256::DIGEST_SIZE],
+}
+
+impl ImageHeader {
+    /// The CRC-32 expected in [`ImageHeader::header_crc`].
+    pub fn crc(&self) -> u32 {
+        let mut header = *self;
+        header.header_crc = 0;
+        crc32(header.as_bytes())
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == IMAGE_MAGIC && (self.header_crc == 0 || self.header_crc == self.crc())
+    }
+}
+
+/// Boot slot marker shared with the boot path.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct BootControl {
+    pub magic: u32,
+    /// Slot booted when no update is pending.
+    pub active: u8,
+    /// Slot holding a verified update which hasn't been confirmed yet.
+    pub pending: u8,
+    /// Boots of the pending slot left before falling back to the active one.
+    pub tries: u8,
+    pub reserved: u8,
+}
+
+impl BootControl {
+    pub const fn new() -> Self {
+        Self {
+            magic: CONTROL_MAGIC,
+            active: 0,
+            pending: NO_SLOT,
+            tries: 0,
+            reserved: 0,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == CONTROL_MAGIC
+            && (self.active as usize) < NUM_SLOTS
+            && (self.pending == NO_SLOT || (self.pending as usize) < NUM_SLOTS)
+    }
+
+    /// Picks the slot to boot, using up one try of the pending slot. The
+    /// boot path must store the block back before booting the slot.
+    pub fn select(&mut self) -> usize {
+        if self.pending != NO_SLOT && self.tries > 0 {
+            self.tries -= 1;
+            return self.pending as usize;
+        }
+        // The update never confirmed itself.
+        self.pending = NO_SLOT;
+        self.tries = 0;
+        self.active as usize
+    }
+}
+
+impl Default for BootControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the slots and the control block are, in bytes from the start of
+/// a block device.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub device: String,
+    pub slots: [u64; NUM_SLOTS],
+    pub slot_size: u64,
+    pub control: u64,
+}
+
+struct Storage {
+    dev: Arc<dyn Device>,
+    layout: Layout,
+    // Serializes updates of the control block, across their I/O.
+    lock: RwSleepLock<()>,
+}
+
+static STORAGE: KOnce<Storage> = KOnce::new();
+
+impl Storage {
+    fn read_exact(&self, pos: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let n = self.dev.read(pos, buf, false)?;
+        if n != buf.len() {
+            return Err(code::EIO);
+        }
+        Ok(())
+    }
+
+    fn write_all(&self, pos: u64, buf: &[u8]) -> Result<(), Error> {
+        let n = self.dev.write(pos, buf, false)?;
+        if n != buf.len() {
+            return Err(code::EIO);
+        }
+        Ok(())
+    }
+
+    fn load_control(&self) -> Result<BootControl, Error> {
+        let mut ctrl = BootControl::new();
+        self.read_exact(self.layout.control, ctrl.as_mut_bytes())?;
+        Ok(ctrl)
+    }
+
+    fn store_control(&self, ctrl: &BootControl) -> Result<(), Error> {
+        self.write_all(self.layout.control, ctrl.as_bytes())?;
+        let _ = self.dev.sync();
+        Ok(())
+    }
+
+    fn update_control(&self, f: impl FnOnce(&mut BootControl)) -> Result<BootControl, Error> {
+        let _guard = self.lock.write();
+        let mut ctrl = self.load_control()?;
+        f(&mut ctrl);
+        self.store_control(&ctrl)?;
+        Ok(ctrl)
+    }
+}
+
+fn storage() -> Result<&'static Storage, Error> {
+    STORAGE.get().ok_or(code::ENODEV)
+}
+
+/// Sets the layout used for updates, usually from the board. The control
+/// block is initialized if it has never been written. Fails with EBUSY if
+/// the layout has already been set.
+pub fn init(layout: Layout) -> Result<(), Error> {
+    if layout.slot_size < core::mem::size_of::<ImageHeader>() as u64 {
+        return Err(code::EINVAL);
+    }
+    let dev = DeviceManager::get()
+        .get_block_device(&layout.device)
+        .ok_or(code::ENODEV)?;
+    let mut first = false;
+    let storage = STORAGE.call_once(|| {
+        first = true;
+        Storage {
+            dev,
+            layout,
+            lock: RwSleepLock::new(()),
+        }
+    });
+    if !first {
+        return Err(code::EBUSY);
+    }
+    let ctrl = storage.load_control()?;
+    if !ctrl.is_valid() {
+        log::warn!("No valid boot control block, booting from slot 0");
+        storage.store_control(&BootControl::new())?;
+    }
+    Ok(())
+}
+
+/// Returns the current boot control block.
+pub fn status() -> Result<BootControl, Error> {
+    storage()?.load_control()
+}
+
+/// Reads the header of `slot` and checks the image against its digest.
+pub fn verify_slot(slot: usize) -> Result<ImageHeader, Error> {
+    let storage = storage()?;
+    let base = *storage.layout.slots.get(slot).ok_or(code::EINVAL)?;
+    let mut header = ImageHeader::new_zeroed();
+    storage.read_exact(base, header.as_mut_bytes())?;
+    let header_size = core::mem::size_of::<ImageHeader>() as u64;
+    if !header.is_valid() || header.image_size as u64 > storage.layout.slot_size - header_size {
+        return Err(code::EINVAL);
+    }
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut pos = base + header_size;
+    let mut left = header.image_size as usize;
+    while left > 0 {
+        let n = left.min(CHUNK_SIZE);
+        storage.read_exact(pos, &mut buf[..n])?;
+        hasher.update(&buf[..n]);
+        pos += n as u64;
+        left -= n;
+    }
+    if !crypto::digest_eq(&hasher.finish(), &header.digest) {
+        return Err(code::EILSEQ);
+    }
+    #[cfg(secure_boot)]
+    {
+        let mut trailer = SignatureTrailer::new_zeroed();
+        let trailer_size = core::mem::size_of::<SignatureTrailer>() as u64;
+        let end = header_size + header.image_size as u64;
+        let trailer = if end + trailer_size <= storage.layout.slot_size {
+            storage.read_exact(base + end, trailer.as_mut_bytes())?;
+            trailer.as_bytes()
+        } else {
+            &[]
+        };
+        verify_signature(slot, &header, trailer)?;
+    }
+    Ok(header)
+}
+
+// Measures the header and checks the signature trailer which follows the
+// image. `trailer` is left empty if there's no room for one.
+#[cfg(secure_boot)]
+fn verify_signature(slot: usize, header: &ImageHeader, trailer: &[u8]) -> Result<(), Error> {
+    let mut artifact = vec![];
+    artifact.extend_from_slice(header.as_bytes());
+    artifact.extend_from_slice(trailer);
+    let name = alloc::format!("slot {}", slot);
+    secure_boot::verify(ArtifactKind::Firmware, &name, &artifact)?;
+    Ok(())
+}
+
+/// Writes an image, header included, to the slot which isn't active.
+#[derive(Debug)]
+pub struct Updater {
+    slot: usize,
+    written: u64,
+}
+
+/// Starts an update. Any update which is still pending is cancelled, since
+/// its slot is about to be overwritten.
+pub fn begin() -> Result<Updater, Error> {
+    let ctrl = storage()?.update_control(|ctrl| {
+        ctrl.pending = NO_SLOT;
+        ctrl.tries = 0;
+    })?;
+    Ok(Updater {
+        slot: (ctrl.active as usize + 1) % NUM_SLOTS,
+        written: 0,
+    })
+}
+
+impl Updater {
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Appends `data` to the image.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let storage = storage()?;
+        if self.written + data.len() as u64 > storage.layout.slot_size {
+            return Err(code::ENOSPC);
+        }
+        storage.write_all(storage.layout.slots[self.slot] + self.written, data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Verifies the image and marks it as pending, so that it's tried on
+    /// the next boot.
+    pub fn finish(self) -> Result<ImageHeader, Error> {
+        let storage = storage()?;
+        let _ = storage.dev.sync();
+        let header = verify_slot(self.slot)?;
+        storage.update_control(|ctrl| {
+            ctrl.pending = self.slot as u8;
+            ctrl.tries = FIRMWARE_UPDATE_TRIES as u8;
+        })?;
+        log::info!(
+            "Firmware version {} written to slot {}",
+            header.version,
+            self.slot
+        );
+        Ok(header)
+    }
+}
+
+/// Makes the pending slot, which we're assumed to run from, the active one.
+/// Does nothing if no update is pending.
+pub fn confirm() -> Result<(), Error> {
+    storage()?.update_control(|ctrl| {
+        if ctrl.pending != NO_SLOT {
+            ctrl.active = ctrl.pending;
+            ctrl.pending = NO_SLOT;
+            ctrl.tries = 0;
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_boot_control_select() {
+        let mut ctrl = BootControl::new();
+        assert!(ctrl.is_valid());
+        assert_eq!(ctrl.select(), 0);
+
+        ctrl.pending = 1;
+        ctrl.tries = 2;
+        assert_eq!(ctrl.select(), 1);
+        assert_eq!(ctrl.select(), 1);
+        // Never confirmed, so we go back to the old image.
+        assert_eq!(ctrl.select(), 0);
+        assert_eq!(ctrl.pending, NO_SLOT);
+
+        ctrl.active = 2;
+        assert!(!ctrl.is_valid());
+    }
+
+    #[test]
+    fn test_image_header_crc() {
+        let mut header = ImageHeader {
+            magic: IMAGE_MAGIC,
+            version: 3,
+            image_size: 4096,
+            header_crc: 0,
+            digest: [0xa5; sha256::DIGEST_SIZE],
+        };
+        // Not checked.
+        assert!(header.is_valid());
+        header.header_crc = header.crc();
+        assert!(header.is_valid());
+        header.image_size += 1;
+        assert!(!header.is_valid());
+    }
+
+    #[test]
+    fn test_update_init_once() {
+        use crate::devices::ramdisk::RamDisk;
+
+        let disk = Arc::new(RamDisk::new(vec![0; 4096]));
+        DeviceManager::get()
+            .register_device(String::from("update0"), disk)
+            .unwrap();
+        let layout = Layout {
+            device: String::from("update0"),
+            slots: [0, 1024],
+            slot_size: 1024,
+            control: 2048,
+        };
+        init(layout.clone()).unwrap();
+        // The blank control block was initialized.
+        assert_eq!(status().unwrap(), BootControl::new());
+        assert_eq!(init(layout), Err(code::EBUSY));
+    }
+
+    #[cfg(secure_boot)]
+    #[test]
+    fn test_reject_bad_signature() {
+        use crate::crypto::ed25519::rfc8032::{PUBLIC_KEY, SIGNATURE};
+
+        // The key may already be there, but the keyring mustn't be sealed
+        // before the tests run.
+        assert_ne!(secure_boot::add_key(&PUBLIC_KEY), Err(code::EPERM));
+        let header = ImageHeader {
+            magic: IMAGE_MAGIC,
+            version: 1,
+            image_size: 0,
+            header_crc: 0,
+            digest: Sha256::digest(&[]),
+        };
+        // Signs 0x72 rather than the header.
+        let trailer = SignatureTrailer {
+            magic: secure_boot::TRAILER_MAGIC,
+            key_id: secure_boot::key_id(&PUBLIC_KEY),
+            signature: SIGNATURE,
+        };
+        assert_eq!(
+            verify_signature(0, &header, trailer.as_bytes()),
+            Err(code::EILSEQ)
+        );
+        // Unsigned images are only refused in lockdown.
+        assert_eq!(
+            verify_signature(0, &header, &[]).is_err(),
+            secure_boot::is_lockdown()
+        );
+    }
+}
@@ -13,13 +13,15 @@
 // limitations under the License.
 
 use crate::{
-    devices::Device,
+    devices::{Device, DeviceId},
     error::{code, Error},
+    sync::RwSleepLock,
     vfs::{
         fs::{FileSystem, FileSystemInfo},
         inode::InodeOps,
         inode_mode::{InodeFileType, InodeMode},
         mount::get_mount_manager,
+        orphan,
         utils::NAME_MAX,
     },
 };
@@ -28,6 +30,7 @@ use alloc::{
     format,
     string::String,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 use core::{
     fmt::{self, Debug},
@@ -37,17 +40,107 @@ use core::{
 };
 use delegate::delegate;
 use log::{debug, error, trace};
-use spin::RwLock;
+
+// Fewest buckets of a directory with children.
+const MIN_BUCKETS: usize = 8;
+
+// FNV-1a, short names hash in a few cycles.
+fn hash_name(name: &str) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash as usize
+}
+
+/// The cached children of a directory, hashed by name, so that looking up
+/// a path takes the same time whatever the size of the directories it goes
+/// through.
+///
+/// The buckets double once they hold an entry each on average. Directories
+/// without cached children, and files, don't allocate any.
+struct ChildIndex {
+    buckets: Vec<Vec<(String, Arc<Dcache>)>>,
+    len: usize,
+}
+
+impl ChildIndex {
+    const fn new() -> Self {
+        Self {
+            buckets: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn bucket(&self, name: &str) -> Option<&Vec<(String, Arc<Dcache>)>> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        Some(&self.buckets[hash_name(name) & (self.buckets.len() - 1)])
+    }
+
+    fn get(&self, name: &str) -> Option<&Arc<Dcache>> {
+        self.bucket(name)?
+            .iter()
+            .find(|(child_name, _)| child_name == name)
+            .map(|(_, child)| child)
+    }
+
+    fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn insert(&mut self, name: String, child: Arc<Dcache>) -> Option<Arc<Dcache>> {
+        if let Some(old) = self.remove(&name) {
+            self.push(name, child);
+            return Some(old);
+        }
+        if self.len >= self.buckets.len() {
+            self.grow();
+        }
+        self.push(name, child);
+        None
+    }
+
+    fn remove(&mut self, name: &str) -> Option<Arc<Dcache>> {
+        let index = hash_name(name) & self.buckets.len().checked_sub(1)?;
+        let bucket = &mut self.buckets[index];
+        let position = bucket
+            .iter()
+            .position(|(child_name, _)| child_name == name)?;
+        self.len -= 1;
+        Some(bucket.swap_remove(position).1)
+    }
+
+    // There must be buckets.
+    fn push(&mut self, name: String, child: Arc<Dcache>) {
+        let index = hash_name(&name) & (self.buckets.len() - 1);
+        self.buckets[index].push((name, child));
+        self.len += 1;
+    }
+
+    fn grow(&mut self) {
+        let len = (self.buckets.len() * 2).max(MIN_BUCKETS);
+        let mut buckets = Vec::with_capacity(len);
+        buckets.resize_with(len, Vec::new);
+        let old = core::mem::replace(&mut self.buckets, buckets);
+        self.len = 0;
+        for (name, child) in old.into_iter().flatten() {
+            self.push(name, child);
+        }
+    }
+}
 
 /// File system lookup cache
 pub struct Dcache {
     // inode will never change after creation
     inode: Arc<dyn InodeOps>,
     // name and parent may change by rename, None means root directory
-    name_and_parent: RwLock<Option<(String, Weak<Dcache>)>>,
-    children: RwLock<BTreeMap<String, Arc<Dcache>>>,
+    name_and_parent: RwSleepLock<Option<(String, Weak<Dcache>)>>,
+    children: RwSleepLock<ChildIndex>,
     // When a child path becomes a mount point of other fs, it will be cached here
-    overrided_children: RwLock<Option<BTreeMap<String, Arc<Dcache>>>>,
+    overrided_children: RwSleepLock<Option<BTreeMap<String, Arc<Dcache>>>>,
     // use to set parent in children
     this: Weak<Dcache>,
     is_mount_point: AtomicBool,
@@ -58,22 +151,22 @@ impl Dcache {
     pub fn new(inode: Arc<dyn InodeOps>, name: String, parent: Weak<Dcache>) -> Arc<Self> {
         Arc::new_cyclic(|weak_self| Self {
             inode,
-            name_and_parent: RwLock::new(Some((name, parent))),
-            children: RwLock::new(BTreeMap::new()),
+            name_and_parent: RwSleepLock::new(Some((name, parent))),
+            children: RwSleepLock::new(ChildIndex::new()),
             this: weak_self.clone(),
             is_mount_point: AtomicBool::new(false),
-            overrided_children: RwLock::new(None),
+            overrided_children: RwSleepLock::new(None),
         })
     }
 
     pub fn new_root(inode: Arc<dyn InodeOps>) -> Arc<Self> {
         Arc::new_cyclic(|weak_self| Self {
             inode,
-            name_and_parent: RwLock::new(None),
-            children: RwLock::new(BTreeMap::new()),
+            name_and_parent: RwSleepLock::new(None),
+            children: RwSleepLock::new(ChildIndex::new()),
             this: weak_self.clone(),
             is_mount_point: AtomicBool::new(false),
-            overrided_children: RwLock::new(None),
+            overrided_children: RwSleepLock::new(None),
         })
     }
 
@@ -97,8 +190,10 @@ impl Dcache {
         if children.contains_key(name) {
             return Err(code::EEXIST);
         }
-        let inode =
-            inode_creator().unwrap_or_else(|| self.inode.create(name, type_, mode).unwrap());
+        let inode = match inode_creator() {
+            Some(inode) => inode,
+            None => self.inode.create(name, type_, mode)?,
+        };
         let child = Self::new(inode, String::from(name), self.get_weak_ref());
         if child.is_dcacheable() {
             children.insert(String::from(name), child.clone());
@@ -129,6 +224,52 @@ impl Dcache {
         Ok(child)
     }
 
+    /// Creates the special file `name` in this directory, for the device
+    /// numbered `rdev`.
+    pub fn mknod(
+        &self,
+        name: &str,
+        type_: InodeFileType,
+        mode: InodeMode,
+        rdev: DeviceId,
+    ) -> Result<Arc<Self>, Error> {
+        if self.inode.type_() != InodeFileType::Directory {
+            return Err(code::ENOTDIR);
+        }
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            return Err(code::EEXIST);
+        }
+
+        let inode = self.inode.mknod(name, type_, mode, rdev)?;
+        let name_str = String::from(name);
+        let child = Self::new(inode, name_str.clone(), self.get_weak_ref());
+        if child.is_dcacheable() {
+            children.insert(name_str, child.clone());
+        }
+        Ok(child)
+    }
+
+    /// Creates the symbolic link `name` in this directory, pointing to
+    /// `target`.
+    pub fn symlink(&self, name: &str, target: &str) -> Result<Arc<Self>, Error> {
+        if self.inode.type_() != InodeFileType::Directory {
+            return Err(code::ENOTDIR);
+        }
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            return Err(code::EEXIST);
+        }
+
+        let inode = self.inode.symlink(name, target)?;
+        let name_str = String::from(name);
+        let child = Self::new(inode, name_str.clone(), self.get_weak_ref());
+        if child.is_dcacheable() {
+            children.insert(name_str, child.clone());
+        }
+        Ok(child)
+    }
+
     pub fn lookup(&self, name: &str) -> Result<Arc<Dcache>, Error> {
         if name.len() > NAME_MAX {
             return Err(code::ENAMETOOLONG);
@@ -245,11 +386,13 @@ impl Dcache {
             return Err(code::EBUSY);
         }
 
+        #[cfg(fsck_on_mount)]
+        crate::vfs::fsck::check_on_mount(fs.as_ref());
         fs.mount(self.this.upgrade().unwrap())?;
         let name_and_parent = self.name_and_parent.read();
         if let Some((name, parent)) = name_and_parent.as_ref() {
             if let Some(parent) = parent.upgrade() {
-                parent.add_mount_point(self.name(), self.this.upgrade().unwrap())?;
+                parent.add_mount_point(name.clone(), self.this.upgrade().unwrap())?;
             }
         } else {
             error!("The root directory is not allowed to mount");
@@ -273,7 +416,7 @@ impl Dcache {
         let name_and_parent = self.name_and_parent.read();
         if let Some((name, parent)) = name_and_parent.as_ref() {
             if let Some(parent) = parent.upgrade() {
-                parent.remove_mount_point(self.name())?;
+                parent.remove_mount_point(name.clone())?;
             }
         } else {
             error!("The root directory is not allowed to unmount");
@@ -317,9 +460,10 @@ impl Dcache {
             return Err(code::EBUSY);
         }
 
+        let inode = child.inode().clone();
         self.inode.unlink(name)?;
         children.remove(name);
-        Ok(())
+        orphan::unlinked(&inode)
     }
 
     pub fn rmdir(&self, name: &str) -> Result<(), Error> {
@@ -338,6 +482,10 @@ impl Dcache {
         Ok(())
     }
 
+    /// Moves the entry `old_name` to `new_name` in `new_dir`, which must be
+    /// on the same file system. An entry already at `new_name` is replaced:
+    /// a directory can only replace an empty directory, and anything else
+    /// only something which isn't a directory.
     pub fn rename(
         &self,
         old_name: &str,
@@ -354,48 +502,75 @@ impl Dcache {
             error!("Invalid name: {} to {}", old_name, new_name);
             return Err(code::EINVAL);
         }
+        if new_name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
 
-        let mut children = self.children.write();
-        let child = match children.get(old_name) {
-            Some(child) => child.clone(),
-            None => {
-                debug!("{} not found", old_name);
-                return Err(code::ENOENT);
-            }
-        };
+        let child = self.lookup(old_name)?;
         if child.is_mount_point() {
             debug!("{} is a mount point", old_name);
             return Err(code::EBUSY);
         }
+        let same_dir = ptr::addr_eq(self, Arc::as_ptr(new_dir));
+        if same_dir && old_name == new_name {
+            return Ok(());
+        }
+        if !same_dir && !self.is_same_fs(new_dir) {
+            debug!("Cannot rename {} across file systems", old_name);
+            return Err(code::EXDEV);
+        }
+        let is_dir = child.type_() == InodeFileType::Directory;
+        if is_dir && new_dir.is_descendant_of(&child) {
+            debug!("Cannot move {} below itself", old_name);
+            return Err(code::EINVAL);
+        }
 
-        // rename in the same directory
-        if ptr::addr_eq(self, Arc::as_ptr(new_dir)) && old_name != new_name {
-            if children.contains_key(new_name) {
-                debug!("{} already exists", new_name);
-                return Err(code::EEXIST);
-            }
-            self.inode.rename(old_name, &self.inode, new_name)?;
-            children.remove(old_name);
-            if child.is_dcacheable() {
-                children.insert(String::from(new_name), child);
-            }
-        } else {
-            let mut new_children = new_dir.children.write();
-            if new_children.contains_key(new_name) {
-                debug!("{} already exists", new_name);
-                return Err(code::EEXIST);
-            }
-            self.inode.rename(old_name, &new_dir.inode, new_name)?;
-            children.remove(old_name);
-            child.set_name_and_parent(new_name, new_dir.this.clone());
-            if child.is_dcacheable() {
-                new_children.insert(String::from(new_name), child);
-            }
+        // The file system replaces the target as part of the rename.
+        let replaced = match new_dir.lookup(new_name) {
+            // Both names are links to the same inode.
+            Ok(target) if Arc::ptr_eq(target.inode(), child.inode()) => return Ok(()),
+            Ok(target) if target.is_mount_point() => return Err(code::EBUSY),
+            Ok(target) => Some(target.inode().clone()),
+            Err(e) if e == code::ENOENT => None,
+            Err(e) => return Err(e),
+        };
+
+        self.inode.rename(old_name, &new_dir.inode, new_name)?;
+        if let Some(replaced) = replaced {
+            new_dir.children.write().remove(new_name);
+            orphan::unlinked(&replaced)?;
+        }
+        self.children.write().remove(old_name);
+        child.set_name_and_parent(new_name, new_dir.this.clone());
+        if child.is_dcacheable() {
+            new_dir
+                .children
+                .write()
+                .insert(String::from(new_name), child);
         }
 
         Ok(())
     }
 
+    fn is_same_fs(&self, other: &Dcache) -> bool {
+        match (self.inode.fs(), other.inode.fs()) {
+            (Some(fs), Some(other_fs)) => Arc::ptr_eq(&fs, &other_fs),
+            _ => false,
+        }
+    }
+
+    // Whether this is `ancestor` or below it.
+    fn is_descendant_of(&self, ancestor: &Arc<Dcache>) -> bool {
+        let mut dir = self.this.upgrade();
+        while let Some(current) = dir {
+            if Arc::ptr_eq(&current, ancestor) {
+                return true;
+            }
+            dir = current.parent();
+        }
+        false
+    }
+
     fn add_mount_point(&self, name: String, mount_point: Arc<Dcache>) -> Result<(), Error> {
         trace!("Add mount point: {} , {:?}", name, mount_point);
         let mut overrided_children = self.overrided_children.write();
@@ -414,6 +589,8 @@ impl Dcache {
     }
 
     fn remove_mount_point(&self, name: String) -> Result<(), Error> {
+        // Same order as add_mount_point.
+        let mut overrided_children = self.overrided_children.write();
         let mut children = self.children.write();
         trace!(
             "Remove mount point: {} , {:?}",
@@ -421,7 +598,6 @@ impl Dcache {
             children.remove(&name).unwrap()
         );
 
-        let mut overrided_children = self.overrided_children.write();
         let overrided_point = overrided_children.as_mut().unwrap().remove(&name);
         if let Some(overrided_point) = overrided_point {
             trace!(
@@ -478,3 +654,62 @@ impl Drop for Dcache {
         trace!("Drop {:?}", self);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{time, vfs::tmpfs::TmpFileSystem};
+    use blueos_test_macro::test;
+
+    const LOOKUPS: usize = 4096;
+    const ROUNDS: usize = 4;
+    // A lookup scanning the children would be 128 times slower in the
+    // largest directory than in the smallest.
+    const MAX_SLOWDOWN: u64 = 4;
+
+    // Cycles taken by `LOOKUPS` lookups in a directory of `entries`, at
+    // best over a few rounds, so that preemption doesn't count.
+    fn lookup_cycles(entries: usize) -> u64 {
+        let fs = TmpFileSystem::new();
+        let dir = Dcache::new_root(fs.root_inode());
+        let names: Vec<String> = (0..entries).map(|i| format!("entry{}", i)).collect();
+        for name in names.iter() {
+            dir.new_child(name, InodeFileType::Regular, InodeMode::from(0o644), || {
+                None
+            })
+            .unwrap();
+        }
+        assert!(dir.children.read().buckets.len() >= entries);
+
+        let mut best = u64::MAX;
+        for _ in 0..ROUNDS {
+            let start = time::get_sys_cycles();
+            for name in names.iter().cycle().take(LOOKUPS) {
+                assert!(dir.lookup(name).is_ok());
+            }
+            best = best.min(time::get_sys_cycles() - start);
+        }
+
+        for name in names.iter() {
+            dir.unlink(name).unwrap();
+        }
+        assert!(dir.find_child(&names[0]).is_none());
+        assert_eq!(dir.children.read().len, 0);
+        best
+    }
+
+    #[test]
+    fn test_dcache_lookup_scaling() {
+        let small = lookup_cycles(16);
+        for entries in [256, 2048] {
+            let cycles = lookup_cycles(entries);
+            assert!(
+                cycles <= small.max(1) * MAX_SLOWDOWN,
+                "{} cycles for {} entries, {} for 16",
+                cycles,
+                entries,
+                small
+            );
+        }
+    }
+}
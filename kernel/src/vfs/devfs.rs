@@ -12,44 +12,143 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! /dev, a tmpfs holding a node for each device registered with the
+//! [`DeviceManager`]. Nodes are added and removed as devices are registered
+//! and unregistered, so nothing has to create them by hand.
+
 use crate::{
     devices::{Device, DeviceManager},
     error::{code, Error},
-    vfs::{inode_mode::InodeMode, path},
+    sync::KOnce,
+    vfs::{dcache::Dcache, inode_mode::InodeMode, path},
 };
 use alloc::sync::Arc;
-use log::debug;
+use log::{debug, warn};
+
+static DEV_DIR: KOnce<Arc<Dcache>> = KOnce::new();
 
 pub fn init() -> Result<(), Error> {
-    DeviceManager::get().foreach(add_device)
+    let dev_dir = path::lookup_path("/dev").ok_or(code::ENOENT)?;
+    DEV_DIR.call_once(|| dev_dir);
+    // Devices registered from now on are added by device_registered, which
+    // may get to some of these first.
+    DeviceManager::get().foreach(|path, dev| match add_device(path, dev) {
+        Err(err) if err == code::EEXIST => Ok(()),
+        res => res,
+    })
 }
 
-fn add_device(path: &str, dev: Arc<dyn Device>) -> Result<(), Error> {
-    let mut dev_dir = path::lookup_path("/dev").ok_or(code::ENOENT)?;
+/// Adds the node of a device registered as `path`, once /dev is mounted.
+pub(crate) fn device_registered(path: &str, dev: Arc<dyn Device>) {
+    if DEV_DIR.get().is_none() {
+        return;
+    }
+    match add_device(path, dev) {
+        Ok(()) => {}
+        Err(err) if err == code::EEXIST => {}
+        Err(err) => warn!("[devfs] Can't add {}: {}", path, err),
+    }
+}
+
+/// Removes the node of the device which was registered as `path`. Files
+/// open on it keep working.
+pub(crate) fn device_unregistered(path: &str) {
+    if DEV_DIR.get().is_none() {
+        return;
+    }
+    if let Err(err) = remove_device(path) {
+        warn!("[devfs] Can't remove {}: {}", path, err);
+    }
+}
 
+// Returns the directory holding the node of `path`, and the node's name.
+fn parent_of(path: &str) -> Result<(Arc<Dcache>, &str), Error> {
+    let mut dir = DEV_DIR.get().ok_or(code::ENOENT)?.clone();
     let mut rel_path = path.trim_start_matches('/');
-    while !rel_path.is_empty() {
-        match rel_path.split_once('/') {
-            Some((next_name, next_path)) => {
-                rel_path = next_path.trim_start_matches('/');
-                dev_dir = dev_dir.lookup(next_name)?;
-            }
-            None => match dev_dir.lookup(rel_path) {
-                Ok(_) => {
-                    return Err(code::EEXIST);
-                }
-                Err(_) => {
-                    dev_dir.create_device(
-                        rel_path,
-                        InodeMode::from_bits_truncate(0o666),
-                        dev.clone(),
-                    )?;
-                    break;
-                }
-            },
-        }
+    while let Some((next_name, next_path)) = rel_path.split_once('/') {
+        rel_path = next_path.trim_start_matches('/');
+        dir = dir.lookup(next_name)?;
+    }
+    if rel_path.is_empty() {
+        return Err(code::EINVAL);
     }
+    Ok((dir, rel_path))
+}
 
+fn add_device(path: &str, dev: Arc<dyn Device>) -> Result<(), Error> {
+    let (dir, name) = parent_of(path)?;
+    dir.create_device(name, InodeMode::from_bits_truncate(0o666), dev)?;
     debug!("[devfs] Added device: {}", path);
     Ok(())
 }
+
+fn remove_device(path: &str) -> Result<(), Error> {
+    let (dir, name) = parent_of(path)?;
+    dir.unlink(name)?;
+    debug!("[devfs] Removed device: {}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{devno, DeviceClass, DeviceId};
+    use alloc::string::String;
+    use blueos_test_macro::test;
+
+    struct Hotplug(DeviceId);
+
+    impl Device for Hotplug {
+        fn name(&self) -> String {
+            String::from("hotplug")
+        }
+
+        fn class(&self) -> DeviceClass {
+            DeviceClass::Char
+        }
+
+        fn id(&self) -> DeviceId {
+            self.0
+        }
+
+        fn read(&self, _pos: u64, buf: &mut [u8], _is_nonblocking: bool) -> Result<usize, Error> {
+            buf.fill(0x5a);
+            Ok(buf.len())
+        }
+
+        fn write(&self, _pos: u64, buf: &[u8], _is_nonblocking: bool) -> Result<usize, Error> {
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_hotplug() {
+        let manager = DeviceManager::get();
+        let major = devno::register_major(DeviceClass::Char, 0, "hotplug").unwrap();
+        let id = devno::alloc_minor(DeviceClass::Char, major).unwrap();
+        assert!(path::lookup_path("/dev/hotplug").is_none());
+
+        manager
+            .register_device(String::from("hotplug"), Arc::new(Hotplug(id)))
+            .unwrap();
+        let node = path::lookup_path("/dev/hotplug").unwrap();
+        assert_eq!(node.inode().file_attr().rdev, id.raw());
+        let mut buf = [0u8; 4];
+        assert_eq!(node.inode().read_at(0, &mut buf, false), Ok(4));
+        assert_eq!(buf, [0x5a; 4]);
+
+        let dev = manager.unregister_device("hotplug").unwrap();
+        assert_eq!(dev.id(), id);
+        assert!(path::lookup_path("/dev/hotplug").is_none());
+        assert!(manager.get_char_device("hotplug").is_none());
+        assert_eq!(
+            manager.unregister_device("hotplug").err(),
+            Some(code::ENOENT)
+        );
+        // The number can be claimed again.
+        manager
+            .register_device(String::from("hotplug"), Arc::new(Hotplug(id)))
+            .unwrap();
+        assert!(manager.unregister_device("hotplug").is_ok());
+    }
+}
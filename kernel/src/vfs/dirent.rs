@@ -130,21 +130,109 @@ impl Dirent {
     }
 }
 
+/// The `linux_dirent64` layout used by getdents64, which has a 64-bit
+/// inode number whatever the word size.
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct Dirent64 {
+    d_ino: u64,
+    d_off: i64,
+    /// The length of the dirent
+    d_reclen: u16,
+    /// The type of the file
+    d_type: u8,
+    // The file name - flexible array member
+    d_name: [u8; 0],
+}
+
+crate::static_assert!(offset_of!(Dirent64, d_name) == 19);
+
+impl Dirent64 {
+    pub const NAME_OFFSET: usize = offset_of!(Self, d_name);
+
+    /// Create a new Dirent64 instance
+    pub const fn new(ino: u64, off: i64, type_: DirentType, reclen: u16) -> Self {
+        Self {
+            d_ino: ino,
+            d_off: off,
+            d_reclen: reclen,
+            d_type: type_ as u8,
+            d_name: [],
+        }
+    }
+
+    /// Get the inode number
+    pub fn ino(&self) -> u64 {
+        self.d_ino
+    }
+
+    /// Get the offset of the next entry
+    pub fn off(&self) -> i64 {
+        self.d_off
+    }
+
+    /// Get the file type
+    pub fn type_(&self) -> DirentType {
+        unsafe { transmute(self.d_type) }
+    }
+
+    /// Get the length of the dirent
+    pub fn reclen(&self) -> u16 {
+        self.d_reclen
+    }
+
+    /// Get the file name as a CStr
+    pub fn name(&self) -> Result<&CStr, FromBytesUntilNulError> {
+        let name_slice = unsafe {
+            core::slice::from_raw_parts(
+                (self as *const Self as *const u8).add(Self::NAME_OFFSET),
+                256,
+            )
+        };
+        CStr::from_bytes_until_nul(name_slice)
+    }
+
+    /// Get a reference to Dirent64 from a raw buffer
+    pub unsafe fn from_buf_ref(buf: &[u8]) -> &Self {
+        let ptr = buf.as_ptr() as *const Self;
+        &*ptr
+    }
+}
+
+/// Record layout written by a [`DirBufferReader`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirentFormat {
+    /// [`Dirent`], the libc `struct dirent`.
+    Native,
+    /// [`Dirent64`].
+    Dirent64,
+}
+
 pub struct DirBufferReader<'a> {
     buf: &'a mut [u8],
     read_pos: usize,
+    format: DirentFormat,
     _marker: PhantomData<&'a mut [u8]>,
 }
 
 impl<'a> DirBufferReader<'a> {
     pub fn new(buf: &'a mut [u8]) -> Self {
+        Self::with_format(buf, DirentFormat::Native)
+    }
+
+    pub fn with_format(buf: &'a mut [u8], format: DirentFormat) -> Self {
         Self {
             buf,
             read_pos: 0,
+            format,
             _marker: PhantomData,
         }
     }
 
+    /// Appends the entry at position `off` of its directory. The `d_off` of
+    /// the record is the position of the next entry, so that seeking the
+    /// directory to it continues the listing after this entry. Returns
+    /// `EINVAL` if the buffer is full.
     pub fn write_node(
         &mut self,
         ino: usize,
@@ -152,23 +240,30 @@ impl<'a> DirBufferReader<'a> {
         type_: InodeFileType,
         name: &str,
     ) -> Result<(), Error> {
+        let (name_offset, align) = match self.format {
+            DirentFormat::Native => (Dirent::NAME_OFFSET, align_of::<Dirent>()),
+            DirentFormat::Dirent64 => (Dirent64::NAME_OFFSET, align_of::<Dirent64>()),
+        };
         let name_len = name.len().min(255);
-        let dirent_size = align_up_size(Dirent::NAME_OFFSET + name_len + 1, align_of::<Dirent>());
+        let dirent_size = align_up_size(name_offset + name_len + 1, align);
         if self.read_pos + dirent_size > self.buf.len() {
-            return Err(code::ENOMEM);
+            return Err(code::EINVAL);
         }
-        // write dirent
-        let dir = Dirent::new(ino, off, DirentType::from(type_), dirent_size as u16);
-        let dir_bytes: &[u8] = unsafe {
-            core::slice::from_raw_parts(&dir as *const Dirent as *const u8, Dirent::NAME_OFFSET)
-        };
-        self.buf[self.read_pos..self.read_pos + Dirent::NAME_OFFSET].copy_from_slice(dir_bytes);
-
-        let name_bytes = name.as_bytes();
-        self.buf
-            [self.read_pos + Dirent::NAME_OFFSET..self.read_pos + Dirent::NAME_OFFSET + name_len]
-            .copy_from_slice(&name_bytes[..name_len]);
-        self.buf[self.read_pos + Dirent::NAME_OFFSET + name_len] = 0;
+        let record = &mut self.buf[self.read_pos..self.read_pos + dirent_size];
+        let type_ = DirentType::from(type_);
+        match self.format {
+            DirentFormat::Native => {
+                let dir = Dirent::new(ino, off + 1, type_, dirent_size as u16);
+                record[..name_offset].copy_from_slice(header_bytes(&dir, name_offset));
+            }
+            DirentFormat::Dirent64 => {
+                let dir = Dirent64::new(ino as u64, off + 1, type_, dirent_size as u16);
+                record[..name_offset].copy_from_slice(header_bytes(&dir, name_offset));
+            }
+        }
+        record[name_offset..name_offset + name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+        // The terminating nul and the padding.
+        record[name_offset + name_len..].fill(0);
         self.read_pos += dirent_size;
 
         Ok(())
@@ -179,6 +274,11 @@ impl<'a> DirBufferReader<'a> {
     }
 }
 
+fn header_bytes<T>(header: &T, len: usize) -> &[u8] {
+    crate::kassert!(len <= size_of::<T>());
+    unsafe { core::slice::from_raw_parts(header as *const T as *const u8, len) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,7 +293,7 @@ mod tests {
             .is_ok());
         let dirent = unsafe { Dirent::from_buf_ref(&buf) };
         assert_eq!(dirent.ino(), 1);
-        assert_eq!(dirent.off(), 0);
+        assert_eq!(dirent.off(), 1);
         assert_eq!(dirent.type_(), DirentType::Reg);
         assert_eq!(
             dirent.reclen(),
@@ -236,4 +336,29 @@ mod tests {
             .write_node(1, 2, InodeFileType::Regular, "test.txt")
             .is_err());
     }
+
+    #[test]
+    fn test_dirent64() {
+        let mut buf = [0xffu8; 256];
+        let mut reader = DirBufferReader::with_format(&mut buf, DirentFormat::Dirent64);
+        assert!(reader
+            .write_node(1, 0, InodeFileType::Regular, "test.txt")
+            .is_ok());
+        assert!(reader
+            .write_node(2, 1, InodeFileType::Directory, "dir")
+            .is_ok());
+        let len = reader.recv_len();
+
+        let first = unsafe { Dirent64::from_buf_ref(&buf) };
+        // 19 bytes of header, 9 of name, padded to 32.
+        assert_eq!(first.reclen(), 32);
+        assert_eq!(first.ino(), 1);
+        assert_eq!(first.off(), 1);
+        assert_eq!(first.name().unwrap().to_string_lossy(), "test.txt");
+        let second = unsafe { Dirent64::from_buf_ref(&buf[first.reclen() as usize..]) };
+        assert_eq!(second.type_(), DirentType::Dir);
+        assert_eq!(second.off(), 2);
+        assert_eq!(second.name().unwrap().to_string_lossy(), "dir");
+        assert_eq!(len, first.reclen() as usize + second.reclen() as usize);
+    }
 }
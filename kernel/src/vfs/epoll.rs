@@ -0,0 +1,326 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! epoll instances.
+//!
+//! An epoll file holds an interest list of other files. Waiting on it polls
+//! all of them with the same [`PollWaiter`], so that whichever becomes ready
+//! first wakes the caller up. Level-triggered entries are reported as long
+//! as their file is ready, edge-triggered ones only for events which weren't
+//! set the last time the file was polled.
+
+use crate::{
+    error::{code, Error},
+    vfs::{
+        file::{FileAttr, FileOps, OpenFlags},
+        inode::InodeAttr,
+        inode_mode::{InodeFileType, InodeMode},
+        poll::{self, PollEvents, PollWaiter},
+    },
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    ffi::c_int,
+    sync::atomic::{AtomicI32, Ordering},
+};
+use spin::Mutex;
+
+pub const EPOLL_CLOEXEC: c_int = libc::O_CLOEXEC;
+
+pub const EPOLL_CTL_ADD: c_int = 1;
+pub const EPOLL_CTL_DEL: c_int = 2;
+pub const EPOLL_CTL_MOD: c_int = 3;
+
+/// Report the entry at most once, until it's re-armed with `EPOLL_CTL_MOD`.
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// Edge-triggered.
+pub const EPOLLET: u32 = 1 << 31;
+
+/// Event mask and user data of an entry. The low bits of the mask are the
+/// [`PollEvents`] bits.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+struct Interest {
+    // Held until the entry is deleted, even if its fd is closed.
+    file: Arc<dyn FileOps>,
+    event: EpollEvent,
+    // Events last seen by an edge-triggered entry.
+    last: PollEvents,
+    // A oneshot entry which has been reported.
+    disabled: bool,
+}
+
+impl Interest {
+    fn new(file: Arc<dyn FileOps>, event: EpollEvent) -> Self {
+        Self {
+            file,
+            event,
+            last: PollEvents::empty(),
+            disabled: false,
+        }
+    }
+
+    fn events(&self) -> PollEvents {
+        PollEvents::from_bits_truncate(self.event.events as i16)
+    }
+}
+
+pub struct EpollFile {
+    interest: Mutex<BTreeMap<c_int, Interest>>,
+    open_flags: AtomicI32,
+}
+
+impl EpollFile {
+    pub fn new(flags: OpenFlags) -> Self {
+        Self {
+            interest: Mutex::new(BTreeMap::new()),
+            open_flags: AtomicI32::new(flags.bits()),
+        }
+    }
+
+    /// Adds, modifies or removes the entry of `file`, open as `fd`. `event`
+    /// is ignored for `EPOLL_CTL_DEL`.
+    pub fn ctl(
+        &self,
+        op: c_int,
+        fd: c_int,
+        file: Arc<dyn FileOps>,
+        event: Option<EpollEvent>,
+    ) -> Result<(), Error> {
+        // Nested instances could form loops.
+        if file.downcast_ref::<EpollFile>().is_some() {
+            return Err(code::EINVAL);
+        }
+        let mut interest = self.interest.lock();
+        match (op, event) {
+            (EPOLL_CTL_ADD, Some(event)) => {
+                if interest.contains_key(&fd) {
+                    return Err(code::EEXIST);
+                }
+                interest.insert(fd, Interest::new(file, event));
+            }
+            (EPOLL_CTL_MOD, Some(event)) => {
+                let entry = interest.get_mut(&fd).ok_or(code::ENOENT)?;
+                *entry = Interest::new(file, event);
+            }
+            (EPOLL_CTL_DEL, _) => {
+                interest.remove(&fd).ok_or(code::ENOENT)?;
+            }
+            _ => return Err(code::EINVAL),
+        }
+        Ok(())
+    }
+
+    // Polls the entries which are enabled, without holding the lock since
+    // a file may block to poll.
+    fn poll_entries(&self, waiter: Option<&Arc<PollWaiter>>) -> Vec<(c_int, PollEvents)> {
+        let entries: Vec<_> = self
+            .interest
+            .lock()
+            .iter()
+            .filter(|(_, entry)| !entry.disabled)
+            .map(|(fd, entry)| (*fd, entry.file.clone(), entry.events()))
+            .collect();
+        entries
+            .into_iter()
+            .map(|(fd, file, events)| {
+                (
+                    fd,
+                    file.poll(events, waiter) & (events | PollEvents::ALWAYS),
+                )
+            })
+            .collect()
+    }
+
+    // Stores the events to report in `out`, returning how many there are.
+    fn collect(&self, out: &mut [EpollEvent], waiter: &Arc<PollWaiter>) -> usize {
+        let polled = self.poll_entries(Some(waiter));
+        let mut interest = self.interest.lock();
+        let mut n = 0;
+        for (fd, revents) in polled {
+            if n == out.len() {
+                break;
+            }
+            // Deleted or disabled while we were polling.
+            let Some(entry) = interest.get_mut(&fd).filter(|entry| !entry.disabled) else {
+                continue;
+            };
+            let mut report = revents;
+            if entry.event.events & EPOLLET != 0 {
+                report &= !entry.last;
+                entry.last = revents;
+            }
+            if report.is_empty() {
+                continue;
+            }
+            if entry.event.events & EPOLLONESHOT != 0 {
+                entry.disabled = true;
+            }
+            out[n] = EpollEvent {
+                events: report.bits() as u16 as u32,
+                data: entry.event.data,
+            };
+            n += 1;
+        }
+        n
+    }
+
+    /// Waits for events, for at most `timeout` ticks or forever if it's
+    /// `None`. Returns how many were stored in `out`.
+    pub fn wait(&self, out: &mut [EpollEvent], timeout: Option<usize>) -> usize {
+        if out.is_empty() {
+            return 0;
+        }
+        poll::poll_until(timeout, |waiter| self.collect(out, waiter))
+    }
+}
+
+impl FileOps for EpollFile {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        let ready = self
+            .poll_entries(waiter)
+            .iter()
+            .any(|(_, revents)| !revents.is_empty());
+        if ready {
+            events & PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    fn stat(&self) -> FileAttr {
+        let attr = InodeAttr::new(
+            0,
+            InodeFileType::Unknown,
+            InodeMode::from_bits_truncate(0o600),
+            0,
+            0,
+            0,
+        );
+        FileAttr::new(0, 0, &attr)
+    }
+
+    fn flags(&self) -> OpenFlags {
+        OpenFlags::from_bits_truncate(self.open_flags.load(Ordering::Relaxed))
+    }
+
+    fn set_flags(&self, flags: OpenFlags) {
+        self.open_flags.store(flags.bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::poll::PollQueue;
+    use blueos_test_macro::test;
+    use core::sync::atomic::AtomicBool;
+
+    struct Flag {
+        ready: AtomicBool,
+        queue: PollQueue,
+    }
+
+    impl Flag {
+        fn set(&self, ready: bool) {
+            self.ready.store(ready, Ordering::Release);
+            self.queue.notify();
+        }
+    }
+
+    impl FileOps for Flag {
+        fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+            if let Some(waiter) = waiter {
+                self.queue.register(waiter);
+            }
+            if self.ready.load(Ordering::Acquire) {
+                events & PollEvents::POLLIN
+            } else {
+                PollEvents::empty()
+            }
+        }
+
+        fn stat(&self) -> FileAttr {
+            FileAttr::default()
+        }
+
+        fn flags(&self) -> OpenFlags {
+            OpenFlags::empty()
+        }
+
+        fn set_flags(&self, _flags: OpenFlags) {}
+    }
+
+    #[test]
+    fn test_epoll_wait() {
+        let flag = Arc::new(Flag {
+            ready: AtomicBool::new(false),
+            queue: PollQueue::new(),
+        });
+        let ep = EpollFile::new(OpenFlags::empty());
+        let event = |events, data| {
+            Some(EpollEvent {
+                events: events | libc::POLLIN as u32,
+                data,
+            })
+        };
+        let mut out = [EpollEvent::default(); 4];
+
+        ep.ctl(EPOLL_CTL_ADD, 3, flag.clone(), event(0, 7)).unwrap();
+        assert_eq!(
+            ep.ctl(EPOLL_CTL_ADD, 3, flag.clone(), event(0, 7)),
+            Err(code::EEXIST)
+        );
+        assert_eq!(ep.wait(&mut out, Some(0)), 0);
+        assert!(ep.poll(PollEvents::POLLIN, None).is_empty());
+
+        // Level-triggered entries are reported until the file isn't ready.
+        flag.set(true);
+        assert_eq!(ep.wait(&mut out, Some(0)), 1);
+        assert_eq!(out[0], event(0, 7).unwrap());
+        assert_eq!(ep.wait(&mut out, None), 1);
+        assert_eq!(ep.poll(PollEvents::POLLIN, None), PollEvents::POLLIN);
+
+        ep.ctl(EPOLL_CTL_MOD, 3, flag.clone(), event(EPOLLET, 8))
+            .unwrap();
+        assert_eq!(ep.wait(&mut out, Some(0)), 1);
+        assert_eq!(out[0].data, 8);
+        assert_eq!(ep.wait(&mut out, Some(0)), 0);
+        flag.set(false);
+        assert_eq!(ep.wait(&mut out, Some(0)), 0);
+        flag.set(true);
+        assert_eq!(ep.wait(&mut out, Some(0)), 1);
+
+        ep.ctl(EPOLL_CTL_MOD, 3, flag.clone(), event(EPOLLONESHOT, 9))
+            .unwrap();
+        assert_eq!(ep.wait(&mut out, Some(0)), 1);
+        assert_eq!(ep.wait(&mut out, Some(0)), 0);
+
+        ep.ctl(EPOLL_CTL_DEL, 3, flag.clone(), None).unwrap();
+        assert_eq!(
+            ep.ctl(EPOLL_CTL_DEL, 3, flag.clone(), None),
+            Err(code::ENOENT)
+        );
+        let nested = Arc::new(EpollFile::new(OpenFlags::empty()));
+        assert_eq!(
+            ep.ctl(EPOLL_CTL_ADD, 4, nested, event(0, 0)),
+            Err(code::EINVAL)
+        );
+    }
+}
@@ -0,0 +1,358 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exposes the entries of the key-value store as the files of a flat
+//! directory. Every write to a file is committed to the store.
+
+use crate::{
+    devices::Device,
+    error::{code, Error},
+    kvstore,
+    vfs::{
+        dcache::Dcache,
+        dirent::DirBufferReader,
+        file::FileAttr,
+        fs::{FileSystem, FileSystemInfo},
+        inode::{InodeAttr, InodeNo, InodeOps},
+        inode_mode::{InodeFileType, InodeMode},
+    },
+};
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use log::warn;
+use spin::RwLock;
+
+const MAGIC: usize = 0x45544353;
+const BLOCK_SIZE: usize = 512;
+const ROOT_INO: InodeNo = 1;
+
+pub struct EtcFileSystem {
+    root: Arc<EtcDir>,
+    next_inode_no: AtomicUsize,
+    fs_info: FileSystemInfo,
+    is_mounted: AtomicBool,
+}
+
+impl EtcFileSystem {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|weak_fs| Self {
+            root: Arc::new_cyclic(|weak_root| EtcDir {
+                attr: RwLock::new(InodeAttr::new(
+                    ROOT_INO,
+                    InodeFileType::Directory,
+                    InodeMode::from_bits_truncate(0o755),
+                    0,
+                    0,
+                    BLOCK_SIZE,
+                )),
+                fs: weak_fs.clone(),
+                this: weak_root.clone(),
+                files: RwLock::new(BTreeMap::new()),
+            }),
+            next_inode_no: AtomicUsize::new(ROOT_INO + 1),
+            fs_info: FileSystemInfo::new(MAGIC, 0, kvstore::MAX_KEY_LEN, BLOCK_SIZE, 0),
+            is_mounted: AtomicBool::new(false),
+        })
+    }
+
+    fn alloc_inode_no(&self) -> InodeNo {
+        self.next_inode_no.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl FileSystem for EtcFileSystem {
+    fn mount(&self, _mount_point: Arc<Dcache>) -> Result<(), Error> {
+        if self.is_mounted.swap(true, Ordering::Relaxed) {
+            warn!("Filesystem already mounted!");
+            return Err(code::EBUSY);
+        }
+        Ok(())
+    }
+
+    fn unmount(&self) -> Result<(), Error> {
+        if !self.is_mounted.swap(false, Ordering::Relaxed) {
+            return Err(code::EINVAL);
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        // Entries are committed as they are written.
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn InodeOps> {
+        self.root.clone()
+    }
+
+    fn fs_info(&self) -> FileSystemInfo {
+        self.fs_info.clone()
+    }
+
+    fn fs_type(&self) -> &str {
+        "etcfs"
+    }
+}
+
+struct EtcDir {
+    attr: RwLock<InodeAttr>,
+    fs: Weak<EtcFileSystem>,
+    this: Weak<EtcDir>,
+    // Inodes handed out so far, so that a key keeps its inode number.
+    files: RwLock<BTreeMap<String, Arc<EtcFile>>>,
+}
+
+impl EtcDir {
+    fn file(&self, key: &str) -> Arc<EtcFile> {
+        if let Some(file) = self.files.read().get(key) {
+            return file.clone();
+        }
+        let ino = self.fs.upgrade().unwrap().alloc_inode_no();
+        let file = Arc::new(EtcFile {
+            attr: RwLock::new(InodeAttr::new(
+                ino,
+                InodeFileType::Regular,
+                InodeMode::from_bits_truncate(0o644),
+                0,
+                0,
+                BLOCK_SIZE,
+            )),
+            fs: self.fs.clone(),
+            key: String::from(key),
+        });
+        self.files
+            .write()
+            .entry(String::from(key))
+            .or_insert(file)
+            .clone()
+    }
+}
+
+impl InodeOps for EtcDir {
+    fn lookup(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name == "." || name == ".." {
+            return Ok(self.this.upgrade().unwrap());
+        }
+        if !kvstore::contains(name) {
+            return Err(code::ENOENT);
+        }
+        Ok(self.file(name))
+    }
+
+    fn getdents_at(&self, offset: usize, reader: &mut DirBufferReader) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut current_offset = offset;
+        let keys = kvstore::keys();
+        let dots = [(self.ino(), "."), (self.ino(), "..")];
+        let entries = dots
+            .into_iter()
+            .map(|(ino, name)| (ino, InodeFileType::Directory, name))
+            .chain(
+                keys.iter()
+                    .map(|key| (self.file(key).ino(), InodeFileType::Regular, key.as_str())),
+            );
+        for (ino, type_, name) in entries.skip(offset) {
+            if let Err(e) = reader.write_node(ino, current_offset as i64, type_, name) {
+                if count == 0 {
+                    return Err(e);
+                }
+                return Ok(count);
+            }
+            count += 1;
+            current_offset += 1;
+        }
+        Ok(count)
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        type_: InodeFileType,
+        _mode: InodeMode,
+    ) -> Result<Arc<dyn InodeOps>, Error> {
+        if type_ != InodeFileType::Regular {
+            return Err(code::EPERM);
+        }
+        if kvstore::contains(name) {
+            return Err(code::EEXIST);
+        }
+        kvstore::set(name, &[])?;
+        Ok(self.file(name))
+    }
+
+    fn create_device(
+        &self,
+        _name: &str,
+        _mode: InodeMode,
+        _device: Arc<dyn Device>,
+    ) -> Result<Arc<dyn InodeOps>, Error> {
+        Err(code::EPERM)
+    }
+
+    fn link(&self, _old: &Arc<dyn InodeOps>, _name: &str) -> Result<(), Error> {
+        Err(code::EPERM)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Error> {
+        kvstore::remove(name)?;
+        self.files.write().remove(name);
+        Ok(())
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), Error> {
+        Err(code::ENOTDIR)
+    }
+
+    fn fs(&self) -> Option<Arc<dyn FileSystem>> {
+        self.fs.upgrade().map(|fs| fs as Arc<dyn FileSystem>)
+    }
+
+    fn ino(&self) -> InodeNo {
+        ROOT_INO
+    }
+
+    fn type_(&self) -> InodeFileType {
+        InodeFileType::Directory
+    }
+
+    fn inode_attr(&self) -> InodeAttr {
+        self.attr.read().clone()
+    }
+
+    fn file_attr(&self) -> FileAttr {
+        FileAttr::new(0, 0, &self.inode_attr())
+    }
+
+    fn mode(&self) -> InodeMode {
+        self.attr.read().mode()
+    }
+
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn atime(&self) -> Duration {
+        self.attr.read().atime()
+    }
+
+    fn set_atime(&self, time: Duration) {
+        self.attr.write().set_atime(time);
+    }
+
+    fn mtime(&self) -> Duration {
+        self.attr.read().mtime()
+    }
+
+    fn set_mtime(&self, time: Duration) {
+        self.attr.write().set_mtime(time);
+    }
+}
+
+struct EtcFile {
+    attr: RwLock<InodeAttr>,
+    fs: Weak<EtcFileSystem>,
+    key: String,
+}
+
+impl EtcFile {
+    fn value(&self) -> Result<Vec<u8>, Error> {
+        kvstore::get(&self.key).ok_or(code::ENOENT)
+    }
+}
+
+impl InodeOps for EtcFile {
+    fn read_at(&self, offset: usize, buf: &mut [u8], _nonblock: bool) -> Result<usize, Error> {
+        let value = self.value()?;
+        let start = value.len().min(offset);
+        let end = value.len().min(offset + buf.len());
+        buf[..end - start].copy_from_slice(&value[start..end]);
+        Ok(end - start)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8], _nonblock: bool) -> Result<usize, Error> {
+        let mut value = self.value()?;
+        let end = offset + buf.len();
+        if end > value.len() {
+            value.resize(end, 0);
+        }
+        value[offset..end].copy_from_slice(buf);
+        kvstore::set(&self.key, &value)?;
+        Ok(buf.len())
+    }
+
+    fn resize(&self, size: usize) -> Result<(), Error> {
+        let mut value = self.value()?;
+        value.resize(size, 0);
+        kvstore::set(&self.key, &value)
+    }
+
+    fn is_dcacheable(&self) -> bool {
+        // Entries can be removed through the store API.
+        false
+    }
+
+    fn fs(&self) -> Option<Arc<dyn FileSystem>> {
+        self.fs.upgrade().map(|fs| fs as Arc<dyn FileSystem>)
+    }
+
+    fn ino(&self) -> InodeNo {
+        self.attr.read().ino()
+    }
+
+    fn type_(&self) -> InodeFileType {
+        InodeFileType::Regular
+    }
+
+    fn inode_attr(&self) -> InodeAttr {
+        let mut attr = self.attr.read().clone();
+        attr.set_size(self.size());
+        attr
+    }
+
+    fn file_attr(&self) -> FileAttr {
+        FileAttr::new(0, 0, &self.inode_attr())
+    }
+
+    fn mode(&self) -> InodeMode {
+        self.attr.read().mode()
+    }
+
+    fn size(&self) -> usize {
+        self.value().map_or(0, |value| value.len())
+    }
+
+    fn atime(&self) -> Duration {
+        self.attr.read().atime()
+    }
+
+    fn set_atime(&self, time: Duration) {
+        self.attr.write().set_atime(time);
+    }
+
+    fn mtime(&self) -> Duration {
+        self.attr.read().mtime()
+    }
+
+    fn set_mtime(&self, time: Duration) {
+        self.attr.write().set_mtime(time);
+    }
+}
@@ -20,6 +20,7 @@ use crate::{
         dirent::DirBufferReader,
         file::FileAttr,
         fs::{FileSystem, FileSystemInfo},
+        fsck::{self, Report},
         inode::{InodeAttr, InodeNo, InodeOps},
         inode_mode::{InodeFileType, InodeMode},
         utils::NAME_MAX,
@@ -28,6 +29,7 @@ use crate::{
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
+    format,
     string::String,
     sync::{Arc, Weak},
 };
@@ -36,7 +38,6 @@ use core::{
     time::Duration,
 };
 use delegate::delegate;
-use embedded_io::ErrorKind;
 use fatfs::{DefaultTimeProvider, IoBase, LossyOemCpConverter, Read, Seek, SeekFrom, Write};
 use log::{debug, error, info, trace, warn};
 use spin::{mutex::Mutex, MutexGuard, RwLock};
@@ -48,7 +49,7 @@ const TYPE_FAT_12: &str = "Fat12";
 const TYPE_FAT_16: &str = "Fat16";
 const TYPE_FAT_32: &str = "Fat32";
 
-use core::{cell::UnsafeCell, mem::MaybeUninit};
+use core::{cell::UnsafeCell, mem::MaybeUninit, ptr};
 
 type Dir = fatfs::Dir<'static, FatStorage, DefaultTimeProvider, LossyOemCpConverter>;
 type File = fatfs::File<'static, FatStorage, DefaultTimeProvider, LossyOemCpConverter>;
@@ -79,7 +80,19 @@ impl FatFileSystem {
         self.next_inode_no.fetch_add(1, Ordering::Relaxed)
     }
 
+    /// Opens the FAT volume on the block device, formatting the device
+    /// first if it doesn't hold one
     pub fn new(device_name: &str) -> Result<Arc<Self>, Error> {
+        Self::open(device_name, true)
+    }
+
+    /// Opens the FAT12, FAT16 or FAT32 volume on the block device, as
+    /// formatted by another system. Fails with EINVAL if there's none.
+    pub fn open_existing(device_name: &str) -> Result<Arc<Self>, Error> {
+        Self::open(device_name, false)
+    }
+
+    fn open(device_name: &str, format: bool) -> Result<Arc<Self>, Error> {
         if INTERNAL_FS_INSTANCES.read().contains_key(device_name) {
             error!("[FatFileSystem] A file system on the same device already exists.");
             return Err(code::EAGAIN);
@@ -93,6 +106,13 @@ impl FatFileSystem {
         let internal_fs = Box::new({
             match fatfs::FileSystem::new(storage.clone(), fatfs::FsOptions::new()) {
                 Ok(fs) => fs,
+                Err(error) if !format => {
+                    error!(
+                        "[FatFileSystem] No FAT volume on {}, {:?}",
+                        device_name, error
+                    );
+                    return Err(code::EINVAL);
+                }
                 Err(_) => {
                     warn!(
                         "[FatFileSystem] Faild to construct internal fs, format it and try again."
@@ -117,7 +137,6 @@ impl FatFileSystem {
             "[FatFileSystem] internal fs created, type {:?}, cluster size {}, total_clusters {}",
             fat_type, cluster_size, total_clusters,
         );
-        debug_assert!(cluster_size == BLOCK_SIZE);
         let fs_info = FileSystemInfo::new(
             MAGIC,
             0,
@@ -286,12 +305,33 @@ impl FileSystem for FatFileSystem {
     }
 
     fn fs_info(&self) -> FileSystemInfo {
-        self.fs_info.clone()
+        let mut info = self.fs_info.clone();
+        let (internal_fs, _) = get_internal_fs_with_guard(&self.device_name);
+        match internal_fs.stats() {
+            Ok(stats) => {
+                info.bfree = stats.free_clusters() as usize;
+                info.bavail = info.bfree;
+            }
+            Err(error) => warn!("[FatFileSystem] Failed to count free clusters, {:?}", error),
+        }
+        info
     }
 
     fn fs_type(&self) -> &str {
         self.fat_type
     }
+
+    fn check(&self, repair: bool) -> Result<Report, Error> {
+        let mut report = Report::default();
+        fsck::check_tree(&self.root_inode(), &mut report)?;
+        let device = DeviceManager::get()
+            .get_block_device(&self.device_name)
+            .ok_or(code::ENODEV)?;
+        // Keep fatfs off the volume while its table is checked.
+        let (_, _guard) = get_internal_fs_with_guard(&self.device_name);
+        fsck::fat::check(device.as_ref(), repair, &mut report)?;
+        Ok(report)
+    }
 }
 
 impl Drop for FatFileSystem {
@@ -312,14 +352,14 @@ enum FatFileData {
 }
 
 struct FatFile {
-    _parent: Weak<FatInode>,
+    parent: Weak<FatInode>,
     internal_file: InternalFsLock<File>,
 }
 
 impl FatFile {
     fn new(parent: &Weak<FatInode>, internal_file: InternalFsLock<File>) -> Self {
         Self {
-            _parent: parent.clone(),
+            parent: parent.clone(),
             internal_file,
         }
     }
@@ -328,6 +368,8 @@ impl FatFile {
 struct FatDir {
     parent: Weak<FatInode>,
     children: BTreeMap<String, Arc<FatInode>>,
+    // Names of unlinked files which are still open, and so still on disk.
+    orphans: BTreeMap<InodeNo, String>,
     internal_dir: InternalFsLock<Dir>,
 }
 
@@ -336,6 +378,7 @@ impl FatDir {
         Self {
             parent: parent.clone(),
             children: BTreeMap::new(),
+            orphans: BTreeMap::new(),
             internal_dir,
         }
     }
@@ -351,6 +394,61 @@ impl FatDir {
     fn remove(&mut self, name: &str) {
         self.children.remove(name);
     }
+
+    // Moves the entry `name`, which a rename is about to replace with
+    // `inode`, to a hidden name so that it can be put back if the rename
+    // fails. Returns the replaced inode and its hidden name.
+    fn stash(
+        &self,
+        inode: &FatInode,
+        name: &str,
+        this: &Weak<FatInode>,
+    ) -> Result<Option<(Arc<FatInode>, String)>, Error> {
+        let Some(replaced) = self.find(name) else {
+            return Ok(None);
+        };
+        let is_dir = inode.type_() == InodeFileType::Directory;
+        match (is_dir, replaced.inner.read().as_dir()) {
+            (true, Some(dir)) if !dir.children.is_empty() || !dir.orphans.is_empty() => {
+                return Err(code::ENOTEMPTY)
+            }
+            (true, Some(_)) | (false, None) => {}
+            (true, None) => return Err(code::ENOTDIR),
+            (false, Some(_)) => return Err(code::EISDIR),
+        }
+        let stash = format!(".rename-{}", replaced.ino());
+        if self.find(&stash).is_some() {
+            return Err(code::EBUSY);
+        }
+        replaced.move_entry(&self.internal_dir, name, None, &stash, this)?;
+        Ok(Some((replaced, stash)))
+    }
+
+    // Puts an entry stashed by a failed rename back under `name`.
+    fn unstash(&self, replaced: &FatInode, stash: &str, name: &str, this: &Weak<FatInode>) {
+        if replaced
+            .move_entry(&self.internal_dir, stash, None, name, this)
+            .is_err()
+        {
+            error!("[FatInode] rename: {} is left as {}", name, stash);
+        }
+    }
+
+    // Removes an entry which a rename replaced: a directory right away, and
+    // a file once it's no longer open.
+    fn drop_stashed(&mut self, replaced: &FatInode, stash: String) {
+        let mut replaced_inner = replaced.inner.write();
+        if replaced_inner.as_dir().is_some() {
+            let (internal_dir, _) = self.internal_dir.get();
+            if internal_dir.remove(&stash).is_err() {
+                warn!("[FatInode] rename: {} is left behind", stash);
+            }
+            return;
+        }
+        // The entry and the clusters of the file are freed by evict.
+        self.orphans.insert(replaced_inner.attr.ino(), stash);
+        replaced_inner.attr.nlinks = 0;
+    }
 }
 
 /// Inode in fat filesystem
@@ -435,6 +533,41 @@ impl FatInode {
     }
 }
 
+impl FatInode {
+    // Moves the entry of this inode from `src` to `dst`, or within `src` if
+    // `dst` is None.
+    fn move_entry(
+        &self,
+        src: &InternalFsLock<Dir>,
+        old_name: &str,
+        dst: Option<&InternalFsLock<Dir>>,
+        new_name: &str,
+        new_parent: &Weak<FatInode>,
+    ) -> Result<(), Error> {
+        let mut inner = self.inner.write();
+        // Both directories share the lock of the file system.
+        let (src, _guard) = src.get();
+        let dst = dst.map_or(src, |dst| &dst.content);
+        match &mut inner.data {
+            FatFileData::File(file) => {
+                // The entry is copied from the disk, so the size must be
+                // written back first.
+                file.internal_file.content.flush()?;
+                src.rename(old_name, dst, new_name)?;
+                // The old handle refers to the removed entry.
+                file.internal_file.content = dst.open_file(new_name)?;
+                file.parent = new_parent.clone();
+            }
+            FatFileData::Directory(dir) => {
+                src.rename(old_name, dst, new_name)?;
+                dir.internal_dir.content = dst.open_dir(new_name)?;
+                dir.parent = new_parent.clone();
+            }
+        }
+        Ok(())
+    }
+}
+
 struct InnerNode {
     attr: InodeAttr,
     data: FatFileData,
@@ -496,12 +629,16 @@ impl InodeOps for FatInode {
         if dir.find(name).is_some() {
             return Err(code::EEXIST);
         }
+        if dir.orphans.values().any(|orphan| orphan == name) {
+            // The entry is removed from the disk once the file is closed.
+            return Err(code::EBUSY);
+        }
         let inode = {
             let (internal_dir, _) = dir.internal_dir.get();
             match type_ {
                 InodeFileType::Directory => {
                     // Check: The dir should not exist
-                    debug_assert!(!internal_dir.contais_entry(name, true).unwrap());
+                    crate::kdebug_assert!(!internal_dir.contais_entry(name, true).unwrap());
                     let internal_dir = internal_dir.create_dir(name)?;
                     FatInode::new_dir(
                         name,
@@ -516,7 +653,7 @@ impl InodeOps for FatInode {
                 }
                 InodeFileType::Regular => {
                     // Check: The file should not exist
-                    debug_assert!(!internal_dir.contais_entry(name, false).unwrap());
+                    crate::kdebug_assert!(!internal_dir.contais_entry(name, false).unwrap());
                     let internal_file = internal_dir.create_file(name)?;
                     FatInode::new_file(
                         name,
@@ -606,7 +743,7 @@ impl InodeOps for FatInode {
             let block_size = inner.attr.blk_size;
             inner.attr.size = new_size;
             inner.attr.blocks = inner.attr.size.div_ceil(block_size);
-            debug_assert!(extents == inner.attr.blocks);
+            crate::kassert!(extents == inner.attr.blocks);
         }
 
         Ok(write_size)
@@ -629,16 +766,15 @@ impl InodeOps for FatInode {
         let mut inner = self.inner.write();
         let dir: &mut FatDir = inner.as_dir_mut().unwrap();
         let inode = dir.find(name).ok_or(code::ENOENT)?;
-        let target = inode.inner.read();
+        let mut target = inode.inner.write();
         if target.attr.type_() == InodeFileType::Directory {
             error!("[FatInode] unlink: cannot unlink directory");
             return Err(code::EPERM);
         }
-        {
-            let (internal_dir, _) = dir.internal_dir.get();
-            internal_dir.remove(name)?;
-        }
+        // The entry and the clusters of the file are freed by evict.
         dir.remove(name);
+        dir.orphans.insert(target.attr.ino(), String::from(name));
+        target.attr.nlinks = 0;
         Ok(())
     }
 
@@ -667,6 +803,89 @@ impl InodeOps for FatInode {
         Ok(())
     }
 
+    fn rename(
+        &self,
+        old_name: &str,
+        target: &Arc<dyn InodeOps>,
+        new_name: &str,
+    ) -> Result<(), Error> {
+        if new_name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let target = target.downcast_ref::<FatInode>().ok_or(code::EXDEV)?;
+        if ptr::eq(self, target) {
+            let mut inner = self.inner.write();
+            let dir: &mut FatDir = inner.as_dir_mut().ok_or(code::ENOTDIR)?;
+            let inode = dir.find(old_name).ok_or(code::ENOENT)?;
+            if old_name == new_name {
+                return Ok(());
+            }
+            if dir.orphans.values().any(|orphan| orphan == new_name) {
+                return Err(code::EBUSY);
+            }
+            let stashed = dir.stash(&inode, new_name, &self.this)?;
+            if let Err(e) =
+                inode.move_entry(&dir.internal_dir, old_name, None, new_name, &self.this)
+            {
+                if let Some((replaced, stash)) = &stashed {
+                    dir.unstash(replaced, stash, new_name, &self.this);
+                }
+                return Err(e);
+            }
+            dir.remove(old_name);
+            dir.insert(new_name, &inode);
+            if let Some((replaced, stash)) = stashed {
+                dir.drop_stashed(&replaced, stash);
+            }
+            return Ok(());
+        }
+
+        // Lock both directories in address order, so that a rename the
+        // other way round can't deadlock with this one.
+        let (mut inner, mut target_inner) = if ptr::from_ref(self) < ptr::from_ref(target) {
+            let inner = self.inner.write();
+            (inner, target.inner.write())
+        } else {
+            let target_inner = target.inner.write();
+            (self.inner.write(), target_inner)
+        };
+        let (Some(dir), Some(target_dir)) = (inner.as_dir_mut(), target_inner.as_dir_mut()) else {
+            error!("[FatInode] rename: not a directory");
+            return Err(code::ENOTDIR);
+        };
+        let inode = dir.find(old_name).ok_or(code::ENOENT)?;
+        if target_dir.orphans.values().any(|orphan| orphan == new_name) {
+            return Err(code::EBUSY);
+        }
+        // Replacing the old parent, which is locked already and still holds
+        // the moved entry.
+        if target_dir
+            .find(new_name)
+            .is_some_and(|replaced| ptr::eq(Arc::as_ptr(&replaced), self))
+        {
+            return Err(code::ENOTEMPTY);
+        }
+        let stashed = target_dir.stash(&inode, new_name, &target.this)?;
+        if let Err(e) = inode.move_entry(
+            &dir.internal_dir,
+            old_name,
+            Some(&target_dir.internal_dir),
+            new_name,
+            &target.this,
+        ) {
+            if let Some((replaced, stash)) = &stashed {
+                target_dir.unstash(replaced, stash, new_name, &target.this);
+            }
+            return Err(e);
+        }
+        dir.remove(old_name);
+        target_dir.insert(new_name, &inode);
+        if let Some((replaced, stash)) = stashed {
+            target_dir.drop_stashed(&replaced, stash);
+        }
+        Ok(())
+    }
+
     fn getdents_at(&self, offset: usize, reader: &mut DirBufferReader) -> Result<usize, Error> {
         if self.type_() != InodeFileType::Directory {
             error!("[FatInode] getdents_at: not a directory");
@@ -750,7 +969,7 @@ impl InodeOps for FatInode {
             inner.attr.size = new_size;
             let block_size = inner.attr.blk_size;
             inner.attr.blocks = inner.attr.size.div_ceil(block_size);
-            debug_assert!(extents == inner.attr.blocks);
+            crate::kassert!(extents == inner.attr.blocks);
         }
         Ok(())
     }
@@ -788,6 +1007,30 @@ impl InodeOps for FatInode {
         Ok(())
     }
 
+    fn evict(&self) -> Result<(), Error> {
+        let parent = {
+            let mut inner = self.inner.write();
+            let Some(file) = inner.as_file_mut() else {
+                return Ok(());
+            };
+            // Nothing may write the entry back once it's removed.
+            let (internal_file, _) = file.internal_file.get_mut();
+            internal_file.flush()?;
+            file.parent.upgrade()
+        };
+        let Some(parent) = parent else {
+            return Ok(());
+        };
+        let mut parent_inner = parent.inner.write();
+        let dir: &mut FatDir = parent_inner.as_dir_mut().unwrap();
+        let Some(name) = dir.orphans.remove(&self.ino()) else {
+            return Ok(());
+        };
+        let (internal_dir, _) = dir.internal_dir.get();
+        internal_dir.remove(&name)?;
+        Ok(())
+    }
+
     fn fsync(&self) -> Result<(), Error> {
         if self.type_() != InodeFileType::Regular {
             error!("[FatInode] fsync: not a file");
@@ -800,10 +1043,11 @@ impl InodeOps for FatInode {
     }
 
     fn file_attr(&self) -> FileAttr {
-        match self.fs() {
+        // Not through fs_info, which counts the free clusters.
+        match self.fs.upgrade() {
             Some(fs) => {
                 let inner = self.inner.read();
-                let dev = fs.fs_info().dev;
+                let dev = fs.fs_info.dev;
                 FileAttr::new(dev, 0, &inner.attr)
             }
             None => FileAttr::default(),
@@ -973,7 +1217,7 @@ impl Seek for FatStorage {
             }
         };
         if new_pos > self.total_size {
-            return Err(ErrorKind::InvalidInput.into());
+            return Err(code::EINVAL.into());
         }
         self.position = new_pos;
         Ok(self.position)
@@ -996,17 +1240,17 @@ impl core::fmt::Display for FatStorage {
     }
 }
 
-// Add several required error types based on ErrorKind
+// The errors of the device, and those fatfs needs to be able to make.
 #[derive(Debug)]
 pub enum FatStorageError {
-    BasicError(ErrorKind),
+    BasicError(Error),
     UnexpectedEof,
 }
 
 impl fatfs::IoError for FatStorageError {
     fn is_interrupted(&self) -> bool {
         match self {
-            FatStorageError::BasicError(e) => *e == ErrorKind::Interrupted,
+            FatStorageError::BasicError(e) => *e == code::EINTR,
             _ => false,
         }
     }
@@ -1016,12 +1260,21 @@ impl fatfs::IoError for FatStorageError {
     }
 
     fn new_write_zero_error() -> Self {
-        Self::BasicError(ErrorKind::WriteZero)
+        Self::BasicError(code::ENOSPC)
     }
 }
 
-impl From<ErrorKind> for FatStorageError {
-    fn from(value: ErrorKind) -> Self {
+impl From<Error> for FatStorageError {
+    fn from(value: Error) -> Self {
         FatStorageError::BasicError(value)
     }
 }
+
+impl From<FatStorageError> for Error {
+    fn from(value: FatStorageError) -> Self {
+        match value {
+            FatStorageError::BasicError(e) => e,
+            FatStorageError::UnexpectedEof => code::EIO,
+        }
+    }
+}
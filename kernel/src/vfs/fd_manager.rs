@@ -16,10 +16,18 @@
 
 use crate::{
     error::{code, Error},
-    vfs::{file::FileOps, path},
+    sync::rcu,
+    vfs::{
+        file::{FileOps, OpenFlags},
+        path,
+    },
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    ffi::c_int,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
 };
-use alloc::{sync::Arc, vec, vec::Vec};
-use core::ffi::c_int;
 use log::warn;
 use spin::{Mutex as SpinLock, Once};
 
@@ -30,21 +38,72 @@ pub const STDERR_FILENO: c_int = 2;
 /// First available file descriptor
 pub const FIRST_FD: usize = 3;
 
-/// File descriptor manager
+/// A slot of the file descriptor table. The file is the open file
+/// description, shared by every descriptor duplicated from the same open,
+/// so they share its offset and status flags. Only `close_on_exec` belongs
+/// to the descriptor itself.
+struct FdEntry {
+    file: Arc<dyn FileOps>,
+    close_on_exec: AtomicBool,
+}
+
+impl FdEntry {
+    fn new(file: Arc<dyn FileOps>, close_on_exec: bool) -> Self {
+        Self {
+            file,
+            close_on_exec: AtomicBool::new(close_on_exec),
+        }
+    }
+}
+
+type Slots = Box<[AtomicPtr<FdEntry>]>;
+
+/// The file descriptor table, looked up without a lock.
+///
+/// Readers find the file of a descriptor under an RCU read section, and
+/// take a reference to it, so that reads and writes don't serialize on the
+/// fd manager. The manager changes the table: it swaps slots, and the slot
+/// array when it grows, and frees what it replaced once no reader can see
+/// it any more.
+pub(crate) struct FdTable {
+    slots: AtomicPtr<Slots>,
+}
+
+impl FdTable {
+    const fn new() -> Self {
+        Self {
+            slots: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn get(&self, fd: c_int) -> Option<Arc<dyn FileOps>> {
+        if fd < 0 {
+            return None;
+        }
+        let _rcu = rcu::read_lock();
+        let slots = unsafe { self.slots.load(Ordering::Acquire).as_ref() }?;
+        let entry = unsafe { slots.get(fd as usize)?.load(Ordering::Acquire).as_ref() }?;
+        Some(entry.file.clone())
+    }
+}
+
+/// File descriptor manager, which changes the table
 pub struct FdManager {
     /// File descriptor table
-    fds: Vec<Option<Arc<dyn FileOps>>>,
+    table: &'static FdTable,
     /// Next available file descriptor
     next_fd: usize,
 }
 
 impl FdManager {
     /// Create new file descriptor manager
-    pub fn new() -> Self {
-        Self {
-            fds: vec![None; FIRST_FD + 1],
+    fn new(table: &'static FdTable) -> Self {
+        let mut manager = Self {
+            table,
             next_fd: FIRST_FD,
-        }
+        };
+        manager.resize(FIRST_FD + 1);
+        manager
     }
 
     pub fn init_stdio(&mut self) -> Result<(), Error> {
@@ -52,116 +111,265 @@ impl FdManager {
         let stdout = path::open_path("/dev/console", libc::O_WRONLY, 0o666)?;
         let stderr = path::open_path("/dev/console", libc::O_WRONLY, 0o666)?;
 
-        self.fds[STDIN_FILENO as usize] = Some(Arc::new(stdin));
-        self.fds[STDOUT_FILENO as usize] = Some(Arc::new(stdout));
-        self.fds[STDERR_FILENO as usize] = Some(Arc::new(stderr));
+        self.replace(
+            STDIN_FILENO as usize,
+            Some(FdEntry::new(Arc::new(stdin), false)),
+        );
+        self.replace(
+            STDOUT_FILENO as usize,
+            Some(FdEntry::new(Arc::new(stdout), false)),
+        );
+        self.replace(
+            STDERR_FILENO as usize,
+            Some(FdEntry::new(Arc::new(stderr), false)),
+        );
 
         Ok(())
     }
 
-    /// Allocate new file descriptor
+    /// Allocate new file descriptor, closed on exec if the file was opened
+    /// with O_CLOEXEC
     pub fn alloc_fd(&mut self, file: Arc<dyn FileOps>) -> c_int {
         let fd = self.next_fd;
-        self.fds[fd] = Some(file);
+        let close_on_exec = file.flags().contains(OpenFlags::O_CLOEXEC);
+        self.replace(fd, Some(FdEntry::new(file, close_on_exec)));
         self.update_next_fd(fd);
         fd as c_int
     }
 
-    /// Duplicate file descriptor
+    /// Duplicate file descriptor into the lowest free one from `minfd`.
+    /// Both share the same open file description.
     pub fn dup_fd(&mut self, fd: c_int, minfd: c_int, close_on_exec: bool) -> Result<c_int, Error> {
         let Some(file) = self.get_file_ops(fd) else {
             return Err(code::EBADF);
         };
+        if minfd < 0 {
+            return Err(code::EINVAL);
+        }
 
         let mut new_fd = minfd as usize;
         if new_fd < FIRST_FD {
             new_fd = FIRST_FD;
         }
         // find minfd
-        let len = self.fds.len();
-        while new_fd < len && self.fds[new_fd].is_some() {
+        let len = self.len();
+        while new_fd < len && self.is_used(new_fd) {
             new_fd += 1;
         }
         if new_fd >= len {
-            // add capacity by std Vec, so we just +1
-            self.fds.resize(new_fd + 1, None);
+            self.resize(new_fd + 1);
         }
 
-        // do dup
-        let file2 = file.dup(close_on_exec)?;
-        self.fds[new_fd] = Some(file2);
+        self.replace(new_fd, Some(FdEntry::new(file, close_on_exec)));
         self.update_next_fd(new_fd);
         Ok(new_fd as c_int)
     }
 
+    /// Duplicate file descriptor into `new_fd`, replacing what it held. If
+    /// that was the last descriptor of its open file description, the
+    /// description is returned for the caller to close.
+    pub fn dup_fd_to(
+        &mut self,
+        fd: c_int,
+        new_fd: c_int,
+        close_on_exec: bool,
+    ) -> Result<Option<Arc<dyn FileOps>>, Error> {
+        let Some(file) = self.get_file_ops(fd) else {
+            return Err(code::EBADF);
+        };
+        if new_fd < 0 {
+            return Err(code::EBADF);
+        }
+
+        let new_fd = new_fd as usize;
+        if new_fd >= self.len() {
+            self.resize(new_fd + 1);
+        }
+        let old = self.replace(new_fd, Some(FdEntry::new(file, close_on_exec)));
+        if new_fd == self.next_fd {
+            self.update_next_fd(new_fd);
+        }
+        Ok(old.and_then(|old| self.unreferenced(old.file)))
+    }
+
+    /// Free file descriptor. If it was the last descriptor of its open file
+    /// description, the description is returned for the caller to close.
+    pub fn close_fd(&mut self, fd: c_int) -> Result<Option<Arc<dyn FileOps>>, Error> {
+        if fd < 0 || fd as usize >= self.len() {
+            warn!("[fd] close_fd: Invalid fd: {}", fd);
+            return Err(code::EBADF);
+        }
+
+        let Some(entry) = self.replace(fd as usize, None) else {
+            warn!("[fd] close_fd: Fd {} not in use", fd);
+            return Err(code::EBADF);
+        };
+        Ok(self.unreferenced(entry.file))
+    }
+
     /// Free file descriptor
     pub fn free_fd(&mut self, fd: c_int) -> Result<(), Error> {
         // close stdio is allowed
-        if fd as usize >= self.fds.len() {
+        if fd as usize >= self.len() {
             warn!("[fd] free_fd: Invalid fd: {}", fd);
             return Err(code::EBADF);
         }
 
-        if self.fds[fd as usize].is_none() {
+        if self.replace(fd as usize, None).is_none() {
             warn!("[fd] free_fd: Fd {} not in use", fd);
             return Err(code::EBADF);
         }
-
-        self.fds[fd as usize] = None;
         Ok(())
     }
 
     /// Get file operation
     pub fn get_file_ops(&self, fd: c_int) -> Option<Arc<dyn FileOps>> {
-        if fd < 0 || fd as usize >= self.fds.len() {
+        if fd < 0 || fd as usize >= self.len() {
             warn!("[fd] get_file_ops: Invalid fd: {}", fd);
             return None;
         }
 
-        match self.fds[fd as usize].as_ref() {
-            Some(file) => Some(file.clone()),
-            None => {
+        match self.entry(fd) {
+            Ok(entry) => Some(entry.file.clone()),
+            Err(_) => {
                 warn!("[fd] get_file_ops: Fd {} not found", fd);
                 None
             }
         }
     }
 
+    /// Whether the file descriptor is closed on exec
+    pub fn close_on_exec(&self, fd: c_int) -> Result<bool, Error> {
+        self.entry(fd)
+            .map(|entry| entry.close_on_exec.load(Ordering::Relaxed))
+    }
+
+    pub fn set_close_on_exec(&mut self, fd: c_int, close_on_exec: bool) -> Result<(), Error> {
+        let entry = self.entry(fd)?;
+        entry.close_on_exec.store(close_on_exec, Ordering::Relaxed);
+        Ok(())
+    }
+
     /// Check if file descriptor is valid
     pub fn is_valid_fd(&self, fd: c_int) -> bool {
-        fd >= 0 && (fd as usize) < self.fds.len() && self.fds[fd as usize].is_some()
+        self.entry(fd).is_ok()
     }
 
     /// Get current number of allocated file descriptors
     pub fn count(&self) -> usize {
-        self.fds.iter().filter(|fd| fd.is_some()).count()
+        (0..self.len()).filter(|&fd| self.is_used(fd)).count()
+    }
+
+    // The manager is the only one to change the table, slots and entries
+    // stay valid while it's borrowed.
+    fn slots(&self) -> &[AtomicPtr<FdEntry>] {
+        match unsafe { self.table.slots.load(Ordering::Acquire).as_ref() } {
+            Some(slots) => slots,
+            None => &[],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots().len()
+    }
+
+    fn is_used(&self, fd: usize) -> bool {
+        !self.slots()[fd].load(Ordering::Relaxed).is_null()
+    }
+
+    fn entry(&self, fd: c_int) -> Result<&FdEntry, Error> {
+        if fd < 0 {
+            return Err(code::EBADF);
+        }
+        let entry = match self.slots().get(fd as usize) {
+            Some(slot) => slot.load(Ordering::Acquire),
+            None => return Err(code::EBADF),
+        };
+        unsafe { entry.as_ref() }.ok_or(code::EBADF)
+    }
+
+    // Puts `entry` in the slot of `fd`, and returns what it held once
+    // readers are done with it.
+    fn replace(&mut self, fd: usize, entry: Option<FdEntry>) -> Option<FdEntry> {
+        let new = entry.map_or(ptr::null_mut(), |entry| Box::into_raw(Box::new(entry)));
+        let old = self.slots()[fd].swap(new, Ordering::AcqRel);
+        if old.is_null() {
+            return None;
+        }
+        rcu::synchronize();
+        Some(*unsafe { Box::from_raw(old) })
+    }
+
+    // Grows the table to at least `len` slots, doubling it so that opening
+    // files one after the other doesn't copy it each time.
+    fn resize(&mut self, len: usize) {
+        let old_len = self.len();
+        if len <= old_len {
+            return;
+        }
+        let len = len.max(old_len * 2);
+        let slots: Vec<AtomicPtr<FdEntry>> = (0..len)
+            .map(|fd| match self.slots().get(fd) {
+                Some(slot) => AtomicPtr::new(slot.load(Ordering::Relaxed)),
+                None => AtomicPtr::new(ptr::null_mut()),
+            })
+            .collect();
+        let slots: Slots = slots.into_boxed_slice();
+        let old = self
+            .table
+            .slots
+            .swap(Box::into_raw(Box::new(slots)), Ordering::AcqRel);
+        if !old.is_null() {
+            rcu::synchronize();
+            // Only frees the old array, the entries moved to the new one.
+            drop(unsafe { Box::from_raw(old) });
+        }
+    }
+
+    /// Returns `file` if no descriptor refers to it any more.
+    fn unreferenced(&self, file: Arc<dyn FileOps>) -> Option<Arc<dyn FileOps>> {
+        let referenced = (0..self.len() as c_int)
+            .filter_map(|fd| self.entry(fd).ok())
+            .any(|entry| Arc::ptr_eq(&entry.file, &file));
+        (!referenced).then_some(file)
     }
 
     fn update_next_fd(&mut self, new_fd: usize) {
-        let fds_len = self.fds.len();
+        let fds_len = self.len();
         // First try: find free fd from new_fd+1 to end
         let next_fd = (new_fd + 1..fds_len)
-            .find(|&fd| self.fds[fd].is_none())
+            .find(|&fd| !self.is_used(fd))
             .unwrap_or_else(|| {
                 // Second try: find free fd from start to new_fd
                 (FIRST_FD..new_fd)
-                    .find(|&fd| self.fds[fd].is_none())
+                    .find(|&fd| !self.is_used(fd))
                     .unwrap_or(fds_len) // If still not found, use fds_len
             });
 
         // Extend array if needed
         if next_fd >= fds_len {
-            self.fds.resize(next_fd + 1, None);
+            self.resize(next_fd + 1);
         }
 
         self.next_fd = next_fd;
     }
 }
 
-// Global file descriptor manager instance
+// Global file descriptor table and its manager
 // TODO: FdManager is used for per process
+static FD_TABLE: FdTable = FdTable::new();
 static FD_MANAGER: Once<SpinLock<FdManager>> = Once::new();
-/// Get file descriptor manager instance
+/// Get file descriptor manager instance, to change the table
 pub(crate) fn get_fd_manager() -> &'static SpinLock<FdManager> {
-    FD_MANAGER.call_once(|| SpinLock::new(FdManager::new()))
+    FD_MANAGER.call_once(|| SpinLock::new(FdManager::new(&FD_TABLE)))
+}
+
+/// Get the file of `fd` without locking the fd manager, for the hot I/O
+/// paths
+pub(crate) fn get_file_ops(fd: c_int) -> Option<Arc<dyn FileOps>> {
+    let file = FD_TABLE.get(fd);
+    if file.is_none() {
+        warn!("[fd] get_file_ops: Fd {} not found", fd);
+    }
+    file
 }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::{
+    devices::{Device, DeviceClass, DeviceId, DeviceManager},
     error::{code, Error},
     vfs::{
         dcache::Dcache,
@@ -20,6 +21,8 @@ use crate::{
         fs::FileSystemInfo,
         inode::{InodeAttr, InodeNo},
         inode_mode::{mode_t, InodeFileType},
+        orphan, page_cache,
+        poll::{PollEvents, PollWaiter},
         utils::SeekFrom,
     },
 };
@@ -170,14 +173,13 @@ pub trait FileOps: Send + Sync + Any {
     fn close(&self) -> Result<(), Error> {
         Ok(())
     }
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        PollEvents::ready(events)
+    }
     fn resize(&self, new_size: usize) -> Result<(), Error> {
         warn!("resize is not implemented");
         Err(code::EINVAL)
     }
-    fn dup(&self, close_on_exec: bool) -> Result<Arc<dyn FileOps>, Error> {
-        warn!("dup is not implemented");
-        Err(code::EINVAL)
-    }
     fn stat(&self) -> FileAttr;
     fn flags(&self) -> OpenFlags;
     fn set_flags(&self, flags: OpenFlags);
@@ -187,10 +189,27 @@ pub trait FileOps: Send + Sync + Any {
 #[derive(Debug)]
 pub struct File {
     dcache: Arc<Dcache>,
+    // The device of a character or block special file, which reads,
+    // writes and ioctls go to instead of the inode.
+    device: Option<Arc<dyn Device>>,
     open_flags: AtomicI32,
     offset: Mutex<usize>, // also lock for read/ write
 }
 
+// Looks up the device a special file of type `type_` stands for. Misc
+// devices are character devices to user space.
+fn special_device(type_: InodeFileType, rdev: usize) -> Result<Arc<dyn Device>, Error> {
+    let manager = DeviceManager::get();
+    let id = DeviceId::from(rdev);
+    let device = match type_ {
+        InodeFileType::BlockDevice => manager.get_device_by_id(DeviceClass::Block, id),
+        _ => manager
+            .get_device_by_id(DeviceClass::Char, id)
+            .or_else(|| manager.get_device_by_id(DeviceClass::Misc, id)),
+    };
+    device.ok_or(code::ENXIO)
+}
+
 impl File {
     pub fn new(
         dcache: Arc<Dcache>,
@@ -209,8 +228,19 @@ impl File {
             return Err(code::EISDIR);
         }
 
+        let device = match inode.type_() {
+            type_ @ (InodeFileType::CharDevice | InodeFileType::BlockDevice) => {
+                let device = special_device(type_, inode.file_attr().rdev)?;
+                device.open()?;
+                Some(device)
+            }
+            _ => None,
+        };
+
+        orphan::open(inode);
         Ok(Self {
             dcache,
+            device,
             open_flags: AtomicI32::new(access_mode as i32 | flags.bits()),
             offset: Mutex::new(0),
         })
@@ -267,11 +297,14 @@ impl FileOps for File {
             return Err(code::EACCES);
         }
         let mut offset = self.offset.lock();
+        if let Some(device) = &self.device {
+            let mut pos = *offset as u64;
+            let ret = device.read_at(&mut pos, buf, self.is_nonblock())?;
+            *offset = pos as usize;
+            return Ok(ret);
+        }
         // TODO: support O_DIRECT
-        let ret = self
-            .dcache
-            .inode()
-            .read_at(*offset, buf, self.is_nonblock())?;
+        let ret = page_cache::read(self.dcache.inode(), *offset, buf, self.is_nonblock())?;
         *offset += ret;
         Ok(ret)
     }
@@ -281,14 +314,16 @@ impl FileOps for File {
             return Err(code::EACCES);
         }
         let mut offset = self.offset.lock();
+        if let Some(device) = &self.device {
+            let ret = device.write(*offset as u64, buf, self.is_nonblock())?;
+            *offset += ret;
+            return Ok(ret);
+        }
         // offset is ignored if O_APPEND is set
         if self.open_flags().contains(OpenFlags::O_APPEND) {
             *offset = self.dcache.size();
         }
-        let ret = self
-            .dcache
-            .inode()
-            .write_at(*offset, buf, self.is_nonblock())?;
+        let ret = page_cache::write(self.dcache.inode(), *offset, buf, self.is_nonblock())?;
         *offset += ret;
         Ok(ret)
     }
@@ -319,7 +354,10 @@ impl FileOps for File {
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> Result<i32, Error> {
-        self.dcache.inode().ioctl(cmd, arg)
+        match &self.device {
+            Some(device) => device.ioctl(cmd, arg).map(|_| 0),
+            None => self.dcache.inode().ioctl(cmd, arg),
+        }
     }
 
     fn flush(&self) -> Result<(), Error> {
@@ -327,7 +365,17 @@ impl FileOps for File {
     }
 
     fn close(&self) -> Result<(), Error> {
-        self.dcache.inode().close()
+        match &self.device {
+            Some(device) => device.close(),
+            None => self.dcache.inode().close(),
+        }
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        match &self.device {
+            Some(device) => device.poll(events, waiter),
+            None => self.dcache.inode().poll(events, waiter),
+        }
     }
 
     fn resize(&self, new_size: usize) -> Result<(), Error> {
@@ -337,20 +385,6 @@ impl FileOps for File {
         self.dcache.inode().resize(new_size)
     }
 
-    fn dup(&self, close_on_exec: bool) -> Result<Arc<dyn FileOps>, Error> {
-        let flags = self.open_flags();
-        let flags = if close_on_exec {
-            flags | OpenFlags::O_CLOEXEC
-        } else {
-            flags
-        };
-        Ok(Arc::new(File::new(
-            self.dcache(),
-            self.access_mode(),
-            flags,
-        )?))
-    }
-
     fn stat(&self) -> FileAttr {
         let inode = self.dcache.inode();
         inode.file_attr()
@@ -365,6 +399,14 @@ impl FileOps for File {
     }
 }
 
+impl Drop for File {
+    fn drop(&mut self) {
+        if let Err(e) = orphan::close(self.dcache.inode()) {
+            warn!("Failed to evict an unlinked inode: {}", e);
+        }
+    }
+}
+
 impl dyn FileOps {
     pub fn downcast_ref<T: FileOps>(&self) -> Option<&T> {
         (self as &dyn Any).downcast_ref::<T>()
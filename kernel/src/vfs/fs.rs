@@ -14,7 +14,11 @@
 
 use crate::{
     error::Error,
-    vfs::{dcache::Dcache, inode::InodeOps},
+    vfs::{
+        dcache::Dcache,
+        fsck::{self, Report},
+        inode::InodeOps,
+    },
 };
 use alloc::sync::Arc;
 use core::{any::Any, fmt::Debug};
@@ -70,6 +74,14 @@ pub trait FileSystem: Any + Send + Sync {
     fn fs_info(&self) -> FileSystemInfo;
 
     fn fs_type(&self) -> &str;
+
+    /// Checks the consistency of the file system, repairing what can be
+    /// if `repair` is set.
+    fn check(&self, repair: bool) -> Result<Report, Error> {
+        let mut report = Report::default();
+        fsck::check_tree(&self.root_inode(), &mut report)?;
+        Ok(report)
+    }
 }
 
 impl dyn FileSystem {
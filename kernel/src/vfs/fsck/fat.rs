@@ -0,0 +1,464 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks the allocation table of a FAT volume against its directory
+//! tree: every cluster chain must end properly, no cluster may belong to
+//! two chains, and every allocated cluster must belong to one. Lost
+//! clusters are freed and broken chains ended when repairing. All the
+//! copies of the table are written back.
+
+use super::{join, Problem, Report};
+use crate::{
+    devices::Device,
+    error::{code, Error},
+};
+use alloc::{string::String, vec, vec::Vec};
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+const ENTRY_FREE: u8 = 0xe5;
+const ENTRY_END: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    // Largest value of an entry, which marks the end of a chain. The eight
+    // values below it are reserved, bad clusters being marked with
+    // `max - 8`.
+    fn max(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+}
+
+struct Volume<'a> {
+    dev: &'a dyn Device,
+    fat_type: FatType,
+    cluster_size: usize,
+    // Offset of the first table, its size, and the number of copies.
+    fat_offset: u64,
+    fat_size: usize,
+    num_fats: usize,
+    // Fixed root directory of FAT12/16, as offset and size.
+    root_dir: (u64, usize),
+    root_cluster: u32,
+    data_offset: u64,
+    clusters: u32,
+    table: Vec<u8>,
+    // Clusters reached from the directory tree.
+    used: Vec<bool>,
+    dirty: bool,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_exact(dev: &dyn Device, pos: u64, buf: &mut [u8]) -> Result<(), Error> {
+    if dev.read(pos, buf, false)? != buf.len() {
+        return Err(code::EIO);
+    }
+    Ok(())
+}
+
+// 8.3 name of a directory entry, as "NAME.EXT".
+fn short_name(entry: &[u8]) -> String {
+    let base = String::from_utf8_lossy(&entry[..8]);
+    let ext = String::from_utf8_lossy(&entry[8..11]);
+    let (base, ext) = (base.trim_end(), ext.trim_end());
+    if ext.is_empty() {
+        String::from(base)
+    } else {
+        alloc::format!("{}.{}", base, ext)
+    }
+}
+
+impl<'a> Volume<'a> {
+    fn open(dev: &'a dyn Device) -> Result<Self, Error> {
+        let mut bpb = [0u8; 512];
+        read_exact(dev, 0, &mut bpb)?;
+        let sector_size = u16_at(&bpb, 11) as usize;
+        let sectors_per_cluster = bpb[13] as usize;
+        let reserved = u16_at(&bpb, 14) as usize;
+        let num_fats = bpb[16] as usize;
+        let root_entries = u16_at(&bpb, 17) as usize;
+        let total = match u16_at(&bpb, 19) {
+            0 => u32_at(&bpb, 32) as usize,
+            total => total as usize,
+        };
+        let fat_sectors = match u16_at(&bpb, 22) {
+            0 => u32_at(&bpb, 36) as usize,
+            size => size as usize,
+        };
+        if u16_at(&bpb, 510) != 0xaa55
+            || !sector_size.is_power_of_two()
+            || sector_size < 512
+            || sectors_per_cluster == 0
+            || num_fats == 0
+            || fat_sectors == 0
+        {
+            return Err(code::EINVAL);
+        }
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE).div_ceil(sector_size);
+        let data_sector = reserved + num_fats * fat_sectors + root_sectors;
+        let clusters =
+            (total.checked_sub(data_sector).ok_or(code::EINVAL)? / sectors_per_cluster) as u32;
+        let fat_type = if clusters < 4085 {
+            FatType::Fat12
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let mut volume = Self {
+            dev,
+            fat_type,
+            cluster_size: sector_size * sectors_per_cluster,
+            fat_offset: (reserved * sector_size) as u64,
+            fat_size: fat_sectors * sector_size,
+            num_fats,
+            root_dir: (
+                ((reserved + num_fats * fat_sectors) * sector_size) as u64,
+                root_sectors * sector_size,
+            ),
+            root_cluster: u32_at(&bpb, 44),
+            data_offset: (data_sector * sector_size) as u64,
+            clusters,
+            table: vec![0u8; fat_sectors * sector_size],
+            used: vec![false; clusters as usize + 2],
+            dirty: false,
+        };
+        read_exact(dev, volume.fat_offset, &mut volume.table)?;
+        Ok(volume)
+    }
+
+    fn entry(&self, cluster: u32) -> u32 {
+        let n = cluster as usize;
+        match self.fat_type {
+            FatType::Fat12 => {
+                let v = u16_at(&self.table, n + n / 2) as u32;
+                if n % 2 == 1 {
+                    v >> 4
+                } else {
+                    v & 0xfff
+                }
+            }
+            FatType::Fat16 => u16_at(&self.table, 2 * n) as u32,
+            FatType::Fat32 => u32_at(&self.table, 4 * n) & 0x0fff_ffff,
+        }
+    }
+
+    fn set_entry(&mut self, cluster: u32, value: u32) {
+        let n = cluster as usize;
+        match self.fat_type {
+            FatType::Fat12 => {
+                let pos = n + n / 2;
+                let old = u16_at(&self.table, pos);
+                let new = if n % 2 == 1 {
+                    (old & 0x000f) | ((value as u16) << 4)
+                } else {
+                    (old & 0xf000) | (value as u16 & 0xfff)
+                };
+                self.table[pos..pos + 2].copy_from_slice(&new.to_le_bytes());
+            }
+            FatType::Fat16 => {
+                self.table[2 * n..2 * n + 2].copy_from_slice(&(value as u16).to_le_bytes())
+            }
+            FatType::Fat32 => {
+                // The top 4 bits are reserved.
+                let old = u32_at(&self.table, 4 * n) & 0xf000_0000;
+                let new = old | (value & 0x0fff_ffff);
+                self.table[4 * n..4 * n + 4].copy_from_slice(&new.to_le_bytes());
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn is_valid(&self, cluster: u32) -> bool {
+        (2..self.clusters + 2).contains(&cluster)
+    }
+
+    // Marks the chain starting at `start` as used, returning its clusters.
+    fn walk(&mut self, path: &str, start: u32, repair: bool, report: &mut Report) -> Vec<u32> {
+        let eoc = self.fat_type.max() - 7;
+        let mut chain = Vec::new();
+        let mut cluster = start;
+        loop {
+            if !self.is_valid(cluster) || self.used[cluster as usize] {
+                let problem = if self.is_valid(cluster) {
+                    Problem::CrossLinked {
+                        path: String::from(path),
+                        cluster,
+                    }
+                } else {
+                    Problem::BadChain {
+                        path: String::from(path),
+                        cluster,
+                    }
+                };
+                report.problems.push(problem);
+                // The chain can only be ended after a cluster of its own.
+                if let (true, Some(last)) = (repair, chain.last()) {
+                    self.set_entry(*last, self.fat_type.max());
+                    report.repaired += 1;
+                }
+                return chain;
+            }
+            self.used[cluster as usize] = true;
+            chain.push(cluster);
+            let next = self.entry(cluster);
+            if next >= eoc {
+                return chain;
+            }
+            if next == 0 || next == self.fat_type.max() - 8 {
+                report.problems.push(Problem::BadChain {
+                    path: String::from(path),
+                    cluster: next,
+                });
+                if repair {
+                    self.set_entry(cluster, self.fat_type.max());
+                    report.repaired += 1;
+                }
+                return chain;
+            }
+            cluster = next;
+        }
+    }
+
+    fn read_chain(&self, chain: &[u32]) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0u8; chain.len() * self.cluster_size];
+        for (cluster, buf) in chain.iter().zip(data.chunks_exact_mut(self.cluster_size)) {
+            let pos = self.data_offset + (*cluster - 2) as u64 * self.cluster_size as u64;
+            read_exact(self.dev, pos, buf)?;
+        }
+        Ok(data)
+    }
+
+    // Walks the chains of the entries of a directory, then of its
+    // subdirectories.
+    fn check_dir(
+        &mut self,
+        path: &str,
+        entries: &[u8],
+        repair: bool,
+        report: &mut Report,
+    ) -> Result<(), Error> {
+        let mut subdirs = Vec::new();
+        for entry in entries.chunks_exact(DIR_ENTRY_SIZE) {
+            match entry[0] {
+                ENTRY_END => break,
+                ENTRY_FREE | b'.' => continue,
+                _ => {}
+            }
+            let attr = entry[11];
+            if attr == ATTR_LONG_NAME || attr & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+            let mut start = u16_at(entry, 26) as u32;
+            if self.fat_type == FatType::Fat32 {
+                start |= (u16_at(entry, 20) as u32) << 16;
+            }
+            let size = u32_at(entry, 28);
+            let entry_path = join(path, &short_name(entry));
+            report.inodes += 1;
+            if start == 0 {
+                if size != 0 && attr & ATTR_DIRECTORY == 0 {
+                    report.problems.push(Problem::SizeMismatch {
+                        path: entry_path,
+                        size,
+                        clusters: 0,
+                    });
+                }
+                continue;
+            }
+            let chain = self.walk(&entry_path, start, repair, report);
+            if attr & ATTR_DIRECTORY != 0 {
+                subdirs.push((entry_path, chain));
+            } else if (size as usize).div_ceil(self.cluster_size) != chain.len() {
+                report.problems.push(Problem::SizeMismatch {
+                    path: entry_path,
+                    size,
+                    clusters: chain.len() as u32,
+                });
+            }
+        }
+        for (path, chain) in subdirs {
+            let entries = self.read_chain(&chain)?;
+            self.check_dir(&path, &entries, repair, report)?;
+        }
+        Ok(())
+    }
+
+    fn check(&mut self, repair: bool, report: &mut Report) -> Result<(), Error> {
+        let entries = if self.fat_type == FatType::Fat32 {
+            let chain = self.walk("/", self.root_cluster, repair, report);
+            self.read_chain(&chain)?
+        } else {
+            let (pos, size) = self.root_dir;
+            let mut entries = vec![0u8; size];
+            read_exact(self.dev, pos, &mut entries)?;
+            entries
+        };
+        self.check_dir("/", &entries, repair, report)?;
+        let bad = self.fat_type.max() - 8;
+        for cluster in 2..self.clusters + 2 {
+            let entry = self.entry(cluster);
+            if entry != 0 && entry != bad && !self.used[cluster as usize] {
+                report.problems.push(Problem::LostCluster { cluster });
+                if repair {
+                    self.set_entry(cluster, 0);
+                    report.repaired += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_back(&self) -> Result<(), Error> {
+        for i in 0..self.num_fats {
+            let pos = self.fat_offset + (i * self.fat_size) as u64;
+            if self.dev.write(pos, &self.table, false)? != self.table.len() {
+                return Err(code::EIO);
+            }
+        }
+        let _ = self.dev.sync();
+        Ok(())
+    }
+}
+
+/// Checks the FAT volume on `dev`, adding what's wrong to `report`.
+pub(crate) fn check(dev: &dyn Device, repair: bool, report: &mut Report) -> Result<(), Error> {
+    let mut volume = Volume::open(dev)?;
+    volume.check(repair, report)?;
+    if volume.dirty {
+        volume.write_back()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::ramdisk::RamDisk;
+    use blueos_test_macro::test;
+
+    fn dir_entry(name: &[u8; 11], attr: u8, start: u16, size: u32) -> [u8; DIR_ENTRY_SIZE] {
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attr;
+        entry[26..28].copy_from_slice(&start.to_le_bytes());
+        entry[28..].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    // A FAT12 volume of 512 byte clusters, with two tables and the data
+    // starting at sector 4.
+    fn image() -> RamDisk {
+        let mut image = vec![0u8; 40 * 512];
+        image[11..13].copy_from_slice(&512u16.to_le_bytes());
+        image[13] = 1;
+        image[14..16].copy_from_slice(&1u16.to_le_bytes());
+        image[16] = 2;
+        image[17..19].copy_from_slice(&16u16.to_le_bytes());
+        image[19..21].copy_from_slice(&40u16.to_le_bytes());
+        image[22..24].copy_from_slice(&1u16.to_le_bytes());
+        image[510..512].copy_from_slice(&0xaa55u16.to_le_bytes());
+        let root = [
+            dir_entry(b"A       TXT", 0, 2, 1000),
+            dir_entry(b"D          ", ATTR_DIRECTORY, 4, 0),
+            // Shares the second cluster of A.
+            dir_entry(b"C          ", 0, 3, 10),
+        ];
+        for (i, entry) in root.iter().enumerate() {
+            let pos = 3 * 512 + i * DIR_ENTRY_SIZE;
+            image[pos..pos + DIR_ENTRY_SIZE].copy_from_slice(entry);
+        }
+        let subdir = [
+            dir_entry(b".          ", ATTR_DIRECTORY, 4, 0),
+            dir_entry(b"..         ", ATTR_DIRECTORY, 0, 0),
+            dir_entry(b"B          ", 0, 5, 10),
+        ];
+        for (i, entry) in subdir.iter().enumerate() {
+            let pos = 6 * 512 + i * DIR_ENTRY_SIZE;
+            image[pos..pos + DIR_ENTRY_SIZE].copy_from_slice(entry);
+        }
+        let disk = RamDisk::new(image);
+        let mut volume = Volume::open(&disk).unwrap();
+        for (cluster, next) in [(0, 0xff8), (1, 0xfff), (2, 3), (3, 0xfff), (4, 0xfff)] {
+            volume.set_entry(cluster, next);
+        }
+        volume.set_entry(5, 0xfff);
+        // Allocated, but to nothing.
+        volume.set_entry(7, 0xfff);
+        volume.write_back().unwrap();
+        disk
+    }
+
+    #[test]
+    fn test_fsck_fat() {
+        let disk = image();
+        let volume = Volume::open(&disk).unwrap();
+        assert_eq!(volume.fat_type, FatType::Fat12);
+        assert_eq!(volume.clusters, 36);
+
+        let mut report = Report::default();
+        check(&disk, false, &mut report).unwrap();
+        assert_eq!(report.inodes, 4);
+        assert_eq!(
+            report.problems,
+            [
+                Problem::CrossLinked {
+                    path: String::from("/C"),
+                    cluster: 3
+                },
+                Problem::SizeMismatch {
+                    path: String::from("/C"),
+                    size: 10,
+                    clusters: 0
+                },
+                Problem::LostCluster { cluster: 7 },
+            ]
+        );
+        assert_eq!(report.repaired, 0);
+
+        let mut report = Report::default();
+        check(&disk, true, &mut report).unwrap();
+        assert_eq!(report.repaired, 1);
+        // Both tables lost the cluster.
+        let image = disk.data();
+        assert_eq!(u16_at(&image, 512 + 10), 0);
+        assert_eq!(u16_at(&image, 1024 + 10), 0);
+        drop(image);
+
+        let mut report = Report::default();
+        check(&disk, true, &mut report).unwrap();
+        assert!(!report
+            .problems
+            .contains(&Problem::LostCluster { cluster: 7 }));
+    }
+}
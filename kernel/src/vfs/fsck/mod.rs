@@ -0,0 +1,284 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! File system consistency checks.
+//!
+//! [`check_tree`] walks the directory tree of any file system through its
+//! inodes, cross-checking each entry against the inode it names. File
+//! systems add checks of their on-disk structures in
+//! [`FileSystem::check`], like the allocation table of [FAT](fat), which
+//! is also where inconsistencies get repaired.
+
+#[cfg(virtio)]
+pub(crate) mod fat;
+
+use crate::{
+    error::{code, Error},
+    vfs::{
+        dirent::{DirBufferReader, Dirent, DirentType},
+        fs::FileSystem,
+        inode::{InodeNo, InodeOps},
+        inode_mode::InodeFileType,
+        path,
+    },
+};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use log::{info, warn};
+
+// Room for a few dozen entries per getdents call.
+const DIRENT_BUF_SIZE: usize = 2048;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// An entry whose inode can't be looked up.
+    Dangling { path: String },
+    /// An entry listing another inode number than the inode it names.
+    InodeMismatch {
+        path: String,
+        entry: InodeNo,
+        inode: InodeNo,
+    },
+    /// An entry listing another type than the inode it names.
+    TypeMismatch { path: String },
+    /// A directory whose `..` isn't the directory containing it.
+    BadParent { path: String },
+    /// A directory reachable from more than one entry.
+    DirectoryLink { path: String },
+    /// A file whose link count isn't the number of entries naming it.
+    LinkCount {
+        path: String,
+        entries: u32,
+        nlinks: u32,
+    },
+    /// A cluster allocated to no file.
+    LostCluster { cluster: u32 },
+    /// A cluster allocated to several files, or twice to one.
+    CrossLinked { path: String, cluster: u32 },
+    /// A cluster chain going to a free, bad or nonexistent cluster.
+    BadChain { path: String, cluster: u32 },
+    /// A file whose size doesn't fit its cluster chain.
+    SizeMismatch {
+        path: String,
+        size: u32,
+        clusters: u32,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Dangling { path } => write!(f, "{}: no such inode", path),
+            Problem::InodeMismatch { path, entry, inode } => {
+                write!(f, "{}: entry names inode {}, found {}", path, entry, inode)
+            }
+            Problem::TypeMismatch { path } => write!(f, "{}: wrong file type in entry", path),
+            Problem::BadParent { path } => write!(f, "{}: wrong parent directory", path),
+            Problem::DirectoryLink { path } => write!(f, "{}: directory linked twice", path),
+            Problem::LinkCount {
+                path,
+                entries,
+                nlinks,
+            } => write!(f, "{}: {} links, {} entries", path, nlinks, entries),
+            Problem::LostCluster { cluster } => write!(f, "cluster {} lost", cluster),
+            Problem::CrossLinked { path, cluster } => {
+                write!(f, "{}: cluster {} cross-linked", path, cluster)
+            }
+            Problem::BadChain { path, cluster } => {
+                write!(f, "{}: bad cluster chain at {}", path, cluster)
+            }
+            Problem::SizeMismatch {
+                path,
+                size,
+                clusters,
+            } => write!(f, "{}: size {} in {} clusters", path, size, clusters),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Inodes checked.
+    pub inodes: usize,
+    pub problems: Vec<Problem>,
+    /// Problems which were repaired.
+    pub repaired: usize,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.len() == self.repaired
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// Lists `dir` as (inode number, type, name), without `.` and `..`.
+fn read_dir(dir: &Arc<dyn InodeOps>) -> Result<Vec<(InodeNo, DirentType, String)>, Error> {
+    let mut entries = Vec::new();
+    let mut buf = vec![0u8; DIRENT_BUF_SIZE];
+    let mut offset = 0;
+    loop {
+        let mut reader = DirBufferReader::new(&mut buf);
+        let count = dir.getdents_at(offset, &mut reader)?;
+        let len = reader.recv_len();
+        if count == 0 {
+            return Ok(entries);
+        }
+        let mut pos = 0;
+        while pos < len {
+            // SAFETY: getdents_at wrote whole dirents up to len.
+            let dirent = unsafe { Dirent::from_buf_ref(&buf[pos..]) };
+            let name = dirent.name().map_err(|_| code::EINVAL)?;
+            let name = name.to_str().map_err(|_| code::EINVAL)?;
+            if name != "." && name != ".." {
+                entries.push((dirent.ino(), dirent.type_(), String::from(name)));
+            }
+            pos += dirent.reclen() as usize;
+        }
+        offset += count;
+    }
+}
+
+/// Walks the tree below `root`, cross-checking directory entries against
+/// the inodes they name, parents, and the link counts of files.
+pub fn check_tree(root: &Arc<dyn InodeOps>, report: &mut Report) -> Result<(), Error> {
+    // Files found so far, with how many entries name them.
+    let mut files: BTreeMap<InodeNo, (String, u32, u32)> = BTreeMap::new();
+    let mut dirs = BTreeSet::from([root.ino()]);
+    let mut pending = vec![(String::from("/"), root.clone())];
+    report.inodes += 1;
+    while let Some((dir_path, dir)) = pending.pop() {
+        for (ino, type_, name) in read_dir(&dir)? {
+            let path = join(&dir_path, &name);
+            let Ok(inode) = dir.lookup(&name) else {
+                report.problems.push(Problem::Dangling { path });
+                continue;
+            };
+            if inode.ino() != ino {
+                report.problems.push(Problem::InodeMismatch {
+                    path: path.clone(),
+                    entry: ino,
+                    inode: inode.ino(),
+                });
+            }
+            if DirentType::from(inode.type_()) != type_ {
+                report
+                    .problems
+                    .push(Problem::TypeMismatch { path: path.clone() });
+            }
+            if inode.type_() != InodeFileType::Directory {
+                files
+                    .entry(inode.ino())
+                    .or_insert_with(|| {
+                        report.inodes += 1;
+                        (path, 0, inode.inode_attr().nlinks)
+                    })
+                    .1 += 1;
+                continue;
+            }
+            if !dirs.insert(inode.ino()) {
+                report.problems.push(Problem::DirectoryLink { path });
+                continue;
+            }
+            report.inodes += 1;
+            // Not all file systems resolve `..` themselves.
+            if let Ok(parent) = inode.lookup("..") {
+                if parent.ino() != dir.ino() {
+                    report
+                        .problems
+                        .push(Problem::BadParent { path: path.clone() });
+                }
+            }
+            pending.push((path, inode));
+        }
+    }
+    for (path, entries, nlinks) in files.into_values() {
+        if entries != nlinks {
+            report.problems.push(Problem::LinkCount {
+                path,
+                entries,
+                nlinks,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn log_report(fs: &dyn FileSystem, report: &Report) {
+    for problem in &report.problems {
+        warn!("fsck: {}: {}", fs.fs_type(), problem);
+    }
+    info!(
+        "fsck: {}: {} inodes, {} problems, {} repaired",
+        fs.fs_type(),
+        report.inodes,
+        report.problems.len(),
+        report.repaired
+    );
+}
+
+/// Checks the file system mounted at `path`, repairing what can be if
+/// `repair` is set.
+pub fn fsck(path: &str, repair: bool) -> Result<Report, Error> {
+    let dir = path::lookup_path(path).ok_or(code::ENOENT)?;
+    let fs = dir.inode().fs().ok_or(code::EINVAL)?;
+    let report = fs.check(repair)?;
+    log_report(fs.as_ref(), &report);
+    Ok(report)
+}
+
+/// Checks and repairs a file system about to be mounted. Problems are
+/// logged, they don't prevent the mount.
+#[cfg(fsck_on_mount)]
+pub(crate) fn check_on_mount(fs: &dyn FileSystem) {
+    match fs.check(true) {
+        Ok(report) => log_report(fs, &report),
+        Err(error) => warn!("fsck: {}: {}", fs.fs_type(), error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{inode_mode::InodeMode, tmpfs::TmpFileSystem};
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_fsck_tree() {
+        let fs = TmpFileSystem::new();
+        let root = fs.root_inode();
+        let mode = InodeMode::from_bits_truncate(0o755);
+        let dir = root.create("d", InodeFileType::Directory, mode).unwrap();
+        let file = dir.create("f", InodeFileType::Regular, mode).unwrap();
+        root.link(&file, "g").unwrap();
+        let report = fs.check(false).unwrap();
+        assert_eq!(report.inodes, 3);
+        assert!(report.problems.is_empty());
+        assert!(report.is_clean());
+    }
+}
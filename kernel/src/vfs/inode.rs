@@ -13,13 +13,14 @@
 // limitations under the License.
 
 use crate::{
-    devices::Device,
+    devices::{Device, DeviceId},
     error::{code, Error},
     vfs::{
         dirent::DirBufferReader,
         file::FileAttr,
         fs::FileSystem,
         inode_mode::{mode_t, InodeFileType, InodeMode},
+        poll::{PollEvents, PollWaiter},
     },
 };
 use alloc::{string::String, sync::Arc};
@@ -159,6 +160,27 @@ pub trait InodeOps: Any + Sync + Send {
         warn!("create_socket is not implemented");
         Err(code::EINVAL)
     }
+    /// Creates the character or block special file `name` for the device
+    /// numbered `rdev`, which needn't be registered yet.
+    fn mknod(
+        &self,
+        name: &str,
+        type_: InodeFileType,
+        mode: InodeMode,
+        rdev: DeviceId,
+    ) -> Result<Arc<dyn InodeOps>, Error> {
+        warn!("mknod is not implemented");
+        Err(code::EPERM)
+    }
+    /// Creates the symbolic link `name` pointing to `target`.
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        warn!("symlink is not implemented");
+        Err(code::EPERM)
+    }
+    /// Returns the target of a symbolic link.
+    fn readlink(&self) -> Result<String, Error> {
+        Err(code::EINVAL)
+    }
     fn close(&self) -> Result<(), Error> {
         Ok(())
     }
@@ -172,6 +194,9 @@ pub trait InodeOps: Any + Sync + Send {
     fn fsync(&self) -> Result<(), Error> {
         Ok(())
     }
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        PollEvents::ready(events)
+    }
     fn lookup(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
         warn!("lookup is not implemented");
         Err(code::ENOTDIR)
@@ -183,6 +208,11 @@ pub trait InodeOps: Any + Sync + Send {
     fn is_dcacheable(&self) -> bool {
         true
     }
+    /// Frees the data of an inode whose last link has been removed, once
+    /// no file has it open.
+    fn evict(&self) -> Result<(), Error> {
+        Ok(())
+    }
     fn fs(&self) -> Option<Arc<dyn FileSystem>>;
     fn ino(&self) -> InodeNo;
     fn type_(&self) -> InodeFileType;
@@ -0,0 +1,78 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block allocation.
+//!
+//! The blocks in use are found by going through the whole tree when the
+//! volume is mounted, and kept in a bitmap. Free blocks are handed out from
+//! a cursor going round the device, so that erases are spread over all of
+//! the blocks rather than the first free ones.
+
+use crate::error::{code, Error};
+use alloc::{vec, vec::Vec};
+
+pub(super) struct Allocator {
+    used: Vec<u32>,
+    count: u32,
+    free: u32,
+    next: u32,
+}
+
+impl Allocator {
+    /// Every block is free. The cursor starts from `seed`, which should
+    /// differ from one mount to the next.
+    pub(super) fn new(count: u32, seed: u32) -> Self {
+        Self {
+            used: vec![0; count.div_ceil(32) as usize],
+            count,
+            free: count,
+            next: seed % count,
+        }
+    }
+
+    fn is_used(&self, block: u32) -> bool {
+        self.used[block as usize / 32] & (1 << (block % 32)) != 0
+    }
+
+    pub(super) fn mark(&mut self, block: u32) {
+        if block < self.count && !self.is_used(block) {
+            self.used[block as usize / 32] |= 1 << (block % 32);
+            self.free -= 1;
+        }
+    }
+
+    pub(super) fn free(&mut self, block: u32) {
+        if block < self.count && self.is_used(block) {
+            self.used[block as usize / 32] &= !(1 << (block % 32));
+            self.free += 1;
+        }
+    }
+
+    pub(super) fn alloc(&mut self) -> Result<u32, Error> {
+        if self.free == 0 {
+            return Err(code::ENOSPC);
+        }
+        while self.is_used(self.next) {
+            self.next = (self.next + 1) % self.count;
+        }
+        let block = self.next;
+        self.mark(block);
+        self.next = (block + 1) % self.count;
+        Ok(block)
+    }
+
+    pub(super) fn free_count(&self) -> u32 {
+        self.free
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Files too large to be inlined in their directory are CTZ skip lists.
+//!
+//! Block `i` of a file starts with pointers to the blocks `i - 2^k`, for
+//! `k` up to the number of trailing zeros of `i`, followed by data. Only
+//! the last block is recorded in the directory, and any other is reached
+//! from it in a logarithmic number of reads. Blocks are never modified in
+//! place: a write copies the blocks from the first one it changes.
+
+use super::{blocks::Allocator, dir::le32, flash::Disk};
+use crate::error::{code, Error};
+use alloc::{vec, vec::Vec};
+
+/// Returns the block holding the byte at `pos`, and the offset of the byte
+/// in it.
+pub(super) fn index(block_size: usize, pos: usize) -> (usize, usize) {
+    let b = block_size - 8;
+    let i = pos / b;
+    if i == 0 {
+        return (0, pos);
+    }
+    let i = (pos - 4 * ((i - 1).count_ones() as usize + 2)) / b;
+    (i, pos - b * i - 4 * i.count_ones() as usize)
+}
+
+// Size of the pointers at the start of block `i`.
+fn pointers_size(i: usize) -> usize {
+    if i == 0 {
+        0
+    } else {
+        4 * (i.trailing_zeros() as usize + 1)
+    }
+}
+
+// Position in the file of the first byte of data of block `i`.
+fn start(block_size: usize, i: usize) -> usize {
+    if i == 0 {
+        0
+    } else {
+        (block_size - 8) * i + 4 * i.count_ones() as usize + pointers_size(i)
+    }
+}
+
+/// Returns the block of the file of `size` bytes ending with `head` which
+/// holds the byte at `pos`, and the offset of the byte in it.
+pub(super) fn find(disk: &Disk, head: u32, size: usize, pos: usize) -> Result<(u32, usize), Error> {
+    let (mut current, _) = index(disk.block_size, size - 1);
+    let (target, off) = index(disk.block_size, pos);
+    let mut block = head;
+    while current > target {
+        let skip = (current - target).ilog2().min(current.trailing_zeros());
+        let mut pointer = [0; 4];
+        disk.read(block, 4 * skip as usize, &mut pointer)?;
+        block = le32(&pointer);
+        current -= 1 << skip;
+    }
+    Ok((block, off))
+}
+
+/// Returns all the blocks of a file, from the first one.
+pub(super) fn blocks(disk: &Disk, head: u32, size: usize) -> Result<Vec<u32>, Error> {
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    let (last, _) = index(disk.block_size, size - 1);
+    let mut blocks = vec![head; last + 1];
+    for i in (1..=last).rev() {
+        let mut pointer = [0; 4];
+        disk.read(blocks[i], 0, &mut pointer)?;
+        blocks[i - 1] = le32(&pointer);
+    }
+    Ok(blocks)
+}
+
+/// Reads the file of `size` bytes ending with `head` from `pos`.
+pub(super) fn read(
+    disk: &Disk,
+    head: u32,
+    size: usize,
+    pos: usize,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let len = buf.len().min(size.saturating_sub(pos));
+    let mut done = 0;
+    while done < len {
+        let (block, off) = find(disk, head, size, pos + done)?;
+        let n = (len - done).min(disk.block_size - off);
+        disk.read(block, off, &mut buf[done..done + n])?;
+        done += n;
+    }
+    Ok(len)
+}
+
+/// Writes a file of `size` bytes, keeping `prefix` as its first blocks.
+/// `fill` gives the data from a position in the file. Returns the new
+/// blocks, the last of which is the head of the file.
+pub(super) fn write(
+    disk: &Disk,
+    allocator: &mut Allocator,
+    prefix: &[u32],
+    size: usize,
+    mut fill: impl FnMut(usize, &mut [u8]) -> Result<(), Error>,
+) -> Result<Vec<u32>, Error> {
+    let (last, _) = index(disk.block_size, size - 1);
+    let mut written: Vec<u32> = Vec::new();
+    let mut data = vec![0xff; disk.block_size];
+    let result = (prefix.len()..=last).try_for_each(|i| {
+        let pointers = pointers_size(i);
+        for k in 0..pointers / 4 {
+            let j = i - (1 << k);
+            let block = prefix
+                .get(j)
+                .copied()
+                .unwrap_or_else(|| written[j - prefix.len()]);
+            data[4 * k..4 * k + 4].copy_from_slice(&block.to_le_bytes());
+        }
+        let start = start(disk.block_size, i);
+        let len = (disk.block_size - pointers).min(size - start);
+        fill(start, &mut data[pointers..pointers + len])?;
+        let end = (pointers + len).next_multiple_of(disk.prog_size);
+        data[pointers + len..end].fill(0xff);
+        loop {
+            let block = allocator.alloc()?;
+            match program(disk, block, &data[..end]) {
+                Ok(()) => {
+                    written.push(block);
+                    return Ok(());
+                }
+                // A worn out block stays allocated, and another is tried.
+                Err(e) if e == code::EILSEQ => continue,
+                Err(e) => {
+                    allocator.free(block);
+                    return Err(e);
+                }
+            }
+        }
+    });
+    if let Err(e) = result {
+        for &block in written.iter() {
+            allocator.free(block);
+        }
+        return Err(e);
+    }
+    Ok(written)
+}
+
+fn program(disk: &Disk, block: u32, data: &[u8]) -> Result<(), Error> {
+    disk.erase(block)?;
+    disk.prog(block, 0, data)?;
+    let mut written = vec![0; data.len()];
+    disk.read(block, 0, &mut written)?;
+    if written != data {
+        return Err(code::EILSEQ);
+    }
+    Ok(())
+}
@@ -0,0 +1,633 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metadata pairs.
+//!
+//! A metadata pair is two blocks, of which the one with the newer revision
+//! count holds a log of commits. A commit is a run of tags, each with its
+//! data, closed by a CRC tag. Tags are big endian and XORed with the tag
+//! before them, so that erased flash reads as an invalid tag. When a block
+//! is full, the live tags are compacted into the other block of the pair
+//! with a newer revision, so that the old block stays valid until the new
+//! one is.
+//!
+//! From version 2.1, a commit also records the CRC of the program unit
+//! following it, as it was when the commit was written. A commit can only
+//! be appended after the last one while that unit is unchanged, since a
+//! torn program may leave it looking erased.
+
+use super::flash::Disk;
+use crate::error::{code, Error};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use blueos_infra::crc::crc32_update;
+
+pub(super) type Pair = [u32; 2];
+
+pub(super) const BLOCK_NULL: u32 = u32::MAX;
+pub(super) const ROOT_PAIR: Pair = [0, 1];
+
+pub(super) const TYPE_REG: u16 = 0x001;
+pub(super) const TYPE_DIR: u16 = 0x002;
+pub(super) const TYPE_SUPERBLOCK: u16 = 0x0ff;
+pub(super) const TYPE_DIRSTRUCT: u16 = 0x200;
+pub(super) const TYPE_INLINESTRUCT: u16 = 0x201;
+pub(super) const TYPE_CTZSTRUCT: u16 = 0x202;
+const TYPE_CREATE: u16 = 0x401;
+pub(super) const TYPE_DELETE: u16 = 0x4ff;
+const TYPE_CRC: u16 = 0x500;
+const TYPE_FCRC: u16 = 0x5ff;
+const TYPE_SOFTTAIL: u16 = 0x600;
+const TYPE_HARDTAIL: u16 = 0x601;
+const TYPE_MOVESTATE: u16 = 0x7ff;
+const ID_NONE: u16 = 0x3ff;
+const SIZE_DELETED: u16 = 0x3ff;
+
+/// The CRC-32 of littlefs, which has no final XOR
+pub(super) fn crc(crc: u32, data: &[u8]) -> u32 {
+    !crc32_update(!crc, data)
+}
+
+pub(super) fn le32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+/// Whether `a` and `b` are the same pair, in either order
+pub(super) fn pair_eq(a: Pair, b: Pair) -> bool {
+    a == b || a == [b[1], b[0]]
+}
+
+/// Whether `a` and `b` share a block, as a pair does once one of its
+/// blocks has been relocated
+pub(super) fn pair_overlaps(a: Pair, b: Pair) -> bool {
+    a.iter().any(|block| b.contains(block))
+}
+
+// Bytes closing a commit: its CRC tag, and the forward CRC on volumes
+// which have them.
+fn crc_size(disk: &Disk) -> usize {
+    if disk.fcrc {
+        20
+    } else {
+        8
+    }
+}
+
+pub(super) fn pair_bytes(pair: Pair) -> Vec<u8> {
+    pair.iter().flat_map(|block| block.to_le_bytes()).collect()
+}
+
+/// A tag, made of a valid bit, an 11 bit type, a 10 bit id and a 10 bit
+/// data size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Tag(u32);
+
+impl Tag {
+    pub(super) const fn new(type_: u16, id: u16, size: u16) -> Self {
+        Self(((type_ as u32) << 20) | ((id as u32) << 10) | size as u32)
+    }
+
+    fn is_valid(self) -> bool {
+        self.0 & 0x8000_0000 == 0
+    }
+
+    fn type_(self) -> u16 {
+        (self.0 >> 20) as u16 & 0x7ff
+    }
+
+    fn type1(self) -> u16 {
+        (self.0 >> 20) as u16 & 0x700
+    }
+
+    fn chunk(self) -> u8 {
+        (self.0 >> 20) as u8
+    }
+
+    fn id(self) -> u16 {
+        (self.0 >> 10) as u16 & 0x3ff
+    }
+
+    fn size(self) -> u16 {
+        self.0 as u16 & 0x3ff
+    }
+
+    fn is_delete(self) -> bool {
+        self.size() == SIZE_DELETED
+    }
+
+    /// Bytes taken by the tag and its data
+    fn dsize(self) -> usize {
+        4 + if self.is_delete() {
+            0
+        } else {
+            self.size() as usize
+        }
+    }
+
+    fn is_crc(self) -> bool {
+        self.type_() & 0x780 == TYPE_CRC
+    }
+}
+
+/// A tag to commit, with its data
+#[derive(Debug, Clone)]
+pub(super) struct Attr {
+    tag: Tag,
+    data: Vec<u8>,
+}
+
+impl Attr {
+    fn new(type_: u16, id: u16, data: &[u8]) -> Self {
+        Self {
+            tag: Tag::new(type_, id, data.len() as u16),
+            data: Vec::from(data),
+        }
+    }
+
+    pub(super) fn create(id: usize) -> Self {
+        Self::new(TYPE_CREATE, id as u16, &[])
+    }
+
+    pub(super) fn delete(id: usize) -> Self {
+        Self::new(TYPE_DELETE, id as u16, &[])
+    }
+
+    pub(super) fn name(type_: u16, id: usize, name: &[u8]) -> Self {
+        Self::new(type_, id as u16, name)
+    }
+
+    pub(super) fn struct_(type_: u16, id: usize, data: &[u8]) -> Self {
+        Self::new(type_, id as u16, data)
+    }
+
+    pub(super) fn tail(tail: Option<Tail>) -> Self {
+        match tail {
+            Some(tail) => Self::new(
+                if tail.hard {
+                    TYPE_HARDTAIL
+                } else {
+                    TYPE_SOFTTAIL
+                },
+                ID_NONE,
+                &pair_bytes(tail.pair),
+            ),
+            None => Self::new(TYPE_SOFTTAIL, ID_NONE, &pair_bytes([BLOCK_NULL; 2])),
+        }
+    }
+
+    fn user(id: usize, type_: u8, data: &[u8]) -> Self {
+        Self::new(0x300 | type_ as u16, id as u16, data)
+    }
+
+    fn dsize(&self) -> usize {
+        self.tag.dsize()
+    }
+}
+
+/// The global state, which is the XOR of the deltas held by every
+/// metadata pair. It records a pending move and the number of orphans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct GState {
+    tag: u32,
+    pair: Pair,
+}
+
+impl GState {
+    fn from_bytes(data: &[u8]) -> Self {
+        if data.len() < 12 {
+            return Self::default();
+        }
+        Self {
+            tag: le32(data),
+            pair: [le32(&data[4..]), le32(&data[8..])],
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut data = Vec::from(self.tag.to_le_bytes());
+        data.extend(pair_bytes(self.pair));
+        data
+    }
+
+    pub(super) fn xor(self, other: Self) -> Self {
+        Self {
+            tag: self.tag ^ other.tag,
+            pair: [self.pair[0] ^ other.pair[0], self.pair[1] ^ other.pair[1]],
+        }
+    }
+
+    fn is_zero(self) -> bool {
+        self == Self::default()
+    }
+
+    pub(super) fn has_orphans(self) -> bool {
+        Tag(self.tag).size() & 0x1ff != 0
+    }
+
+    pub(super) fn add_orphans(&mut self, n: i32) {
+        self.tag = self.tag.wrapping_add(n as u32);
+        self.tag = (self.tag & 0x7fff_ffff) | ((self.has_orphans() as u32) << 31);
+    }
+
+    pub(super) fn clear_orphans(&mut self) {
+        self.tag &= !(0x8000_0000 | 0x1ff);
+    }
+
+    /// The entry being moved out of its pair, if any
+    pub(super) fn pending_move(self) -> Option<(Pair, usize)> {
+        (Tag(self.tag).type1() != 0).then_some((self.pair, Tag(self.tag).id() as usize))
+    }
+
+    pub(super) fn set_move(&mut self, moved: Option<(Pair, usize)>) {
+        self.tag &= !Tag::new(0x7ff, 0x3ff, 0).0;
+        match moved {
+            Some((pair, id)) => {
+                self.tag |= Tag::new(TYPE_DELETE, id as u16, 0).0;
+                self.pair = pair;
+            }
+            None => self.pair = [0, 0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Tail {
+    /// A hard tail continues the same directory, a soft one is the next
+    /// directory of the list threading all of them.
+    pub hard: bool,
+    pub pair: Pair,
+}
+
+/// An entry of a directory, as of the tags with its id
+#[derive(Debug, Clone, Default)]
+pub(super) struct Entry {
+    /// Inode number, or 0 if the entry isn't a file or directory
+    pub ino: usize,
+    pub type_: u16,
+    pub name: Vec<u8>,
+    pub struct_type: u16,
+    pub struct_data: Vec<u8>,
+    pub user_attrs: BTreeMap<u8, Vec<u8>>,
+}
+
+impl Entry {
+    pub(super) fn is_dir(&self) -> bool {
+        self.type_ == TYPE_DIR
+    }
+
+    /// The tags of the entry, as `id`
+    pub(super) fn attrs(&self, id: usize) -> Vec<Attr> {
+        let mut attrs = vec![
+            Attr::name(self.type_, id, &self.name),
+            Attr::struct_(self.struct_type, id, &self.struct_data),
+        ];
+        attrs.extend(
+            self.user_attrs
+                .iter()
+                .map(|(&type_, data)| Attr::user(id, type_, data)),
+        );
+        attrs
+    }
+
+    fn size(&self) -> usize {
+        self.attrs(0).iter().map(Attr::dsize).sum()
+    }
+}
+
+/// A metadata pair, with the state left by its commits
+#[derive(Debug, Clone)]
+pub(super) struct Mdir {
+    /// The block holding the newest revision comes first.
+    pub pair: Pair,
+    pub rev: u32,
+    pub entries: Vec<Entry>,
+    pub tail: Option<Tail>,
+    pub gdelta: GState,
+    // End of the last commit, and the tag it ended with
+    off: usize,
+    ptag: u32,
+    // Whether what follows the last commit can be programmed
+    erased: bool,
+}
+
+impl Mdir {
+    /// A pair of newly allocated blocks, yet to be written
+    pub(super) fn new(pair: Pair, rev: u32) -> Self {
+        Self {
+            pair,
+            rev,
+            entries: Vec::new(),
+            tail: None,
+            gdelta: GState::default(),
+            off: 0,
+            ptag: u32::MAX,
+            erased: false,
+        }
+    }
+
+    /// Reads the pair, from whichever block has the newest valid commit.
+    pub(super) fn fetch(disk: &Disk, pair: Pair) -> Result<Self, Error> {
+        let mut best: Option<Self> = None;
+        for (i, &block) in pair.iter().enumerate() {
+            let data = disk.read_block(block)?;
+            let Some(mut mdir) = Self::replay(&data, disk.prog_size) else {
+                continue;
+            };
+            if best
+                .as_ref()
+                .is_some_and(|best| (mdir.rev.wrapping_sub(best.rev) as i32) <= 0)
+            {
+                continue;
+            }
+            mdir.pair = [block, pair[1 - i]];
+            best = Some(mdir);
+        }
+        best.ok_or(code::EILSEQ)
+    }
+
+    // Replays the commits of a block, up to the first one which is torn or
+    // corrupted. Returns None if none is valid.
+    fn replay(data: &[u8], prog_size: usize) -> Option<Self> {
+        let mut mdir = Self::new([BLOCK_NULL; 2], le32(data));
+        let mut valid = false;
+        let mut pending = Vec::new();
+        let mut crc = crc(u32::MAX, &data[..4]);
+        let mut ptag = u32::MAX;
+        // The forward CRC of the last commit, as the size and CRC of what
+        // followed it
+        let mut fcrc = None;
+        let mut off = 4;
+        while off + 4 <= data.len() {
+            let word = u32::from_be_bytes(data[off..off + 4].try_into().unwrap());
+            let tag = Tag(word ^ ptag);
+            if !tag.is_valid() {
+                mdir.erased = Tag(ptag).is_crc()
+                    && off % prog_size == 0
+                    && fcrc.is_none_or(|(size, sum)| {
+                        data.get(off..off + size)
+                            .is_some_and(|next| self::crc(u32::MAX, next) == sum)
+                    });
+                break;
+            }
+            if off + tag.dsize() > data.len() {
+                break;
+            }
+            ptag = tag.0;
+            crc = self::crc(crc, &data[off..off + 4]);
+            if tag.is_crc() {
+                if off + 8 > data.len() || crc != le32(&data[off + 4..]) {
+                    break;
+                }
+                ptag ^= ((tag.chunk() & 1) as u32) << 31;
+                for (tag, range) in pending.drain(..) {
+                    mdir.apply(tag, &data[range]);
+                }
+                off += tag.dsize();
+                mdir.off = off;
+                mdir.ptag = ptag;
+                valid = true;
+                crc = u32::MAX;
+                continue;
+            }
+            let range = off + 4..off + tag.dsize();
+            crc = self::crc(crc, &data[range.clone()]);
+            fcrc = (tag.type_() == TYPE_FCRC && range.len() >= 8).then(|| {
+                (
+                    le32(&data[range.start..]) as usize,
+                    le32(&data[range.start + 4..]),
+                )
+            });
+            pending.push((tag, range));
+            off += tag.dsize();
+        }
+        valid.then_some(mdir)
+    }
+
+    fn apply(&mut self, tag: Tag, data: &[u8]) {
+        let id = tag.id() as usize;
+        if tag.type1() == 0x400 {
+            match tag.type_() {
+                TYPE_CREATE if id <= self.entries.len() => {
+                    self.entries.insert(id, Entry::default());
+                }
+                TYPE_DELETE if id < self.entries.len() => {
+                    self.entries.remove(id);
+                }
+                _ => {}
+            }
+            return;
+        }
+        if tag.type1() == 0x600 {
+            let pair = [le32(data), le32(&data[4..])];
+            self.tail = (pair != [BLOCK_NULL; 2]).then_some(Tail {
+                hard: tag.type_() == TYPE_HARDTAIL,
+                pair,
+            });
+            return;
+        }
+        if tag.type_() == TYPE_MOVESTATE {
+            self.gdelta = GState::from_bytes(data);
+            return;
+        }
+        if !matches!(tag.type1(), 0x000 | 0x200 | 0x300) || tag.id() == ID_NONE {
+            return;
+        }
+        if id >= self.entries.len() {
+            self.entries.resize(id + 1, Entry::default());
+        }
+        let entry = &mut self.entries[id];
+        match tag.type1() {
+            0x000 => {
+                entry.type_ = tag.type_();
+                entry.name = Vec::from(data);
+            }
+            0x200 => {
+                entry.struct_type = tag.type_();
+                entry.struct_data = Vec::from(data);
+            }
+            _ if tag.is_delete() => {
+                entry.user_attrs.remove(&tag.chunk());
+            }
+            _ => {
+                entry.user_attrs.insert(tag.chunk(), Vec::from(data));
+            }
+        }
+    }
+
+    /// Applies `attrs` to the entries, and records `gdelta` as the delta of
+    /// the pair if given. Returns the new state, without writing it.
+    pub(super) fn with(&self, attrs: &[Attr], gdelta: Option<GState>) -> Self {
+        let mut mdir = self.clone();
+        for attr in attrs {
+            mdir.apply(attr.tag, &attr.data);
+        }
+        if let Some(gdelta) = gdelta {
+            mdir.gdelta = gdelta;
+        }
+        mdir
+    }
+
+    /// Appends a commit of `attrs` to the newest block. Returns the new
+    /// state, or None if the commit doesn't fit and the pair must be
+    /// compacted instead.
+    pub(super) fn append(
+        &self,
+        disk: &Disk,
+        attrs: &[Attr],
+        gdelta: Option<GState>,
+    ) -> Result<Option<Self>, Error> {
+        let mut attrs = Vec::from(attrs);
+        if let Some(gdelta) = gdelta {
+            attrs.push(Attr::new(TYPE_MOVESTATE, ID_NONE, &gdelta.to_bytes()));
+        }
+        let size = attrs.iter().map(Attr::dsize).sum::<usize>() + crc_size(disk);
+        if !self.erased || self.off + size.next_multiple_of(disk.prog_size) > disk.block_size {
+            return Ok(None);
+        }
+        let mut commit = Commit::new(disk, self.pair[0], self.off, self.ptag);
+        for attr in attrs.iter() {
+            commit.attr(attr);
+        }
+        let (off, ptag) = match commit.finish() {
+            Ok(end) => end,
+            // The block may be worn out, which compaction deals with.
+            Err(e) if e == code::EILSEQ => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut mdir = self.with(&attrs, None);
+        mdir.off = off;
+        mdir.ptag = ptag;
+        Ok(Some(mdir))
+    }
+
+    /// Whether the entries fit in a block with room left for commits,
+    /// otherwise they must be split over several pairs
+    pub(super) fn fits(disk: &Disk, entries: &[Entry]) -> bool {
+        let size = 4 + entries.iter().map(Entry::size).sum::<usize>();
+        // Leave room for the tail, the global state and the CRC.
+        let limit =
+            (disk.block_size - 40).min((disk.block_size / 2).next_multiple_of(disk.prog_size));
+        entries.len() < 0xff && size <= limit
+    }
+
+    /// Writes the whole state with the next revision count to the second
+    /// block, which becomes the newest.
+    pub(super) fn compact(&mut self, disk: &Disk) -> Result<(), Error> {
+        let block = self.pair[1];
+        disk.erase(block)?;
+        let mut commit = Commit::new(disk, block, 0, u32::MAX);
+        commit.raw(&self.rev.to_le_bytes());
+        for (id, entry) in self.entries.iter().enumerate() {
+            for attr in entry.attrs(id) {
+                commit.attr(&attr);
+            }
+        }
+        if self.tail.is_some() {
+            commit.attr(&Attr::tail(self.tail));
+        }
+        if !self.gdelta.is_zero() {
+            commit.attr(&Attr::new(TYPE_MOVESTATE, ID_NONE, &self.gdelta.to_bytes()));
+        }
+        let (off, ptag) = commit.finish()?;
+        self.pair.swap(0, 1);
+        self.off = off;
+        self.ptag = ptag;
+        self.erased = true;
+        Ok(())
+    }
+}
+
+/// A commit being built, to be programmed at once
+struct Commit<'a> {
+    disk: &'a Disk,
+    block: u32,
+    begin: usize,
+    ptag: u32,
+    crc: u32,
+    data: Vec<u8>,
+}
+
+impl<'a> Commit<'a> {
+    fn new(disk: &'a Disk, block: u32, begin: usize, ptag: u32) -> Self {
+        Self {
+            disk,
+            block,
+            begin,
+            ptag,
+            crc: u32::MAX,
+            data: Vec::new(),
+        }
+    }
+
+    fn raw(&mut self, data: &[u8]) {
+        self.crc = crc(self.crc, data);
+        self.data.extend_from_slice(data);
+    }
+
+    fn attr(&mut self, attr: &Attr) {
+        let tag = attr.tag.0 & 0x7fff_ffff;
+        self.raw(&(tag ^ self.ptag).to_be_bytes());
+        self.raw(&attr.data);
+        self.ptag = tag;
+    }
+
+    /// Closes the commit with CRC tags padding it to the program size,
+    /// programs it and checks it. Returns the end of the commit and the
+    /// tag it ends with.
+    fn finish(mut self) -> Result<(usize, u32), Error> {
+        let disk = self.disk;
+        let mut off = self.begin + self.data.len();
+        if (off + 8).next_multiple_of(disk.prog_size) > disk.block_size {
+            return Err(code::ENOSPC);
+        }
+        let reserve = crc_size(disk);
+        let end = (off + reserve)
+            .min(disk.block_size)
+            .next_multiple_of(disk.prog_size);
+        while off < end {
+            let mut next = (end - off - 4).min(0x3fe) + off + 4;
+            if next < end {
+                next = next.min(end - reserve);
+            }
+            // The valid bit of the next commit must differ from what is at
+            // its place, so that it doesn't look valid until written.
+            let mut word = [0xff; 4];
+            if next + 4 <= disk.block_size {
+                disk.read(self.block, next, &mut word)?;
+            }
+            let reset = !u32::from_be_bytes(word) >> 31;
+            if disk.fcrc && next >= end && next + disk.prog_size <= disk.block_size {
+                let mut erased = vec![0; disk.prog_size];
+                disk.read(self.block, next, &mut erased)?;
+                let mut fcrc = Vec::from((disk.prog_size as u32).to_le_bytes());
+                fcrc.extend(crc(u32::MAX, &erased).to_le_bytes());
+                self.attr(&Attr::new(TYPE_FCRC, ID_NONE, &fcrc));
+                off += fcrc.len() + 4;
+            }
+            let tag = Tag::new(TYPE_CRC + reset as u16, ID_NONE, (next - off - 4) as u16);
+            self.raw(&(tag.0 ^ self.ptag).to_be_bytes());
+            self.data.extend_from_slice(&self.crc.to_le_bytes());
+            self.data.resize(next - self.begin, 0xff);
+            self.ptag = tag.0 ^ (reset << 31);
+            self.crc = u32::MAX;
+            off = next;
+        }
+        disk.prog(self.block, self.begin, &self.data)?;
+        let mut written = vec![0; self.data.len()];
+        disk.read(self.block, self.begin, &mut written)?;
+        if written != self.data {
+            return Err(code::EILSEQ);
+        }
+        Ok((end, self.ptag))
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage under littlefs, and memory mapped NOR flash.
+
+use crate::{
+    error::{code, Error},
+    sync::SpinLock,
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::ptr;
+
+/// Sizes of the units of a storage device, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub read_size: usize,
+    pub prog_size: usize,
+    pub erase_size: usize,
+    pub size: usize,
+}
+
+/// Storage which is programmed and erased in units, like flash. Offsets
+/// and lengths are multiples of the matching unit of the [`Geometry`].
+pub trait BlockDevice: Send + Sync {
+    fn geometry(&self) -> Geometry;
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+    /// Programs erased storage.
+    fn prog(&self, offset: usize, data: &[u8]) -> Result<(), Error>;
+    fn erase(&self, offset: usize, len: usize) -> Result<(), Error>;
+    fn sync(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The controller of a NOR flash, which programs and erases it while it's
+/// read through its memory mapping.
+pub trait NorFlashOps: Send + Sync {
+    /// Programs `data` at `offset` from the start of the flash. Programming
+    /// can only clear bits.
+    fn program(&self, offset: usize, data: &[u8]) -> Result<(), Error>;
+    /// Sets the sector at `offset` to all ones.
+    fn erase_sector(&self, offset: usize) -> Result<(), Error>;
+}
+
+/// A memory mapped NOR flash. Reads are plain loads from the mapping.
+pub struct NorFlash {
+    base: *const u8,
+    geometry: Geometry,
+    ops: Box<dyn NorFlashOps>,
+}
+
+// The mapping is only read, and is written through the controller.
+unsafe impl Send for NorFlash {}
+unsafe impl Sync for NorFlash {}
+
+impl NorFlash {
+    /// # Safety
+    ///
+    /// The `size` bytes at `base` must be the mapping of the flash driven
+    /// by `ops`, and stay mapped.
+    pub unsafe fn new(
+        base: usize,
+        size: usize,
+        sector_size: usize,
+        prog_size: usize,
+        ops: Box<dyn NorFlashOps>,
+    ) -> Self {
+        Self {
+            base: base as *const u8,
+            geometry: Geometry {
+                read_size: 1,
+                prog_size,
+                erase_size: sector_size,
+                size,
+            },
+            ops,
+        }
+    }
+
+    /// Emulates a NOR flash in RAM, for boards without one and for tests.
+    pub fn emulated(size: usize, sector_size: usize, prog_size: usize) -> Self {
+        let mem = Box::into_raw(vec![0xffu8; size].into_boxed_slice()) as *mut u8;
+        let ops = Box::new(EmulatedFlash {
+            mem,
+            size,
+            sector_size,
+        });
+        unsafe { Self::new(mem as usize, size, sector_size, prog_size, ops) }
+    }
+
+    fn check(&self, offset: usize, len: usize, unit: usize) -> Result<(), Error> {
+        let end = offset.checked_add(len).ok_or(code::EINVAL)?;
+        if end > self.geometry.size || offset % unit != 0 || len % unit != 0 {
+            return Err(code::EINVAL);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for NorFlash {
+    fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.check(offset, buf.len(), self.geometry.read_size)?;
+        unsafe { ptr::copy_nonoverlapping(self.base.add(offset), buf.as_mut_ptr(), buf.len()) };
+        Ok(())
+    }
+
+    fn prog(&self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        self.check(offset, data.len(), self.geometry.prog_size)?;
+        self.ops.program(offset, data)
+    }
+
+    fn erase(&self, offset: usize, len: usize) -> Result<(), Error> {
+        self.check(offset, len, self.geometry.erase_size)?;
+        for sector in (offset..offset + len).step_by(self.geometry.erase_size) {
+            self.ops.erase_sector(sector)?;
+        }
+        Ok(())
+    }
+}
+
+struct EmulatedFlash {
+    mem: *mut u8,
+    size: usize,
+    sector_size: usize,
+}
+
+unsafe impl Send for EmulatedFlash {}
+unsafe impl Sync for EmulatedFlash {}
+
+impl NorFlashOps for EmulatedFlash {
+    fn program(&self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter().enumerate() {
+            unsafe { *self.mem.add(offset + i) &= byte };
+        }
+        Ok(())
+    }
+
+    fn erase_sector(&self, offset: usize) -> Result<(), Error> {
+        unsafe { self.mem.add(offset).write_bytes(0xff, self.sector_size) };
+        Ok(())
+    }
+}
+
+impl Drop for EmulatedFlash {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.mem, self.size)) });
+    }
+}
+
+/// A device as seen by a volume, split in blocks of `block_size`. Reads
+/// needn't be aligned.
+pub(super) struct Disk {
+    device: Arc<dyn BlockDevice>,
+    read_size: usize,
+    pub prog_size: usize,
+    pub block_size: usize,
+    pub block_count: u32,
+    /// Whether commits record the CRC of what follows them, which volumes
+    /// have from version 2.1
+    pub fcrc: bool,
+}
+
+impl Disk {
+    pub(super) fn new(device: Arc<dyn BlockDevice>, block_size: usize) -> Result<Self, Error> {
+        let geometry = device.geometry();
+        if block_size < 128
+            || [geometry.read_size, geometry.prog_size, geometry.erase_size]
+                .iter()
+                .any(|&unit| unit == 0 || block_size % unit != 0)
+        {
+            return Err(code::EINVAL);
+        }
+        let block_count = u32::try_from(geometry.size / block_size).map_err(|_| code::EINVAL)?;
+        if block_count < 2 {
+            return Err(code::EINVAL);
+        }
+        Ok(Self {
+            device,
+            read_size: geometry.read_size,
+            prog_size: geometry.prog_size,
+            block_size,
+            block_count,
+            fcrc: true,
+        })
+    }
+
+    // The address of `len` bytes at `off` in `block`, which a corrupted
+    // pointer may put out of bounds.
+    fn addr(&self, block: u32, off: usize, len: usize) -> Result<usize, Error> {
+        if block >= self.block_count || off + len > self.block_size {
+            return Err(code::EILSEQ);
+        }
+        Ok(block as usize * self.block_size + off)
+    }
+
+    pub(super) fn read(&self, block: u32, off: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let addr = self.addr(block, off, buf.len())?;
+        let start = addr - addr % self.read_size;
+        let end = (addr + buf.len()).next_multiple_of(self.read_size);
+        if start == addr && end == addr + buf.len() {
+            return self.device.read(addr, buf);
+        }
+        let mut aligned = vec![0; end - start];
+        self.device.read(start, &mut aligned)?;
+        buf.copy_from_slice(&aligned[addr - start..addr - start + buf.len()]);
+        Ok(())
+    }
+
+    pub(super) fn read_block(&self, block: u32) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; self.block_size];
+        self.read(block, 0, &mut data)?;
+        Ok(data)
+    }
+
+    pub(super) fn prog(&self, block: u32, off: usize, data: &[u8]) -> Result<(), Error> {
+        let addr = self.addr(block, off, data.len())?;
+        self.device.prog(addr, data)
+    }
+
+    pub(super) fn erase(&self, block: u32) -> Result<(), Error> {
+        let addr = self.addr(block, 0, self.block_size)?;
+        self.device.erase(addr, self.block_size)
+    }
+
+    pub(super) fn sync(&self) -> Result<(), Error> {
+        self.device.sync()
+    }
+}
+
+// Devices which can be mounted with littlefs, by name.
+static DEVICES: SpinLock<BTreeMap<String, Arc<dyn BlockDevice>>> = SpinLock::new(BTreeMap::new());
+// Devices with a mounted volume.
+static BUSY: SpinLock<BTreeSet<String>> = SpinLock::new(BTreeSet::new());
+
+// Registering and mounting are done by threads, so the tables are locked
+// with interrupts enabled and keys are made before taking them.
+
+/// Makes `device` available to littlefs mounts as `name`.
+pub fn register_device(name: &str, device: Arc<dyn BlockDevice>) -> Result<(), Error> {
+    let name = String::from(name);
+    match DEVICES.lock().try_insert(name, device) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(code::EEXIST),
+    }
+}
+
+/// Takes the device `name` for a volume, until [`release`].
+pub(super) fn claim(name: &str) -> Result<Arc<dyn BlockDevice>, Error> {
+    let device = DEVICES.lock().get(name).cloned().ok_or(code::ENODEV)?;
+    let name = String::from(name);
+    if !BUSY.lock().insert(name) {
+        return Err(code::EBUSY);
+    }
+    Ok(device)
+}
+
+pub(super) fn release(name: &str) {
+    BUSY.lock().remove(name);
+}
@@ -0,0 +1,464 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! littlefs, a file system for small flash devices which survives power
+//! loss at any point and spreads erases over the whole device.
+//!
+//! This follows version 2.1 of the on-disk format of the reference
+//! implementation, and mounts volumes of versions 2.0 and 2.1. It has only
+//! been checked against volumes it made itself, not against images written
+//! by the reference tools. Devices are registered by name with
+//! [`register_device`], and mounted with the "littlefs" type, as in
+//! `mount("nor0", "/data", "littlefs", 0, "format,block_cycles=100")`.
+//!
+//! Timestamps and modes aren't stored: files are 0644 and directories 0755.
+
+mod blocks;
+mod ctz;
+mod dir;
+mod flash;
+mod volume;
+
+pub use flash::{register_device, BlockDevice, Geometry, NorFlash, NorFlashOps};
+
+use super::tmpfs::parse_size;
+use crate::{
+    devices::Device,
+    error::{code, Error},
+    vfs::{
+        dcache::Dcache,
+        dirent::DirBufferReader,
+        file::FileAttr,
+        fs::{FileSystem, FileSystemInfo},
+        inode::{InodeAttr, InodeNo, InodeOps},
+        inode_mode::{InodeFileType, InodeMode},
+    },
+};
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use flash::Disk;
+use log::{info, warn};
+use spin::{Mutex, RwLock};
+use volume::{Volume, ROOT_INO};
+
+const MAGIC: usize = 0x6c667332;
+
+/// Options of a littlefs mount, from the `data` argument of mount as in
+/// "format,block_size=4k,block_cycles=100".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LittlefsOptions {
+    /// Zero means the erase size of the device.
+    pub block_size: usize,
+    /// Erases of a metadata pair before it's moved to other blocks. Zero
+    /// disables wear leveling of the metadata.
+    pub block_cycles: u32,
+    /// Whether to format the device if it doesn't hold a volume
+    pub format: bool,
+}
+
+impl Default for LittlefsOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 0,
+            block_cycles: 500,
+            format: false,
+        }
+    }
+}
+
+impl LittlefsOptions {
+    pub fn parse(options: &str) -> Result<Self, Error> {
+        let mut parsed = Self::default();
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                None if option == "format" => parsed.format = true,
+                Some(("block_size", value)) => parsed.block_size = parse_size(value)?,
+                Some(("block_cycles", value)) => {
+                    parsed.block_cycles = value.parse().map_err(|_| code::EINVAL)?
+                }
+                _ => {
+                    warn!("littlefs: unknown mount option {}", option);
+                    return Err(code::EINVAL);
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub struct LittleFileSystem {
+    root: Arc<LfsInode>,
+    volume: Mutex<Volume>,
+    device_name: String,
+    is_mounted: AtomicBool,
+    // Inodes handed out so far, so that the VFS sees one per file.
+    inodes: RwLock<BTreeMap<InodeNo, Weak<LfsInode>>>,
+}
+
+impl LittleFileSystem {
+    /// Mounts the volume on the device registered as `device_name`.
+    pub fn open(device_name: &str, options: LittlefsOptions) -> Result<Arc<Self>, Error> {
+        let device = flash::claim(device_name)?;
+        let volume = Self::mount_volume(device, device_name, options)
+            .inspect_err(|_| flash::release(device_name))?;
+
+        Ok(Arc::new_cyclic(|weak_fs| Self {
+            root: Arc::new_cyclic(|this| LfsInode::new(ROOT_INO, true, weak_fs, this)),
+            volume: Mutex::new(volume),
+            device_name: String::from(device_name),
+            is_mounted: AtomicBool::new(false),
+            inodes: RwLock::new(BTreeMap::new()),
+        }))
+    }
+
+    fn mount_volume(
+        device: Arc<dyn BlockDevice>,
+        device_name: &str,
+        options: LittlefsOptions,
+    ) -> Result<Volume, Error> {
+        let block_size = match options.block_size {
+            0 => device.geometry().erase_size,
+            block_size => block_size,
+        };
+        let disk = || Disk::new(device.clone(), block_size);
+        match Volume::mount(disk()?, options.block_cycles) {
+            Err(e) if options.format && (e == code::EINVAL || e == code::EILSEQ) => {
+                info!("littlefs: formatting {}", device_name);
+                Volume::format(&disk()?)?;
+                Volume::mount(disk()?, options.block_cycles)
+            }
+            result => result,
+        }
+    }
+
+    fn inode(self: &Arc<Self>, ino: InodeNo, is_dir: bool) -> Arc<LfsInode> {
+        if ino == ROOT_INO {
+            return self.root.clone();
+        }
+        if let Some(inode) = self.inodes.read().get(&ino).and_then(Weak::upgrade) {
+            return inode;
+        }
+        let mut inodes = self.inodes.write();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return inode;
+        }
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new_cyclic(|this| LfsInode::new(ino, is_dir, &Arc::downgrade(self), this));
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn live_inode(&self, ino: InodeNo) -> Option<Arc<LfsInode>> {
+        self.inodes.read().get(&ino).and_then(Weak::upgrade)
+    }
+}
+
+impl Drop for LittleFileSystem {
+    fn drop(&mut self) {
+        flash::release(&self.device_name);
+    }
+}
+
+impl FileSystem for LittleFileSystem {
+    fn mount(&self, _mount_point: Arc<Dcache>) -> Result<(), Error> {
+        if self.is_mounted.swap(true, Ordering::Relaxed) {
+            warn!("Filesystem already mounted!");
+            return Err(code::EBUSY);
+        }
+        Ok(())
+    }
+
+    fn unmount(&self) -> Result<(), Error> {
+        if !self.is_mounted.swap(false, Ordering::Relaxed) {
+            return Err(code::EINVAL);
+        }
+        self.sync()
+    }
+
+    fn sync(&self) -> Result<(), Error> {
+        // Changes are committed as they're made.
+        self.volume.lock().sync()
+    }
+
+    fn root_inode(&self) -> Arc<dyn InodeOps> {
+        self.root.clone()
+    }
+
+    fn fs_info(&self) -> FileSystemInfo {
+        let volume = self.volume.lock();
+        let mut info = FileSystemInfo::new(
+            MAGIC,
+            0,
+            volume.name_max(),
+            volume.block_size(),
+            volume.block_count(),
+        );
+        info.bfree = volume.free_blocks();
+        info.bavail = info.bfree;
+        info
+    }
+
+    fn fs_type(&self) -> &str {
+        "littlefs"
+    }
+}
+
+struct LfsInode {
+    attr: RwLock<InodeAttr>,
+    fs: Weak<LittleFileSystem>,
+    this: Weak<LfsInode>,
+}
+
+impl LfsInode {
+    fn new(ino: InodeNo, is_dir: bool, fs: &Weak<LittleFileSystem>, this: &Weak<LfsInode>) -> Self {
+        let (type_, mode) = if is_dir {
+            (InodeFileType::Directory, 0o755)
+        } else {
+            (InodeFileType::Regular, 0o644)
+        };
+        Self {
+            attr: RwLock::new(InodeAttr::new(
+                ino,
+                type_,
+                InodeMode::from_bits_truncate(mode),
+                0,
+                0,
+                0,
+            )),
+            fs: fs.clone(),
+            this: this.clone(),
+        }
+    }
+
+    fn fs_ref(&self) -> Arc<LittleFileSystem> {
+        self.fs.upgrade().unwrap()
+    }
+
+    fn is_dir(&self) -> bool {
+        self.type_() == InodeFileType::Directory
+    }
+
+    fn dir_only(&self) -> Result<(), Error> {
+        if !self.is_dir() {
+            return Err(code::ENOTDIR);
+        }
+        Ok(())
+    }
+
+    fn file_only(&self) -> Result<(), Error> {
+        if self.is_dir() {
+            return Err(code::EISDIR);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, name: &str, is_dir: bool) -> Result<(), Error> {
+        self.dir_only()?;
+        let fs = self.fs_ref();
+        let ino = fs.volume.lock().remove(self.ino(), name, is_dir)?;
+        match fs.live_inode(ino) {
+            Some(inode) => inode.attr.write().nlinks = 0,
+            // Nothing has the file open, nor will.
+            None if !is_dir => fs.volume.lock().evict(ino)?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl InodeOps for LfsInode {
+    fn lookup(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        self.dir_only()?;
+        if name == "." {
+            return Ok(self.this.upgrade().unwrap());
+        }
+        let fs = self.fs_ref();
+        if name == ".." {
+            let parent = fs.volume.lock().parent(self.ino());
+            return Ok(fs.inode(parent, true));
+        }
+        let (ino, is_dir) = fs.volume.lock().lookup(self.ino(), name)?;
+        Ok(fs.inode(ino, is_dir))
+    }
+
+    fn getdents_at(&self, offset: usize, reader: &mut DirBufferReader) -> Result<usize, Error> {
+        self.dir_only()?;
+        let fs = self.fs_ref();
+        let (parent, entries) = {
+            let volume = fs.volume.lock();
+            (volume.parent(self.ino()), volume.entries(self.ino()))
+        };
+        let mut count = 0;
+        let mut current_offset = offset;
+        let dots = [(self.ino(), true, "."), (parent, true, "..")];
+        let entries = dots.into_iter().chain(
+            entries
+                .iter()
+                .map(|(ino, is_dir, name)| (*ino, *is_dir, name.as_str())),
+        );
+        for (ino, is_dir, name) in entries.skip(offset) {
+            let type_ = if is_dir {
+                InodeFileType::Directory
+            } else {
+                InodeFileType::Regular
+            };
+            if let Err(e) = reader.write_node(ino, current_offset as i64, type_, name) {
+                if count == 0 {
+                    return Err(e);
+                }
+                return Ok(count);
+            }
+            count += 1;
+            current_offset += 1;
+        }
+        Ok(count)
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        type_: InodeFileType,
+        _mode: InodeMode,
+    ) -> Result<Arc<dyn InodeOps>, Error> {
+        self.dir_only()?;
+        let is_dir = match type_ {
+            InodeFileType::Regular => false,
+            InodeFileType::Directory => true,
+            _ => return Err(code::EPERM),
+        };
+        let fs = self.fs_ref();
+        let ino = fs.volume.lock().create(self.ino(), name, is_dir)?;
+        Ok(fs.inode(ino, is_dir))
+    }
+
+    fn create_device(
+        &self,
+        _name: &str,
+        _mode: InodeMode,
+        _device: Arc<dyn Device>,
+    ) -> Result<Arc<dyn InodeOps>, Error> {
+        Err(code::EPERM)
+    }
+
+    fn link(&self, _old: &Arc<dyn InodeOps>, _name: &str) -> Result<(), Error> {
+        Err(code::EPERM)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Error> {
+        self.remove(name, false)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Error> {
+        self.remove(name, true)
+    }
+
+    fn rename(
+        &self,
+        old_name: &str,
+        target: &Arc<dyn InodeOps>,
+        new_name: &str,
+    ) -> Result<(), Error> {
+        self.dir_only()?;
+        let target = target.downcast_ref::<LfsInode>().ok_or(code::EXDEV)?;
+        target.dir_only()?;
+        let fs = self.fs_ref();
+        let replaced = fs
+            .volume
+            .lock()
+            .rename(self.ino(), old_name, target.ino(), new_name)?;
+        let Some(ino) = replaced else {
+            return Ok(());
+        };
+        match fs.live_inode(ino) {
+            Some(inode) => inode.attr.write().nlinks = 0,
+            None => fs.volume.lock().evict(ino)?,
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8], _nonblock: bool) -> Result<usize, Error> {
+        self.file_only()?;
+        self.fs_ref().volume.lock().read(self.ino(), offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8], _nonblock: bool) -> Result<usize, Error> {
+        self.file_only()?;
+        self.fs_ref().volume.lock().write(self.ino(), offset, buf)
+    }
+
+    fn resize(&self, size: usize) -> Result<(), Error> {
+        self.file_only()?;
+        self.fs_ref().volume.lock().truncate(self.ino(), size)
+    }
+
+    fn evict(&self) -> Result<(), Error> {
+        self.fs_ref().volume.lock().evict(self.ino())
+    }
+
+    fn fs(&self) -> Option<Arc<dyn FileSystem>> {
+        self.fs.upgrade().map(|fs| fs as Arc<dyn FileSystem>)
+    }
+
+    fn ino(&self) -> InodeNo {
+        self.attr.read().ino()
+    }
+
+    fn type_(&self) -> InodeFileType {
+        self.attr.read().type_()
+    }
+
+    fn inode_attr(&self) -> InodeAttr {
+        let mut attr = self.attr.read().clone();
+        let volume = self.fs_ref().volume.lock();
+        attr.blk_size = volume.block_size();
+        attr.size = volume.size(self.ino());
+        attr.blocks = attr.size.div_ceil(attr.blk_size);
+        attr
+    }
+
+    fn file_attr(&self) -> FileAttr {
+        FileAttr::new(0, 0, &self.inode_attr())
+    }
+
+    fn mode(&self) -> InodeMode {
+        self.attr.read().mode()
+    }
+
+    fn size(&self) -> usize {
+        self.fs_ref().volume.lock().size(self.ino())
+    }
+
+    fn atime(&self) -> Duration {
+        self.attr.read().atime()
+    }
+
+    fn set_atime(&self, time: Duration) {
+        self.attr.write().set_atime(time);
+    }
+
+    fn mtime(&self) -> Duration {
+        self.attr.read().mtime()
+    }
+
+    fn set_mtime(&self, time: Duration) {
+        self.attr.write().set_mtime(time);
+    }
+}
@@ -0,0 +1,1039 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A mounted volume.
+//!
+//! The metadata of the whole tree is read when mounting, and every
+//! directory is kept as the chain of its metadata pairs. Changes are
+//! committed to the pairs as they're made, then applied to the copy in
+//! memory, which is what lookups go through.
+//!
+//! Besides the tree, the directories are threaded in a list by the tails
+//! of their last pairs. A change needing commits to two pairs records in
+//! the global state what to finish if it's torn by a power loss: the entry
+//! being moved by a rename, or the number of pairs left out of or wrongly
+//! linked in the list, which are fixed when mounting.
+
+use super::{
+    blocks::Allocator,
+    ctz,
+    dir::{
+        self, le32, pair_bytes, pair_eq, pair_overlaps, Attr, Entry, GState, Mdir, Pair, Tail,
+        ROOT_PAIR, TYPE_CTZSTRUCT, TYPE_DIR, TYPE_DIRSTRUCT, TYPE_INLINESTRUCT, TYPE_REG,
+        TYPE_SUPERBLOCK,
+    },
+    flash::Disk,
+};
+use crate::error::{code, Error};
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use log::warn;
+
+pub(super) const ROOT_INO: usize = 1;
+
+const MAGIC: &[u8] = b"littlefs";
+const VERSION: u32 = 0x0002_0001;
+const NAME_MAX: u32 = 255;
+const FILE_MAX: u32 = 0x7fff_ffff;
+const ATTR_MAX: u32 = 1022;
+
+/// The data of a file
+enum Data {
+    Inline(Vec<u8>),
+    Ctz { head: u32, size: usize },
+}
+
+impl Data {
+    fn of(entry: &Entry) -> Result<Self, Error> {
+        match entry.struct_type {
+            TYPE_INLINESTRUCT => Ok(Self::Inline(entry.struct_data.clone())),
+            TYPE_CTZSTRUCT if entry.struct_data.len() >= 8 => Ok(Self::Ctz {
+                head: le32(&entry.struct_data),
+                size: le32(&entry.struct_data[4..]) as usize,
+            }),
+            _ => Err(code::EILSEQ),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::Inline(data) => data.len(),
+            Self::Ctz { size, .. } => *size,
+        }
+    }
+
+    fn blocks(&self, disk: &Disk) -> Result<Vec<u32>, Error> {
+        match *self {
+            Self::Inline(_) => Ok(Vec::new()),
+            Self::Ctz { head, size } => ctz::blocks(disk, head, size),
+        }
+    }
+
+    fn read(&self, disk: &Disk, pos: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Self::Inline(data) => {
+                let start = data.len().min(pos);
+                let end = data.len().min(pos + buf.len());
+                buf[..end - start].copy_from_slice(&data[start..end]);
+                Ok(end - start)
+            }
+            &Self::Ctz { head, size } => ctz::read(disk, head, size, pos, buf),
+        }
+    }
+}
+
+pub(super) struct Volume {
+    disk: Disk,
+    block_cycles: u32,
+    name_max: usize,
+    file_max: usize,
+    inline_max: usize,
+    allocator: Allocator,
+    // Directories by inode number, as the chain of their pairs
+    dirs: BTreeMap<usize, Vec<Mdir>>,
+    parents: BTreeMap<usize, usize>,
+    // Files unlinked while open, whose blocks are kept until they're
+    // evicted
+    detached: BTreeMap<usize, Entry>,
+    // The global state as it should be, and as it is on the disk
+    gstate: GState,
+    gdisk: GState,
+    next_ino: usize,
+}
+
+impl Volume {
+    /// Writes an empty volume.
+    pub(super) fn format(disk: &Disk) -> Result<(), Error> {
+        disk.erase(ROOT_PAIR[0])?;
+        disk.erase(ROOT_PAIR[1])?;
+        let superblock = [
+            VERSION,
+            disk.block_size as u32,
+            disk.block_count,
+            NAME_MAX,
+            FILE_MAX,
+            ATTR_MAX,
+        ];
+        let mut root = Mdir::new([ROOT_PAIR[1], ROOT_PAIR[0]], 1);
+        root.entries.push(Entry {
+            type_: TYPE_SUPERBLOCK,
+            name: Vec::from(MAGIC),
+            struct_type: TYPE_INLINESTRUCT,
+            struct_data: superblock.iter().flat_map(|n| n.to_le_bytes()).collect(),
+            ..Default::default()
+        });
+        root.compact(disk)
+    }
+
+    /// Reads the volume on `disk`. Fails with EINVAL if there's none.
+    pub(super) fn mount(mut disk: Disk, block_cycles: u32) -> Result<Self, Error> {
+        let root = Mdir::fetch(&disk, ROOT_PAIR).map_err(|_| code::EINVAL)?;
+        let Some(superblock) = root.entries.first().filter(|entry| {
+            entry.type_ == TYPE_SUPERBLOCK
+                && entry.name == MAGIC
+                && entry.struct_type == TYPE_INLINESTRUCT
+                && entry.struct_data.len() >= 24
+        }) else {
+            return Err(code::EINVAL);
+        };
+        let field = |i: usize| le32(&superblock.struct_data[4 * i..]);
+        let (version, block_size, block_count) = (field(0), field(1), field(2));
+        if version >> 16 != 2 || version & 0xffff > 1 {
+            warn!("littlefs: unsupported version {:#x}", version);
+            return Err(code::EINVAL);
+        }
+        if block_size as usize != disk.block_size || block_count > disk.block_count {
+            warn!(
+                "littlefs: volume of {} blocks of {} bytes, the device has {} of {}",
+                block_count, block_size, disk.block_count, disk.block_size
+            );
+            return Err(code::EINVAL);
+        }
+        disk.block_count = block_count;
+        disk.fcrc = version & 0xffff >= 1;
+        let or_default = |n: u32, default: u32| (if n == 0 { default } else { n }) as usize;
+        let name_max = or_default(field(3), NAME_MAX);
+        let file_max = or_default(field(4), FILE_MAX);
+        let inline_max = or_default(field(5), ATTR_MAX)
+            .min(0x3fe)
+            .min(disk.block_size / 8);
+
+        // Read all the pairs of the list.
+        let mut thread = vec![root];
+        while let Some(tail) = thread.last().and_then(|mdir| mdir.tail) {
+            if thread.len() > block_count as usize {
+                return Err(code::EILSEQ);
+            }
+            thread.push(Mdir::fetch(&disk, tail.pair)?);
+        }
+        let gstate = thread
+            .iter()
+            .fold(GState::default(), |gstate, mdir| gstate.xor(mdir.gdelta));
+        let seed = thread
+            .iter()
+            .fold(0, |seed, mdir| dir::crc(seed, &mdir.rev.to_le_bytes()));
+
+        let mut volume = Self {
+            allocator: Allocator::new(block_count, seed),
+            disk,
+            block_cycles,
+            name_max,
+            file_max,
+            inline_max,
+            dirs: BTreeMap::new(),
+            parents: BTreeMap::new(),
+            detached: BTreeMap::new(),
+            gstate,
+            gdisk: gstate,
+            next_ino: ROOT_INO + 1,
+        };
+        volume.load_tree(&thread)?;
+        // Until the list is fixed, what's in it may be in use as well.
+        volume.allocator = volume.scan()?;
+        for mdir in thread.iter() {
+            mdir.pair
+                .iter()
+                .for_each(|&block| volume.allocator.mark(block));
+        }
+        if let Some((pair, id)) = gstate.pending_move() {
+            volume.finish_move(pair, id)?;
+        }
+        if gstate.has_orphans() {
+            volume.deorphan(&thread)?;
+        }
+        volume.allocator = volume.scan()?;
+        Ok(volume)
+    }
+
+    // Reads the directories from the root, numbering their entries.
+    fn load_tree(&mut self, thread: &[Mdir]) -> Result<(), Error> {
+        let moved = self.gstate.pending_move();
+        let mut todo = vec![(ROOT_INO, ROOT_PAIR)];
+        while let Some((ino, head)) = todo.pop() {
+            if self.dirs.len() > self.disk.block_count as usize {
+                return Err(code::EILSEQ);
+            }
+            let mut chain: Vec<Mdir> = Vec::new();
+            let mut pair = head;
+            loop {
+                if chain.len() > self.disk.block_count as usize {
+                    return Err(code::EILSEQ);
+                }
+                let mdir = match thread.iter().find(|mdir| pair_eq(mdir.pair, pair)) {
+                    Some(mdir) => mdir.clone(),
+                    None => Mdir::fetch(&self.disk, pair)?,
+                };
+                let tail = mdir.tail;
+                chain.push(mdir);
+                match tail {
+                    Some(tail) if tail.hard => pair = tail.pair,
+                    _ => break,
+                }
+            }
+            for mdir in chain.iter_mut() {
+                let pair = mdir.pair;
+                for (id, entry) in mdir.entries.iter_mut().enumerate() {
+                    // An entry being moved is already in its new place.
+                    let moving = moved.is_some_and(|moved| pair_eq(moved.0, pair) && moved.1 == id);
+                    if !matches!(entry.type_, TYPE_REG | TYPE_DIR) || moving {
+                        continue;
+                    }
+                    entry.ino = self.next_ino;
+                    self.next_ino += 1;
+                    self.parents.insert(entry.ino, ino);
+                    if entry.is_dir() {
+                        todo.push((entry.ino, dir_pair(entry)?));
+                    }
+                }
+            }
+            self.dirs.insert(ino, chain);
+        }
+        Ok(())
+    }
+
+    // Returns the blocks in use by the tree and the unlinked files.
+    fn scan(&self) -> Result<Allocator, Error> {
+        let mut allocator = Allocator::new(self.disk.block_count, self.allocator_seed());
+        let entries = self.dirs.values().flatten().flat_map(|mdir| {
+            mdir.pair.iter().for_each(|&block| allocator.mark(block));
+            mdir.entries.iter()
+        });
+        let mut files = Vec::new();
+        for entry in entries.chain(self.detached.values()) {
+            if entry.type_ == TYPE_REG {
+                files.push(Data::of(entry)?);
+            }
+        }
+        for data in files {
+            for block in data.blocks(&self.disk)? {
+                allocator.mark(block);
+            }
+        }
+        Ok(allocator)
+    }
+
+    fn allocator_seed(&self) -> u32 {
+        self.dirs
+            .values()
+            .flatten()
+            .fold(0, |seed, mdir| dir::crc(seed, &mdir.rev.to_le_bytes()))
+    }
+
+    // Removes the entry left in its old place by a torn rename.
+    fn finish_move(&mut self, pair: Pair, id: usize) -> Result<(), Error> {
+        self.gstate.set_move(None);
+        let Some((dir, m)) = self.mdir_at(|mdir| pair_eq(mdir.pair, pair)) else {
+            warn!("littlefs: moved entry in unknown pair {:?}", pair);
+            return Ok(());
+        };
+        self.commit(dir, m, &[Attr::delete(id)])
+    }
+
+    // Fixes the list of pairs after a torn removal of a directory, or a
+    // torn relocation of one of its pairs.
+    fn deorphan(&mut self, thread: &[Mdir]) -> Result<(), Error> {
+        let mut current = (ROOT_INO, 0);
+        for _ in 0..2 * self.disk.block_count {
+            let Some(tail) = self.dirs[&current.0][current.1].tail else {
+                break;
+            };
+            if tail.hard {
+                if current.1 + 1 >= self.dirs[&current.0].len() {
+                    return Err(code::EILSEQ);
+                }
+                current.1 += 1;
+                continue;
+            }
+            if let Some((dir, _)) = self.mdir_at(|mdir| pair_eq(mdir.pair, tail.pair)) {
+                current = (dir, 0);
+                continue;
+            }
+            if let Some((dir, 0)) = self.mdir_at(|mdir| pair_overlaps(mdir.pair, tail.pair)) {
+                let pair = self.dirs[&dir][0].pair;
+                self.commit(current.0, current.1, &[soft_tail(pair)])?;
+                continue;
+            }
+            // A directory which was removed: skip it and its chain.
+            let mut pair = tail.pair;
+            let next = loop {
+                let orphan = match thread.iter().find(|mdir| pair_eq(mdir.pair, pair)) {
+                    Some(mdir) => mdir.clone(),
+                    None => Mdir::fetch(&self.disk, pair)?,
+                };
+                match orphan.tail {
+                    Some(tail) if tail.hard => pair = tail.pair,
+                    tail => break tail,
+                }
+            };
+            self.commit(current.0, current.1, &[Attr::tail(next)])?;
+        }
+        // The list now goes through the tree, which holds the state.
+        self.gdisk = self
+            .dirs
+            .values()
+            .flatten()
+            .fold(GState::default(), |gstate, mdir| gstate.xor(mdir.gdelta));
+        self.gstate = self.gdisk;
+        self.gstate.clear_orphans();
+        if self.gstate != self.gdisk {
+            self.commit(ROOT_INO, 0, &[])?;
+        }
+        Ok(())
+    }
+
+    // Finds the pair matching `pred`, as a directory and an index in its
+    // chain.
+    fn mdir_at(&self, pred: impl Fn(&Mdir) -> bool) -> Option<(usize, usize)> {
+        self.dirs
+            .iter()
+            .find_map(|(&dir, chain)| chain.iter().position(&pred).map(|m| (dir, m)))
+    }
+
+    // The pair before `pair` in the list of directories.
+    fn pred(&self, pair: Pair) -> Option<(usize, usize)> {
+        self.mdir_at(|mdir| {
+            mdir.tail
+                .is_some_and(|tail| !tail.hard && pair_eq(tail.pair, pair))
+        })
+    }
+
+    // Finds a live entry by name, as the index of its pair and its id.
+    fn find(&self, dir: usize, name: &[u8]) -> Option<(usize, usize)> {
+        self.find_entry(dir, |entry| entry.ino != 0 && entry.name == name)
+    }
+
+    fn find_entry(&self, dir: usize, pred: impl Fn(&Entry) -> bool) -> Option<(usize, usize)> {
+        self.dirs
+            .get(&dir)?
+            .iter()
+            .enumerate()
+            .find_map(|(m, mdir)| {
+                mdir.entries
+                    .iter()
+                    .position(|entry| matches!(entry.type_, TYPE_REG | TYPE_DIR) && pred(entry))
+                    .map(|id| (m, id))
+            })
+    }
+
+    // Where the entry of `ino` is, in its parent directory.
+    fn position(&self, ino: usize) -> Option<(usize, usize, usize)> {
+        let dir = *self.parents.get(&ino)?;
+        self.find_entry(dir, |entry| entry.ino == ino)
+            .map(|(m, id)| (dir, m, id))
+    }
+
+    fn entry(&self, ino: usize) -> Result<&Entry, Error> {
+        if let Some(entry) = self.detached.get(&ino) {
+            return Ok(entry);
+        }
+        let (dir, m, id) = self.position(ino).ok_or(code::ENOENT)?;
+        Ok(&self.dirs[&dir][m].entries[id])
+    }
+
+    /// Commits `attrs` to the pair `m` of the directory `dir`, along with
+    /// the changes to the global state.
+    fn commit(&mut self, dir: usize, m: usize, attrs: &[Attr]) -> Result<(), Error> {
+        let mdir = &self.dirs[&dir][m];
+        let gdelta =
+            (self.gstate != self.gdisk).then(|| mdir.gdelta.xor(self.gstate).xor(self.gdisk));
+        match mdir.append(&self.disk, attrs, gdelta)? {
+            Some(mdir) => self.dirs.get_mut(&dir).unwrap()[m] = mdir,
+            None => {
+                let mdir = mdir.with(attrs, gdelta);
+                self.compact(dir, m, mdir)?;
+            }
+        }
+        self.gdisk = self.gstate;
+        Ok(())
+    }
+
+    // Writes `mdir` as a whole in place of the pair `m` of `dir`. What
+    // doesn't fit is split into new pairs after it, and the pair is
+    // relocated once it has been erased `block_cycles` times, or expanded
+    // if it's the root pair.
+    fn compact(&mut self, dir: usize, m: usize, mut mdir: Mdir) -> Result<(), Error> {
+        let mut tails = Vec::new();
+        let mut end = mdir.entries.len();
+        loop {
+            let mut split = 0;
+            while end - split > 1 && !Mdir::fits(&self.disk, &mdir.entries[split..end]) {
+                split += (end - split) / 2;
+            }
+            if split == 0 {
+                break;
+            }
+            let mut tail = self.alloc_mdir()?;
+            tail.entries = mdir.entries.drain(split..end).collect();
+            tail.tail = mdir.tail;
+            if let Err(e) = self.write_new(&mut tail) {
+                self.free_mdirs(tails.iter().chain([&tail]));
+                return Err(e);
+            }
+            mdir.tail = Some(Tail {
+                hard: true,
+                pair: tail.pair,
+            });
+            tails.insert(0, tail);
+            end = split;
+        }
+
+        let old = mdir.pair;
+        let is_root = pair_eq(old, ROOT_PAIR);
+        mdir.rev = mdir.rev.wrapping_add(1);
+        let cycled = self.block_cycles > 0 && mdir.rev % ((self.block_cycles + 1) | 1) == 0;
+        if cycled && is_root {
+            match self.expand_root(&mut mdir) {
+                Ok(Some(tail)) => tails.insert(0, tail),
+                Ok(None) => {}
+                Err(e) => warn!("littlefs: failed to expand the superblock: {}", e),
+            }
+        }
+        let (mut relocate, mut relocated, mut failed) = (cycled && !is_root, false, false);
+        loop {
+            if relocate {
+                match self.allocator.alloc() {
+                    Ok(block) => {
+                        mdir.pair[1] = block;
+                        relocated = true;
+                    }
+                    // Keep erasing the pair rather than fail.
+                    Err(_) if !failed => relocate = false,
+                    Err(e) => {
+                        self.free_mdirs(&tails);
+                        return Err(e);
+                    }
+                }
+            }
+            match mdir.compact(&self.disk) {
+                Ok(()) => break,
+                Err(e) if e == code::EILSEQ && !is_root => {
+                    warn!("littlefs: block {} is worn out", mdir.pair[1]);
+                    (relocate, failed) = (true, true);
+                }
+                Err(e) => {
+                    self.free_mdirs(&tails);
+                    return Err(e);
+                }
+            }
+        }
+
+        let new = mdir.pair;
+        let chain = self.dirs.get_mut(&dir).unwrap();
+        chain[m] = mdir;
+        chain.splice(m + 1..m + 1, tails);
+        self.gdisk = self.gstate;
+        if relocated {
+            if let Some((pair, id)) = self.gstate.pending_move() {
+                if pair_eq(pair, old) {
+                    self.gstate.set_move(Some((new, id)));
+                }
+            }
+            self.relink(dir, m, old, new)?;
+            // The block replaced is free, unless it failed.
+            if !failed {
+                self.allocator.free(old[1]);
+            }
+        }
+        Ok(())
+    }
+
+    // Moves the entries of the root pair but the superblock to a new pair
+    // after it. The root pair can't be relocated, so this leaves it with
+    // the superblock only, which seldom changes. The new pair is never
+    // given back, so like the reference implementation this is only done
+    // while at most half of the volume is used.
+    fn expand_root(&mut self, root: &mut Mdir) -> Result<Option<Mdir>, Error> {
+        let used = self.disk.block_count - self.allocator.free_count();
+        let moving = self
+            .gstate
+            .pending_move()
+            .is_some_and(|(pair, _)| pair_eq(pair, root.pair));
+        if root.entries.len() < 2 || used >= self.disk.block_count / 2 || moving {
+            return Ok(None);
+        }
+        let mut tail = self.alloc_mdir()?;
+        tail.entries = root.entries.split_off(1);
+        tail.tail = root.tail;
+        if let Err(e) = self.write_new(&mut tail) {
+            self.free_mdirs([&tail]);
+            root.entries.append(&mut tail.entries);
+            return Err(e);
+        }
+        root.tail = Some(Tail {
+            hard: true,
+            pair: tail.pair,
+        });
+        Ok(Some(tail))
+    }
+
+    // Points what referred to the pair `old` of `dir` to `new`.
+    fn relink(&mut self, dir: usize, m: usize, old: Pair, new: Pair) -> Result<(), Error> {
+        if m > 0 {
+            let tail = Some(Tail {
+                hard: true,
+                pair: new,
+            });
+            return self.commit(dir, m - 1, &[Attr::tail(tail)]);
+        }
+        let (parent, pm, id) = self.position(dir).ok_or(code::EILSEQ)?;
+        let dirstruct = Attr::struct_(TYPE_DIRSTRUCT, id, &pair_bytes(new));
+        if self.pred(old) == Some((parent, pm)) {
+            return self.commit(parent, pm, &[dirstruct, soft_tail(new)]);
+        }
+        // Until the list is fixed, the pair is an orphan.
+        self.gstate.add_orphans(1);
+        self.commit(parent, pm, &[dirstruct])?;
+        self.gstate.add_orphans(-1);
+        let (pred, pm) = self.pred(old).ok_or(code::EILSEQ)?;
+        self.commit(pred, pm, &[soft_tail(new)])
+    }
+
+    // A pair of free blocks, to be written with `write_new`.
+    fn alloc_mdir(&mut self) -> Result<Mdir, Error> {
+        let first = self.allocator.alloc()?;
+        let second = self
+            .allocator
+            .alloc()
+            .inspect_err(|_| self.allocator.free(first))?;
+        // The first block isn't erased until the pair is compacted again,
+        // so the revision count must be newer than what it may hold.
+        let mut rev = [0; 4];
+        if let Err(e) = self.disk.read(first, 0, &mut rev) {
+            self.allocator.free(first);
+            self.allocator.free(second);
+            return Err(e);
+        }
+        let mut rev = u32::from_le_bytes(rev);
+        if self.block_cycles > 0 {
+            // Start a cycle, rather than relocate the pair right away.
+            let cycle = (self.block_cycles + 1) | 1;
+            rev = rev.wrapping_add(cycle - 1) / cycle * cycle;
+        }
+        Ok(Mdir::new([first, second], rev))
+    }
+
+    fn write_new(&mut self, mdir: &mut Mdir) -> Result<(), Error> {
+        mdir.rev = mdir.rev.wrapping_add(1);
+        loop {
+            match mdir.compact(&self.disk) {
+                Ok(()) => return Ok(()),
+                Err(e) if e == code::EILSEQ => mdir.pair[1] = self.allocator.alloc()?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn free_mdirs<'a>(&mut self, mdirs: impl IntoIterator<Item = &'a Mdir>) {
+        for mdir in mdirs {
+            mdir.pair
+                .iter()
+                .for_each(|&block| self.allocator.free(block));
+        }
+    }
+
+    // Drops the pairs of `dir` left empty, except the first one.
+    fn drop_empty(&mut self, dir: usize) -> Result<(), Error> {
+        while let Some(m) = self.dirs[&dir]
+            .iter()
+            .skip(1)
+            .position(|mdir| mdir.entries.is_empty())
+        {
+            let mdir = &self.dirs[&dir][m + 1];
+            let (pair, tail) = (mdir.pair, mdir.tail);
+            // Its delta leaves the global state with it.
+            let gdelta = mdir.gdelta;
+            self.gdisk = self.gdisk.xor(gdelta);
+            self.commit(dir, m, &[Attr::tail(tail)])
+                .inspect_err(|_| self.gdisk = self.gdisk.xor(gdelta))?;
+            let chain = self.dirs.get_mut(&dir).unwrap();
+            if let Some(m) = chain.iter().position(|mdir| mdir.pair == pair) {
+                let mdir = chain.remove(m);
+                self.free_mdirs([&mdir]);
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn block_size(&self) -> usize {
+        self.disk.block_size
+    }
+
+    pub(super) fn block_count(&self) -> usize {
+        self.disk.block_count as usize
+    }
+
+    pub(super) fn free_blocks(&self) -> usize {
+        self.allocator.free_count() as usize
+    }
+
+    pub(super) fn name_max(&self) -> usize {
+        self.name_max
+    }
+
+    pub(super) fn sync(&self) -> Result<(), Error> {
+        self.disk.sync()
+    }
+
+    /// Returns the inode number of the entry `name` of `dir`, and whether
+    /// it's a directory.
+    pub(super) fn lookup(&self, dir: usize, name: &str) -> Result<(usize, bool), Error> {
+        let (m, id) = self.find(dir, name.as_bytes()).ok_or(code::ENOENT)?;
+        let entry = &self.dirs[&dir][m].entries[id];
+        Ok((entry.ino, entry.is_dir()))
+    }
+
+    pub(super) fn parent(&self, ino: usize) -> usize {
+        self.parents.get(&ino).copied().unwrap_or(ROOT_INO)
+    }
+
+    /// Returns the entries of `dir`, as their inode number, whether they're
+    /// a directory and their name.
+    pub(super) fn entries(&self, dir: usize) -> Vec<(usize, bool, String)> {
+        let Some(chain) = self.dirs.get(&dir) else {
+            return Vec::new();
+        };
+        chain
+            .iter()
+            .flat_map(|mdir| mdir.entries.iter())
+            .filter(|entry| entry.ino != 0)
+            .map(|entry| {
+                let name = String::from_utf8_lossy(&entry.name).into_owned();
+                (entry.ino, entry.is_dir(), name)
+            })
+            .collect()
+    }
+
+    pub(super) fn size(&self, ino: usize) -> usize {
+        match self.entry(ino) {
+            Ok(entry) if entry.type_ == TYPE_REG => Data::of(entry).map_or(0, |data| data.size()),
+            _ => 0,
+        }
+    }
+
+    pub(super) fn read(&self, ino: usize, pos: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        Data::of(self.entry(ino)?)?.read(&self.disk, pos, buf)
+    }
+
+    pub(super) fn write(&mut self, ino: usize, pos: usize, buf: &[u8]) -> Result<usize, Error> {
+        let size = self.size(ino);
+        let end = pos.checked_add(buf.len()).ok_or(code::EFBIG)?;
+        self.rewrite(ino, size.max(end), pos, buf)?;
+        Ok(buf.len())
+    }
+
+    pub(super) fn truncate(&mut self, ino: usize, size: usize) -> Result<(), Error> {
+        let data = Data::of(self.entry(ino)?)?;
+        match data {
+            Data::Ctz { head, size: old } if size < old && size > self.inline_max => {
+                // The blocks up to the new end stay as they are.
+                let (head, _) = ctz::find(&self.disk, head, old, size - 1)?;
+                let blocks = data.blocks(&self.disk)?;
+                let data = Data::Ctz { head, size };
+                let kept = data.blocks(&self.disk)?.len();
+                self.set_data(ino, &data)?;
+                blocks[kept..]
+                    .iter()
+                    .for_each(|&block| self.allocator.free(block));
+                Ok(())
+            }
+            _ => self.rewrite(ino, size, data.size().min(size), &[]),
+        }
+    }
+
+    // Replaces the data of `ino` with `size` bytes of its current data,
+    // with `buf` written at `pos` and zeroes in the gaps.
+    fn rewrite(&mut self, ino: usize, size: usize, pos: usize, buf: &[u8]) -> Result<(), Error> {
+        if size > self.file_max {
+            return Err(code::EFBIG);
+        }
+        let old = Data::of(self.entry(ino)?)?;
+        let old_blocks = old.blocks(&self.disk)?;
+        let disk = &self.disk;
+        let mut fill = |start: usize, data: &mut [u8]| {
+            data.fill(0);
+            let old_end = old.size().min(start + data.len());
+            if start < old_end {
+                old.read(disk, start, &mut data[..old_end - start])?;
+            }
+            let from = pos.max(start);
+            let to = (pos + buf.len()).min(start + data.len());
+            if from < to {
+                data[from - start..to - start].copy_from_slice(&buf[from - pos..to - pos]);
+            }
+            Ok(())
+        };
+
+        let (new, written, kept) = if size <= self.inline_max {
+            let mut data = vec![0; size];
+            fill(0, &mut data)?;
+            (Data::Inline(data), Vec::new(), 0)
+        } else {
+            // Blocks before the first one written are kept.
+            let kept = match old {
+                Data::Ctz { size: old_size, .. } => {
+                    ctz::index(disk.block_size, pos.min(old_size)).0
+                }
+                Data::Inline(_) => 0,
+            };
+            let written = ctz::write(disk, &mut self.allocator, &old_blocks[..kept], size, fill)?;
+            let head = *written.last().unwrap_or_else(|| &old_blocks[kept - 1]);
+            (Data::Ctz { head, size }, written, kept)
+        };
+        if let Err(e) = self.set_data(ino, &new) {
+            written.iter().for_each(|&block| self.allocator.free(block));
+            return Err(e);
+        }
+        old_blocks[kept..]
+            .iter()
+            .for_each(|&block| self.allocator.free(block));
+        Ok(())
+    }
+
+    fn set_data(&mut self, ino: usize, data: &Data) -> Result<(), Error> {
+        let (type_, bytes) = match data {
+            Data::Inline(data) => (TYPE_INLINESTRUCT, data.clone()),
+            &Data::Ctz { head, size } => (TYPE_CTZSTRUCT, pair_bytes([head, size as u32])),
+        };
+        if let Some(entry) = self.detached.get_mut(&ino) {
+            entry.struct_type = type_;
+            entry.struct_data = bytes;
+            return Ok(());
+        }
+        let (dir, m, id) = self.position(ino).ok_or(code::ENOENT)?;
+        self.commit(dir, m, &[Attr::struct_(type_, id, &bytes)])
+    }
+
+    /// Creates the empty file or directory `name` in `dir`. Returns its
+    /// inode number.
+    pub(super) fn create(&mut self, dir: usize, name: &str, is_dir: bool) -> Result<usize, Error> {
+        if name.len() > self.name_max {
+            return Err(code::ENAMETOOLONG);
+        }
+        let chain = self.dirs.get(&dir).ok_or(code::ENOENT)?;
+        if self.find(dir, name.as_bytes()).is_some() {
+            return Err(code::EEXIST);
+        }
+        // New entries go to the last pair, and new directories follow it
+        // in the list.
+        let m = chain.len() - 1;
+        let id = chain[m].entries.len();
+        let tail = chain[m].tail;
+        let mut attrs = vec![Attr::create(id)];
+        let child = if is_dir {
+            let mut child = self.alloc_mdir()?;
+            child.tail = tail;
+            if let Err(e) = self.write_new(&mut child) {
+                self.free_mdirs([&child]);
+                return Err(e);
+            }
+            attrs.push(Attr::name(TYPE_DIR, id, name.as_bytes()));
+            attrs.push(Attr::struct_(TYPE_DIRSTRUCT, id, &pair_bytes(child.pair)));
+            attrs.push(soft_tail(child.pair));
+            Some(child)
+        } else {
+            attrs.push(Attr::name(TYPE_REG, id, name.as_bytes()));
+            attrs.push(Attr::struct_(TYPE_INLINESTRUCT, id, &[]));
+            None
+        };
+        if let Err(e) = self.commit(dir, m, &attrs) {
+            if let Some(child) = child {
+                self.free_mdirs([&child]);
+            }
+            return Err(e);
+        }
+
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.adopt(dir, name.as_bytes(), ino);
+        if let Some(child) = child {
+            self.dirs.insert(ino, vec![child]);
+        }
+        Ok(ino)
+    }
+
+    // Numbers the entry `name` just committed to `dir`.
+    fn adopt(&mut self, dir: usize, name: &[u8], ino: usize) {
+        if let Some((m, id)) = self.find_entry(dir, |entry| entry.ino == 0 && entry.name == name) {
+            self.dirs.get_mut(&dir).unwrap()[m].entries[id].ino = ino;
+        }
+        self.parents.insert(ino, dir);
+    }
+
+    /// Removes the file or the empty directory `name` from `dir`. Returns
+    /// its inode number. The blocks of a file are kept until it's evicted.
+    pub(super) fn remove(&mut self, dir: usize, name: &str, is_dir: bool) -> Result<usize, Error> {
+        let (m, id) = self.find(dir, name.as_bytes()).ok_or(code::ENOENT)?;
+        let entry = self.dirs[&dir][m].entries[id].clone();
+        match (entry.is_dir(), is_dir) {
+            (true, false) => return Err(code::EISDIR),
+            (false, true) => return Err(code::ENOTDIR),
+            _ => {}
+        }
+        let ino = entry.ino;
+        if !is_dir {
+            self.commit(dir, m, &[Attr::delete(id)])?;
+            self.parents.remove(&ino);
+            self.detached.insert(ino, entry);
+            self.drop_empty(dir)?;
+            return Ok(ino);
+        }
+
+        if !self.is_empty_dir(ino) {
+            return Err(code::ENOTEMPTY);
+        }
+        let chain = &self.dirs[&ino];
+        let head = chain[0].pair;
+        let tail = chain.last().unwrap().tail;
+        let gdelta = chain
+            .iter()
+            .fold(GState::default(), |gstate, mdir| gstate.xor(mdir.gdelta));
+        // The directory leaves the list along with its delta.
+        if self.pred(head) == Some((dir, m)) {
+            self.gdisk = self.gdisk.xor(gdelta);
+            self.commit(dir, m, &[Attr::delete(id), Attr::tail(tail)])
+                .inspect_err(|_| self.gdisk = self.gdisk.xor(gdelta))?;
+            let chain = self.dirs.remove(&ino).unwrap();
+            self.free_mdirs(&chain);
+            self.parents.remove(&ino);
+        } else {
+            self.gstate.add_orphans(1);
+            self.commit(dir, m, &[Attr::delete(id)])
+                .inspect_err(|_| self.gstate.add_orphans(-1))?;
+            self.drop_orphan_dir(ino)?;
+        }
+        self.drop_empty(dir)?;
+        Ok(ino)
+    }
+
+    fn is_empty_dir(&self, ino: usize) -> bool {
+        self.dirs[&ino].iter().all(|mdir| mdir.entries.is_empty())
+    }
+
+    // Takes the pairs of the directory `ino`, whose entry has been deleted
+    // with an orphan counted in the global state, out of the list and
+    // frees them.
+    fn drop_orphan_dir(&mut self, ino: usize) -> Result<(), Error> {
+        let chain = &self.dirs[&ino];
+        let head = chain[0].pair;
+        let tail = chain.last().unwrap().tail;
+        let gdelta = chain
+            .iter()
+            .fold(GState::default(), |gstate, mdir| gstate.xor(mdir.gdelta));
+        self.gstate.add_orphans(-1);
+        let (pred, pm) = self.pred(head).ok_or(code::EILSEQ)?;
+        self.gdisk = self.gdisk.xor(gdelta);
+        self.commit(pred, pm, &[Attr::tail(tail)])?;
+        let chain = self.dirs.remove(&ino).unwrap();
+        self.free_mdirs(&chain);
+        self.parents.remove(&ino);
+        Ok(())
+    }
+
+    /// Moves the entry `name` of `dir` to `new_name` in `new_dir`. An entry
+    /// already at `new_name` is replaced by the commit which creates the
+    /// new one, and its inode number is returned. The blocks of a replaced
+    /// file are kept until it's evicted.
+    pub(super) fn rename(
+        &mut self,
+        dir: usize,
+        name: &str,
+        new_dir: usize,
+        new_name: &str,
+    ) -> Result<Option<usize>, Error> {
+        if new_name.len() > self.name_max {
+            return Err(code::ENAMETOOLONG);
+        }
+        let (m, id) = self.find(dir, name.as_bytes()).ok_or(code::ENOENT)?;
+        let chain = self.dirs.get(&new_dir).ok_or(code::ENOENT)?;
+        let entry = self.dirs[&dir][m].entries[id].clone();
+        let replaced = match self.find(new_dir, new_name.as_bytes()) {
+            Some((new_m, new_id)) => {
+                let old = chain[new_m].entries[new_id].clone();
+                if old.ino == entry.ino {
+                    return Ok(None);
+                }
+                match (entry.is_dir(), old.is_dir()) {
+                    (true, false) => return Err(code::ENOTDIR),
+                    (false, true) => return Err(code::EISDIR),
+                    (true, true) if !self.is_empty_dir(old.ino) => return Err(code::ENOTEMPTY),
+                    _ => {}
+                }
+                Some((new_m, new_id, old))
+            }
+            None => None,
+        };
+        let mut moved = entry.clone();
+        moved.name = Vec::from(new_name.as_bytes());
+
+        let Some((new_m, new_id, old)) = replaced else {
+            if dir == new_dir {
+                self.commit(dir, m, &[Attr::name(entry.type_, id, new_name.as_bytes())])?;
+                return Ok(None);
+            }
+            // The entry is created in its new directory along with a move
+            // in the global state, which tells where to remove it from
+            // should the removal be lost.
+            let new_m = chain.len() - 1;
+            let new_id = chain[new_m].entries.len();
+            let mut attrs = vec![Attr::create(new_id)];
+            attrs.extend(moved.attrs(new_id));
+            self.move_entry(dir, (m, id), new_dir, new_m, new_name, &attrs)?;
+            return Ok(None);
+        };
+
+        // The replaced entry is deleted by the commit which creates the
+        // new one in its place. A replaced directory is an orphan until
+        // its pairs are out of the list.
+        let mut attrs = vec![Attr::delete(new_id), Attr::create(new_id)];
+        attrs.extend(moved.attrs(new_id));
+        if old.is_dir() {
+            self.gstate.add_orphans(1);
+        }
+        let moved = if dir == new_dir && new_m == m {
+            // Both entries are in the same pair, so one commit does it all.
+            attrs.push(Attr::delete(id));
+            self.commit(dir, m, &attrs).map(|_| {
+                self.adopt(dir, new_name.as_bytes(), entry.ino);
+            })
+        } else {
+            self.move_entry(dir, (m, id), new_dir, new_m, new_name, &attrs)
+        };
+        if let Err(e) = moved {
+            if old.is_dir() {
+                self.gstate.add_orphans(-1);
+            }
+            return Err(e);
+        }
+        let ino = old.ino;
+        if old.is_dir() {
+            self.drop_orphan_dir(ino)?;
+        } else {
+            self.parents.remove(&ino);
+            self.detached.insert(ino, old);
+        }
+        Ok(Some(ino))
+    }
+
+    // Commits `attrs`, which create the entry at `at` in `dir` as
+    // `new_name` in the pair `new_m` of `new_dir`, then removes it from its
+    // old place. The global state tells where to remove it from should the
+    // removal be lost.
+    fn move_entry(
+        &mut self,
+        dir: usize,
+        (m, id): (usize, usize),
+        new_dir: usize,
+        new_m: usize,
+        new_name: &str,
+        attrs: &[Attr],
+    ) -> Result<(), Error> {
+        let entry = self.dirs[&dir][m].entries[id].clone();
+        self.gstate.set_move(Some((self.dirs[&dir][m].pair, id)));
+        if let Err(e) = self.commit(new_dir, new_m, attrs) {
+            self.gstate.set_move(None);
+            return Err(e);
+        }
+        self.adopt(new_dir, new_name.as_bytes(), entry.ino);
+
+        self.gstate.set_move(None);
+        // Both entries have the same number if they're in the same
+        // directory, but not the same name.
+        let (m, id) = self
+            .find_entry(dir, |old| old.ino == entry.ino && old.name == entry.name)
+            .ok_or(code::EILSEQ)?;
+        self.commit(dir, m, &[Attr::delete(id)])?;
+        self.drop_empty(dir)
+    }
+
+    /// Frees the blocks of a file which has been removed.
+    pub(super) fn evict(&mut self, ino: usize) -> Result<(), Error> {
+        let Some(entry) = self.detached.remove(&ino) else {
+            return Ok(());
+        };
+        for block in Data::of(&entry)?.blocks(&self.disk)? {
+            self.allocator.free(block);
+        }
+        Ok(())
+    }
+}
+
+fn soft_tail(pair: Pair) -> Attr {
+    Attr::tail(Some(Tail { hard: false, pair }))
+}
+
+fn dir_pair(entry: &Entry) -> Result<Pair, Error> {
+    if entry.struct_type != TYPE_DIRSTRUCT || entry.struct_data.len() < 8 {
+        return Err(code::EILSEQ);
+    }
+    Ok([le32(&entry.struct_data), le32(&entry.struct_data[4..])])
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Memory mapped files.
+//!
+//! There is a single address space, so like on no-MMU Linux a mapping is a
+//! page aligned kernel buffer filled from the file. The `MAP_SHARED`
+//! mappings of a file share its pages in the [page cache], so they see
+//! each other's stores as well as the file's reads and writes.
+//!
+//! The protection flags aren't enforced, and a mapping can only be removed
+//! as a whole.
+//!
+//! [page cache]: super::page_cache
+
+use crate::{
+    allocator::page::PAGE_SIZE,
+    error::{code, Error},
+    sync::SpinLock,
+    vfs::{
+        file::{File, FileOps},
+        inode::InodeOps,
+        inode_mode::InodeFileType,
+        page_cache::{self, PageBuf},
+    },
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::ops::Range;
+use log::warn;
+
+enum Backing {
+    /// Anonymous and `MAP_PRIVATE` mappings have pages of their own.
+    Private(PageBuf),
+    /// Pages of a file in the page cache
+    Shared {
+        inode: Arc<dyn InodeOps>,
+        pages: Range<usize>,
+    },
+}
+
+struct Mapping {
+    backing: Backing,
+    // The files mapped here and whether they were mapped writable, one per
+    // mmap since shared pages are mapped at the same address each time.
+    // They are kept open while mapped, even once their descriptors are
+    // closed.
+    files: Vec<(Option<Arc<dyn FileOps>>, bool)>,
+}
+
+// Mappings by address and length.
+static MAPPINGS: SpinLock<BTreeMap<(usize, usize), Mapping>> = SpinLock::new(BTreeMap::new());
+
+/// Maps `len` bytes of `file` from `offset`, which must be page aligned,
+/// or zeroed memory if `file` is None. Returns the address of the mapping.
+pub fn mmap(
+    file: Option<Arc<dyn FileOps>>,
+    len: usize,
+    offset: usize,
+    shared: bool,
+    writable: bool,
+) -> Result<usize, Error> {
+    if len == 0 || offset % PAGE_SIZE != 0 {
+        return Err(code::EINVAL);
+    }
+    let len = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(code::ENOMEM)?;
+
+    let buf = match &file {
+        None => PageBuf::new(len)?,
+        Some(file_ops) => {
+            let file = file_ops.downcast_ref::<File>().ok_or(code::ENODEV)?;
+            if file.type_() != InodeFileType::Regular {
+                return Err(code::ENODEV);
+            }
+            if !file.access_mode().is_readable() {
+                return Err(code::EACCES);
+            }
+            if shared && writable && !file.access_mode().is_writable() {
+                return Err(code::EACCES);
+            }
+            let dcache = file.dcache();
+            let inode = dcache.inode();
+            if shared {
+                let first = offset / PAGE_SIZE;
+                let pages = first..first + len / PAGE_SIZE;
+                let addr = page_cache::map(inode, pages.clone(), writable)?;
+                MAPPINGS
+                    .lock()
+                    .entry((addr, len))
+                    .or_insert_with(|| Mapping {
+                        backing: Backing::Shared {
+                            inode: inode.clone(),
+                            pages,
+                        },
+                        files: Vec::new(),
+                    })
+                    .files
+                    .push((Some(file_ops.clone()), writable));
+                return Ok(addr);
+            }
+            let buf = PageBuf::new(len)?;
+            buf.read(inode, offset)?;
+            buf
+        }
+    };
+
+    let addr = buf.addr();
+    MAPPINGS.lock().insert(
+        (addr, len),
+        Mapping {
+            backing: Backing::Private(buf),
+            files: vec![(file, writable)],
+        },
+    );
+    Ok(addr)
+}
+
+/// Removes the mapping at `addr`. Shared pages which are no longer mapped
+/// are written back if they're dirty.
+pub fn munmap(addr: usize, len: usize) -> Result<(), Error> {
+    if len == 0 {
+        return Err(code::EINVAL);
+    }
+    let key = (addr, len.next_multiple_of(PAGE_SIZE));
+    let (shared, file) = {
+        let mut mappings = MAPPINGS.lock();
+        let Some(mapping) = mappings.get_mut(&key) else {
+            if let Some((&(start, len), _)) = mappings.range(..=(addr, usize::MAX)).next_back() {
+                if addr < start + len {
+                    warn!("munmap: only whole mappings can be removed");
+                }
+            }
+            return Err(code::EINVAL);
+        };
+        let (file, writable) = mapping.files.pop().unwrap();
+        let shared = match &mapping.backing {
+            Backing::Shared { inode, pages } => Some((inode.clone(), pages.clone(), writable)),
+            Backing::Private(_) => None,
+        };
+        if mapping.files.is_empty() {
+            mappings.remove(&key);
+        }
+        (shared, file)
+    };
+    if let Some((inode, pages, writable)) = shared {
+        if let Err(e) = page_cache::unmap(&inode, pages, writable) {
+            warn!("munmap: failed to write back {:#x}: {}", addr, e);
+        }
+    }
+    // Closed once written back.
+    drop(file);
+    Ok(())
+}
+
+/// Writes the dirty pages of the shared mapping holding `addr..addr + len`
+/// back to the file. Returns the number of pages written.
+pub fn msync(addr: usize, len: usize) -> Result<usize, Error> {
+    if addr % PAGE_SIZE != 0 {
+        return Err(code::EINVAL);
+    }
+    let end = addr.checked_add(len).ok_or(code::ENOMEM)?;
+    let range = {
+        let mappings = MAPPINGS.lock();
+        let Some((&(start, _), mapping)) = mappings
+            .range(..=(addr, usize::MAX))
+            .rev()
+            .find(|(&(start, len), _)| end <= start + len)
+        else {
+            return Err(code::ENOMEM);
+        };
+        match &mapping.backing {
+            Backing::Shared { inode, pages } => {
+                let first = pages.start + (addr - start) / PAGE_SIZE;
+                let last = pages.start + (end - start).div_ceil(PAGE_SIZE);
+                Some((inode.clone(), first..last))
+            }
+            Backing::Private(_) => None,
+        }
+    };
+    match range {
+        Some((inode, pages)) => page_cache::sync(&inode, pages),
+        None => Ok(0),
+    }
+}
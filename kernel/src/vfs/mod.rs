@@ -27,16 +27,28 @@ use log::{debug, error, warn};
 
 mod dcache;
 mod devfs;
+pub(crate) use devfs::{device_registered, device_unregistered};
 pub mod dirent;
+pub mod epoll;
+#[cfg(kvstore)]
+mod etcfs;
 #[cfg(virtio)]
 mod fatfs;
 mod fd_manager;
 mod file;
 mod fs;
+pub mod fsck;
 mod inode;
 mod inode_mode;
+#[cfg(littlefs)]
+pub mod littlefs;
+mod mmap;
 mod mount;
+pub mod orphan;
+mod page_cache;
 mod path;
+mod pipe;
+pub mod poll;
 #[cfg(procfs)]
 mod procfs;
 #[cfg(procfs)]
@@ -121,6 +133,21 @@ pub fn vfs_init() -> Result<(), Error> {
         debug!("Mounted procfs at '/proc'");
     }
 
+    #[cfg(kvstore)]
+    {
+        let etc_name = String::from("etc");
+        let etcfs = etcfs::EtcFileSystem::new();
+        cwd.new_child(
+            etc_name.as_str(),
+            InodeFileType::Directory,
+            InodeMode::from(0o755),
+            || None,
+        )?;
+        let etcfs_mount_point = Dcache::new(etcfs.root_inode(), etc_name, cwd.get_weak_ref());
+        etcfs_mount_point.mount(etcfs)?;
+        debug!("Mounted etcfs at '/etc'");
+    }
+
     debug!("VFS initialized successfully");
     Ok(())
 }
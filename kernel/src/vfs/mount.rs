@@ -14,11 +14,17 @@
 
 #[cfg(virtio)]
 use crate::vfs::fatfs::FatFileSystem;
+#[cfg(littlefs)]
+use crate::vfs::littlefs::{LittleFileSystem, LittlefsOptions};
 #[cfg(procfs)]
 use crate::vfs::procfs::ProcFileSystem;
 use crate::{
     error::{code, Error},
-    vfs::{dcache::Dcache, fs::FileSystem, tmpfs::TmpFileSystem},
+    vfs::{
+        dcache::Dcache,
+        fs::FileSystem,
+        tmpfs::{TmpFileSystem, TmpfsLimits},
+    },
 };
 
 use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
@@ -91,9 +97,15 @@ pub fn get_mount_manager() -> &'static MountManager {
     MOUNT_MANAGER.call_once(MountManager::new)
 }
 
-pub fn get_fs(fs_type: &str, device: &str) -> Option<Arc<dyn FileSystem>> {
+pub fn get_fs(fs_type: &str, device: &str, data: &str) -> Option<Arc<dyn FileSystem>> {
     match fs_type {
-        "tmpfs" => Some(TmpFileSystem::new()),
+        "tmpfs" => match TmpfsLimits::parse(data) {
+            Ok(limits) => Some(TmpFileSystem::with_limits(limits)),
+            Err(error) => {
+                error!("Invalid tmpfs options {}, {}", data, error);
+                None
+            }
+        },
         #[cfg(virtio)]
         "fatfs" => match FatFileSystem::new(device) {
             Ok(fs) => Some(fs),
@@ -105,6 +117,32 @@ pub fn get_fs(fs_type: &str, device: &str) -> Option<Arc<dyn FileSystem>> {
                 None
             }
         },
+        // Volumes formatted elsewhere, such as on SD cards, are never
+        // formatted over.
+        #[cfg(virtio)]
+        "vfat" => match FatFileSystem::open_existing(device) {
+            Ok(fs) => Some(fs),
+            Err(error) => {
+                error!(
+                    "Fail to open vfat file system on device {}, {}",
+                    device, error
+                );
+                None
+            }
+        },
+        #[cfg(littlefs)]
+        "littlefs" => match LittlefsOptions::parse(data)
+            .and_then(|options| LittleFileSystem::open(device, options))
+        {
+            Ok(fs) => Some(fs),
+            Err(error) => {
+                error!(
+                    "Fail to mount littlefs on device {} with options {}, {}",
+                    device, data, error
+                );
+                None
+            }
+        },
         _ => None,
     }
 }
@@ -0,0 +1,81 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inodes which are unlinked while open.
+//!
+//! Removing the last link of an inode only removes its name. Its data is
+//! freed by [`InodeOps::evict`] once no [`File`](super::file::File) has it
+//! open, so that the open files keep working until they're closed. Until
+//! then, the inode is an orphan.
+
+use crate::{error::Error, sync::SpinLock, vfs::inode::InodeOps};
+use alloc::{collections::BTreeMap, sync::Arc};
+
+#[derive(Default)]
+struct Entry {
+    open: usize,
+    orphan: bool,
+}
+
+// Inodes with open files, by address.
+static OPEN: SpinLock<BTreeMap<usize, Entry>> = SpinLock::new(BTreeMap::new());
+
+fn key(inode: &Arc<dyn InodeOps>) -> usize {
+    Arc::as_ptr(inode) as *const () as usize
+}
+
+/// Counts a new open file of `inode`.
+pub(crate) fn open(inode: &Arc<dyn InodeOps>) {
+    OPEN.lock().entry(key(inode)).or_default().open += 1;
+}
+
+/// Counts an open file of `inode` as closed, evicting the inode if it was
+/// the last one and the inode is an orphan.
+pub(crate) fn close(inode: &Arc<dyn InodeOps>) -> Result<(), Error> {
+    let evict = {
+        let mut open = OPEN.lock();
+        let Some(entry) = open.get_mut(&key(inode)) else {
+            return Ok(());
+        };
+        entry.open -= 1;
+        if entry.open > 0 {
+            return Ok(());
+        }
+        open.remove(&key(inode)).is_some_and(|entry| entry.orphan)
+    };
+    if evict {
+        inode.evict()?;
+    }
+    Ok(())
+}
+
+/// Called once a link of `inode` has been removed. The inode is evicted
+/// right away if it has no links left and isn't open, or once its last
+/// open file is closed otherwise.
+pub(crate) fn unlinked(inode: &Arc<dyn InodeOps>) -> Result<(), Error> {
+    if inode.inode_attr().nlinks > 0 {
+        return Ok(());
+    }
+    if let Some(entry) = OPEN.lock().get_mut(&key(inode)) {
+        entry.orphan = true;
+        return Ok(());
+    }
+    inode.evict()
+}
+
+/// Returns the number of orphans, which are waiting for their open files
+/// to be closed.
+pub fn count() -> usize {
+    OPEN.lock().values().filter(|entry| entry.orphan).count()
+}
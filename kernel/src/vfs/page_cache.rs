@@ -0,0 +1,381 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Page cache of the regular files mapped with `MAP_SHARED`.
+//!
+//! Pages are cached per inode and indexed by their page number in the
+//! file, for as long as a mapping has them. Reads and writes of a file go
+//! through its cached pages, so that they see the stores to the mappings
+//! and the mappings see what's written.
+//!
+//! A page is dirty once a write has changed it. Stores to a mapping can't
+//! be trapped, so a page mapped writable counts as dirty for as long as it
+//! is mapped so. Dirty pages are written back by [`sync`], and by
+//! [`unmap`] before the last mapping of a page lets it go.
+
+use crate::{
+    allocator::page::{self, PAGE_SIZE},
+    error::{code, Error},
+    sync::SpinLock,
+    vfs::inode::InodeOps,
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{ops::Range, ptr::NonNull, slice};
+
+/// A zero filled, page aligned kernel buffer
+pub(super) struct PageBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// The buffer is only reached through its address by whoever mapped it.
+unsafe impl Send for PageBuf {}
+unsafe impl Sync for PageBuf {}
+
+impl PageBuf {
+    #[track_caller]
+    pub(super) fn new(len: usize) -> Result<Self, Error> {
+        let ptr = NonNull::new(page::alloc_pages(len / PAGE_SIZE, "mmap")).ok_or(code::ENOMEM)?;
+        Ok(Self { ptr, len })
+    }
+
+    pub(super) fn addr(&self) -> usize {
+        self.ptr.as_ptr() as usize
+    }
+
+    fn page(&self, index: usize) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr().add(index * PAGE_SIZE), PAGE_SIZE) }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn page_mut(&self, index: usize) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr().add(index * PAGE_SIZE), PAGE_SIZE) }
+    }
+
+    /// Fills the buffer from `inode`, starting at `offset`. What's past the
+    /// end of the file is left zeroed.
+    pub(super) fn read(&self, inode: &Arc<dyn InodeOps>, offset: usize) -> Result<(), Error> {
+        for index in 0..self.len / PAGE_SIZE {
+            let page = self.page_mut(index);
+            let mut read = 0;
+            while read < PAGE_SIZE {
+                let n =
+                    inode.read_at(offset + index * PAGE_SIZE + read, &mut page[read..], false)?;
+                if n == 0 {
+                    break;
+                }
+                read += n;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PageBuf {
+    fn drop(&mut self) {
+        page::free_pages(self.ptr.as_ptr());
+    }
+}
+
+/// A cached page, which is a page of the buffer of the mapping which read
+/// it in.
+struct Page {
+    buf: Arc<PageBuf>,
+    index: usize,
+    dirty: bool,
+    // Mappings having the page, and those of them which are writable.
+    users: usize,
+    writers: usize,
+}
+
+impl Page {
+    fn addr(&self) -> usize {
+        self.buf.addr() + self.index * PAGE_SIZE
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty || self.writers > 0
+    }
+}
+
+struct InodeCache {
+    inode: Arc<dyn InodeOps>,
+    pages: BTreeMap<usize, Page>,
+}
+
+// Cached pages, by inode address.
+static CACHES: SpinLock<BTreeMap<usize, InodeCache>> = SpinLock::new(BTreeMap::new());
+
+fn inode_key(inode: &Arc<dyn InodeOps>) -> usize {
+    Arc::as_ptr(inode) as *const () as usize
+}
+
+// Returns the address of the pages in `pages` if they're all cached and
+// contiguous, None if none of them is cached, and fails otherwise.
+fn find_mapped(cache: Option<&InodeCache>, pages: &Range<usize>) -> Result<Option<usize>, Error> {
+    let Some(cache) = cache else {
+        return Ok(None);
+    };
+    let Some(first) = cache.pages.get(&pages.start) else {
+        return match cache.pages.range(pages.clone()).next() {
+            // Another mapping overlaps these pages.
+            Some(_) => Err(code::EINVAL),
+            None => Ok(None),
+        };
+    };
+    let addr = first.addr();
+    for index in pages.clone() {
+        match cache.pages.get(&index) {
+            Some(page) if page.addr() == addr + (index - pages.start) * PAGE_SIZE => {}
+            _ => return Err(code::EINVAL),
+        }
+    }
+    Ok(Some(addr))
+}
+
+// Counts a new mapping of `pages`, which are all cached.
+fn add_users(cache: &mut InodeCache, pages: Range<usize>, writable: bool) {
+    for (_, page) in cache.pages.range_mut(pages) {
+        page.users += 1;
+        if writable {
+            page.writers += 1;
+        }
+    }
+}
+
+/// Maps `pages` of `inode`, reading them in if they aren't cached yet.
+/// Returns their address, which is the same for every mapping of a page. A
+/// range which overlaps a mapped one, without being part of it, can't be
+/// mapped.
+pub(super) fn map(
+    inode: &Arc<dyn InodeOps>,
+    pages: Range<usize>,
+    writable: bool,
+) -> Result<usize, Error> {
+    let key = inode_key(inode);
+    {
+        let mut caches = CACHES.lock();
+        if let Some(addr) = find_mapped(caches.get(&key), &pages)? {
+            add_users(caches.get_mut(&key).unwrap(), pages, writable);
+            return Ok(addr);
+        }
+    }
+
+    // Read without holding the cache, and use what another mapping may
+    // have read meanwhile.
+    let buf = PageBuf::new(pages.len() * PAGE_SIZE)?;
+    buf.read(inode, pages.start * PAGE_SIZE)?;
+    let buf = Arc::new(buf);
+    let mut caches = CACHES.lock();
+    if let Some(addr) = find_mapped(caches.get(&key), &pages)? {
+        add_users(caches.get_mut(&key).unwrap(), pages, writable);
+        return Ok(addr);
+    }
+    let cache = caches.entry(key).or_insert_with(|| InodeCache {
+        inode: inode.clone(),
+        pages: BTreeMap::new(),
+    });
+    for (index, page) in pages.enumerate() {
+        cache.pages.insert(
+            page,
+            Page {
+                buf: buf.clone(),
+                index,
+                dirty: false,
+                users: 1,
+                writers: writable as usize,
+            },
+        );
+    }
+    Ok(buf.addr())
+}
+
+// Writes the dirty pages of `inode` which `filter` picks back, leaving out
+// whatever is past the end of the file. Returns the number of pages
+// written.
+fn write_back(
+    inode: &Arc<dyn InodeOps>,
+    pages: Range<usize>,
+    filter: impl Fn(&Page) -> bool,
+) -> Result<usize, Error> {
+    let key = inode_key(inode);
+    // Marked clean before being written, so that a write meanwhile makes
+    // them dirty again.
+    let dirty: Vec<(usize, Arc<PageBuf>, usize)> = {
+        let mut caches = CACHES.lock();
+        let Some(cache) = caches.get_mut(&key) else {
+            return Ok(0);
+        };
+        cache
+            .pages
+            .range_mut(pages)
+            .filter(|(_, page)| page.is_dirty() && filter(page))
+            .map(|(&index, page)| {
+                page.dirty = false;
+                (index, page.buf.clone(), page.index)
+            })
+            .collect()
+    };
+    let size = inode.size();
+    let mut written = 0;
+    for (i, (index, buf, buf_index)) in dirty.iter().enumerate() {
+        let pos = index * PAGE_SIZE;
+        if pos >= size {
+            continue;
+        }
+        let len = PAGE_SIZE.min(size - pos);
+        if let Err(e) = inode.write_at(pos, &buf.page(*buf_index)[..len], false) {
+            // What wasn't written is still dirty.
+            if let Some(cache) = CACHES.lock().get_mut(&key) {
+                for (index, _, _) in &dirty[i..] {
+                    if let Some(page) = cache.pages.get_mut(index) {
+                        page.dirty = true;
+                    }
+                }
+            }
+            return Err(e);
+        }
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Writes the dirty pages among `pages` of `inode` back to the file.
+/// Returns the number of pages written.
+pub(super) fn sync(inode: &Arc<dyn InodeOps>, pages: Range<usize>) -> Result<usize, Error> {
+    write_back(inode, pages, |_| true)
+}
+
+/// Counts a mapping of `pages` of `inode` as removed. Pages which are no
+/// longer mapped are written back if they're dirty, and leave the cache.
+pub(super) fn unmap(
+    inode: &Arc<dyn InodeOps>,
+    pages: Range<usize>,
+    writable: bool,
+) -> Result<(), Error> {
+    // Write back while still mapped, so that a new mapping of the pages
+    // either shares them or reads what was written.
+    let written = write_back(inode, pages.clone(), |page| page.users == 1);
+
+    let key = inode_key(inode);
+    let mut caches = CACHES.lock();
+    let Some(cache) = caches.get_mut(&key) else {
+        return written.map(|_| ());
+    };
+    for index in pages {
+        let Some(page) = cache.pages.get_mut(&index) else {
+            continue;
+        };
+        page.users -= 1;
+        if writable {
+            page.writers -= 1;
+        }
+        if page.users == 0 {
+            cache.pages.remove(&index);
+        }
+    }
+    if cache.pages.is_empty() {
+        caches.remove(&key);
+    }
+    written.map(|_| ())
+}
+
+/// Reads from `inode` at `pos`, taking the pages which are cached from the
+/// cache.
+pub(super) fn read(
+    inode: &Arc<dyn InodeOps>,
+    pos: usize,
+    buf: &mut [u8],
+    nonblock: bool,
+) -> Result<usize, Error> {
+    let key = inode_key(inode);
+    if !CACHES.lock().contains_key(&key) {
+        return inode.read_at(pos, buf, nonblock);
+    }
+    let size = inode.size();
+    let mut done = 0;
+    while done < buf.len() {
+        let pos = pos + done;
+        let (index, offset) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (buf.len() - done).min(PAGE_SIZE - offset);
+        let chunk = &mut buf[done..done + len];
+        let cached = {
+            let caches = CACHES.lock();
+            match caches.get(&key).and_then(|cache| cache.pages.get(&index)) {
+                Some(page) => {
+                    let len = len.min(size.saturating_sub(pos));
+                    chunk[..len].copy_from_slice(&page.buf.page(page.index)[offset..offset + len]);
+                    Some(len)
+                }
+                None => None,
+            }
+        };
+        let n = match cached {
+            Some(n) => n,
+            None => inode.read_at(pos, chunk, nonblock)?,
+        };
+        done += n;
+        if n < len {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// Writes to `inode` at `pos`. Pages which are cached take the data and
+/// become dirty, unless it goes past the end of the file, which the file
+/// must then grow to.
+pub(super) fn write(
+    inode: &Arc<dyn InodeOps>,
+    pos: usize,
+    buf: &[u8],
+    nonblock: bool,
+) -> Result<usize, Error> {
+    let key = inode_key(inode);
+    if !CACHES.lock().contains_key(&key) {
+        return inode.write_at(pos, buf, nonblock);
+    }
+    let mut done = 0;
+    while done < buf.len() {
+        let pos = pos + done;
+        let (index, offset) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+        let len = (buf.len() - done).min(PAGE_SIZE - offset);
+        let chunk = &buf[done..done + len];
+        let grows = pos + len > inode.size();
+        let cached = {
+            let mut caches = CACHES.lock();
+            match caches
+                .get_mut(&key)
+                .and_then(|cache| cache.pages.get_mut(&index))
+            {
+                Some(page) => {
+                    page.buf.page_mut(page.index)[offset..offset + len].copy_from_slice(chunk);
+                    page.dirty |= !grows;
+                    true
+                }
+                None => false,
+            }
+        };
+        let n = if cached && !grows {
+            len
+        } else {
+            inode.write_at(pos, chunk, nonblock)?
+        };
+        done += n;
+        if n < len {
+            break;
+        }
+    }
+    Ok(done)
+}
@@ -17,33 +17,88 @@ use crate::{
     error::{code, Error},
     vfs::{
         dcache::Dcache,
-        file::{AccessMode, File, OpenFlags},
+        file::{AccessMode, File, FileOps, OpenFlags},
         inode_mode::{mode_t, InodeFileType, InodeMode},
+        pipe,
         root::get_root_dir,
     },
 };
-use alloc::{string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 use spin::{Mutex as SpinMutex, Once};
 
-// FIXME: move WORKING_DIR to FsEnv
-static WORKING_DIR: Once<SpinMutex<Arc<Dcache>>> = Once::new();
+/// Maximum number of symbolic links followed while resolving one path
+const MAX_SYMLINKS: usize = 40;
 
-pub fn get_working_dir() -> Arc<Dcache> {
-    WORKING_DIR
-        .call_once(|| {
-            let dir: &'static Arc<Dcache> = get_root_dir();
-            SpinMutex::new(dir.clone())
+/// The directories a process resolves paths from: absolute paths start
+/// from `root`, which `..` can't go above, and relative ones from `cwd`.
+struct FsEnv {
+    root: Arc<Dcache>,
+    cwd: Arc<Dcache>,
+}
+
+// TODO: keep an FsEnv per process once there are processes
+static FS_ENV: Once<SpinMutex<FsEnv>> = Once::new();
+
+fn fs_env() -> &'static SpinMutex<FsEnv> {
+    FS_ENV.call_once(|| {
+        let root: &'static Arc<Dcache> = get_root_dir();
+        SpinMutex::new(FsEnv {
+            root: root.clone(),
+            cwd: root.clone(),
         })
-        .lock()
-        .clone()
+    })
+}
+
+pub fn get_working_dir() -> Arc<Dcache> {
+    fs_env().lock().cwd.clone()
 }
 
 pub fn set_working_dir(dir: Arc<Dcache>) -> Result<(), Error> {
-    let mut working_dir = WORKING_DIR.get().unwrap().lock();
-    *working_dir = dir;
+    fs_env().lock().cwd = dir;
+    Ok(())
+}
+
+/// Returns the root directory of the process, set by `chroot`
+pub fn get_process_root() -> Arc<Dcache> {
+    fs_env().lock().root.clone()
+}
+
+/// Changes the root directory of the process. The working directory is
+/// left alone, even if it's outside the new root.
+pub fn set_process_root(dir: Arc<Dcache>) -> Result<(), Error> {
+    if dir.type_() != InodeFileType::Directory {
+        return Err(code::ENOTDIR);
+    }
+    fs_env().lock().root = dir;
     Ok(())
 }
 
+/// Returns the absolute path of `dcache` as seen from the process root. An
+/// entry outside of the process root gets its path from the file system
+/// root instead.
+pub fn absolute_path(dcache: &Arc<Dcache>) -> String {
+    let root = get_process_root();
+    let mut names = Vec::new();
+    let mut current = dcache.clone();
+    while !Arc::ptr_eq(&current, &root) {
+        let Some(parent) = current.parent() else {
+            return dcache.get_full_path();
+        };
+        names.push(current.name());
+        current = parent;
+    }
+
+    let mut path = String::new();
+    for name in names.iter().rev() {
+        path.push('/');
+        path.push_str(name);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
 enum FilePath<'a> {
     Absolute(&'a str),
     Relative(&'a str),
@@ -60,10 +115,42 @@ impl<'a> FilePath<'a> {
 }
 
 pub fn lookup_path(path: &str) -> Option<Arc<Dcache>> {
-    match FilePath::new(path) {
-        FilePath::Absolute(path) => lookup_in_dir(get_root_dir(), path.trim_start_matches('/')),
-        FilePath::Relative(path) => lookup_in_dir(&get_working_dir(), path),
+    resolve(path).ok()
+}
+
+/// Resolves `path` to its directory entry, starting from the working
+/// directory if it's relative. Symbolic links are followed, and a trailing
+/// slash requires a directory.
+pub fn resolve(path: &str) -> Result<Arc<Dcache>, Error> {
+    resolve_at(path, true)
+}
+
+/// Like [`resolve`], but returns a symbolic link in the last component
+/// itself rather than what it points to, unless `path` ends with a slash.
+pub fn resolve_nofollow(path: &str) -> Result<Arc<Dcache>, Error> {
+    resolve_at(path, path.ends_with('/'))
+}
+
+fn resolve_at(path: &str, follow: bool) -> Result<Arc<Dcache>, Error> {
+    if path.is_empty() {
+        return Err(code::ENOENT);
+    }
+    let mut walk = PathWalk::new();
+    let start = match FilePath::new(path) {
+        FilePath::Absolute(_) => walk.root.clone(),
+        FilePath::Relative(_) => get_working_dir(),
+    };
+    let dcache = lookup_in_dir(&start, path, follow, &mut walk)?;
+    if path.ends_with('/') && dcache.type_() != InodeFileType::Directory {
+        return Err(code::ENOTDIR);
     }
+    Ok(dcache)
+}
+
+/// Returns the absolute path of `path` without any `.` or `..` component
+/// or symbolic link, as found by walking the dcache.
+pub fn realpath(path: &str) -> Result<String, Error> {
+    Ok(absolute_path(&resolve(path)?))
 }
 
 pub fn find_parent_and_name(path: &str) -> Option<(Arc<Dcache>, &str)> {
@@ -71,27 +158,30 @@ pub fn find_parent_and_name(path: &str) -> Option<(Arc<Dcache>, &str)> {
         FilePath::Relative(path) => {
             let (parent, name) = split_path(path)?;
             let dir = get_working_dir();
-            let parent = lookup_in_dir(&dir, parent)?;
+            let parent = lookup_in_dir(&dir, parent, true, &mut PathWalk::new()).ok()?;
             Some((parent, name))
         }
         FilePath::Absolute(path) => {
             let (parent, name) = split_path(path)?;
-            let dir = get_root_dir();
-            let parent = lookup_in_dir(dir, parent.trim_start_matches('/'))?;
+            let mut walk = PathWalk::new();
+            let root = walk.root.clone();
+            let parent = lookup_in_dir(&root, parent, true, &mut walk).ok()?;
             Some((parent, name))
         }
     }
 }
 
-pub fn open_path(path: &str, flags: i32, mode: mode_t) -> Result<File, Error> {
-    // TODO: add support for symlink
-    let open_flags = OpenFlags::from_bits_truncate(flags);
-    let access_mode = AccessMode::from(flags);
-    let dcache = match lookup_path(path) {
-        Some(dcache) => {
-            if open_flags.contains(OpenFlags::O_NOFOLLOW)
-                && dcache.type_() == InodeFileType::SymLink
-            {
+// Looks `path` up for open, creating a regular file if it's missing and
+// O_CREAT is given.
+fn open_dcache(path: &str, open_flags: OpenFlags, mode: mode_t) -> Result<Arc<Dcache>, Error> {
+    let found = if open_flags.contains(OpenFlags::O_NOFOLLOW) {
+        resolve_nofollow(path)
+    } else {
+        resolve(path)
+    };
+    let dcache = match found {
+        Ok(dcache) => {
+            if dcache.type_() == InodeFileType::SymLink {
                 return Err(code::ELOOP);
             }
             if open_flags.contains(OpenFlags::O_CREAT) && open_flags.contains(OpenFlags::O_EXCL) {
@@ -104,7 +194,8 @@ pub fn open_path(path: &str, flags: i32, mode: mode_t) -> Result<File, Error> {
             }
             dcache
         }
-        None => {
+        Err(e) if e != code::ENOENT => return Err(e),
+        Err(_) => {
             if open_flags.contains(OpenFlags::O_CREAT) {
                 if open_flags.contains(OpenFlags::O_DIRECTORY) || path.ends_with('/') {
                     return Err(code::ENOTDIR);
@@ -122,12 +213,32 @@ pub fn open_path(path: &str, flags: i32, mode: mode_t) -> Result<File, Error> {
             }
         }
     };
+    Ok(dcache)
+}
+
+pub fn open_path(path: &str, flags: i32, mode: mode_t) -> Result<File, Error> {
+    let dcache = open_dcache(path, OpenFlags::from_bits_truncate(flags), mode)?;
+    new_file(dcache, flags)
+}
+
+/// Like [`open_path`], but a FIFO is opened as an end of its pipe.
+pub fn open_file(path: &str, flags: i32, mode: mode_t) -> Result<Arc<dyn FileOps>, Error> {
+    let dcache = open_dcache(path, OpenFlags::from_bits_truncate(flags), mode)?;
+    if dcache.type_() == InodeFileType::Fifo {
+        return pipe::open_fifo(dcache.inode(), flags);
+    }
+    Ok(Arc::new(new_file(dcache, flags)?))
+}
+
+fn new_file(dcache: Arc<Dcache>, flags: i32) -> Result<File, Error> {
+    let open_flags = OpenFlags::from_bits_truncate(flags);
+    let access_mode = AccessMode::from(flags);
     // resize to 0 if O_TRUNC is set
     if open_flags.contains(OpenFlags::O_TRUNC) && access_mode.is_writable() {
         dcache.inode().resize(0)?;
     }
 
-    let file = File::new(dcache, AccessMode::from(flags), open_flags)?;
+    let file = File::new(dcache, access_mode, open_flags)?;
     Ok(file)
 }
 
@@ -265,33 +376,61 @@ pub fn is_valid_path(path: &str) -> bool {
     true
 }
 
-fn lookup_in_dir(dir: &Arc<Dcache>, path: &str) -> Option<Arc<Dcache>> {
-    // TODO: add support for symlink
+/// State of one path resolution
+struct PathWalk {
+    /// Where absolute paths and symbolic links start
+    root: Arc<Dcache>,
+    /// Number of symbolic links followed so far, to fail with ELOOP on a
+    /// cycle
+    links: usize,
+}
+
+impl PathWalk {
+    fn new() -> Self {
+        Self {
+            root: get_process_root(),
+            links: 0,
+        }
+    }
+}
+
+/// Walks `path` from `dir`, following symbolic links in every component
+/// but the last, which is only followed if `follow` is set. `..` never
+/// leaves the root of the walk.
+fn lookup_in_dir(
+    dir: &Arc<Dcache>,
+    path: &str,
+    follow: bool,
+    walk: &mut PathWalk,
+) -> Result<Arc<Dcache>, Error> {
     let mut current = dir.clone();
-    let mut cur_path = path;
-
-    while !cur_path.is_empty() {
-        match cur_path.split_once('/') {
-            Some((next_name, next_path)) => {
-                let next_path = next_path.trim_start_matches('/');
-                let next = current.lookup(next_name).ok()?;
-                if next.type_() == InodeFileType::Directory {
-                    current = next;
-                    cur_path = next_path;
-                } else {
-                    // not a directory
-                    return None;
-                }
-            }
-            None => {
-                // end of path, just find in cur dir
-                current = current.lookup(cur_path).ok()?;
-                break;
-            }
+    let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+    while let Some(name) = names.next() {
+        if name == ".." && Arc::ptr_eq(&current, &walk.root) {
+            continue;
+        }
+        let next = current.lookup(name)?;
+        if next.type_() != InodeFileType::SymLink || (names.peek().is_none() && !follow) {
+            current = next;
+            continue;
+        }
+
+        walk.links += 1;
+        if walk.links > MAX_SYMLINKS {
+            return Err(code::ELOOP);
+        }
+        let target = next.inode().readlink()?;
+        if target.is_empty() {
+            return Err(code::ENOENT);
+        }
+        // A relative target starts from the directory holding the link.
+        let start = match FilePath::new(&target) {
+            FilePath::Absolute(_) => walk.root.clone(),
+            FilePath::Relative(_) => current,
         };
+        current = lookup_in_dir(&start, &target, true, walk)?;
     }
-
-    Some(current)
+    Ok(current)
 }
 
 #[cfg(test)]
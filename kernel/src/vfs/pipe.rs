@@ -0,0 +1,441 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pipes and FIFOs.
+//!
+//! A [`Pipe`] is a ring buffer with a read end and a write end, each open
+//! file of a pipe being one of them, or both for a FIFO opened O_RDWR.
+//! `pipe2` creates an anonymous pipe. A FIFO is an inode created by
+//! `mkfifo`, whose opens share the pipe of the inode. Blocked readers,
+//! writers and openers sleep on a futex bumped whenever the pipe changes.
+//!
+//! Reading a pipe without writers returns end of file, and writing one
+//! without readers fails with EPIPE. There are no signals to raise SIGPIPE.
+
+use crate::{
+    error::{code, Error},
+    sync::{atomic_wait, atomic_wake},
+    vfs::{
+        file::{AccessMode, FileAttr, FileOps, OpenFlags},
+        inode::{InodeAttr, InodeOps},
+        inode_mode::{InodeFileType, InodeMode},
+        poll::{PollEvents, PollQueue, PollWaiter},
+    },
+};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use blueos_infra::ringbuffer::BoxedRingBuffer;
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Size of the buffer of a pipe.
+pub const PIPE_SIZE: usize = 4096;
+/// Writes of at most this many bytes aren't interleaved with other writes.
+pub const PIPE_BUF: usize = 512;
+
+pub struct Pipe {
+    rb: BoxedRingBuffer,
+    // The ring buffer allows a single reader and a single writer at a
+    // time.
+    read_lock: Mutex<()>,
+    write_lock: Mutex<()>,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
+    // Number of times each end has been opened, for FIFO opens waiting for
+    // the other end.
+    read_opens: AtomicUsize,
+    write_opens: AtomicUsize,
+    // Bumped whenever data moves or an end is opened or closed.
+    futex: AtomicUsize,
+    poll_queue: PollQueue,
+}
+
+impl Pipe {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            rb: BoxedRingBuffer::new(PIPE_SIZE),
+            read_lock: Mutex::new(()),
+            write_lock: Mutex::new(()),
+            readers: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+            read_opens: AtomicUsize::new(0),
+            write_opens: AtomicUsize::new(0),
+            futex: AtomicUsize::new(0),
+            poll_queue: PollQueue::new(),
+        })
+    }
+
+    fn changed(&self) {
+        self.futex.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&self.futex, usize::MAX);
+        self.poll_queue.notify();
+    }
+
+    fn open_end(&self, access_mode: AccessMode) {
+        if access_mode.is_readable() {
+            self.readers.fetch_add(1, Ordering::AcqRel);
+            self.read_opens.fetch_add(1, Ordering::AcqRel);
+        }
+        if access_mode.is_writable() {
+            self.writers.fetch_add(1, Ordering::AcqRel);
+            self.write_opens.fetch_add(1, Ordering::AcqRel);
+        }
+        self.changed();
+    }
+
+    fn close_end(&self, access_mode: AccessMode) {
+        if access_mode.is_readable() {
+            self.readers.fetch_sub(1, Ordering::AcqRel);
+        }
+        if access_mode.is_writable() {
+            self.writers.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.changed();
+    }
+
+    // Sleeps until the other end has been opened at least once since
+    // `opens` was `start`.
+    fn wait_for_open(&self, opens: &AtomicUsize, start: usize) {
+        loop {
+            let seq = self.futex.load(Ordering::Acquire);
+            if opens.load(Ordering::Acquire) != start {
+                return;
+            }
+            let _ = atomic_wait(&self.futex, seq, None);
+        }
+    }
+
+    fn pop(&self, buf: &mut [u8]) -> usize {
+        let _guard = self.read_lock.lock();
+        // SAFETY: readers are serialized by read_lock.
+        let mut reader = unsafe { self.rb.reader() };
+        let mut n = 0;
+        while n < buf.len() {
+            let m = reader.pop(|data| {
+                let m = data.len().min(buf.len() - n);
+                buf[n..n + m].copy_from_slice(&data[..m]);
+                m
+            });
+            if m == 0 {
+                break;
+            }
+            n += m;
+        }
+        n
+    }
+
+    // Pushes as much of `buf` as fits, or nothing if `atomic` and it
+    // doesn't all fit.
+    fn push(&self, buf: &[u8], atomic: bool) -> usize {
+        let _guard = self.write_lock.lock();
+        // SAFETY: writers are serialized by write_lock.
+        let mut writer = unsafe { self.rb.writer() };
+        let room: usize = writer.push_slices().iter().map(|slice| slice.len()).sum();
+        if room == 0 || (atomic && room < buf.len()) {
+            return 0;
+        }
+        let mut n = 0;
+        while n < buf.len() {
+            let m = writer.push(|space| {
+                let m = space.len().min(buf.len() - n);
+                space[..m].copy_from_slice(&buf[n..n + m]);
+                m
+            });
+            if m == 0 {
+                break;
+            }
+            n += m;
+        }
+        n
+    }
+
+    pub fn read(&self, buf: &mut [u8], nonblock: bool) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let seq = self.futex.load(Ordering::Acquire);
+            let n = self.pop(buf);
+            if n > 0 {
+                self.changed();
+                return Ok(n);
+            }
+            if self.writers.load(Ordering::Acquire) == 0 {
+                return Ok(0);
+            }
+            if nonblock {
+                return Err(code::EAGAIN);
+            }
+            let _ = atomic_wait(&self.futex, seq, None);
+        }
+    }
+
+    pub fn write(&self, buf: &[u8], nonblock: bool) -> Result<usize, Error> {
+        let atomic = buf.len() <= PIPE_BUF;
+        let mut written = 0;
+        while written < buf.len() {
+            let seq = self.futex.load(Ordering::Acquire);
+            if self.readers.load(Ordering::Acquire) == 0 {
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(code::EPIPE)
+                };
+            }
+            let n = self.push(&buf[written..], atomic);
+            if n > 0 {
+                written += n;
+                self.changed();
+                continue;
+            }
+            if nonblock {
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(code::EAGAIN)
+                };
+            }
+            let _ = atomic_wait(&self.futex, seq, None);
+        }
+        Ok(written)
+    }
+
+    fn poll(
+        &self,
+        access_mode: AccessMode,
+        events: PollEvents,
+        waiter: Option<&Arc<PollWaiter>>,
+    ) -> PollEvents {
+        if let Some(waiter) = waiter {
+            self.poll_queue.register(waiter);
+        }
+        let mut ready = PollEvents::empty();
+        if access_mode.is_readable() {
+            if !self.rb.is_empty() {
+                ready |= PollEvents::POLLIN;
+            }
+            if self.writers.load(Ordering::Acquire) == 0 {
+                ready |= PollEvents::POLLHUP;
+            }
+        }
+        if access_mode.is_writable() {
+            if self.readers.load(Ordering::Acquire) == 0 {
+                ready |= PollEvents::POLLERR;
+            } else if !self.rb.is_full() {
+                ready |= PollEvents::POLLOUT;
+            }
+        }
+        ready & (events | PollEvents::ALWAYS)
+    }
+}
+
+/// An open end of a pipe.
+pub struct PipeFile {
+    pipe: Arc<Pipe>,
+    access_mode: AccessMode,
+    open_flags: AtomicI32,
+    // The inode of a FIFO, none for an anonymous pipe.
+    inode: Option<Arc<dyn InodeOps>>,
+}
+
+impl PipeFile {
+    fn new(
+        pipe: Arc<Pipe>,
+        access_mode: AccessMode,
+        flags: OpenFlags,
+        inode: Option<Arc<dyn InodeOps>>,
+    ) -> Self {
+        pipe.open_end(access_mode);
+        Self {
+            pipe,
+            access_mode,
+            open_flags: AtomicI32::new(flags.bits()),
+            inode,
+        }
+    }
+
+    fn is_nonblock(&self) -> bool {
+        self.flags().contains(OpenFlags::O_NONBLOCK)
+    }
+}
+
+impl FileOps for PipeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if !self.access_mode.is_readable() {
+            return Err(code::EBADF);
+        }
+        self.pipe.read(buf, self.is_nonblock())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        if !self.access_mode.is_writable() {
+            return Err(code::EBADF);
+        }
+        self.pipe.write(buf, self.is_nonblock())
+    }
+
+    fn close(&self) -> Result<(), Error> {
+        self.pipe.close_end(self.access_mode);
+        Ok(())
+    }
+
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
+        self.pipe.poll(self.access_mode, events, waiter)
+    }
+
+    fn stat(&self) -> FileAttr {
+        if let Some(inode) = &self.inode {
+            return inode.file_attr();
+        }
+        let attr = InodeAttr::new(
+            0,
+            InodeFileType::Fifo,
+            InodeMode::from_bits_truncate(0o600),
+            0,
+            0,
+            0,
+        );
+        FileAttr::new(0, 0, &attr)
+    }
+
+    fn flags(&self) -> OpenFlags {
+        OpenFlags::from_bits_truncate(self.open_flags.load(Ordering::Relaxed))
+    }
+
+    fn set_flags(&self, flags: OpenFlags) {
+        self.open_flags.store(flags.bits(), Ordering::Relaxed);
+    }
+}
+
+/// Creates an anonymous pipe, and returns its read end and its write end.
+pub fn pipe(flags: OpenFlags) -> (Arc<PipeFile>, Arc<PipeFile>) {
+    let pipe = Pipe::new();
+    let reader = PipeFile::new(pipe.clone(), AccessMode::O_RDONLY, flags, None);
+    let writer = PipeFile::new(pipe, AccessMode::O_WRONLY, flags, None);
+    (Arc::new(reader), Arc::new(writer))
+}
+
+// The pipes of the FIFOs which are open, by inode. An open end holds its
+// inode, so that the address isn't reused while the pipe is alive.
+static FIFOS: Mutex<Vec<(usize, Weak<Pipe>)>> = Mutex::new(Vec::new());
+
+fn fifo_pipe(inode: &Arc<dyn InodeOps>) -> Arc<Pipe> {
+    let key = Arc::as_ptr(inode) as *const () as usize;
+    let mut fifos = FIFOS.lock();
+    fifos.retain(|(_, pipe)| pipe.strong_count() > 0);
+    if let Some(pipe) = fifos
+        .iter()
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, pipe)| pipe.upgrade())
+    {
+        return pipe;
+    }
+    let pipe = Pipe::new();
+    fifos.push((key, Arc::downgrade(&pipe)));
+    pipe
+}
+
+/// Opens an end of the FIFO `inode`. Unless O_NONBLOCK or O_RDWR is given,
+/// it waits for the other end to be opened. Opening the write end with
+/// O_NONBLOCK fails with ENXIO if there is no reader.
+pub fn open_fifo(inode: &Arc<dyn InodeOps>, flags: i32) -> Result<Arc<dyn FileOps>, Error> {
+    let access_mode = AccessMode::from(flags);
+    let open_flags = OpenFlags::from_bits_truncate(flags);
+    let nonblock = open_flags.contains(OpenFlags::O_NONBLOCK);
+    if (access_mode.is_readable() && !inode.mode().is_readable())
+        || (access_mode.is_writable() && !inode.mode().is_writable())
+    {
+        return Err(code::EACCES);
+    }
+    let pipe = fifo_pipe(inode);
+    if access_mode == AccessMode::O_WRONLY && nonblock && pipe.readers.load(Ordering::Acquire) == 0
+    {
+        return Err(code::ENXIO);
+    }
+
+    let read_opens = pipe.read_opens.load(Ordering::Acquire);
+    let write_opens = pipe.write_opens.load(Ordering::Acquire);
+    let file = Arc::new(PipeFile::new(
+        pipe.clone(),
+        access_mode,
+        open_flags,
+        Some(inode.clone()),
+    ));
+    if !nonblock {
+        match access_mode {
+            AccessMode::O_RDONLY if pipe.writers.load(Ordering::Acquire) == 0 => {
+                pipe.wait_for_open(&pipe.write_opens, write_opens)
+            }
+            AccessMode::O_WRONLY if pipe.readers.load(Ordering::Acquire) == 0 => {
+                pipe.wait_for_open(&pipe.read_opens, read_opens)
+            }
+            _ => {}
+        }
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use blueos_test_macro::test;
+
+    #[test]
+    fn test_pipe() {
+        let (reader, writer) = pipe(OpenFlags::O_NONBLOCK);
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read(&mut buf), Err(code::EAGAIN));
+        assert_eq!(writer.read(&mut buf), Err(code::EBADF));
+        assert_eq!(reader.write(b"x"), Err(code::EBADF));
+        assert_eq!(reader.poll(PollEvents::POLLIN, None), PollEvents::empty());
+        assert_eq!(writer.poll(PollEvents::POLLOUT, None), PollEvents::POLLOUT);
+
+        assert_eq!(writer.write(b"hello"), Ok(5));
+        assert_eq!(reader.poll(PollEvents::POLLIN, None), PollEvents::POLLIN);
+        assert_eq!(reader.read(&mut buf[..3]), Ok(3));
+        assert_eq!(reader.read(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"lo");
+
+        // Fill the pipe, a small write must then fit whole or not at all.
+        let big = vec![7u8; PIPE_SIZE - 2];
+        assert_eq!(writer.write(&big), Ok(PIPE_SIZE - 2));
+        assert_eq!(writer.write(b"abc"), Err(code::EAGAIN));
+        assert_eq!(writer.write(b"ab"), Ok(2));
+        assert_eq!(writer.poll(PollEvents::POLLOUT, None), PollEvents::empty());
+
+        writer.close().unwrap();
+        let mut drain = vec![0u8; PIPE_SIZE];
+        assert_eq!(reader.read(&mut drain), Ok(PIPE_SIZE));
+        // No writer left: end of file.
+        assert_eq!(reader.read(&mut buf), Ok(0));
+        assert!(reader
+            .poll(PollEvents::POLLIN, None)
+            .contains(PollEvents::POLLHUP));
+        reader.close().unwrap();
+    }
+
+    #[test]
+    fn test_pipe_without_reader() {
+        let (reader, writer) = pipe(OpenFlags::empty());
+        reader.close().unwrap();
+        assert_eq!(writer.write(b"x"), Err(code::EPIPE));
+        assert!(writer
+            .poll(PollEvents::POLLOUT, None)
+            .contains(PollEvents::POLLERR));
+        writer.close().unwrap();
+    }
+}
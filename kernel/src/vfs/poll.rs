@@ -0,0 +1,235 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Readiness polling for files.
+//!
+//! A file reports which of the requested events it's ready for. When the
+//! caller passes a [`PollWaiter`], a file that can block also registers it
+//! in a [`PollQueue`], and notifies that queue whenever its readiness may
+//! have changed, so that the caller can sleep until then and poll again.
+//! An async task waits with [`poll_file`] instead, which wakes its waker.
+
+use crate::{
+    error::code,
+    sync::{atomic_wait, atomic_wake, SpinLock},
+    time,
+    vfs::file::FileOps,
+};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use bitflags::bitflags;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PollEvents: i16 {
+        const POLLIN = libc::POLLIN;
+        const POLLPRI = libc::POLLPRI;
+        const POLLOUT = libc::POLLOUT;
+        const POLLERR = libc::POLLERR;
+        const POLLHUP = libc::POLLHUP;
+        const POLLNVAL = libc::POLLNVAL;
+    }
+}
+
+impl PollEvents {
+    /// Events reported whether they were requested or not.
+    pub const ALWAYS: Self = Self::POLLERR.union(Self::POLLHUP).union(Self::POLLNVAL);
+
+    /// Readiness of a file which never blocks.
+    pub fn ready(events: Self) -> Self {
+        events & (Self::POLLIN | Self::POLLOUT)
+    }
+}
+
+/// A thread waiting in poll for any of several files to become ready, or
+/// an async task through its waker.
+#[derive(Debug, Default)]
+pub struct PollWaiter {
+    woken: AtomicUsize,
+    waker: SpinLock<Option<Waker>>,
+}
+
+impl PollWaiter {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Must be called before polling the files, so that a notification
+    /// arriving in between isn't missed.
+    pub fn reset(&self) {
+        self.woken.store(0, Ordering::Release);
+    }
+
+    pub fn wake(&self) {
+        if self.woken.swap(1, Ordering::AcqRel) == 0 {
+            let _ = atomic_wake(&self.woken, usize::MAX);
+        }
+        if let Some(waker) = self.waker.irqsave_lock().take() {
+            waker.wake();
+        }
+    }
+
+    /// Makes the next wake also wake `waker`.
+    pub fn set_waker(&self, waker: &Waker) {
+        let mut current = self.waker.irqsave_lock();
+        if !current.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            *current = Some(waker.clone());
+        }
+    }
+
+    /// Sleeps until woken, or for at most `timeout` ticks. Returns false if
+    /// the timeout expired.
+    pub fn wait(&self, timeout: Option<usize>) -> bool {
+        !matches!(atomic_wait(&self.woken, 0, timeout), Err(e) if e == code::ETIMEDOUT)
+    }
+}
+
+/// Waiters registered with a file. They are held weakly, and dropped
+/// once their poll has returned.
+pub struct PollQueue {
+    waiters: SpinLock<Vec<Weak<PollWaiter>>>,
+}
+
+impl PollQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, waiter: &Arc<PollWaiter>) {
+        let waiter = Arc::downgrade(waiter);
+        let mut waiters = self.waiters.irqsave_lock();
+        waiters.retain(|w| w.strong_count() > 0);
+        if !waiters.iter().any(|w| w.ptr_eq(&waiter)) {
+            waiters.push(waiter);
+        }
+    }
+
+    /// Wakes every registered waiter. Can be called from interrupt context.
+    pub fn notify(&self) {
+        let waiters = self.waiters.irqsave_lock();
+        for waiter in waiters.iter().filter_map(Weak::upgrade) {
+            waiter.wake();
+        }
+    }
+}
+
+/// Calls `poll` until it reports a nonzero number of ready files, sleeping
+/// in between until one of them notifies the waiter it's given. Gives up
+/// after `timeout` ticks, returning 0, or waits forever if it's `None`.
+pub fn poll_until(
+    timeout: Option<usize>,
+    mut poll: impl FnMut(&Arc<PollWaiter>) -> usize,
+) -> usize {
+    let deadline = timeout.map(|ticks| time::get_sys_ticks() + ticks);
+    let waiter = PollWaiter::new();
+    loop {
+        waiter.reset();
+        let ready = poll(&waiter);
+        if ready > 0 || timeout == Some(0) {
+            return ready;
+        }
+        let ticks = match deadline {
+            Some(deadline) => {
+                let now = time::get_sys_ticks();
+                if now >= deadline {
+                    return 0;
+                }
+                Some(deadline - now)
+            }
+            None => None,
+        };
+        if !waiter.wait(ticks) {
+            return 0;
+        }
+    }
+}
+
+/// Future of [`poll_file`].
+pub struct PollFile {
+    file: Arc<dyn FileOps>,
+    events: PollEvents,
+    waiter: Arc<PollWaiter>,
+}
+
+impl Future for PollFile {
+    type Output = PollEvents;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<PollEvents> {
+        self.waiter.reset();
+        self.waiter.set_waker(ctx.waker());
+        let revents = self.file.poll(self.events, Some(&self.waiter));
+        if revents.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(revents)
+        }
+    }
+}
+
+/// Waits asynchronously until `file` is ready for any of `events`, and
+/// returns those it's ready for.
+pub fn poll_file(file: Arc<dyn FileOps>, events: PollEvents) -> PollFile {
+    PollFile {
+        file,
+        events,
+        waiter: PollWaiter::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asynk, scheduler,
+        vfs::{file::OpenFlags, pipe},
+    };
+    use blueos_test_macro::test;
+    use core::sync::atomic::AtomicBool;
+
+    static READY: AtomicBool = AtomicBool::new(false);
+
+    #[test]
+    fn test_poll_file() {
+        let (reader, writer) = pipe::pipe(OpenFlags::O_NONBLOCK);
+        let task_reader = reader.clone();
+        asynk::spawn(async move {
+            let revents = poll_file(task_reader.clone(), PollEvents::POLLIN).await;
+            assert_eq!(revents, PollEvents::POLLIN);
+            let mut buf = [0u8; 4];
+            assert_eq!(task_reader.read(&mut buf), Ok(4));
+            READY.store(true, Ordering::Release);
+        });
+        // The task waits on the empty pipe until it's written.
+        for _ in 0..4 {
+            scheduler::yield_me();
+        }
+        assert!(!READY.load(Ordering::Acquire));
+        assert_eq!(writer.write(b"ping"), Ok(4));
+        while !READY.load(Ordering::Acquire) {
+            scheduler::yield_me();
+        }
+        writer.close().unwrap();
+        reader.close().unwrap();
+    }
+}
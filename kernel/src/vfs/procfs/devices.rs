@@ -0,0 +1,55 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    devices::{DeviceClass, DeviceManager},
+    error::Error,
+    vfs::procfs::ProcFileOps,
+};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+/// Lists the registered devices by class, with their numbers.
+pub(crate) struct DeviceList;
+
+impl ProcFileOps for DeviceList {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let manager = DeviceManager::get();
+        let mut result = String::with_capacity(256);
+        for (i, (class, title)) in [
+            (DeviceClass::Char, "Character devices:"),
+            (DeviceClass::Block, "Block devices:"),
+            (DeviceClass::Misc, "Misc devices:"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if i > 0 {
+                writeln!(result).unwrap();
+            }
+            writeln!(result, "{}", title).unwrap();
+            let mut devices = manager.get_devices_by_class(class);
+            devices.sort_by_key(|(_, dev)| dev.id().raw());
+            for (name, dev) in devices {
+                let id = dev.id();
+                writeln!(result, "{:>4}:{:<4} {}", id.major(), id.minor(), name).unwrap();
+            }
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
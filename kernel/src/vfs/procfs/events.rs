@@ -0,0 +1,38 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    error::Error,
+    events::{self, Event},
+    vfs::procfs::ProcFileOps,
+};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+/// Lists the kernel event counters, one `name count` per line.
+pub(crate) struct EventList;
+
+impl ProcFileOps for EventList {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(32 * Event::ALL.len());
+        for event in Event::ALL {
+            writeln!(result, "{} {}", event.name(), events::get(event)).unwrap();
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
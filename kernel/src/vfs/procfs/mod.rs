@@ -12,13 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod devices;
+mod events;
+#[cfg(irqsoff_tracer)]
+mod irqsoff;
 mod memory_info;
+mod page_owner;
+#[cfg(profiler)]
+mod profile;
+mod softirqs;
 mod stat;
+mod storage;
 mod task;
+mod uptime;
 
+use devices::DeviceList;
+use events::EventList;
+#[cfg(irqsoff_tracer)]
+use irqsoff::IrqsOff;
 use memory_info::MemoryInfo;
+use page_owner::PageOwnerList;
+#[cfg(profiler)]
+use profile::Profile;
+use softirqs::Softirqs;
 use stat::SystemStat;
+use storage::StorageHealthList;
 use task::ProcTaskFile;
+use uptime::Uptime;
 
 use crate::{
     devices::Device,
@@ -136,6 +156,16 @@ impl ProcFileSystem {
 
         self.root.create_meminfo_file("meminfo")?;
         self.root.create_stat_file("stat")?;
+        self.root.create_devices_file("devices")?;
+        self.root.create_storage_file("storage")?;
+        self.root.create_page_owner_file("pageowner")?;
+        self.root.create_events_file("events")?;
+        self.root.create_uptime_file("uptime")?;
+        #[cfg(profiler)]
+        self.root.create_profile_file("profile")?;
+        self.root.create_softirqs_file("softirqs")?;
+        #[cfg(irqsoff_tracer)]
+        self.root.create_irqsoff_file("irqsoff")?;
 
         // not support process yet, use thread info instead. and put all threads in /proc
         let mut global_queue_visitor = GlobalQueueVisitor::new();
@@ -274,6 +304,93 @@ impl ProcDir {
         Ok(inode)
     }
 
+    pub fn create_devices_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(DeviceList {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    pub fn create_storage_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(StorageHealthList {}, ino, self.base.fs.clone(), true)
+            as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    pub fn create_page_owner_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(PageOwnerList {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(profiler)]
+    pub fn create_profile_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Profile {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    pub fn create_softirqs_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(Softirqs {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    #[cfg(irqsoff_tracer)]
+    pub fn create_irqsoff_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(IrqsOff {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    pub fn create_events_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode =
+            ProcFile::new(EventList {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
+    pub fn create_uptime_file(&self, name: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let ino = self.base.fs.upgrade().unwrap().alloc_inode_no();
+        let inode = ProcFile::new(Uptime {}, ino, self.base.fs.clone(), true) as Arc<dyn InodeOps>;
+        self.insert(name, inode.clone());
+        Ok(inode)
+    }
+
     pub fn create_dir(&self, name: &str, is_dcacheable: bool) -> Result<Arc<Self>, Error> {
         if name.len() > NAME_MAX {
             return Err(code::ENAMETOOLONG);
@@ -503,11 +620,9 @@ pub fn trace_thread_create(thread: ThreadNode) -> Result<(), Error> {
     if !procfs.is_mounted() {
         return Err(code::EINVAL);
     }
-    let root = procfs.root.clone();
-    let pid_dir = root.lookup("0")?;
-    let task_dir = pid_dir.lookup("task")?;
-    let task_dir = task_dir.downcast_ref::<ProcDir>().ok_or(code::EINVAL)?;
-    let thread_dir = task_dir.create_dir(Thread::id(&thread).to_string().as_str(), false)?;
+    let thread_dir = procfs
+        .root
+        .create_dir(Thread::id(&thread).to_string().as_str(), false)?;
     let _ = thread_dir.create_task_file("status", thread.clone())?;
     Ok(())
 }
@@ -517,10 +632,6 @@ pub fn trace_thread_close(thread: ThreadNode) -> Result<(), Error> {
     if !procfs.is_mounted() {
         return Err(code::EINVAL);
     }
-    let root = procfs.root.clone();
-    let pid_dir = root.lookup("0")?;
-    let task_dir = pid_dir.lookup("task")?;
-    let task_dir = task_dir.downcast_ref::<ProcDir>().ok_or(code::EINVAL)?;
-    task_dir.remove(Thread::id(&thread).to_string().as_str());
+    procfs.root.remove(Thread::id(&thread).to_string().as_str());
     Ok(())
 }
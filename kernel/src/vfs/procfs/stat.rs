@@ -16,7 +16,7 @@ use super::ProcFileOps;
 use crate::{
     error::Error,
     irq::irq_trace::{IrqTraceInfo, IRQ_COUNTERS, PER_CPU_TRACE_INFO},
-    scheduler, thread, time,
+    scheduler, softirq, thread, time,
 };
 use alloc::{string::String, vec::Vec};
 use blueos_kconfig::NUM_CORES;
@@ -98,6 +98,7 @@ fn format_cpu_time() -> String {
     let mut total_system_time: u64 = 0;
     let mut total_idle_time: u64 = 0;
     let mut total_irq_time: u64 = 0;
+    let mut total_softirq_time: u64 = 0;
 
     let mut cpu_stats = [CpuStat::default(); NUM_CORES + 1];
     loop {
@@ -116,16 +117,20 @@ fn format_cpu_time() -> String {
             let irq_time = time::get_cycles_to_ms(irq_trace.total_irq_process_cycles) / 10;
             total_system_time += system_time;
             total_idle_time += idle_time;
+            let softirq_time = time::get_cycles_to_ms(softirq::cycles(cpu_id)) / 10;
             total_irq_time += irq_time;
+            total_softirq_time += softirq_time;
             cpu_stats[cpu_id + 1].cpu_id = cpu_id;
             cpu_stats[cpu_id + 1].system = system_time;
             cpu_stats[cpu_id + 1].idle = idle_time;
             cpu_stats[cpu_id + 1].irq = irq_time;
+            cpu_stats[cpu_id + 1].softirq = softirq_time;
         }
         cpu_stats[0].cpu_id = NUM_CORES; // total
         cpu_stats[0].system = total_system_time;
         cpu_stats[0].idle = total_idle_time;
         cpu_stats[0].irq = total_irq_time;
+        cpu_stats[0].softirq = total_softirq_time;
 
         break;
     }
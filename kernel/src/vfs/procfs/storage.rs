@@ -0,0 +1,63 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    devices::{storage::HEALTH_ERASE_COUNTS, DeviceClass, DeviceManager},
+    error::Error,
+    vfs::procfs::ProcFileOps,
+};
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+/// Lists the I/O and wear statistics of the block devices, one per line.
+/// Erase counts are `-` for media which doesn't report them.
+pub(crate) struct StorageHealthList;
+
+impl ProcFileOps for StorageHealthList {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let mut result = String::with_capacity(256);
+        writeln!(
+            result,
+            "name bytes_read bytes_written reads writes read_errors write_errors erases max_block_erases"
+        )
+        .unwrap();
+        for (name, dev) in DeviceManager::get().get_devices_by_class(DeviceClass::Block) {
+            let Ok(health) = dev.health() else {
+                continue;
+            };
+            write!(
+                result,
+                "{} {} {} {} {} {} {}",
+                name,
+                health.bytes_read,
+                health.bytes_written,
+                health.reads,
+                health.writes,
+                health.read_errors,
+                health.write_errors
+            )
+            .unwrap();
+            if health.flags & HEALTH_ERASE_COUNTS != 0 {
+                writeln!(result, " {} {}", health.erases, health.max_block_erases).unwrap();
+            } else {
+                writeln!(result, " - -").unwrap();
+            }
+        }
+        Ok(result.into_bytes())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
@@ -15,6 +15,7 @@
 use super::ProcFileOps;
 use crate::{
     error::Error,
+    scheduler,
     thread::{Thread, ThreadNode},
 };
 use alloc::{format, string::String, vec::Vec};
@@ -32,11 +33,26 @@ impl ProcTaskFile {
 
 impl ProcFileOps for ProcTaskFile {
     fn get_content(&self) -> Result<Vec<u8>, Error> {
-        let mut result = String::with_capacity(64);
-        writeln!(result, "{:<9} {}", "Name:", self.thread.kind_to_str()).unwrap();
-        writeln!(result, "{:<9} {}", "State:", self.thread.state_to_str()).unwrap();
-        writeln!(result, "{:<9} {}", "Tid:", Thread::id(&self.thread)).unwrap();
-        writeln!(result, "{:<9} {}", "Priority:", self.thread.priority()).unwrap();
+        let thread = &self.thread;
+        let name = thread.name();
+        let name = if name.is_empty() {
+            thread.kind_to_str()
+        } else {
+            &name
+        };
+        // Only the saved SP of other threads is meaningful.
+        let stack_used = if Thread::id(thread) == scheduler::current_thread_id() {
+            thread.stack_usage()
+        } else {
+            thread.saved_stack_usage()
+        };
+        let mut result = String::with_capacity(128);
+        writeln!(result, "{:<11} {}", "Name:", name).unwrap();
+        writeln!(result, "{:<11} {}", "State:", thread.state_to_str()).unwrap();
+        writeln!(result, "{:<11} {}", "Tid:", Thread::id(thread)).unwrap();
+        writeln!(result, "{:<11} {}", "Priority:", thread.priority()).unwrap();
+        writeln!(result, "{:<11} {} B", "StackUsed:", stack_used).unwrap();
+        writeln!(result, "{:<11} {} B", "StackSize:", thread.stack_size()).unwrap();
         Ok(result.as_bytes().to_vec())
     }
 
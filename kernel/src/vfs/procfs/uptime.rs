@@ -0,0 +1,47 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::ProcFileOps;
+use crate::{error::Error, scheduler, time};
+use alloc::{string::String, vec::Vec};
+use blueos_kconfig::NUM_CORES;
+use core::fmt::Write;
+
+/// Seconds since boot and seconds spent idle summed over the cores, as in
+/// Linux.
+pub(crate) struct Uptime;
+
+impl ProcFileOps for Uptime {
+    fn get_content(&self) -> Result<Vec<u8>, Error> {
+        let uptime_ms = time::tick_get_millisecond() as u64;
+        let idle_ms: u64 = (0..NUM_CORES)
+            .map(|cpu_id| time::get_cycles_to_ms(scheduler::get_idle_thread(cpu_id).get_cycles()))
+            .sum();
+        let mut result = String::with_capacity(32);
+        writeln!(
+            result,
+            "{}.{:02} {}.{:02}",
+            uptime_ms / 1000,
+            uptime_ms % 1000 / 10,
+            idle_ms / 1000,
+            idle_ms % 1000 / 10
+        )
+        .unwrap();
+        Ok(result.as_bytes().to_vec())
+    }
+
+    fn set_content(&self, content: Vec<u8>) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
@@ -16,7 +16,7 @@ use crate::{
     error::{code, Error},
     net::connection::Connection,
     vfs::{
-        fd_manager::get_fd_manager,
+        fd_manager::{get_fd_manager, get_file_ops},
         file::{FileAttr, FileOps, OpenFlags},
         inode::InodeOps,
         inode_mode::InodeMode,
@@ -174,11 +174,6 @@ impl FileOps for SocketFile {
         Err(code::EINVAL)
     }
 
-    fn dup(&self, close_on_exec: bool) -> Result<Arc<dyn FileOps>, Error> {
-        warn!("Illegal dup on socket, dup is not implemented");
-        Err(code::EINVAL)
-    }
-
     fn stat(&self) -> FileAttr {
         self.inode.file_attr()
     }
@@ -213,10 +208,7 @@ pub fn free_sock_fd(fd: i32) -> Result<(), Error> {
 }
 
 pub fn sock_attach_to_fd(fd: i32, socket: Arc<Connection>) -> Result<i32, Error> {
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        fd_manager.get_file_ops(fd).ok_or(code::EBADF)?
-    };
+    let file_ops = get_file_ops(fd).ok_or(code::EBADF)?;
     let file_ops_ptr = Arc::as_ptr(&file_ops) as *const ();
     if let Some(socket_file) = unsafe { (file_ops_ptr as *const SocketFile).as_ref() } {
         socket_file.set_socket(socket);
@@ -228,10 +220,7 @@ pub fn sock_attach_to_fd(fd: i32, socket: Arc<Connection>) -> Result<i32, Error>
 }
 
 pub fn get_sock_by_fd(fd: i32) -> Result<Arc<Connection>, Error> {
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        fd_manager.get_file_ops(fd).ok_or(code::EBADF)?
-    };
+    let file_ops = get_file_ops(fd).ok_or(code::EBADF)?;
     try_get_socket(&file_ops).ok_or_else(|| {
         warn!("File descriptor {} is not a socket", fd);
         code::ERROR
@@ -14,19 +14,24 @@
 
 //! C API for VFS operations  
 use crate::{
+    devices::DeviceId,
     error::code,
+    time::{self, syscalls as time_syscalls},
     vfs::{
         dcache::Dcache,
-        dirent::DirBufferReader,
-        fd_manager::get_fd_manager,
+        dirent::{DirBufferReader, DirentFormat},
+        epoll::{self, EpollEvent, EpollFile},
+        fd_manager::{get_fd_manager, get_file_ops},
         file::{File, FileAttr, FileOps, OpenFlags},
         fs::FileSystemInfo,
         inode_mode::{InodeFileType, InodeMode},
-        mount, path,
+        mmap, mount, path, pipe,
+        poll::{self, PollEvents, PollWaiter},
         utils::SeekFrom,
     },
 };
-use alloc::{slice, string::String, sync::Arc};
+use alloc::{slice, string::String, sync::Arc, vec::Vec};
+use blueos_infra::ringbuffer::BoxedRingBuffer;
 use core::{
     ffi::{c_char, c_int, c_ulong, c_void, CStr},
     mem::size_of,
@@ -41,7 +46,7 @@ pub fn mount(
     path: *const c_char,
     filesystemtype: *const c_char,
     _rwflag: c_ulong,
-    _data: *const c_void,
+    data: *const c_void,
 ) -> c_int {
     if path.is_null() || filesystemtype.is_null() {
         return -libc::EINVAL;
@@ -66,9 +71,22 @@ pub fn mount(
         }
     };
 
-    let Some(dir) = path::lookup_path(target) else {
-        warn!("[mount] Invalid target path: {}", target);
-        return -libc::EINVAL;
+    // The file system specific options, if any, are a string.
+    let data = if data.is_null() {
+        ""
+    } else {
+        match unsafe { CStr::from_ptr(data as *const c_char).to_str() } {
+            Ok(s) => s,
+            Err(_) => return -libc::EINVAL,
+        }
+    };
+
+    let dir = match path::resolve(target) {
+        Ok(dir) => dir,
+        Err(e) => {
+            warn!("[mount] Invalid target path: {}", target);
+            return e.to_errno();
+        }
     };
 
     if dir.inode().type_() != InodeFileType::Directory {
@@ -81,7 +99,7 @@ pub fn mount(
         return -libc::EEXIST;
     }
 
-    let fs = match mount::get_fs(fs_type, device.unwrap_or("")) {
+    let fs = match mount::get_fs(fs_type, device.unwrap_or(""), data) {
         Some(fs) => fs,
         None => {
             warn!("[mount] Invalid filesystem type: {}", fs_type);
@@ -145,11 +163,9 @@ pub fn open(path: *const c_char, flags: c_int, mode: libc::mode_t) -> c_int {
         mode
     );
 
-    let file = {
-        match path::open_path(file_path, flags, mode) {
-            Ok(file) => Arc::new(file),
-            Err(e) => return e.to_errno(),
-        }
+    let file = match path::open_file(file_path, flags, mode) {
+        Ok(file) => file,
+        Err(e) => return e.to_errno(),
     };
 
     let mut fd_manager = get_fd_manager().lock();
@@ -162,16 +178,38 @@ pub fn creat(path: *const c_char, mode: libc::mode_t) -> c_int {
     open(path, flags, mode)
 }
 
-/// Close a file descriptor
+/// Create a pipe, storing the fd of its read end in `fds[0]` and of its
+/// write end in `fds[1]`
+pub fn pipe(fds: *mut c_int) -> c_int {
+    pipe2(fds, 0)
+}
+
+/// Like `pipe`, with O_NONBLOCK and O_CLOEXEC allowed in `flags`
+pub fn pipe2(fds: *mut c_int, flags: c_int) -> c_int {
+    if fds.is_null() {
+        return -libc::EFAULT;
+    }
+    if flags & !(libc::O_NONBLOCK | libc::O_CLOEXEC) != 0 {
+        return -libc::EINVAL;
+    }
+    let (reader, writer) = pipe::pipe(OpenFlags::from_bits_truncate(flags));
+    let mut fd_manager = get_fd_manager().lock();
+    let read_fd = fd_manager.alloc_fd(reader);
+    let write_fd = fd_manager.alloc_fd(writer);
+    unsafe {
+        fds.write(read_fd);
+        fds.add(1).write(write_fd);
+    }
+    0
+}
+
+/// Close a file descriptor. The open file description is closed along with
+/// its last descriptor.
 pub fn close(fd: i32) -> i32 {
-    let file_ops = {
-        let mut fd_manager = get_fd_manager().lock();
-        let entry = match fd_manager.get_file_ops(fd) {
-            Some(entry) => entry,
-            None => return -libc::EBADF,
-        };
-        let _ = fd_manager.free_fd(fd);
-        entry
+    let file_ops = match get_fd_manager().lock().close_fd(fd) {
+        Ok(Some(file_ops)) => file_ops,
+        Ok(None) => return 0,
+        Err(e) => return e.to_errno(),
     };
 
     match file_ops.close() {
@@ -180,6 +218,47 @@ pub fn close(fd: i32) -> i32 {
     }
 }
 
+/// Duplicate a file descriptor into the lowest free one
+pub fn dup(fd: i32) -> c_int {
+    match get_fd_manager().lock().dup_fd(fd, 0, false) {
+        Ok(new_fd) => new_fd,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Duplicate a file descriptor into `new_fd`, closing what it held first
+pub fn dup2(fd: i32, new_fd: i32) -> c_int {
+    if fd == new_fd {
+        return if get_fd_manager().lock().is_valid_fd(fd) {
+            fd
+        } else {
+            -libc::EBADF
+        };
+    }
+    dup3(fd, new_fd, 0)
+}
+
+/// Like `dup2`, but `new_fd` can be made close-on-exec with O_CLOEXEC, and
+/// must differ from `fd`
+pub fn dup3(fd: i32, new_fd: i32, flags: c_int) -> c_int {
+    if fd == new_fd || flags & !libc::O_CLOEXEC != 0 {
+        return -libc::EINVAL;
+    }
+
+    let replaced = match get_fd_manager()
+        .lock()
+        .dup_fd_to(fd, new_fd, flags & libc::O_CLOEXEC != 0)
+    {
+        Ok(replaced) => replaced,
+        Err(e) => return e.to_errno(),
+    };
+    // Like close(), errors closing the replaced file are ignored.
+    if let Some(file_ops) = replaced {
+        let _ = file_ops.close();
+    }
+    new_fd
+}
+
 /// Read from a file
 pub fn read(fd: i32, buf: *mut u8, count: usize) -> isize {
     if buf.is_null() {
@@ -190,12 +269,9 @@ pub fn read(fd: i32, buf: *mut u8, count: usize) -> isize {
         return 0;
     }
 
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF as isize,
-        }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF as isize,
     };
 
     let slice = unsafe { slice::from_raw_parts_mut(buf, count) };
@@ -215,12 +291,9 @@ pub fn write(fd: i32, buf: *const u8, count: usize) -> isize {
         return 0;
     }
 
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF as isize,
-        }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF as isize,
     };
 
     let slice = unsafe { slice::from_raw_parts(buf, count) };
@@ -230,6 +303,219 @@ pub fn write(fd: i32, buf: *const u8, count: usize) -> isize {
     }
 }
 
+/// Size of the kernel buffer `splice` hands data over through.
+const SPLICE_BUFFER_SIZE: usize = 512;
+
+/// Move up to `len` bytes from `fd_in` to `fd_out` inside the kernel
+///
+/// Data is read into a ring buffer and written out from it, so a short
+/// write on `fd_out` is retried from where it stopped instead of being
+/// bounced back to the caller. Returns the number of bytes written to
+/// `fd_out`, which is less than `len` if `fd_in` reaches its end. Bytes
+/// still buffered when `fd_out` fails are dropped.
+pub fn splice(fd_in: i32, fd_out: i32, len: usize) -> isize {
+    if len == 0 {
+        return 0;
+    }
+
+    let (file_in, file_out) = match (get_file_ops(fd_in), get_file_ops(fd_out)) {
+        (Some(file_in), Some(file_out)) => (file_in, file_out),
+        _ => return -libc::EBADF as isize,
+    };
+
+    let rb = BoxedRingBuffer::new(SPLICE_BUFFER_SIZE.min(len));
+    // Safety: the ring buffer is local, we are the only reader and writer.
+    let mut reader = unsafe { rb.reader() };
+    let mut writer = unsafe { rb.writer() };
+    let mut to_read = len;
+    let mut written = 0;
+    loop {
+        if to_read > 0 && !writer.is_full() {
+            let mut error = None;
+            let n = writer.push(|buf| {
+                let n = buf.len().min(to_read);
+                file_in.read(&mut buf[..n]).unwrap_or_else(|e| {
+                    error = Some(e);
+                    0
+                })
+            });
+            if let Some(e) = error {
+                if written == 0 && reader.is_empty() {
+                    return e.to_errno() as isize;
+                }
+                to_read = 0;
+            } else if n == 0 {
+                // End of input.
+                to_read = 0;
+            } else {
+                to_read -= n;
+            }
+        }
+
+        if reader.is_empty() {
+            break;
+        }
+        let mut error = None;
+        let n = reader.pop(|buf| {
+            file_out.write(buf).unwrap_or_else(|e| {
+                error = Some(e);
+                0
+            })
+        });
+        if let Some(e) = error {
+            if written == 0 {
+                return e.to_errno() as isize;
+            }
+            break;
+        }
+        if n == 0 {
+            break;
+        }
+        written += n;
+    }
+
+    written as isize
+}
+
+// Fills in the `revents` of every entry, returning how many are nonzero.
+// Negative fds are ignored, and closed ones reported as POLLNVAL.
+fn poll_fds(
+    fds: &mut [libc::pollfd],
+    files: &[Option<Arc<dyn FileOps>>],
+    waiter: &Arc<PollWaiter>,
+) -> usize {
+    let mut ready = 0;
+    for (pfd, file) in fds.iter_mut().zip(files) {
+        let events = PollEvents::from_bits_truncate(pfd.events);
+        let revents = match file {
+            Some(file) => file.poll(events, Some(waiter)),
+            None if pfd.fd >= 0 => PollEvents::POLLNVAL,
+            None => PollEvents::empty(),
+        };
+        pfd.revents = (revents & (events | PollEvents::ALWAYS)).bits();
+        if pfd.revents != 0 {
+            ready += 1;
+        }
+    }
+    ready
+}
+
+fn do_poll(fds: *mut libc::pollfd, nfds: usize, timeout: Option<usize>) -> c_int {
+    if fds.is_null() && nfds > 0 {
+        return -libc::EINVAL;
+    }
+    let fds = if nfds == 0 {
+        &mut []
+    } else {
+        unsafe { slice::from_raw_parts_mut(fds, nfds) }
+    };
+    let files: Vec<Option<Arc<dyn FileOps>>> = fds
+        .iter()
+        .map(|pfd| {
+            if pfd.fd < 0 {
+                None
+            } else {
+                get_file_ops(pfd.fd)
+            }
+        })
+        .collect();
+    poll::poll_until(timeout, |waiter| poll_fds(fds, &files, waiter)) as c_int
+}
+
+/// Wait for some of `fds` to become ready
+///
+/// Waits forever if `timeout` is negative, and doesn't wait at all if it's
+/// zero. Returns the number of entries with a nonzero `revents`, which is
+/// 0 if the timeout expired.
+pub fn poll(fds: *mut libc::pollfd, nfds: usize, timeout: c_int) -> c_int {
+    let timeout = match timeout {
+        ..0 => None,
+        0 => Some(0),
+        ms => Some(time::tick_from_millisecond(ms as usize)),
+    };
+    do_poll(fds, nfds, timeout)
+}
+
+/// Like [`poll`], with the timeout given as a `timespec`
+///
+/// A null `tmo` waits forever. Signal masks aren't implemented, so
+/// `sigmask` is ignored.
+pub fn ppoll(
+    fds: *mut libc::pollfd,
+    nfds: usize,
+    tmo: *const Timespec,
+    _sigmask: *const libc::sigset_t,
+) -> c_int {
+    let timeout = if tmo.is_null() {
+        None
+    } else {
+        match time_syscalls::reltime_to_ticks(tmo) {
+            Ok(ticks) => Some(ticks),
+            Err(e) => return e,
+        }
+    };
+    do_poll(fds, nfds, timeout)
+}
+
+/// Create an epoll instance
+pub fn epoll_create1(flags: c_int) -> c_int {
+    if flags & !epoll::EPOLL_CLOEXEC != 0 {
+        return -libc::EINVAL;
+    }
+    let flags = OpenFlags::from_bits_truncate(flags);
+    get_fd_manager()
+        .lock()
+        .alloc_fd(Arc::new(EpollFile::new(flags)))
+}
+
+/// Add, modify or remove an entry in the interest list of `epfd`
+pub fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *const EpollEvent) -> c_int {
+    let (ep, file) = match (get_file_ops(epfd), get_file_ops(fd)) {
+        (Some(ep), Some(file)) => (ep, file),
+        _ => return -libc::EBADF,
+    };
+    let Some(ep) = ep.downcast_ref::<EpollFile>() else {
+        return -libc::EINVAL;
+    };
+    let event = if op == epoll::EPOLL_CTL_DEL {
+        None
+    } else if event.is_null() {
+        return -libc::EFAULT;
+    } else {
+        Some(unsafe { event.read() })
+    };
+    match ep.ctl(op, fd, file, event) {
+        Ok(()) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Wait for events on the interest list of `epfd`
+///
+/// `timeout` is in milliseconds, as for [`poll`]. Returns the number of
+/// events stored in `events`.
+pub fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int {
+    if maxevents <= 0 {
+        return -libc::EINVAL;
+    }
+    if events.is_null() {
+        return -libc::EFAULT;
+    }
+    let Some(ep) = get_file_ops(epfd) else {
+        return -libc::EBADF;
+    };
+    let Some(ep) = ep.downcast_ref::<EpollFile>() else {
+        return -libc::EINVAL;
+    };
+    let timeout = match timeout {
+        ..0 => None,
+        0 => Some(0),
+        ms => Some(time::tick_from_millisecond(ms as usize)),
+    };
+    let events = unsafe { slice::from_raw_parts_mut(events, maxevents as usize) };
+    ep.wait(events, timeout) as c_int
+}
+
 /// Seek in a file
 pub fn lseek(fd: i32, offset: i64, whence: i32) -> i64 {
     debug!(
@@ -248,12 +534,9 @@ pub fn lseek(fd: i32, offset: i64, whence: i32) -> i64 {
         _ => return -libc::EINVAL as i64,
     };
 
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF as i64,
-        }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF as i64,
     };
 
     match file_ops.seek(seek_from) {
@@ -286,12 +569,9 @@ pub fn truncate(path: *const c_char, length: libc::off_t) -> c_int {
 pub fn ftruncate(fd: i32, length: libc::off_t) -> c_int {
     debug!("ftruncate: fd = {}, length = {}", fd, length);
 
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF,
-        }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF,
     };
 
     match file_ops.resize(length as usize) {
@@ -321,18 +601,11 @@ pub fn fcntl(fd: i32, cmd: c_int, args: usize) -> c_int {
             };
             new_fd as c_int
         }
-        libc::F_GETFD => {
-            let fd_manager = get_fd_manager().lock();
-            let fd_entry = match fd_manager.get_file_ops(fd) {
-                Some(entry) => entry,
-                None => return -libc::EBADF,
-            };
-            if fd_entry.flags().contains(OpenFlags::O_CLOEXEC) {
-                FD_CLOEXEC
-            } else {
-                0
-            }
-        }
+        libc::F_GETFD => match get_fd_manager().lock().close_on_exec(fd) {
+            Ok(true) => FD_CLOEXEC,
+            Ok(false) => 0,
+            Err(e) => e.to_errno(),
+        },
         libc::F_SETFD => {
             let flags = args as c_int;
             if flags & !FD_CLOEXEC != 0 {
@@ -340,41 +613,30 @@ pub fn fcntl(fd: i32, cmd: c_int, args: usize) -> c_int {
             }
 
             let is_cloexec = (args as c_int) & FD_CLOEXEC != 0;
-
-            let fd_manager = get_fd_manager().lock();
-            let fd_entry = match fd_manager.get_file_ops(fd) {
-                Some(entry) => entry,
-                None => return -libc::EBADF,
-            };
-            if is_cloexec {
-                fd_entry.set_flags(fd_entry.flags() | OpenFlags::O_CLOEXEC);
-            } else {
-                fd_entry.set_flags(fd_entry.flags() & !OpenFlags::O_CLOEXEC);
+            match get_fd_manager().lock().set_close_on_exec(fd, is_cloexec) {
+                Ok(_) => 0,
+                Err(e) => e.to_errno(),
             }
-            0
         }
         libc::F_GETFL => {
-            let fd_manager = get_fd_manager().lock();
-            let fd_entry = match fd_manager.get_file_ops(fd) {
+            let fd_entry = match get_file_ops(fd) {
                 Some(entry) => entry,
                 None => return -libc::EBADF,
             };
-            fd_entry.flags().bits() as c_int
+            // O_CLOEXEC belongs to the descriptor, not the open file.
+            (fd_entry.flags() - OpenFlags::O_CLOEXEC).bits() as c_int
         }
         libc::F_SETFL => {
-            // this operation can change only O_NONBLOCK for now
-            let fd_manager = get_fd_manager().lock();
-            let fd_entry = match fd_manager.get_file_ops(fd) {
+            // this operation can change only O_APPEND and O_NONBLOCK for now,
+            // for every descriptor sharing the open file
+            let fd_entry = match get_file_ops(fd) {
                 Some(entry) => entry,
                 None => return -libc::EBADF,
             };
 
-            let oflags = args as c_int;
-            if oflags & libc::O_NONBLOCK == 0 {
-                fd_entry.set_flags(fd_entry.flags() & !OpenFlags::O_NONBLOCK);
-            } else {
-                fd_entry.set_flags(fd_entry.flags() | OpenFlags::O_NONBLOCK);
-            }
+            let settable = OpenFlags::O_APPEND | OpenFlags::O_NONBLOCK;
+            let oflags = OpenFlags::from(args as c_int) & settable;
+            fd_entry.set_flags((fd_entry.flags() - settable) | oflags);
             0
         }
 
@@ -422,6 +684,103 @@ pub fn link(old_path: *const c_char, new_path: *const c_char) -> c_int {
     }
 }
 
+/// Create the symbolic link `link_path` pointing to `target`
+pub fn symlink(target: *const c_char, link_path: *const c_char) -> c_int {
+    if target.is_null() || link_path.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let target = match unsafe { CStr::from_ptr(target).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let link_path = match unsafe { CStr::from_ptr(link_path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    if target.is_empty() {
+        return -libc::ENOENT;
+    }
+
+    let Some((dir, name)) = path::find_parent_and_name(link_path) else {
+        warn!("[symlink] Invalid path: {}", link_path);
+        return -libc::ENOENT;
+    };
+
+    debug!("[symlink] {} -> {}", link_path, target);
+
+    match dir.symlink(name, target) {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Copy the target of the symbolic link `path` to `buf`, without a
+/// terminating null byte, truncating it to `len` bytes
+pub fn readlink(path: *const c_char, buf: *mut c_char, len: usize) -> isize {
+    if path.is_null() || buf.is_null() || len == 0 {
+        return -libc::EINVAL as isize;
+    }
+
+    let path_str = match unsafe { CStr::from_ptr(path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL as isize,
+    };
+
+    let dir_entry = match path::resolve_nofollow(path_str) {
+        Ok(entry) => entry,
+        Err(e) => return e.to_errno() as isize,
+    };
+    let target = match dir_entry.inode().readlink() {
+        Ok(target) => target,
+        Err(e) => return e.to_errno() as isize,
+    };
+    let copy_len = target.len().min(len);
+    unsafe {
+        copy_nonoverlapping(target.as_ptr(), buf as *mut u8, copy_len);
+    }
+    copy_len as isize
+}
+
+/// Rename a file or directory, replacing `new_path` if it exists
+pub fn rename(old_path: *const c_char, new_path: *const c_char) -> c_int {
+    if old_path.is_null() || new_path.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let old_path = match unsafe { CStr::from_ptr(old_path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let new_path = match unsafe { CStr::from_ptr(new_path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let Some((old_dir, old_name)) = path::find_parent_and_name(old_path) else {
+        warn!("[rename] Invalid path: {}", old_path);
+        return -libc::ENOENT;
+    };
+    let Some((new_dir, new_name)) = path::find_parent_and_name(new_path) else {
+        warn!("[rename] Invalid path: {}", new_path);
+        return -libc::ENOENT;
+    };
+
+    debug!("[rename] {} -> {}", old_path, new_path);
+
+    match old_dir.rename(
+        old_name.trim_end_matches('/'),
+        &new_dir,
+        new_name.trim_end_matches('/'),
+    ) {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
 pub fn unlink(path: *const c_char) -> c_int {
     if path.is_null() {
         return -libc::EINVAL;
@@ -476,6 +835,67 @@ pub fn mkdir(path: *const c_char, mode: libc::mode_t) -> i32 {
     }
 }
 
+/// Create a FIFO, which is opened as an end of a pipe shared by its opens
+pub fn mkfifo(path: *const c_char, mode: libc::mode_t) -> c_int {
+    if path.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let file_path = match unsafe { CStr::from_ptr(path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let Some((dir, name)) = path::find_parent_and_name(file_path) else {
+        return -libc::ENOENT;
+    };
+
+    match dir.new_child(name, InodeFileType::Fifo, InodeMode::from(mode), || None) {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Create a file of the type in `mode`. A character or block special file
+/// stands for the device numbered `dev`, which is looked up when it's
+/// opened.
+pub fn mknod(path: *const c_char, mode: libc::mode_t, dev: libc::dev_t) -> c_int {
+    if path.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let file_path = match unsafe { CStr::from_ptr(path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let Some((dir, name)) = path::find_parent_and_name(file_path) else {
+        return -libc::ENOENT;
+    };
+
+    let type_ = match mode & libc::S_IFMT {
+        0 => InodeFileType::Regular,
+        _ => InodeFileType::from(mode),
+    };
+    let result = match type_ {
+        InodeFileType::Regular | InodeFileType::Fifo => {
+            dir.new_child(name, type_, InodeMode::from(mode), || None)
+        }
+        InodeFileType::CharDevice | InodeFileType::BlockDevice => dir.mknod(
+            name,
+            type_,
+            InodeMode::from(mode),
+            DeviceId::from(dev as usize),
+        ),
+        InodeFileType::Directory => return -libc::EPERM,
+        _ => return -libc::EINVAL,
+    };
+    match result {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
 pub fn rmdir(path: *const c_char) -> c_int {
     if path.is_null() {
         return -libc::EINVAL;
@@ -503,12 +923,24 @@ pub fn rmdir(path: *const c_char) -> c_int {
 }
 
 pub fn getdents(fd: i32, buf: *mut u8, buf_len: usize) -> c_int {
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF,
-        }
+    do_getdents(fd, buf, buf_len, DirentFormat::Native)
+}
+
+/// Read directory entries as `linux_dirent64` records
+///
+/// The `d_off` of each record can be passed to `lseek` to continue reading
+/// after it.
+pub fn getdents64(fd: i32, buf: *mut u8, buf_len: usize) -> c_int {
+    do_getdents(fd, buf, buf_len, DirentFormat::Dirent64)
+}
+
+fn do_getdents(fd: i32, buf: *mut u8, buf_len: usize, format: DirentFormat) -> c_int {
+    if buf.is_null() {
+        return -libc::EFAULT;
+    }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF,
     };
 
     let file = match file_ops.downcast_ref::<File>() {
@@ -520,7 +952,7 @@ pub fn getdents(fd: i32, buf: *mut u8, buf_len: usize) -> c_int {
     }
 
     let buf = unsafe { slice::from_raw_parts_mut(buf, buf_len) };
-    let mut reader = DirBufferReader::new(buf);
+    let mut reader = DirBufferReader::with_format(buf, format);
 
     match file.getdents(&mut reader) {
         Ok(_) => reader.recv_len() as c_int,
@@ -529,6 +961,7 @@ pub fn getdents(fd: i32, buf: *mut u8, buf_len: usize) -> c_int {
 }
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy)]
 pub struct Timespec {
     pub tv_sec: libc::time_t,
     pub tv_nsec: libc::c_long,
@@ -540,7 +973,7 @@ impl From<Duration> for Timespec {
     fn from(duration: Duration) -> Timespec {
         let sec = duration.as_secs() as libc::time_t;
         let nsec = duration.subsec_nanos() as libc::c_long;
-        debug_assert!(sec >= 0); // nsec >= 0 always holds
+        crate::kassert!(sec >= 0); // nsec >= 0 always holds
         Timespec {
             tv_sec: sec,
             tv_nsec: nsec,
@@ -617,15 +1050,34 @@ pub fn stat(path: *const c_char, buf: *mut Stat) -> c_int {
     0
 }
 
+/// Like `stat`, but a symbolic link is reported itself
+pub fn lstat(path: *const c_char, buf: *mut Stat) -> c_int {
+    if path.is_null() || buf.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let path_str = match unsafe { CStr::from_ptr(path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let dir_entry = match path::resolve_nofollow(path_str) {
+        Ok(entry) => entry,
+        Err(e) => return e.to_errno(),
+    };
+    let stat = Stat::from(dir_entry.inode().file_attr());
+    unsafe {
+        copy_nonoverlapping(&stat, buf, 1);
+    }
+    0
+}
+
 pub fn fstat(fd: i32, buf: *mut Stat) -> c_int {
     debug!("fstat: fd = {}", fd);
 
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF,
-        }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF,
     };
 
     let file_attr = file_ops.stat();
@@ -702,12 +1154,9 @@ pub fn statfs(path: *const c_char, buf: *mut Statfs) -> c_int {
 pub fn fstatfs(fd: i32, buf: *mut Statfs) -> c_int {
     debug!("fstat: fd = {}", fd);
 
-    let file_ops = {
-        let fd_manager = get_fd_manager().lock();
-        match fd_manager.get_file_ops(fd) {
-            Some(ops) => ops,
-            None => return -libc::EBADF,
-        }
+    let file_ops = match get_file_ops(fd) {
+        Some(ops) => ops,
+        None => return -libc::EBADF,
     };
     let file = match file_ops.downcast_ref::<File>() {
         Some(file) => file,
@@ -732,10 +1181,13 @@ pub fn chdir(path: *const c_char) -> c_int {
         Err(_) => return -libc::EINVAL,
     };
 
-    let dir_entry = match path::lookup_path(path_str) {
-        Some(entry) => entry,
-        None => return -libc::EINVAL,
+    let dir_entry = match path::resolve(path_str) {
+        Ok(entry) => entry,
+        Err(e) => return e.to_errno(),
     };
+    if dir_entry.type_() != InodeFileType::Directory {
+        return -libc::ENOTDIR;
+    }
 
     match path::set_working_dir(dir_entry.clone()) {
         Ok(_) => 0,
@@ -743,13 +1195,95 @@ pub fn chdir(path: *const c_char) -> c_int {
     }
 }
 
+/// Map `len` bytes of the file `fd` from `offset`, or zeroed memory with
+/// MAP_ANONYMOUS. Returns the address of the mapping, or a negative errno.
+pub fn mmap(
+    addr: *mut c_void,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: libc::off_t,
+) -> isize {
+    let shared = match flags & (libc::MAP_SHARED | libc::MAP_PRIVATE) {
+        libc::MAP_SHARED => true,
+        libc::MAP_PRIVATE => false,
+        _ => return -libc::EINVAL as isize,
+    };
+    // There's a single address space, so the address is only a hint.
+    if flags & libc::MAP_FIXED != 0 || offset < 0 {
+        return -libc::EINVAL as isize;
+    }
+    let file = if flags & libc::MAP_ANONYMOUS != 0 {
+        None
+    } else {
+        match get_file_ops(fd) {
+            Some(file) => Some(file),
+            None => return -libc::EBADF as isize,
+        }
+    };
+
+    let writable = prot & libc::PROT_WRITE != 0;
+    debug!("[mmap] fd = {}, len = {}, offset = {}", fd, len, offset);
+    match mmap::mmap(file, len, offset as usize, shared, writable) {
+        Ok(addr) => addr as isize,
+        Err(e) => e.to_errno() as isize,
+    }
+}
+
+/// Remove the mapping at `addr`, which must be removed as a whole
+pub fn munmap(addr: *mut c_void, len: usize) -> c_int {
+    match mmap::munmap(addr as usize, len) {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Write the dirty pages of a shared mapping back to its file
+pub fn msync(addr: *mut c_void, len: usize, flags: c_int) -> c_int {
+    let sync = flags & (libc::MS_SYNC | libc::MS_ASYNC);
+    if flags & !(libc::MS_SYNC | libc::MS_ASYNC | libc::MS_INVALIDATE) != 0
+        || sync == libc::MS_SYNC | libc::MS_ASYNC
+    {
+        return -libc::EINVAL;
+    }
+    // Write-back is always synchronous, and the mapping never holds stale
+    // pages to invalidate as it's the file's page cache.
+    match mmap::msync(addr as usize, len) {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Change the root directory that absolute paths start from
+pub fn chroot(path: *const c_char) -> c_int {
+    if path.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let path_str = match unsafe { CStr::from_ptr(path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let dir_entry = match path::resolve(path_str) {
+        Ok(entry) => entry,
+        Err(e) => return e.to_errno(),
+    };
+
+    match path::set_process_root(dir_entry) {
+        Ok(_) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
 pub fn getcwd(buf: *mut c_char, len: usize) -> c_int {
     if buf.is_null() || len == 0 {
         return -libc::EINVAL;
     }
 
     let cwd = path::get_working_dir();
-    let cwd_str = cwd.get_full_path();
+    let cwd_str = path::absolute_path(&cwd);
     let cwd_str_len = cwd_str.len();
     if cwd_str_len > len - 1 {
         return -libc::ERANGE;
@@ -761,6 +1295,32 @@ pub fn getcwd(buf: *mut c_char, len: usize) -> c_int {
     cwd_str_len as c_int
 }
 
+/// Writes the canonical absolute path of `path` to `buf`, which holds `len`
+/// bytes. Returns the length of the path, or a negative errno.
+pub fn realpath(path: *const c_char, buf: *mut c_char, len: usize) -> c_int {
+    if path.is_null() || buf.is_null() || len == 0 {
+        return -libc::EINVAL;
+    }
+
+    let path_str = match unsafe { CStr::from_ptr(path).to_str() } {
+        Ok(s) => s,
+        Err(_) => return -libc::EINVAL,
+    };
+
+    let resolved = match path::realpath(path_str) {
+        Ok(resolved) => resolved,
+        Err(e) => return e.to_errno(),
+    };
+    if resolved.len() > len - 1 {
+        return -libc::ENAMETOOLONG;
+    }
+    unsafe {
+        copy_nonoverlapping(resolved.as_ptr(), buf as *mut u8, resolved.len());
+        *(buf as *mut u8).add(resolved.len()) = 0;
+    }
+    resolved.len() as c_int
+}
+
 /// Convert open flags to readable string for debugging
 fn flags_to_string(flags: c_int) -> String {
     let mut result = String::new();
@@ -805,6 +1365,45 @@ fn flags_to_string(flags: c_int) -> String {
     result
 }
 
+mod ffi {
+    use super::*;
+    use crate::{allocator, vfs::utils::PATH_MAX};
+    use core::ptr;
+
+    /// Canonicalizes `path` into `resolved`, which must hold `PATH_MAX`
+    /// bytes, or into a buffer from `malloc` if `resolved` is null. Returns
+    /// null on failure.
+    #[no_mangle]
+    #[linkage = "weak"]
+    pub extern "C" fn realpath(path: *const c_char, resolved: *mut c_char) -> *mut c_char {
+        if path.is_null() {
+            return ptr::null_mut();
+        }
+        let path_str = match unsafe { CStr::from_ptr(path).to_str() } {
+            Ok(s) => s,
+            Err(_) => return ptr::null_mut(),
+        };
+        let canonical = match path::realpath(path_str) {
+            Ok(canonical) if canonical.len() < PATH_MAX => canonical,
+            _ => return ptr::null_mut(),
+        };
+
+        let buf = if resolved.is_null() {
+            allocator::malloc(canonical.len() + 1)
+        } else {
+            resolved as *mut u8
+        };
+        if buf.is_null() {
+            return ptr::null_mut();
+        }
+        unsafe {
+            copy_nonoverlapping(canonical.as_ptr(), buf, canonical.len());
+            *buf.add(canonical.len()) = 0;
+        }
+        buf as *mut c_char
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
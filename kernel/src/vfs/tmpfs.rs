@@ -13,7 +13,8 @@
 // limitations under the License.
 
 use crate::{
-    devices::Device,
+    allocator,
+    devices::{Device, DeviceId},
     error::{code, Error},
     vfs::{
         dcache::Dcache,
@@ -22,6 +23,7 @@ use crate::{
         fs::{FileSystem, FileSystemInfo},
         inode::{InodeAttr, InodeNo, InodeOps},
         inode_mode::{InodeFileType, InodeMode},
+        poll::{PollEvents, PollWaiter},
         utils::NAME_MAX,
     },
 };
@@ -32,6 +34,7 @@ use alloc::{
     vec::Vec,
 };
 use core::{
+    mem, ptr,
     sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     time::Duration,
 };
@@ -48,9 +51,62 @@ enum TmpFileData {
     Directory(TmpDir),
     File(Vec<u8>),
     Device(Arc<dyn Device>),
-    // TODO: support symlink
-    // SymLink(String),
+    // A special file made by mknod, bound to its device when opened.
+    Special(DeviceId),
+    SymLink(String),
     Socket(),
+    Fifo(),
+}
+
+/// Capacity of a tmpfs mount, from the `data` argument of mount as in
+/// "size=64k,nr_inodes=32". Zero means no limit but the free memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TmpfsLimits {
+    pub max_blocks: usize,
+    pub max_inodes: usize,
+}
+
+impl TmpfsLimits {
+    pub fn parse(options: &str) -> Result<Self, Error> {
+        let mut limits = Self::default();
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or(code::EINVAL)?;
+            let value = parse_size(value)?;
+            match key {
+                "size" => limits.max_blocks = value.div_ceil(BLOCK_SIZE),
+                "nr_blocks" => limits.max_blocks = value,
+                "nr_inodes" => limits.max_inodes = value,
+                _ => {
+                    warn!("tmpfs: unknown mount option {}", key);
+                    return Err(code::EINVAL);
+                }
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// Parses a number with an optional k, m or g suffix
+pub(super) fn parse_size(value: &str) -> Result<usize, Error> {
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 10),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 20),
+        Some(b'g' | b'G') => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    let value = digits.parse::<usize>().map_err(|_| code::EINVAL)?;
+    value.checked_mul(1 << shift).ok_or(code::EINVAL)
+}
+
+/// Takes `n` from the counter `used`, unless it would go past `max`, which
+/// is no limit if zero
+fn charge(used: &AtomicUsize, max: usize, n: usize) -> Result<(), Error> {
+    used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+        let used = used.checked_add(n)?;
+        (max == 0 || used <= max).then_some(used)
+    })
+    .map(|_| ())
+    .map_err(|_| code::ENOSPC)
 }
 
 #[derive(Debug)]
@@ -60,10 +116,18 @@ pub struct TmpFileSystem {
     next_inode_no: AtomicUsize,
     fs_info: FileSystemInfo,
     is_mounted: AtomicBool,
+    limits: TmpfsLimits,
+    // Blocks taken by file data, and inodes in use including the root
+    used_blocks: AtomicUsize,
+    used_inodes: AtomicUsize,
 }
 
 impl TmpFileSystem {
     pub fn new() -> Arc<Self> {
+        Self::with_limits(TmpfsLimits::default())
+    }
+
+    pub fn with_limits(limits: TmpfsLimits) -> Arc<Self> {
         Arc::new_cyclic(|weak_fs| Self {
             root: Arc::new_cyclic(|weak_root| TmpInode {
                 inner: RwLock::new(InnerNode {
@@ -83,12 +147,29 @@ impl TmpFileSystem {
             next_inode_no: AtomicUsize::new(ROOT_INO + 1),
             is_mounted: AtomicBool::new(false),
             fs_info: FileSystemInfo::new(MAGIC, 0, NAME_MAX, BLOCK_SIZE, 0),
+            limits,
+            used_blocks: AtomicUsize::new(0),
+            used_inodes: AtomicUsize::new(1),
         })
     }
 
-    /// Allocate new inode number
-    fn alloc_inode_no(&self) -> InodeNo {
-        self.next_inode_no.fetch_add(1, Ordering::Relaxed)
+    /// Allocate new inode number, failing with ENOSPC once the inode limit
+    /// is reached
+    fn alloc_inode_no(&self) -> Result<InodeNo, Error> {
+        charge(&self.used_inodes, self.limits.max_inodes, 1)?;
+        Ok(self.next_inode_no.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn free_inode(&self) {
+        self.used_inodes.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn alloc_blocks(&self, n: usize) -> Result<(), Error> {
+        charge(&self.used_blocks, self.limits.max_blocks, n)
+    }
+
+    fn free_blocks(&self, n: usize) {
+        self.used_blocks.fetch_sub(n, Ordering::Relaxed);
     }
 
     fn check_mounted(&self) -> bool {
@@ -121,7 +202,25 @@ impl FileSystem for TmpFileSystem {
         self.root.clone()
     }
     fn fs_info(&self) -> FileSystemInfo {
-        self.fs_info.clone()
+        let mut info = self.fs_info.clone();
+        let used_blocks = self.used_blocks.load(Ordering::Relaxed);
+        let used_inodes = self.used_inodes.load(Ordering::Relaxed);
+        // Without a limit, what's left is bound by the free memory.
+        let heap = allocator::memory_info();
+        let free_memory = heap.total.saturating_sub(heap.used);
+        info.bfree = match self.limits.max_blocks {
+            0 => free_memory / BLOCK_SIZE,
+            max => max.saturating_sub(used_blocks),
+        };
+        info.ffree = match self.limits.max_inodes {
+            0 => free_memory / mem::size_of::<TmpInode>(),
+            max => max.saturating_sub(used_inodes),
+        };
+        info.blocks = used_blocks + info.bfree;
+        info.bavail = info.bfree;
+        info.files = used_inodes + info.ffree;
+        info.favail = info.ffree;
+        info
     }
     fn fs_type(&self) -> &str {
         "tmpfs"
@@ -226,6 +325,32 @@ impl TmpInode {
         })
     }
 
+    fn new_symlink(
+        fs: &Weak<TmpFileSystem>,
+        inode_no: InodeNo,
+        uid: u32,
+        gid: u32,
+        target: &str,
+    ) -> Arc<Self> {
+        let mut attr = InodeAttr::new(
+            inode_no,
+            InodeFileType::SymLink,
+            InodeMode::from_bits_truncate(0o777),
+            uid,
+            gid,
+            0,
+        );
+        attr.set_size(target.len());
+        Arc::new_cyclic(|weak_inode| Self {
+            inner: RwLock::new(InnerNode {
+                attr,
+                data: TmpFileData::SymLink(String::from(target)),
+            }),
+            this: weak_inode.clone(),
+            fs: fs.clone(),
+        })
+    }
+
     fn new_socket(
         fs: &Weak<TmpFileSystem>,
         inode_no: InodeNo,
@@ -242,6 +367,82 @@ impl TmpInode {
             fs: fs.clone(),
         })
     }
+
+    fn new_fifo(
+        fs: &Weak<TmpFileSystem>,
+        inode_no: InodeNo,
+        mode: InodeMode,
+        uid: u32,
+        gid: u32,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|weak_inode| Self {
+            inner: RwLock::new(InnerNode {
+                attr: InodeAttr::new(inode_no, InodeFileType::Fifo, mode, uid, gid, 0),
+                data: TmpFileData::Fifo(),
+            }),
+            this: weak_inode.clone(),
+            fs: fs.clone(),
+        })
+    }
+
+    fn new_special(
+        fs: &Weak<TmpFileSystem>,
+        inode_no: InodeNo,
+        type_: InodeFileType,
+        mode: InodeMode,
+        rdev: DeviceId,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|weak_inode| Self {
+            inner: RwLock::new(InnerNode {
+                attr: InodeAttr::new(inode_no, type_, mode, 0, 0, 0),
+                data: TmpFileData::Special(rdev),
+            }),
+            this: weak_inode.clone(),
+            fs: fs.clone(),
+        })
+    }
+
+    /// Resizes the data of a regular file, taking the blocks it grows by
+    /// from the file system or giving back those it shrinks by.
+    fn resize_data(&self, inner: &mut InnerNode, size: usize) -> Result<(), Error> {
+        let blocks = size.div_ceil(BLOCK_SIZE);
+        let old_blocks = inner.attr.size.div_ceil(BLOCK_SIZE);
+        if let Some(fs) = self.fs.upgrade() {
+            if blocks > old_blocks {
+                fs.alloc_blocks(blocks - old_blocks)?;
+            } else {
+                fs.free_blocks(old_blocks - blocks);
+            }
+        }
+        inner.as_file_mut().unwrap().resize(size, 0);
+        inner.attr.size = size;
+        inner.attr.blocks = blocks;
+        Ok(())
+    }
+
+    // Checks that `inode` may replace `replaced`: a directory can only
+    // replace an empty directory, and anything else only something which
+    // isn't a directory.
+    fn check_replace(inode: &TmpInode, replaced: &TmpInode) -> Result<(), Error> {
+        let is_dir = inode.inner.read().as_dir().is_some();
+        match (is_dir, replaced.inner.read().as_dir()) {
+            (true, Some(dir)) if !dir.children.is_empty() => Err(code::ENOTEMPTY),
+            (true, Some(_)) | (false, None) => Ok(()),
+            (true, None) => Err(code::ENOTDIR),
+            (false, Some(_)) => Err(code::EISDIR),
+        }
+    }
+
+    // Drops the links of an entry of `parent` which a rename replaced.
+    fn drop_replaced(parent: &mut InnerNode, replaced: &TmpInode) {
+        let mut replaced = replaced.inner.write();
+        if replaced.as_dir().is_some() {
+            // Its ".." entry and the link from its own "." go away too.
+            parent.dec_nlinks();
+            replaced.dec_nlinks();
+        }
+        replaced.dec_nlinks();
+    }
 }
 
 #[derive(Debug)]
@@ -326,14 +527,18 @@ impl InodeOps for TmpInode {
             return Err(code::EEXIST);
         }
 
-        let ino = self.fs.upgrade().unwrap().alloc_inode_no();
+        if !matches!(
+            type_,
+            InodeFileType::Directory | InodeFileType::Regular | InodeFileType::Fifo
+        ) {
+            warn!("create: unsupported file type: {:?}", type_);
+            return Err(code::EINVAL);
+        }
+        let ino = self.fs.upgrade().unwrap().alloc_inode_no()?;
         let inode = match type_ {
             InodeFileType::Directory => TmpInode::new_dir(&self.fs, ino, mode, 0, 0, &self.this),
-            InodeFileType::Regular => TmpInode::new_file(&self.fs, ino, mode, 0, 0),
-            _ => {
-                warn!("create: unsupported file type: {:?}", type_);
-                return Err(code::EINVAL);
-            }
+            InodeFileType::Fifo => TmpInode::new_fifo(&self.fs, ino, mode, 0, 0),
+            _ => TmpInode::new_file(&self.fs, ino, mode, 0, 0),
         };
         dir.insert(name, &inode);
         if type_ == InodeFileType::Directory {
@@ -365,9 +570,12 @@ impl InodeOps for TmpInode {
             return Err(code::EEXIST);
         }
 
-        device.open()?;
-
-        let ino = self.fs.upgrade().unwrap().alloc_inode_no();
+        let fs = self.fs.upgrade().unwrap();
+        let ino = fs.alloc_inode_no()?;
+        if let Err(e) = device.open() {
+            fs.free_inode();
+            return Err(e);
+        }
         let inode = TmpInode::new_device(&self.fs, ino, mode, 0, 0, device);
         dir.insert(name, &inode);
         inner.inc_size();
@@ -375,37 +583,100 @@ impl InodeOps for TmpInode {
         Ok(inode)
     }
 
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn InodeOps>, Error> {
+        assert!(self.type_() == InodeFileType::Directory);
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        if name == "." || name == ".." {
+            return Err(code::EEXIST);
+        }
+
+        let mut inner = self.inner.write();
+        let dir = inner.as_dir_mut().unwrap();
+        if dir.find(name).is_some() {
+            return Err(code::EEXIST);
+        }
+
+        let ino = self.fs.upgrade().unwrap().alloc_inode_no()?;
+        let inode = TmpInode::new_symlink(&self.fs, ino, 0, 0, target);
+        dir.insert(name, &inode);
+        inner.inc_size();
+
+        Ok(inode)
+    }
+
+    fn mknod(
+        &self,
+        name: &str,
+        type_: InodeFileType,
+        mode: InodeMode,
+        rdev: DeviceId,
+    ) -> Result<Arc<dyn InodeOps>, Error> {
+        assert!(self.type_() == InodeFileType::Directory);
+        if name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        if name == "." || name == ".." {
+            return Err(code::EEXIST);
+        }
+        if !matches!(
+            type_,
+            InodeFileType::CharDevice | InodeFileType::BlockDevice
+        ) {
+            return Err(code::EINVAL);
+        }
+
+        let mut inner = self.inner.write();
+        let dir = inner.as_dir_mut().unwrap();
+        if dir.find(name).is_some() {
+            return Err(code::EEXIST);
+        }
+
+        let ino = self.fs.upgrade().unwrap().alloc_inode_no()?;
+        let inode = TmpInode::new_special(&self.fs, ino, type_, mode, rdev);
+        dir.insert(name, &inode);
+        inner.inc_size();
+
+        Ok(inode)
+    }
+
+    fn readlink(&self) -> Result<String, Error> {
+        match &self.inner.read().data {
+            TmpFileData::SymLink(target) => Ok(target.clone()),
+            _ => Err(code::EINVAL),
+        }
+    }
+
     fn create_socket(&self, mode: InodeMode) -> Result<Arc<dyn InodeOps>, Error> {
         assert!(self.type_() == InodeFileType::Directory);
         let mut inner = self.inner.write();
-        let ino = self.fs.upgrade().unwrap().alloc_inode_no();
+        let ino = self.fs.upgrade().unwrap().alloc_inode_no()?;
         let inode = TmpInode::new_socket(&self.fs, ino, mode, 0, 0);
         inner.inc_size();
 
         Ok(inode)
     }
 
-    fn close(&self) -> Result<(), Error> {
+    fn poll(&self, events: PollEvents, waiter: Option<&Arc<PollWaiter>>) -> PollEvents {
         let inner = self.inner.read();
-        if let Some(device) = inner.as_device() {
-            device.close()?;
+        match inner.as_device() {
+            Some(device) => device.poll(events, waiter),
+            None => PollEvents::ready(events),
         }
-        Ok(())
     }
 
     fn read_at(&self, offset: usize, buf: &mut [u8], nonblock: bool) -> Result<usize, Error> {
         let inner = self.inner.read();
         if let Some(device) = inner.as_device() {
-            return device
-                .read(offset as u64, buf, nonblock)
-                .map_err(Error::from);
+            return device.read(offset as u64, buf, nonblock);
         }
 
         let Some(data) = inner.as_file() else {
             warn!("read_at: inode is not a file");
             return Err(code::EISDIR);
         };
-        debug_assert!(data.len() == inner.attr.size);
+        crate::kassert!(data.len() == inner.attr.size);
         let file_size = inner.attr.size;
         let read_pos = file_size.min(offset);
         let read_end = file_size.min(offset + buf.len());
@@ -418,29 +689,18 @@ impl InodeOps for TmpInode {
     fn write_at(&self, offset: usize, buf: &[u8], nonblock: bool) -> Result<usize, Error> {
         let mut inner = self.inner.write();
         if let Some(device) = inner.as_device() {
-            return device
-                .write(offset as u64, buf, nonblock)
-                .map_err(Error::from);
+            return device.write(offset as u64, buf, nonblock);
         }
 
-        let write_end = offset + buf.len();
-        let need_resize;
-        {
-            let file_size = inner.attr.size;
-            let Some(data) = inner.as_file_mut() else {
-                warn!("write_at: inode is not a file");
-                return Err(code::EISDIR);
-            };
-
-            need_resize = write_end > file_size;
-            if need_resize {
-                data.resize(write_end, 0);
-            }
-            data[offset..write_end].copy_from_slice(buf);
+        if inner.as_file().is_none() {
+            warn!("write_at: inode is not a file");
+            return Err(code::EISDIR);
         }
-        if need_resize {
-            inner.attr.size = write_end;
+        let write_end = offset + buf.len();
+        if write_end > inner.attr.size {
+            self.resize_data(&mut inner, write_end)?;
         }
+        inner.as_file_mut().unwrap()[offset..write_end].copy_from_slice(buf);
 
         Ok(buf.len())
     }
@@ -542,6 +802,83 @@ impl InodeOps for TmpInode {
         Ok(())
     }
 
+    fn rename(
+        &self,
+        old_name: &str,
+        target: &Arc<dyn InodeOps>,
+        new_name: &str,
+    ) -> Result<(), Error> {
+        if new_name.len() > NAME_MAX {
+            return Err(code::ENAMETOOLONG);
+        }
+        let target = target.downcast_ref::<TmpInode>().ok_or(code::EXDEV)?;
+        if ptr::eq(self, target) {
+            let mut inner = self.inner.write();
+            let Some(dir) = inner.as_dir_mut() else {
+                debug!("rename: inode is not a directory");
+                return Err(code::ENOTDIR);
+            };
+            let inode = dir.find(old_name).ok_or(code::ENOENT)?;
+            let replaced = dir.find(new_name);
+            if let Some(replaced) = &replaced {
+                if Arc::ptr_eq(replaced, &inode) {
+                    return Ok(());
+                }
+                Self::check_replace(&inode, replaced)?;
+            }
+            dir.remove(old_name);
+            dir.insert(new_name, &inode);
+            if let Some(replaced) = replaced {
+                inner.dec_size();
+                Self::drop_replaced(&mut inner, &replaced);
+            }
+            return Ok(());
+        }
+
+        // Lock both directories in address order, so that a rename the
+        // other way round can't deadlock with this one.
+        let (mut inner, mut target_inner) = if ptr::from_ref(self) < ptr::from_ref(target) {
+            let inner = self.inner.write();
+            (inner, target.inner.write())
+        } else {
+            let target_inner = target.inner.write();
+            (self.inner.write(), target_inner)
+        };
+        let (Some(dir), Some(target_dir)) = (inner.as_dir_mut(), target_inner.as_dir_mut()) else {
+            debug!("rename: inode is not a directory");
+            return Err(code::ENOTDIR);
+        };
+        let inode = dir.find(old_name).ok_or(code::ENOENT)?;
+        let replaced = target_dir.find(new_name);
+        if let Some(replaced) = &replaced {
+            if Arc::ptr_eq(replaced, &inode) {
+                return Ok(());
+            }
+            // Replacing the old parent, which is locked already and still
+            // holds the moved entry.
+            if ptr::eq(Arc::as_ptr(replaced), self) {
+                return Err(code::ENOTEMPTY);
+            }
+            Self::check_replace(&inode, replaced)?;
+        }
+        dir.remove(old_name);
+        target_dir.insert(new_name, &inode);
+        inner.dec_size();
+        match replaced {
+            Some(replaced) => Self::drop_replaced(&mut target_inner, &replaced),
+            None => target_inner.inc_size(),
+        }
+
+        let mut moved = inode.inner.write();
+        if let Some(moved_dir) = moved.as_dir_mut() {
+            // The ".." entry moves to the new parent.
+            moved_dir.parent = target.this.clone();
+            inner.dec_nlinks();
+            target_inner.inc_nlinks();
+        }
+        Ok(())
+    }
+
     fn getdents_at(&self, offset: usize, reader: &mut DirBufferReader) -> Result<usize, Error> {
         let inner = self.inner.read();
         let Some(dir) = inner.as_dir() else {
@@ -606,12 +943,19 @@ impl InodeOps for TmpInode {
 
     fn resize(&self, size: usize) -> Result<(), Error> {
         let mut inner = self.inner.write();
-        let Some(data) = inner.as_file_mut() else {
+        if inner.as_file().is_none() {
             warn!("resize: inode is not a file");
             return Err(code::EISDIR);
-        };
-        data.resize(size, 0);
-        inner.attr.size = size;
+        }
+        self.resize_data(&mut inner, size)
+    }
+
+    fn evict(&self) -> Result<(), Error> {
+        let mut inner = self.inner.write();
+        if inner.as_file().is_some() {
+            self.resize_data(&mut inner, 0)?;
+            *inner.as_file_mut().unwrap() = Vec::new();
+        }
         Ok(())
     }
 
@@ -624,10 +968,10 @@ impl InodeOps for TmpInode {
             Some(fs) => {
                 let inner = self.inner.read();
                 let dev = fs.fs_info().dev;
-                let rdev: usize = if let Some(device) = inner.as_device() {
-                    device.id().into()
-                } else {
-                    0
+                let rdev: usize = match &inner.data {
+                    TmpFileData::Device(device) => device.id().into(),
+                    TmpFileData::Special(rdev) => (*rdev).into(),
+                    _ => 0,
                 };
                 FileAttr::new(dev, rdev, &inner.attr)
             }
@@ -672,5 +1016,12 @@ impl InodeOps for TmpInode {
 impl Drop for TmpInode {
     fn drop(&mut self) {
         trace!("Drop {:?}", self);
+        if let Some(fs) = self.fs.upgrade() {
+            let inner = self.inner.get_mut();
+            if inner.as_file().is_some() {
+                fs.free_blocks(inner.attr.size.div_ceil(BLOCK_SIZE));
+            }
+            fs.free_inode();
+        }
     }
 }
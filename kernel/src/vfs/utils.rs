@@ -25,3 +25,6 @@ pub enum SeekFrom {
 
 /// Maximum bytes in a file name
 pub const NAME_MAX: usize = 255;
+
+/// Maximum bytes in a path, including the terminating null byte
+pub const PATH_MAX: usize = 4096;
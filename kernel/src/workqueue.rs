@@ -0,0 +1,246 @@
+// Copyright (c) 2025 vivo Mobile Communication Co., Ltd.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Work queues, to defer the processing of interrupts to threads.
+//!
+//! An interrupt handler does what can't wait, queues a [`Work`] and
+//! returns; the worker threads of the queue run the work later, with
+//! interrupts enabled, at the priority of the queue. Queueing never
+//! allocates nor sleeps, so it can be done from interrupt context.
+//!
+//! A work is queued once however many times it's scheduled before it
+//! runs, and runs again if it's scheduled while it runs. Works are usually
+//! statics, declared with [`static_arc`](crate::static_arc):
+//!
+//! ```ignore
+//! static_arc! {
+//!     RX_WORK(Work, Work::new(process_rx)),
+//! }
+//!
+//! fn rx_irq() {
+//!     // Drain the FIFO...
+//!     workqueue::schedule_work(&RX_WORK);
+//! }
+//! ```
+//!
+//! [`schedule_work`] queues on the system queue, drivers needing another
+//! priority or more workers create their own [`WorkQueue`]. Queues are run
+//! by the same workers as [`ThreadPool`](crate::thread::ThreadPool)s,
+//! which are never stopped.
+
+use crate::{
+    config::SYSTEM_WORK_QUEUE_PRIORITY,
+    error::{code, Error},
+    static_arc,
+    sync::SpinLock,
+    thread::{Jobs, Workers},
+    types::{impl_simple_intrusive_adapter, Arc, ArcList, IlistHead, ThreadPriority},
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+impl_simple_intrusive_adapter!(WorkNode, Work, node);
+
+pub struct Work {
+    node: IlistHead<Work, WorkNode>,
+    func: fn(),
+    // Whether it's on a queue.
+    pending: AtomicBool,
+}
+
+impl Work {
+    pub const fn new(func: fn()) -> Self {
+        Self {
+            node: IlistHead::new(),
+            func,
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    // Marks it pending, returns false if it was already.
+    pub(crate) fn set_pending(&self) -> bool {
+        !self.pending.swap(true, Ordering::AcqRel)
+    }
+
+    pub(crate) fn run(&self) {
+        // Cleared first, so that scheduling it while it runs queues it
+        // again.
+        self.pending.store(false, Ordering::Release);
+        (self.func)();
+    }
+}
+
+pub struct WorkQueue {
+    works: SpinLock<ArcList<Work, WorkNode>>,
+    started: AtomicBool,
+    workers: Workers,
+}
+
+impl WorkQueue {
+    pub const fn const_new() -> Self {
+        Self {
+            works: SpinLock::new(ArcList::new()),
+            started: AtomicBool::new(false),
+            workers: Workers::new(),
+        }
+    }
+
+    /// Creates a queue run by `workers` threads named `name`, at
+    /// `priority`. Workers never exit, queues live as long as the kernel.
+    pub fn new(
+        name: &'static str,
+        priority: ThreadPriority,
+        workers: usize,
+    ) -> Result<Arc<Self>, Error> {
+        let queue = Arc::new(Self::const_new());
+        Self::start(&queue, name, priority, workers)?;
+        Ok(queue)
+    }
+
+    /// Starts the workers of a queue created with
+    /// [`const_new`](Self::const_new). Fails with EINVAL if there are no
+    /// workers, and with EBUSY if the queue has been started already.
+    pub fn start(
+        this: &Arc<Self>,
+        name: &'static str,
+        priority: ThreadPriority,
+        workers: usize,
+    ) -> Result<(), Error> {
+        if workers == 0 {
+            return Err(code::EINVAL);
+        }
+        {
+            let mut works = this.works.irqsave_lock();
+            if this.started.load(Ordering::Relaxed) {
+                return Err(code::EBUSY);
+            }
+            works.init();
+            this.started.store(true, Ordering::Release);
+        }
+        Workers::start(this, name, priority, workers);
+        Ok(())
+    }
+
+    /// Queues `work` to be run by a worker, and wakes one. Returns false if
+    /// it's pending already, or if the queue hasn't been started. Can be
+    /// called from interrupt context.
+    pub fn queue(&self, work: &Arc<Work>) -> bool {
+        if !self.started.load(Ordering::Acquire) || !work.set_pending() {
+            return false;
+        }
+        self.works.irqsave_lock().push_back(work.clone());
+        self.workers.notify();
+        true
+    }
+}
+
+impl Jobs for WorkQueue {
+    fn workers(&self) -> &Workers {
+        &self.workers
+    }
+
+    fn run_next(&self) -> bool {
+        let Some(work) = self.works.irqsave_lock().pop_front() else {
+            return false;
+        };
+        work.run();
+        true
+    }
+}
+
+static_arc! {
+    SYSTEM_QUEUE(WorkQueue, WorkQueue::const_new()),
+}
+
+pub(crate) fn init() {
+    let result = WorkQueue::start(&SYSTEM_QUEUE, "kworker", SYSTEM_WORK_QUEUE_PRIORITY, 1);
+    crate::kassert!(result.is_ok(), "system work queue can't be started");
+}
+
+/// Queues `work` on the system queue, see [`WorkQueue::queue`].
+pub fn schedule_work(work: &Arc<Work>) -> bool {
+    SYSTEM_QUEUE.queue(work)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scheduler,
+        sync::{atomic_wait, atomic_wake},
+    };
+    use blueos_test_macro::test;
+    use core::sync::atomic::AtomicUsize;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    static GATE: AtomicUsize = AtomicUsize::new(0);
+    static_arc! {
+        COUNT_WORK(Work, Work::new(count)),
+    }
+    static_arc! {
+        GATE_WORK(Work, Work::new(wait_for_gate)),
+    }
+
+    fn count() {
+        RUNS.fetch_add(1, Ordering::Release);
+        let _ = atomic_wake(&RUNS, usize::MAX);
+    }
+
+    fn wait_for_gate() {
+        while GATE.load(Ordering::Acquire) == 0 {
+            let _ = atomic_wait(&GATE, 0, None);
+        }
+    }
+
+    fn wait_for_runs(n: usize) {
+        loop {
+            let runs = RUNS.load(Ordering::Acquire);
+            if runs >= n {
+                return;
+            }
+            let _ = atomic_wait(&RUNS, runs, None);
+        }
+    }
+
+    #[test]
+    fn test_schedule_work() {
+        let runs = RUNS.load(Ordering::Acquire);
+        assert!(schedule_work(&COUNT_WORK));
+        wait_for_runs(runs + 1);
+
+        let priority = scheduler::current_thread().priority();
+        let queue = WorkQueue::new("test_wq", priority, 1).unwrap();
+        assert_eq!(
+            WorkQueue::start(&queue, "test_wq", priority, 1),
+            Err(code::EBUSY)
+        );
+        // The only worker waits in the first work, the second one stays
+        // queued, once.
+        assert!(queue.queue(&GATE_WORK));
+        while GATE_WORK.is_pending() {
+            scheduler::yield_me();
+        }
+        assert!(queue.queue(&COUNT_WORK));
+        assert!(!queue.queue(&COUNT_WORK));
+        assert!(!schedule_work(&COUNT_WORK));
+        GATE.store(1, Ordering::Release);
+        let _ = atomic_wake(&GATE, usize::MAX);
+        wait_for_runs(runs + 2);
+        assert_eq!(RUNS.load(Ordering::Acquire), runs + 2);
+        assert!(!WorkQueue::const_new().queue(&COUNT_WORK));
+    }
+}
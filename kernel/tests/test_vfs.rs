@@ -14,7 +14,7 @@
 
 #![allow(dead_code)]
 use crate::net::net_utils;
-use alloc::{boxed::Box, ffi::CString, format, string::String, vec};
+use alloc::{boxed::Box, collections::BTreeSet, ffi::CString, format, string::String, vec};
 use blueos::{
     allocator,
     error::{
@@ -25,7 +25,8 @@ use blueos::{
     sync::atomic_wait as futex,
     thread::{Builder as ThreadBuilder, Entry, Stack},
     vfs::{
-        dirent::{Dirent, DirentType},
+        dirent::{Dirent, Dirent64, DirentType},
+        orphan,
         syscalls::*,
     },
 };
@@ -147,6 +148,218 @@ fn test_read_and_write(path_prefix: String, test_data_len: usize) {
     close(fd);
 }
 
+#[test]
+fn test_poll() {
+    let path = c"/poll_test.txt";
+    let fd = open(path.as_ptr(), O_CREAT | O_RDWR | O_TRUNC, 0o644);
+    assert!(fd >= 0);
+    let mut fds = [
+        libc::pollfd {
+            fd,
+            events: libc::POLLIN | libc::POLLOUT,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: -1,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    // Regular files are always ready, ignored entries never are.
+    assert_eq!(poll(fds.as_mut_ptr(), fds.len(), -1), 1);
+    assert_eq!(fds[0].revents, libc::POLLIN | libc::POLLOUT);
+    assert_eq!(fds[1].revents, 0);
+
+    close(fd);
+    fds[0].events = libc::POLLIN;
+    assert_eq!(poll(fds.as_mut_ptr(), 1, 0), 1);
+    assert_eq!(fds[0].revents, libc::POLLNVAL);
+}
+
+#[test]
+fn test_splice() {
+    let src_path = c"/splice_src.txt";
+    let dst_path = c"/splice_dst.txt";
+    let mode: libc::mode_t = 0o644;
+
+    let src = open(src_path.as_ptr(), O_CREAT | O_RDWR | O_TRUNC, mode);
+    assert!(src >= 0);
+    let test_data = b"0123456789".repeat(200);
+    assert_eq!(
+        write(src, test_data.as_ptr(), test_data.len()),
+        test_data.len() as isize
+    );
+    assert_eq!(lseek(src, 0, SEEK_SET), 0);
+
+    let dst = open(dst_path.as_ptr(), O_CREAT | O_RDWR | O_TRUNC, mode);
+    assert!(dst >= 0);
+    // Ask for more than available, only what's in the source is moved.
+    assert_eq!(splice(src, dst, 4096), test_data.len() as isize);
+    assert_eq!(splice(src, dst, 4096), 0);
+    assert_eq!(splice(src, -1, 4096), -libc::EBADF as isize);
+
+    assert_eq!(lseek(dst, 0, SEEK_SET), 0);
+    let mut read_buf = vec![0u8; test_data.len()];
+    assert_eq!(
+        read(dst, read_buf.as_mut_ptr(), read_buf.len()),
+        test_data.len() as isize
+    );
+    assert_eq!(read_buf, test_data);
+
+    close(src);
+    close(dst);
+    unlink(src_path.as_ptr());
+    unlink(dst_path.as_ptr());
+}
+
+#[test]
+fn test_pipe() {
+    let mut fds = [-1; 2];
+    assert_eq!(pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK), 0);
+    let [rfd, wfd] = fds;
+    let mut buf = [0u8; 16];
+    assert_eq!(
+        read(rfd, buf.as_mut_ptr(), buf.len()),
+        -libc::EAGAIN as isize
+    );
+    assert_eq!(write(wfd, b"ping".as_ptr(), 4), 4);
+    assert_eq!(read(rfd, buf.as_mut_ptr(), buf.len()), 4);
+    assert_eq!(&buf[..4], b"ping");
+    let mut st: Stat = unsafe { mem::zeroed() };
+    assert_eq!(fstat(rfd, &mut st), 0);
+    assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFIFO);
+
+    // Without a writer, the reader sees end of file.
+    close(wfd);
+    assert_eq!(read(rfd, buf.as_mut_ptr(), buf.len()), 0);
+    close(rfd);
+
+    assert_eq!(pipe2(fds.as_mut_ptr(), libc::O_APPEND), -libc::EINVAL);
+    assert_eq!(pipe(fds.as_mut_ptr()), 0);
+    let [rfd, wfd] = fds;
+    close(rfd);
+    assert_eq!(write(wfd, b"x".as_ptr(), 1), -libc::EPIPE as isize);
+    close(wfd);
+}
+
+#[test]
+fn test_fifo() {
+    let path = c"/test_fifo";
+    assert_eq!(mkfifo(path.as_ptr(), 0o644), 0);
+    assert_eq!(mkfifo(path.as_ptr(), 0o644), -libc::EEXIST);
+    let mut st: Stat = unsafe { mem::zeroed() };
+    assert_eq!(stat(path.as_ptr(), &mut st), 0);
+    assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFIFO);
+
+    // Nobody reads it yet.
+    assert_eq!(
+        open(path.as_ptr(), O_WRONLY | libc::O_NONBLOCK, 0),
+        -libc::ENXIO
+    );
+    let rfd = open(path.as_ptr(), O_RDONLY | libc::O_NONBLOCK, 0);
+    assert!(rfd >= 0);
+    let wfd = open(path.as_ptr(), O_WRONLY, 0);
+    assert!(wfd >= 0);
+    assert_eq!(write(wfd, b"fifo".as_ptr(), 4), 4);
+    let mut buf = [0u8; 16];
+    assert_eq!(read(rfd, buf.as_mut_ptr(), buf.len()), 4);
+    assert_eq!(&buf[..4], b"fifo");
+    close(wfd);
+    close(rfd);
+
+    // Opening both ends at once doesn't wait.
+    let fd = open(path.as_ptr(), O_RDWR, 0);
+    assert!(fd >= 0);
+    assert_eq!(write(fd, b"rw".as_ptr(), 2), 2);
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 2);
+    close(fd);
+    assert_eq!(unlink(path.as_ptr()), 0);
+}
+
+#[test]
+fn test_dev_null_and_full() {
+    let mut buf = [1u8; 8];
+    let null = open(c"/dev/null".as_ptr(), O_RDWR, 0);
+    assert!(null >= 0);
+    assert_eq!(write(null, b"discard".as_ptr(), 7), 7);
+    assert_eq!(read(null, buf.as_mut_ptr(), buf.len()), 0);
+    close(null);
+
+    let full = open(c"/dev/full".as_ptr(), O_RDWR, 0);
+    assert!(full >= 0);
+    assert_eq!(write(full, b"x".as_ptr(), 1), -libc::ENOSPC as isize);
+    assert_eq!(read(full, buf.as_mut_ptr(), buf.len()), buf.len() as isize);
+    assert_eq!(buf, [0; 8]);
+    close(full);
+}
+
+// The USB stick QEMU attaches, 16 MiB reading as zeros.
+#[cfg(all(usb, target_board = "qemu_virt64_aarch64"))]
+#[test]
+fn test_usb_storage_read() {
+    const SIZE: i64 = 16 << 20;
+    let mut st: Stat = unsafe { mem::zeroed() };
+    assert_eq!(stat(c"/dev/sda".as_ptr(), &mut st), 0);
+    assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFBLK);
+
+    let fd = open(c"/dev/sda".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    let mut buf = [0xa5u8; 512];
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 512);
+    assert_eq!(buf, [0; 512]);
+    // The last block, then the end of the device.
+    buf.fill(0xa5);
+    assert_eq!(lseek(fd, SIZE - 512, SEEK_SET), SIZE - 512);
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 512);
+    assert_eq!(buf, [0; 512]);
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 0);
+    close(fd);
+}
+
+#[test]
+fn test_mknod() {
+    let mut st: Stat = unsafe { mem::zeroed() };
+    assert_eq!(stat(c"/dev/null".as_ptr(), &mut st), 0);
+
+    // Another name for /dev/null, outside of /dev.
+    let path = c"/test_null";
+    assert_eq!(mknod(path.as_ptr(), libc::S_IFCHR | 0o666, st.st_rdev), 0);
+    assert_eq!(
+        mknod(path.as_ptr(), libc::S_IFCHR | 0o666, st.st_rdev),
+        -libc::EEXIST
+    );
+    let mut node: Stat = unsafe { mem::zeroed() };
+    assert_eq!(stat(path.as_ptr(), &mut node), 0);
+    assert_eq!(node.st_mode & libc::S_IFMT, libc::S_IFCHR);
+    assert_eq!(node.st_rdev, st.st_rdev);
+    let fd = open(path.as_ptr(), O_RDWR, 0);
+    assert!(fd >= 0);
+    let mut buf = [1u8; 8];
+    assert_eq!(write(fd, b"discard".as_ptr(), 7), 7);
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 0);
+    close(fd);
+    assert_eq!(unlink(path.as_ptr()), 0);
+
+    // Nothing has this number, the node can be made but not opened.
+    let path = c"/test_nodev";
+    assert_eq!(
+        mknod(path.as_ptr(), libc::S_IFCHR | 0o666, st.st_rdev | 0xfff),
+        0
+    );
+    assert_eq!(open(path.as_ptr(), O_RDWR, 0), -libc::ENXIO);
+    assert_eq!(unlink(path.as_ptr()), 0);
+
+    assert_eq!(
+        mknod(c"/test_dir".as_ptr(), libc::S_IFDIR | 0o755, 0),
+        -libc::EPERM
+    );
+    let path = c"/test_regular";
+    assert_eq!(mknod(path.as_ptr(), 0o644, 0), 0);
+    assert_eq!(stat(path.as_ptr(), &mut node), 0);
+    assert_eq!(node.st_mode & libc::S_IFMT, libc::S_IFREG);
+    assert_eq!(unlink(path.as_ptr()), 0);
+}
+
 #[test]
 fn test_multiple_open() {
     println!("Test the tmpfs mounted at /");
@@ -221,6 +434,601 @@ fn test_multiple_open(path_prefix: String) {
     close(fd2);
 }
 
+#[test]
+fn test_dup_shares_offset() {
+    let path = c"/test_dup.txt";
+    let fd = open(path.as_ptr(), O_CREAT | O_RDWR | O_TRUNC, 0o644);
+    assert!(fd >= 0);
+
+    // Descriptors from dup and F_DUPFD share the offset with the original.
+    let fd_dup = dup(fd);
+    assert!(fd_dup >= 0 && fd_dup != fd);
+    let fd_min = fcntl(fd, libc::F_DUPFD_CLOEXEC, 20);
+    assert!(fd_min >= 20);
+    assert_eq!(write(fd, b"abc".as_ptr(), 3), 3);
+    assert_eq!(write(fd_dup, b"def".as_ptr(), 3), 3);
+    assert_eq!(lseek(fd_min, 0, libc::SEEK_CUR), 6);
+    assert_eq!(lseek(fd_min, 1, SEEK_SET), 1);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 5);
+    assert_eq!(&buf[..5], b"bcdef");
+
+    // Only close-on-exec belongs to the descriptor.
+    assert_eq!(fcntl(fd_min, libc::F_GETFD, 0), libc::FD_CLOEXEC);
+    assert_eq!(fcntl(fd, libc::F_GETFD, 0), 0);
+    assert_eq!(fcntl(fd_dup, libc::F_SETFL, libc::O_APPEND as usize), 0);
+    assert_ne!(fcntl(fd, libc::F_GETFL, 0) & libc::O_APPEND, 0);
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(write(fd_min, b"g".as_ptr(), 1), 1);
+    assert_eq!(lseek(fd, 0, libc::SEEK_CUR), 7);
+
+    // Closing one descriptor leaves the others usable.
+    assert_eq!(close(fd), 0);
+    assert_eq!(lseek(fd_dup, 0, SEEK_SET), 0);
+    assert_eq!(read(fd_min, buf.as_mut_ptr(), buf.len()), 7);
+    assert_eq!(&buf[..7], b"abcdefg");
+
+    // dup2 replaces the target descriptor, like a shell's 2>&1.
+    let other = open(path.as_ptr(), O_RDONLY, 0);
+    assert!(other >= 0);
+    assert_eq!(dup2(fd_dup, other), other);
+    assert_eq!(lseek(other, 0, libc::SEEK_CUR), 7);
+    assert_eq!(dup2(other, other), other);
+    assert_eq!(dup3(other, other, 0), -libc::EINVAL);
+    assert_eq!(dup2(fd, other), -libc::EBADF);
+
+    for fd in [fd_dup, fd_min, other] {
+        assert_eq!(close(fd), 0);
+    }
+    assert_eq!(close(fd_dup), -libc::EBADF);
+    assert_eq!(unlink(path.as_ptr()), 0);
+}
+
+#[test]
+fn test_unlink_while_open() {
+    println!("Test the tmpfs mounted at /");
+    test_unlink_while_open(String::from("/"));
+
+    #[cfg(virtio)]
+    {
+        println!("Test the fatfs mounted at /fat");
+        test_unlink_while_open(String::from("/fat/"));
+    }
+}
+
+fn test_unlink_while_open(path_prefix: String) {
+    let mut test_path = path_prefix.clone();
+    test_path.push_str("test_orphan.txt");
+    let test_path = CString::new(test_path).expect("Failed to create CString");
+    let path_ptr = test_path.as_ptr() as *const c_char;
+    let orphans = orphan::count();
+
+    let fd1 = open(path_ptr, O_CREAT | O_RDWR, 0o644);
+    assert!(fd1 >= 0);
+    let fd2 = open(path_ptr, O_RDONLY, 0);
+    assert!(fd2 >= 0);
+    let test_data = b"Unlinked but still open\n";
+    assert_eq!(
+        write(fd1, test_data.as_ptr(), test_data.len()),
+        test_data.len() as isize
+    );
+
+    assert_eq!(unlink(path_ptr), 0);
+    assert_eq!(open(path_ptr, O_RDONLY, 0), -libc::ENOENT);
+    assert_eq!(orphan::count(), orphans + 1);
+    let mut st: Stat = unsafe { mem::zeroed() };
+    assert_eq!(fstat(fd1, &mut st), 0);
+    assert_eq!(st.st_nlink, 0);
+
+    // The data outlives the name until the last fd is closed.
+    assert_eq!(
+        write(fd1, test_data.as_ptr(), test_data.len()),
+        test_data.len() as isize
+    );
+    close(fd1);
+    assert_eq!(orphan::count(), orphans + 1);
+    let mut read_buf = [0u8; 64];
+    let read_size = read(fd2, read_buf.as_mut_ptr(), read_buf.len());
+    assert_eq!(read_size, 2 * test_data.len() as isize);
+    assert_eq!(&read_buf[..test_data.len()], test_data);
+    close(fd2);
+    assert_eq!(orphan::count(), orphans);
+
+    // The name can be reused once the orphan is gone.
+    let fd = open(path_ptr, O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    assert_eq!(read(fd, read_buf.as_mut_ptr(), read_buf.len()), 0);
+    close(fd);
+    assert_eq!(unlink(path_ptr), 0);
+}
+
+#[test]
+fn test_rename() {
+    println!("Test the tmpfs mounted at /");
+    test_rename(String::from("/"));
+
+    #[cfg(virtio)]
+    {
+        println!("Test the fatfs mounted at /fat");
+        test_rename(String::from("/fat/"));
+
+        let fd = open(c"/test_xdev.txt".as_ptr(), O_CREAT | O_RDWR, 0o644);
+        assert!(fd >= 0);
+        close(fd);
+        assert_eq!(
+            rename(c"/test_xdev.txt".as_ptr(), c"/fat/test_xdev.txt".as_ptr()),
+            -libc::EXDEV
+        );
+        assert_eq!(unlink(c"/test_xdev.txt".as_ptr()), 0);
+    }
+}
+
+fn test_rename(path_prefix: String) {
+    let path = |name: &str| CString::new(format!("{}{}", path_prefix, name)).unwrap();
+    let create = |name: &str, data: &[u8]| {
+        let fd = open(path(name).as_ptr(), O_CREAT | O_RDWR | O_TRUNC, 0o644);
+        assert!(fd >= 0, "[VFS Test Rename]: Failed to create {}", name);
+        assert_eq!(write(fd, data.as_ptr(), data.len()), data.len() as isize);
+        close(fd);
+    };
+    let content = |name: &str| {
+        let fd = open(path(name).as_ptr(), O_RDONLY, 0);
+        assert!(fd >= 0, "[VFS Test Rename]: Failed to open {}", name);
+        let mut buf = [0u8; 64];
+        let len = read(fd, buf.as_mut_ptr(), buf.len());
+        close(fd);
+        assert!(len >= 0);
+        vec::Vec::from(&buf[..len as usize])
+    };
+
+    // In the same directory, while the file is open.
+    create("rename_a", b"aaaa");
+    let fd = open(path("rename_a").as_ptr(), O_RDWR, 0);
+    assert!(fd >= 0);
+    assert_eq!(
+        rename(path("rename_a").as_ptr(), path("rename_b").as_ptr()),
+        0
+    );
+    assert_eq!(open(path("rename_a").as_ptr(), O_RDONLY, 0), -libc::ENOENT);
+    assert_eq!(write(fd, b"bb".as_ptr(), 2), 2);
+    close(fd);
+    assert_eq!(content("rename_b"), b"aaaabb");
+
+    // Into another directory, replacing a file.
+    assert_eq!(mkdir(path("rename_d1").as_ptr(), 0o755), 0);
+    create("rename_d1/rename_c", b"cc");
+    assert_eq!(
+        rename(
+            path("rename_b").as_ptr(),
+            path("rename_d1/rename_c").as_ptr()
+        ),
+        0
+    );
+    assert_eq!(content("rename_d1/rename_c"), b"aaaabb");
+
+    // A directory with its contents, below another one.
+    assert_eq!(mkdir(path("rename_d2").as_ptr(), 0o755), 0);
+    assert_eq!(
+        rename(
+            path("rename_d1").as_ptr(),
+            path("rename_d2/rename_d3").as_ptr()
+        ),
+        0
+    );
+    assert_eq!(content("rename_d2/rename_d3/rename_c"), b"aaaabb");
+    assert_eq!(
+        open(path("rename_d1/rename_c").as_ptr(), O_RDONLY, 0),
+        -libc::ENOENT
+    );
+
+    // Replacing a file which is still open, which keeps its data until
+    // it's closed.
+    create("rename_e", b"ee");
+    create("rename_f", b"ff");
+    let fd = open(path("rename_f").as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    assert_eq!(
+        rename(path("rename_e").as_ptr(), path("rename_f").as_ptr()),
+        0
+    );
+    assert_eq!(content("rename_f"), b"ee");
+    let mut buf = [0u8; 4];
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 2);
+    assert_eq!(&buf[..2], b"ff");
+    close(fd);
+    assert_eq!(unlink(path("rename_f").as_ptr()), 0);
+
+    // Invalid moves.
+    assert_eq!(
+        rename(
+            path("rename_d2").as_ptr(),
+            path("rename_d2/rename_d3/rename_d4").as_ptr()
+        ),
+        -libc::EINVAL
+    );
+    assert_eq!(mkdir(path("rename_d5").as_ptr(), 0o755), 0);
+    assert_eq!(
+        rename(path("rename_d5").as_ptr(), path("rename_d2").as_ptr()),
+        -libc::ENOTEMPTY
+    );
+    assert_eq!(
+        rename(
+            path("rename_d2/rename_d3/rename_c").as_ptr(),
+            path("rename_d5").as_ptr()
+        ),
+        -libc::EISDIR
+    );
+    assert_eq!(
+        rename(
+            path("rename_d5").as_ptr(),
+            path("rename_d2/rename_d3/rename_c").as_ptr()
+        ),
+        -libc::ENOTDIR
+    );
+    assert_eq!(
+        rename(path("rename_none").as_ptr(), path("rename_d5").as_ptr()),
+        -libc::ENOENT
+    );
+
+    // An empty directory can be replaced.
+    assert_eq!(
+        rename(
+            path("rename_d5").as_ptr(),
+            path("rename_d2/rename_d3").as_ptr()
+        ),
+        -libc::ENOTEMPTY
+    );
+    assert_eq!(unlink(path("rename_d2/rename_d3/rename_c").as_ptr()), 0);
+    assert_eq!(
+        rename(
+            path("rename_d5").as_ptr(),
+            path("rename_d2/rename_d3").as_ptr()
+        ),
+        0
+    );
+    assert_eq!(rmdir(path("rename_d2/rename_d3").as_ptr()), 0);
+    assert_eq!(rmdir(path("rename_d2").as_ptr()), 0);
+}
+
+#[test]
+fn test_realpath() {
+    let resolve = |path: &CStr| {
+        let mut buf = [0u8; 64];
+        let len = realpath(path.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert!(
+            len >= 0,
+            "[VFS Test Realpath]: Failed to resolve {:?}",
+            path
+        );
+        String::from_utf8(buf[..len as usize].to_vec()).unwrap()
+    };
+    let error = |path: &CStr| {
+        let mut buf = [0u8; 64];
+        realpath(path.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len())
+    };
+
+    assert_eq!(mkdir(c"/realpath_d1".as_ptr(), 0o755), 0);
+    assert_eq!(mkdir(c"/realpath_d1/d2".as_ptr(), 0o755), 0);
+    let fd = open(c"/realpath_d1/d2/file".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    close(fd);
+
+    assert_eq!(resolve(c"/"), "/");
+    assert_eq!(resolve(c"/.."), "/");
+    assert_eq!(resolve(c"//realpath_d1/./d2/"), "/realpath_d1/d2");
+    assert_eq!(
+        resolve(c"/realpath_d1/d2/../d2/file"),
+        "/realpath_d1/d2/file"
+    );
+    assert_eq!(error(c"/realpath_d1/d2/file/"), -libc::ENOTDIR);
+    assert_eq!(error(c"/realpath_d1/d2/file/.."), -libc::ENOTDIR);
+    assert_eq!(error(c"/realpath_d1/none"), -libc::ENOENT);
+    assert_eq!(error(c""), -libc::ENOENT);
+
+    // Relative paths start from the working directory.
+    assert_eq!(chdir(c"/realpath_d1/d2/file".as_ptr()), -libc::ENOTDIR);
+    assert_eq!(chdir(c"/realpath_d1/none".as_ptr()), -libc::ENOENT);
+    assert_eq!(chdir(c"/realpath_d1/./d2/..".as_ptr()), 0);
+    assert_eq!(resolve(c"."), "/realpath_d1");
+    assert_eq!(resolve(c"d2/file"), "/realpath_d1/d2/file");
+    assert_eq!(resolve(c"../realpath_d1/d2"), "/realpath_d1/d2");
+    let mut buf = [0u8; 8];
+    assert_eq!(
+        realpath(
+            c"d2/file".as_ptr(),
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len()
+        ),
+        -libc::ENAMETOOLONG
+    );
+    assert_eq!(chdir(c"/".as_ptr()), 0);
+
+    assert_eq!(unlink(c"/realpath_d1/d2/file".as_ptr()), 0);
+    assert_eq!(rmdir(c"/realpath_d1/d2".as_ptr()), 0);
+    assert_eq!(rmdir(c"/realpath_d1".as_ptr()), 0);
+}
+
+#[test]
+fn test_symlink() {
+    let link_target = |path: &CStr| {
+        let mut buf = [0u8; 64];
+        let len = readlink(path.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert!(len >= 0, "[VFS Test Symlink]: Failed to read {:?}", path);
+        String::from_utf8(buf[..len as usize].to_vec()).unwrap()
+    };
+
+    assert_eq!(mkdir(c"/symlink_d1".as_ptr(), 0o755), 0);
+    let fd = open(c"/symlink_d1/file".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    assert_eq!(write(fd, b"data".as_ptr(), 4), 4);
+    close(fd);
+
+    // Relative targets start from the directory holding the link.
+    assert_eq!(symlink(c"file".as_ptr(), c"/symlink_d1/rel".as_ptr()), 0);
+    assert_eq!(
+        symlink(c"/symlink_d1".as_ptr(), c"/symlink_abs".as_ptr()),
+        0
+    );
+    assert_eq!(
+        symlink(c"file".as_ptr(), c"/symlink_d1/rel".as_ptr()),
+        -libc::EEXIST
+    );
+    assert_eq!(link_target(c"/symlink_d1/rel"), "file");
+    assert_eq!(link_target(c"/symlink_abs"), "/symlink_d1");
+    assert_eq!(
+        readlink(
+            c"/symlink_d1/file".as_ptr(),
+            [0u8; 8].as_mut_ptr() as *mut c_char,
+            8
+        ),
+        -libc::EINVAL as isize
+    );
+
+    let fd = open(c"/symlink_abs/rel".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 4);
+    assert_eq!(&buf[..4], b"data");
+    close(fd);
+    assert_eq!(
+        open(c"/symlink_d1/rel".as_ptr(), O_RDONLY | libc::O_NOFOLLOW, 0),
+        -libc::ELOOP
+    );
+
+    let mut st: Stat = unsafe { mem::zeroed() };
+    assert_eq!(stat(c"/symlink_d1/rel".as_ptr(), &mut st), 0);
+    assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFREG);
+    assert_eq!(lstat(c"/symlink_d1/rel".as_ptr(), &mut st), 0);
+    assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFLNK);
+    assert_eq!(st.st_size, 4);
+    assert_eq!(lstat(c"/symlink_abs/".as_ptr(), &mut st), 0);
+    assert_eq!(st.st_mode & libc::S_IFMT, libc::S_IFDIR);
+
+    let mut path = [0u8; 64];
+    let len = realpath(
+        c"/symlink_abs/rel".as_ptr(),
+        path.as_mut_ptr() as *mut c_char,
+        path.len(),
+    );
+    assert!(len >= 0);
+    assert_eq!(&path[..len as usize], b"/symlink_d1/file");
+
+    // Links pointing at each other never resolve.
+    assert_eq!(
+        symlink(c"loop_b".as_ptr(), c"/symlink_d1/loop_a".as_ptr()),
+        0
+    );
+    assert_eq!(
+        symlink(c"loop_a".as_ptr(), c"/symlink_d1/loop_b".as_ptr()),
+        0
+    );
+    assert_eq!(
+        open(c"/symlink_d1/loop_a".as_ptr(), O_RDONLY, 0),
+        -libc::ELOOP
+    );
+    assert_eq!(
+        open(c"/symlink_d1/loop_a/file".as_ptr(), O_RDONLY, 0),
+        -libc::ELOOP
+    );
+
+    // Removing a link leaves its target alone.
+    for link in [
+        c"/symlink_d1/loop_a",
+        c"/symlink_d1/loop_b",
+        c"/symlink_d1/rel",
+    ] {
+        assert_eq!(unlink(link.as_ptr()), 0);
+    }
+    assert_eq!(unlink(c"/symlink_abs".as_ptr()), 0);
+    assert_eq!(stat(c"/symlink_d1/file".as_ptr(), &mut st), 0);
+    assert_eq!(st.st_size, 4);
+    assert_eq!(unlink(c"/symlink_d1/file".as_ptr()), 0);
+    assert_eq!(rmdir(c"/symlink_d1".as_ptr()), 0);
+}
+
+#[test]
+fn test_chroot() {
+    let resolve = |path: &CStr| {
+        let mut buf = [0u8; 64];
+        let len = realpath(path.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len());
+        assert!(len >= 0, "[VFS Test Chroot]: Failed to resolve {:?}", path);
+        String::from_utf8(buf[..len as usize].to_vec()).unwrap()
+    };
+
+    assert_eq!(mkdir(c"/chroot_d".as_ptr(), 0o755), 0);
+    assert_eq!(mkdir(c"/chroot_d/sub".as_ptr(), 0o755), 0);
+    let fd = open(c"/chroot_d/sub/file".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    close(fd);
+    assert_eq!(symlink(c"/sub".as_ptr(), c"/chroot_d/abs".as_ptr()), 0);
+    assert_eq!(chroot(c"/chroot_d/sub/file".as_ptr()), -libc::ENOTDIR);
+    assert_eq!(chroot(c"/chroot_none".as_ptr()), -libc::ENOENT);
+
+    assert_eq!(chroot(c"/chroot_d".as_ptr()), 0);
+    let fd = open(c"/sub/file".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    close(fd);
+    assert_eq!(open(c"/chroot_d".as_ptr(), O_RDONLY, 0), -libc::ENOENT);
+    // Neither .. nor absolute symbolic links get out of the new root.
+    assert_eq!(resolve(c"/.."), "/");
+    assert_eq!(resolve(c"/sub/../../.."), "/");
+    assert_eq!(resolve(c"/abs/file"), "/sub/file");
+
+    // The working directory is still outside, and relative paths start
+    // from it, which is the way back out.
+    assert_eq!(resolve(c"chroot_d/sub"), "/sub");
+    assert_eq!(chroot(c".".as_ptr()), 0);
+    assert_eq!(resolve(c"/chroot_d/abs"), "/chroot_d/sub");
+
+    assert_eq!(unlink(c"/chroot_d/abs".as_ptr()), 0);
+    assert_eq!(unlink(c"/chroot_d/sub/file".as_ptr()), 0);
+    assert_eq!(rmdir(c"/chroot_d/sub".as_ptr()), 0);
+    assert_eq!(rmdir(c"/chroot_d".as_ptr()), 0);
+}
+
+#[test]
+fn test_mmap() {
+    let page = 4096;
+    let fd = open(c"/mmap_file".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    let data = [b'a'; 6000];
+    assert_eq!(write(fd, data.as_ptr(), data.len()), data.len() as isize);
+
+    let map =
+        |len, prot, flags, fd, offset| mmap(core::ptr::null_mut(), len, prot, flags, fd, offset);
+    let rw = libc::PROT_READ | libc::PROT_WRITE;
+    assert_eq!(map(0, rw, libc::MAP_SHARED, fd, 0), -libc::EINVAL as isize);
+    assert_eq!(
+        map(page, rw, libc::MAP_SHARED, fd, 1),
+        -libc::EINVAL as isize
+    );
+    assert_eq!(map(page, rw, 0, fd, 0), -libc::EINVAL as isize);
+    assert_eq!(
+        map(page, rw, libc::MAP_SHARED, 1000, 0),
+        -libc::EBADF as isize
+    );
+
+    // Mappings of the same range share their pages.
+    let shared = map(2 * page, rw, libc::MAP_SHARED, fd, 0);
+    assert!(shared > 0 && shared as usize % page == 0);
+    assert_eq!(map(2 * page, rw, libc::MAP_SHARED, fd, 0), shared);
+    let bytes = unsafe { core::slice::from_raw_parts_mut(shared as *mut u8, 2 * page) };
+    assert_eq!(bytes[5999], b'a');
+    // Past the end of the file the pages are zeroed.
+    assert_eq!(bytes[6000], 0);
+
+    // Private pages are never written back.
+    let private = map(page, rw, libc::MAP_PRIVATE, fd, 0);
+    assert!(private > 0 && private != shared);
+    unsafe { *(private as *mut u8) = b'p' };
+    assert_eq!(munmap(private as *mut c_void, page), 0);
+
+    bytes[0] = b'x';
+    bytes[page] = b'y';
+    assert_eq!(
+        msync(shared as *mut c_void, page, libc::MS_SYNC | libc::MS_ASYNC),
+        -libc::EINVAL
+    );
+    assert_eq!(msync(shared as *mut c_void, page, libc::MS_SYNC), 0);
+    let mut buf = [0u8; 1];
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(read(fd, buf.as_mut_ptr(), 1), 1);
+    assert_eq!(buf[0], b'x');
+    // Reads and writes go through the mapped pages.
+    assert_eq!(lseek(fd, page as i64, SEEK_SET), page as i64);
+    assert_eq!(read(fd, buf.as_mut_ptr(), 1), 1);
+    assert_eq!(buf[0], b'y');
+    assert_eq!(lseek(fd, 1, SEEK_SET), 1);
+    assert_eq!(write(fd, b"w".as_ptr(), 1), 1);
+    assert_eq!(bytes[1], b'w');
+
+    // Removing the last mapping writes the rest back, without growing the
+    // file.
+    assert_eq!(munmap(shared as *mut c_void, page), -libc::EINVAL);
+    assert_eq!(munmap(shared as *mut c_void, 2 * page), 0);
+    assert_eq!(bytes[page], b'y');
+    assert_eq!(munmap(shared as *mut c_void, 2 * page), 0);
+    assert_eq!(lseek(fd, page as i64, SEEK_SET), page as i64);
+    assert_eq!(read(fd, buf.as_mut_ptr(), 1), 1);
+    assert_eq!(buf[0], b'y');
+    assert_eq!(lseek(fd, 0, libc::SEEK_END), data.len() as i64);
+    assert_eq!(munmap(shared as *mut c_void, 2 * page), -libc::EINVAL);
+
+    // Written pages are dirty even when no mapping can store to them.
+    let ro = map(page, libc::PROT_READ, libc::MAP_SHARED, fd, 0);
+    assert!(ro > 0);
+    assert_eq!(unsafe { *(ro as *const u8).add(1) }, b'w');
+    assert_eq!(lseek(fd, 2, SEEK_SET), 2);
+    assert_eq!(write(fd, b"z".as_ptr(), 1), 1);
+    assert_eq!(unsafe { *(ro as *const u8).add(2) }, b'z');
+    assert_eq!(munmap(ro as *mut c_void, page), 0);
+    assert_eq!(lseek(fd, 2, SEEK_SET), 2);
+    assert_eq!(read(fd, buf.as_mut_ptr(), 1), 1);
+    assert_eq!(buf[0], b'z');
+
+    // A read only descriptor can't back a writable shared mapping.
+    close(fd);
+    let fd = open(c"/mmap_file".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    assert_eq!(
+        map(page, rw, libc::MAP_SHARED, fd, 0),
+        -libc::EACCES as isize
+    );
+    close(fd);
+
+    let anon = map(page, rw, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+    assert!(anon > 0);
+    assert_eq!(unsafe { *(anon as *const u8) }, 0);
+    assert_eq!(munmap(anon as *mut c_void, page), 0);
+    assert_eq!(unlink(c"/mmap_file".as_ptr()), 0);
+}
+
+#[test]
+fn test_tmpfs_limits() {
+    let dir = c"/tmpfs_lim".as_ptr();
+    let tmpfs = c"tmpfs".as_ptr();
+    let statfs_of = |path: &CStr| {
+        let mut buf: Statfs = unsafe { mem::zeroed() };
+        assert_eq!(statfs(path.as_ptr(), &mut buf), 0);
+        buf
+    };
+
+    let mount_with = |options: &CStr| {
+        let data = options.as_ptr() as *const c_void;
+        mount(core::ptr::null(), dir, tmpfs, 0, data)
+    };
+
+    assert_eq!(mkdir(dir, 0o755), 0);
+    assert_eq!(mount_with(c"size=8q"), -libc::EINVAL);
+    assert_eq!(mount_with(c"size=8k,nr_inodes=3"), 0);
+    let info = statfs_of(c"/tmpfs_lim");
+    assert_eq!((info.f_blocks, info.f_bfree), (2, 2));
+    // The root directory takes one of the inodes.
+    assert_eq!((info.f_files, info.f_ffree), (3, 2));
+
+    let fd = open(c"/tmpfs_lim/a".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    let data = [b'a'; 4096];
+    assert_eq!(write(fd, data.as_ptr(), 4096), 4096);
+    assert_eq!(write(fd, data.as_ptr(), 4096), 4096);
+    assert_eq!(write(fd, data.as_ptr(), 1), -libc::ENOSPC as isize);
+    let mut info: Statfs = unsafe { mem::zeroed() };
+    assert_eq!(fstatfs(fd, &mut info), 0);
+    assert_eq!((info.f_bfree, info.f_ffree), (0, 1));
+    assert_eq!(ftruncate(fd, 4096), 0);
+    assert_eq!(statfs_of(c"/tmpfs_lim").f_bfree, 1);
+    close(fd);
+
+    assert_eq!(mkdir(c"/tmpfs_lim/b".as_ptr(), 0o755), 0);
+    assert_eq!(mkdir(c"/tmpfs_lim/c".as_ptr(), 0o755), -libc::ENOSPC);
+    assert_eq!(statfs_of(c"/tmpfs_lim").f_ffree, 0);
+
+    assert_eq!(rmdir(c"/tmpfs_lim/b".as_ptr()), 0);
+    assert_eq!(unlink(c"/tmpfs_lim/a".as_ptr()), 0);
+    assert_eq!(umount(dir), 0);
+    assert_eq!(rmdir(dir), 0);
+}
+
 #[test]
 fn test_directory_tree() {
     println!("[VFS Test DirctoryTree] Test the tmpfs mounted at /");
@@ -411,6 +1219,76 @@ fn verify_directory(path: *const c_char) -> Result<(), c_int> {
     Ok(())
 }
 
+#[test]
+fn test_getdents64_large_dir() {
+    const NUM_FILES: usize = 2000;
+    let dir_path = c"/test_large_dir";
+    assert_eq!(mkdir(dir_path.as_ptr(), 0o755), 0);
+    for i in 0..NUM_FILES {
+        let path = CString::new(format!("/test_large_dir/file_{}", i)).unwrap();
+        let fd = open(path.as_ptr(), O_CREAT | O_RDWR, 0o644);
+        assert!(fd >= 0, "[VFS Test LargeDir]: Failed to create file {}", i);
+        close(fd);
+    }
+
+    let dir = open(dir_path.as_ptr(), O_RDONLY | O_DIRECTORY, 0);
+    assert!(dir >= 0);
+    let mut tiny_buf = [0u8; 8];
+    assert_eq!(
+        getdents64(dir, tiny_buf.as_mut_ptr(), tiny_buf.len()),
+        -libc::EINVAL
+    );
+
+    // A few entries per call, so that the listing takes many of them.
+    let mut buf = [0u8; 512];
+    let mut names = BTreeSet::new();
+    let mut mark = None;
+    let mut after_mark = None;
+    loop {
+        let len = getdents64(dir, buf.as_mut_ptr(), buf.len());
+        assert!(
+            len >= 0,
+            "[VFS Test LargeDir]: getdents64 failed, err = {}",
+            len
+        );
+        if len == 0 {
+            break;
+        }
+        let mut pos = 0;
+        while pos < len as usize {
+            let entry = unsafe { Dirent64::from_buf_ref(&buf[pos..]) };
+            let name = String::from(entry.name().unwrap().to_str().unwrap());
+            if mark.is_some() && after_mark.is_none() {
+                after_mark = Some(name.clone());
+            }
+            if name == "file_1000" {
+                mark = Some(entry.off());
+            }
+            pos += entry.reclen() as usize;
+            assert!(names.insert(name), "[VFS Test LargeDir]: Duplicate entry");
+        }
+    }
+    assert_eq!(names.len(), NUM_FILES + 2);
+    for i in 0..NUM_FILES {
+        assert!(names.contains(&format!("file_{}", i)));
+    }
+
+    // Seeking to the d_off of an entry continues right after it.
+    let mark = mark.unwrap();
+    assert_eq!(lseek(dir, mark, SEEK_SET), mark);
+    let len = getdents64(dir, buf.as_mut_ptr(), buf.len());
+    assert!(len > 0);
+    let entry = unsafe { Dirent64::from_buf_ref(&buf) };
+    assert_eq!(entry.name().unwrap().to_str().unwrap(), after_mark.unwrap());
+    close(dir);
+
+    for i in 0..NUM_FILES {
+        let path = CString::new(format!("/test_large_dir/file_{}", i)).unwrap();
+        assert_eq!(unlink(path.as_ptr()), 0);
+    }
+    assert_eq!(rmdir(dir_path.as_ptr()), 0);
+}
+
 #[test]
 fn test_std_fds() {
     // Test writing to stdout (fd 1)
@@ -448,11 +1326,11 @@ fn test_fatfs_mount_unmount() {
     // Unmount /fat
     assert_eq!(umount(mount_path_1), 0);
 
-    // Mount the fatfs using the virt-storage device to /fat2
+    // Mount the fatfs using the vda device to /fat2
     assert!(mkdir(mount_path_2, mode) == 0);
     assert_eq!(
         mount(
-            c"virt-storage".as_ptr() as *const c_char,
+            c"vda".as_ptr() as *const c_char,
             mount_path_2,
             c"fatfs".as_ptr() as *const c_char,
             0,
@@ -478,10 +1356,10 @@ fn test_fatfs_mount_unmount() {
 
     // Trying to create the directory /fat, expected failure because the path exists
     assert_eq!(mkdir(mount_path_1, mode), EEXIST.to_errno());
-    // Mount the fatfs using the virt-storage device to /fat
+    // Mount the fatfs using the vda device to /fat
     assert_eq!(
         mount(
-            c"virt-storage".as_ptr() as *const c_char,
+            c"vda".as_ptr() as *const c_char,
             mount_path_1,
             c"fatfs".as_ptr() as *const c_char,
             0,
@@ -502,6 +1380,268 @@ fn test_fatfs_mount_unmount() {
     close(fd);
 }
 
+#[cfg(virtio)]
+#[test]
+fn test_vfat_mount() {
+    let storage = c"vda".as_ptr();
+    let free_blocks = |path: &CStr| {
+        let mut buf: Statfs = unsafe { mem::zeroed() };
+        assert_eq!(statfs(path.as_ptr(), &mut buf), 0);
+        assert!(buf.f_bfree <= buf.f_blocks);
+        buf.f_bfree
+    };
+
+    // Mount the volume formatted at boot as vfat, which never formats.
+    assert_eq!(umount(c"/fat".as_ptr()), 0);
+    assert_eq!(mkdir(c"/vfat".as_ptr(), 0o755), 0);
+    assert_eq!(
+        mount(
+            storage,
+            c"/vfat".as_ptr(),
+            c"vfat".as_ptr(),
+            0,
+            core::ptr::null()
+        ),
+        0
+    );
+    assert_eq!(
+        mount(
+            c"no-storage".as_ptr(),
+            c"/fat".as_ptr(),
+            c"vfat".as_ptr(),
+            0,
+            core::ptr::null()
+        ),
+        -libc::EINVAL
+    );
+
+    let free = free_blocks(c"/vfat");
+    let fd = open(c"/vfat/vfat.txt".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    let data = [b'v'; 4096];
+    assert_eq!(write(fd, data.as_ptr(), data.len()), data.len() as isize);
+    close(fd);
+    assert!(free_blocks(c"/vfat") < free);
+    assert_eq!(unlink(c"/vfat/vfat.txt".as_ptr()), 0);
+
+    assert_eq!(umount(c"/vfat".as_ptr()), 0);
+    assert_eq!(rmdir(c"/vfat".as_ptr()), 0);
+    assert_eq!(
+        mount(
+            storage,
+            c"/fat".as_ptr(),
+            c"fatfs".as_ptr(),
+            0,
+            core::ptr::null()
+        ),
+        0
+    );
+}
+
+#[cfg(littlefs)]
+#[test]
+fn test_littlefs() {
+    use blueos::vfs::littlefs::{register_device, NorFlash};
+
+    let nor = c"nor0".as_ptr();
+    let dir = c"/lfs".as_ptr();
+    let mount_with = |options: &CStr| {
+        let data = options.as_ptr() as *const c_void;
+        mount(nor, dir, c"littlefs".as_ptr(), 0, data)
+    };
+    let free_blocks = || {
+        let mut buf: Statfs = unsafe { mem::zeroed() };
+        assert_eq!(statfs(dir, &mut buf), 0);
+        buf.f_bfree
+    };
+    let read_all = |path: &CStr, len: usize| {
+        let fd = open(path.as_ptr(), O_RDONLY, 0);
+        assert!(fd >= 0);
+        let mut buf = vec![0u8; len + 1];
+        assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), len as isize);
+        close(fd);
+        buf.truncate(len);
+        buf
+    };
+
+    // 64 sectors of 4 KiB, whose metadata pairs are moved every few
+    // erases.
+    let flash = NorFlash::emulated(64 * 4096, 4096, 16);
+    assert!(register_device("nor0", alloc::sync::Arc::new(flash)).is_ok());
+    assert_eq!(mkdir(dir, 0o755), 0);
+    // A blank device is only formatted when asked to.
+    assert_eq!(mount_with(c"block_cycles=8"), -libc::EINVAL);
+    assert_eq!(mount_with(c"format,block_cycles=8"), 0);
+    let mut info: Statfs = unsafe { mem::zeroed() };
+    assert_eq!(statfs(dir, &mut info), 0);
+    assert_eq!((info.f_bsize, info.f_blocks, info.f_bfree), (4096, 64, 62));
+
+    // Small files are inlined in their directory, larger ones take blocks.
+    let fd = open(c"/lfs/small".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    assert_eq!(write(fd, b"inline".as_ptr(), 6), 6);
+    close(fd);
+    assert_eq!(free_blocks(), 62);
+    let big: alloc::vec::Vec<u8> = (0..3 * 4096 + 100).map(|i| (i % 251) as u8).collect();
+    let fd = open(c"/lfs/big".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    for chunk in big.chunks(1000) {
+        assert_eq!(write(fd, chunk.as_ptr(), chunk.len()), chunk.len() as isize);
+    }
+    close(fd);
+    assert_eq!(read_all(c"/lfs/big", big.len()), big);
+    assert_eq!(free_blocks(), 58);
+
+    assert_eq!(mkdir(c"/lfs/d".as_ptr(), 0o755), 0);
+    assert_eq!(rename(c"/lfs/small".as_ptr(), c"/lfs/d/moved".as_ptr()), 0);
+    assert_eq!(rename(c"/lfs/big".as_ptr(), c"/lfs/big2".as_ptr()), 0);
+    // Enough entries to split the directory over several pairs.
+    for i in 0..100 {
+        let path = CString::new(format!("/lfs/d/file{}", i)).unwrap();
+        let fd = open(path.as_ptr(), O_CREAT | O_RDWR, 0o644);
+        assert!(fd >= 0);
+        assert_eq!(write(fd, path.as_ptr() as *const u8, 12), 12);
+        close(fd);
+    }
+    assert_eq!(rmdir(c"/lfs/d".as_ptr()), -libc::ENOTEMPTY);
+
+    // An unlinked file keeps its blocks until it's closed.
+    let fd = open(c"/lfs/big2".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    let free = free_blocks();
+    assert_eq!(unlink(c"/lfs/big2".as_ptr()), 0);
+    assert_eq!(free_blocks(), free);
+    let mut buf = [0u8; 100];
+    assert_eq!(lseek(fd, 3 * 4096, SEEK_SET), 3 * 4096);
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 100);
+    assert_eq!(buf[..], big[3 * 4096..]);
+    close(fd);
+    assert_eq!(free_blocks(), free + 4);
+
+    // Everything is found again once remounted.
+    assert_eq!(umount(dir), 0);
+    assert_eq!(mount_with(c"block_cycles=8"), 0);
+    assert_eq!(read_all(c"/lfs/d/moved", 6), b"inline");
+    assert_eq!(read_all(c"/lfs/d/file42", 12), b"/lfs/d/file4");
+    assert!(open(c"/lfs/big2".as_ptr(), O_RDONLY, 0) < 0);
+    for i in 0..100 {
+        let path = CString::new(format!("/lfs/d/file{}", i)).unwrap();
+        assert_eq!(unlink(path.as_ptr()), 0);
+    }
+    assert_eq!(unlink(c"/lfs/d/moved".as_ptr()), 0);
+    assert_eq!(rmdir(c"/lfs/d".as_ptr()), 0);
+    assert_eq!(free_blocks(), 62);
+
+    assert_eq!(umount(dir), 0);
+    assert_eq!(rmdir(dir), 0);
+}
+
+#[cfg(littlefs)]
+#[test]
+fn test_littlefs_superblock_wear() {
+    use blueos::vfs::littlefs::{register_device, NorFlash, NorFlashOps};
+
+    const SECTOR: usize = 4096;
+    static SUPERBLOCK_ERASES: AtomicUsize = AtomicUsize::new(0);
+
+    // A flash in RAM which counts the erases of the superblock pair.
+    struct Flash(*mut u8);
+    unsafe impl Send for Flash {}
+    unsafe impl Sync for Flash {}
+    impl NorFlashOps for Flash {
+        fn program(&self, offset: usize, data: &[u8]) -> Result<(), Error> {
+            for (i, byte) in data.iter().enumerate() {
+                unsafe { *self.0.add(offset + i) &= byte };
+            }
+            Ok(())
+        }
+
+        fn erase_sector(&self, offset: usize) -> Result<(), Error> {
+            if offset < 2 * SECTOR {
+                SUPERBLOCK_ERASES.fetch_add(1, Ordering::Relaxed);
+            }
+            unsafe { self.0.add(offset).write_bytes(0xff, SECTOR) };
+            Ok(())
+        }
+    }
+
+    let size = 32 * SECTOR;
+    let mem = Box::leak(vec![0xffu8; size].into_boxed_slice()).as_mut_ptr();
+    let flash = unsafe { NorFlash::new(mem as usize, size, SECTOR, 16, Box::new(Flash(mem))) };
+    assert!(register_device("nor1", alloc::sync::Arc::new(flash)).is_ok());
+    let dir = c"/lfs_wear".as_ptr();
+    let mount_with = |options: &CStr| {
+        let data = options.as_ptr() as *const c_void;
+        mount(c"nor1".as_ptr(), dir, c"littlefs".as_ptr(), 0, data)
+    };
+    assert_eq!(mkdir(dir, 0o755), 0);
+    assert_eq!(mount_with(c"format,block_cycles=8"), 0);
+
+    let fd = open(c"/lfs_wear/keep".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    assert_eq!(write(fd, b"kept".as_ptr(), 4), 4);
+    close(fd);
+    // Every change to the root directory is a commit to its pair. Once
+    // that pair has been erased block_cycles times, the entries move to
+    // another pair, which is relocated as it wears, and the superblock
+    // pair is left alone.
+    for _ in 0..1500 {
+        let fd = open(c"/lfs_wear/churn".as_ptr(), O_CREAT | O_RDWR, 0o644);
+        assert!(fd >= 0);
+        assert_eq!(write(fd, b"churn".as_ptr(), 5), 5);
+        close(fd);
+        assert_eq!(unlink(c"/lfs_wear/churn".as_ptr()), 0);
+    }
+    assert!(SUPERBLOCK_ERASES.load(Ordering::Relaxed) < 20);
+
+    assert_eq!(umount(dir), 0);
+    assert_eq!(mount_with(c"block_cycles=8"), 0);
+    let fd = open(c"/lfs_wear/keep".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), 4);
+    assert_eq!(&buf[..4], b"kept");
+    close(fd);
+    assert_eq!(unlink(c"/lfs_wear/keep".as_ptr()), 0);
+    assert_eq!(umount(dir), 0);
+    assert_eq!(rmdir(dir), 0);
+}
+
+// The pflash the board registers, driven through its CFI commands.
+#[cfg(all(littlefs, target_board = "qemu_virt64_aarch64"))]
+#[test]
+fn test_littlefs_pflash() {
+    let dir = c"/pflash".as_ptr();
+    let mount_with = |options: &CStr| {
+        let data = options.as_ptr() as *const c_void;
+        mount(c"pflash1".as_ptr(), dir, c"littlefs".as_ptr(), 0, data)
+    };
+    assert_eq!(mkdir(dir, 0o755), 0);
+    assert_eq!(mount_with(c"format"), 0);
+    let mut info: Statfs = unsafe { mem::zeroed() };
+    assert_eq!(statfs(dir, &mut info), 0);
+    assert_eq!((info.f_bsize, info.f_blocks), (256 * 1024, 256));
+
+    let data: alloc::vec::Vec<u8> = (0..5000).map(|i| (i % 253) as u8).collect();
+    let fd = open(c"/pflash/file".as_ptr(), O_CREAT | O_RDWR, 0o644);
+    assert!(fd >= 0);
+    assert_eq!(write(fd, data.as_ptr(), data.len()), data.len() as isize);
+    close(fd);
+
+    // Read back from the flash, not from what the mount cached.
+    assert_eq!(umount(dir), 0);
+    assert_eq!(mount_with(c""), 0);
+    let fd = open(c"/pflash/file".as_ptr(), O_RDONLY, 0);
+    assert!(fd >= 0);
+    let mut buf = vec![0u8; data.len() + 1];
+    assert_eq!(read(fd, buf.as_mut_ptr(), buf.len()), data.len() as isize);
+    close(fd);
+    assert_eq!(&buf[..data.len()], &data[..]);
+    assert_eq!(unlink(c"/pflash/file".as_ptr()), 0);
+    assert_eq!(umount(dir), 0);
+    assert_eq!(rmdir(dir), 0);
+}
+
 #[cfg(procfs)]
 #[test]
 fn test_procfs_posix() {
@@ -587,6 +1727,84 @@ fn test_procfs_posix() {
     close(fd);
 }
 
+#[cfg(procfs)]
+#[test]
+fn test_procfs_uptime_and_status() {
+    let fd = open(c"/proc/uptime".as_ptr(), O_RDONLY, 0o444);
+    assert!(fd >= 0, "[VFS Test proc posix] Failed to open /proc/uptime");
+    let mut buf = [0u8; 64];
+    let len = read(fd, buf.as_mut_ptr(), buf.len());
+    assert!(len > 0, "[VFS Test proc posix] Failed to read /proc/uptime");
+    let content = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    let mut fields = content.trim_end().split(' ');
+    let uptime = fields.next().unwrap();
+    assert!(uptime.split_once('.').is_some_and(|(s, cs)| {
+        s.parse::<u64>().is_ok() && cs.len() == 2 && cs.parse::<u64>().is_ok()
+    }));
+    assert!(fields.next().is_some());
+    close(fd);
+    let status_path =
+        CString::new(format!("/proc/{}/status", scheduler::current_thread_id())).unwrap();
+    let fd = open(status_path.as_ptr(), O_RDONLY, 0o444);
+    assert!(
+        fd >= 0,
+        "[VFS Test proc posix] Failed to open {:?}",
+        status_path
+    );
+    let mut buf = [0u8; 512];
+    let len = read(fd, buf.as_mut_ptr(), buf.len());
+    assert!(len > 0);
+    let content = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    assert!(content.contains("State:      running"));
+    assert!(content.contains("StackUsed:"));
+    assert!(content.contains("StackSize:"));
+    close(fd);
+}
+
+#[cfg(procfs)]
+#[test]
+fn test_procfs_pageowner() {
+    let pages = allocator::page::alloc_pages(1, "test_procfs");
+    assert!(!pages.is_null());
+    let fd = open(c"/proc/pageowner".as_ptr(), O_RDONLY, 0o444);
+    assert!(
+        fd >= 0,
+        "[VFS Test proc posix] Failed to open /proc/pageowner"
+    );
+    let mut buf = [0u8; 1024];
+    let len = read(fd, buf.as_mut_ptr(), buf.len());
+    assert!(
+        len > 0,
+        "[VFS Test proc posix] Failed to read /proc/pageowner"
+    );
+    let content = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    assert!(content.starts_with("owner pages"));
+    assert!(content.contains("test_procfs 1"));
+    close(fd);
+    allocator::page::free_pages(pages);
+}
+
+#[cfg(procfs)]
+#[test]
+fn test_procfs_devices() {
+    let path = c"/proc/devices".as_ptr() as *const c_char;
+    let fd = open(path, O_RDONLY, 0o444);
+    assert!(
+        fd >= 0,
+        "[VFS Test proc posix] Failed to open /proc/devices"
+    );
+    let mut buf = [0u8; 1024];
+    let len = read(fd, buf.as_mut_ptr(), buf.len());
+    assert!(
+        len > 0,
+        "[VFS Test proc posix] Failed to read /proc/devices"
+    );
+    let content = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    assert!(content.starts_with("Character devices:"));
+    assert!(content.contains("   1:3    null"));
+    close(fd);
+}
+
 fn read_fd_content(path_str: &str, fd: i32) -> usize {
     let mut read_buf;
     let mut read_size = 0;
@@ -44,7 +44,7 @@ fn oops(info: &core::panic::PanicInfo) -> ! {
         semihosting::println!("{}", info);
         semihosting::println!("{}", info.message());
     }
-    loop {}
+    blueos::handle_panic(info)
 }
 
 #[used]